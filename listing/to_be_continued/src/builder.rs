@@ -0,0 +1,314 @@
+use std::fmt;
+use std::collections::HashMap;
+use crate::bytecode::{ByteCode, MAX_EXTRA_ARG};
+use crate::parse::{ConstKey, FuncProto, LocalVar, UpIndex};
+use crate::value::Value;
+
+// Build functions by byte codes directly, for code generators of other
+// front-end languages targeting this VM, e.g. the main function of
+// `print("hello")`:
+//
+//     let mut b = ProtoBuilder::new().param("_ENV");
+//     let print = b.constant("print");
+//     b.emit(ByteCode::GetField(1, 0, print as u8));
+//     let hello = b.constant("hello");
+//     b.load_const(2, hello);
+//     b.emit(ByteCode::Call(1, 2, 0));
+//     b.emit(ByteCode::Return0);
+//     let proto = b.finish()?;
+//     ExeState::new().exec_main(&proto);
+//
+// The byte codes are checked by finish(): indexes of constants and
+// upvalues, targets of jumps, ExtraArg, and the end of the function. So
+// mistakes are reported before running, but not by panics of the VM in
+// the middle. Values in registers are not checked, e.g. calling a nil
+// value, which are runtime errors same with Lua code. The stack size is
+// computed from the registers used.
+//
+// Jump offsets are relative to the following byte code, e.g. `Jump(d)`
+// at @pc goes to `pc+1+d`, while `ForLoop(_, d)` goes back to `pc+1-d`.
+pub struct ProtoBuilder {
+    proto: FuncProto,
+    const_indexes: HashMap<ConstKey, usize>, // same with the parser's
+    active: Vec<usize>, // indexes in @proto.locals of the locals in scope
+    line: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    pub icode: usize, // the length of byte codes for the end
+    pub msg: String,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "byte code {}: {}", self.icode, self.msg)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl Default for ProtoBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProtoBuilder {
+    pub fn new() -> Self {
+        ProtoBuilder {
+            proto: FuncProto {
+                chunk_name: String::from("=?"),
+                ..FuncProto::default()
+            },
+            const_indexes: HashMap::new(),
+            active: Vec::new(),
+            line: 0,
+        }
+    }
+
+    // chunk name for error messages, see parse::load_named()
+    pub fn chunk_name(mut self, name: &str) -> Self {
+        self.proto.chunk_name = name.to_string();
+        self
+    }
+
+    // Declare a parameter at the register following the previous ones.
+    // The main function, for ExeState::exec_main(), takes `_ENV`.
+    pub fn param(mut self, name: &str) -> Self {
+        assert!(self.proto.byte_codes.is_empty() && self.active.len() == self.proto.nparam,
+            "parameters should be declared before locals and byte codes");
+        self.local(name);
+        self.proto.nparam += 1;
+        self
+    }
+
+    pub fn varargs(mut self) -> Self {
+        self.proto.has_varargs = true;
+        self
+    }
+
+    // Add the constant if not added yet, and return its index.
+    pub fn constant(&mut self, c: impl Into<Value>) -> usize {
+        let c = c.into();
+        let key = ConstKey::from(&c);
+        if let Some(&i) = self.const_indexes.get(&key) {
+            return i;
+        }
+        assert!(self.proto.constants.len() <= MAX_EXTRA_ARG, "too many constants");
+        self.proto.constants.push(c);
+        self.const_indexes.insert(key, self.proto.constants.len() - 1);
+        self.proto.constants.len() - 1
+    }
+
+    // Add the inner function, built by another builder, as a constant
+    // for `Closure`, and return its index. See closure().
+    pub fn function(&mut self, proto: FuncProto) -> usize {
+        self.proto.constants.push(Value::LuaFunction(proto.into()));
+        self.proto.constants.len() - 1
+    }
+
+    // Declare an upvalue, which is a local or an upvalue of the enclosing
+    // function, and return its index.
+    pub fn upvalue(&mut self, name: &str, up: UpIndex) -> u8 {
+        assert!(self.proto.upindexes.len() < u8::MAX as usize, "too many upvalues");
+        self.proto.upindexes.push(up);
+        self.proto.upvalue_names.push(name.to_string());
+        (self.proto.upindexes.len() - 1) as u8
+    }
+
+    // Declare a local variable at the register following the locals in
+    // scope, which is in scope from the next byte code until end_local().
+    // Return its register.
+    pub fn local(&mut self, name: &str) -> u8 {
+        assert!(self.active.len() < u8::MAX as usize, "too many local variables");
+        self.active.push(self.proto.locals.len());
+        self.proto.locals.push(LocalVar {
+            name: name.to_string(),
+            icode_start: self.proto.byte_codes.len(),
+            icode_end: usize::MAX, // set by end_local() or finish()
+        });
+        (self.active.len() - 1) as u8
+    }
+
+    // end the scope of the last local variable in scope
+    pub fn end_local(&mut self) {
+        let i = self.active.pop().expect("no local variable in scope");
+        self.proto.locals[i].icode_end = self.proto.byte_codes.len();
+    }
+
+    // source line of the following byte codes, for error messages
+    pub fn line(&mut self, line: u32) {
+        self.line = line;
+    }
+
+    // index of the next byte code, e.g. as a jump target
+    pub fn pc(&self) -> usize {
+        self.proto.byte_codes.len()
+    }
+
+    // Append the byte code, and return its index.
+    pub fn emit(&mut self, code: ByteCode) -> usize {
+        self.proto.byte_codes.push(code);
+        self.proto.lines.push(self.line);
+        self.proto.byte_codes.len() - 1
+    }
+
+    // Replace the byte code at @icode, e.g. a jump whose target is known
+    // after emitting the following byte codes.
+    pub fn patch(&mut self, icode: usize, code: ByteCode) {
+        self.proto.byte_codes[icode] = code;
+    }
+
+    // load the constant @k into register @dst, by LoadConstX if @k does
+    // not fit in LoadConst
+    pub fn load_const(&mut self, dst: u8, k: usize) {
+        match u16::try_from(k) {
+            Ok(k) => { self.emit(ByteCode::LoadConst(dst, k)); }
+            Err(_) => {
+                self.emit(ByteCode::LoadConstX(dst));
+                self.emit(ByteCode::extra_arg(k));
+            }
+        }
+    }
+
+    // create a closure of the inner function at constant @k, added by
+    // function(), into register @dst
+    pub fn closure(&mut self, dst: u8, k: usize) {
+        match u16::try_from(k) {
+            Ok(k) => { self.emit(ByteCode::Closure(dst, k)); }
+            Err(_) => {
+                self.emit(ByteCode::ClosureX(dst));
+                self.emit(ByteCode::extra_arg(k));
+            }
+        }
+    }
+
+    // Check the byte codes, and return the function.
+    pub fn finish(mut self) -> Result<FuncProto, BuildError> {
+        let end = self.proto.byte_codes.len();
+        for &i in &self.active {
+            self.proto.locals[i].icode_end = end;
+        }
+
+        let nreg = self.proto.byte_codes.iter()
+            .flat_map(|code| code.registers())
+            .map(|r| r as usize + 1)
+            .max().unwrap_or(0);
+        self.proto.max_stack_size = nreg.max(self.proto.nparam);
+
+        check_proto(&self.proto)?;
+        Ok(self.proto)
+    }
+}
+
+// Check the byte codes of @p, which is built by ProtoBuilder, or loaded
+// from a binary chunk which may be corrupted or crafted, see
+// dump::undump(). Registers are checked against the stack size too,
+// which is computed by finish() but read from binary chunks. Inner
+// functions are not checked here, but when they are built or loaded.
+pub(crate) fn check_proto(p: &FuncProto) -> Result<(), BuildError> {
+    let end = p.byte_codes.len();
+    if !matches!(p.byte_codes.last(), Some(ByteCode::Return0 |
+            ByteCode::Return(_, _) | ByteCode::TailCall(_, _) | ByteCode::Jump(_))) {
+        return Err(BuildError { icode: end, msg: "missing return at the end".into() });
+    }
+    if p.nparam > p.max_stack_size {
+        return Err(BuildError { icode: 0, msg: "parameters out of stack size".into() });
+    }
+    if !p.lines.is_empty() && p.lines.len() != end {
+        return Err(BuildError { icode: end, msg: "lines mismatch".into() });
+    }
+    for (icode, &code) in p.byte_codes.iter().enumerate() {
+        check_code(p, icode, code).map_err(|msg| BuildError { icode, msg })?;
+    }
+    Ok(())
+}
+
+fn check_code(p: &FuncProto, icode: usize, code: ByteCode) -> Result<(), String> {
+    use ByteCode::*;
+    if let Some(r) = code.registers().into_iter().find(|&r| r as usize >= p.max_stack_size) {
+        return Err(format!("register {r} out of stack size"));
+    }
+    let constant = |k: usize| if k < p.constants.len() {
+        Ok(())
+    } else {
+        Err(format!("constant {k} out of range"))
+    };
+    let upvalue = |up: u8| if (up as usize) < p.upindexes.len() {
+        Ok(())
+    } else {
+        Err(format!("upvalue {up} out of range"))
+    };
+    let target = |t: isize| match usize::try_from(t).ok().and_then(|t| p.byte_codes.get(t)) {
+        Some(ExtraArg(_, _)) => Err(format!("jump to ExtraArg at {t}")),
+        Some(_) => Ok(()),
+        None => Err(format!("jump to {t} out of range")),
+    };
+    let extra_arg = || match p.byte_codes.get(icode + 1) {
+        Some(code @ ExtraArg(_, _)) => Ok(code.ax()),
+        _ => Err(String::from("ExtraArg expected")),
+    };
+    let pc = icode as isize;
+
+    match code {
+        LoadConst(_, k) => constant(k as usize),
+        LoadConstX(_) => constant(extra_arg()?),
+        GetUpvalue(_, up) | SetUpvalue(up, _) => upvalue(up),
+        SetUpvalueConst(up, k) => upvalue(up).and(constant(k as usize)),
+
+        SetField(_, k, _) | GetField(_, _, k) | GetFieldSelf(_, _, k) |
+            SetTableConst(_, _, k) | SetIntConst(_, _, k) => constant(k as usize),
+        SetFieldConst(_, k, v) => constant(k as usize).and(constant(v as usize)),
+        SetUpField(t, k, _) | GetUpField(_, t, k) => upvalue(t).and(constant(k as usize)),
+        SetUpFieldConst(t, k, v) =>
+            upvalue(t).and(constant(k as usize)).and(constant(v as usize)),
+
+        Jump(d) | TestAndJump(_, d) | TestOrJump(_, d) => target(pc + 1 + d as isize),
+        TestAndSetJump(_, _, d) | TestOrSetJump(_, _, d) => target(pc + 1 + d as isize),
+        ForPrepare(_, d) => target(pc + 1 + d as isize),
+        ForLoop(_, d) => target(pc + 1 - d as isize),
+        ForCallLoop(_, _, 0) => target(pc + 1).and(target(pc + 2)),
+        ForCallLoop(_, _, d) => target(pc + 1 - d as isize),
+
+        // skip the next byte code if the condition fails
+        Equal(_, _, _) | EqualInt(_, _, _) | NotEq(_, _, _) | NotEqInt(_, _, _) |
+            LesEq(_, _, _) | LesEqInt(_, _, _) | GreEq(_, _, _) | GreEqInt(_, _, _) |
+            Less(_, _, _) | LessInt(_, _, _) | Greater(_, _, _) | GreaterInt(_, _, _) |
+            SetFalseSkip(_) => target(pc + 2),
+        EqualConst(_, k, _) | NotEqConst(_, k, _) | LesEqConst(_, k, _) |
+            GreEqConst(_, k, _) | LessConst(_, k, _) | GreaterConst(_, k, _) =>
+            constant(k as usize).and(target(pc + 2)),
+
+        AddConst(_, _, k) | SubConst(_, _, k) | MulConst(_, _, k) | ModConst(_, _, k) |
+            DivConst(_, _, k) | IdivConst(_, _, k) | PowConst(_, _, k) |
+            BitAndConst(_, _, k) | BitXorConst(_, _, k) | BitOrConst(_, _, k) |
+            ShiftLConst(_, _, k) | ShiftRConst(_, _, k) => constant(k as usize),
+
+        Closure(_, k) => check_closure(p, k as usize),
+        ClosureX(_) => check_closure(p, extra_arg()?),
+
+        ExtraArg(_, _) => match icode.checked_sub(1).map(|i| p.byte_codes[i]) {
+            Some(LoadConstX(_) | ClosureX(_)) => Ok(()),
+            _ => Err(String::from("unexpected ExtraArg")),
+        }
+        _ => Ok(()),
+    }
+}
+
+// the inner function should capture existing locals and upvalues
+fn check_closure(p: &FuncProto, k: usize) -> Result<(), String> {
+    let Some(Value::LuaFunction(inner)) = p.constants.get(k) else {
+        return Err(format!("constant {k} is not a function"));
+    };
+    for up in &inner.upindexes {
+        match *up {
+            UpIndex::Local(i) if i >= p.max_stack_size =>
+                return Err(format!("captured local {i} out of range")),
+            UpIndex::Upvalue(i) if i >= p.upindexes.len() =>
+                return Err(format!("captured upvalue {i} out of range")),
+            _ => (),
+        }
+    }
+    Ok(())
+}
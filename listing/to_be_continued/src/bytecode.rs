@@ -1,122 +1,369 @@
-#[derive(Debug)]
-pub enum ByteCode {
+// Byte codes are defined by byte_codes!, which generates:
+//   - the enum ByteCode, used by the parser and the VM;
+//   - the enum OpCode, numbers of the byte codes by the order here;
+//   - the conversions between ByteCode and Instruction, the packed form.
+//
+// The fields are named here for the conversions only. So adding a byte
+// code needs no more change in this file.
+macro_rules! byte_codes {
+    ($($name:ident $(($($field:ident: $ty:ty),*))?,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum ByteCode {
+            $($name $(($($ty),*))?,)*
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($name,)*
+        }
+
+        impl ByteCode {
+            #[allow(unused_variables)]
+            pub fn opcode(&self) -> OpCode {
+                match self {
+                    $(ByteCode::$name $(($($field),*))? => OpCode::$name,)*
+                }
+            }
+        }
+
+        impl From<ByteCode> for Instruction {
+            #[allow(unused_assignments, unused_mut)]
+            fn from(code: ByteCode) -> Self {
+                let mut word = code.opcode() as u32;
+                let mut shift = 8;
+                match code {
+                    $(ByteCode::$name $(($($field),*))? => {
+                        $($(
+                            shift = <$ty as Operand>::start(shift);
+                            word |= $field.encode() << shift;
+                            shift += 8 * <$ty as Operand>::WIDTH;
+                        )*)?
+                    })*
+                }
+                Instruction(word)
+            }
+        }
+
+        impl TryFrom<Instruction> for ByteCode {
+            type Error = u8; // the invalid opcode
+
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            fn try_from(inst: Instruction) -> Result<Self, u8> {
+                let op = inst.opcode();
+                let mut shift = 8;
+                $(
+                    if op == OpCode::$name as u8 {
+                        $($(
+                            shift = <$ty as Operand>::start(shift);
+                            let $field = <$ty as Operand>::decode(inst.0 >> shift);
+                            shift += 8 * <$ty as Operand>::WIDTH;
+                        )*)?
+                        return Ok(ByteCode::$name $(($($field),*))?);
+                    }
+                )*
+                Err(op)
+            }
+        }
+    };
+}
+
+// Packed form of byte codes, for dumping and loading chunks:
+//
+//     | C: 8 | B: 8 | A: 8 | opcode: 8 |
+//     |    Bx: 16   |
+//     |        Ax: 24        |
+//
+// The operands are filled in A, B and C by order, while 16-bit operands
+// take both B and C as Bx, e.g. A is unused by Jump. Bools are 0 or 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction(pub u32);
+
+impl Instruction {
+    pub fn opcode(self) -> u8 {
+        self.0 as u8
+    }
+    pub fn a(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+    pub fn b(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+    pub fn c(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+    pub fn bx(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+    pub fn sbx(self) -> i16 {
+        self.bx() as i16
+    }
+    pub fn ax(self) -> u32 {
+        self.0 >> 8
+    }
+}
+
+// operand types of byte codes
+trait Operand {
+    const WIDTH: u32; // in bytes
+    fn encode(self) -> u32;
+    fn decode(bits: u32) -> Self;
+
+    // the bit position of this operand following @shift, where 16-bit
+    // operands are always Bx
+    fn start(shift: u32) -> u32 {
+        if Self::WIDTH == 2 { 16 } else { shift }
+    }
+}
+
+impl Operand for u8 {
+    const WIDTH: u32 = 1;
+    fn encode(self) -> u32 { self as u32 }
+    fn decode(bits: u32) -> Self { bits as u8 }
+}
+impl Operand for bool {
+    const WIDTH: u32 = 1;
+    fn encode(self) -> u32 { self as u32 }
+    fn decode(bits: u32) -> Self { bits as u8 != 0 }
+}
+impl Operand for u16 {
+    const WIDTH: u32 = 2;
+    fn encode(self) -> u32 { self as u32 }
+    fn decode(bits: u32) -> Self { bits as u16 }
+}
+impl Operand for i16 {
+    const WIDTH: u32 = 2;
+    fn encode(self) -> u32 { self as u16 as u32 }
+    fn decode(bits: u32) -> Self { bits as u16 as i16 }
+}
+
+byte_codes! {
     // local variable
-    LoadConst(u8, u16),
-    LoadNil(u8, u8),
-    LoadBool(u8, bool),
-    LoadInt(u8, i16),
-    Move(u8, u8),
+    LoadConst(dst: u8, k: u16),
+    LoadConstX(dst: u8),
+    LoadNil(dst: u8, n: u8),
+    LoadBool(dst: u8, b: bool),
+    LoadInt(dst: u8, i: i16),
+    Move(dst: u8, src: u8),
+    MoveN(dst: u8, src: u8, n: u8),
 
     // upvalues
-    GetUpvalue(u8, u8),
-    SetUpvalue(u8, u8),
-    SetUpvalueConst(u8, u8),
-    Close(u8),
+    GetUpvalue(dst: u8, src: u8),
+    SetUpvalue(dst: u8, src: u8),
+    SetUpvalueConst(dst: u8, src: u8),
+    Close(ilocal: u8),
 
     // table
-    NewTable(u8, u8, u8),
-    SetTable(u8, u8, u8),
-    SetField(u8, u8, u8),
-    SetInt(u8, u8, u8),
-    SetTableConst(u8, u8, u8),
-    SetFieldConst(u8, u8, u8),
-    SetIntConst(u8, u8, u8),
-    SetList(u8, u8),
-    GetTable(u8, u8, u8),
-    GetField(u8, u8, u8),
-    GetInt(u8, u8, u8),
-    GetFieldSelf(u8, u8, u8),
+    NewTable(dst: u8, narray: u8, nmap: u8),
+    SetTable(t: u8, k: u8, v: u8),
+    SetField(t: u8, k: u8, v: u8),
+    SetInt(t: u8, k: u8, v: u8),
+    SetTableConst(t: u8, k: u8, v: u8),
+    SetFieldConst(t: u8, k: u8, v: u8),
+    SetIntConst(t: u8, k: u8, v: u8),
+    SetList(table: u8, n: u8),
+    GetTable(dst: u8, t: u8, k: u8),
+    GetField(dst: u8, t: u8, k: u8),
+    GetInt(dst: u8, t: u8, k: u8),
+    GetFieldSelf(dst: u8, t: u8, k: u8),
 
     // upvalue table, covers global variables
-    SetUpField(u8, u8, u8),
-    SetUpFieldConst(u8, u8, u8),
-    GetUpField(u8, u8, u8),
+    SetUpField(t: u8, k: u8, v: u8),
+    SetUpFieldConst(t: u8, k: u8, v: u8),
+    GetUpField(dst: u8, t: u8, k: u8),
 
     // condition structures
-    Jump(i16),
-    TestAndJump(u8, i16),
-    TestOrJump(u8, i16),
-    TestAndSetJump(u8, u8, u8),
-    TestOrSetJump(u8, u8, u8),
+    Jump(d: i16),
+    TestAndJump(cond: u8, d: i16),
+    TestOrJump(cond: u8, d: i16),
+    TestAndSetJump(dst: u8, cond: u8, d: u8),
+    TestOrSetJump(dst: u8, cond: u8, d: u8),
 
     // for-loop
-    ForPrepare(u8, u16),
-    ForLoop(u8, u16),
-    ForCallLoop(u8, u8, u8),
+    ForPrepare(dst: u8, d: u16),
+    ForLoop(dst: u8, d: u16),
+    ForCallLoop(dst: u8, nvar: u8, d: u8),
 
     // function call
-    Closure(u8, u16),
-    Call(u8, u8, u8),
-    CallSet(u8, u8, u8),
-    TailCall(u8, u8),
+    Closure(dst: u8, proto: u16),
+    ClosureX(dst: u8),
+    Call(func: u8, narg_plus: u8, want: u8),
+    CallSet(dst: u8, func: u8, narg_plus: u8),
+    TailCall(func: u8, narg_plus: u8),
     Return0,
-    Return(u8, u8),
-    VarArgs(u8, u8),
+    Return(iret: u8, nret: u8),
+    VarArgs(dst: u8, want: u8),
 
     // unops
-    Neg(u8, u8),
-    Not(u8, u8),
-    BitNot(u8, u8),
-    Len(u8, u8),
+    Neg(dst: u8, src: u8),
+    Not(dst: u8, src: u8),
+    BitNot(dst: u8, src: u8),
+    Len(dst: u8, src: u8),
 
     // binops
-    Add(u8, u8, u8),
-    AddConst(u8, u8, u8),
-    AddInt(u8, u8, u8),
-    Sub(u8, u8, u8),
-    SubInt(u8, u8, u8),
-    SubConst(u8, u8, u8),
-    Mul(u8, u8, u8),
-    MulInt(u8, u8, u8),
-    MulConst(u8, u8, u8),
-    Mod(u8, u8, u8),
-    ModInt(u8, u8, u8),
-    ModConst(u8, u8, u8),
-    Div(u8, u8, u8),
-    DivInt(u8, u8, u8),
-    DivConst(u8, u8, u8),
-    Idiv(u8, u8, u8),
-    IdivInt(u8, u8, u8),
-    IdivConst(u8, u8, u8),
-    Pow(u8, u8, u8),
-    PowInt(u8, u8, u8),
-    PowConst(u8, u8, u8),
-    BitAnd(u8, u8, u8),
-    BitAndInt(u8, u8, u8),
-    BitAndConst(u8, u8, u8),
-    BitXor(u8, u8, u8),
-    BitXorInt(u8, u8, u8),
-    BitXorConst(u8, u8, u8),
-    BitOr(u8, u8, u8),
-    BitOrInt(u8, u8, u8),
-    BitOrConst(u8, u8, u8),
-    ShiftL(u8, u8, u8),
-    ShiftLInt(u8, u8, u8),
-    ShiftLConst(u8, u8, u8),
-    ShiftR(u8, u8, u8),
-    ShiftRInt(u8, u8, u8),
-    ShiftRConst(u8, u8, u8),
-
-    Equal(u8, u8, bool),
-    EqualInt(u8, u8, bool),
-    EqualConst(u8, u8, bool),
-    NotEq(u8, u8, bool),
-    NotEqInt(u8, u8, bool),
-    NotEqConst(u8, u8, bool),
-    LesEq(u8, u8, bool),
-    LesEqInt(u8, u8, bool),
-    LesEqConst(u8, u8, bool),
-    GreEq(u8, u8, bool),
-    GreEqInt(u8, u8, bool),
-    GreEqConst(u8, u8, bool),
-    Less(u8, u8, bool),
-    LessInt(u8, u8, bool),
-    LessConst(u8, u8, bool),
-    Greater(u8, u8, bool),
-    GreaterInt(u8, u8, bool),
-    GreaterConst(u8, u8, bool),
-
-    SetFalseSkip(u8),
-
-    Concat(u8, u8, u8),
+    Add(dst: u8, a: u8, b: u8),
+    AddConst(dst: u8, a: u8, b: u8),
+    AddInt(dst: u8, a: u8, b: u8),
+    Sub(dst: u8, a: u8, b: u8),
+    SubInt(dst: u8, a: u8, b: u8),
+    SubConst(dst: u8, a: u8, b: u8),
+    Mul(dst: u8, a: u8, b: u8),
+    MulInt(dst: u8, a: u8, b: u8),
+    MulConst(dst: u8, a: u8, b: u8),
+    Mod(dst: u8, a: u8, b: u8),
+    ModInt(dst: u8, a: u8, b: u8),
+    ModConst(dst: u8, a: u8, b: u8),
+    Div(dst: u8, a: u8, b: u8),
+    DivInt(dst: u8, a: u8, b: u8),
+    DivConst(dst: u8, a: u8, b: u8),
+    Idiv(dst: u8, a: u8, b: u8),
+    IdivInt(dst: u8, a: u8, b: u8),
+    IdivConst(dst: u8, a: u8, b: u8),
+    Pow(dst: u8, a: u8, b: u8),
+    PowInt(dst: u8, a: u8, b: u8),
+    PowConst(dst: u8, a: u8, b: u8),
+    BitAnd(dst: u8, a: u8, b: u8),
+    BitAndInt(dst: u8, a: u8, b: u8),
+    BitAndConst(dst: u8, a: u8, b: u8),
+    BitXor(dst: u8, a: u8, b: u8),
+    BitXorInt(dst: u8, a: u8, b: u8),
+    BitXorConst(dst: u8, a: u8, b: u8),
+    BitOr(dst: u8, a: u8, b: u8),
+    BitOrInt(dst: u8, a: u8, b: u8),
+    BitOrConst(dst: u8, a: u8, b: u8),
+    ShiftL(dst: u8, a: u8, b: u8),
+    ShiftLInt(dst: u8, a: u8, b: u8),
+    ShiftLConst(dst: u8, a: u8, b: u8),
+    ShiftR(dst: u8, a: u8, b: u8),
+    ShiftRInt(dst: u8, a: u8, b: u8),
+    ShiftRConst(dst: u8, a: u8, b: u8),
+
+    Equal(a: u8, b: u8, r: bool),
+    EqualInt(a: u8, b: u8, r: bool),
+    EqualConst(a: u8, b: u8, r: bool),
+    NotEq(a: u8, b: u8, r: bool),
+    NotEqInt(a: u8, b: u8, r: bool),
+    NotEqConst(a: u8, b: u8, r: bool),
+    LesEq(a: u8, b: u8, r: bool),
+    LesEqInt(a: u8, b: u8, r: bool),
+    LesEqConst(a: u8, b: u8, r: bool),
+    GreEq(a: u8, b: u8, r: bool),
+    GreEqInt(a: u8, b: u8, r: bool),
+    GreEqConst(a: u8, b: u8, r: bool),
+    Less(a: u8, b: u8, r: bool),
+    LessInt(a: u8, b: u8, r: bool),
+    LessConst(a: u8, b: u8, r: bool),
+    Greater(a: u8, b: u8, r: bool),
+    GreaterInt(a: u8, b: u8, r: bool),
+    GreaterConst(a: u8, b: u8, r: bool),
+
+    SetFalseSkip(dst: u8),
+
+    Concat(dst: u8, a: u8, b: u8),
+
+    // argument of the previous byte code, which is too big to be put in it
+    ExtraArg(a: u8, bx: u16),
+}
+
+// max argument of ExtraArg
+pub const MAX_EXTRA_ARG: usize = (1 << 24) - 1;
+
+impl ByteCode {
+    // ExtraArg of the 24-bit argument @ax, see ax(). It follows LoadConstX
+    // and ClosureX, for constant indexes out of u16.
+    pub fn extra_arg(ax: usize) -> Self {
+        assert!(ax <= MAX_EXTRA_ARG);
+        ByteCode::ExtraArg(ax as u8, (ax >> 8) as u16)
+    }
+
+    // the argument of ExtraArg, which is the same with Instruction::ax()
+    // of the packed form
+    pub fn ax(&self) -> usize {
+        match *self {
+            ByteCode::ExtraArg(a, bx) => (bx as usize) << 8 | a as usize,
+            _ => panic!("expect ExtraArg"),
+        }
+    }
+
+    // the register set by this byte code, or the first one if it sets a
+    // range of registers. Used to recover the variable name for error
+    // messages.
+    pub fn dst(&self) -> Option<u8> {
+        use ByteCode::*;
+        match *self {
+            LoadConst(dst, _) | LoadConstX(dst) | LoadInt(dst, _) => Some(dst),
+            LoadNil(dst, _) | LoadBool(dst, _) | Move(dst, _) | MoveN(dst, _, _) => Some(dst),
+            GetUpvalue(dst, _) | NewTable(dst, _, _) => Some(dst),
+            GetTable(dst, _, _) | GetField(dst, _, _) | GetInt(dst, _, _) |
+                GetFieldSelf(dst, _, _) | GetUpField(dst, _, _) => Some(dst),
+            TestAndSetJump(dst, _, _) | TestOrSetJump(dst, _, _) => Some(dst),
+            ForPrepare(dst, _) | ForLoop(dst, _) | ForCallLoop(dst, _, _) => Some(dst),
+            Closure(dst, _) | ClosureX(dst) => Some(dst),
+            Call(dst, _, _) | CallSet(dst, _, _) | VarArgs(dst, _) => Some(dst),
+            Neg(dst, _) | Not(dst, _) | BitNot(dst, _) | Len(dst, _) => Some(dst),
+            Add(dst, _, _) | AddConst(dst, _, _) | AddInt(dst, _, _) |
+                Sub(dst, _, _) | SubInt(dst, _, _) | SubConst(dst, _, _) |
+                Mul(dst, _, _) | MulInt(dst, _, _) | MulConst(dst, _, _) |
+                Mod(dst, _, _) | ModInt(dst, _, _) | ModConst(dst, _, _) |
+                Div(dst, _, _) | DivInt(dst, _, _) | DivConst(dst, _, _) |
+                Idiv(dst, _, _) | IdivInt(dst, _, _) | IdivConst(dst, _, _) |
+                Pow(dst, _, _) | PowInt(dst, _, _) | PowConst(dst, _, _) |
+                BitAnd(dst, _, _) | BitAndInt(dst, _, _) | BitAndConst(dst, _, _) |
+                BitXor(dst, _, _) | BitXorInt(dst, _, _) | BitXorConst(dst, _, _) |
+                BitOr(dst, _, _) | BitOrInt(dst, _, _) | BitOrConst(dst, _, _) |
+                ShiftL(dst, _, _) | ShiftLInt(dst, _, _) | ShiftLConst(dst, _, _) |
+                ShiftR(dst, _, _) | ShiftRInt(dst, _, _) | ShiftRConst(dst, _, _) |
+                Concat(dst, _, _) => Some(dst),
+            SetFalseSkip(dst) => Some(dst),
+            _ => None,
+        }
+    }
+
+    // registers read or set by this byte code, where ranges are given
+    // by their first registers. Used to find the uses of a variable.
+    pub fn registers(&self) -> Vec<u8> {
+        use ByteCode::*;
+        match *self {
+            LoadConst(r, _) | LoadConstX(r) | LoadNil(r, _) | LoadBool(r, _) | LoadInt(r, _) |
+                GetUpvalue(r, _) | SetUpvalue(_, r) | NewTable(r, _, _) | SetList(r, _) |
+                SetUpField(_, _, r) | GetUpField(r, _, _) |
+                TestAndJump(r, _) | TestOrJump(r, _) |
+                ForPrepare(r, _) | ForLoop(r, _) | ForCallLoop(r, _, _) |
+                Closure(r, _) | ClosureX(r) | Call(r, _, _) | TailCall(r, _) |
+                Return(r, _) | VarArgs(r, _) | SetFalseSkip(r) => vec![r],
+            Move(a, b) | MoveN(a, b, _) | TestAndSetJump(a, b, _) | TestOrSetJump(a, b, _) |
+                CallSet(a, b, _) | Neg(a, b) | Not(a, b) | BitNot(a, b) | Len(a, b) => vec![a, b],
+            SetField(a, _, b) | SetInt(a, _, b) | SetTableConst(a, b, _) => vec![a, b],
+            SetFieldConst(a, _, _) | SetIntConst(a, _, _) => vec![a],
+            GetField(a, b, _) | GetInt(a, b, _) | GetFieldSelf(a, b, _) => vec![a, b],
+            AddConst(a, b, _) | AddInt(a, b, _) | SubInt(a, b, _) | SubConst(a, b, _) |
+                MulInt(a, b, _) | MulConst(a, b, _) | ModInt(a, b, _) | ModConst(a, b, _) |
+                DivInt(a, b, _) | DivConst(a, b, _) | IdivInt(a, b, _) | IdivConst(a, b, _) |
+                PowInt(a, b, _) | PowConst(a, b, _) | BitAndInt(a, b, _) | BitAndConst(a, b, _) |
+                BitXorInt(a, b, _) | BitXorConst(a, b, _) | BitOrInt(a, b, _) | BitOrConst(a, b, _) |
+                ShiftLInt(a, b, _) | ShiftLConst(a, b, _) | ShiftRInt(a, b, _) | ShiftRConst(a, b, _) =>
+                vec![a, b],
+            SetTable(a, b, c) | GetTable(a, b, c) | Concat(a, b, c) |
+                Add(a, b, c) | Sub(a, b, c) | Mul(a, b, c) | Mod(a, b, c) | Div(a, b, c) |
+                Idiv(a, b, c) | Pow(a, b, c) | BitAnd(a, b, c) | BitXor(a, b, c) | BitOr(a, b, c) |
+                ShiftL(a, b, c) | ShiftR(a, b, c) => vec![a, b, c],
+            Equal(a, b, _) | NotEq(a, b, _) | LesEq(a, b, _) | GreEq(a, b, _) |
+                Less(a, b, _) | Greater(a, b, _) => vec![a, b],
+            EqualInt(a, _, _) | EqualConst(a, _, _) | NotEqInt(a, _, _) | NotEqConst(a, _, _) |
+                LesEqInt(a, _, _) | LesEqConst(a, _, _) | GreEqInt(a, _, _) | GreEqConst(a, _, _) |
+                LessInt(a, _, _) | LessConst(a, _, _) | GreaterInt(a, _, _) | GreaterConst(a, _, _) =>
+                vec![a],
+            SetUpvalueConst(_, _) | SetUpFieldConst(_, _, _) | Close(_) | Jump(_) |
+                Return0 | ExtraArg(_, _) => vec![],
+        }
+    }
+
+    // jumps, after which we can not track the registers anymore
+    pub fn is_jump(&self) -> bool {
+        use ByteCode::*;
+        matches!(self, Jump(_) | TestAndJump(_, _) | TestOrJump(_, _) |
+            TestAndSetJump(_, _, _) | TestOrSetJump(_, _, _) |
+            ForPrepare(_, _) | ForLoop(_, _) | ForCallLoop(_, _, _) |
+            SetFalseSkip(_))
+    }
 }
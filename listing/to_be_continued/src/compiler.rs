@@ -0,0 +1,51 @@
+use crate::dump;
+use crate::parse::{self, FuncProto};
+
+// Front ends which compile chunks into functions for the VM, used by
+// `ExeState::exec_file()` and `require`. Set by
+// `ExeStateBuilder::compiler()`, e.g. a transpiler of another language
+// targeting Lua, which may generate Lua source and then call
+// parse::load_named(), or build byte codes by builder::ProtoBuilder.
+//
+// The @chunk_name follows the conventions of parse::load_named(), e.g.
+// `@path` for files. Errors are raised by panics, same with the parser,
+// e.g. "name:1: syntax error", so they are caught by protected calls.
+pub trait Compiler {
+    fn compile(&self, chunk: &[u8], chunk_name: &str) -> FuncProto;
+}
+
+// Lua source code only, by parse::load_named().
+pub struct SourceCompiler;
+
+impl Compiler for SourceCompiler {
+    fn compile(&self, chunk: &[u8], chunk_name: &str) -> FuncProto {
+        parse::load_named(chunk, chunk_name)
+    }
+}
+
+// Binary chunks only, by dump::undump(), e.g. to run precompiled
+// scripts without the risk of loading untrusted source code.
+pub struct BinaryCompiler;
+
+impl Compiler for BinaryCompiler {
+    fn compile(&self, chunk: &[u8], chunk_name: &str) -> FuncProto {
+        if !dump::is_binary(chunk) {
+            panic!("{}: attempt to load a text chunk", parse::short_source(chunk_name));
+        }
+        dump::undump(chunk, chunk_name)
+    }
+}
+
+// The default one: binary chunks or source code, by the first byte,
+// same with `luaL_loadfile()` in mode "bt".
+pub struct DefaultCompiler;
+
+impl Compiler for DefaultCompiler {
+    fn compile(&self, chunk: &[u8], chunk_name: &str) -> FuncProto {
+        if dump::is_binary(chunk) {
+            BinaryCompiler.compile(chunk, chunk_name)
+        } else {
+            SourceCompiler.compile(chunk, chunk_name)
+        }
+    }
+}
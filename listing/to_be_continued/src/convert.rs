@@ -0,0 +1,201 @@
+use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+use crate::value::{ConversionError, Table, TableHandle, Value};
+
+// Typed arguments and return values of Rust functions, so they need not
+// check the arguments one by one:
+//
+//     let f = state.create_typed_function("repeat", |(s, n): (String, Option<i64>)| {
+//         s.repeat(n.unwrap_or(2) as usize)
+//     });
+//
+// Where `repeat({})` raises "bad argument #1 to 'repeat' (string
+// expected, got table)", same with the standard library.
+//
+// The conversions are based on the `TryFrom<Value>` ones in value.rs,
+// while the errors are in the messages of Lua but not ConversionError.
+
+// Convert an argument, where None is for no value, e.g. the missing last
+// ones. The error is the message in the parentheses of "bad argument",
+// e.g. "number expected, got string".
+pub trait FromLua: Sized {
+    fn from_lua(v: Option<&Value>) -> Result<Self, String>;
+}
+
+// Convert all arguments. The error is with the argument's index from 1.
+pub trait FromLuaMulti: Sized {
+    fn from_lua_multi(args: &[Value]) -> Result<Self, (usize, String)>;
+}
+
+pub trait IntoLua {
+    fn into_lua(self) -> Value;
+}
+
+// Convert the return values, or raise the error.
+pub trait IntoLuaMulti {
+    fn into_lua_multi(self) -> Vec<Value>;
+}
+
+fn type_name(v: Option<&Value>) -> &'static str {
+    v.map_or("no value", Value::type_name)
+}
+
+fn expected(what: &str, v: Option<&Value>) -> String {
+    format!("{what} expected, got {}", type_name(v))
+}
+
+fn try_convert<T>(v: Option<&Value>, what: &str) -> Result<T, String>
+    where T: TryFrom<Value, Error = ConversionError>
+{
+    let Some(v) = v else {
+        return Err(expected(what, None));
+    };
+    T::try_from(v.clone()).map_err(|e| match e {
+        ConversionError::Type { .. } => expected(what, Some(v)),
+        // e.g. 1.5, same with Lua, but "no u32 representation" for -1
+        ConversionError::Range { .. } if i64::try_from(v.clone()).is_err() =>
+            "number has no integer representation".into(),
+        e => e.to_string(),
+    })
+}
+
+macro_rules! impl_from_lua {
+    ($what:literal: $($t:ty),*) => { $(
+        impl FromLua for $t {
+            fn from_lua(v: Option<&Value>) -> Result<Self, String> {
+                try_convert(v, $what)
+            }
+        }
+    )* }
+}
+impl_from_lua!("number": i64, i32, u32, usize, f64);
+impl_from_lua!("string": String, Vec<u8>);
+
+// any value, where only nil, false and no value are false
+impl FromLua for bool {
+    fn from_lua(v: Option<&Value>) -> Result<Self, String> {
+        Ok(v.is_some_and(bool::from))
+    }
+}
+
+// any value, and nil for no value
+impl FromLua for Value {
+    fn from_lua(v: Option<&Value>) -> Result<Self, String> {
+        Ok(v.cloned().unwrap_or(Value::Nil))
+    }
+}
+
+impl FromLua for TableHandle {
+    fn from_lua(v: Option<&Value>) -> Result<Self, String> {
+        match v {
+            Some(Value::Table(t)) => Ok(TableHandle::new(t.clone())),
+            v => Err(expected("table", v)),
+        }
+    }
+}
+
+// optional arguments, which are None for nil or no value
+impl<T: FromLua> FromLua for Option<T> {
+    fn from_lua(v: Option<&Value>) -> Result<Self, String> {
+        match v {
+            None | Some(Value::Nil) => Ok(None),
+            v => T::from_lua(v).map(Some),
+        }
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for T {
+    fn from_lua_multi(args: &[Value]) -> Result<Self, (usize, String)> {
+        T::from_lua(args.first()).map_err(|e| (1, e))
+    }
+}
+
+// all arguments, e.g. for variadic functions
+impl FromLuaMulti for Vec<Value> {
+    fn from_lua_multi(args: &[Value]) -> Result<Self, (usize, String)> {
+        Ok(args.to_vec())
+    }
+}
+
+macro_rules! impl_from_lua_multi {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: FromLua),*> FromLuaMulti for ($($t,)*) {
+            #[allow(unused_variables)]
+            fn from_lua_multi(args: &[Value]) -> Result<Self, (usize, String)> {
+                Ok(($($t::from_lua(args.get($i)).map_err(|e| ($i + 1, e))?,)*))
+            }
+        }
+    }
+}
+impl_from_lua_multi!();
+impl_from_lua_multi!(A 0);
+impl_from_lua_multi!(A 0, B 1);
+impl_from_lua_multi!(A 0, B 1, C 2);
+impl_from_lua_multi!(A 0, B 1, C 2, D 3);
+impl_from_lua_multi!(A 0, B 1, C 2, D 3, E 4);
+impl_from_lua_multi!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+macro_rules! impl_into_lua {
+    ($($t:ty),*) => { $(
+        impl IntoLua for $t {
+            fn into_lua(self) -> Value {
+                self.into()
+            }
+        }
+    )* }
+}
+impl_into_lua!(Value, bool, i64, i32, u32, usize, f64, String, &str, Vec<u8>, &[u8],
+    Table, TableHandle);
+
+impl IntoLua for Rc<RefCell<Table>> {
+    fn into_lua(self) -> Value {
+        Value::Table(self)
+    }
+}
+
+// nil for None
+impl<T: IntoLua> IntoLua for Option<T> {
+    fn into_lua(self) -> Value {
+        self.map_or(Value::Nil, T::into_lua)
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for T {
+    fn into_lua_multi(self) -> Vec<Value> {
+        vec![self.into_lua()]
+    }
+}
+
+impl IntoLuaMulti for Vec<Value> {
+    fn into_lua_multi(self) -> Vec<Value> {
+        self
+    }
+}
+
+// raise the error by Err, e.g. for `?` in the function
+impl<T: IntoLuaMulti, E: fmt::Display> IntoLuaMulti for Result<T, E> {
+    fn into_lua_multi(self) -> Vec<Value> {
+        match self {
+            Ok(v) => v.into_lua_multi(),
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+macro_rules! impl_into_lua_multi {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: IntoLua),*> IntoLuaMulti for ($($t,)*) {
+            fn into_lua_multi(self) -> Vec<Value> {
+                vec![$(self.$i.into_lua()),*]
+            }
+        }
+    }
+}
+impl_into_lua_multi!();
+impl_into_lua_multi!(A 0);
+impl_into_lua_multi!(A 0, B 1);
+impl_into_lua_multi!(A 0, B 1, C 2);
+impl_into_lua_multi!(A 0, B 1, C 2, D 3);
+impl_into_lua_multi!(A 0, B 1, C 2, D 3, E 4);
+impl_into_lua_multi!(A 0, B 1, C 2, D 3, E 4, F 5);
@@ -0,0 +1,68 @@
+// Classes of bytes for patterns, e.g. `%a` and `%d`, and for
+// `string.upper()` and `string.lower()`, same with the "C" locale of the
+// official Lua. They do not depend on the locale of the process, so
+// bytes >= 0x80, e.g. of UTF-8 sequences, are in none of the classes,
+// and are not changed by the case conversions.
+
+const ALPHA: u16 = 1 << 0;
+const DIGIT: u16 = 1 << 1;
+const LOWER: u16 = 1 << 2;
+const UPPER: u16 = 1 << 3;
+const SPACE: u16 = 1 << 4;
+const XDIGIT: u16 = 1 << 5;
+const PUNCT: u16 = 1 << 6;
+const CNTRL: u16 = 1 << 7;
+const GRAPH: u16 = 1 << 8;
+
+static CLASSES: [u16; 256] = build_classes();
+
+const fn build_classes() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 128 {
+        let b = i as u8;
+        let mut c = 0;
+        if b.is_ascii_alphabetic() { c |= ALPHA; }
+        if b.is_ascii_digit() { c |= DIGIT; }
+        if b.is_ascii_lowercase() { c |= LOWER; }
+        if b.is_ascii_uppercase() { c |= UPPER; }
+        // isspace() includes '\v', but is_ascii_whitespace() does not
+        if b.is_ascii_whitespace() || b == 0x0b { c |= SPACE; }
+        if b.is_ascii_hexdigit() { c |= XDIGIT; }
+        if b.is_ascii_punctuation() { c |= PUNCT; }
+        if b.is_ascii_control() { c |= CNTRL; }
+        if b.is_ascii_graphic() { c |= GRAPH; }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+// Whether @b is in the class of the letter @class, e.g. b'a' for `%a`,
+// where the upper case letter is the complement, e.g. b'A' for `%A`.
+// Return None if @class is not a class letter.
+pub fn in_class(class: u8, b: u8) -> Option<bool> {
+    let flags = match class.to_ascii_lowercase() {
+        b'a' => ALPHA,
+        b'd' => DIGIT,
+        b'l' => LOWER,
+        b'u' => UPPER,
+        b's' => SPACE,
+        b'w' => ALPHA | DIGIT,
+        b'x' => XDIGIT,
+        b'p' => PUNCT,
+        b'c' => CNTRL,
+        b'g' => GRAPH,
+        _ => return None,
+    };
+    let is_in = CLASSES[b as usize] & flags != 0;
+    Some(is_in != class.is_ascii_uppercase())
+}
+
+pub fn to_upper(b: u8) -> u8 {
+    if CLASSES[b as usize] & LOWER != 0 { b - b'a' + b'A' } else { b }
+}
+
+pub fn to_lower(b: u8) -> u8 {
+    if CLASSES[b as usize] & UPPER != 0 { b - b'A' + b'a' } else { b }
+}
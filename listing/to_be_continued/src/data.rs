@@ -0,0 +1,128 @@
+use std::io::Read;
+use crate::lex::{Lex, Token};
+use crate::parse::{short_source, MAX_SYNTAX_DEPTH};
+use crate::value::{Value, Table};
+
+// Load data files in Lua syntax, e.g. game data or configurations,
+// which are a single value, usually a big table constructor:
+//
+//     return {
+//         { id = 1, name = "sword", damage = 12.5, tags = { "melee" } },
+//         { id = 2, name = "bow", damage = 8, ["range"] = 30 },
+//         ...
+//     }
+//
+// The tables are built while reading the file, without byte codes and
+// constants, so the memory is bounded by the result itself. By
+// parse::load(), the byte codes and constants of such a file are
+// several times larger than the data, and stay until the chunk is run.
+//
+// Only constants are allowed: nil, booleans, numbers with optional unary
+// minus, strings and nested table constructors. The leading `return`
+// and a trailing `;` are optional. Other expressions, e.g. `1 + 2` or
+// `f()`, raise syntax errors, and the file should be loaded by
+// parse::load() instead.
+//
+// Same with the official Lua, positional fields win over explicit
+// integer keys, e.g. `{ [1] = "a", "b" }` is `{ "b" }`.
+pub fn load(input: impl Read, chunk_name: &str) -> Value {
+    let mut reader = DataReader {
+        lex: Lex::new(input, short_source(chunk_name)),
+        depth: 0,
+    };
+    if reader.lex.peek() == Token::Return {
+        reader.lex.next();
+    }
+    let value = reader.value();
+    if reader.lex.peek() == Token::SemiColon {
+        reader.lex.next();
+    }
+    reader.lex.expect(Token::Eos);
+    value
+}
+
+struct DataReader<R: Read> {
+    lex: Lex<R>,
+    depth: usize, // nested tables, limited same with the parser
+}
+
+impl<R: Read> DataReader<R> {
+    fn value(&mut self) -> Value {
+        match self.lex.next() {
+            Token::Nil => Value::Nil,
+            Token::True => Value::Boolean(true),
+            Token::False => Value::Boolean(false),
+            Token::Integer(i) => Value::Integer(i),
+            Token::Float(f) => Value::Float(f),
+            Token::String => Value::from(self.lex.string()),
+            Token::Sub => match self.lex.next() {
+                Token::Integer(i) => Value::Integer(i.wrapping_neg()),
+                Token::Float(f) => Value::Float(-f),
+                t => self.unexpected(t),
+            }
+            Token::CurlyL => self.table(),
+            t => self.unexpected(t),
+        }
+    }
+
+    // BNF:
+    //   tableconstructor ::= `{` [fieldlist] `}`
+    //   fieldlist ::= field {fieldsep field} [fieldsep]
+    //   field ::= `[` exp `]` `=` exp | Name `=` exp | exp
+    //   fieldsep ::= `,` | `;`
+    fn table(&mut self) -> Value {
+        self.depth += 1;
+        if self.depth > MAX_SYNTAX_DEPTH {
+            self.lex.syntax_error("chunk has too many syntax levels".into());
+        }
+
+        let mut table = Table::new(0, 0);
+        let mut narray = 0;
+        loop {
+            match self.lex.peek() {
+                Token::CurlyR => {
+                    self.lex.next();
+                    break;
+                }
+                Token::SqurL => {
+                    self.lex.next();
+                    let key = self.value();
+                    self.lex.expect(Token::SqurR);
+                    self.lex.expect(Token::Assign);
+                    let value = self.value();
+                    match key {
+                        Value::Nil => self.lex.syntax_error("nil can not be table key".into()),
+                        Value::Float(f) if f.is_nan() =>
+                            self.lex.syntax_error("NaN can not be table key".into()),
+                        Value::Integer(i) if i >= 1 && i <= narray => (), // overwritten
+                        _ => table.new_index(key, value),
+                    }
+                }
+                Token::Name(sym) => {
+                    self.lex.next();
+                    self.lex.expect(Token::Assign);
+                    let value = self.value();
+                    table.new_index(Value::from(&*self.lex.name(sym)), value);
+                }
+                _ => {
+                    narray += 1;
+                    let value = self.value();
+                    table.new_index_array(narray, value);
+                }
+            }
+
+            match self.lex.next() {
+                Token::SemiColon | Token::Comma => (),
+                Token::CurlyR => break,
+                t => self.unexpected(t),
+            }
+        }
+
+        self.depth -= 1;
+        Value::from(table)
+    }
+
+    fn unexpected(&self, t: Token) -> ! {
+        self.lex.syntax_error(format!("constant expected near {}", self.lex.describe(t)))
+    }
+}
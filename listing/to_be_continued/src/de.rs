@@ -0,0 +1,245 @@
+use std::fmt;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use crate::error::LuaError;
+use crate::utils::ftoi;
+use crate::value::Value;
+
+// Deserialize Lua values into Rust types by serde, e.g. the tables of
+// configuration files into structs:
+//
+//     #[derive(Deserialize)]
+//     struct Server { name: String, port: u16, tags: Vec<String> }
+//
+//     let server: Server = lua_rs::de::from_value(lua.globals().get("server"))?;
+//
+// Tables are maps or structs by their keys, and sequences by the array
+// part, so `{"a", "b"}` is a Vec. Enums are strings for the unit
+// variants, or tables of one key for the others, e.g. `{tcp = 8080}`.
+// Integers are from integers and floats with exact integer values, same
+// with `TryFrom<Value>`, but strings are not converted into numbers.
+//
+// It's built with the cargo feature "serde".
+pub fn from_value<T: DeserializeOwned>(v: Value) -> Result<T, Error> {
+    T::deserialize(Deserializer(v))
+}
+
+// the message of serde, e.g. "missing field `port`"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl From<Error> for LuaError {
+    fn from(e: Error) -> Self {
+        LuaError::runtime(e.0)
+    }
+}
+
+pub struct Deserializer(Value);
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Deserializer;
+    fn into_deserializer(self) -> Deserializer {
+        Deserializer(self)
+    }
+}
+
+impl Deserializer {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match &self.0 {
+            Value::Nil => Unexpected::Unit,
+            &Value::Boolean(b) => Unexpected::Bool(b),
+            &Value::Integer(i) => Unexpected::Signed(i),
+            &Value::Float(f) => Unexpected::Float(f),
+            v => match v.as_bytes() {
+                Some(s) => match std::str::from_utf8(s) {
+                    Ok(s) => Unexpected::Str(s),
+                    Err(_) => Unexpected::Bytes(s),
+                }
+                None => Unexpected::Other(v.type_name()),
+            }
+        }
+    }
+
+    // the integer of integers and integral floats
+    fn integer(&self, exp: &dyn de::Expected) -> Result<i64, Error> {
+        match self.0 {
+            Value::Integer(i) => Ok(i),
+            Value::Float(f) => ftoi(f).ok_or_else(|| de::Error::invalid_value(self.unexpected(), exp)),
+            _ => Err(de::Error::invalid_type(self.unexpected(), exp)),
+        }
+    }
+}
+
+// integers are visited as i64, and the visitors check the range
+macro_rules! deserialize_integer {
+    ($($method:ident)*) => { $(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            let i = self.integer(&visitor)?;
+            visitor.visit_i64(i)
+        }
+    )* }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Table(ref t) => {
+                if t.borrow().map.is_empty() && !t.borrow().array.is_empty() {
+                    self.deserialize_seq(visitor)
+                } else {
+                    self.deserialize_map(visitor)
+                }
+            }
+            ref v => match v.as_bytes() {
+                Some(s) => match std::str::from_utf8(s) {
+                    Ok(s) => visitor.visit_string(s.to_string()),
+                    Err(_) => visitor.visit_byte_buf(s.to_vec()),
+                }
+                None => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+            }
+        }
+    }
+
+    deserialize_integer!(deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64);
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0.as_bytes() {
+            Some(s) => visitor.visit_byte_buf(s.to_vec()),
+            None => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+            -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // the array part, where the holes are nil
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let Value::Table(t) = &self.0 else {
+            return Err(de::Error::invalid_type(self.unexpected(), &visitor));
+        };
+        let array = t.borrow().array.clone();
+        let mut seq = SeqDeserializer::new(array.into_iter());
+        let v = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(v)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V)
+            -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    // all entries, where the keys of the array part are integers
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let Value::Table(t) = &self.0 else {
+            return Err(de::Error::invalid_type(self.unexpected(), &visitor));
+        };
+        let entries: Vec<(Value, Value)> = {
+            let t = t.borrow();
+            let array = t.array.iter().enumerate()
+                .filter(|(_, v)| !matches!(v, Value::Nil))
+                .map(|(i, v)| (Value::Integer(i as i64 + 1), v.clone()));
+            array.chain(t.map.iter().map(|(k, v)| (k.clone(), v.clone()))).collect()
+        };
+        let mut map = MapDeserializer::new(entries.into_iter());
+        let v = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(v)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, _fields: &'static [&'static str],
+            visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str],
+            visitor: V) -> Result<V::Value, Error> {
+        if let Some(s) = self.0.as_str() {
+            return visitor.visit_enum(s.to_string().into_deserializer());
+        }
+        let Value::Table(t) = &self.0 else {
+            return Err(de::Error::invalid_type(self.unexpected(), &"string or table"));
+        };
+        let t = t.borrow();
+        match (t.array.is_empty(), t.map.len(), t.map.iter().next()) {
+            (true, 1, Some((k, v))) => visitor.visit_enum(Enum(k.clone(), v.clone())),
+            _ => Err(de::Error::invalid_value(Unexpected::Map, &"table of one key")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char str string unit unit_struct identifier ignored_any
+    }
+}
+
+// the variant of a table `{variant = value}`
+struct Enum(Value, Value);
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = Error;
+    type Variant = Deserializer;
+
+    fn variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Deserializer), Error> {
+        let variant = seed.deserialize(Deserializer(self.0))?;
+        Ok((variant, Deserializer(self.1)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V)
+            -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt::Write;
+use crate::bytecode::ByteCode;
+use crate::parse::FuncProto;
+use crate::value::Value;
+
+// Disassemble the function and its inner functions, as `luac -l -l`.
+// Names of locals, upvalues and constants are shown in comments, e.g.
+//
+//     function <main> (4 byte codes, 1 params, 4 slots, 0 upvalues, 2 locals, 1 constants)
+//       [0]  LoadInt(1, 7)          ; local 'a'
+//       [1]  GetField(2, 0, 0)      ; local '_ENV', "print"
+//       ...
+//     constants (1):
+//       [0]  "print"
+//     locals (2):
+//       [0]  _ENV  0  4
+//       [1]  a     1  4
+//     upvalues (0):
+//
+// Inner functions are named by their indexes in constants, e.g.
+// `<main[2][0]>`, and are listed after the outer one.
+pub fn disassemble(proto: &FuncProto) -> String {
+    let mut buf = String::new();
+    disassemble_into(proto, "main", &mut buf);
+    buf
+}
+
+fn disassemble_into(proto: &FuncProto, name: &str, buf: &mut String) {
+    let _ = writeln!(buf, "function <{name}> ({} byte codes, {} params{}, {} slots, \
+            {} upvalues, {} locals, {} constants)",
+        proto.byte_codes.len(), proto.nparam, if proto.has_varargs { "+" } else { "" },
+        proto.max_stack_size, proto.upindexes.len(), proto.locals.len(), proto.constants.len());
+
+    for (i, code) in proto.byte_codes.iter().enumerate() {
+        let code_str = format!("{code:?}");
+        let comment = comment(proto, i, name);
+        if comment.is_empty() {
+            let _ = writeln!(buf, "  [{i}]  {code_str}");
+        } else {
+            let _ = writeln!(buf, "  [{i}]  {code_str:<24}; {}", comment.join(", "));
+        }
+    }
+
+    let _ = writeln!(buf, "constants ({}):", proto.constants.len());
+    for (i, c) in proto.constants.iter().enumerate() {
+        let _ = writeln!(buf, "  [{i}]  {}", const_str(c, &format!("{name}[{i}]")));
+    }
+
+    let _ = writeln!(buf, "locals ({}):", proto.locals.len());
+    let width = proto.locals.iter().map(|v| v.name.len()).max().unwrap_or(0);
+    for (i, var) in proto.locals.iter().enumerate() {
+        let end = var.icode_end.min(proto.byte_codes.len());
+        let _ = writeln!(buf, "  [{i}]  {:<width$}  {}  {end}", var.name, var.icode_start);
+    }
+
+    let _ = writeln!(buf, "upvalues ({}):", proto.upindexes.len());
+    let width = proto.upvalue_names.iter().map(String::len).max().unwrap_or(0);
+    for (i, (name, up)) in proto.upvalue_names.iter().zip(&proto.upindexes).enumerate() {
+        let _ = writeln!(buf, "  [{i}]  {name:<width$}  {up:?}");
+    }
+
+    for (i, c) in proto.constants.iter().enumerate() {
+        if let Value::LuaFunction(f) = c {
+            buf.push('\n');
+            disassemble_into(f, &format!("{name}[{i}]"), buf);
+        }
+    }
+}
+
+fn const_str(c: &Value, name: &str) -> String {
+    match c {
+        Value::LuaFunction(_) => format!("function <{name}>"),
+        _ => match c.as_bytes() {
+            Some(s) => format!("{:?}", String::from_utf8_lossy(s)),
+            None => c.to_string(),
+        }
+    }
+}
+
+// names of the local set by the byte code, and of the upvalues and the
+// constants it takes
+fn comment(proto: &FuncProto, icode: usize, name: &str) -> Vec<String> {
+    use ByteCode::*;
+
+    let code = proto.byte_codes[icode];
+    let mut items = Vec::new();
+
+    // the local is in scope after the byte code which initializes it
+    let local = |reg: u8| proto.local_name(reg, icode)
+        .or_else(|| proto.local_name(reg, icode + 1))
+        .map(|name| format!("local '{name}'"));
+    let upvalue = |up: u8| proto.upvalue_names.get(up as usize)
+        .map(|name| format!("upvalue '{name}'"));
+    let constant = |k: usize| proto.constants.get(k)
+        .map(|c| const_str(c, &format!("{name}[{k}]")));
+
+    if let Some(dst) = code.dst() {
+        items.extend(local(dst));
+    }
+    match code {
+        LoadConst(_, k) => items.extend(constant(k as usize)),
+        LoadConstX(_) | ClosureX(_) => items.extend(constant(proto.byte_codes[icode + 1].ax())),
+        Closure(_, k) => items.extend(constant(k as usize)),
+        GetUpvalue(_, up) | SetUpvalue(up, _) => items.extend(upvalue(up)),
+        SetUpvalueConst(up, k) => {
+            items.extend(upvalue(up));
+            items.extend(constant(k as usize));
+        }
+        GetUpField(_, t, k) | SetUpField(t, k, _) => {
+            items.extend(upvalue(t));
+            items.extend(constant(k as usize));
+        }
+        SetUpFieldConst(t, k, v) => {
+            items.extend(upvalue(t));
+            items.extend(constant(k as usize));
+            items.extend(constant(v as usize));
+        }
+        GetField(_, t, k) | SetField(t, k, _) | GetFieldSelf(_, t, k) => {
+            items.extend(local(t));
+            items.extend(constant(k as usize));
+        }
+        SetFieldConst(t, k, v) => {
+            items.extend(local(t));
+            items.extend(constant(k as usize));
+            items.extend(constant(v as usize));
+        }
+        SetTableConst(_, _, v) | SetIntConst(_, _, v) => items.extend(constant(v as usize)),
+        _ => (),
+    }
+    items
+}
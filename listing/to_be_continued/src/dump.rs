@@ -0,0 +1,389 @@
+use std::rc::Rc;
+use crate::builder;
+use crate::bytecode::{ByteCode, Instruction};
+use crate::parse::{FuncProto, LocalVar, UpIndex};
+use crate::value::Value;
+
+// Binary chunks, dumped from compiled functions and loaded without
+// parsing. The format is portable: all numbers are little-endian with
+// fixed sizes whatever the host is, and lengths and counts are varints
+// but not `usize`. So chunks dumped on x86_64 load on ARM or 32-bit
+// WASM. Chunks from other builds, e.g. with different byte codes, or
+// from the official Lua, are rejected by the header:
+//
+//     signature    "\x1bLua"
+//     version      0x54, Lua 5.4
+//     format       'R' and FORMAT_VERSION, not the official format 0
+//     data         "\x19\x93\r\n\x1a\n", to detect text-mode conversions
+//     sizes        of Instruction, integer and float, in bytes
+//     checks       CHECK_INT and CHECK_FLOAT, to detect byte orders
+//
+// followed by the main function:
+//
+//     source       string, the chunk name, empty if stripped
+//     nparam       varint
+//     has_varargs  byte
+//     stack size   varint
+//     byte codes   varint count, then Instructions
+//     constants    varint count, then tagged values, with inner functions
+//     upindexes    varint count, then tag bytes and varints
+//     locals       varint count, then names and scopes, 0 if stripped
+//     upvalues     varint count, then names, 0 if stripped
+//     lines        varint count, then varint lines, 0 if stripped
+//
+// Strings are varint lengths followed by the bytes. The operands of the
+// byte codes are checked after loading each function, same with the ones
+// built by builder::ProtoBuilder, so corrupted chunks are rejected
+// instead of panicking in the VM.
+
+const SIGNATURE: &[u8] = b"\x1bLua";
+const VERSION: u8 = 0x54;
+const FORMAT: u8 = b'R';
+const FORMAT_VERSION: u8 = 1;
+const DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const CHECK_INT: i64 = 0x5678;
+const CHECK_FLOAT: f64 = 370.5;
+
+// tags of constants
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_FUNCTION: u8 = 6;
+
+// Dump the function, with its inner functions, into a binary chunk.
+// The debug information, names of locals and upvalues and the chunk
+// name, is omitted if @strip.
+pub fn dump(proto: &FuncProto, strip: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(SIGNATURE);
+    buf.extend_from_slice(&[VERSION, FORMAT, FORMAT_VERSION]);
+    buf.extend_from_slice(DATA);
+    buf.extend_from_slice(&[
+        size_of::<Instruction>() as u8,
+        size_of::<i64>() as u8,
+        size_of::<f64>() as u8,
+    ]);
+    buf.extend_from_slice(&CHECK_INT.to_le_bytes());
+    buf.extend_from_slice(&CHECK_FLOAT.to_le_bytes());
+    dump_function(proto, strip, &mut buf);
+    buf
+}
+
+// Return whether @chunk is a binary chunk but not source code, by the
+// first byte of the signature, which is not valid in source code.
+pub fn is_binary(chunk: &[u8]) -> bool {
+    chunk.first() == Some(&SIGNATURE[0])
+}
+
+fn dump_function(proto: &FuncProto, strip: bool, buf: &mut Vec<u8>) {
+    dump_string(if strip { b"" } else { proto.chunk_name.as_bytes() }, buf);
+    dump_varint(proto.nparam, buf);
+    buf.push(proto.has_varargs as u8);
+    dump_varint(proto.max_stack_size, buf);
+
+    dump_varint(proto.byte_codes.len(), buf);
+    for &code in &proto.byte_codes {
+        buf.extend_from_slice(&Instruction::from(code).0.to_le_bytes());
+    }
+
+    dump_varint(proto.constants.len(), buf);
+    for c in &proto.constants {
+        match c {
+            Value::Nil => buf.push(TAG_NIL),
+            Value::Boolean(false) => buf.push(TAG_FALSE),
+            Value::Boolean(true) => buf.push(TAG_TRUE),
+            Value::Integer(i) => {
+                buf.push(TAG_INTEGER);
+                buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                buf.push(TAG_FLOAT);
+                buf.extend_from_slice(&f.to_le_bytes());
+            }
+            Value::LuaFunction(f) => {
+                buf.push(TAG_FUNCTION);
+                dump_function(f, strip, buf);
+            }
+            _ => match c.as_bytes() {
+                Some(s) => {
+                    buf.push(TAG_STRING);
+                    dump_string(s, buf);
+                }
+                None => unreachable!("invalid constant {c:?}"),
+            }
+        }
+    }
+
+    dump_varint(proto.upindexes.len(), buf);
+    for up in &proto.upindexes {
+        let (tag, i) = match up {
+            UpIndex::Local(i) => (0, i),
+            UpIndex::Upvalue(i) => (1, i),
+        };
+        buf.push(tag);
+        dump_varint(*i, buf);
+    }
+
+    if strip {
+        buf.extend_from_slice(&[0, 0, 0]);
+        return;
+    }
+    dump_varint(proto.locals.len(), buf);
+    for var in &proto.locals {
+        dump_string(var.name.as_bytes(), buf);
+        dump_varint(var.icode_start, buf);
+        dump_varint(var.icode_end, buf);
+    }
+    dump_varint(proto.upvalue_names.len(), buf);
+    for name in &proto.upvalue_names {
+        dump_string(name.as_bytes(), buf);
+    }
+    dump_varint(proto.lines.len(), buf);
+    for &line in &proto.lines {
+        dump_varint(line as usize, buf);
+    }
+}
+
+// 7 bits a byte, low bits first, with the high bit set if more bytes
+fn dump_varint(n: usize, buf: &mut Vec<u8>) {
+    let mut n = n as u64;
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn dump_string(s: &[u8], buf: &mut Vec<u8>) {
+    dump_varint(s.len(), buf);
+    buf.extend_from_slice(s);
+}
+
+// Load the binary chunk dumped by dump(). The @chunk_name is used in
+// error messages only, see parse::load_named().
+//
+// Invalid chunks raise errors as syntax errors, e.g.
+// "name: bad binary format (truncated chunk)".
+pub fn undump(chunk: &[u8], chunk_name: &str) -> FuncProto {
+    let name = match chunk_name.as_bytes().first() {
+        Some(b'@' | b'=') => &chunk_name[1..],
+        Some(&b) if b == SIGNATURE[0] => "binary string",
+        _ => chunk_name,
+    };
+    let mut undumper = Undumper { chunk, pos: 0, name };
+    undumper.header();
+    let proto = undumper.function("");
+    if undumper.pos != chunk.len() {
+        undumper.error("extra bytes");
+    }
+    proto
+}
+
+struct Undumper<'a> {
+    chunk: &'a [u8],
+    pos: usize,
+    name: &'a str,
+}
+
+impl<'a> Undumper<'a> {
+    fn error(&self, why: &str) -> ! {
+        panic!("{}: bad binary format ({why})", self.name)
+    }
+
+    fn bytes(&mut self, n: usize) -> &'a [u8] {
+        if n > self.chunk.len() - self.pos {
+            self.error("truncated chunk");
+        }
+        let s = &self.chunk[self.pos .. self.pos + n];
+        self.pos += n;
+        s
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.bytes(1)[0]
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes(4).try_into().unwrap())
+    }
+    fn i64(&mut self) -> i64 {
+        i64::from_le_bytes(self.bytes(8).try_into().unwrap())
+    }
+    fn f64(&mut self) -> f64 {
+        f64::from_le_bytes(self.bytes(8).try_into().unwrap())
+    }
+
+    // varints which overflow usize are rejected, e.g. on 32-bit hosts
+    fn varint(&mut self) -> usize {
+        let n = self.varint64();
+        usize::try_from(n).unwrap_or_else(|_| self.error("size overflow"))
+    }
+
+    fn varint64(&mut self) -> u64 {
+        let mut n: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte();
+            if shift >= 64 || (shift == 63 && b > 1) {
+                self.error("integer overflow");
+            }
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        n
+    }
+
+    // a count of items which take at least @item_size bytes each, so
+    // corrupted counts fail without allocating huge memory
+    fn count(&mut self, item_size: usize) -> usize {
+        let n = self.varint();
+        if n.saturating_mul(item_size) > self.chunk.len() - self.pos {
+            self.error("truncated chunk");
+        }
+        n
+    }
+
+    fn string(&mut self) -> &'a [u8] {
+        let len = self.varint();
+        self.bytes(len)
+    }
+
+    fn utf8_string(&mut self) -> String {
+        match std::str::from_utf8(self.string()) {
+            Ok(s) => s.to_string(),
+            Err(_) => self.error("invalid name"),
+        }
+    }
+
+    fn check_literal(&mut self, s: &[u8], why: &str) {
+        if self.bytes(s.len()) != s {
+            self.error(why);
+        }
+    }
+
+    fn check_size(&mut self, size: usize, what: &str) {
+        if self.byte() as usize != size {
+            self.error(&format!("{what} size mismatch"));
+        }
+    }
+
+    fn header(&mut self) {
+        self.check_literal(SIGNATURE, "not a binary chunk");
+        if self.byte() != VERSION {
+            self.error("version mismatch");
+        }
+        if self.byte() != FORMAT {
+            self.error("format mismatch");
+        }
+        if self.byte() != FORMAT_VERSION {
+            self.error("format version mismatch");
+        }
+        self.check_literal(DATA, "corrupted chunk");
+        self.check_size(size_of::<Instruction>(), "Instruction");
+        self.check_size(size_of::<i64>(), "integer");
+        self.check_size(size_of::<f64>(), "float");
+        if self.i64() != CHECK_INT {
+            self.error("integer format mismatch");
+        }
+        if self.f64() != CHECK_FLOAT {
+            self.error("float format mismatch");
+        }
+    }
+
+    // Stripped functions take the source of @parent, or "=?" for the
+    // main function, same with the official Lua.
+    fn function(&mut self, parent: &str) -> FuncProto {
+        let mut source = self.utf8_string();
+        if source.is_empty() {
+            source = match parent {
+                "" => String::from("=?"),
+                _ => parent.to_string(),
+            };
+        }
+        let nparam = self.varint();
+        let has_varargs = self.byte() != 0;
+        let max_stack_size = self.varint();
+
+        let n = self.count(4);
+        let mut byte_codes = Vec::with_capacity(n);
+        for _ in 0..n {
+            let code = ByteCode::try_from(Instruction(self.u32()))
+                .unwrap_or_else(|op| self.error(&format!("invalid opcode {op}")));
+            byte_codes.push(code);
+        }
+
+        let n = self.count(1);
+        let mut constants = Vec::with_capacity(n);
+        for _ in 0..n {
+            let c = match self.byte() {
+                TAG_NIL => Value::Nil,
+                TAG_FALSE => Value::Boolean(false),
+                TAG_TRUE => Value::Boolean(true),
+                TAG_INTEGER => Value::Integer(self.i64()),
+                TAG_FLOAT => Value::Float(self.f64()),
+                TAG_STRING => Value::from(self.string()),
+                TAG_FUNCTION => Value::LuaFunction(Rc::new(self.function(&source))),
+                tag => self.error(&format!("invalid constant tag {tag}")),
+            };
+            constants.push(c);
+        }
+
+        let n = self.count(2);
+        let mut upindexes = Vec::with_capacity(n);
+        for _ in 0..n {
+            let up = match self.byte() {
+                0 => UpIndex::Local(self.varint()),
+                1 => UpIndex::Upvalue(self.varint()),
+                tag => self.error(&format!("invalid upvalue tag {tag}")),
+            };
+            upindexes.push(up);
+        }
+
+        let n = self.count(3);
+        let mut locals = Vec::with_capacity(n);
+        for _ in 0..n {
+            locals.push(LocalVar {
+                name: self.utf8_string(),
+                icode_start: self.varint(),
+                // usize::MAX for scopes to the end, saturated on 32-bit hosts
+                icode_end: usize::try_from(self.varint64()).unwrap_or(usize::MAX),
+            });
+        }
+        let n = self.count(1);
+        let mut upvalue_names = Vec::with_capacity(n);
+        for _ in 0..n {
+            upvalue_names.push(self.utf8_string());
+        }
+        let n = self.count(1);
+        let mut lines = Vec::with_capacity(n);
+        for _ in 0..n {
+            let line = u32::try_from(self.varint64())
+                .unwrap_or_else(|_| self.error("line overflow"));
+            lines.push(line);
+        }
+
+        let proto = FuncProto {
+            has_varargs,
+            nparam,
+            constants,
+            upindexes,
+            byte_codes,
+            max_stack_size,
+            chunk_name: source,
+            lines,
+            locals,
+            upvalue_names,
+        };
+        // the VM trusts the operands, e.g. indexes of constants
+        if let Err(e) = builder::check_proto(&proto) {
+            self.error(&e.to_string());
+        }
+        proto
+    }
+}
@@ -0,0 +1,146 @@
+use std::io;
+use std::path::PathBuf;
+
+// max number of lines kept in the history file
+#[cfg(feature = "readline")]
+const HISTORY_MAX: usize = 1000;
+
+// Return the start position of the word to complete in the line before
+// the cursor, and the candidates to replace it, e.g. Repl::complete().
+pub type Completer = Box<dyn Fn(&str) -> (usize, Vec<String>)>;
+
+// Line editor for the REPL.
+//
+// With the "readline" feature, it's rustyline, supporting the editing
+// keys, browsing the history by Up/Down, and completion by Tab with the
+// Completer. Lines are appended into the history file once entered, so
+// the history is kept even if the REPL is killed.
+//
+// Otherwise, lines are read from stdin without editing, and neither the
+// history nor the completer is used.
+pub struct LineEditor {
+    #[cfg(feature = "readline")]
+    editor: rustyline::Editor<readline::Helper, rustyline::history::FileHistory>,
+    #[cfg(feature = "readline")]
+    history_file: Option<PathBuf>,
+}
+
+impl LineEditor {
+    // Load the history from @history_file if any, and append new lines
+    // into it. A missing file is fine, which is created by the first line.
+    #[cfg(feature = "readline")]
+    pub fn new(completer: Completer, history_file: Option<PathBuf>) -> io::Result<Self> {
+        use rustyline::config::Config;
+        use rustyline::Editor;
+
+        let config = Config::builder()
+            .max_history_size(HISTORY_MAX)
+            .and_then(|b| b.history_ignore_dups(true))
+            .map_err(readline::io_error)?
+            .build();
+        let mut editor = Editor::with_config(config).map_err(readline::io_error)?;
+        editor.set_helper(Some(readline::Helper(completer)));
+        if let Some(path) = &history_file {
+            let _ = editor.load_history(path);
+        }
+        Ok(LineEditor { editor, history_file })
+    }
+
+    #[cfg(not(feature = "readline"))]
+    pub fn new(_completer: Completer, _history_file: Option<PathBuf>) -> io::Result<Self> {
+        Ok(LineEditor {})
+    }
+}
+
+#[cfg(feature = "readline")]
+mod readline {
+    use std::io;
+    use rustyline::completion::Completer as RlCompleter;
+    use rustyline::error::ReadlineError;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::validate::Validator;
+    use rustyline::Context;
+    use super::{Completer, LineEditor};
+
+    // rustyline's helper, which only completes by the Completer
+    pub struct Helper(pub Completer);
+
+    impl RlCompleter for Helper {
+        type Candidate = String;
+
+        fn complete(&self, line: &str, pos: usize, _: &Context<'_>)
+                -> rustyline::Result<(usize, Vec<String>)> {
+            Ok((self.0)(&line[..pos]))
+        }
+    }
+    impl Hinter for Helper {
+        type Hint = String;
+    }
+    impl Highlighter for Helper {}
+    impl Validator for Helper {}
+    impl rustyline::Helper for Helper {}
+
+    pub fn io_error(e: ReadlineError) -> io::Error {
+        match e {
+            ReadlineError::Io(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+
+    impl LineEditor {
+        pub fn history(&self) -> Vec<String> {
+            self.editor.history().iter().cloned().collect()
+        }
+
+        // Add a line into the history, and the file. Empty lines and the
+        // duplicated lines with the last one are skipped. Errors of the
+        // file are ignored, so the REPL goes on without it.
+        pub fn add_history(&mut self, line: &str) {
+            if line.trim().is_empty() {
+                return;
+            }
+            if self.editor.add_history_entry(line).unwrap_or(false) {
+                if let Some(path) = &self.history_file {
+                    let _ = self.editor.append_history(path);
+                }
+            }
+        }
+
+        // Read a line from the terminal, and return None at the end of
+        // input. Ctrl-C discards the line, and Ctrl-D quits on empty line.
+        pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+            loop {
+                match self.editor.readline(prompt) {
+                    Ok(line) => return Ok(Some(line)),
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(ReadlineError::Eof) => return Ok(None),
+                    Err(e) => return Err(io_error(e)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "readline"))]
+impl LineEditor {
+    pub fn history(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    pub fn add_history(&mut self, _line: &str) {}
+
+    // Read a line from stdin, and return None at the end of input.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        use std::io::{BufRead, Write};
+
+        let mut stdout = io::stdout();
+        write!(stdout, "{prompt}")?;
+        stdout.flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
+}
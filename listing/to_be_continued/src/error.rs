@@ -0,0 +1,124 @@
+use std::fmt;
+use std::io;
+use crate::value::{ConversionError, Value};
+
+// errors for embedders, who can match on the kinds
+#[derive(Debug)]
+pub enum LuaError {
+    // error in lexing or parsing the source code, where @source is the
+    // short source of the chunk, see parse::short_source()
+    SyntaxError {
+        source: String,
+        line: usize,
+        msg: String,
+    },
+
+    // error raised when running, by `error()` or by the VM. The @value
+    // is the error object, which is a string message in most cases. The
+    // @traceback is of the Lua functions unwound by the error, e.g.
+    // "stack traceback:\n\ttest.lua:2: in local 'f'", or empty if it's
+    // not caught by the VM. It's displayed by the alternate format only,
+    // e.g. `format!("{err:#}")`.
+    RuntimeError {
+        value: Value,
+        traceback: String,
+    },
+
+    // fail to convert a value into the Rust type, e.g. by `Lua::get()`
+    TypeError(ConversionError),
+
+    // fail to allocate memory, or reach the memory limit
+    MemoryError,
+
+    // fail to read the source code, or in io library
+    IoError(io::Error),
+}
+
+impl LuaError {
+    pub fn syntax(source: impl Into<String>, line: usize, msg: impl Into<String>) -> Self {
+        LuaError::SyntaxError { source: source.into(), line, msg: msg.into() }
+    }
+
+    pub fn runtime(value: impl Into<Value>) -> Self {
+        LuaError::RuntimeError { value: value.into(), traceback: String::new() }
+    }
+
+    // the error object for Lua, e.g. returned by `pcall()`, which is the
+    // message if it's not a runtime error
+    pub fn into_value(self) -> Value {
+        match self {
+            LuaError::RuntimeError { value, .. } => value,
+            e => e.to_string().into(),
+        }
+    }
+
+    // Convert the message "source:line: msg" raised by the lexer and the
+    // parser into SyntaxError, or RuntimeError for other messages without
+    // the position, e.g. of binary chunks.
+    pub(crate) fn from_compile_error(msg: String) -> Self {
+        for (i, _) in msg.match_indices(':') {
+            let rest = &msg[i+1..];
+            let ndigit = rest.bytes().take_while(u8::is_ascii_digit).count();
+            if ndigit > 0 && rest[ndigit..].starts_with(": ") {
+                if let Ok(line) = rest[..ndigit].parse() {
+                    return LuaError::syntax(&msg[..i], line, &rest[ndigit+2..]);
+                }
+            }
+        }
+        LuaError::runtime(msg)
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LuaError::SyntaxError { source, line, msg } => write!(f, "{source}:{line}: {msg}"),
+            LuaError::RuntimeError { value, traceback } => {
+                match value {
+                    Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_) |
+                    Value::Integer(_) | Value::Float(_) => write!(f, "{value}")?,
+                    // same with the standalone Lua interpreter
+                    _ => write!(f, "(error object is a {} value)", value.type_name())?,
+                }
+                if f.alternate() && !traceback.is_empty() {
+                    write!(f, "\n{traceback}")?;
+                }
+                Ok(())
+            }
+            LuaError::TypeError(e) => write!(f, "{e}"),
+            LuaError::MemoryError => write!(f, "not enough memory"),
+            LuaError::IoError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+// `Value` is not `Send` because of `Rc`, so `LuaError` neither. Use
+// `to_string()` to convert it into `anyhow::Error` if need.
+impl std::error::Error for LuaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LuaError::IoError(e) => Some(e),
+            LuaError::TypeError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LuaError {
+    fn from(e: io::Error) -> Self {
+        LuaError::IoError(e)
+    }
+}
+
+impl From<ConversionError> for LuaError {
+    fn from(e: ConversionError) -> Self {
+        LuaError::TypeError(e)
+    }
+}
+
+// the message of the host's errors, e.g. by TryFrom of its types
+impl From<String> for LuaError {
+    fn from(msg: String) -> Self {
+        LuaError::runtime(msg)
+    }
+}
@@ -0,0 +1,220 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+use crate::value::{Table, Value};
+use crate::vm::{LuaClosure, Upvalue};
+
+// Collector of reference cycles.
+//
+// Values are freed by reference counting of `Rc`, which leaks cycles,
+// e.g. `a.b = b; b.a = a`, or a table holding a closure whose upvalue
+// refers back to the table. So the objects which may make cycles, the
+// tables, Lua closures and upvalues, are tracked here by `Weak`, and
+// collected by mark-and-sweep, which is triggered by allocation pressure,
+// see should_collect().
+//
+// There is no root set of the VM to mark from. Instead, the references
+// among the tracked objects are counted, and the objects having more
+// `Rc` references than these are referred by others, e.g. the stack, the
+// Rust functions, or the untracked objects, so they are the roots. The
+// objects unreachable from the roots are garbage, which are referred only
+// by each other, and the cycles are broken by clearing them.
+//
+// It's not necessary to replace the `Rc` of the values by an arena, and
+// then it's transparent to the Rust functions. Long strings never refer
+// to others, so they are not tracked. Coroutines and Rust closures are
+// not tracked neither, and the objects referred by them are roots, so
+// cycles through them still leak.
+#[derive(Default)]
+struct Heap {
+    tables: Vec<Weak<RefCell<Table>>>,
+    closures: Vec<Weak<LuaClosure>>,
+    upvalues: Vec<Weak<RefCell<Upvalue>>>,
+
+    // objects tracked since the last collection, which triggers the next
+    // one when reaching @threshold
+    allocated: usize,
+    threshold: usize,
+
+    stopped: bool, // by `collectgarbage("stop")`
+}
+
+// minimal threshold, otherwise it's the number of live objects after the
+// last collection, so the next one is triggered when they are doubled,
+// similar with the default pause of the official Lua
+const MIN_THRESHOLD: usize = 1000;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap { threshold: MIN_THRESHOLD, ..Heap::default() });
+    static CYCLES: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn track_table(t: &Rc<RefCell<Table>>) {
+    HEAP.with_borrow_mut(|heap| {
+        heap.tables.push(Rc::downgrade(t));
+        heap.allocated += 1;
+    });
+}
+
+pub fn track_closure(c: &Rc<LuaClosure>) {
+    HEAP.with_borrow_mut(|heap| {
+        heap.closures.push(Rc::downgrade(c));
+        heap.allocated += 1;
+    });
+}
+
+pub fn track_upvalue(up: &Rc<RefCell<Upvalue>>) {
+    HEAP.with_borrow_mut(|heap| {
+        heap.upvalues.push(Rc::downgrade(up));
+        heap.allocated += 1;
+    });
+}
+
+pub fn should_collect() -> bool {
+    HEAP.with_borrow(|heap| heap.allocated >= heap.threshold && !heap.stopped)
+}
+
+pub fn set_stopped(stopped: bool) {
+    HEAP.with_borrow_mut(|heap| heap.stopped = stopped);
+}
+
+pub fn is_running() -> bool {
+    HEAP.with_borrow(|heap| !heap.stopped)
+}
+
+// number of collections by the current thread
+pub fn cycles() -> u64 {
+    CYCLES.with(Cell::get)
+}
+
+enum Object {
+    Table(Rc<RefCell<Table>>),
+    Closure(Rc<LuaClosure>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+}
+
+impl Object {
+    fn ptr(&self) -> *const () {
+        match self {
+            Object::Table(t) => Rc::as_ptr(t) as *const (),
+            Object::Closure(c) => Rc::as_ptr(c) as *const (),
+            Object::Upvalue(up) => Rc::as_ptr(up) as *const (),
+        }
+    }
+
+    // the `Rc` references, excluding the one in Object
+    fn refs(&self) -> usize {
+        match self {
+            Object::Table(t) => Rc::strong_count(t) - 1,
+            Object::Closure(c) => Rc::strong_count(c) - 1,
+            Object::Upvalue(up) => Rc::strong_count(up) - 1,
+        }
+    }
+
+    // Call @f with the objects referred by this one. Return false if
+    // it's borrowed and can not be traversed, e.g. by a running Rust
+    // function, so it's a root.
+    fn traverse(&self, f: &mut impl FnMut(*const ())) -> bool {
+        let mut value = |v: &Value| match v {
+            Value::Table(t) => f(Rc::as_ptr(t) as *const ()),
+            Value::LuaClosure(c) => f(Rc::as_ptr(c) as *const ()),
+            _ => (),
+        };
+        match self {
+            Object::Table(t) => {
+                let Ok(t) = t.try_borrow() else {
+                    return false;
+                };
+                t.for_each_value(&mut value);
+                if let Some(mt) = &t.metatable {
+                    f(Rc::as_ptr(mt) as *const ());
+                }
+            }
+            Object::Closure(c) => {
+                for up in &c.upvalues {
+                    f(Rc::as_ptr(up) as *const ());
+                }
+            }
+            Object::Upvalue(up) => match up.try_borrow() {
+                Ok(up) => if let Upvalue::Closed(v) = &*up {
+                    value(v);
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+// Run a full collection, and return the number of objects collected.
+pub fn collect() -> usize {
+    // live objects, and the tracking list is pruned
+    let objects: Vec<Object> = HEAP.with_borrow_mut(|heap| {
+        heap.tables.retain(|t| t.strong_count() > 0);
+        heap.closures.retain(|c| c.strong_count() > 0);
+        heap.upvalues.retain(|up| up.strong_count() > 0);
+        heap.tables.iter().filter_map(|t| t.upgrade().map(Object::Table))
+            .chain(heap.closures.iter().filter_map(|c| c.upgrade().map(Object::Closure)))
+            .chain(heap.upvalues.iter().filter_map(|up| up.upgrade().map(Object::Upvalue)))
+            .collect()
+    });
+    let index: HashMap<*const (), usize> = objects.iter().enumerate()
+        .map(|(i, obj)| (obj.ptr(), i))
+        .collect();
+
+    // references from outside of the tracked objects
+    let mut external: Vec<usize> = objects.iter().map(Object::refs).collect();
+    let mut children: Vec<Vec<usize>> = Vec::with_capacity(objects.len());
+    for (i, obj) in objects.iter().enumerate() {
+        let mut list = Vec::new();
+        let traversed = obj.traverse(&mut |p| if let Some(&j) = index.get(&p) {
+            list.push(j);
+        });
+        if !traversed {
+            external[i] = usize::MAX;
+        }
+        for &j in &list {
+            external[j] = external[j].saturating_sub(1);
+        }
+        children.push(list);
+    }
+
+    // mark from the roots
+    let mut marked = vec![false; objects.len()];
+    let mut work: Vec<usize> = (0..objects.len()).filter(|&i| external[i] > 0).collect();
+    while let Some(i) = work.pop() {
+        if !mem::replace(&mut marked[i], true) {
+            work.extend(children[i].iter().filter(|&&j| !marked[j]));
+        }
+    }
+
+    // Break the cycles of garbage. The contents are dropped at the end,
+    // when no table is borrowed, since they may free other tables.
+    let mut tables = Vec::new();
+    let mut values = Vec::new();
+    let mut ngarbage = 0;
+    for (obj, _) in objects.iter().zip(&marked).filter(|(_, &m)| !m) {
+        ngarbage += 1;
+        match obj {
+            Object::Table(t) => tables.push(t.borrow_mut().take_all()),
+            Object::Upvalue(up) => {
+                if let Upvalue::Closed(v) = &mut *up.borrow_mut() {
+                    values.push(mem::replace(v, Value::Nil));
+                }
+            }
+            Object::Closure(_) => (), // freed by the cleared referrers
+        }
+    }
+
+    let live = objects.len() - ngarbage;
+    HEAP.with_borrow_mut(|heap| {
+        heap.allocated = 0;
+        heap.threshold = live.max(MIN_THRESHOLD);
+    });
+    CYCLES.with(|n| n.set(n.get() + 1));
+    drop(objects);
+    drop(tables);
+    drop(values);
+    ngarbage
+}
@@ -1,8 +1,23 @@
 use std::mem;
-use std::io::{Read, Bytes};
-use std::iter::Peekable;
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::io::{Read, ErrorKind};
+use crate::value::Value;
+use crate::utils::str_to_number;
+use crate::error::LuaError;
 
-#[derive(Debug, PartialEq)]
+// Limits of the source, so pathological inputs get syntax errors but
+// not exhaust the memory. The official Lua has no such limits except
+// the memory.
+const MAX_TOKEN_LEN: usize = 1 << 24; // strings, names and numbers
+const MAX_LONG_BRACKET_LEVEL: usize = 255; // `[==[` is level 2
+
+const INPUT_CHUNK: usize = 8192;
+
+// Tokens are Copy without heap payloads, so peeking and matching them
+// cost nothing. The payloads of names and strings are kept by the
+// lexer, see Lex::name() and Lex::string().
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Token {
     // keywords
     And,    Break,  Do,     Else,   Elseif, End,
@@ -24,114 +39,316 @@ pub enum Token {
     // constant values
     Integer(i64),
     Float(f64),
-    String(Vec<u8>),
+    String, // see Lex::string()
 
-    // name of variables or table keys
-    Name(String),
+    // name of variables or table keys, interned by the lexer, so
+    // occurrences of the same name share one allocation
+    Name(Sym),
 
     // end
     Eos,
 }
 
+impl Token {
+    // the source text of keywords and symbols, and `<eof>` for the end
+    pub fn text(self) -> &'static str {
+        match self {
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::Mod => "%",
+            Token::Pow => "^",
+            Token::Len => "#",
+            Token::BitAnd => "&",
+            Token::BitNot => "~",
+            Token::BitOr => "|",
+            Token::ShiftL => "<<",
+            Token::ShiftR => ">>",
+            Token::Idiv => "//",
+            Token::Equal => "==",
+            Token::NotEq => "~=",
+            Token::LesEq => "<=",
+            Token::GreEq => ">=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::Assign => "=",
+            Token::ParL => "(",
+            Token::ParR => ")",
+            Token::CurlyL => "{",
+            Token::CurlyR => "}",
+            Token::SqurL => "[",
+            Token::SqurR => "]",
+            Token::DoubColon => "::",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Dots => "...",
+            Token::Eos => "<eof>",
+            Token::Integer(_) | Token::Float(_) | Token::String | Token::Name(_) =>
+                unreachable!("token with payload: {self:?}"),
+        }
+    }
+}
+
+// interned name, see Lex::name()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sym(u32);
+
 #[derive(Debug)]
 pub struct Lex<R: Read> {
-    input: Peekable::<Bytes::<R>>,
+    input: R,
+    chunk: Box<[u8]>, // read from @input, and lexed in chunk[pos..end]
+    pos: usize,
+    end: usize,
+    eof: bool,
     ahead: Token,
+    buf: Vec<u8>, // reused for reading numbers and names
+    names: HashMap<Rc<str>, Sym>, // interned names, see intern()
+    symbols: Vec<Rc<str>>, // indexed by Sym
+    string: Vec<u8>, // payload of the last Token::String returned by next(), see string()
+    ahead_string: Vec<u8>, // payload of the last Token::String or number read
+    line: usize, // current line number, for error messages
+    ahead_line: usize, // line of the token ahead
+    last_line: usize, // line of the last token returned by next()
+    source: String, // short source of the chunk, for error messages
 }
 
 impl<R: Read> Lex<R> {
-    pub fn new(input: R) -> Self {
+    // the @input is read in chunks, so it needs no buffered reader.
+    // The @source is shown in error messages, see parse::short_source().
+    pub fn new(input: R, source: String) -> Self {
         Lex {
-            input: input.bytes().peekable(),
+            input,
+            chunk: vec![0; INPUT_CHUNK].into_boxed_slice(),
+            pos: 0,
+            end: 0,
+            eof: false,
             ahead: Token::Eos,
+            buf: Vec::new(),
+            names: HashMap::new(),
+            symbols: Vec::new(),
+            string: Vec::new(),
+            ahead_string: Vec::new(),
+            line: 1,
+            ahead_line: 1,
+            last_line: 1,
+            source,
         }
     }
 
+    // not Iterator, since it returns Token::Eos but not None at the end
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Token {
-        if self.ahead == Token::Eos {
-            self.do_next()
+        let t = if self.ahead == Token::Eos {
+            let t = self.do_next();
+            self.last_line = self.line;
+            t
         } else {
+            self.last_line = self.ahead_line;
             mem::replace(&mut self.ahead, Token::Eos)
+        };
+        if matches!(t, Token::String | Token::Integer(_) | Token::Float(_)) {
+            mem::swap(&mut self.string, &mut self.ahead_string);
         }
+        t
     }
 
-    pub fn peek(&mut self) -> &Token {
+    pub fn peek(&mut self) -> Token {
         if self.ahead == Token::Eos {
             self.ahead = self.do_next();
+            self.ahead_line = self.line;
         }
-        &self.ahead
+        self.ahead
+    }
+
+    // the name of Token::Name
+    pub fn name(&self, sym: Sym) -> Rc<str> {
+        self.symbols[sym.0 as usize].clone()
+    }
+
+    // the payload of the last Token::String returned by next(), which
+    // is allocated only here, so the string is read into reused buffer.
+    // After Token::Integer and Token::Float, it's the number as spelled
+    // in the source, e.g. for minify.
+    pub fn string(&self) -> Vec<u8> {
+        self.string.clone()
     }
 //
+    // the message carries the source and the line number, see
+    // `LuaError::SyntaxError`
+    pub fn syntax_error(&self, msg: String) -> ! {
+        panic!("{}", LuaError::syntax(&self.source, self.line, msg))
+    }
+
+    // line of the last token returned by next() but not peek(), for
+    // the byte codes generated by it
+    pub fn last_line(&self) -> usize {
+        self.last_line
+    }
+
+    // the shared string of @name, which is allocated only at the first
+    // occurrence in the chunk
+    pub fn intern(&mut self, name: &str) -> Rc<str> {
+        let sym = self.symbol(name);
+        self.name(sym)
+    }
+    fn symbol(&mut self, name: &str) -> Sym {
+        if let Some(&sym) = self.names.get(name) {
+            return sym;
+        }
+        let sym = Sym(self.symbols.len() as u32);
+        let s: Rc<str> = Rc::from(name);
+        self.symbols.push(s.clone());
+        self.names.insert(s, sym);
+        sym
+    }
+
     pub fn expect(&mut self, t: Token) {
-        assert_eq!(self.next(), t);
+        let got = self.next();
+        if got != t {
+            self.expected(t, got);
+        }
+    }
+
+    // raise "'@t' expected near @got"
+    pub fn expected(&self, t: Token, got: Token) -> ! {
+        let t = match t {
+            Token::Eos => t.text().into(),
+            _ => format!("'{}'", t.text()),
+        };
+        self.syntax_error(format!("{t} expected near {}", self.describe(got)))
+    }
+
+    // the token as in source, for error messages, same with the
+    // official Lua, e.g. `'end'`, `'x'` for names, and `<eof>`
+    pub fn describe(&self, t: Token) -> String {
+        match t {
+            Token::Name(sym) => format!("'{}'", self.name(sym)),
+            Token::String => format!("'\"{}\"'", String::from_utf8_lossy(&self.string)),
+            Token::Integer(i) => format!("'{i}'"),
+            Token::Float(f) => format!("'{}'", Value::Float(f)),
+            Token::Eos => t.text().into(),
+            t => format!("'{}'", t.text()),
+        }
     }
 
     fn do_next(&mut self) -> Token {
-        if let Some(byt) = self.next_byte() {
-            match byt {
-                b'\n' | b'\r' | b'\t' | b' ' => self.do_next(),
-                b'+' => Token::Add,
-                b'*' => Token::Mul,
-                b'%' => Token::Mod,
-                b'^' => Token::Pow,
-                b'#' => Token::Len,
-                b'&' => Token::BitAnd,
-                b'|' => Token::BitOr,
-                b'(' => Token::ParL,
-                b')' => Token::ParR,
-                b'{' => Token::CurlyL,
-                b'}' => Token::CurlyR,
-                b'[' => Token::SqurL,
-                b']' => Token::SqurR,
-                b';' => Token::SemiColon,
-                b',' => Token::Comma,
-                b'/' => self.check_ahead(b'/', Token::Idiv, Token::Div),
-                b'=' => self.check_ahead(b'=', Token::Equal, Token::Assign),
-                b'~' => self.check_ahead(b'=', Token::NotEq, Token::BitNot),
-                b':' => self.check_ahead(b':', Token::DoubColon, Token::Colon),
-                b'<' => self.check_ahead2(b'=', Token::LesEq, b'<', Token::ShiftL, Token::Less),
-                b'>' => self.check_ahead2(b'=', Token::GreEq, b'>', Token::ShiftR, Token::Greater),
-                b'\'' | b'"' => self.read_string(byt),
-                b'.' => match self.peek_byte() {
-                    b'.' => {
-                        self.next_byte();
-                        if self.peek_byte() == b'.' {
-                            self.next_byte();
-                            Token::Dots
-                        } else {
-                            Token::Concat
-                        }
-                    }
-                    b'0'..=b'9' => self.read_decimal('.'),
-                    _ => Token::Dot,
+        // skip blanks and comments by loop but not recursion, which
+        // may overflow the stack on long blanks
+        let byt = loop {
+            match self.next_byte() {
+                None => return Token::Eos,
+                Some(b'\n' | b'\r' | b'\t' | b' ' | 0x0b | 0x0c) => (),
+                Some(b'-') if self.peek_byte() == b'-' => {
+                    self.next_byte();
+                    self.read_comment();
                 }
-                b'-' => {
-                    if self.peek_byte() == b'-' {
+                Some(byt) => break byt,
+            }
+        };
+
+        match byt {
+            b'+' => Token::Add,
+            b'-' => Token::Sub,
+            b'*' => Token::Mul,
+            b'%' => Token::Mod,
+            b'^' => Token::Pow,
+            b'#' => Token::Len,
+            b'&' => Token::BitAnd,
+            b'|' => Token::BitOr,
+            b'(' => Token::ParL,
+            b')' => Token::ParR,
+            b'{' => Token::CurlyL,
+            b'}' => Token::CurlyR,
+            b'[' => match self.peek_byte() {
+                b'[' | b'=' => self.read_long_string(),
+                _ => Token::SqurL,
+            }
+            b']' => Token::SqurR,
+            b';' => Token::SemiColon,
+            b',' => Token::Comma,
+            b'/' => self.check_ahead(b'/', Token::Idiv, Token::Div),
+            b'=' => self.check_ahead(b'=', Token::Equal, Token::Assign),
+            b'~' => self.check_ahead(b'=', Token::NotEq, Token::BitNot),
+            b':' => self.check_ahead(b':', Token::DoubColon, Token::Colon),
+            b'<' => self.check_ahead2(b'=', Token::LesEq, b'<', Token::ShiftL, Token::Less),
+            b'>' => self.check_ahead2(b'=', Token::GreEq, b'>', Token::ShiftR, Token::Greater),
+            b'\'' | b'"' => self.read_string(byt),
+            b'.' => match self.peek_byte() {
+                b'.' => {
+                    self.next_byte();
+                    if self.peek_byte() == b'.' {
                         self.next_byte();
-                        self.read_comment();
-                        self.do_next()
+                        Token::Dots
                     } else {
-                        Token::Sub
+                        Token::Concat
                     }
                 }
-                ch@b'0'..=b'9' => self.read_decimal(ch as char),
-                b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.read_name(byt),
-                _ => panic!("invalid char {byt}"),
+                b'0'..=b'9' => self.read_number(b'.'),
+                _ => Token::Dot,
             }
-        } else {
-            Token::Eos
+            b'0'..=b'9' => self.read_number(byt),
+            b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.read_name(byt),
+            _ => self.syntax_error(format!("invalid char {byt}")),
         }
     }
 
     fn peek_byte(&mut self) -> u8 {
-        match self.input.peek() {
-            Some(Ok(byt)) => *byt,
-            Some(_) => panic!("lex peek error"),
-            None => b'\0', // good for usage
+        if self.pos == self.end && !self.fill() {
+            return b'\0'; // good for usage
         }
+        self.chunk[self.pos]
     }
     fn next_byte(&mut self) -> Option<u8> {
-        self.input.next().map(|r| r.unwrap())
+        if self.pos == self.end && !self.fill() {
+            return None;
+        }
+        let byt = self.chunk[self.pos];
+        self.pos += 1;
+        if byt == b'\n' {
+            self.line += 1;
+        }
+        Some(byt)
+    }
+    // read the next chunk of input, and return false at the end
+    fn fill(&mut self) -> bool {
+        while !self.eof {
+            match self.input.read(&mut self.chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => {
+                    (self.pos, self.end) = (0, n);
+                    return true;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => self.syntax_error(format!("read error: {e}")),
+            }
+        }
+        false
     }
 
     fn check_ahead(&mut self, ahead: u8, long: Token, short: Token) -> Token {
@@ -155,45 +372,78 @@ impl<R: Read> Lex<R> {
         }
     }
 
-    fn read_decimal(&mut self, ahead: char) -> Token {
-        // TODO heximal
-        let mut is_float = ahead == '.';
-        let mut buf = String::new();
-        buf.push(ahead);
+    // Read bytes of the number as Lua's official implement does, and
+    // then convert them by the scanner shared with `tonumber()`.
+    fn read_number(&mut self, first: u8) -> Token {
+        let mut buf = mem::take(&mut self.buf);
+        buf.clear();
+        buf.push(first);
+
+        let mut expo = b'e';
+        if first == b'0' && matches!(self.peek_byte(), b'x' | b'X') {
+            buf.push(self.next_byte().unwrap());
+            expo = b'p';
+        }
         loop {
             let byt = self.peek_byte();
-            match byt {
-                b'0' ..= b'9' => buf.push(byt as char),
-                b'.' | b'e' | b'E' | b'+' | b'-' => {
-                    buf.push(byt as char);
-                    is_float = true;
+            if byt.to_ascii_lowercase() == expo {
+                buf.push(byt);
+                self.next_byte();
+                if matches!(self.peek_byte(), b'+' | b'-') {
+                    buf.push(self.next_byte().unwrap());
                 }
-                _ => break,
+            } else if byt.is_ascii_hexdigit() || byt == b'.' {
+                buf.push(byt);
+                self.next_byte();
+            } else {
+                break;
             }
-            self.next_byte();
+            self.check_token_len(buf.len());
         }
 
-        if is_float {
-            Token::Float(buf.parse::<f64>().unwrap())
-        } else {
-            Token::Integer(buf.parse::<i64>().unwrap())
+        // a following letter makes a malformed number, e.g. `3x`
+        let byt = self.peek_byte();
+        if byt.is_ascii_alphanumeric() || byt == b'_' {
+            self.syntax_error(format!("malformed number near '{}{}'",
+                String::from_utf8_lossy(&buf), byt as char));
         }
+
+        let token = match str_to_number(&buf) {
+            Some(Value::Integer(i)) => Token::Integer(i),
+            Some(Value::Float(f)) => Token::Float(f),
+            _ => self.syntax_error(format!("malformed number near '{}'",
+                String::from_utf8_lossy(&buf))),
+        };
+        self.ahead_string.clear();
+        self.ahead_string.extend_from_slice(&buf);
+        self.buf = buf;
+        token
     }
 
     fn read_string(&mut self, quote: u8) -> Token {
-        let mut s = Vec::new();
+        let mut s = mem::take(&mut self.ahead_string);
+        s.clear();
         loop {
-            match self.next_byte().expect("unfinished string") {
-                b'\n' => panic!("unfinished string"),
-                b'\\' => s.push(self.read_escape()),
-                byt if byt == quote => break,
-                byt => s.push(byt),
+            // check before consuming the new line, to report the right line
+            if self.peek_byte() == b'\n' {
+                self.syntax_error("unfinished string".into());
+            }
+            match self.next_byte() {
+                None => self.syntax_error("unfinished string".into()),
+                Some(b'\\') => s.push(self.read_escape()),
+                Some(byt) if byt == quote => break,
+                Some(byt) => s.push(byt),
             }
+            self.check_token_len(s.len());
         }
-        Token::String(s)
+        self.ahead_string = s;
+        Token::String
     }
     fn read_escape(&mut self) -> u8 {
-        match self.next_byte().expect("string escape") {
+        let Some(byt) = self.next_byte() else {
+            self.syntax_error("unfinished string".into());
+        };
+        match byt {
             b'a' => 0x07,
             b'b' => 0x08,
             b'f' => 0x0c,
@@ -204,42 +454,65 @@ impl<R: Read> Lex<R> {
             b'\\' => b'\\',
             b'"' => b'"',
             b'\'' => b'\'',
+            b'\n' | b'\r' => { // escaped newline, where "\r\n" and "\n\r" are one
+                let second = self.peek_byte();
+                if matches!(second, b'\r' | b'\n') && second != byt {
+                    self.next_byte();
+                }
+                b'\n'
+            }
             b'x' => { // format: \xXX
-                let n1 = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
-                let n2 = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
+                let n1 = self.read_hex_digit();
+                let n2 = self.read_hex_digit();
                 (n1 * 16 + n2) as u8
             }
-            ch@b'0'..=b'9' => { // format: \d[d[d]]
-                let mut n = char::to_digit(ch as char, 10).unwrap(); // TODO no unwrap
-                if let Some(d) = char::to_digit(self.peek_byte() as char, 10) {
-                    self.next_byte();
-                    n = n * 10 + d;
-                    if let Some(d) = char::to_digit(self.peek_byte() as char, 10) {
-                        self.next_byte();
-                        n = n * 10 + d;
+            b'0'..=b'9' => { // format: \d[d[d]]
+                let mut n = (byt - b'0') as u32;
+                for _ in 0..2 {
+                    match char::to_digit(self.peek_byte() as char, 10) {
+                        Some(d) => {
+                            self.next_byte();
+                            n = n * 10 + d;
+                        }
+                        None => break,
                     }
                 }
-                u8::try_from(n).expect("decimal escape too large")
+                u8::try_from(n).unwrap_or_else(|_| self.syntax_error("decimal escape too large".into()))
             }
-            _ => panic!("invalid string escape")
+            _ => self.syntax_error("invalid string escape".into()),
+        }
+    }
+
+    // not consume the byte if it's not a hex digit, for the message line
+    fn read_hex_digit(&mut self) -> u32 {
+        match char::to_digit(self.peek_byte() as char, 16) {
+            Some(d) => {
+                self.next_byte();
+                d
+            }
+            None => self.syntax_error("hexadecimal digit expected".into()),
         }
     }
 
     fn read_name(&mut self, first: u8) -> Token {
-        let mut s = String::new();
-        s.push(first as char);
+        let mut buf = mem::take(&mut self.buf);
+        buf.clear();
+        buf.push(first);
 
         loop {
-            let ch = self.peek_byte() as char;
-            if ch.is_alphanumeric() || ch == '_' {
+            let byt = self.peek_byte();
+            if byt.is_ascii_alphanumeric() || byt == b'_' {
                 self.next_byte();
-                s.push(ch);
+                buf.push(byt);
+                self.check_token_len(buf.len());
             } else {
                 break;
             }
         }
 
-        match &s as &str { // TODO optimize by hash
+        // names are ASCII
+        let s = std::str::from_utf8(&buf).unwrap();
+        let token = match s {
             "and"      => Token::And,
             "break"    => Token::Break,
             "do"       => Token::Do,
@@ -262,22 +535,104 @@ impl<R: Read> Lex<R> {
             "true"     => Token::True,
             "until"    => Token::Until,
             "while"    => Token::While,
-            _          => Token::Name(s),
-        }
+            _          => Token::Name(self.symbol(s)),
+        };
+        self.buf = buf;
+        token
     }
 
     // '--' has been read
     fn read_comment(&mut self) {
-        match self.next_byte() {
-            None => (),
-            Some(b'[') => todo!("long comment"),
-            Some(_) => { // line comment
-                while let Some(byt) = self.next_byte() {
-                    if byt == b'\n' {
-                        break;
-                    }
+        if self.peek_byte() == b'[' {
+            self.next_byte();
+            if let Some(level) = self.read_long_level() {
+                self.read_long_content(level, "comment", None);
+                return;
+            }
+            // not long bracket, so a line comment
+        }
+        while let Some(byt) = self.next_byte() {
+            if byt == b'\n' {
+                break;
+            }
+        }
+    }
+
+    // The first '[' has been read. Read the following `=`s and `[` of a
+    // long bracket, and return the level which is the number of `=`s.
+    // Return None if no `[` follows, while the `=`s are consumed.
+    fn read_long_level(&mut self) -> Option<usize> {
+        let mut level = 0;
+        while self.peek_byte() == b'=' {
+            self.next_byte();
+            level += 1;
+            if level > MAX_LONG_BRACKET_LEVEL {
+                self.syntax_error("long bracket level too deep".into());
+            }
+        }
+        if self.peek_byte() == b'[' {
+            self.next_byte();
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    // long string, e.g. `[==[ ... ]==]`, where the first '[' has been read
+    fn read_long_string(&mut self) -> Token {
+        let Some(level) = self.read_long_level() else {
+            self.syntax_error("invalid long string delimiter".into());
+        };
+        let mut s = mem::take(&mut self.ahead_string);
+        s.clear();
+        self.read_long_content(level, "string", Some(&mut s));
+        self.ahead_string = s;
+        Token::String
+    }
+
+    // Read the content of long string or comment until the closing long
+    // bracket of @level into @keep. The first newline is skipped. The
+    // content is dropped if no @keep, so long comments have no length
+    // limit.
+    fn read_long_content(&mut self, level: usize, what: &str, mut keep: Option<&mut Vec<u8>>) {
+        if matches!(self.peek_byte(), b'\r' | b'\n') {
+            let first = self.next_byte();
+            let second = self.peek_byte();
+            if matches!(second, b'\r' | b'\n') && Some(second) != first {
+                self.next_byte();
+            }
+        }
+        loop {
+            let Some(byt) = self.next_byte() else {
+                self.syntax_error(format!("unfinished long {what} near <eof>"));
+            };
+            if byt == b']' {
+                let mut n = 0;
+                while n < level && self.peek_byte() == b'=' {
+                    self.next_byte();
+                    n += 1;
+                }
+                if n == level && self.peek_byte() == b']' {
+                    self.next_byte();
+                    return;
+                }
+                // not the closing bracket, so they are the content
+                if let Some(s) = keep.as_deref_mut() {
+                    s.push(b']');
+                    s.resize(s.len() + n, b'=');
                 }
+            } else if let Some(s) = keep.as_deref_mut() {
+                s.push(byt);
+            }
+            if let Some(s) = keep.as_deref() {
+                self.check_token_len(s.len());
             }
         }
     }
+
+    fn check_token_len(&self, len: usize) {
+        if len > MAX_TOKEN_LEN {
+            self.syntax_error("lexical element too long".into());
+        }
+    }
 }
@@ -0,0 +1,34 @@
+// Lua errors are raised by panics, and caught by the protected calls,
+// see vm::catch_error()
+#[cfg(panic = "abort")]
+compile_error!("lua-rs does not support panic = \"abort\"");
+
+pub mod value;
+pub mod convert;
+pub mod bytecode;
+pub mod disasm;
+pub mod dump;
+pub mod data;
+pub mod verify;
+pub mod builder;
+pub mod compiler;
+pub mod parse;
+pub mod vm;
+pub mod stdlib;
+pub mod error;
+pub mod pool;
+pub mod repl;
+pub mod editor;
+pub mod lex;
+pub mod minify;
+pub mod memory;
+pub mod ctype;
+pub mod patterns;
+pub mod send;
+pub mod lua;
+pub mod utils;
+#[cfg(feature = "serde")]
+pub mod de;
+mod gc;
+
+pub use lua::Lua;
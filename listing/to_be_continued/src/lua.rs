@@ -0,0 +1,135 @@
+use std::rc::Rc;
+use crate::error::LuaError;
+use crate::parse::FuncProto;
+use crate::value::{TableHandle, Value};
+use crate::vm::ExeState;
+
+// High-level API for embedding, which hides ExeState and FuncProto, and
+// returns errors as LuaError. Lua errors are still Rust panics inside,
+// which are caught by the protected calls without being reported by the
+// panic hook, see vm::catch_error(). So it needs `panic = "unwind"`,
+// which is the default, and does not work with `panic = "abort"`:
+//
+//     let mut lua = Lua::new();
+//     lua.set_global("x", 20);
+//     lua.load("function add(a) return x + a end")?.call(&[])?;
+//     let n: i64 = lua.eval("add(22)")?;
+//     let rets = lua.call("add", &[Value::Integer(1)])?;
+//
+// For the lower-level API, e.g. Rust functions and userdata, use the
+// state by state().
+pub struct Lua {
+    state: ExeState,
+}
+
+// A loaded chunk, to call once or more.
+pub struct Chunk<'a> {
+    lua: &'a mut Lua,
+    proto: Rc<FuncProto>,
+}
+
+impl Default for Lua {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<ExeState> for Lua {
+    // e.g. the state configured by ExeStateBuilder
+    fn from(state: ExeState) -> Self {
+        Lua { state }
+    }
+}
+
+impl Lua {
+    pub fn new() -> Self {
+        Lua { state: ExeState::new() }
+    }
+
+    pub fn state(&mut self) -> &mut ExeState {
+        &mut self.state
+    }
+
+    // Compile the source code or binary chunk, without running it. The
+    // chunk is named by the source, same with `luaL_loadstring()`, e.g.
+    // `[string "x = 1"]:1:` in error messages.
+    pub fn load(&mut self, chunk: impl AsRef<[u8]>) -> Result<Chunk<'_>, LuaError> {
+        let chunk = chunk.as_ref();
+        let proto = self.compile(chunk, &String::from_utf8_lossy(chunk))?;
+        Ok(Chunk { lua: self, proto: Rc::new(proto) })
+    }
+
+    // Evaluate the expression, or run the statements, and convert the
+    // first return value, e.g. `lua.eval::<i64>("1 + 2")`.
+    pub fn eval<T>(&mut self, source: &str) -> Result<T, LuaError>
+        where T: TryFrom<Value>, LuaError: From<T::Error>
+    {
+        let proto = match self.compile(format!("return {source}").as_bytes(), source) {
+            Ok(proto) => proto,
+            Err(_) => self.compile(source.as_bytes(), source)?,
+        };
+        let rets = Chunk { lua: self, proto: Rc::new(proto) }.call(&[])?;
+        convert(rets.into_iter().next().unwrap_or(Value::Nil))
+    }
+
+    // Call the global function @name, and return its return values.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, LuaError> {
+        let f = self.globals().get(name);
+        if !f.is_function() {
+            return Err(LuaError::runtime(format!("global '{name}' is not a function")));
+        }
+        self.state.pcall(f, args)
+    }
+
+    pub fn globals(&self) -> TableHandle {
+        self.state.globals()
+    }
+
+    pub fn set_global(&self, name: &str, v: impl Into<Value>) {
+        self.globals().set(name, v);
+    }
+
+    // Get the global variable @name converted to @T, e.g.
+    // `lua.get::<i64>("x")`.
+    pub fn get<T>(&self, name: &str) -> Result<T, LuaError>
+        where T: TryFrom<Value>, LuaError: From<T::Error>
+    {
+        convert(self.globals().get(name))
+    }
+
+    // Run a configuration file in a restricted environment, same with
+    // ExeState::eval_config(), and deserialize the table it returns into
+    // @T, e.g. a struct with `#[derive(Deserialize)]`, see de.rs.
+    #[cfg(feature = "serde")]
+    pub fn eval_config<T>(path: impl AsRef<std::path::Path>) -> Result<T, LuaError>
+        where T: serde::de::DeserializeOwned
+    {
+        Ok(crate::de::from_value(ExeState::exec_config(path)?)?)
+    }
+
+    fn compile(&self, chunk: &[u8], chunk_name: &str) -> Result<FuncProto, LuaError> {
+        self.state.try_load(chunk, chunk_name)
+    }
+}
+
+impl Chunk<'_> {
+    // Run the chunk with @args as its varargs `...`, and return its
+    // return values.
+    pub fn call(&mut self, args: &[Value]) -> Result<Vec<Value>, LuaError> {
+        self.lua.state.try_exec_main(self.proto.clone(), args)
+    }
+
+    // Run the chunk without arguments, and convert the first return value.
+    pub fn eval<T>(&mut self) -> Result<T, LuaError>
+        where T: TryFrom<Value>, LuaError: From<T::Error>
+    {
+        let rets = self.call(&[])?;
+        convert(rets.into_iter().next().unwrap_or(Value::Nil))
+    }
+}
+
+fn convert<T>(v: Value) -> Result<T, LuaError>
+    where T: TryFrom<Value>, LuaError: From<T::Error>
+{
+    Ok(T::try_from(v)?)
+}
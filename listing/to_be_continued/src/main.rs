@@ -1,22 +1,182 @@
+use std::cell::RefCell;
 use std::env;
-use std::fs::File;
-use std::io::BufReader;
-
-mod value;
-mod bytecode;
-mod lex;
-mod parse;
-mod vm;
-mod utils;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::Instant;
+use lua_rs::disasm;
+use lua_rs::dump;
+use lua_rs::editor::LineEditor;
+use lua_rs::error::LuaError;
+use lua_rs::minify;
+use lua_rs::parse;
+use lua_rs::repl::Repl;
+use lua_rs::vm;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} script", args[0]);
-        return;
+    match args.get(1).map(String::as_str) {
+        None => repl(),
+        Some("-c") => compile(&args[2..]),
+        Some("-l") => list(&args[2..]),
+        Some("-m") => minify(&args[2..]),
+        Some(path) => exec(path, &args[2..]),
+    }
+}
+
+// `-c [-s] [-o output] file`, compile the file into a binary chunk, same
+// with `luac`, which is written into "luac.out" by default. The chunk is
+// run as the source file, e.g. `lua-rs luac.out`, but without parsing.
+// Debug information is stripped by `-s`.
+fn compile(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: lua-rs -c [-s] [-o output] file");
+        process::exit(1);
+    };
+    let (mut strip, mut output, mut path) = (false, "luac.out", None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" => strip = true,
+            "-o" => output = args.next().unwrap_or_else(|| usage()),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let file = File::open(path).unwrap_or_else(|e| {
+        eprintln!("{path}: {e}");
+        process::exit(1);
+    });
+    panic::set_hook(Box::new(|_| {}));
+    let proto = panic::catch_unwind(|| parse::load_named(file, &format!("@{path}")))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", vm::panic_message(&*e));
+            process::exit(1);
+        });
+    if let Err(e) = fs::write(output, dump::dump(&proto, strip)) {
+        eprintln!("{output}: {e}");
+        process::exit(1);
+    }
+}
+
+// `-l file...`, list the byte codes as `luac -l -l`
+fn list(paths: &[String]) {
+    for path in paths {
+        let file = File::open(path).unwrap_or_else(|e| {
+            eprintln!("{path}: {e}");
+            process::exit(1);
+        });
+        let proto = parse::load_named(file, &format!("@{path}"));
+        print!("{}", disasm::disassemble(&proto));
+    }
+}
+
+// `-m file...`, write the minified files into stdout, and the sizes
+// into stderr
+fn minify(paths: &[String]) {
+    for path in paths {
+        let source = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("{path}: {e}");
+            process::exit(1);
+        });
+        let (code, report) = minify::minify(&source);
+        io::stdout().write_all(&code).and_then(|_| io::stdout().write_all(b"\n")).unwrap();
+        eprintln!("{path}: {report}");
+    }
+}
+
+fn exec(path: &str, args: &[String]) {
+    // errors are shown here, but not by the panic hook, which is called
+    // for the ones caught by `pcall()` too
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut state = vm::ExeState::new();
+    state.set_arg(path, args);
+    state.open_timer();
+    match panic::catch_unwind(AssertUnwindSafe(|| state.exec_file(path))) {
+        Ok(Ok(_)) => run_timers(&mut state),
+        Ok(Err(e@LuaError::IoError(_))) => {
+            eprintln!("{path}: {e}");
+            process::exit(1);
+        }
+        Ok(Err(e)) => {
+            eprintln!("{e:#}"); // with the traceback
+            process::exit(1);
+        }
+        Err(e) => {
+            state.flush(); // the output before the error
+            eprintln!("{}", vm::panic_message(&*e));
+            process::exit(1);
+        }
     }
-    let file = File::open(&args[1]).unwrap();
+}
 
-    let proto = parse::load(BufReader::new(file));
-    vm::ExeState::new().execute(&proto, &Vec::new());
+// the event loop of the `timer` module after the script, until there is
+// no timer
+fn run_timers(state: &mut vm::ExeState) {
+    while let Some(due) = state.next_timer() {
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        if let Err(e) = state.poll_timers(Instant::now()) {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn repl() {
+    // errors are shown by the REPL, but not the panic hook
+    panic::set_hook(Box::new(|_| {}));
+
+    // shared with the completer of the editor
+    let repl = Rc::new(RefCell::new(Repl::new()));
+    let completer = {
+        let repl = repl.clone();
+        Box::new(move |before: &str| repl.borrow().complete(before))
+    };
+    let history_file = env::var_os("HOME").map(|home| Path::new(&home).join(".lua_rs_history"));
+    let mut editor = match LineEditor::new(completer, history_file) {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    let mut source = String::new();
+    loop {
+        // continue the incomplete source, e.g. a function by lines
+        let prompt = if source.is_empty() { "> " } else { ">> " };
+        let line = match editor.read_line(prompt) {
+            Ok(Some(line)) => line,
+            Ok(None) if source.is_empty() => return,
+            Ok(None) => {
+                // drop the incomplete source
+                println!();
+                source.clear();
+                continue;
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        };
+        editor.add_history(&line);
+        if !source.is_empty() {
+            source.push('\n');
+        }
+        source += &line;
+        if !Repl::is_complete(&source) {
+            continue;
+        }
+        match repl.borrow_mut().eval(&std::mem::take(&mut source)) {
+            Ok(s) if s.is_empty() => (),
+            Ok(s) => println!("{s}"),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
 }
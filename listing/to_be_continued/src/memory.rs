@@ -0,0 +1,64 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// A global allocator counting the allocated bytes by thread, for the
+// memory limit and watermarks of ExeState, see ExeStateBuilder::max_memory().
+// Rust does not support allocators for values, so it must be set by the
+// program embedding the VM:
+//
+//     #[global_allocator]
+//     static ALLOC: lua_rs::memory::CountingAlloc = lua_rs::memory::CountingAlloc;
+//
+// Without it, allocated() is always 0 and there is no limit.
+//
+// Values are not `Send`, so they are allocated and freed in the same
+// thread. The counter may be negative if memory is freed by another
+// thread, e.g. results sent by channels, which is fine because only
+// the differences are used.
+pub struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+}
+
+// The counter has no destructor, so it's accessible even when the thread
+// is exiting, but ignore it if not.
+fn add(n: isize) {
+    let _ = ALLOCATED.try_with(|a| a.set(a.get() + n));
+}
+
+// bytes allocated and not freed by the current thread
+pub fn allocated() -> isize {
+    ALLOCATED.try_with(Cell::get).unwrap_or(0)
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = unsafe { System.alloc(layout) };
+        if !p.is_null() {
+            add(layout.size() as isize);
+        }
+        p
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let p = unsafe { System.alloc_zeroed(layout) };
+        if !p.is_null() {
+            add(layout.size() as isize);
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        add(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let p = unsafe { System.realloc(ptr, layout, new_size) };
+        if !p.is_null() {
+            add(new_size as isize - layout.size() as isize);
+        }
+        p
+    }
+}
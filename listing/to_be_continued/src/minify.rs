@@ -0,0 +1,669 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+use crate::lex::{Lex, Token};
+use crate::parse::binop_pri;
+
+// Source-to-source minifier, e.g. for shipping scripts in packages with
+// limited sizes. It strips comments and blanks, renames local variables
+// into short names, and writes strings and numbers in their shortest
+// forms. The result runs the same as the source.
+//
+// There is no AST, because the parser generates byte codes directly, so
+// the minifier walks the tokens by the same grammar, only for the scopes
+// of local variables. Syntax errors are raised by panics, same with the
+// parser, while semantic errors, e.g. `break` outside loops, are not
+// checked.
+//
+// Locals are named by their indexes in the live locals, including the
+// ones of enclosing functions, so locals visible at the same place never
+// share names. Names appearing in the source are never generated, so
+// the global variables and fields are not shadowed.
+pub fn minify(source: &[u8]) -> (Vec<u8>, MinifyReport) {
+    // all names, to be avoided
+    let mut lex = Lex::new(source, "?".into());
+    let mut reserved: HashSet<Rc<str>> = ["_ENV", "self", "continue"].into_iter()
+        .map(Rc::from).collect();
+    loop {
+        match lex.next() {
+            Token::Name(sym) => { reserved.insert(lex.name(sym)); }
+            Token::Eos => break,
+            _ => (),
+        }
+    }
+
+    let mut m = Minifier {
+        lex: Lex::new(source, "?".into()),
+        out: Vec::with_capacity(source.len() / 2),
+        last_number: false,
+        locals: Vec::new(),
+        reserved,
+        short_names: Vec::new(),
+        candidate: 0,
+        renamed: 0,
+    };
+    match m.block() {
+        Token::Eos => (),
+        t => m.unexpected(t),
+    }
+
+    let report = MinifyReport {
+        input_size: source.len(),
+        output_size: m.out.len(),
+        renamed: m.renamed,
+    };
+    (m.out, report)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinifyReport {
+    pub input_size: usize,
+    pub output_size: usize,
+    pub renamed: usize, // number of local variables renamed
+}
+
+impl fmt::Display for MinifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = if self.input_size == 0 {
+            100.0
+        } else {
+            self.output_size as f64 * 100.0 / self.input_size as f64
+        };
+        write!(f, "{} -> {} bytes ({percent:.1}%), {} locals renamed",
+            self.input_size, self.output_size, self.renamed)
+    }
+}
+
+struct Minifier<'a> {
+    lex: Lex<&'a [u8]>,
+    out: Vec<u8>,
+    last_number: bool, // whether the last token written is a number
+
+    // live locals, (name in source, new name)
+    locals: Vec<(Rc<str>, Rc<str>)>,
+
+    reserved: HashSet<Rc<str>>,
+    short_names: Vec<Rc<str>>, // generated names, indexed by locals
+    candidate: usize, // next candidate of short_names
+    renamed: usize,
+}
+
+impl Minifier<'_> {
+    // block, until the returned token, e.g. `end`
+    fn block(&mut self) -> Token {
+        let nvar = self.locals.len();
+        let t = self.block_scope();
+        self.locals.truncate(nvar);
+        t
+    }
+
+    // same with block() but without expiring internal local variables
+    fn block_scope(&mut self) -> Token {
+        loop {
+            match self.lex.next() {
+                Token::SemiColon => (),
+                t@Token::Name(_) | t@Token::ParL => {
+                    if self.is_continue(t) {
+                        self.emit(b"continue");
+                        continue;
+                    }
+                    // `;` is kept before `(`, otherwise it's parsed as
+                    // arguments of the last statement, e.g. `a = f;(g)()`
+                    if t == Token::ParL && !self.out.is_empty() {
+                        self.emit(b";");
+                    }
+                    self.prefixexp(t);
+                    if matches!(self.lex.peek(), Token::Assign | Token::Comma) {
+                        while self.lex.peek() == Token::Comma {
+                            self.next_token();
+                            let t = self.lex.next();
+                            self.prefixexp(t);
+                        }
+                        self.expect(Token::Assign);
+                        self.explist();
+                    }
+                }
+                Token::Local => {
+                    self.emit(b"local");
+                    if self.lex.peek() == Token::Function {
+                        self.next_token();
+                        let name = self.read_name();
+                        self.local_new(name);
+                        self.funcbody(false);
+                    } else {
+                        self.local_variables();
+                    }
+                }
+                Token::Function => {
+                    self.emit(b"function");
+                    let name = self.read_name();
+                    self.variable(name);
+                    let mut with_self = false;
+                    loop {
+                        match self.lex.peek() {
+                            Token::Dot => (),
+                            Token::Colon => with_self = true,
+                            _ => break,
+                        }
+                        self.next_token();
+                        self.field_name();
+                        if with_self {
+                            break;
+                        }
+                    }
+                    self.funcbody(with_self);
+                }
+                Token::If => {
+                    self.emit(b"if");
+                    loop {
+                        self.exp();
+                        self.expect(Token::Then);
+                        match self.block() {
+                            Token::End => break,
+                            Token::Elseif => self.emit(b"elseif"),
+                            Token::Else => {
+                                self.emit(b"else");
+                                self.block_end();
+                                break;
+                            }
+                            t => self.unexpected(t),
+                        }
+                    }
+                    self.emit(b"end");
+                }
+                Token::While => {
+                    self.emit(b"while");
+                    self.exp();
+                    self.expect(Token::Do);
+                    self.block_end();
+                    self.emit(b"end");
+                }
+                Token::Repeat => {
+                    // the condition is in the scope of the block
+                    self.emit(b"repeat");
+                    let nvar = self.locals.len();
+                    match self.block_scope() {
+                        Token::Until => self.emit(b"until"),
+                        t => self.unexpected(t),
+                    }
+                    self.exp();
+                    self.locals.truncate(nvar);
+                }
+                Token::For => self.for_stat(),
+                Token::Do => {
+                    self.emit(b"do");
+                    self.block_end();
+                    self.emit(b"end");
+                }
+                Token::Break => self.emit(b"break"),
+                Token::DoubColon => {
+                    self.emit(b"::");
+                    self.field_name();
+                    self.expect(Token::DoubColon);
+                }
+                Token::Goto => {
+                    self.emit(b"goto");
+                    self.field_name();
+                }
+                Token::Return => {
+                    self.emit(b"return");
+                    if !matches!(self.lex.peek(), Token::SemiColon | Token::End | Token::Else
+                            | Token::Elseif | Token::Until | Token::Eos) {
+                        self.explist();
+                    }
+                }
+                t => break t,
+            }
+        }
+    }
+
+    // block ended by `end`
+    fn block_end(&mut self) {
+        match self.block() {
+            Token::End => (),
+            t => self.unexpected(t),
+        }
+    }
+
+    // `continue` statement, see parse.rs
+    fn is_continue(&mut self, t: Token) -> bool {
+        let Token::Name(sym) = t else { return false; };
+        &*self.lex.name(sym) == "continue"
+            && matches!(self.lex.peek(), Token::End | Token::Elseif | Token::Else)
+    }
+
+    // BNF:
+    //   local attnamelist [`=` explist]
+    fn local_variables(&mut self) {
+        let mut vars = Vec::new();
+        loop {
+            // the previous ones are not visible yet, so count them
+            let name = self.read_name();
+            let new_name = self.short_name(self.locals.len() + vars.len());
+            self.rename_count(&name, &new_name);
+            self.emit(new_name.as_bytes());
+            vars.push((name, new_name));
+
+            // attrib
+            if self.lex.peek() == Token::Less {
+                self.next_token();
+                self.field_name();
+                self.expect(Token::Greater);
+            }
+            if self.lex.peek() != Token::Comma {
+                break;
+            }
+            self.next_token();
+        }
+
+        // the variables are visible after the expressions
+        if self.lex.peek() == Token::Assign {
+            self.next_token();
+            self.explist();
+        }
+        self.locals.extend(vars);
+    }
+
+    fn for_stat(&mut self) {
+        self.emit(b"for");
+        let mut vars = vec![self.read_name()];
+        let new_name = self.new_name(&vars[0]);
+        self.emit(new_name.as_bytes());
+        let mut new_names = vec![new_name];
+
+        match self.lex.peek() {
+            Token::Assign => { // numerical
+                self.next_token();
+                self.explist();
+            }
+            _ => { // generic
+                while self.lex.peek() == Token::Comma {
+                    self.next_token();
+                    let name = self.read_name();
+                    // the previous ones are not visible yet, so count them
+                    let new_name = self.short_name(self.locals.len() + vars.len());
+                    self.rename_count(&name, &new_name);
+                    self.emit(new_name.as_bytes());
+                    vars.push(name);
+                    new_names.push(new_name);
+                }
+                self.expect(Token::In);
+                self.explist();
+            }
+        }
+        self.expect(Token::Do);
+
+        let nvar = self.locals.len();
+        self.locals.extend(vars.into_iter().zip(new_names));
+        self.block_end();
+        self.emit(b"end");
+        self.locals.truncate(nvar);
+    }
+
+    // BNF:
+    //   funcbody ::= `(` [parlist] `)` block end
+    fn funcbody(&mut self, with_self: bool) {
+        let nvar = self.locals.len();
+        if with_self {
+            let name: Rc<str> = Rc::from("self");
+            self.locals.push((name.clone(), name));
+        }
+        self.expect(Token::ParL);
+        loop {
+            match self.lex.next() {
+                Token::Name(sym) => {
+                    let name = self.lex.name(sym);
+                    self.local_new(name);
+                    match self.lex.next() {
+                        Token::Comma => self.emit(b","),
+                        Token::ParR => break,
+                        t => self.unexpected(t),
+                    }
+                }
+                Token::Dots => {
+                    self.emit(b"...");
+                    self.lex.expect(Token::ParR);
+                    break;
+                }
+                Token::ParR => break,
+                t => self.unexpected(t),
+            }
+        }
+        self.emit(b")");
+        self.block_end();
+        self.emit(b"end");
+        self.locals.truncate(nvar);
+    }
+
+    fn explist(&mut self) {
+        self.exp();
+        while self.lex.peek() == Token::Comma {
+            self.next_token();
+            self.exp();
+        }
+    }
+
+    // the operators are written in order, so the priorities only tell
+    // where the expression ends
+    fn exp(&mut self) {
+        let ahead = self.lex.next();
+        self.exp_with_ahead(ahead);
+    }
+    fn exp_with_ahead(&mut self, mut ahead: Token) {
+        loop {
+            match ahead {
+                t@(Token::Nil | Token::True | Token::False | Token::Dots
+                    | Token::Integer(_) | Token::Float(_) | Token::String) => self.write_token(t),
+                Token::Function => {
+                    self.emit(b"function");
+                    self.funcbody(false);
+                }
+                Token::CurlyL => self.table_constructor(),
+                t@(Token::Sub | Token::Not | Token::BitNot | Token::Len) => {
+                    // the operand follows
+                    self.write_token(t);
+                    ahead = self.lex.next();
+                    continue;
+                }
+                t => self.prefixexp(t),
+            }
+            if binop_pri(self.lex.peek()).0 < 0 {
+                return;
+            }
+            self.next_token();
+            ahead = self.lex.next();
+        }
+    }
+
+    // BNF:
+    //   prefixexp ::= (Name | `(` exp `)`) { `[` exp `]` | `.` Name | args | `:` Name args }
+    fn prefixexp(&mut self, ahead: Token) {
+        match ahead {
+            Token::Name(sym) => {
+                let name = self.lex.name(sym);
+                self.variable(name);
+            }
+            Token::ParL => {
+                self.emit(b"(");
+                self.exp();
+                self.expect(Token::ParR);
+            }
+            t => self.unexpected(t),
+        }
+        loop {
+            match self.lex.peek() {
+                Token::SqurL => {
+                    self.next_token();
+                    self.exp();
+                    self.expect(Token::SqurR);
+                }
+                Token::Dot => {
+                    self.next_token();
+                    self.field_name();
+                }
+                Token::Colon => {
+                    self.next_token();
+                    self.field_name();
+                    self.args();
+                }
+                Token::ParL | Token::CurlyL | Token::String => self.args(),
+                _ => return,
+            }
+        }
+    }
+
+    fn args(&mut self) {
+        match self.lex.next() {
+            Token::ParL => {
+                self.emit(b"(");
+                if self.lex.peek() != Token::ParR {
+                    self.explist();
+                }
+                self.expect(Token::ParR);
+            }
+            Token::CurlyL => self.table_constructor(),
+            Token::String => self.write_token(Token::String),
+            t => self.unexpected(t),
+        }
+    }
+
+    // the `{` has been read
+    fn table_constructor(&mut self) {
+        self.emit(b"{");
+        loop {
+            match self.lex.peek() {
+                Token::CurlyR => break,
+                Token::SqurL => {
+                    self.next_token();
+                    self.exp();
+                    self.expect(Token::SqurR);
+                    self.expect(Token::Assign);
+                    self.exp();
+                }
+                t@Token::Name(sym) => {
+                    self.lex.next();
+                    if self.lex.peek() == Token::Assign {
+                        self.emit(self.lex.name(sym).as_bytes());
+                        self.next_token();
+                        self.exp();
+                    } else {
+                        self.exp_with_ahead(t);
+                    }
+                }
+                _ => self.exp(),
+            }
+
+            match self.lex.peek() {
+                // the trailing separator is dropped
+                Token::Comma | Token::SemiColon => {
+                    self.lex.next();
+                    if self.lex.peek() != Token::CurlyR {
+                        self.emit(b",");
+                    }
+                }
+                Token::CurlyR => break,
+                t => self.unexpected(t),
+            }
+        }
+        self.lex.next();
+        self.emit(b"}");
+    }
+
+    // a name which is not a variable, e.g. field, method or label
+    fn field_name(&mut self) {
+        let name = self.read_name();
+        self.emit(name.as_bytes());
+    }
+
+    fn read_name(&mut self) -> Rc<str> {
+        match self.lex.next() {
+            Token::Name(sym) => self.lex.name(sym),
+            t => self.unexpected(t),
+        }
+    }
+
+    // reference of a variable: the new name of the local, or the global
+    fn variable(&mut self, name: Rc<str>) {
+        let new_name = match self.locals.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, new_name)) => new_name.clone(),
+            None => name,
+        };
+        self.emit(new_name.as_bytes());
+    }
+
+    // declare a local which is visible at once, and write its new name
+    fn local_new(&mut self, name: Rc<str>) {
+        let new_name = self.new_name(&name);
+        self.emit(new_name.as_bytes());
+        self.locals.push((name, new_name));
+    }
+
+    // new name of the next local
+    fn new_name(&mut self, name: &Rc<str>) -> Rc<str> {
+        let new_name = self.short_name(self.locals.len());
+        self.rename_count(name, &new_name);
+        new_name
+    }
+
+    fn rename_count(&mut self, name: &Rc<str>, new_name: &Rc<str>) {
+        if name != new_name {
+            self.renamed += 1;
+        }
+    }
+
+    // the short name of the @i-th live local, which avoids keywords and
+    // all names in the source
+    fn short_name(&mut self, i: usize) -> Rc<str> {
+        while self.short_names.len() <= i {
+            let name = candidate_name(self.candidate);
+            self.candidate += 1;
+            if !is_keyword(&name) && !self.reserved.contains(name.as_str()) {
+                self.short_names.push(Rc::from(name));
+            }
+        }
+        self.short_names[i].clone()
+    }
+
+    // read and write the next token
+    fn next_token(&mut self) {
+        let t = self.lex.next();
+        self.write_token(t);
+    }
+
+    fn expect(&mut self, t: Token) {
+        self.lex.expect(t);
+        self.write_token(t);
+    }
+
+    fn unexpected(&self, t: Token) -> ! {
+        self.lex.syntax_error(format!("unexpected {}", self.lex.describe(t)))
+    }
+
+    fn write_token(&mut self, t: Token) {
+        match t {
+            Token::Integer(_) | Token::Float(_) => {
+                // the shorter one of the formatted value and the source
+                let s = format_number(t).into_bytes();
+                let source = self.lex.string();
+                self.emit(if source.len() < s.len() { &source } else { &s });
+                self.last_number = true;
+            }
+            Token::String => {
+                let s = quote_string(&self.lex.string());
+                self.emit(&s);
+            }
+            Token::Name(sym) => {
+                let name = self.lex.name(sym);
+                self.emit(name.as_bytes());
+            }
+            t => self.emit(t.text().as_bytes()),
+        }
+    }
+
+    // write @s, with a space before it if it would be merged with the
+    // last token, e.g. names, `a - -b` which is not a comment, and
+    // `local x<const> = 1`
+    fn emit(&mut self, s: &[u8]) {
+        if let (Some(&last), Some(&first)) = (self.out.last(), s.first()) {
+            let word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+            let space = (word(last) && word(first))
+                || (last == b'-' && first == b'-')
+                || (last == b'[' && matches!(first, b'[' | b'='))
+                || (matches!(last, b'<' | b'>' | b'=' | b'~') && first == b'=')
+                || (first == b'.' && (last == b'.' || self.last_number))
+                || (last == b'.' && first.is_ascii_digit());
+            if space {
+                self.out.push(b' ');
+            }
+        }
+        self.out.extend_from_slice(s);
+        self.last_number = false;
+    }
+}
+
+// the shortest spelling of the number of @t by formatting
+fn format_number(t: Token) -> String {
+    match t {
+        // negative ones are from overflowed hexadecimals
+        Token::Integer(i) if i < 0 => format!("{:#x}", i as u64),
+        Token::Integer(i) => i.to_string(),
+        Token::Float(f) if f.is_infinite() => String::from("1e999"),
+        Token::Float(f) => {
+            let s = format!("{f:?}");
+            match s.strip_prefix("0.") {
+                Some(frac) => format!(".{frac}"),
+                None => s,
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+// the @k-th candidate of short names: a, b, ..., Z, _, a0, b0, ...
+fn candidate_name(mut k: usize) -> String {
+    const FIRST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+    const REST: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+    let mut name = vec![FIRST[k % FIRST.len()]];
+    k /= FIRST.len();
+    while k > 0 {
+        k -= 1;
+        name.push(REST[k % REST.len()]);
+        k /= REST.len();
+    }
+    String::from_utf8(name).unwrap()
+}
+
+fn is_keyword(name: &str) -> bool {
+    matches!(name, "and" | "break" | "do" | "else" | "elseif" | "end" | "false" | "for"
+        | "function" | "goto" | "if" | "in" | "local" | "nil" | "not" | "or" | "repeat"
+        | "return" | "then" | "true" | "until" | "while")
+}
+
+// The shorter one of quoted string and long string. The quote is the
+// one with less escapes, and only the necessary characters are escaped.
+fn quote_string(s: &[u8]) -> Vec<u8> {
+    let ndouble = s.iter().filter(|&&b| b == b'"').count();
+    let nsingle = s.iter().filter(|&&b| b == b'\'').count();
+    let quote = if ndouble > nsingle { b'\'' } else { b'"' };
+
+    let mut quoted = vec![quote];
+    for (i, &b) in s.iter().enumerate() {
+        match b {
+            b'\\' => quoted.extend_from_slice(b"\\\\"),
+            b'\n' => quoted.extend_from_slice(b"\\n"),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            b if b == quote => quoted.extend_from_slice(&[b'\\', b]),
+            b if b < 0x20 || b == 0x7f => {
+                // 3 digits if followed by a digit, e.g. "\0011" for "\1" and "1"
+                if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    quoted.extend_from_slice(format!("\\{b:03}").as_bytes());
+                } else {
+                    quoted.extend_from_slice(format!("\\{b}").as_bytes());
+                }
+            }
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(quote);
+
+    // long string, if shorter and the content is kept as is: no `\r`,
+    // which is converted, and the first newline, which is skipped, is
+    // added before the content
+    if !s.contains(&b'\r') {
+        let mut level = 0;
+        while s.windows(level + 2).any(|w| w[0] == b']' && w[level + 1] == b']'
+                && w[1..=level].iter().all(|&b| b == b'=')) {
+            level += 1;
+        }
+        let eqs = "=".repeat(level);
+        let newline = if s.first() == Some(&b'\n') { "\n" } else { "" };
+        let len = s.len() + newline.len() + 4 + 2 * level;
+        if len < quoted.len() {
+            let mut long = format!("[{eqs}[{newline}").into_bytes();
+            long.extend_from_slice(s);
+            long.extend_from_slice(format!("]{eqs}]").as_bytes());
+            return long;
+        }
+    }
+    quoted
+}
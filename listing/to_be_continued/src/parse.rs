@@ -1,17 +1,27 @@
 use std::rc::Rc;
 use std::io::Read;
+use std::mem::{self, Discriminant};
+use std::collections::HashMap;
 use std::cmp::Ordering;
 use crate::lex::{Lex, Token};
-use crate::bytecode::ByteCode;
+use crate::bytecode::{ByteCode, MAX_EXTRA_ARG};
 use crate::value::Value;
-use crate::utils::ftoi;
+use crate::utils::{ftoi, int_div, int_mod, float_div, float_mod, shift_left, shift_right};
+
+// default limit of nested expressions and blocks, same with LUAI_MAXCCALLS
+pub const MAX_SYNTAX_DEPTH: usize = 200;
+
+// limits of a function, same with the official Lua, because registers and
+// upvalues are u8 in byte codes
+const MAX_LOCALS: usize = 200;
+const MAX_UPVALUES: usize = 255;
 
 type FnBc2u8 = fn(u8, u8) -> ByteCode;
 type FnBc3u8 = fn(u8, u8, u8) -> ByteCode;
 type FnBcBool = fn(u8, u8, bool) -> ByteCode;
 
 // expression description, inner layer between source code and byte code
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 enum ExpDesc {
     // constants
     Nil,
@@ -47,6 +57,24 @@ enum ExpDesc {
     Compare(FnBcBool, usize, usize, Vec<usize>, Vec<usize>),
 }
 
+// Key of constants for deduplication, see add_const(). Floats are
+// compared by bits, so 0.0 and -0.0 are different constants, and
+// integers and floats with the same value are different too.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ConstKey {
+    Float(u64),
+    Other(Discriminant<Value>, Value),
+}
+
+impl From<&Value> for ConstKey {
+    fn from(c: &Value) -> Self {
+        match *c {
+            Value::Float(f) => ConstKey::Float(f.to_bits()),
+            _ => ConstKey::Other(mem::discriminant(c), c.clone()),
+        }
+    }
+}
+
 // see discharge_const()
 enum ConstStack {
     Const(usize),
@@ -56,9 +84,19 @@ enum ConstStack {
 // mark both goto and label
 #[derive(Debug)]
 struct GotoLabel {
-    name: String,
+    name: Rc<str>,
     icode: usize,
     nvar: usize,
+    close: bool, // for gotos, whether any local left is captured as upvalue
+}
+
+// jumps of `break` and `continue` in a loop, fixed at the end of the loop
+#[derive(Debug)]
+struct LoopBlock {
+    nvar: usize, // locals outside the loop
+    breaks: Vec<usize>,
+    continues: Vec<(usize, usize)>, // (icode, nvar)
+    close: bool, // whether any local in the loop is captured as upvalue
 }
 
 // index of locals/upvalues in upper functions
@@ -68,6 +106,15 @@ pub enum UpIndex {
     Upvalue(usize),
 }
 
+// local variable and its scope [icode_start, icode_end) in byte codes,
+// debug information for error messages
+#[derive(Debug)]
+pub struct LocalVar {
+    pub name: String,
+    pub icode_start: usize,
+    pub icode_end: usize,
+}
+
 // core struct, generated in parse phase and executed in VM
 #[derive(Debug, Default)]
 pub struct FuncProto {
@@ -76,19 +123,110 @@ pub struct FuncProto {
     pub constants: Vec<Value>,
     pub upindexes: Vec<UpIndex>,
     pub byte_codes: Vec<ByteCode>,
+
+    // number of registers used, computed by the parser, for the VM to
+    // reserve the call frame once
+    pub max_stack_size: usize,
+
+    // debug information
+    pub chunk_name: String, // see load_named()
+    pub lines: Vec<u32>, // source line of each byte code
+    pub locals: Vec<LocalVar>,
+    pub upvalue_names: Vec<String>,
+}
+
+impl FuncProto {
+    // name of the local variable at register @reg at byte code @icode
+    pub fn local_name(&self, reg: u8, icode: usize) -> Option<&str> {
+        self.locals.iter()
+            .filter(|v| v.icode_start <= icode && icode < v.icode_end)
+            .nth(reg as usize)
+            .map(|v| v.name.as_str())
+            .filter(|name| !name.is_empty()) // hidden variables in for-loop
+    }
+
+    // Describe the value at register @reg before byte code @icode, for
+    // error messages, e.g. "local 'x'" or "global 'print'". This tracks
+    // byte codes backward until any jump.
+    pub fn describe_reg(&self, reg: u8, icode: usize) -> Option<String> {
+        if let Some(name) = self.local_name(reg, icode) {
+            return Some(format!("local '{name}'"));
+        }
+
+        let const_name = |k: usize| self.constants[k].as_bytes()
+            .map(|s| String::from_utf8_lossy(s).into_owned());
+
+        for i in (0..icode).rev() {
+            let code = &self.byte_codes[i];
+            if code.is_jump() {
+                return None;
+            }
+            if code.dst() != Some(reg) {
+                continue;
+            }
+            return match *code {
+                ByteCode::Move(_, src) => self.local_name(src, i)
+                    .map(|name| format!("local '{name}'")),
+                // names are missing in stripped binary chunks
+                ByteCode::GetUpvalue(_, up) => self.upvalue_names.get(up as usize)
+                    .map(|name| format!("upvalue '{name}'")),
+                ByteCode::GetUpField(_, t, k) => {
+                    let is_env = self.upvalue_names.get(t as usize).is_some_and(|n| n == "_ENV");
+                    let kind = if is_env { "global" } else { "field" };
+                    const_name(k as usize).map(|k| format!("{kind} '{k}'"))
+                }
+                ByteCode::GetField(_, t, k) => {
+                    let kind = if self.local_name(t, i) == Some("_ENV") { "global" } else { "field" };
+                    const_name(k as usize).map(|k| format!("{kind} '{k}'"))
+                }
+                ByteCode::GetFieldSelf(_, _, k) =>
+                    const_name(k as usize).map(|k| format!("method '{k}'")),
+                ByteCode::GetInt(_, _, _) => Some("field 'integer index'".into()),
+                ByteCode::LoadConst(_, k) =>
+                    const_name(k as usize).map(|k| format!("constant '{k}'")),
+                _ => None,
+            };
+        }
+        None
+    }
 }
 
 // level of inner functions, used for matching upvalue
 #[derive(Debug, Default)]
 struct Level {
-    locals: Vec<(String, bool)>, // (name, referred-as-upvalue)
-    upvalues: Vec<(String, UpIndex)>,
+    locals: Vec<(Rc<str>, bool, bool)>, // (name, referred-as-upvalue, const)
+    upvalues: Vec<(Rc<str>, UpIndex)>,
+
+    // `<const>` locals with constant initializers, which are folded at
+    // use sites and take no register, see local_variables()
+    consts: Vec<(Rc<str>, ExpDesc, usize)>, // (name, value, #locals before it)
+}
+
+impl Level {
+    // search the folded constant, which is not shadowed by local
+    // variable @ilocal
+    fn find_const(&self, name: &str, ilocal: Option<usize>) -> Option<ExpDesc> {
+        let (_, desc, nlocal) = self.consts.iter().rev().find(|c| &*c.0 == name)?;
+        match ilocal {
+            Some(i) if i >= *nlocal => None, // local declared later
+            _ => Some(desc.clone()),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ParseContext<R: Read> {
     levels: Vec<Level>,
     lex: Lex<R>,
+    chunk_name: String,
+
+    // top-level locals of the main chunk are globals, see load_session()
+    session: bool,
+
+    // The parser is recursive descent, so the nested levels are limited
+    // to avoid overflowing the Rust stack, see enter_level().
+    depth: usize,
+    max_depth: usize,
 }
 
 #[derive(Debug)]
@@ -98,10 +236,11 @@ struct ParseProto<'a, R: Read> {
 
     // internal stuff for parsing
     sp: usize,
-    break_blocks: Vec<Vec<usize>>,
-    continue_blocks: Vec<Vec<(usize, usize)>>,
+    const_indexes: HashMap<ConstKey, usize>, // see add_const()
+    loop_blocks: Vec<LoopBlock>,
     gotos: Vec<GotoLabel>,
     labels: Vec<GotoLabel>,
+    nblock: usize, // depth of nested blocks in this function
     ctx: &'a mut ParseContext<R>,
 }
 
@@ -125,11 +264,24 @@ impl<'a, R: Read> ParseProto<'a, R> {
     //     local attnamelist [`=` explist]
     fn block(&mut self) -> Token {
         let nvar = self.local_num();
+        let nconst = self.const_num();
+        self.enter_level();
+        self.nblock += 1;
         let end_token = self.block_scope();
+        self.nblock -= 1;
+        self.leave_level();
         self.local_expire(nvar);
+        self.const_expire(nconst);
         end_token
     }
 
+    // the block is ended by @got, see block(), but @want is expected
+    fn check_end(&self, got: Token, want: Token) {
+        if got != want {
+            self.ctx.lex.expected(want, got);
+        }
+    }
+
     // same with block() but without expiring internal local variables
     fn block_scope(&mut self) -> Token {
         let igoto = self.gotos.len();
@@ -142,7 +294,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 Token::SemiColon => (),
                 t@Token::Name(_) | t@Token::ParL => {
                     // this is not standard!
-                    if self.try_continue_stat(&t) {
+                    if self.try_continue_stat(t) {
                         continue;
                     }
 
@@ -152,15 +304,16 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     if let ExpDesc::Call(ifunc, narg_plus) = desc {
                         // prefixexp() matches the whole functioncall statement.
                         let code = ByteCode::Call(ifunc as u8, narg_plus as u8, 0);
-                        self.fp.byte_codes.push(code);
+                        self.push_code(code);
                     } else {
                         // prefixexp() matches only the first variable, so we
                         // continue the statement
                         self.assignment(desc);
                     }
                 }
+                Token::Local if self.is_session_top() => self.session_local(),
                 Token::Local =>
-                    if self.ctx.lex.peek() == &Token::Function {
+                    if self.ctx.lex.peek() == Token::Function {
                         self.local_function()
                     } else {
                         self.local_variables()
@@ -188,25 +341,90 @@ impl<'a, R: Read> ParseProto<'a, R> {
     //   attnamelist ::=  Name attrib {`,` Name attrib}
     fn local_variables(&mut self) {
         // variable names
-        let mut vars = vec![self.read_name()];
-        while self.ctx.lex.peek() == &Token::Comma {
+        let mut vars = vec![self.read_attname()];
+        while self.ctx.lex.peek() == Token::Comma {
             self.ctx.lex.next();
-            vars.push(self.read_name());
+            vars.push(self.read_attname());
         }
 
-        if self.ctx.lex.peek() == &Token::Assign {
+        if self.ctx.lex.peek() == Token::Assign {
             // explist
             self.ctx.lex.next();
-            self.explist_want(vars.len());
+            let (nexp, last_exp, mergeable) = self.explist_mergeable();
+
+            // Same with Lua, if the last variable is `<const>` with a
+            // constant value, it is folded as compile-time constant.
+            if nexp + 1 == vars.len() && vars.last().unwrap().1 && is_const_desc(&last_exp) {
+                let (name, _) = vars.pop().unwrap();
+                for (var, is_const) in vars.into_iter() {
+                    self.local_new_attr(var, is_const);
+                }
+                let level = self.ctx.levels.last_mut().unwrap();
+                level.consts.push((name, last_exp, level.locals.len()));
+                return;
+            }
+            self.explist_adjust(vars.len(), nexp, last_exp, mergeable);
         } else {
             // no exp, load nils
+            self.reserve_registers(self.sp + vars.len());
             let code = ByteCode::LoadNil(self.sp as u8, vars.len() as u8);
-            self.fp.byte_codes.push(code);
+            self.push_code(code);
         }
 
         // append vars into self.locals after evaluating explist
-        for var in vars.into_iter() {
-            self.local_new(var);
+        for (var, is_const) in vars.into_iter() {
+            self.local_new_attr(var, is_const);
+        }
+    }
+
+    fn is_session_top(&self) -> bool {
+        self.ctx.session && self.ctx.levels.len() == 1 && self.nblock == 0
+    }
+
+    // `local` statement at the top level of a session chunk, where the
+    // variables are assigned as globals, so they are kept for the
+    // following chunks. The attribute `<const>` is ignored.
+    fn session_local(&mut self) {
+        if self.ctx.lex.peek() == Token::Function {
+            self.ctx.lex.next();
+            let name = self.read_name();
+            let var = self.simple_name(name);
+            let f = self.funcbody(false);
+            self.assign_var(var, f);
+            return;
+        }
+
+        let mut vars = vec![self.read_attname().0];
+        while self.ctx.lex.peek() == Token::Comma {
+            self.ctx.lex.next();
+            vars.push(self.read_attname().0);
+        }
+        let vars: Vec<ExpDesc> = vars.into_iter().map(|v| self.simple_name(v)).collect();
+
+        if self.ctx.lex.peek() == Token::Assign {
+            self.ctx.lex.next();
+            self.assign_explist(vars);
+        } else {
+            for var in vars {
+                self.assign_var(var, ExpDesc::Nil);
+            }
+        }
+    }
+
+    // Name attrib, return (name, is-const)
+    //   attrib ::= [`<` Name `>`]
+    fn read_attname(&mut self) -> (Rc<str>, bool) {
+        let name = self.read_name();
+        if self.ctx.lex.peek() != Token::Less {
+            return (name, false);
+        }
+        self.ctx.lex.next();
+        let attr = self.read_name();
+        self.ctx.lex.expect(Token::Greater);
+        match &*attr {
+            "const" => (name, true),
+            "close" => self.ctx.lex.syntax_error("<close> variable is not supported".into()),
+            _ => self.ctx.lex.syntax_error(format!("unknown attribute '{attr}'")),
         }
     }
 
@@ -215,7 +433,8 @@ impl<'a, R: Read> ParseProto<'a, R> {
     fn local_function(&mut self) {
         self.ctx.lex.next();
         let name = self.read_name();
-        println!("== function: {name}");
+        #[cfg(feature = "trace")]
+        eprintln!("== function: {name}");
 
         // create `name` local variable before parsing funcbody(),
         // so the function can be called in body as recursion.
@@ -238,13 +457,15 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     self.ctx.lex.next();
                     let name = self.read_name();
                     let t = self.discharge_any(desc);
-                    desc = ExpDesc::IndexField(t, self.add_const(name));
+                    let ikey = self.add_const(&*name);
+                    desc = self.index_field(t, ikey);
                 }
                 Token::Colon => { // `:` Name
                     self.ctx.lex.next();
                     let name = self.read_name();
                     let t = self.discharge_any(desc);
-                    desc = ExpDesc::IndexField(t, self.add_const(name));
+                    let ikey = self.add_const(&*name);
+                    desc = self.index_field(t, ikey);
 
                     break true;
                 }
@@ -267,17 +488,17 @@ impl<'a, R: Read> ParseProto<'a, R> {
         let mut has_varargs = false;
         let mut params = Vec::new();
         if with_self {
-            params.push(String::from("self"));
+            params.push(self.ctx.lex.intern("self"));
         }
         self.ctx.lex.expect(Token::ParL);
         loop {
             match self.ctx.lex.next() {
                 Token::Name(name) => {
-                    params.push(name);
+                    params.push(self.ctx.lex.name(name));
                     match self.ctx.lex.next() {
                         Token::Comma => (),
                         Token::ParR => break,
-                        t => panic!("invalid parameter {t:?}"),
+                        t => self.ctx.lex.expected(Token::ParR, t),
                     }
                 }
                 Token::Dots => {
@@ -286,7 +507,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     break;
                 },
                 Token::ParR => break,
-                t => panic!("invalid parameter {t:?}"),
+                t => self.ctx.lex.syntax_error(format!("<name> expected near {}", self.ctx.lex.describe(t))),
             }
         }
 
@@ -315,10 +536,17 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     vars.push(self.prefixexp(token));
                 }
                 Token::Assign => break,
-                t => panic!("invalid assign {t:?}"),
+                t => self.ctx.lex.syntax_error(format!("syntax error near {}", self.ctx.lex.describe(t))),
             }
         }
+        for var in vars.iter() {
+            self.check_assignable(var);
+        }
+        self.assign_explist(vars);
+    }
 
+    // explist after `=`, and assign to @vars
+    fn assign_explist(&mut self, mut vars: Vec<ExpDesc>) {
         let sp0 = self.sp;
         let (mut nexp, last_exp) = self.explist();
 
@@ -341,9 +569,13 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
 
         // assign previous variables from tmp registers, in reverse order
+        let icode = self.fp.byte_codes.len();
         while let Some(var) = vars.pop() {
             nexp -= 1;
             self.assign_from_stack(var, sp0 + nexp);
+            if self.fp.byte_codes.len() > icode + 1 {
+                self.merge_last_codes();
+            }
         }
     }
 
@@ -365,7 +597,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
             end_token = self.block();
         }
 
-        assert_eq!(end_token, Token::End);
+        self.check_end(end_token, Token::End);
 
         let iend = self.fp.byte_codes.len() - 1;
         for i in jmp_ends.into_iter() {
@@ -387,7 +619,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
         // Make a fake byte-code to hold the place, and fix it
         // at the end of whole if-statment.
         if matches!(end_token, Token::Elseif | Token::Else) {
-            self.fp.byte_codes.push(ByteCode::Jump(0));
+            self.push_code(ByteCode::Jump(0));
             jmp_ends.push(self.fp.byte_codes.len() - 1);
         }
 
@@ -408,11 +640,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         self.push_loop_block();
 
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block();
+        self.check_end(end_token, Token::End);
 
         // jump back
         let iend = self.fp.byte_codes.len();
-        self.fp.byte_codes.push(ByteCode::Jump(-((iend - istart) as i16) - 1));
+        self.push_code(ByteCode::Jump(-((iend - istart) as i16) - 1));
 
         self.pop_loop_block(istart);
 
@@ -427,26 +660,44 @@ impl<'a, R: Read> ParseProto<'a, R> {
         self.push_loop_block();
 
         let nvar = self.local_num();
+        let nconst = self.const_num();
 
-        assert_eq!(self.block_scope(), Token::Until);
+        self.enter_level();
+        self.nblock += 1;
+        let end_token = self.block_scope();
+        self.check_end(end_token, Token::Until);
+        self.nblock -= 1;
+        self.leave_level();
         let iend = self.fp.byte_codes.len();
 
         let condition = self.exp();
         let false_list = self.test_or_jump(condition);
-        self.fix_test_list_to(false_list, istart);
+        if self.loop_blocks.last().unwrap().close || self.local_captured(nvar) {
+            // close the captured locals before the next iteration
+            self.push_code(ByteCode::Jump(0));
+            let iexit = self.fp.byte_codes.len() - 1;
+            self.fix_test_list(false_list);
+            self.push_code(ByteCode::Close(nvar as u8));
+            let iback = self.fp.byte_codes.len();
+            self.push_code(ByteCode::Jump(-((iback + 1 - istart) as i16)));
+            self.fix_test_list(vec![iexit]);
+        } else {
+            self.fix_test_list_to(false_list, istart);
+        }
 
         self.pop_loop_block(iend);
 
         // expire internal local variables AFTER reading condition exp
         // and pop_loop_block()
         self.local_expire(nvar);
+        self.const_expire(nconst);
     }
 
     // * numerical: for Name `=` ...
     // * generic:   for Name {, Name} in ...
     fn for_stat(&mut self) {
         let name = self.read_name();
-        if self.ctx.lex.peek() == &Token::Assign {
+        if self.ctx.lex.peek() == Token::Assign {
             self.numerical_for(name);
         } else {
             self.generic_for(name);
@@ -455,7 +706,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
     // BNF:
     //   for Name `=` exp `,` exp [`,` exp] do block end
-    fn numerical_for(&mut self, name: String) {
+    fn numerical_for(&mut self, name: Rc<str>) {
         self.ctx.lex.next(); // skip `=`
 
         // 2 or 3 exps
@@ -465,33 +716,38 @@ impl<'a, R: Read> ParseProto<'a, R> {
         match nexp + 1 {
             2 => self.discharge(self.sp, ExpDesc::Integer(1)),
             3 => (),
-            _ => panic!("invalid numerical for exp"),
+            1 => {
+                let t = self.ctx.lex.peek();
+                self.ctx.lex.expected(Token::Comma, t);
+            }
+            _ => self.ctx.lex.syntax_error("too many expressions in numerical for".into()),
         }
 
+        self.push_loop_block();
+
         // create 3 local variables: the first is iterator,
         // and the other two to keep stack positions.
         self.local_new(name);
-        self.local_new(String::from(""));
-        self.local_new(String::from(""));
+        self.local_hidden();
+        self.local_hidden();
 
         self.ctx.lex.expect(Token::Do);
 
         // ByteCode::ForPrepare, without argument
-        self.fp.byte_codes.push(ByteCode::ForPrepare(0, 0));
+        self.push_code(ByteCode::ForPrepare(0, 0));
         let iprepare = self.fp.byte_codes.len() - 1;
         let iname = self.sp - 3;
 
-        self.push_loop_block();
-
         // parse block!
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block();
+        self.check_end(end_token, Token::End);
 
         // expire 3 local variables above, before ByteCode::ForLoop
         self.local_expire(self.local_num() - 3);
 
         // ByteCode::ForLoop, and fix ByteCode::ForPrepare above
         let d = self.fp.byte_codes.len() - iprepare;
-        self.fp.byte_codes.push(ByteCode::ForLoop(iname as u8, d as u16));
+        self.push_code(ByteCode::ForLoop(iname as u8, d as u16));
         self.fp.byte_codes[iprepare] = ByteCode::ForPrepare(iname as u8, d as u16);
 
         self.pop_loop_block(self.fp.byte_codes.len() - 1);
@@ -500,15 +756,15 @@ impl<'a, R: Read> ParseProto<'a, R> {
     // BNF:
     //   stat ::= for namelist in explist do block end
     //   namelist ::= Name {`,` Name}
-    fn generic_for(&mut self, name: String) {
+    fn generic_for(&mut self, name: Rc<str>) {
         // namelist
         let mut vars = vec![name];
         loop {
             match self.ctx.lex.next() {
                 Token::Comma => continue,
                 Token::In => break,
-                Token::Name(name) => vars.push(name),
-                _ => panic!("invalid generic_for namelist"),
+                Token::Name(name) => vars.push(self.ctx.lex.name(name)),
+                t => self.ctx.lex.syntax_error(format!("'=' or 'in' expected near {}", self.ctx.lex.describe(t))),
             }
         }
 
@@ -516,10 +772,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
         let iter = self.sp;
         self.explist_want(3);
 
+        self.push_loop_block();
+
         let nvar = vars.len();
-        self.local_new(String::from("")); // iterator function
-        self.local_new(String::from("")); // immutable state
-        self.local_new(String::from("")); // control variable
+        self.local_hidden(); // iterator function
+        self.local_hidden(); // immutable state
+        self.local_hidden(); // control variable
         for var in vars.into_iter() {
             self.local_new(var);
         }
@@ -527,13 +785,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
         self.ctx.lex.expect(Token::Do);
 
         // jump to ByteCode::ForCallLoop at end of block
-        self.fp.byte_codes.push(ByteCode::Jump(0));
+        self.push_code(ByteCode::Jump(0));
         let ijump = self.fp.byte_codes.len() - 1;
 
-        self.push_loop_block();
-
         // parse block!
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block();
+        self.check_end(end_token, Token::End);
 
         // expire local variables above, before ByteCode::Jump
         self.local_expire(self.local_num() - 3 - nvar);
@@ -542,27 +799,29 @@ impl<'a, R: Read> ParseProto<'a, R> {
         // call the iter function and check the control variable
         let d = self.fp.byte_codes.len() - ijump;
         self.fp.byte_codes[ijump] = ByteCode::Jump(d as i16 - 1);
+        let icall = self.fp.byte_codes.len();
         if let Ok(d) = u8::try_from(d) {
-            self.fp.byte_codes.push(ByteCode::ForCallLoop(iter as u8, nvar as u8, d as u8));
+            self.push_code(ByteCode::ForCallLoop(iter as u8, nvar as u8, d));
         } else {
-            self.fp.byte_codes.push(ByteCode::ForCallLoop(iter as u8, nvar as u8, 0));
-            self.fp.byte_codes.push(ByteCode::Jump(-(d as i16) - 1));
+            self.push_code(ByteCode::ForCallLoop(iter as u8, nvar as u8, 0));
+            self.push_code(ByteCode::Jump(-(d as i16) - 1));
         }
 
-        self.pop_loop_block(self.fp.byte_codes.len() - 1);
+        self.pop_loop_block(icall);
     }
 
     fn break_stat(&mut self) {
-        let Some(breaks) = self.break_blocks.last_mut() else {
-            panic!("break outside loop");
-        };
-        self.fp.byte_codes.push(ByteCode::Jump(0));
-        breaks.push(self.fp.byte_codes.len() - 1);
+        if self.loop_blocks.is_empty() {
+            self.ctx.lex.syntax_error("break outside loop".into());
+        }
+        self.push_code(ByteCode::Jump(0));
+        let icode = self.fp.byte_codes.len() - 1;
+        self.loop_blocks.last_mut().unwrap().breaks.push(icode);
     }
 
-    fn try_continue_stat(&mut self, name: &Token) -> bool {
+    fn try_continue_stat(&mut self, name: Token) -> bool {
         let Token::Name(name) = name else { return false; };
-        if name.as_str() != "continue" {
+        if &*self.ctx.lex.name(name) != "continue" {
             return false;
         }
         if !matches!(self.ctx.lex.peek(), Token::End | Token::Elseif | Token::Else) {
@@ -570,41 +829,66 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
 
         let nvar = self.local_num();
-        let Some(continues) = self.continue_blocks.last_mut() else {
-            panic!("continue outside loop");
-        };
-        self.fp.byte_codes.push(ByteCode::Jump(0));
-        continues.push((self.fp.byte_codes.len() - 1, nvar));
+        if self.loop_blocks.is_empty() {
+            self.ctx.lex.syntax_error("continue outside loop".into());
+        }
+        self.push_code(ByteCode::Jump(0));
+        let icode = self.fp.byte_codes.len() - 1;
+        self.loop_blocks.last_mut().unwrap().continues.push((icode, nvar));
         true
     }
 
-    // before entering loop block
+    // before entering loop block, and before the loop's own locals
     fn push_loop_block(&mut self) {
-        self.break_blocks.push(Vec::new());
-        self.continue_blocks.push(Vec::new());
+        self.loop_blocks.push(LoopBlock {
+            nvar: self.local_num(),
+            breaks: Vec::new(),
+            continues: Vec::new(),
+            close: false,
+        });
     }
     // after leaving loop block, fix `break` and `continue` Jumps
+    //
+    // They jump over the Close at the end of the blocks, so if any local
+    // in the loop is captured, the upvalues are closed at their targets
+    // instead, or they would be shared by the next iteration or by the
+    // following locals at the same registers.
     fn pop_loop_block(&mut self, icontinue: usize) {
-        // breaks
-        let iend = self.fp.byte_codes.len() - 1;
-        for i in self.break_blocks.pop().unwrap().into_iter() {
-            self.fp.byte_codes[i] = ByteCode::Jump((iend - i) as i16);
-        }
-
-        // continues
+        let block = self.loop_blocks.pop().unwrap();
+
+        // continues, to Close and then to @icontinue
+        let icontinue = if block.close && !block.continues.is_empty() {
+            self.push_code(ByteCode::Jump(2)); // skipped by the loop's exit
+            let iclose = self.fp.byte_codes.len();
+            self.push_code(ByteCode::Close(block.nvar as u8));
+            self.push_code(ByteCode::Jump(-((iclose + 2 - icontinue) as i16)));
+            iclose
+        } else {
+            icontinue
+        };
         let end_nvar = self.local_num();
-        for (i, i_nvar) in self.continue_blocks.pop().unwrap().into_iter() {
+        for (i, i_nvar) in block.continues.into_iter() {
             if i_nvar < end_nvar {
-                panic!("continue jump into local scope");
+                self.ctx.lex.syntax_error("continue jump into local scope".into());
             }
             self.fp.byte_codes[i] = ByteCode::Jump((icontinue as isize - i as isize) as i16 - 1);
         }
+
+        // breaks, to Close after the loop
+        let iend = self.fp.byte_codes.len() - 1;
+        for &i in &block.breaks {
+            self.fp.byte_codes[i] = ByteCode::Jump((iend - i) as i16);
+        }
+        if block.close && !block.breaks.is_empty() {
+            self.push_code(ByteCode::Close(block.nvar as u8));
+        }
     }
 
     // BNF:
     //   do block end
     fn do_stat(&mut self) {
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block();
+        self.check_end(end_token, Token::End);
     }
 
     // BNF:
@@ -630,7 +914,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // check duplicate
         if self.labels.iter().any(|l|l.name == name) {
-            panic!("duplicate label {name}");
+            self.ctx.lex.syntax_error(format!("duplicate label {name}"));
         }
 
         let icode = self.fp.byte_codes.len();
@@ -638,13 +922,18 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // match previous gotos
         let mut no_dsts = Vec::new();
+        let mut close = None;
         for goto in self.gotos.drain(igoto..) {
             if goto.name == name {
                 if !is_last && goto.nvar < nvar {
-                    panic!("goto jump into scope {}", goto.name);
+                    self.ctx.lex.syntax_error(format!("goto jump into scope {}", goto.name));
                 }
                 let dist = icode - goto.icode;
                 self.fp.byte_codes[goto.icode] = ByteCode::Jump(dist as i16 - 1);
+                if goto.close {
+                    let level = goto.nvar.min(nvar);
+                    close = Some(close.map_or(level, |c: usize| c.min(level)));
+                }
             } else {
                 // no matched label
                 no_dsts.push(goto);
@@ -652,8 +941,14 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
         self.gotos.append(&mut no_dsts);
 
+        // the gotos jump over the Close at the end of the blocks they
+        // leave, so close the captured locals here
+        if let Some(from) = close {
+            self.push_code(ByteCode::Close(from as u8));
+        }
+
         // save the label for following gotos
-        self.labels.push(GotoLabel { name, icode, nvar });
+        self.labels.push(GotoLabel { name, icode, nvar, close: false });
     }
 
     // BNF:
@@ -664,18 +959,20 @@ impl<'a, R: Read> ParseProto<'a, R> {
         // match previous label
         if let Some(label) = self.labels.iter().rev().find(|l|l.name == name) {
             // find label
-            let dist = self.fp.byte_codes.len() - label.icode;
+            let icode = label.icode;
             self.local_check_close(label.nvar);
-            self.fp.byte_codes.push(ByteCode::Jump(-(dist as i16) - 1));
+            let dist = self.fp.byte_codes.len() - icode;
+            self.push_code(ByteCode::Jump(-(dist as i16) - 1));
 
         } else {
             // not find label, push a fake byte code and save the goto
-            self.fp.byte_codes.push(ByteCode::Jump(0));
+            self.push_code(ByteCode::Jump(0));
 
             self.gotos.push(GotoLabel {
                 name,
                 icode: self.fp.byte_codes.len() - 1,
                 nvar: self.local_num(),
+                close: false,
             });
         }
     }
@@ -696,12 +993,13 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 let (nexp, last_exp) = self.explist();
 
                 // check optional ';'
-                if self.ctx.lex.peek() == &Token::SemiColon {
+                if self.ctx.lex.peek() == Token::SemiColon {
                     self.ctx.lex.next();
                 }
                 // check block end
                 if !is_block_end(self.ctx.lex.peek()) {
-                    panic!("'end' expected");
+                    let t = self.ctx.lex.peek();
+                    self.ctx.lex.expected(Token::End, t);
                 }
 
                 if let (0, &ExpDesc::Local(i)) = (nexp, &last_exp) {
@@ -723,7 +1021,21 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 }
             }
         };
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
+    }
+
+    fn check_assignable(&self, var: &ExpDesc) {
+        match var {
+            ExpDesc::Local(i) => {
+                let (name, _, is_const) = &self.ctx.levels.last().unwrap().locals[*i];
+                if *is_const {
+                    self.ctx.lex.syntax_error(format!("attempt to assign to const variable '{name}'"));
+                }
+            }
+            // folded constant
+            var if is_const_desc(var) => self.ctx.lex.syntax_error("attempt to assign to const variable".into()),
+            _ => (),
+        }
     }
 
     // process assignment: var = value
@@ -751,7 +1063,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
             ExpDesc::IndexUpField(t, key) => ByteCode::SetUpField(t as u8, key as u8, value as u8),
             _ => panic!("assign from stack"),
         };
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
     }
 
     fn assign_from_const(&mut self, var: ExpDesc, value: usize) {
@@ -763,17 +1075,85 @@ impl<'a, R: Read> ParseProto<'a, R> {
             ExpDesc::IndexUpField(t, key) => ByteCode::SetUpFieldConst(t as u8, key as u8, value as u8),
             _ => panic!("assign from const"),
         };
+        self.push_code(code);
+    }
+
+    // push the byte code, with the current line for debug information
+    fn push_code(&mut self, code: ByteCode) {
         self.fp.byte_codes.push(code);
+        self.fp.lines.push(self.ctx.lex.last_line() as u32);
     }
 
-    // add the value to constants
+    // Add the constant if not added yet, and return its index. The
+    // constants are ordered by their first uses in `fp.constants`, while
+    // `const_indexes` is only an index of it and is never iterated, so
+    // compiling the same source always gives the same byte codes.
     fn add_const(&mut self, c: impl Into<Value>) -> usize {
         let c = c.into();
+        let key = ConstKey::from(&c);
+        if let Some(&i) = self.const_indexes.get(&key) {
+            return i;
+        }
         let constants = &mut self.fp.constants;
-        constants.iter().position(|v| v.same(&c)).unwrap_or_else(|| {
-            constants.push(c);
-            constants.len() - 1
-        })
+        if constants.len() > MAX_EXTRA_ARG {
+            self.ctx.lex.syntax_error("too many constants".into());
+        }
+        constants.push(c);
+        self.const_indexes.insert(key, constants.len() - 1);
+        constants.len() - 1
+    }
+
+    // Most byte codes take constant indexes in u8. Return ConstStack::Const
+    // if @k fits in; otherwise load the constant into stack.
+    fn const_or_stack(&mut self, k: usize) -> ConstStack {
+        if k <= u8::MAX as usize {
+            ConstStack::Const(k)
+        } else {
+            ConstStack::Stack(self.load_const(self.sp, k))
+        }
+    }
+    fn add_const_u8(&mut self, c: impl Into<Value>) -> ConstStack {
+        let k = self.add_const(c);
+        self.const_or_stack(k)
+    }
+
+    // load constant @k into @dst, and update self.sp=dst+1
+    fn load_const(&mut self, dst: usize, k: usize) -> usize {
+        self.reserve_registers(dst + 1);
+        let code = self.code_load_const(dst, k);
+        self.push_code(code);
+        self.sp = dst + 1;
+        dst
+    }
+
+    // LoadConst, or LoadConstX followed by ExtraArg if @k is out of u16.
+    // Return the last byte code, which is left to the caller to push.
+    fn code_load_const(&mut self, dst: usize, k: usize) -> ByteCode {
+        match u16::try_from(k) {
+            Ok(k) => ByteCode::LoadConst(dst as u8, k),
+            Err(_) => {
+                self.push_code(ByteCode::LoadConstX(dst as u8));
+                ByteCode::extra_arg(k)
+            }
+        }
+    }
+
+    // @itable[constants[@ikey]], by Index if @ikey does not fit in
+    // IndexField
+    fn index_field(&mut self, itable: usize, ikey: usize) -> ExpDesc {
+        match self.const_or_stack(ikey) {
+            ConstStack::Const(ikey) => ExpDesc::IndexField(itable, ikey),
+            ConstStack::Stack(ikey) => ExpDesc::Index(itable, ikey),
+        }
+    }
+    // same with index_field(), while the table is an upvalue
+    fn index_up_field(&mut self, itable: usize, ikey: usize) -> ExpDesc {
+        if ikey <= u8::MAX as usize {
+            ExpDesc::IndexUpField(itable, ikey)
+        } else {
+            let itable = self.discharge_any(ExpDesc::Upvalue(itable));
+            self.index_field(itable, ikey)
+        }
     }
 
     // explist ::= exp {`,` exp}
@@ -781,23 +1161,49 @@ impl<'a, R: Read> ParseProto<'a, R> {
     // Read expressions, discharge front ones, and keep last one.
     // Return the number of front expressions and the last expression.
     fn explist(&mut self) -> (usize, ExpDesc) {
+        let (n, desc, _) = self.explist_mergeable();
+        (n, desc)
+    }
+
+    // Same with explist(), while continuous Move and LoadNil of front
+    // expressions are merged. The returned bool tells if the last
+    // expression can be merged too, see explist_want().
+    fn explist_mergeable(&mut self) -> (usize, ExpDesc, bool) {
         let sp0 = self.sp;
         let mut n = 0;
+        let mut last_single = false;
         loop {
+            let icode = self.fp.byte_codes.len();
             let desc = self.exp();
-            if self.ctx.lex.peek() != &Token::Comma {
+            if self.ctx.lex.peek() != Token::Comma {
                 self.sp = sp0 + n;
-                return (n, desc);
+                let mergeable = last_single && is_single_code(&desc)
+                    && self.fp.byte_codes.len() == icode;
+                return (n, desc, mergeable);
             }
             self.ctx.lex.next();
 
+            // No jump lands among single Move or LoadNil codes, so it's
+            // safe to merge them.
+            let single = is_single_code(&desc);
             self.discharge(sp0 + n, desc);
+            let single = single && self.fp.byte_codes.len() == icode + 1;
+            if single && last_single {
+                self.merge_last_codes();
+            }
+            last_single = single;
             n += 1;
         }
     }
 
     fn explist_want(&mut self, want: usize) {
-        let (nexp, last_exp) = self.explist();
+        let (nexp, last_exp, mergeable) = self.explist_mergeable();
+        self.explist_adjust(want, nexp, last_exp, mergeable);
+    }
+
+    // adjust the expressions to #want values
+    fn explist_adjust(&mut self, want: usize, nexp: usize, last_exp: ExpDesc, mergeable: bool) {
+        let icode = self.fp.byte_codes.len();
         match (nexp + 1).cmp(&want) {
             Ordering::Equal => {
                 self.discharge(self.sp, last_exp);
@@ -811,6 +1217,48 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 self.sp -= nexp - want;
             }
         }
+        if mergeable {
+            for _ in icode .. self.fp.byte_codes.len() {
+                self.merge_last_codes();
+            }
+        }
+    }
+
+    // Merge the last 2 byte codes into one if they are LoadNil or Move
+    // on continuous registers. The caller makes sure that no jump lands
+    // at the last one.
+    fn merge_last_codes(&mut self) {
+        let codes = &mut self.fp.byte_codes;
+        let [.., prev, last] = codes.as_slice() else {
+            return;
+        };
+        let merged = match (prev, last) {
+            (&ByteCode::LoadNil(d1, n1), &ByteCode::LoadNil(d2, n2))
+                if d1 as usize + n1 as usize == d2 as usize =>
+                ByteCode::LoadNil(d1, n1 + n2),
+
+            // ascending, in explist
+            (&ByteCode::Move(d1, s1), &ByteCode::Move(d2, s2))
+                if d1 as usize + 1 == d2 as usize && s1 as usize + 1 == s2 as usize =>
+                ByteCode::MoveN(d1, s1, 2),
+            (&ByteCode::MoveN(d1, s1, n), &ByteCode::Move(d2, s2))
+                if d1 as usize + n as usize == d2 as usize
+                    && s1 as usize + n as usize == s2 as usize =>
+                ByteCode::MoveN(d1, s1, n + 1),
+
+            // descending, in assignment
+            (&ByteCode::Move(d1, s1), &ByteCode::Move(d2, s2))
+                if d2 as usize + 1 == d1 as usize && s2 as usize + 1 == s1 as usize =>
+                ByteCode::MoveN(d2, s2, 2),
+            (&ByteCode::MoveN(d1, s1, n), &ByteCode::Move(d2, s2))
+                if d2 as usize + 1 == d1 as usize && s2 as usize + 1 == s1 as usize =>
+                ByteCode::MoveN(d2, s2, n + 1),
+
+            _ => return,
+        };
+        codes.pop();
+        *codes.last_mut().unwrap() = merged;
+        self.fp.lines.pop();
     }
 
     // BNF:
@@ -834,6 +1282,8 @@ impl<'a, R: Read> ParseProto<'a, R> {
         self.do_exp(0, ahead)
     }
     fn do_exp(&mut self, limit: i32, ahead: Token) -> ExpDesc {
+        self.enter_level();
+
         // beta
         let mut desc = match ahead {
             Token::Nil => ExpDesc::Nil,
@@ -841,11 +1291,11 @@ impl<'a, R: Read> ParseProto<'a, R> {
             Token::False => ExpDesc::Boolean(false),
             Token::Integer(i) => ExpDesc::Integer(i),
             Token::Float(f) => ExpDesc::Float(f),
-            Token::String(s) => ExpDesc::String(s),
+            Token::String => ExpDesc::String(self.ctx.lex.string()),
 
             Token::Dots => {
                 if !self.fp.has_varargs {
-                    panic!("no varargs");
+                    self.ctx.lex.syntax_error("no varargs".into());
                 }
                 ExpDesc::VarArgs
             }
@@ -866,6 +1316,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
             // Non-operator tokens' priority is -1(lowest) so they always break here.
             let (left_pri, right_pri) = binop_pri(self.ctx.lex.peek());
             if left_pri <= limit {
+                self.leave_level();
                 return desc;
             }
 
@@ -876,6 +1327,18 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
     }
 
+    // Called when entering nested expressions and blocks, which are
+    // parsed by recursion.
+    fn enter_level(&mut self) {
+        self.ctx.depth += 1;
+        if self.ctx.depth > self.ctx.max_depth {
+            self.ctx.lex.syntax_error("chunk has too many syntax levels".into());
+        }
+    }
+    fn leave_level(&mut self) {
+        self.ctx.depth -= 1;
+    }
+
     // used for unary operand
     fn exp_unop(&mut self) -> ExpDesc {
         self.exp_limit(12) // 12 is all unary operators' priority
@@ -916,13 +1379,16 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // beta
         let mut desc = match ahead {
-            Token::Name(name) => self.simple_name(name),
+            Token::Name(name) => {
+                let name = self.ctx.lex.name(name);
+                self.simple_name(name)
+            }
             Token::ParL => { // `(` exp `)`
                 let desc = self.exp();
                 self.ctx.lex.expect(Token::ParR);
                 desc
             }
-            t => panic!("invalid prefixexp {t:?}"),
+            t => self.ctx.lex.syntax_error(format!("unexpected symbol near {}", self.ctx.lex.describe(t))),
         };
 
         // A' = alpha A'
@@ -936,14 +1402,17 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     desc = match (desc, key) {
                         // special case: upvalue-table and string-key
                         (ExpDesc::Upvalue(itable), ExpDesc::String(key)) => {
-                            ExpDesc::IndexUpField(itable, self.add_const(key))
+                            let ikey = self.add_const(key);
+                            self.index_up_field(itable, ikey)
                         }
                         // normal case
                         (table, key) => {
                             let itable = self.discharge_if_need(sp0, table);
                             match key {
-                                ExpDesc::String(key) =>
-                                    ExpDesc::IndexField(itable, self.add_const(key)),
+                                ExpDesc::String(key) => {
+                                    let ikey = self.add_const(key);
+                                    self.index_field(itable, ikey)
+                                }
                                 ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
                                     ExpDesc::IndexInt(itable, u8::try_from(i).unwrap()),
                                 _ =>
@@ -955,33 +1424,46 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 Token::Dot => { // .Name
                     self.ctx.lex.next();
                     let name = self.read_name();
-                    let ikey = self.add_const(name);
+                    let ikey = self.add_const(&*name);
 
                     desc = if let ExpDesc::Upvalue(itable) = desc {
-                        ExpDesc::IndexUpField(itable, ikey)
+                        self.index_up_field(itable, ikey)
                     } else {
                         let itable = self.discharge_if_need(sp0, desc);
-                        ExpDesc::IndexField(itable, ikey)
+                        self.index_field(itable, ikey)
                     };
                 }
                 Token::Colon => { // :Name args
                     self.ctx.lex.next();
                     let name = self.read_name();
-                    let ikey = self.add_const(name);
+                    let ikey = self.add_const(&*name);
                     let itable = self.discharge_if_need(sp0, desc);
 
                     // GetFieldSelf:
                     //   stack[sp0] := itable[ikey]  # load function
                     //   stack[sp0+1] := itable      # load table as first argument
-                    self.fp.byte_codes.push(
-                        ByteCode::GetFieldSelf(sp0 as u8, itable as u8, ikey as u8));
+                    self.reserve_registers(sp0 + 2);
+                    if ikey <= u8::MAX as usize {
+                        self.push_code(
+                            ByteCode::GetFieldSelf(sp0 as u8, itable as u8, ikey as u8));
+                    } else {
+                        // the key does not fit, so load it into stack
+                        // after the table, which is overwritten by arguments
+                        if itable != sp0 + 1 {
+                            self.push_code(
+                                ByteCode::Move(sp0 as u8 + 1, itable as u8));
+                        }
+                        self.load_const(sp0 + 2, ikey);
+                        self.push_code(
+                            ByteCode::GetTable(sp0 as u8, sp0 as u8 + 1, sp0 as u8 + 2));
+                    }
 
                     // discharge following arguments begin at sp0+2
                     self.sp = sp0 + 2;
 
                     desc = self.args(1);
                 }
-                Token::ParL | Token::CurlyL | Token::String(_) => { // args
+                Token::ParL | Token::CurlyL | Token::String => { // args
                     self.discharge(sp0, desc);
                     desc = self.args(0);
                 }
@@ -994,35 +1476,82 @@ impl<'a, R: Read> ParseProto<'a, R> {
         self.ctx.levels.last().unwrap().locals.len()
     }
 
-    fn local_new(&mut self, name: String) {
-        self.ctx.levels.last_mut().unwrap().locals.push((name, false));
+    fn local_new(&mut self, name: Rc<str>) {
+        self.local_new_attr(name, false);
+    }
+    // hidden variable in for-loop, which can not be referred by name
+    fn local_hidden(&mut self) {
+        let name = self.ctx.lex.intern("");
+        self.local_new(name);
+    }
+    fn local_new_attr(&mut self, name: Rc<str>, is_const: bool) {
+        if self.local_num() >= MAX_LOCALS {
+            self.ctx.lex.syntax_error(format!("too many local variables (limit is {MAX_LOCALS})"));
+        }
+        self.reserve_registers(self.local_num() + 1);
+        self.fp.locals.push(LocalVar {
+            name: name.to_string(),
+            icode_start: self.fp.byte_codes.len(),
+            icode_end: usize::MAX,
+        });
+        self.ctx.levels.last_mut().unwrap().locals.push((name, false, is_const));
+    }
+
+    fn const_num(&self) -> usize {
+        self.ctx.levels.last().unwrap().consts.len()
+    }
+    fn const_expire(&mut self, from: usize) {
+        self.ctx.levels.last_mut().unwrap().consts.truncate(from);
     }
 
     fn local_expire(&mut self, from: usize) {
+        // end the scopes of the dropped locals, which are the last
+        // ones still in scope
+        let n = self.local_num() - from;
+        let icode = self.fp.byte_codes.len();
+        for var in self.fp.locals.iter_mut().rev().filter(|v| v.icode_end == usize::MAX).take(n) {
+            var.icode_end = icode;
+        }
+
         // drop locals
         let mut vars = self.ctx.levels.last_mut().unwrap().locals.drain(from..);
 
         // generate Close if any dropped local variable referred as upvalue
         if vars.any(|v| v.1) {
-            self.fp.byte_codes.push(ByteCode::Close(from as u8));
+            drop(vars);
+            self.push_code(ByteCode::Close(from as u8));
+            if let Some(block) = self.loop_blocks.last_mut() {
+                block.close = true;
+            }
+            for goto in self.gotos.iter_mut().filter(|g| g.nvar > from) {
+                goto.close = true;
+            }
         }
     }
 
     // generate Close if any local variable in [from..] referred as upvalue
     fn local_check_close(&mut self, from: usize) {
-        let mut vars = self.ctx.levels.last().unwrap().locals[from..].iter();
-        if vars.any(|v| v.1) {
-            self.fp.byte_codes.push(ByteCode::Close(from as u8));
+        if self.local_captured(from) {
+            self.push_code(ByteCode::Close(from as u8));
         }
     }
 
+    // if any local variable in [from..] is referred as upvalue
+    fn local_captured(&self, from: usize) -> bool {
+        self.ctx.levels.last().unwrap().locals[from..].iter().any(|v| v.1)
+    }
+
     // match the name as local, upvalue, or global
-    fn simple_name(&mut self, name: String) -> ExpDesc {
+    fn simple_name(&mut self, name: Rc<str>) -> ExpDesc {
         let mut level_iter = self.ctx.levels.iter_mut().rev();
 
         // search from locals and upvalues in current level
         let level = level_iter.next().unwrap();
-        if let Some(i) = level.locals.iter().rposition(|v| v.0 == name) {
+        let ilocal = level.locals.iter().rposition(|v| v.0 == name);
+        if let Some(desc) = level.find_const(&name, ilocal) {
+            return desc;
+        }
+        if let Some(i) = ilocal {
             // search reversely, so new variable covers old one with same name
             return ExpDesc::Local(i);
         }
@@ -1032,7 +1561,11 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // search in upper levels
         for (depth, level) in level_iter.enumerate() {
-            if let Some(i) = level.locals.iter().rposition(|v| v.0 == name) {
+            let ilocal = level.locals.iter().rposition(|v| v.0 == name);
+            if let Some(desc) = level.find_const(&name, ilocal) {
+                return desc;
+            }
+            if let Some(i) = ilocal {
                 level.locals[i].1 = true; // mark it referred as upvalue
                 return self.create_upvalue(name, UpIndex::Local(i), depth);
             }
@@ -1042,17 +1575,21 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
 
         // not matched as local or upvalue, so global variable, by _ENV[name]
-        let iname = self.add_const(name);
-        match self.simple_name("_ENV".into()) {
-            ExpDesc::Local(i) => ExpDesc::IndexField(i, iname),
-            ExpDesc::Upvalue(i) => ExpDesc::IndexUpField(i, iname),
+        let iname = self.add_const(&*name);
+        let env = self.ctx.lex.intern("_ENV");
+        match self.simple_name(env) {
+            ExpDesc::Local(i) => self.index_field(i, iname),
+            ExpDesc::Upvalue(i) => self.index_up_field(i, iname),
             _ => panic!("no here"), // because "_ENV" must exist!
         }
     }
 
-    fn create_upvalue(&mut self, name: String, mut upidx: UpIndex, depth: usize) -> ExpDesc {
+    fn create_upvalue(&mut self, name: Rc<str>, mut upidx: UpIndex, depth: usize) -> ExpDesc {
         let levels = &mut self.ctx.levels;
         let last = levels.len() - 1;
+        if levels[last-depth ..].iter().any(|level| level.upvalues.len() >= MAX_UPVALUES) {
+            self.ctx.lex.syntax_error(format!("too many upvalues (limit is {MAX_UPVALUES})"));
+        }
 
         // create upvalue in middle levels, if any
         for Level { upvalues, .. } in levels[last-depth .. last].iter_mut() {
@@ -1069,9 +1606,9 @@ impl<'a, R: Read> ParseProto<'a, R> {
     // unop `-`
     fn unop_neg(&mut self) -> ExpDesc {
         match self.exp_unop() {
-            ExpDesc::Integer(i) => ExpDesc::Integer(-i),
+            ExpDesc::Integer(i) => ExpDesc::Integer(i.wrapping_neg()),
             ExpDesc::Float(f) => ExpDesc::Float(-f),
-            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::String(_) => panic!("invalid - operator"),
+            ExpDesc::Nil | ExpDesc::Boolean(_) => self.ctx.lex.syntax_error("invalid - operator".into()),
             desc => ExpDesc::UnaryOp(ByteCode::Neg, self.discharge_any(desc))
         }
     }
@@ -1089,7 +1626,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
     fn unop_bitnot(&mut self) -> ExpDesc {
         match self.exp_unop() {
             ExpDesc::Integer(i) => ExpDesc::Integer(!i),
-            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Float(_) | ExpDesc::String(_) => panic!("invalid ~ operator"),
+            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Float(_) | ExpDesc::String(_) => self.ctx.lex.syntax_error("invalid ~ operator".into()),
             desc => ExpDesc::UnaryOp(ByteCode::BitNot, self.discharge_any(desc)),
         }
     }
@@ -1097,7 +1634,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
     fn unop_len(&mut self) -> ExpDesc {
         match self.exp_unop() {
             ExpDesc::String(s) => ExpDesc::Integer(s.len() as i64),
-            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Integer(_) | ExpDesc::Float(_) => panic!("invalid ~ operator"),
+            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Integer(_) | ExpDesc::Float(_) => self.ctx.lex.syntax_error("invalid ~ operator".into()),
             desc => ExpDesc::UnaryOp(ByteCode::Len, self.discharge_any(desc)),
         }
     }
@@ -1129,6 +1666,16 @@ impl<'a, R: Read> ParseProto<'a, R> {
             return r;
         }
 
+        // swap the left-const-operand to right for commutative operators,
+        // in order to use opi/opk in do_compare(). Not for `+` and `*`,
+        // whose metamethods get the operands in the original order.
+        let (left, right) = if matches!(binop, Token::Equal | Token::NotEq)
+                && matches!(left, ExpDesc::Integer(_) | ExpDesc::Float(_)) {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
         match binop {
             Token::Add => self.do_binop(left, right, ByteCode::Add, ByteCode::AddInt, ByteCode::AddConst),
             Token::Sub => self.do_binop(left, right, ByteCode::Sub, ByteCode::SubInt, ByteCode::SubConst),
@@ -1152,8 +1699,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
             Token::Concat => {
                 // TODO support multiple operants
-                let left = self.discharge_any(left);
+                // The right operand first, because a constant left one is
+                // not discharged yet, see preprocess_binop_left(), and it
+                // would be cleared by a function call as the right one if
+                // loaded above the call, e.g. `"a" .. f()`.
                 let right = self.discharge_any(right);
+                let left = self.discharge_any(left);
                 ExpDesc::BinaryOp(ByteCode::Concat, left, right)
             }
 
@@ -1180,59 +1731,59 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
     }
 
-    fn do_binop(&mut self, mut left: ExpDesc, mut right: ExpDesc,
+    fn do_binop(&mut self, left: ExpDesc, right: ExpDesc,
             opr: FnBc3u8, opi: FnBc3u8, opk: FnBc3u8) -> ExpDesc {
 
-        if opr == ByteCode::Add || opr == ByteCode::Mul { // commutative
-            if matches!(left, ExpDesc::Integer(_) | ExpDesc::Float(_)) {
-                // swap the left-const-operand to right, in order to use opi/opk
-                (left, right) = (right, left);
-            }
-        }
-
-        let left = self.discharge_any(left);
-
+        // discharge the right operand first, because a constant left
+        // operand is not discharged yet, see preprocess_binop_left(), and
+        // its register would be overwritten by a right function call,
+        // e.g. `1 - f(x)`
         let (op, right) = match right {
             ExpDesc::Integer(i) =>
                 if let Ok(i) = u8::try_from(i) {
                     (opi, i as usize)
                 } else {
-                    (opk, self.add_const(i))
+                    self.binop_const(opr, opk, i)
                 }
-            ExpDesc::Float(f) => (opk, self.add_const(f)),
+            ExpDesc::Float(f) => self.binop_const(opr, opk, f),
             _ => (opr, self.discharge_any(right)),
         };
 
+        let left = self.discharge_any(left);
+
         ExpDesc::BinaryOp(op, left, right)
     }
 
-    fn do_compare(&mut self, mut left: ExpDesc, mut right: ExpDesc,
+    fn do_compare(&mut self, left: ExpDesc, right: ExpDesc,
             opr: FnBcBool, opi: FnBcBool, opk: FnBcBool) -> ExpDesc {
 
-        if opr == ByteCode::Equal || opr == ByteCode::NotEq { // commutative
-            if matches!(left, ExpDesc::Integer(_) | ExpDesc::Float(_)) {
-                // swap the left-const-operand to right, in order to use opi/opk
-                (left, right) = (right, left);
-            }
-        }
-
-        let left = self.discharge_any(left);
-
+        // discharge the right operand first, same with do_binop()
         let (op, right) = match right {
             ExpDesc::Integer(i) =>
                 if let Ok(i) = u8::try_from(i) {
                     (opi, i as usize)
                 } else {
-                    (opk, self.add_const(i))
+                    self.binop_const(opr, opk, i)
                 }
-            ExpDesc::Float(f) => (opk, self.add_const(f)),
-            ExpDesc::String(s) => (opk, self.add_const(s)),
+            ExpDesc::Float(f) => self.binop_const(opr, opk, f),
+            ExpDesc::String(s) => self.binop_const(opr, opk, s),
             _ => (opr, self.discharge_any(right)),
         };
 
+        let left = self.discharge_any(left);
+
         ExpDesc::Compare(op, left, right, Vec::new(), Vec::new())
     }
 
+    // @opk with constant @c as right operand, or @opr with @c loaded into
+    // stack if its index does not fit
+    fn binop_const<T>(&mut self, opr: T, opk: T, c: impl Into<Value>) -> (T, usize) {
+        match self.add_const_u8(c) {
+            ConstStack::Const(i) => (opk, i),
+            ConstStack::Stack(i) => (opr, i),
+        }
+    }
+
     // Generate a TestOrJump: test @condition or jump to somewhere unknown.
     // Link the new code to previous false-list if any.
     // Close true-list if any.
@@ -1244,7 +1795,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 return Vec::new();
             }
             ExpDesc::Compare(op, left, right, true_list, false_list) => {
-                self.fp.byte_codes.push(op(left as u8, right as u8, true));
+                self.push_code(op(left as u8, right as u8, true));
                 (ByteCode::Jump(0), Some(true_list), false_list)
             }
             ExpDesc::Test(condition, true_list, false_list) => {
@@ -1257,7 +1808,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
             }
         };
 
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
 
         false_list.push(self.fp.byte_codes.len() - 1);
 
@@ -1277,7 +1828,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 return Vec::new();
             }
             ExpDesc::Compare(op, left, right, true_list, false_list) => {
-                self.fp.byte_codes.push(op(left as u8, right as u8, false));
+                self.push_code(op(left as u8, right as u8, false));
                 (ByteCode::Jump(0), true_list, Some(false_list))
             }
             ExpDesc::Test(condition, true_list, false_list) => {
@@ -1290,7 +1841,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
             }
         };
 
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
 
         true_list.push(self.fp.byte_codes.len() - 1);
 
@@ -1334,13 +1885,13 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     if icondition == dst {
                         ByteCode::TestOrJump(icondition, jmp as i16)
                     } else {
-                        ByteCode::TestOrSetJump(dst as u8, icondition, jmp as u8)
+                        ByteCode::TestOrSetJump(dst, icondition, jmp as u8)
                     }
                 ByteCode::TestAndJump(icondition, 0) =>
                     if icondition == dst {
                         ByteCode::TestAndJump(icondition, jmp as i16)
                     } else {
-                        ByteCode::TestAndSetJump(dst as u8, icondition, jmp as u8)
+                        ByteCode::TestAndSetJump(dst, icondition, jmp as u8)
                     }
                 _ => panic!("invalid Test"),
             };
@@ -1353,7 +1904,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
         let ifunc = self.sp - 1 - implicit_argn;
         let narg = match self.ctx.lex.next() {
             Token::ParL => {
-                if self.ctx.lex.peek() != &Token::ParR {
+                if self.ctx.lex.peek() != Token::ParR {
                     let (nexp, last_exp) = self.explist();
                     self.ctx.lex.expect(Token::ParR);
                     if self.discharge_try_expand(last_exp, 0) {
@@ -1370,11 +1921,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 self.table_constructor();
                 Some(1)
             }
-            Token::String(s) => {
+            Token::String => {
+                let s = self.ctx.lex.string();
                 self.discharge(ifunc+1, ExpDesc::String(s));
                 Some(1)
             }
-            t => panic!("invalid args {t:?}"),
+            t => self.ctx.lex.syntax_error(format!("function arguments expected near {}", self.ctx.lex.describe(t))),
         };
 
         // n+1: for fixed #n arguments
@@ -1406,6 +1958,7 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
     // discharge @desc into @dst, and update self.sp=dst+1
     fn discharge(&mut self, dst: usize, desc: ExpDesc) {
+        self.reserve_registers(dst + 1);
         let code = match desc {
             ExpDesc::Nil => ByteCode::LoadNil(dst as u8, 1),
             ExpDesc::Boolean(b) => ByteCode::LoadBool(dst as u8, b),
@@ -1413,10 +1966,17 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 if let Ok(i) = i16::try_from(i) {
                     ByteCode::LoadInt(dst as u8, i)
                 } else {
-                    ByteCode::LoadConst(dst as u8, self.add_const(i) as u16)
+                    let k = self.add_const(i);
+                    self.code_load_const(dst, k)
                 }
-            ExpDesc::Float(f) => ByteCode::LoadConst(dst as u8, self.add_const(f) as u16),
-            ExpDesc::String(s) => ByteCode::LoadConst(dst as u8, self.add_const(s) as u16),
+            ExpDesc::Float(f) => {
+                let k = self.add_const(f);
+                self.code_load_const(dst, k)
+            }
+            ExpDesc::String(s) => {
+                let k = self.add_const(s);
+                self.code_load_const(dst, k)
+            }
             ExpDesc::Local(src) =>
                 if dst != src {
                     ByteCode::Move(dst as u8, src as u8)
@@ -1429,45 +1989,91 @@ impl<'a, R: Read> ParseProto<'a, R> {
             ExpDesc::IndexInt(itable, ikey) => ByteCode::GetInt(dst as u8, itable as u8, ikey),
             ExpDesc::IndexUpField(itable, ikey) => ByteCode::GetUpField(dst as u8, itable as u8, ikey as u8),
             ExpDesc::VarArgs => ByteCode::VarArgs(dst as u8, 1),
-            ExpDesc::Function(f) => ByteCode::LoadConst(dst as u8, f as u16),
-            ExpDesc::Closure(f) => ByteCode::Closure(dst as u8, f as u16),
+            ExpDesc::Function(f) => self.code_load_const(dst, f),
+            ExpDesc::Closure(f) => match u16::try_from(f) {
+                Ok(f) => ByteCode::Closure(dst as u8, f),
+                Err(_) => {
+                    self.push_code(ByteCode::ClosureX(dst as u8));
+                    ByteCode::extra_arg(f)
+                }
+            }
             ExpDesc::Call(ifunc, narg_plus) => ByteCode::CallSet(dst as u8, ifunc as u8, narg_plus as u8),
             ExpDesc::UnaryOp(op, i) => op(dst as u8, i as u8),
             ExpDesc::BinaryOp(op, left, right) => op(dst as u8, left as u8, right as u8),
             ExpDesc::Test(condition, true_list, false_list) => {
-                // fix TestSet list after discharging
                 self.discharge(dst, *condition);
+
+                // Jumps in lists come from comparisons, e.g. `a == 1 or b`,
+                // which need loading the boolean result. While TestAndJump
+                // and TestOrJump are fixed to TestAndSetJump and
+                // TestOrSetJump, which set the value and jump to end.
+                let is_jump = |i: &usize| matches!(self.fp.byte_codes[*i], ByteCode::Jump(_));
+                let (true_jumps, true_list): (Vec<_>, Vec<_>) = true_list.into_iter().partition(is_jump);
+                let (false_jumps, false_list): (Vec<_>, Vec<_>) = false_list.into_iter().partition(is_jump);
+                if !true_jumps.is_empty() || !false_jumps.is_empty() {
+                    self.push_code(ByteCode::Jump(0));
+                    let skip_list = vec![self.fp.byte_codes.len() - 1];
+                    match (true_jumps.is_empty(), false_jumps.is_empty()) {
+                        (false, false) => {
+                            self.fix_test_list(false_jumps);
+                            self.push_code(ByteCode::SetFalseSkip(dst as u8));
+                            self.fix_test_list(true_jumps);
+                            self.push_code(ByteCode::LoadBool(dst as u8, true));
+                        }
+                        (false, true) => {
+                            self.fix_test_list(true_jumps);
+                            self.push_code(ByteCode::LoadBool(dst as u8, true));
+                        }
+                        (true, false) => {
+                            self.fix_test_list(false_jumps);
+                            self.push_code(ByteCode::LoadBool(dst as u8, false));
+                        }
+                        (true, true) => unreachable!(),
+                    }
+                    self.fix_test_list(skip_list);
+                }
+
+                // fix TestSet list after discharging
                 self.fix_test_set_list(true_list, dst);
                 self.fix_test_set_list(false_list, dst);
                 return;
             }
             ExpDesc::Compare(op, left, right, true_list, false_list) => {
-                self.fp.byte_codes.push(op(left as u8, right as u8, false));
-                self.fp.byte_codes.push(ByteCode::Jump(1));
+                self.push_code(op(left as u8, right as u8, false));
+                self.push_code(ByteCode::Jump(1));
 
                 // terminate false-list to SetFalseSkip
                 self.fix_test_list(false_list);
-                self.fp.byte_codes.push(ByteCode::SetFalseSkip(dst as u8));
+                self.push_code(ByteCode::SetFalseSkip(dst as u8));
                 // terminate true-list to LoadBool(true)
                 self.fix_test_list(true_list);
                 ByteCode::LoadBool(dst as u8, true)
             }
         };
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
         self.sp = dst + 1;
     }
 
+    // Registers below @top are used. Registers are u8 in byte codes, and
+    // the last one is left for self.sp.
+    fn reserve_registers(&mut self, top: usize) {
+        if top > u8::MAX as usize {
+            self.ctx.lex.syntax_error("function or expression needs too many registers".into());
+        }
+        self.fp.max_stack_size = self.fp.max_stack_size.max(top);
+    }
+
     // for constant types, add @desc to constants;
     // otherwise, discharge @desc into stack
     fn discharge_const(&mut self, desc: ExpDesc) -> ConstStack {
         match desc {
             // add const
-            ExpDesc::Nil => ConstStack::Const(self.add_const(())),
-            ExpDesc::Boolean(b) => ConstStack::Const(self.add_const(b)),
-            ExpDesc::Integer(i) => ConstStack::Const(self.add_const(i)),
-            ExpDesc::Float(f) => ConstStack::Const(self.add_const(f)),
-            ExpDesc::String(s) => ConstStack::Const(self.add_const(s)),
-            ExpDesc::Function(f) => ConstStack::Const(f),
+            ExpDesc::Nil => self.add_const_u8(()),
+            ExpDesc::Boolean(b) => self.add_const_u8(b),
+            ExpDesc::Integer(i) => self.add_const_u8(i),
+            ExpDesc::Float(f) => self.add_const_u8(f),
+            ExpDesc::String(s) => self.add_const_u8(s),
+            ExpDesc::Function(f) => self.const_or_stack(f),
 
             // discharge to stack
             _ => ConstStack::Stack(self.discharge_any(desc)),
@@ -1477,8 +2083,9 @@ impl<'a, R: Read> ParseProto<'a, R> {
     fn discharge_expand_want(&mut self, desc: ExpDesc, want: usize) {
         debug_assert!(want > 1);
         if !self.discharge_try_expand(desc, want) {
+            self.reserve_registers(self.sp + want - 1);
             let code = ByteCode::LoadNil(self.sp as u8, want as u8 - 1);
-            self.fp.byte_codes.push(code);
+            self.push_code(code);
         }
     }
 
@@ -1487,13 +2094,15 @@ impl<'a, R: Read> ParseProto<'a, R> {
     fn discharge_try_expand(&mut self, desc: ExpDesc, want: usize) -> bool {
         match desc {
             ExpDesc::Call(ifunc, narg_plus) => {
+                self.reserve_registers(ifunc + want);
                 let code = ByteCode::Call(ifunc as u8, narg_plus as u8, want as u8);
-                self.fp.byte_codes.push(code);
+                self.push_code(code);
                 true
             }
             ExpDesc::VarArgs => {
+                self.reserve_registers(self.sp + want.max(1));
                 let code = ByteCode::VarArgs(self.sp as u8, want as u8);
-                self.fp.byte_codes.push(code);
+                self.push_code(code);
                 true
             }
             _ => {
@@ -1503,12 +2112,22 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
     }
 
+    // SetField by constant key @k, or SetTable with the key loaded into
+    // stack if its index does not fit
+    fn table_field_key(&mut self, k: impl Into<Value>) -> (FnBc3u8, FnBc3u8, usize) {
+        match self.add_const_u8(k) {
+            ConstStack::Const(i) => (ByteCode::SetField, ByteCode::SetFieldConst, i),
+            ConstStack::Stack(i) => (ByteCode::SetTable, ByteCode::SetTableConst, i),
+        }
+    }
+
     fn table_constructor(&mut self) -> ExpDesc {
         let table = self.sp;
         self.sp += 1;
+        self.reserve_registers(self.sp);
 
         let inew = self.fp.byte_codes.len();
-        self.fp.byte_codes.push(ByteCode::NewTable(table as u8, 0, 0));
+        self.push_code(ByteCode::NewTable(table as u8, 0, 0));
 
         enum TableEntry {
             Map((FnBc3u8, FnBc3u8, usize)),
@@ -1522,6 +2141,21 @@ impl<'a, R: Read> ParseProto<'a, R> {
         let mut narray: usize = 0;
         let mut nmap: usize = 0;
         loop {
+            // discharge the last array entry before parsing the next one,
+            // which may use the registers, e.g. a nested table constructor
+            if self.ctx.lex.peek() != Token::CurlyR {
+                if let Some((sp, last)) = last_array_entry.take() {
+                    self.discharge(sp, last);
+                    self.sp = sp + 1;
+
+                    narray += 1;
+                    if narray.is_multiple_of(50) { // reset the array members every 50
+                        self.push_code(ByteCode::SetList(table as u8, 50));
+                        self.sp = table + 1;
+                    }
+                }
+            }
+
             let sp0 = self.sp;
 
             // parse entry of map or array?
@@ -1540,24 +2174,24 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     TableEntry::Map(match key {
                         ExpDesc::Local(i) =>
                             (ByteCode::SetTable, ByteCode::SetTableConst, i),
-                        ExpDesc::String(s) =>
-                            (ByteCode::SetField, ByteCode::SetFieldConst, self.add_const(s)),
+                        ExpDesc::String(s) => self.table_field_key(s),
                         ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
                             (ByteCode::SetInt, ByteCode::SetIntConst, i as usize),
                         ExpDesc::Nil =>
-                            panic!("nil can not be table key"),
+                            self.ctx.lex.syntax_error("nil can not be table key".into()),
                         ExpDesc::Float(f) if f.is_nan() =>
-                            panic!("NaN can not be table key"),
+                            self.ctx.lex.syntax_error("NaN can not be table key".into()),
                         _ => (ByteCode::SetTable, ByteCode::SetTableConst, self.discharge_any(key)),
                     })
                 }
-                Token::Name(_) => {
-                    let name = self.read_name();
-                    if self.ctx.lex.peek() == &Token::Assign { // Name `=` exp
+                t@Token::Name(sym) => {
+                    self.ctx.lex.next();
+                    if self.ctx.lex.peek() == Token::Assign { // Name `=` exp
                         self.ctx.lex.next();
-                        TableEntry::Map((ByteCode::SetField, ByteCode::SetFieldConst, self.add_const(name)))
+                        let name = self.ctx.lex.name(sym);
+                        TableEntry::Map(self.table_field_key(&*name))
                     } else { // Name
-                        TableEntry::Array(self.exp_with_ahead(Token::Name(name)))
+                        TableEntry::Array(self.exp_with_ahead(t))
                     }
                 }
                 _ => { // exp
@@ -1573,21 +2207,13 @@ impl<'a, R: Read> ParseProto<'a, R> {
                         ConstStack::Const(i) => opk(table as u8, key as u8, i as u8),
                         ConstStack::Stack(i) => op(table as u8, key as u8, i as u8),
                     };
-                    self.fp.byte_codes.push(code);
+                    self.push_code(code);
 
                     nmap += 1;
                     self.sp = sp0;
                 }
                 TableEntry::Array(desc) => {
-                    if let Some(last) = last_array_entry.replace(desc) {
-                        self.discharge(sp0, last);
-
-                        narray += 1;
-                        if narray % 50 == 0 { // reset the array members every 50
-                            self.fp.byte_codes.push(ByteCode::SetList(table as u8, 50));
-                            self.sp = table + 1;
-                        }
-                    }
+                    last_array_entry = Some((sp0, desc));
                 }
             }
 
@@ -1595,19 +2221,22 @@ impl<'a, R: Read> ParseProto<'a, R> {
             match self.ctx.lex.next() {
                 Token::SemiColon | Token::Comma => (), // yes
                 Token::CurlyR => break, // no
-                t => panic!("invalid table {t:?}"),
+                t => self.ctx.lex.expected(Token::CurlyR, t),
             }
         }
 
-        if let Some(last) = last_array_entry {
+        if let Some((sp, last)) = last_array_entry {
+            self.sp = sp;
             let num = if self.discharge_try_expand(last, 0) {
                 // do not update @narray
                 0 // 0 is special, means all following values in stack
             } else {
                 narray += 1;
-                (self.sp - (table + 1)) as u8
+                (sp - table) as u8
             };
-            self.fp.byte_codes.push(ByteCode::SetList(table as u8, num));
+            self.push_code(ByteCode::SetList(table as u8, num));
+        } else if !narray.is_multiple_of(50) { // followed by map entries
+            self.push_code(ByteCode::SetList(table as u8, (narray % 50) as u8));
         }
 
         // reset narray and nmap
@@ -1619,42 +2248,129 @@ impl<'a, R: Read> ParseProto<'a, R> {
         ExpDesc::Local(table)
     }
 
-    fn read_name(&mut self) -> String {
-        if let Token::Name(name) = self.ctx.lex.next() {
-            name
-        } else {
-            panic!("expect name");
+    fn read_name(&mut self) -> Rc<str> {
+        match self.ctx.lex.next() {
+            Token::Name(name) => self.ctx.lex.name(name),
+            t => self.ctx.lex.syntax_error(format!("<name> expected near {}", self.ctx.lex.describe(t))),
         }
     }
 }
 
 pub fn load(input: impl Read) -> FuncProto {
+    do_load(input, "?", false, MAX_SYNTAX_DEPTH)
+}
+
+// Load the source code in memory, e.g. embedded snippets. The source
+// itself is the chunk name, so it's shown as `[string "..."]` in error
+// messages, same with `load()` in Lua.
+pub fn load_str(source: &str) -> FuncProto {
+    do_load(source.as_bytes(), source, false, MAX_SYNTAX_DEPTH)
+}
+
+// Load with the chunk name, which is shown in error messages following
+// the conventions of the official Lua:
+//   - `@path` for files, shown as the path;
+//   - `=name` for other sources, shown as the name, e.g. `=stdin`;
+//   - otherwise the source string itself, shown as `[string "..."]`.
+// The name is `?` if not given, as `lua_load()`.
+pub fn load_named(input: impl Read, chunk_name: &str) -> FuncProto {
+    do_load(input, chunk_name, false, MAX_SYNTAX_DEPTH)
+}
+
+// Load with the limit of nested expressions and blocks, for deeper
+// code than MAX_SYNTAX_DEPTH. Make sure the Rust stack is large enough.
+pub fn load_with_depth(input: impl Read, max_depth: usize) -> FuncProto {
+    do_load(input, "?", false, max_depth)
+}
+
+// Load a chunk of an interactive session, e.g. a line of the REPL.
+// Local variables at the top level of the chunk are compiled as global
+// variables, so they are visible to the following chunks. Locals in
+// inner blocks and functions are not affected.
+pub fn load_session(input: impl Read) -> FuncProto {
+    do_load(input, "=stdin", true, MAX_SYNTAX_DEPTH)
+}
+
+// max length of short sources, same with LUA_IDSIZE minus the ending '\0'
+const MAX_SHORT_SOURCE: usize = 59;
+
+// Short source of the chunk name for messages, see load_named(). Long
+// names are truncated as `luaO_chunkid()`: file paths keep their ends,
+// and source strings keep their first lines.
+pub fn short_source(chunk_name: &str) -> String {
+    if let Some(name) = chunk_name.strip_prefix('=') {
+        name[..floor_boundary(name, MAX_SHORT_SOURCE)].to_string()
+    } else if let Some(path) = chunk_name.strip_prefix('@') {
+        if path.len() <= MAX_SHORT_SOURCE {
+            path.to_string()
+        } else {
+            let mut start = path.len() - (MAX_SHORT_SOURCE - 3);
+            while !path.is_char_boundary(start) {
+                start += 1;
+            }
+            format!("...{}", &path[start..])
+        }
+    } else {
+        // leave room for `[string "` and `..."]`
+        let max = MAX_SHORT_SOURCE - 14;
+        match chunk_name.split_once('\n') {
+            None if chunk_name.len() < max => format!("[string \"{chunk_name}\"]"),
+            line => {
+                let line = line.map_or(chunk_name, |(line, _)| line);
+                format!("[string \"{}...\"]", &line[..floor_boundary(line, max)])
+            }
+        }
+    }
+}
+
+// the largest char boundary of @s not after @n
+fn floor_boundary(s: &str, n: usize) -> usize {
+    let mut n = n.min(s.len());
+    while !s.is_char_boundary(n) {
+        n -= 1;
+    }
+    n
+}
+
+fn do_load(input: impl Read, chunk_name: &str, session: bool, max_depth: usize) -> FuncProto {
     let mut ctx = ParseContext {
-        lex: Lex::new(input),
+        lex: Lex::new(input, short_source(chunk_name)),
+        chunk_name: chunk_name.into(),
         levels: Default::default(),
+        session,
+        depth: 0,
+        max_depth,
     };
-    chunk(&mut ctx, false, vec!["_ENV".into()], Token::Eos) // XXX has_varargs->true
+    chunk(&mut ctx, true, vec!["_ENV".into()], Token::Eos)
 }
 
-fn chunk(ctx: &mut ParseContext<impl Read>, has_varargs: bool, params: Vec<String>, end_token: Token) -> FuncProto {
+fn chunk(ctx: &mut ParseContext<impl Read>, has_varargs: bool, params: Vec<Rc<str>>, end_token: Token) -> FuncProto {
     // prepare
     let fp = FuncProto {
-        has_varargs: has_varargs,
+        has_varargs,
         nparam: params.len(),
+        chunk_name: ctx.chunk_name.clone(),
+        locals: params.iter().map(|p| LocalVar {
+            name: p.to_string(),
+            icode_start: 0,
+            icode_end: usize::MAX,
+        }).collect(),
         ..Default::default()
     };
 
     ctx.levels.push(Level {
-        locals: params.into_iter().map(|p|(p, false)).collect(),
+        locals: params.into_iter().map(|p|(p, false, false)).collect(),
         upvalues: Vec::new(),
+        consts: Vec::new(),
     });
 
     let mut proto = ParseProto {
         sp: 0,
-        break_blocks: Vec::new(),
-        continue_blocks: Vec::new(),
+        const_indexes: HashMap::new(),
+        loop_blocks: Vec::new(),
         gotos: Vec::new(),
         labels: Vec::new(),
+        nblock: 0,
 
         fp,
         ctx,
@@ -1664,32 +2380,41 @@ fn chunk(ctx: &mut ParseContext<impl Read>, has_varargs: bool, params: Vec<Strin
     // use `block_scope()` because local variables will be dropped
     // after function, and upvalues will be closed in `Return`
     // byte code.
-    assert_eq!(proto.block_scope(), end_token);
+    let got = proto.block_scope();
+    proto.check_end(got, end_token);
 
     if let Some(goto) = proto.gotos.first() {
-        panic!("goto {} no destination", &goto.name);
+        let line = proto.fp.lines.get(goto.icode).copied().unwrap_or(0);
+        proto.ctx.lex.syntax_error(format!("no visible label '{}' for goto at line {line}", goto.name));
     }
 
     // clear
     let ParseProto { mut fp, ctx, ..} = proto;
 
     let level = ctx.levels.pop().unwrap();
-    fp.upindexes = level.upvalues.into_iter().map(|u| u.1).collect();
+    (fp.upvalue_names, fp.upindexes) = level.upvalues.into_iter()
+        .map(|(name, up)| (name.to_string(), up))
+        .unzip();
+    fp.max_stack_size = fp.max_stack_size.max(fp.nparam);
 
     fp.byte_codes.push(ByteCode::Return0);
-
-    println!("constants: {:?}", &fp.constants);
-    println!("upindexes: {:?}", &fp.upindexes);
-    println!("byte_codes:");
-    for (i,c) in fp.byte_codes.iter().enumerate() {
-        println!("  {i}\t{c:?}");
+    fp.lines.push(ctx.lex.last_line() as u32);
+
+    #[cfg(feature = "trace")]
+    {
+        eprintln!("constants: {:?}", &fp.constants);
+        eprintln!("upindexes: {:?}", &fp.upindexes);
+        eprintln!("byte_codes:");
+        for (i,c) in fp.byte_codes.iter().enumerate() {
+            eprintln!("  {i}\t{c:?}");
+        }
     }
 
     fp
 }
 
 // priorities of binops
-fn binop_pri(binop: &Token) -> (i32, i32) {
+pub(crate) fn binop_pri(binop: Token) -> (i32, i32) {
     match binop {
         Token::Pow => (14, 13), // right associative
         Token::Mul | Token::Mod | Token::Div | Token::Idiv => (11, 11),
@@ -1707,17 +2432,30 @@ fn binop_pri(binop: &Token) -> (i32, i32) {
     }
 }
 
-fn is_block_end(t: &Token) -> bool {
+fn is_const_desc(desc: &ExpDesc) -> bool {
+    matches!(desc, ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Integer(_) |
+        ExpDesc::Float(_) | ExpDesc::String(_))
+}
+
+// expressions discharged by a single Move or LoadNil
+fn is_single_code(desc: &ExpDesc) -> bool {
+    matches!(desc, ExpDesc::Local(_) | ExpDesc::Nil)
+}
+
+fn is_block_end(t: Token) -> bool {
     matches!(t, Token::End | Token::Elseif | Token::Else | Token::Until | Token::Eos)
 }
 
 fn fold_const(binop: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc> {
     match binop {
-        Token::Add => do_fold_const(left, right, |a,b|a+b, |a,b|a+b),
-        Token::Sub => do_fold_const(left, right, |a,b|a-b, |a,b|a-b),
-        Token::Mul => do_fold_const(left, right, |a,b|a*b, |a,b|a*b),
-        Token::Mod => do_fold_const(left, right, |a,b|a%b, |a,b|a%b),
-        Token::Idiv => do_fold_const(left, right, |a,b|a/b, |a,b|a/b),
+        Token::Add => do_fold_const(left, right, i64::wrapping_add, |a,b|a+b),
+        Token::Sub => do_fold_const(left, right, i64::wrapping_sub, |a,b|a-b),
+        Token::Mul => do_fold_const(left, right, i64::wrapping_mul, |a,b|a*b),
+
+        // integer division by zero is raised at runtime
+        Token::Mod | Token::Idiv if matches!(right, ExpDesc::Integer(0)) => None,
+        Token::Mod => do_fold_const(left, right, int_mod, float_mod),
+        Token::Idiv => do_fold_const(left, right, int_div, float_div),
 
         Token::Div => do_fold_const_float(left, right, |a,b|a/b),
         Token::Pow => do_fold_const_float(left, right, |a,b|a.powf(b)),
@@ -1725,8 +2463,8 @@ fn fold_const(binop: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc>
         Token::BitAnd => do_fold_const_int(left, right, |a,b|a&b),
         Token::BitNot => do_fold_const_int(left, right, |a,b|a^b),
         Token::BitOr  => do_fold_const_int(left, right, |a,b|a|b),
-        Token::ShiftL => do_fold_const_int(left, right, |a,b|a<<b),
-        Token::ShiftR => do_fold_const_int(left, right, |a,b|a>>b),
+        Token::ShiftL => do_fold_const_int(left, right, shift_left),
+        Token::ShiftR => do_fold_const_int(left, right, shift_right),
 
         Token::Concat => {
             if let (ExpDesc::String(s1), ExpDesc::String(s2)) = (left, right) {
@@ -1750,12 +2488,14 @@ fn do_fold_const(left: &ExpDesc, right: &ExpDesc, arith_i: fn(i64,i64)->i64, ari
     }
 }
 
+// Floats without integer representation are not folded, and the error
+// is raised at runtime.
 fn do_fold_const_int(left: &ExpDesc, right: &ExpDesc, arith_i: fn(i64,i64)->i64) -> Option<ExpDesc> {
     let (i1, i2) = match (left, right) {
         (&ExpDesc::Integer(i1), &ExpDesc::Integer(i2)) => (i1, i2),
-        (&ExpDesc::Float(f1), &ExpDesc::Float(f2)) => (ftoi(f1).unwrap(), ftoi(f2).unwrap()),
-        (&ExpDesc::Float(f1), &ExpDesc::Integer(i2)) => (ftoi(f1).unwrap(), i2),
-        (&ExpDesc::Integer(i1), &ExpDesc::Float(f2)) => (i1, ftoi(f2).unwrap()),
+        (&ExpDesc::Float(f1), &ExpDesc::Float(f2)) => (ftoi(f1)?, ftoi(f2)?),
+        (&ExpDesc::Float(f1), &ExpDesc::Integer(i2)) => (ftoi(f1)?, i2),
+        (&ExpDesc::Integer(i1), &ExpDesc::Float(f2)) => (i1, ftoi(f2)?),
         (_, _) => return None,
     };
     Some(ExpDesc::Integer(arith_i(i1, i2)))
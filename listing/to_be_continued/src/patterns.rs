@@ -0,0 +1,519 @@
+use std::fmt;
+use std::ops::Range;
+use crate::ctype;
+
+// Pattern matching of Lua, for `string.gmatch()` and others, ported from
+// lstrlib.c of the official Lua 5.4. It's public for the host to match
+// with the same semantics as the scripts:
+//
+//     let pattern = LuaPattern::new("(%w+)=(%w+)")?;
+//     for caps in pattern.captures_iter(b"a=1, b=2") {
+//         println!("{:?} {:?}", caps.get(1), caps.get(2));
+//     }
+//
+// The pattern is checked when created, so the matching never fails. The
+// official Lua reports the malformed parts only when reaching them, e.g.
+// `string.find("b", "(a")` returns nil there, but raises the error
+// "unfinished capture" here.
+
+// limits, same with LUA_MAXCAPTURES and MAXCCALLS
+const MAX_CAPTURES: usize = 32;
+const MAX_DEPTH: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    pub msg: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+fn error(msg: impl Into<String>) -> PatternError {
+    PatternError { msg: msg.into() }
+}
+
+#[derive(Debug, Clone)]
+pub struct LuaPattern {
+    pattern: Vec<u8>, // including the anchor '^'
+    anchor: bool,
+}
+
+// a capture of the match, see Captures::get()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture<'s> {
+    Bytes(&'s [u8]),
+
+    // offset in the subject of the position capture `()`, from 0, while
+    // it's from 1 for Lua
+    Position(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Captures<'s> {
+    subject: &'s [u8],
+    range: Range<usize>,
+    captures: Vec<Capture<'s>>,
+}
+
+impl<'s> Captures<'s> {
+    // range of the whole match in the subject
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn whole(&self) -> &'s [u8] {
+        &self.subject[self.range.clone()]
+    }
+
+    // The @i-th capture from 1, or the whole match for 0, same with `%0`
+    // and `%1` in the replacement of `string.gsub()`.
+    pub fn get(&self, i: usize) -> Option<Capture<'s>> {
+        if i == 0 {
+            Some(Capture::Bytes(self.whole()))
+        } else {
+            self.captures.get(i - 1).copied()
+        }
+    }
+
+    // The values returned by `string.match()` and `string.gmatch()`,
+    // which are the captures, or the whole match if there is none.
+    pub fn values(&self) -> Vec<Capture<'s>> {
+        if self.captures.is_empty() {
+            vec![Capture::Bytes(self.whole())]
+        } else {
+            self.captures.clone()
+        }
+    }
+}
+
+impl LuaPattern {
+    pub fn new(pattern: impl AsRef<[u8]>) -> Result<Self, PatternError> {
+        let pattern = pattern.as_ref().to_vec();
+        let anchor = pattern.first() == Some(&b'^');
+
+        // the '^' is not an anchor for captures_iter()
+        check(&pattern, 0)?;
+        if anchor {
+            check(&pattern, 1)?;
+        }
+        Ok(LuaPattern { pattern, anchor })
+    }
+
+    // Find the first match from the offset @init, same with
+    // `string.find()`. It's at @init only if the pattern starts with '^'.
+    pub fn find_at<'s>(&self, subject: &'s [u8], init: usize) -> Option<Captures<'s>> {
+        if init > subject.len() {
+            return None;
+        }
+        let mut ms = MatchState::new(subject, &self.pattern);
+        let p = self.anchor as usize;
+        for s in init..=subject.len() {
+            ms.level = 0;
+            if let Some(e) = ms.do_match(s, p) {
+                return Some(ms.captures(s, e));
+            }
+            if self.anchor {
+                break;
+            }
+        }
+        None
+    }
+
+    // Match at the offset @s only, which is for `string.gsub()`, where
+    // the '^' is the anchor but is skipped.
+    pub(crate) fn match_at<'s>(&self, subject: &'s [u8], s: usize) -> Option<Captures<'s>> {
+        let mut ms = MatchState::new(subject, &self.pattern);
+        ms.do_match(s, self.anchor as usize).map(|e| ms.captures(s, e))
+    }
+
+    pub fn is_anchored(&self) -> bool {
+        self.anchor
+    }
+
+    pub fn captures<'s>(&self, subject: &'s [u8]) -> Option<Captures<'s>> {
+        self.find_at(subject, 0)
+    }
+
+    pub fn is_match(&self, subject: &[u8]) -> bool {
+        self.find_at(subject, 0).is_some()
+    }
+
+    // Iterate the matches, same with `string.gmatch()`, where an empty
+    // match right after the previous one is skipped, and the '^' matches
+    // itself but is not an anchor.
+    pub fn captures_iter<'p, 's>(&'p self, subject: &'s [u8]) -> CapturesIter<'p, 's> {
+        CapturesIter { pattern: self, subject, pos: 0, last: None }
+    }
+
+    // The next match of captures_iter() from @pos, where @last is the end
+    // of the previous match, and both are updated, same with
+    // `gmatch_aux()` in lstrlib.c.
+    pub(crate) fn next_match<'s>(&self, subject: &'s [u8], pos: &mut usize,
+            last: &mut Option<usize>) -> Option<Captures<'s>> {
+        let mut ms = MatchState::new(subject, &self.pattern);
+        for s in *pos..=subject.len() {
+            ms.level = 0;
+            match ms.do_match(s, 0) {
+                Some(e) if Some(e) != *last => {
+                    *pos = e;
+                    *last = Some(e);
+                    return Some(ms.captures(s, e));
+                }
+                _ => (),
+            }
+        }
+        *pos = subject.len() + 1;
+        None
+    }
+}
+
+pub struct CapturesIter<'p, 's> {
+    pattern: &'p LuaPattern,
+    subject: &'s [u8],
+    pos: usize,
+    last: Option<usize>,
+}
+
+impl<'s> Iterator for CapturesIter<'_, 's> {
+    type Item = Captures<'s>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pattern.next_match(self.subject, &mut self.pos, &mut self.last)
+    }
+}
+
+// Check the pattern from @p, following the steps of do_match(), so the
+// matching never meets a malformed pattern.
+fn check(pat: &[u8], mut p: usize) -> Result<(), PatternError> {
+    let mut closed = Vec::new(); // of the captures
+    let mut depth = 1; // of the recursions of do_match()
+    while p < pat.len() {
+        match pat[p] {
+            b'(' => {
+                if closed.len() >= MAX_CAPTURES {
+                    return Err(error("too many captures"));
+                }
+                let position = pat.get(p + 1) == Some(&b')');
+                closed.push(position);
+                p += if position { 2 } else { 1 };
+                depth += 1;
+            }
+            b')' => {
+                let Some(l) = closed.iter().rposition(|&c| !c) else {
+                    return Err(error("invalid pattern capture"));
+                };
+                closed[l] = true;
+                p += 1;
+                depth += 1;
+            }
+            b'$' if p + 1 == pat.len() => p += 1,
+            b'%' if pat.get(p + 1) == Some(&b'b') => {
+                if p + 3 >= pat.len() {
+                    return Err(error("malformed pattern (missing arguments to '%b')"));
+                }
+                p += 4;
+            }
+            b'%' if pat.get(p + 1) == Some(&b'f') => {
+                p += 2;
+                if pat.get(p) != Some(&b'[') {
+                    return Err(error("missing '[' after '%f' in pattern"));
+                }
+                p = checked_class_end(pat, p)?;
+            }
+            b'%' if pat.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                let l = (pat[p + 1] - b'0') as usize;
+                if l == 0 || !closed.get(l - 1).copied().unwrap_or(false) {
+                    return Err(error(format!("invalid capture index %{l}")));
+                }
+                p += 2;
+            }
+            _ => {
+                let ep = checked_class_end(pat, p)?;
+                if matches!(pat.get(ep), Some(b'*' | b'+' | b'-' | b'?')) {
+                    depth += 1;
+                    p = ep + 1;
+                } else {
+                    p = ep;
+                }
+            }
+        }
+    }
+    if closed.contains(&false) {
+        return Err(error("unfinished capture"));
+    }
+    if depth > MAX_DEPTH {
+        return Err(error("pattern too complex"));
+    }
+    Ok(())
+}
+
+fn checked_class_end(pat: &[u8], p: usize) -> Result<usize, PatternError> {
+    class_end(pat, p).ok_or_else(|| match pat[p] {
+        b'%' => error("malformed pattern (ends with '%')"),
+        _ => error("malformed pattern (missing ']')"),
+    })
+}
+
+// The end of the single char class at @p, e.g. `a`, `%a` or `[a-z]`,
+// or None if it's malformed, same with `classEnd()` in lstrlib.c.
+fn class_end(pat: &[u8], mut p: usize) -> Option<usize> {
+    let c = pat[p];
+    p += 1;
+    match c {
+        b'%' => (p < pat.len()).then_some(p + 1),
+        b'[' => {
+            if pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // the first char is not the end, e.g. `[]]`
+            loop {
+                if p == pat.len() {
+                    return None;
+                }
+                let c = pat[p];
+                p += 1;
+                if c == b'%' && p < pat.len() {
+                    p += 1; // escaped, e.g. `%]`
+                }
+                if pat.get(p) == Some(&b']') {
+                    return Some(p + 1);
+                }
+            }
+        }
+        _ => Some(p),
+    }
+}
+
+// Whether @c matches the class @cl of `%cl`. It's literal if @cl is not
+// a class letter, e.g. `%.`.
+fn match_class(c: u8, cl: u8) -> bool {
+    ctype::in_class(cl, c).unwrap_or(cl == c)
+}
+
+// Whether @c matches the set from '[' at @p to ']' at @ec.
+fn match_bracket_class(c: u8, pat: &[u8], mut p: usize, ec: usize) -> bool {
+    let mut sig = true;
+    if pat[p + 1] == b'^' {
+        sig = false;
+        p += 1;
+    }
+    p += 1;
+    while p < ec {
+        if pat[p] == b'%' {
+            p += 1;
+            if match_class(c, pat[p]) {
+                return sig;
+            }
+        } else if pat[p + 1] == b'-' && p + 2 < ec {
+            p += 2;
+            if pat[p - 2] <= c && c <= pat[p] {
+                return sig;
+            }
+        } else if pat[p] == c {
+            return sig;
+        }
+        p += 1;
+    }
+    !sig
+}
+
+#[derive(Clone, Copy)]
+enum CapLen {
+    Unfinished,
+    Position,
+    Len(usize),
+}
+
+struct MatchState<'s, 'p> {
+    src: &'s [u8],
+    pat: &'p [u8],
+    level: usize,
+    capture: [(usize, CapLen); MAX_CAPTURES],
+}
+
+impl<'s, 'p> MatchState<'s, 'p> {
+    fn new(src: &'s [u8], pat: &'p [u8]) -> Self {
+        MatchState { src, pat, level: 0, capture: [(0, CapLen::Unfinished); MAX_CAPTURES] }
+    }
+
+    fn captures(&self, start: usize, end: usize) -> Captures<'s> {
+        let captures = self.capture[..self.level].iter().map(|&(init, len)| match len {
+            CapLen::Len(len) => Capture::Bytes(&self.src[init..init+len]),
+            CapLen::Position => Capture::Position(init),
+            CapLen::Unfinished => unreachable!("unfinished capture"), // by check()
+        }).collect();
+        Captures { subject: self.src, range: start..end, captures }
+    }
+
+    fn class_end(&self, p: usize) -> usize {
+        class_end(self.pat, p).expect("malformed pattern") // by check()
+    }
+
+    // Match the pattern from @p at @s of the subject, and return the end
+    // of the match, same with `match()` in lstrlib.c. The recursions are
+    // limited by check().
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Option<usize> {
+        while p < self.pat.len() {
+            match self.pat[p] {
+                b'(' => {
+                    return if self.pat.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CapLen::Position)
+                    } else {
+                        self.start_capture(s, p + 1, CapLen::Unfinished)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => {
+                    return (s == self.src.len()).then_some(s);
+                }
+                b'%' if self.pat[p + 1] == b'b' => {
+                    s = self.match_balance(s, p + 2)?;
+                    p += 4;
+                }
+                b'%' if self.pat[p + 1] == b'f' => {
+                    p += 2;
+                    let ep = self.class_end(p);
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if match_bracket_class(previous, self.pat, p, ep - 1)
+                            || !match_bracket_class(current, self.pat, p, ep - 1) {
+                        return None;
+                    }
+                    p = ep;
+                }
+                b'%' if self.pat[p + 1].is_ascii_digit() => {
+                    s = self.match_capture(s, self.pat[p + 1])?;
+                    p += 2;
+                }
+                _ => {
+                    let ep = self.class_end(p);
+                    let quantifier = self.pat.get(ep).copied();
+                    if !self.single_match(s, p, ep) {
+                        // accept empty
+                        if !matches!(quantifier, Some(b'*' | b'?' | b'-')) {
+                            return None;
+                        }
+                        p = ep + 1;
+                        continue;
+                    }
+                    match quantifier {
+                        Some(b'?') => {
+                            if let Some(e) = self.do_match(s + 1, ep + 1) {
+                                return Some(e);
+                            }
+                            p = ep + 1;
+                        }
+                        Some(b'+') => return self.max_expand(s + 1, p, ep),
+                        Some(b'*') => return self.max_expand(s, p, ep),
+                        Some(b'-') => return self.min_expand(s, p, ep),
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        }
+        Some(s)
+    }
+
+    // whether the byte at @s matches the single char class from @p to @ep
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat[p + 1]),
+            b'[' => match_bracket_class(c, self.pat, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // the longest repetition for `*` and `+`, and then backtracking
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Option<usize> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        loop {
+            if let Some(e) = self.do_match(s + i, ep + 1) {
+                return Some(e);
+            }
+            if i == 0 {
+                return None;
+            }
+            i -= 1;
+        }
+    }
+
+    // the shortest repetition for `-`
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Option<usize> {
+        loop {
+            if let Some(e) = self.do_match(s, ep + 1) {
+                return Some(e);
+            }
+            if !self.single_match(s, p, ep) {
+                return None;
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: CapLen) -> Option<usize> {
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let res = self.do_match(s, p);
+        if res.is_none() {
+            self.level -= 1;
+        }
+        res
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Option<usize> {
+        let l = (0..self.level).rev()
+            .find(|&l| matches!(self.capture[l].1, CapLen::Unfinished))
+            .expect("invalid pattern capture"); // by check()
+        self.capture[l].1 = CapLen::Len(s - self.capture[l].0);
+        let res = self.do_match(s, p);
+        if res.is_none() {
+            self.capture[l].1 = CapLen::Unfinished;
+        }
+        res
+    }
+
+    // back reference `%1`, which never matches a position capture
+    fn match_capture(&self, s: usize, l: u8) -> Option<usize> {
+        let (init, CapLen::Len(len)) = self.capture[(l - b'1') as usize] else {
+            return None;
+        };
+        let cap = &self.src[init..init+len];
+        self.src[s..].starts_with(cap).then_some(s + len)
+    }
+
+    // `%bxy`, where @p is at x
+    fn match_balance(&self, s: usize, p: usize) -> Option<usize> {
+        if self.src.get(s) != Some(&self.pat[p]) {
+            return None;
+        }
+        let (b, e) = (self.pat[p], self.pat[p + 1]);
+        let mut cont = 1;
+        for i in s+1..self.src.len() {
+            let c = self.src[i];
+            if c == e {
+                cont -= 1;
+                if cont == 0 {
+                    return Some(i + 1);
+                }
+            } else if c == b {
+                cont += 1;
+            }
+        }
+        None
+    }
+}
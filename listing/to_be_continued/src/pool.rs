@@ -0,0 +1,112 @@
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use crate::error::LuaError;
+use crate::parse;
+use crate::vm::{self, ExeState};
+
+// A pool of worker threads, each owns a pre-warmed ExeState.
+//
+// ExeState and Value are not `Send` because of `Rc`, so a state stays in
+// its thread, and jobs are closures that run on the state there and
+// return `Send` results, e.g. converted from the returned Values.
+//
+// The prelude is run once by each new state, for the common functions
+// and data of the jobs. A state is replaced by a new one if a job fails,
+// because a failed state may be inconsistent. If the prelude fails, the
+// worker keeps running, and its jobs fail with the prelude's error.
+pub struct LuaPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+// Run on the state, or on the error of the prelude if it fails, and
+// return false if the job fails.
+type Job = Box<dyn FnOnce(Result<&mut ExeState, &str>) -> bool + Send>;
+
+impl LuaPool {
+    pub fn new(nworker: usize, prelude: &str) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..nworker.max(1)).map(|_| {
+            let receiver = Arc::clone(&receiver);
+            let prelude = prelude.to_string();
+            thread::spawn(move || worker(&receiver, &prelude))
+        }).collect();
+
+        LuaPool { sender: Some(sender), workers }
+    }
+
+    // Submit a job, and return the handle to receive its result, or the
+    // error if the job fails.
+    pub fn submit<R, F>(&self, job: F) -> JobHandle<R>
+        where R: Send + 'static, F: FnOnce(&mut ExeState) -> R + Send + 'static
+    {
+        let (tx, rx) = mpsc::channel();
+        let job: Job = Box::new(move |state| {
+            // LuaError is not `Send`, so send the message
+            let result = match state {
+                Ok(state) => vm::catch_error(|| job(state))
+                    .map_err(|e| vm::panic_message(&*e)),
+                Err(msg) => Err(msg.to_string()),
+            };
+            let ok = result.is_ok();
+            let _ = tx.send(result); // the handle may be dropped
+            ok
+        });
+        self.sender.as_ref().unwrap().send(job).expect("workers are gone");
+        JobHandle(rx)
+    }
+}
+
+// the result of a submitted job, see LuaPool::submit()
+pub struct JobHandle<R>(mpsc::Receiver<Result<R, String>>);
+
+impl<R> JobHandle<R> {
+    // Wait for the result, or the error of the job, which is
+    // LuaError::MemoryError for "not enough memory" and RuntimeError for
+    // others, e.g. the failure of the prelude.
+    pub fn recv(&self) -> Result<R, LuaError> {
+        match self.0.recv() {
+            Ok(Ok(r)) => Ok(r),
+            Ok(Err(msg)) if msg == LuaError::MemoryError.to_string() => Err(LuaError::MemoryError),
+            Ok(Err(msg)) => Err(LuaError::runtime(msg)),
+            Err(_) => Err(LuaError::runtime("the worker is gone")),
+        }
+    }
+}
+
+// a new state which has run the prelude, or the prelude's error
+fn new_state(prelude: &str) -> Result<ExeState, String> {
+    vm::catch_error(|| {
+        let mut state = ExeState::new();
+        let proto = parse::load_named(prelude.as_bytes(), prelude);
+        state.exec_main(&proto);
+        state
+    }).map_err(|e| vm::panic_message(&*e))
+}
+
+fn worker(receiver: &Mutex<mpsc::Receiver<Job>>, prelude: &str) {
+    let mut state = new_state(prelude);
+    loop {
+        // the lock is released before running the job
+        let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        let Ok(job) = job else {
+            return; // the pool is dropped
+        };
+        let ok = job(state.as_mut().map_err(|msg| msg.as_str()));
+        if !ok && state.is_ok() {
+            state = new_state(prelude);
+        }
+    }
+}
+
+impl Drop for LuaPool {
+    // wait for the submitted jobs
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for w in self.workers.drain(..) {
+            let _ = w.join();
+        }
+    }
+}
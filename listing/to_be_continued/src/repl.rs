@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+use crate::parse::{self, FuncProto};
+use crate::value::Value;
+use crate::vm::{ExeState, catch_error, panic_message};
+
+// results of the recent expressions, in globals `_`, `_2` and `_3`
+const HISTORY: [&str; 3] = ["_", "_2", "_3"];
+
+const KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for",
+    "function", "goto", "if", "in", "local", "nil", "not", "or",
+    "repeat", "return", "then", "true", "until", "while",
+];
+
+// Interactive session, used by the standalone interpreter without script.
+//
+// Each line is tried as an expression first, same with the official
+// Lua, and its results are pretty-printed and saved into the history
+// globals. Otherwise it is executed as statements. Top-level locals are
+// kept between lines as globals, see parse::load_session().
+pub struct Repl {
+    state: ExeState,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl { state: ExeState::new() }
+    }
+
+    // Execute a line, and return the text to show, which is the results
+    // of an expression or empty for statements, or the error message.
+    // The line may be several lines, see is_complete().
+    pub fn eval(&mut self, line: &str) -> Result<String, String> {
+        let (proto, is_expr) = match load(&format!("return {line}")) {
+            Ok(proto) => (proto, true),
+            Err(_) => (load(line)?, false),
+        };
+
+        let f = Value::LuaFunction(Rc::new(proto));
+        let globals = self.state.env();
+        let rets = self.state.pcall(f, &[globals]);
+        self.state.flush();
+        let rets = rets.map_err(|e| e.to_string())?;
+
+        if is_expr {
+            self.push_history(rets.first().cloned().unwrap_or(Value::Nil));
+        }
+        Ok(rets.iter().map(pretty).collect::<Vec<_>>().join("\t"))
+    }
+
+    // Whether the source is complete, or it ends in the middle of a
+    // statement, e.g. an open `function` or `{`, so the interpreter
+    // reads more lines before eval(). Other syntax errors are complete,
+    // and reported by eval().
+    pub fn is_complete(source: &str) -> bool {
+        // both as expression, e.g. `1 +`, and as statements
+        match (load(&format!("return {source}")), load(source)) {
+            (Err(expr_msg), Err(stat_msg)) =>
+                !is_incomplete_error(&expr_msg) && !is_incomplete_error(&stat_msg),
+            _ => true,
+        }
+    }
+
+    // Complete the name before the cursor, for LineEditor. The names
+    // are keywords and global variables, or the fields of tables for
+    // `a.b` and `a:b`. Return the start position of the name and the
+    // candidates.
+    pub fn complete(&self, before: &str) -> (usize, Vec<String>) {
+        let start = before.rfind(|c: char| !is_name_char(c) && c != '.' && c != ':')
+            .map_or(0, |i| i + 1);
+        let word = &before[start..];
+
+        let (table, prefix, start) = match word.rfind(['.', ':']) {
+            Some(i) => {
+                let mut table = self.state.env();
+                for field in word[..i].split(['.', ':']) {
+                    table = match table {
+                        Value::Table(_) => table.index(&field.into()),
+                        _ => return (start, Vec::new()),
+                    };
+                }
+                (table, &word[i+1..], start + i + 1)
+            }
+            None => (self.state.env(), word, start),
+        };
+
+        let mut candidates: Vec<String> = Vec::new();
+        if let Value::Table(t) = &table {
+            candidates.extend(t.borrow().map.keys()
+                .filter_map(|k| k.as_str())
+                .filter(|k| is_name(k) && k.starts_with(prefix))
+                .map(String::from));
+        }
+        if start == 0 || word.len() == prefix.len() {
+            candidates.extend(KEYWORDS.iter()
+                .filter(|k| k.starts_with(prefix))
+                .map(|k| k.to_string()));
+        }
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+
+    fn push_history(&mut self, v: Value) {
+        let globals = self.state.env();
+        for i in (1..HISTORY.len()).rev() {
+            let prev = globals.index(&HISTORY[i - 1].into());
+            globals.new_index(HISTORY[i].into(), prev);
+        }
+        globals.new_index(HISTORY[0].into(), v);
+    }
+}
+
+// the parser raises errors by panic
+fn load(source: &str) -> Result<FuncProto, String> {
+    catch_error(|| parse::load_session(source.as_bytes()))
+        .map_err(|e| panic_message(&*e))
+}
+
+// Both the parser and the lexer report errors at the end of source by
+// "near <eof>", e.g. "'end' expected near <eof>" or "unfinished long
+// string near <eof>", but not unfinished short strings, which can not
+// continue on the next line.
+fn is_incomplete_error(msg: &str) -> bool {
+    msg.ends_with("near <eof>")
+}
+
+// Format the value in Lua syntax, where tables are expanded recursively
+// and strings are quoted. Tables already being printed are shown as
+// `<cycle>`. Map entries are sorted to get a stable output.
+pub fn pretty(v: &Value) -> String {
+    let mut buf = String::new();
+    pretty_into(v, &mut buf, &mut HashSet::new());
+    buf
+}
+
+fn pretty_into(v: &Value, buf: &mut String, visiting: &mut HashSet<*const ()>) {
+    let Value::Table(t) = v else {
+        match v.as_bytes() {
+            Some(s) => *buf += &format!("{:?}", String::from_utf8_lossy(s)),
+            None => *buf += &v.to_string(),
+        }
+        return;
+    };
+
+    let ptr = Rc::as_ptr(t) as *const ();
+    if !visiting.insert(ptr) {
+        *buf += "<cycle>";
+        return;
+    }
+
+    let t = t.borrow();
+    let mut items: Vec<String> = t.array.iter()
+        .map(|v| {
+            let mut item = String::new();
+            pretty_into(v, &mut item, visiting);
+            item
+        })
+        .collect();
+
+    let mut entries: Vec<String> = t.map.iter()
+        .map(|(k, v)| {
+            let mut entry = match k.as_str() {
+                Some(name) if is_name(name) => name.to_string(),
+                _ => format!("[{}]", pretty(k)),
+            };
+            entry += " = ";
+            pretty_into(v, &mut entry, visiting);
+            entry
+        })
+        .collect();
+    entries.sort();
+    items.append(&mut entries);
+
+    if items.is_empty() {
+        *buf += "{}";
+    } else {
+        *buf += &format!("{{ {} }}", items.join(", "));
+    }
+    visiting.remove(&ptr);
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_name(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && s.chars().all(is_name_char)
+        && !KEYWORDS.contains(&s)
+}
@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::stdlib::channel::{self, Channel};
+use crate::value::{Table, Value};
+
+// Deep copy of a value, to be sent to another thread and converted into
+// a value of the state there, e.g. messages of channels.
+//
+// Values are not `Send` because of `Rc`, so the copy is made of owned
+// data. Tables are copied with their contents, where the references
+// among them are kept, e.g. a table referred twice is copied once, and
+// cycles are copied too. Channels are shared but not copied, so the
+// states can talk through the channels they send to each other.
+//
+// Functions and coroutines can not be copied, and metatables are not
+// copied, since they are usually methods which can not be copied neither.
+pub struct SendValue {
+    root: Item,
+    tables: Vec<Vec<(Item, Item)>>,
+}
+
+enum Item {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(Vec<u8>),
+    Table(usize), // index in SendValue::tables
+    Channel(Arc<Channel>),
+}
+
+impl SendValue {
+    // Copy @v, or return the type name of the value which can not be
+    // copied, e.g. "function" in a field.
+    pub fn new(v: &Value) -> Result<Self, &'static str> {
+        let mut copier = Copier { tables: Vec::new(), index: HashMap::new() };
+        let root = copier.copy(v)?;
+        Ok(SendValue { root, tables: copier.tables })
+    }
+
+    // convert into a value of the current thread
+    pub fn into_value(self) -> Value {
+        // create all tables first, and then fill them, for the cycles
+        let tables: Vec<Rc<_>> = self.tables.iter()
+            .map(|entries| match Value::from(Table::new(0, entries.len())) {
+                Value::Table(t) => t,
+                _ => unreachable!(),
+            })
+            .collect();
+        for (t, entries) in tables.iter().zip(self.tables) {
+            let mut t = t.borrow_mut();
+            for (k, v) in entries {
+                t.new_index(k.into_value(&tables), v.into_value(&tables));
+            }
+        }
+        self.root.into_value(&tables)
+    }
+}
+
+struct Copier {
+    tables: Vec<Vec<(Item, Item)>>,
+    index: HashMap<*const (), usize>, // copied tables
+}
+
+impl Copier {
+    fn copy(&mut self, v: &Value) -> Result<Item, &'static str> {
+        let item = match v {
+            Value::Nil => Item::Nil,
+            &Value::Boolean(b) => Item::Boolean(b),
+            &Value::Integer(i) => Item::Integer(i),
+            &Value::Float(f) => Item::Float(f),
+            Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_) =>
+                Item::String(v.as_bytes().unwrap().to_vec()),
+            Value::Table(t) => {
+                if let Some(ch) = channel::channel_of(t) {
+                    return Ok(Item::Channel(ch));
+                }
+                let p = Rc::as_ptr(t) as *const ();
+                if let Some(&i) = self.index.get(&p) {
+                    return Ok(Item::Table(i));
+                }
+                let i = self.tables.len();
+                self.index.insert(p, i);
+                self.tables.push(Vec::new());
+
+                // not borrowed during copying, for cycles
+                let pairs: Vec<(Value, Value)> = {
+                    let t = t.borrow();
+                    let array = t.array.iter().enumerate()
+                        .filter(|(_, v)| !matches!(v, Value::Nil))
+                        .map(|(i, v)| (Value::Integer(i as i64 + 1), v.clone()));
+                    let map = t.map.iter().map(|(k, v)| (k.clone(), v.clone()));
+                    array.chain(map).collect()
+                };
+                let mut entries = Vec::with_capacity(pairs.len());
+                for (k, v) in &pairs {
+                    entries.push((self.copy(k)?, self.copy(v)?));
+                }
+                self.tables[i] = entries;
+                Item::Table(i)
+            }
+            _ => return Err(v.type_name()),
+        };
+        Ok(item)
+    }
+}
+
+impl Item {
+    fn into_value(self, tables: &[Rc<RefCell<Table>>]) -> Value {
+        match self {
+            Item::Nil => Value::Nil,
+            Item::Boolean(b) => Value::Boolean(b),
+            Item::Integer(i) => Value::Integer(i),
+            Item::Float(f) => Value::Float(f),
+            Item::String(s) => Value::from(s),
+            Item::Table(i) => Value::Table(tables[i].clone()),
+            Item::Channel(ch) => channel::channel_value(ch),
+        }
+    }
+}
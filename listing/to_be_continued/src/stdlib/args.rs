@@ -0,0 +1,125 @@
+use crate::value::{Value, Table};
+use crate::vm::ExeState;
+
+// Module `args`, loaded by `require "args"`, for command line scripts
+// to parse their arguments in the global table `arg`.
+pub fn load(state: &mut ExeState) -> i32 {
+    state.push(super::new_lib(&[
+        ("getopt", getopt),
+    ]));
+    1
+}
+
+// args.getopt(argv, shortopts [, longopts])
+//
+// Parse options in the array @argv, same with POSIX `getopt()` for
+// short options and Python's `getopt` module for long options:
+//   - @shortopts is letters of options, e.g. "hvo:", where a letter
+//     followed by `:` takes an argument, as `-o file` or `-ofile`.
+//     Options without argument can be grouped, as `-hv`;
+//   - @longopts is an array of names, e.g. {"help", "output="}, where
+//     a name ended with `=` takes an argument, as `--output file` or
+//     `--output=file`;
+//   - parsing stops at the first non-option argument or `--`.
+//
+// Return a table of options, mapping names to their arguments or true,
+// and an array of the remaining arguments. Unknown options or missing
+// arguments raise errors.
+fn getopt(state: &mut ExeState) -> i32 {
+    let argv = arg_strings(state.get(1), 1);
+    let shortopts = match state.get::<&Value>(2).as_str() {
+        Some(s) => s.to_string(),
+        None => panic!("bad argument #2 to 'getopt' (string expected, got {})",
+            state.get::<&Value>(2).type_name()),
+    };
+    let longopts = if state.get_top() >= 3 && state.get::<&Value>(3) != &Value::Nil {
+        arg_strings(state.get(3), 3)
+    } else {
+        Vec::new()
+    };
+
+    let mut opts = Table::new(0, 0);
+    let mut args = argv.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let value = match long_option(&longopts, name) {
+                None => panic!("unknown option '--{name}'"),
+                Some(false) if value.is_some() => panic!("option '--{name}' takes no argument"),
+                Some(false) => Value::Boolean(true),
+                Some(true) => value.or_else(|| args.next())
+                    .unwrap_or_else(|| panic!("option '--{name}' requires an argument"))
+                    .into(),
+            };
+            opts.map.insert(name.into(), value);
+
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // grouped short options
+            for (i, c) in arg[1..].char_indices() {
+                let takes_arg = match shortopts.find(c) {
+                    Some(j) if c != ':' => shortopts[j+1..].starts_with(':'),
+                    _ => panic!("unknown option '-{c}'"),
+                };
+                if !takes_arg {
+                    opts.map.insert(c.to_string().into(), Value::Boolean(true));
+                    continue;
+                }
+                // the rest of this argument, or the next one
+                let rest = &arg[1 + i + c.len_utf8()..];
+                let value = match rest {
+                    "" => args.next()
+                        .unwrap_or_else(|| panic!("option '-{c}' requires an argument")),
+                    _ => rest.to_string(),
+                };
+                opts.map.insert(c.to_string().into(), value.into());
+                break;
+            }
+
+        } else {
+            // the first non-option argument, which is kept
+            let mut rest = Table::new(0, 0);
+            rest.extend_array(std::iter::once(arg).chain(args).map(Value::from));
+            return push_results(state, opts, rest);
+        }
+    }
+
+    let mut rest = Table::new(0, 0);
+    rest.extend_array(args.map(Value::from));
+    push_results(state, opts, rest)
+}
+
+// Return Some(takes-argument) if @name is in @longopts.
+fn long_option(longopts: &[String], name: &str) -> Option<bool> {
+    longopts.iter().find_map(|o| match o.strip_suffix('=') {
+        Some(o) if o == name => Some(true),
+        None if o == name => Some(false),
+        _ => None,
+    })
+}
+
+fn push_results(state: &mut ExeState, opts: Table, rest: Table) -> i32 {
+    state.push(Value::from(opts));
+    state.push(Value::from(rest));
+    2
+}
+
+// array of strings at argument #@iarg, where numbers are converted
+fn arg_strings(v: &Value, iarg: usize) -> Vec<String> {
+    let Value::Table(t) = v else {
+        panic!("bad argument #{iarg} to 'getopt' (table expected, got {})", v.type_name());
+    };
+    t.borrow().array.iter().map(|v| match v {
+        Value::Integer(_) | Value::Float(_) => v.to_string(),
+        _ => match v.as_str() {
+            Some(s) => s.to_string(),
+            None => panic!("bad argument #{iarg} to 'getopt' (strings expected, got {})",
+                v.type_name()),
+        }
+    }).collect()
+}
@@ -0,0 +1,212 @@
+use crate::utils::{start_pos, end_pos};
+use crate::value::Value;
+use crate::vm::ExeState;
+
+// Mutable byte buffer, for binary data edited in place, while strings
+// are immutable and every edit makes a new one:
+//
+//     local b = bytes.new(4)           -- 4 zero bytes
+//     b:set(1, 0x89, "PNG")
+//     b:append("\r\n")
+//     print(b:len(), b:get(1), b:tostring(2, 4))  --> 6  137  PNG
+//
+// Positions start from 1, and negative ones count from the end, same
+// with the string library. Writes are in the buffer only, and append()
+// grows it.
+//
+// The buffers are userdata of Bytes, registered to every state, so the
+// host can read and write them by ExeState::get_userdata::<Bytes>().
+pub struct Bytes(pub Vec<u8>);
+
+// same with the strings built by the string library
+const MAX_SIZE: usize = i32::MAX as usize;
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("new", new),
+    ])
+}
+
+pub(crate) fn register(state: &mut ExeState) {
+    state.register_userdata::<Bytes>("bytes", &[
+        ("len", len),
+        ("get", get),
+        ("set", set),
+        ("fill", fill),
+        ("slice", slice),
+        ("append", append),
+        ("tostring", tostring),
+    ]);
+}
+
+// the content of strings and buffers, copied so the buffer of self can
+// be an argument too, e.g. `b:append(b)`
+pub(crate) fn arg_data(state: &ExeState, iarg: usize, fname: &str) -> Vec<u8> {
+    if iarg > state.get_top() {
+        panic!("bad argument #{iarg} to '{fname}' (string or bytes expected, got no value)");
+    }
+    if let Some(b) = state.get_userdata::<Bytes>(iarg) {
+        return b.0.clone();
+    }
+    let v = state.get::<&Value>(iarg);
+    match v {
+        Value::Integer(_) | Value::Float(_) => v.to_string().into_bytes(),
+        _ => match v.as_bytes() {
+            Some(s) => s.to_vec(),
+            None => panic!("bad argument #{iarg} to '{fname}' (string or bytes expected, got {})",
+                v.type_name()),
+        }
+    }
+}
+
+fn arg_byte(state: &ExeState, iarg: usize, fname: &str) -> u8 {
+    let b: i64 = state.check(iarg, fname);
+    u8::try_from(b).unwrap_or_else(|_| panic!("bad argument #{iarg} to '{fname}' (value out of range)"))
+}
+
+// the range of positions (@iarg, @iarg+1) in the buffer of @len bytes,
+// from @i to @j by default, as 0-based start and end
+fn arg_range(state: &ExeState, iarg: usize, fname: &str, i: i64, j: i64, len: usize) -> (usize, usize) {
+    let i = state.check::<Option<i64>>(iarg, fname).unwrap_or(i);
+    let j = state.check::<Option<i64>>(iarg + 1, fname).unwrap_or(j);
+    let (start, end) = (start_pos(i, len), end_pos(j, len));
+    if start > end {
+        (0, 0)
+    } else {
+        (start - 1, end)
+    }
+}
+
+// bytes.new(n [, byte]), bytes.new(s)
+//
+// Return a new buffer of @n bytes of @byte, which is 0 by default, or of
+// the content of the string or buffer @s.
+fn new(state: &mut ExeState) -> i32 {
+    let data = match state.get_top() {
+        0 => Vec::new(),
+        _ => match state.get::<&Value>(1) {
+            Value::Integer(_) | Value::Float(_) => {
+                let n: usize = state.check(1, "new");
+                if n > MAX_SIZE {
+                    panic!("bad argument #1 to 'new' (buffer too large)");
+                }
+                let b = if state.get_top() >= 2 { arg_byte(state, 2, "new") } else { 0 };
+                vec![b; n]
+            }
+            _ => arg_data(state, 1, "new"),
+        }
+    };
+    let b = state.create_userdata(Bytes(data));
+    state.push(b);
+    1
+}
+
+// b:len()
+fn len(state: &mut ExeState) -> i32 {
+    let n = state.check_userdata::<Bytes>(1, "len").0.len();
+    state.push(n);
+    1
+}
+
+// b:get(i [, j])
+//
+// Return the bytes from @i to @j, where @j is @i by default, same with
+// `string.byte()`.
+fn get(state: &mut ExeState) -> i32 {
+    let i = state.check::<Option<i64>>(2, "get").unwrap_or(1);
+    let bytes: Vec<u8> = {
+        let b = state.check_userdata::<Bytes>(1, "get");
+        let (start, end) = arg_range(state, 2, "get", i, i, b.0.len());
+        b.0[start..end].to_vec()
+    };
+    for &byte in &bytes {
+        state.push(Value::Integer(byte.into()));
+    }
+    bytes.len() as i32
+}
+
+// b:set(i, ...)
+//
+// Write the arguments from position @i, which are bytes as integers, or
+// strings and buffers. They must be in the buffer. Return the buffer.
+fn set(state: &mut ExeState) -> i32 {
+    let i: i64 = state.check(2, "set");
+    let mut data = Vec::new();
+    for iarg in 3..=state.get_top() {
+        match state.get::<&Value>(iarg) {
+            Value::Integer(_) | Value::Float(_) => data.push(arg_byte(state, iarg, "set")),
+            _ => data.extend(arg_data(state, iarg, "set")),
+        }
+    }
+    {
+        let mut b = state.check_userdata::<Bytes>(1, "set");
+        let len = b.0.len() as i64;
+        let start = if i < 0 { len + i + 1 } else { i };
+        if start < 1 || start - 1 + data.len() as i64 > len {
+            panic!("bad argument #2 to 'set' (index out of range)");
+        }
+        let start = start as usize - 1;
+        b.0[start..start+data.len()].copy_from_slice(&data);
+    }
+    state.set_top(1);
+    1
+}
+
+// b:fill(byte [, i [, j]])
+//
+// Set the bytes from @i to @j, which are the whole buffer by default, to
+// @byte. Return the buffer.
+fn fill(state: &mut ExeState) -> i32 {
+    let byte = arg_byte(state, 2, "fill");
+    {
+        let mut b = state.check_userdata::<Bytes>(1, "fill");
+        let (start, end) = arg_range(state, 3, "fill", 1, -1, b.0.len());
+        b.0[start..end].fill(byte);
+    }
+    state.set_top(1);
+    1
+}
+
+// b:slice([i [, j]])
+//
+// Return a new buffer of the bytes from @i to @j, same with `string.sub()`.
+fn slice(state: &mut ExeState) -> i32 {
+    let data = {
+        let b = state.check_userdata::<Bytes>(1, "slice");
+        let (start, end) = arg_range(state, 2, "slice", 1, -1, b.0.len());
+        b.0[start..end].to_vec()
+    };
+    let b = state.create_userdata(Bytes(data));
+    state.push(b);
+    1
+}
+
+// b:append(...)
+//
+// Append the strings or buffers to the buffer. Return the buffer.
+fn append(state: &mut ExeState) -> i32 {
+    let data: Vec<Vec<u8>> = (2..=state.get_top())
+        .map(|iarg| arg_data(state, iarg, "append"))
+        .collect();
+    let mut b = state.check_userdata::<Bytes>(1, "append");
+    if data.iter().map(Vec::len).sum::<usize>() > MAX_SIZE - b.0.len() {
+        panic!("buffer too large");
+    }
+    b.0.extend(data.concat());
+    drop(b);
+    state.set_top(1);
+    1
+}
+
+// b:tostring([i [, j]])
+//
+// Return the string of the bytes from @i to @j, or the whole buffer.
+fn tostring(state: &mut ExeState) -> i32 {
+    let data = {
+        let b = state.check_userdata::<Bytes>(1, "tostring");
+        let (start, end) = arg_range(state, 2, "tostring", 1, -1, b.0.len());
+        b.0[start..end].to_vec()
+    };
+    state.push(data);
+    1
+}
@@ -0,0 +1,272 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use crate::parse;
+use crate::send::SendValue;
+use crate::value::{Table, Value};
+use crate::vm::{self, ExeState};
+
+// Channels for message passing between states in different threads,
+// and `channel.spawn()` to start the states, so a worker architecture
+// can be built by scripts, e.g.:
+//
+//     local jobs, results = channel.new(), channel.new()
+//     channel.spawn([[
+//         local jobs, results = ...
+//         for job in jobs.recv, jobs do results:send(job * 2) end
+//     ]], jobs, results)
+//     jobs:send(21)
+//     jobs:close()
+//     print(results:recv()) -- 42
+//
+// Messages are deep copied, see SendValue. A channel is a table with the
+// methods, which refers to the shared Channel, so it can be sent too.
+// There may be several senders and receivers of a channel, and each
+// message is received by one of them.
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("new", new),
+        ("spawn", spawn),
+    ])
+}
+
+pub struct Channel {
+    queue: Mutex<Queue>,
+    capacity: Option<usize>, // None for unbounded
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+struct Queue {
+    messages: VecDeque<SendValue>,
+    closed: bool,
+}
+
+impl Channel {
+    // Lua errors are raised after the guard is dropped, so the mutex is
+    // not poisoned, while a poisoned one is still used anyway since the
+    // queue is always consistent.
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// The channel tables of this thread, which refer to the Channel. A channel
+// received by a thread is converted to the table of the thread if there
+// is one, so its identity is kept, e.g. as a key.
+type ChannelTable = (Weak<RefCell<Table>>, Arc<Channel>);
+
+thread_local! {
+    static TABLES: RefCell<Vec<ChannelTable>> = const { RefCell::new(Vec::new()) };
+}
+
+// the Channel of the table @t, if it's a channel
+pub fn channel_of(t: &Rc<RefCell<Table>>) -> Option<Arc<Channel>> {
+    let p = Rc::as_ptr(t);
+    TABLES.with_borrow(|tables| tables.iter()
+        .find(|(w, _)| w.as_ptr() == p)
+        .map(|(_, ch)| ch.clone()))
+}
+
+// the table of @ch in this thread, which is created if not existing
+pub fn channel_value(ch: Arc<Channel>) -> Value {
+    let found = TABLES.with_borrow(|tables| tables.iter()
+        .find(|(_, c)| Arc::ptr_eq(c, &ch))
+        .and_then(|(w, _)| w.upgrade()));
+    if let Some(t) = found {
+        return Value::Table(t);
+    }
+
+    let t = super::new_lib(&[
+        ("send", send),
+        ("try_send", try_send),
+        ("recv", recv),
+        ("try_recv", try_recv),
+        ("close", close),
+        ("len", len),
+    ]);
+    let Value::Table(rc) = &t else {
+        unreachable!();
+    };
+    TABLES.with_borrow_mut(|tables| {
+        tables.retain(|(w, _)| w.strong_count() > 0);
+        tables.push((Rc::downgrade(rc), ch));
+    });
+    t
+}
+
+fn check_channel(state: &ExeState, fname: &str) -> Arc<Channel> {
+    match state.get::<&Value>(1) {
+        Value::Table(t) => channel_of(t),
+        _ => None,
+    }.unwrap_or_else(|| panic!("bad argument #1 to '{fname}' (channel expected, got {})",
+        state.get::<&Value>(1).type_name()))
+}
+
+fn check_message(state: &ExeState, fname: &str) -> SendValue {
+    let v = if state.get_top() < 2 { &Value::Nil } else { state.get::<&Value>(2) };
+    if v == &Value::Nil {
+        panic!("bad argument #2 to '{fname}' (message expected, got nil)");
+    }
+    SendValue::new(v).unwrap_or_else(|t|
+        panic!("bad argument #2 to '{fname}' (can not send {t} values)"))
+}
+
+// channel.new([capacity])
+//
+// Return a new channel, which is unbounded without @capacity, otherwise
+// senders are blocked when @capacity messages are queued.
+fn new(state: &mut ExeState) -> i32 {
+    let capacity = match state.get_top() {
+        0 => None,
+        _ => match state.get::<&Value>(1) {
+            Value::Nil => None,
+            &Value::Integer(n) if n > 0 => Some(n as usize),
+            Value::Integer(_) => panic!("bad argument #1 to 'new' (capacity must be positive)"),
+            v => panic!("bad argument #1 to 'new' (number expected, got {})", v.type_name()),
+        }
+    };
+    let ch = Channel {
+        queue: Mutex::new(Queue { messages: VecDeque::new(), closed: false }),
+        capacity,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    };
+    state.push(channel_value(Arc::new(ch)));
+    1
+}
+
+// ch:send(msg)
+//
+// Send @msg, blocking while the channel is full. Raise an error if the
+// channel is closed. Messages can not be nil.
+fn send(state: &mut ExeState) -> i32 {
+    let ch = check_channel(state, "send");
+    let msg = check_message(state, "send");
+    let mut queue = ch.lock();
+    while !queue.closed && ch.capacity.is_some_and(|cap| queue.messages.len() >= cap) {
+        queue = ch.not_full.wait(queue).unwrap_or_else(PoisonError::into_inner);
+    }
+    if queue.closed {
+        drop(queue);
+        panic!("send on a closed channel");
+    }
+    queue.messages.push_back(msg);
+    ch.not_empty.notify_one();
+    0
+}
+
+// ch:try_send(msg)
+//
+// Send @msg and return true, or return false if the channel is full.
+fn try_send(state: &mut ExeState) -> i32 {
+    let ch = check_channel(state, "try_send");
+    let msg = check_message(state, "try_send");
+    let mut queue = ch.lock();
+    if queue.closed {
+        drop(queue);
+        panic!("send on a closed channel");
+    }
+    let sent = ch.capacity.is_none_or(|cap| queue.messages.len() < cap);
+    if sent {
+        queue.messages.push_back(msg);
+        ch.not_empty.notify_one();
+    }
+    drop(queue);
+    state.push(sent);
+    1
+}
+
+// ch:recv()
+//
+// Receive a message, blocking while the channel is empty. Return nil if
+// the channel is closed and empty, so `for msg in ch.recv, ch do` loops
+// until the channel is closed.
+fn recv(state: &mut ExeState) -> i32 {
+    let ch = check_channel(state, "recv");
+    let mut queue = ch.lock();
+    while queue.messages.is_empty() && !queue.closed {
+        queue = ch.not_empty.wait(queue).unwrap_or_else(PoisonError::into_inner);
+    }
+    let msg = queue.messages.pop_front();
+    ch.not_full.notify_one();
+    drop(queue);
+    state.push(msg.map_or(Value::Nil, SendValue::into_value));
+    1
+}
+
+// ch:try_recv()
+//
+// Receive a message, or return nil if the channel is empty.
+fn try_recv(state: &mut ExeState) -> i32 {
+    let ch = check_channel(state, "try_recv");
+    let msg = ch.lock().messages.pop_front();
+    if msg.is_some() {
+        ch.not_full.notify_one();
+    }
+    state.push(msg.map_or(Value::Nil, SendValue::into_value));
+    1
+}
+
+// ch:close()
+//
+// Close the channel, so no more messages can be sent, while the queued
+// ones can still be received. The blocked senders and receivers are
+// woken up.
+fn close(state: &mut ExeState) -> i32 {
+    let ch = check_channel(state, "close");
+    ch.lock().closed = true;
+    ch.not_empty.notify_all();
+    ch.not_full.notify_all();
+    0
+}
+
+// ch:len()
+//
+// Return the number of queued messages.
+fn len(state: &mut ExeState) -> i32 {
+    let ch = check_channel(state, "len");
+    let n = ch.lock().messages.len();
+    state.push(n);
+    1
+}
+
+// channel.spawn(source, ...)
+//
+// Run the Lua @source in a new state in a new thread, with the other
+// arguments, e.g. channels, as the varargs of the chunk. The arguments
+// are deep copied too. Errors of the chunk are printed to stderr, and
+// do not affect the current state.
+fn spawn(state: &mut ExeState) -> i32 {
+    let source = match state.get::<&Value>(1).as_bytes() {
+        Some(s) => s.to_vec(),
+        None => panic!("bad argument #1 to 'spawn' (string expected, got {})",
+            state.get::<&Value>(1).type_name()),
+    };
+    let args = (2..=state.get_top())
+        .map(|i| SendValue::new(state.get::<&Value>(i)).unwrap_or_else(|t|
+            panic!("bad argument #{i} to 'spawn' (can not send {t} values)")))
+        .collect::<Vec<_>>();
+
+    thread::spawn(move || {
+        let mut state = ExeState::new();
+        let proto = match vm::catch_error(|| parse::load_named(&source[..], "=spawn")) {
+            Ok(proto) => proto,
+            Err(e) => {
+                eprintln!("spawn: {}", vm::panic_message(&*e));
+                return;
+            }
+        };
+        let f = Value::LuaFunction(Rc::new(proto));
+        // the main chunk gets `_ENV` as its first parameter
+        let args: Vec<Value> = [state.env()].into_iter()
+            .chain(args.into_iter().map(SendValue::into_value))
+            .collect();
+        if let Err(e) = state.pcall(f, &args) {
+            eprintln!("spawn: {e}");
+        }
+    });
+    0
+}
@@ -0,0 +1,152 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use crate::value::Value;
+use crate::vm::{Coroutine, ExeState};
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("create", create),
+        ("resume", resume),
+        ("yield", lib_yield),
+        ("status", status),
+        ("wrap", wrap),
+        ("running", running),
+        ("isyieldable", isyieldable),
+        #[cfg(feature = "transfer")]
+        ("transfer", transfer),
+    ])
+}
+
+fn check_function(state: &ExeState, fname: &str) -> Value {
+    let f = state.get::<&Value>(1);
+    if !f.is_function() {
+        panic!("bad argument #1 to '{fname}' (function expected, got {})", f.type_name());
+    }
+    f.clone()
+}
+
+fn check_coroutine(state: &ExeState, fname: &str) -> Rc<RefCell<Coroutine>> {
+    match state.get::<&Value>(1) {
+        Value::Coroutine(co) => co.clone(),
+        v => panic!("bad argument #1 to '{fname}' (coroutine expected, got {})", v.type_name()),
+    }
+}
+
+// arguments from @from to the top
+fn args_from(state: &ExeState, from: usize) -> Vec<Value> {
+    (from..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect()
+}
+
+// coroutine.create(f)
+fn create(state: &mut ExeState) -> i32 {
+    let f = check_function(state, "create");
+    state.push(Value::Coroutine(Rc::new(RefCell::new(Coroutine::new(f)))));
+    1
+}
+
+// coroutine.resume(co, ...)
+//
+// Return true and the values passed to `coroutine.yield()` or returned
+// by the function, or false and the error object.
+fn resume(state: &mut ExeState) -> i32 {
+    let co = check_coroutine(state, "resume");
+    let args = args_from(state, 2);
+    match state.resume(&co, &args) {
+        Ok(rets) => {
+            state.push(true);
+            let n = rets.len();
+            rets.into_iter().for_each(|v| state.push(v));
+            n as i32 + 1
+        }
+        Err(err) => {
+            state.push(false);
+            state.push(err.into_value());
+            2
+        }
+    }
+}
+
+// coroutine.yield(...)
+//
+// All arguments are returned to resume(), see ExeState::yield_on_return().
+fn lib_yield(state: &mut ExeState) -> i32 {
+    state.yield_on_return();
+    state.get_top() as i32
+}
+
+// coroutine.status(co)
+fn status(state: &mut ExeState) -> i32 {
+    let co = check_coroutine(state, "status");
+    let name = co.borrow().status().name();
+    state.push(name);
+    1
+}
+
+// coroutine.wrap(f)
+//
+// Return a function which resumes a new coroutine of @f with its
+// arguments, and returns the values yielded or returned. Errors are
+// propagated to the caller with the same error objects, but not
+// returned as resume() does.
+fn wrap(state: &mut ExeState) -> i32 {
+    let f = check_function(state, "wrap");
+    let co = Rc::new(RefCell::new(Coroutine::new(f)));
+    let c = move |state: &mut ExeState| {
+        let args = args_from(state, 1);
+        match state.resume(&co, &args) {
+            Ok(rets) => {
+                let n = rets.len();
+                rets.into_iter().for_each(|v| state.push(v));
+                n as i32
+            }
+            Err(err) => state.raise_error(err.into_value(), 0),
+        }
+    };
+    state.push(Value::RustClosure(Rc::new(RefCell::new(Box::new(c)))));
+    1
+}
+
+// coroutine.running()
+//
+// Return the running coroutine, and true if it's the main thread.
+fn running(state: &mut ExeState) -> i32 {
+    let (co, is_main) = state.running();
+    state.push(Value::Coroutine(co));
+    state.push(is_main);
+    2
+}
+
+// coroutine.isyieldable()
+fn isyieldable(state: &mut ExeState) -> i32 {
+    let yieldable = state.is_yieldable();
+    state.push(yieldable);
+    1
+}
+
+// coroutine.transfer(co, ...)
+//
+// Suspend the running coroutine, and switch to @co with the arguments,
+// without returning to the resumer, e.g. for schedulers written in Lua.
+// The values are returned when @co or another one transfers back, or
+// the running one is resumed. Out of coroutines, it's same with wrap()
+// but @co is resumed only once. See ExeState::transfer_on_return().
+#[cfg(feature = "transfer")]
+fn transfer(state: &mut ExeState) -> i32 {
+    let co = check_coroutine(state, "transfer");
+    if co.borrow().status() != crate::vm::CoStatus::Suspended {
+        panic!("cannot transfer to non-suspended coroutine");
+    }
+    if state.running().1 {
+        let args = args_from(state, 2);
+        return match state.resume(&co, &args) {
+            Ok(rets) => {
+                let n = rets.len();
+                rets.into_iter().for_each(|v| state.push(v));
+                n as i32
+            }
+            Err(err) => state.raise_error(err.into_value(), 0),
+        };
+    }
+    state.transfer_on_return(co);
+    state.get_top() as i32 - 1
+}
@@ -0,0 +1,371 @@
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Datelike, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime,
+    NaiveTime, Offset, TimeDelta, TimeZone, Timelike, Utc};
+use crate::value::{Value, Table};
+use crate::vm::ExeState;
+
+// Timestamps in RFC 3339, calendar fields and durations, beyond the
+// `os.date()` and `os.time()` of the C library:
+//
+//     local t = datetime.parse("2024-02-29T12:00:00+08:00")
+//     print(datetime.format(t + datetime.parse_duration("1h30m")))
+//         --> 2024-02-29T05:30:00Z
+//     local start = datetime.monotonic()
+//     ...
+//     print(datetime.format_duration(datetime.monotonic() - start))
+//
+// Times are Unix timestamps in seconds, same with `os.time()`, which
+// are floats for fractions, in microseconds. So the arithmetic of times
+// and durations is just the one of numbers.
+//
+// Time zones are "UTC", the fixed offsets like "+08:00" or in seconds,
+// and "local" for the one of the system. The calendar is the proleptic
+// Gregorian one, without leap seconds. The times and time zones are by
+// the chrono crate.
+//
+// It's built with the cargo feature "datetime".
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("now", now),
+        ("monotonic", monotonic),
+        ("parse", parse),
+        ("format", format),
+        ("fields", fields),
+        ("time", time),
+        ("parse_duration", parse_duration),
+        ("format_duration", format_duration),
+    ])
+}
+
+#[derive(Clone, Copy)]
+enum Zone {
+    Fixed(FixedOffset),
+    Local,
+}
+
+impl Zone {
+    // the offset at the UTC time @t
+    fn offset(self, t: &NaiveDateTime) -> FixedOffset {
+        match self {
+            Zone::Fixed(offset) => offset,
+            Zone::Local => Local.offset_from_utc_datetime(t).fix(),
+        }
+    }
+
+    // the offset at the local time @local. It's ambiguous or missing
+    // around the DST changes, then the one of the time before the change
+    // wins.
+    fn offset_of_local(self, local: &NaiveDateTime) -> FixedOffset {
+        match self {
+            Zone::Fixed(offset) => offset,
+            Zone::Local => match Local.offset_from_local_datetime(local) {
+                LocalResult::Single(offset) => offset.fix(),
+                // repeated, where the offset before is the larger one
+                LocalResult::Ambiguous(a, b) => [a.fix(), b.fix()].into_iter()
+                    .max_by_key(FixedOffset::local_minus_utc).unwrap(),
+                // skipped, where the offset before is the smaller one
+                LocalResult::None => [*local - TimeDelta::days(1), *local + TimeDelta::days(1)]
+                    .iter().map(|t| self.offset(t))
+                    .min_by_key(FixedOffset::local_minus_utc).unwrap(),
+            }
+        }
+    }
+}
+
+// the time zone argument, which is UTC by default
+fn arg_zone(state: &ExeState, iarg: usize, fname: &str) -> Zone {
+    let v = state.check::<Value>(iarg, fname);
+    match &v {
+        Value::Nil => Zone::Fixed(Utc.fix()),
+        Value::Integer(_) | Value::Float(_) => {
+            let offset: i64 = state.check(iarg, fname);
+            match i32::try_from(offset).ok().and_then(FixedOffset::east_opt) {
+                Some(offset) => Zone::Fixed(offset),
+                None => panic!("bad argument #{iarg} to '{fname}' (offset out of range)"),
+            }
+        }
+        _ => match v.as_str() {
+            Some("UTC" | "Z") => Zone::Fixed(Utc.fix()),
+            Some("local") => Zone::Local,
+            Some(s) => match s.parse() {
+                Ok(offset) => Zone::Fixed(offset),
+                Err(_) => panic!("bad argument #{iarg} to '{fname}' (invalid time zone '{s}')"),
+            }
+            None => panic!("bad argument #{iarg} to '{fname}' (string expected, got {})",
+                v.type_name()),
+        }
+    }
+}
+
+// the timestamp argument, in microseconds
+fn arg_time(state: &ExeState, iarg: usize, fname: &str) -> NaiveDateTime {
+    let micros = match state.check::<Value>(iarg, fname) {
+        Value::Integer(t) => t.checked_mul(1_000_000),
+        Value::Float(t) if t.is_finite() && t.abs() < 1e15 => Some((t * 1e6).round() as i64),
+        Value::Float(_) => None,
+        v => panic!("bad argument #{iarg} to '{fname}' (number expected, got {})", v.type_name()),
+    };
+    micros.and_then(DateTime::from_timestamp_micros)
+        .unwrap_or_else(|| panic!("bad argument #{iarg} to '{fname}' (time out of range)"))
+        .naive_utc()
+}
+
+// the timestamp of the UTC time, as an integer if there is no fraction
+fn time_value(t: &NaiveDateTime) -> Value {
+    // rounded to the microseconds
+    let micros = (*t + TimeDelta::nanoseconds(500)).and_utc().timestamp_micros();
+    let (secs, micros) = (micros.div_euclid(1_000_000), micros.rem_euclid(1_000_000));
+    if micros == 0 {
+        Value::Integer(secs)
+    } else {
+        Value::Float(secs as f64 + micros as f64 / 1e6)
+    }
+}
+
+// datetime.now()
+//
+// Return the current time, with the fraction.
+fn now(state: &mut ExeState) -> i32 {
+    let t = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    };
+    state.push(t);
+    1
+}
+
+// datetime.monotonic()
+//
+// Return the seconds since a fixed point, which never goes backwards
+// even if the system time is changed, e.g. for timeouts and elapsed
+// times.
+fn monotonic(state: &mut ExeState) -> i32 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let start = START.get_or_init(Instant::now);
+    state.push(start.elapsed().as_secs_f64());
+    1
+}
+
+// datetime.parse(s)
+//
+// Parse the RFC 3339 time @s, e.g. "2024-02-29T12:00:00Z", or the date
+// only, which is the midnight in UTC. Return the time and the offset of
+// the time zone in seconds, or nil and the message if it's invalid.
+fn parse(state: &mut ExeState) -> i32 {
+    let s: String = state.check(1, "parse");
+    let t = if s.len() == 10 {
+        DateTime::parse_from_rfc3339(&format!("{s}T00:00:00Z"))
+    } else {
+        DateTime::parse_from_rfc3339(&s)
+    };
+    match t {
+        Ok(t) => {
+            state.push(time_value(&t.naive_utc()));
+            state.push(t.offset().local_minus_utc());
+            2
+        }
+        Err(_) => {
+            state.push(());
+            state.push(format!("invalid RFC 3339 time '{s}'"));
+            2
+        }
+    }
+}
+
+// datetime.format(t [, zone])
+//
+// Return the time @t in RFC 3339, in the time zone @zone, which is UTC
+// by default. The fraction is in microseconds, without trailing zeros.
+fn format(state: &mut ExeState) -> i32 {
+    let t = arg_time(state, 1, "format");
+    let zone = arg_zone(state, 2, "format");
+    let offset = zone.offset(&t);
+    let local = t + offset;
+    if !(0..=9999).contains(&local.year()) {
+        panic!("bad argument #1 to 'format' (time out of range)");
+    }
+
+    let mut s = local.format("%Y-%m-%dT%H:%M:%S").to_string();
+    let micros = local.nanosecond() / 1000;
+    if micros != 0 {
+        s += format!(".{micros:06}").trim_end_matches('0');
+    }
+    if offset.local_minus_utc() == 0 {
+        s.push('Z');
+    } else {
+        s += &offset.to_string();
+    }
+    state.push(s);
+    1
+}
+
+// datetime.fields(t [, zone])
+//
+// Return the calendar fields of the time @t in the time zone @zone as a
+// table: year, month, day, hour, min, sec, usec for the fraction, wday
+// from 1 for Sunday, yday from 1, and offset in seconds.
+fn fields(state: &mut ExeState) -> i32 {
+    let t = arg_time(state, 1, "fields");
+    let zone = arg_zone(state, 2, "fields");
+    let offset = zone.offset(&t);
+    let local = t + offset;
+
+    let table = Value::from(Table::new(0, 10));
+    for (key, n) in [
+        ("year", i64::from(local.year())),
+        ("month", local.month().into()),
+        ("day", local.day().into()),
+        ("hour", local.hour().into()),
+        ("min", local.minute().into()),
+        ("sec", local.second().into()),
+        ("usec", (local.nanosecond() / 1000).into()),
+        ("wday", local.weekday().number_from_sunday().into()),
+        ("yday", local.ordinal().into()),
+        ("offset", offset.local_minus_utc().into()),
+    ] {
+        table.new_index(key.into(), Value::Integer(n));
+    }
+    state.push(table);
+    1
+}
+
+// datetime.time(fields [, zone])
+//
+// Return the time of the calendar fields in the time zone @zone, which
+// is the reverse of `datetime.fields()`. The year, month and day are
+// required, and others are 0 by default. Fields out of range are
+// normalized, e.g. `{year=2024, month=1, day=31 + 1}` is February 1, so
+// it's for the calendar arithmetic.
+fn time(state: &mut ExeState) -> i32 {
+    let table: Value = state.check::<crate::value::TableHandle>(1, "time").into();
+    let zone = arg_zone(state, 2, "time");
+
+    let field = |key: &str, required: bool| -> i64 {
+        match table.index(&key.into()) {
+            Value::Nil if !required => 0,
+            Value::Nil => panic!("field '{key}' missing in date table"),
+            v => i64::try_from(v).unwrap_or_else(|_| panic!("field '{key}' is not an integer")),
+        }
+    };
+    let (year, month, day) = (field("year", true), field("month", true), field("day", true));
+    let (hour, min, sec, usec) = (field("hour", false), field("min", false), field("sec", false),
+        field("usec", false));
+
+    // the first day of the month, then the rest are added as a duration,
+    // e.g. month 13 is the January of the next year, and day 0 is the
+    // last day of the previous month
+    let local = (|| {
+        let month = month.checked_sub(1)?;
+        let year = i32::try_from(year.checked_add(month.div_euclid(12))?).ok()?;
+        let first = NaiveDate::from_ymd_opt(year, month.rem_euclid(12) as u32 + 1, 1)?;
+        let delta = TimeDelta::try_days(day.checked_sub(1)?)?
+            .checked_add(&TimeDelta::try_hours(hour)?)?
+            .checked_add(&TimeDelta::try_minutes(min)?)?
+            .checked_add(&TimeDelta::try_seconds(sec)?)?
+            .checked_add(&TimeDelta::microseconds(usec))?;
+        first.and_time(NaiveTime::MIN).checked_add_signed(delta)
+    })().unwrap_or_else(|| panic!("time result out of range"));
+    let t = local - zone.offset_of_local(&local);
+    state.push(time_value(&t));
+    1
+}
+
+const UNITS: [(&str, f64); 8] = [
+    ("ns", 1e-9), ("us", 1e-6), ("µs", 1e-6), ("ms", 1e-3),
+    ("s", 1.0), ("m", 60.0), ("h", 3600.0), ("d", 86400.0),
+];
+
+// "1h30m" into seconds
+fn parse_go_duration(s: &str) -> Option<f64> {
+    let (sign, mut s) = match s.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if s == "0" {
+        return Some(0.0);
+    }
+    if s.is_empty() {
+        return None;
+    }
+    let mut total = 0.0;
+    while !s.is_empty() {
+        let n = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let number: f64 = s[..n].parse().ok()?;
+        s = &s[n..];
+        let u = s.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(s.len());
+        let (_, scale) = UNITS.iter().find(|(unit, _)| *unit == &s[..u])?;
+        total += number * scale;
+        s = &s[u..];
+    }
+    Some(sign * total)
+}
+
+// datetime.parse_duration(s)
+//
+// Return the seconds of the duration @s, which is a sequence of numbers
+// with units, e.g. "1h30m", "1.5s" or "-300ms". The units are "ns",
+// "us", "ms", "s", "m", "h" and "d". Return nil and the message if it's
+// invalid.
+fn parse_duration(state: &mut ExeState) -> i32 {
+    let s: String = state.check(1, "parse_duration");
+    match parse_go_duration(&s) {
+        Some(secs) if secs.fract() == 0.0 && secs.abs() < 1e15 => state.push(secs as i64),
+        Some(secs) => state.push(secs),
+        None => {
+            state.push(());
+            state.push(format!("invalid duration '{s}'"));
+            return 2;
+        }
+    }
+    1
+}
+
+// datetime.format_duration(secs)
+//
+// Return the duration in the format of `parse_duration()`, e.g.
+// "1h30m0s", or "1.5ms" for ones less than a second.
+fn format_duration(state: &mut ExeState) -> i32 {
+    let secs: f64 = state.check(1, "format_duration");
+    if !secs.is_finite() {
+        panic!("bad argument #1 to 'format_duration' (duration out of range)");
+    }
+    let sign = if secs < 0.0 { "-" } else { "" };
+    let secs = secs.abs();
+
+    let s = if secs == 0.0 {
+        "0s".to_string()
+    } else if secs < 1e-6 {
+        format!("{sign}{}ns", trim_float(secs * 1e9, 0))
+    } else if secs < 1e-3 {
+        format!("{sign}{}us", trim_float(secs * 1e6, 3))
+    } else if secs < 1.0 {
+        format!("{sign}{}ms", trim_float(secs * 1e3, 6))
+    } else {
+        let whole = secs.trunc();
+        let mut s = sign.to_string();
+        let hours = (whole / 3600.0).trunc();
+        if hours > 0.0 {
+            s += &format!("{hours}h");
+        }
+        let mins = (whole / 60.0).trunc() % 60.0;
+        if hours > 0.0 || mins > 0.0 {
+            s += &format!("{mins}m");
+        }
+        s += &trim_float(secs % 60.0, 6);
+        s.push('s');
+        s
+    };
+    state.push(s);
+    1
+}
+
+// the number with at most @digits fraction digits, without trailing zeros
+fn trim_float(n: f64, digits: usize) -> String {
+    let s = format!("{n:.digits$}");
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        s
+    }
+}
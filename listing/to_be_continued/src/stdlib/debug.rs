@@ -0,0 +1,54 @@
+use crate::parse::FuncProto;
+use crate::value::Value;
+use crate::vm::ExeState;
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("getlocal", getlocal),
+        ("getupvalue", getupvalue),
+    ])
+}
+
+// debug.getlocal(f, n)
+//
+// Return the name of the @n-th parameter of the Lua function @f, or nil
+// if no such parameter, same with the official Lua for function argument.
+// The level form, for locals of active functions, is not supported,
+// because the VM does not keep records of call frames.
+fn getlocal(state: &mut ExeState) -> i32 {
+    let n: i64 = state.get(2);
+    let param = |proto: &FuncProto| usize::try_from(n - 1).ok()
+        .filter(|&i| i < proto.nparam)
+        .and_then(|i| proto.local_name(i as u8, 0))
+        .map(Value::from);
+
+    let name = match state.get::<&Value>(1) {
+        Value::LuaFunction(f) => param(f),
+        Value::LuaClosure(c) => param(&c.proto),
+        Value::RustFunction(_) | Value::RustClosure(_) => None,
+        Value::Integer(_) | Value::Float(_) =>
+            panic!("bad argument #1 to 'getlocal' (level is not supported)"),
+        v => panic!("bad argument #1 to 'getlocal' (function expected, got {})", v.type_name()),
+    };
+    state.push(name.unwrap_or(Value::Nil));
+    1
+}
+
+// debug.getupvalue(f, n)
+//
+// Return the name and the value of the @n-th upvalue of function @f, or
+// nothing if no such upvalue. The name is "(no name)" if stripped.
+fn getupvalue(state: &mut ExeState) -> i32 {
+    let n: i64 = state.get(2);
+    let Value::LuaClosure(c) = state.get::<&Value>(1).clone() else {
+        return 0;
+    };
+    let Some(i) = usize::try_from(n - 1).ok().filter(|&i| i < c.upvalues.len()) else {
+        return 0;
+    };
+    let value = state.get_upvalue(&c.upvalues[i].borrow());
+    let name = c.proto.upvalue_names.get(i).map_or("(no name)", String::as_str);
+    state.push(name);
+    state.push(value);
+    2
+}
@@ -0,0 +1,125 @@
+use crate::value::Value;
+use crate::vm::ExeState;
+use super::bytes::arg_data;
+
+// Binary-to-text encodings, e.g. for data in JSON or HTTP headers:
+//
+//     local token = encoding.base64_encode("user:pass")
+//     print(encoding.hex("\1\255"))  --> 01ff
+//
+// The data to encode is a string or a bytes buffer. Invalid input of the
+// decoders is returned as nil and the message, same with `io.open()`,
+// since it's often from outside.
+//
+// It's built with the cargo feature "encoding".
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("base64_encode", base64_encode),
+        ("base64_decode", base64_decode),
+        ("hex", hex),
+        ("hex_decode", hex_decode),
+    ])
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// the alphabet of the second argument "url", or the standard one
+fn arg_alphabet(state: &ExeState, fname: &str) -> &'static [u8; 64] {
+    match state.check::<Option<String>>(2, fname).as_deref() {
+        None | Some("std") => BASE64,
+        Some("url") => BASE64_URL,
+        Some(s) => panic!("bad argument #2 to '{fname}' (invalid alphabet '{s}')"),
+    }
+}
+
+fn push_decoded(state: &mut ExeState, result: Result<Vec<u8>, &str>) -> i32 {
+    match result {
+        Ok(data) => {
+            state.push(data);
+            1
+        }
+        Err(msg) => {
+            state.push(());
+            state.push(msg);
+            2
+        }
+    }
+}
+
+// encoding.base64_encode(data [, alphabet])
+//
+// The @alphabet is "std" by default, or "url" for the URL and filename
+// safe one of RFC 4648, which uses '-' and '_', and has no padding.
+fn base64_encode(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "base64_encode");
+    let alphabet = arg_alphabet(state, "base64_encode");
+    let mut out = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(alphabet[(n >> (18 - 6 * i) & 0x3f) as usize]);
+        }
+        if alphabet == BASE64 {
+            out.resize(out.len() + 3 - chunk.len(), b'=');
+        }
+    }
+    state.push(out);
+    1
+}
+
+// encoding.base64_decode(s [, alphabet])
+//
+// The padding is optional.
+fn base64_decode(state: &mut ExeState) -> i32 {
+    let s = arg_data(state, 1, "base64_decode");
+    let alphabet = arg_alphabet(state, "base64_decode");
+    push_decoded(state, decode_base64(&s, alphabet))
+}
+
+fn decode_base64(s: &[u8], alphabet: &[u8; 64]) -> Result<Vec<u8>, &'static str> {
+    let data = match s.iter().position(|&c| c == b'=') {
+        Some(i) if s.len().is_multiple_of(4) && s.len() - i <= 2 && s[i..].iter().all(|&c| c == b'=') => &s[..i],
+        Some(_) => return Err("invalid base64 padding"),
+        None => s,
+    };
+    if data.len() % 4 == 1 {
+        return Err("invalid base64 length");
+    }
+    let mut out = Vec::with_capacity(data.len() / 4 * 3 + 2);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let v = alphabet.iter().position(|&a| a == c).ok_or("invalid base64 character")?;
+            n |= (v as u32) << (18 - 6 * i);
+        }
+        out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Ok(out)
+}
+
+// encoding.hex(data)
+//
+// Return the lower-case hex of @data.
+fn hex(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "hex");
+    let s: String = data.iter().map(|b| format!("{b:02x}")).collect();
+    state.push(s);
+    1
+}
+
+// encoding.hex_decode(s)
+//
+// Digits are in upper or lower case.
+fn hex_decode(state: &mut ExeState) -> i32 {
+    let s = arg_data(state, 1, "hex_decode");
+    let result = if !s.len().is_multiple_of(2) {
+        Err("invalid hex length")
+    } else {
+        s.chunks(2).map(|pair| {
+            let digit = |c: u8| (c as char).to_digit(16).ok_or("invalid hex digit");
+            Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8)
+        }).collect()
+    };
+    push_decoded(state, result)
+}
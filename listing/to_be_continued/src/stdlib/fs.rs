@@ -0,0 +1,146 @@
+use std::env;
+use std::fs::{self, Metadata};
+use std::time::UNIX_EPOCH;
+use crate::value::{Table, Value};
+use crate::vm::ExeState;
+
+// File system library, modeled on LuaFileSystem, e.g. for build scripts:
+//
+//     for name in fs.dir("src") do
+//         local attr = fs.attributes("src/" .. name)
+//         if attr.mode == "file" then print(name, attr.size) end
+//     end
+//
+// Failures are returned as nil, the message and the error number, same
+// with `io.open()`, except that fs.dir() raises the error.
+//
+// It's built with the cargo feature "fs".
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("attributes", attributes),
+        ("chdir", chdir),
+        ("currentdir", currentdir),
+        ("dir", dir),
+        ("mkdir", mkdir),
+        ("rmdir", rmdir),
+    ])
+}
+
+fn check_path(state: &ExeState, iarg: usize, fname: &str) -> String {
+    let v = if state.get_top() < iarg { &Value::Nil } else { state.get::<&Value>(iarg) };
+    match v.as_str() {
+        Some(s) => s.to_string(),
+        None => panic!("bad argument #{iarg} to '{fname}' (string expected, got {})", v.type_name()),
+    }
+}
+
+// push true, or the failure
+fn push_result(state: &mut ExeState, result: std::io::Result<()>, path: &str) -> i32 {
+    match result {
+        Ok(()) => {
+            state.push(true);
+            1
+        }
+        Err(e) => super::io::push_error(state, &e, Some(path)),
+    }
+}
+
+// fs.dir(path)
+//
+// Return an iterator of the entry names in the directory, including "."
+// and "..", same with LuaFileSystem. The order is not specified.
+fn dir(state: &mut ExeState) -> i32 {
+    let path = check_path(state, 1, "dir");
+    let entries = fs::read_dir(&path).unwrap_or_else(|e|
+        panic!("cannot open {path}: {}", super::io::error_message(&e)));
+    let names = [".".into(), "..".into()].into_iter()
+        .chain(entries.filter_map(|e| e.ok())
+            .map(|e| Value::from(e.file_name().to_string_lossy().as_ref())));
+    let iter = state.create_iterator(names);
+    state.push(iter);
+    1
+}
+
+// fs.mkdir(path), fs.rmdir(path)
+//
+// Create or remove the directory, which is not recursive.
+fn mkdir(state: &mut ExeState) -> i32 {
+    let path = check_path(state, 1, "mkdir");
+    push_result(state, fs::create_dir(&path), &path)
+}
+
+fn rmdir(state: &mut ExeState) -> i32 {
+    let path = check_path(state, 1, "rmdir");
+    push_result(state, fs::remove_dir(&path), &path)
+}
+
+// fs.currentdir(), fs.chdir(path)
+fn currentdir(state: &mut ExeState) -> i32 {
+    match env::current_dir() {
+        Ok(dir) => {
+            state.push(dir.to_string_lossy().as_ref());
+            1
+        }
+        Err(e) => super::io::push_error(state, &e, None),
+    }
+}
+
+fn chdir(state: &mut ExeState) -> i32 {
+    let path = check_path(state, 1, "chdir");
+    push_result(state, env::set_current_dir(&path), &path)
+}
+
+// fs.attributes(path [, name])
+//
+// Return the table of attributes, or the one of @name:
+//   - mode: "file", "directory", "link" or "other", where symbolic links
+//     are followed, so "link" is for broken ones only;
+//   - size: in bytes;
+//   - modification, access: time in seconds, same with `os.time()`.
+fn attributes(state: &mut ExeState) -> i32 {
+    let path = check_path(state, 1, "attributes");
+    let meta = match fs::metadata(&path).or_else(|_| fs::symlink_metadata(&path)) {
+        Ok(meta) => meta,
+        Err(e) => return super::io::push_error(state, &e, Some(&path)),
+    };
+
+    let mut t = Table::new(0, 4);
+    t.map.insert("mode".into(), mode(&meta).into());
+    t.map.insert("size".into(), Value::Integer(meta.len() as i64));
+    t.map.insert("modification".into(), seconds(meta.modified()));
+    t.map.insert("access".into(), seconds(meta.accessed()));
+
+    if state.get_top() >= 2 && state.get::<&Value>(2) != &Value::Nil {
+        let name = state.get::<&Value>(2);
+        match t.map.get(name) {
+            Some(v) => {
+                let v = v.clone();
+                state.push(v);
+            }
+            None => panic!("bad argument #2 to 'attributes' (invalid attribute name '{name}')"),
+        }
+    } else {
+        state.push(Value::from(t));
+    }
+    1
+}
+
+fn mode(meta: &Metadata) -> &'static str {
+    let ft = meta.file_type();
+    if ft.is_file() {
+        "file"
+    } else if ft.is_dir() {
+        "directory"
+    } else if ft.is_symlink() {
+        "link"
+    } else {
+        "other"
+    }
+}
+
+fn seconds(t: std::io::Result<std::time::SystemTime>) -> Value {
+    match t.ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(d) => Value::Integer(d.as_secs() as i64),
+        None => Value::Nil,
+    }
+}
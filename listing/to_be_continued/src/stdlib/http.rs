@@ -0,0 +1,207 @@
+use std::io::Read;
+use std::time::Duration;
+use ureq::http;
+use ureq::{Agent, AsSendBody, Body, SendBody};
+use crate::value::{Table, Value};
+use crate::vm::ExeState;
+
+// HTTP client, e.g. for scripts calling webhooks:
+//
+//     local resp, err = http.post("https://example.com/hook", '{"ok":true}',
+//         { headers = { ["Content-Type"] = "application/json" }, timeout = 5 })
+//     if resp then print(resp.status, resp.body) end
+//
+// It's backed by ureq, with HTTPS by rustls, and redirects are followed.
+// The requests block the state until the response is received, or the
+// timeout.
+//
+// The responses are tables of `status`, `headers` whose names are in
+// lower case, and `body`. Failures of the network are returned as nil
+// and the message, while HTTP errors, e.g. 404, are responses.
+//
+// It's built with the cargo feature "http".
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("get", get),
+        ("post", post),
+        ("request", request),
+    ])
+}
+
+struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+}
+
+struct Response {
+    status: i64,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// http.get(url [, options])
+//
+// The @options is a table of `headers` and `timeout` in seconds.
+fn get(state: &mut ExeState) -> i32 {
+    let url = check_string(state, 1, "get");
+    let mut req = Request::new("GET", url);
+    req.set_options(state, 2, "get");
+    send(state, req)
+}
+
+// http.post(url, body [, options])
+fn post(state: &mut ExeState) -> i32 {
+    let url = check_string(state, 1, "post");
+    let body = check_string(state, 2, "post");
+    let mut req = Request::new("POST", url);
+    req.body = Some(body.into_bytes());
+    req.set_options(state, 3, "post");
+    send(state, req)
+}
+
+// http.request(options)
+//
+// The @options is a table of `url`, `method` which is "GET" by default,
+// `body`, `headers` and `timeout`.
+fn request(state: &mut ExeState) -> i32 {
+    let options = state.get::<&Value>(1).clone();
+    let Value::Table(_) = options else {
+        panic!("bad argument #1 to 'request' (table expected, got {})", options.type_name());
+    };
+    let url = match options.index(&"url".into()).as_str() {
+        Some(url) => url.to_string(),
+        None => panic!("bad argument #1 to 'request' (field 'url' is not a string)"),
+    };
+    let method = match options.index(&"method".into()) {
+        Value::Nil => "GET".to_string(),
+        v => v.as_str().map(str::to_uppercase).unwrap_or_else(||
+            panic!("bad argument #1 to 'request' (field 'method' is not a string)")),
+    };
+    let mut req = Request::new(&method, url);
+    req.body = match options.index(&"body".into()) {
+        Value::Nil => None,
+        v => Some(v.as_bytes().unwrap_or_else(||
+            panic!("bad argument #1 to 'request' (field 'body' is not a string)")).to_vec()),
+    };
+    req.set_options(state, 1, "request");
+    send(state, req)
+}
+
+fn check_string(state: &ExeState, iarg: usize, fname: &str) -> String {
+    let v = if state.get_top() < iarg { &Value::Nil } else { state.get::<&Value>(iarg) };
+    match v {
+        Value::Integer(_) | Value::Float(_) => v.to_string(),
+        _ => match v.as_str() {
+            Some(s) => s.to_string(),
+            None => panic!("bad argument #{iarg} to '{fname}' (string expected, got {})", v.type_name()),
+        }
+    }
+}
+
+fn send(state: &mut ExeState, req: Request) -> i32 {
+    state.flush(); // e.g. the progress printed before
+    match req.send() {
+        Ok(resp) => {
+            let mut headers = Table::new(0, resp.headers.len());
+            for (name, value) in resp.headers {
+                // repeated headers are joined, same with most clients
+                let value = match headers.map.get(&name.as_str().into()) {
+                    Some(prev) => format!("{prev}, {value}"),
+                    None => value,
+                };
+                headers.map.insert(name.into(), value.into());
+            }
+            let mut t = Table::new(0, 3);
+            t.map.insert("status".into(), Value::Integer(resp.status));
+            t.map.insert("headers".into(), Value::from(headers));
+            t.map.insert("body".into(), Value::from(resp.body));
+            state.push(Value::from(t));
+            1
+        }
+        Err(msg) => {
+            state.push(());
+            state.push(msg);
+            2
+        }
+    }
+}
+
+impl Request {
+    fn new(method: &str, url: String) -> Self {
+        Request { method: method.to_string(), url, headers: Vec::new(), body: None, timeout: None }
+    }
+
+    // the fields `headers` and `timeout` of the options table at @iarg
+    fn set_options(&mut self, state: &ExeState, iarg: usize, fname: &str) {
+        if state.get_top() < iarg {
+            return;
+        }
+        let options = state.get::<&Value>(iarg);
+        match options {
+            Value::Nil => return,
+            Value::Table(_) => (),
+            v => panic!("bad argument #{iarg} to '{fname}' (table expected, got {})", v.type_name()),
+        }
+
+        match options.index(&"headers".into()) {
+            Value::Nil => (),
+            Value::Table(t) => {
+                let t = t.borrow();
+                for (name, value) in &t.map {
+                    let value = match value {
+                        Value::Integer(_) | Value::Float(_) => Some(value.to_string()),
+                        _ => value.as_str().map(str::to_string),
+                    };
+                    match (name.as_str(), value) {
+                        (Some(name), Some(value)) => self.headers.push((name.to_string(), value)),
+                        _ => panic!("bad argument #{iarg} to '{fname}' (invalid header '{name}')"),
+                    }
+                }
+            }
+            _ => panic!("bad argument #{iarg} to '{fname}' (field 'headers' is not a table)"),
+        }
+
+        self.timeout = match options.index(&"timeout".into()).to_number() {
+            None => None,
+            Some(Value::Integer(i)) if i > 0 => Some(Duration::from_secs(i as u64)),
+            Some(Value::Float(f)) if f > 0.0 && f.is_finite() => Some(Duration::from_secs_f64(f)),
+            _ => panic!("bad argument #{iarg} to '{fname}' (field 'timeout' should be positive)"),
+        };
+    }
+
+    fn send(&self) -> Result<Response, String> {
+        let agent: Agent = Agent::config_builder()
+            .timeout_global(self.timeout)
+            .http_status_as_error(false)
+            .user_agent(concat!("lua-rs/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .into();
+        let mut builder = http::Request::builder()
+            .method(self.method.as_str())
+            .uri(&self.url);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let mut resp = match &self.body {
+            Some(body) => run(&agent, builder.body(body.as_slice()))?,
+            None => run(&agent, builder.body(SendBody::none()))?,
+        };
+
+        let headers = resp.headers().iter()
+            .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect();
+        let mut body = Vec::new();
+        resp.body_mut().as_reader().read_to_end(&mut body).map_err(|e| e.to_string())?;
+        Ok(Response { status: resp.status().as_u16() as i64, headers, body })
+    }
+}
+
+// build and send the request, where errors are returned as messages
+fn run(agent: &Agent, req: http::Result<http::Request<impl AsSendBody>>)
+        -> Result<http::Response<Body>, String> {
+    let req = req.map_err(|e| e.to_string())?;
+    agent.run(req).map_err(|e| e.to_string())
+}
@@ -0,0 +1,739 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+use crate::utils::ftoi;
+use crate::value::{Table, UserData, Value};
+use crate::vm::ExeState;
+
+// Files are userdata of LuaFile, with their own metatables whose
+// `__index` are the methods, e.g. `f:read("l")`. The files of `io.popen()`
+// are the pipes to the commands.
+//
+// The standard output is the output of the state, same with `print()`,
+// so they are in order, and it can be redirected by the host, see
+// ExeStateBuilder::output().
+pub fn new_lib() -> Value {
+    let lib = super::new_lib(&[
+        ("open", open),
+        ("popen", popen),
+        ("close", io_close),
+        ("type", io_type),
+    ]);
+
+    // the default input and output files
+    let stdin = new_file(Stream::Stdin);
+    let stdout = new_file(Stream::Stdout);
+    let stderr = new_file(Stream::Stderr);
+
+    let input = stdin.clone();
+    lib.new_index("read".into(), rust_closure(move |state| read_file(state, &input, 1)));
+    let input = stdin.clone();
+    lib.new_index("lines".into(), rust_closure(move |state| io_lines(state, &input)));
+    let output = stdout.clone();
+    lib.new_index("write".into(), rust_closure(move |state| write_file(state, &output, 1)));
+
+    lib.new_index("stdin".into(), stdin);
+    lib.new_index("stdout".into(), stdout);
+    lib.new_index("stderr".into(), stderr);
+    lib
+}
+
+fn rust_closure(f: impl FnMut(&mut ExeState) -> i32 + 'static) -> Value {
+    Value::RustClosure(Rc::new(RefCell::new(Box::new(f))))
+}
+
+pub struct LuaFile {
+    stream: Option<Stream>, // None after closed
+
+    // Buffered data read ahead, from @rpos. Reading and writing on the
+    // same file are mixed, so the reading buffer is discarded before
+    // writing and seeking, and the writing buffer is flushed before
+    // reading and seeking, same with the C standard library.
+    rbuf: Vec<u8>,
+    rpos: usize,
+    wbuf: Vec<u8>,
+}
+
+enum Stream {
+    File(File),
+    Pipe(Child), // with the stdout piped to read, or the stdin to write
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+// flush the writing buffer when it's larger than this
+const BUFFER_SIZE: usize = 8192;
+
+fn new_file(stream: Stream) -> Value {
+    let methods = super::new_lib(&[
+        ("read", read),
+        ("write", write),
+        ("lines", lines),
+        ("seek", seek),
+        ("flush", flush),
+        ("close", close),
+    ]);
+    let mut meta = Table::new(0, 1);
+    meta.map.insert("__index".into(), methods);
+    let Value::Table(meta) = Value::from(meta) else {
+        unreachable!();
+    };
+    let file = LuaFile { stream: Some(stream), rbuf: Vec::new(), rpos: 0, wbuf: Vec::new() };
+    Value::from(UserData::new(file, Some(meta)))
+}
+
+impl LuaFile {
+    fn stream(&mut self) -> &mut Stream {
+        self.stream.as_mut().unwrap_or_else(|| panic!("attempt to use a closed file"))
+    }
+
+    // Read more data into the buffer. Return false at the end of file.
+    fn fill(&mut self, state: &mut ExeState) -> io::Result<bool> {
+        self.flush_write()?;
+        if self.rpos > 0 {
+            self.rbuf.drain(..self.rpos);
+            self.rpos = 0;
+        }
+        let len = self.rbuf.len();
+        self.rbuf.resize(len + BUFFER_SIZE, 0);
+        let stream = self.stream.as_mut().unwrap_or_else(|| panic!("attempt to use a closed file"));
+        let n = match stream {
+            Stream::File(f) => f.read(&mut self.rbuf[len..]),
+            Stream::Pipe(c) => match &mut c.stdout {
+                Some(out) => out.read(&mut self.rbuf[len..]),
+                None => Err(io::Error::from(io::ErrorKind::Unsupported)),
+            }
+            Stream::Stdin => {
+                state.flush(); // for prompts
+                io::stdin().read(&mut self.rbuf[len..])
+            }
+            Stream::Stdout | Stream::Stderr => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        };
+        self.rbuf.truncate(len + *n.as_ref().unwrap_or(&0));
+        Ok(n? > 0)
+    }
+
+    fn buffered(&self) -> &[u8] {
+        &self.rbuf[self.rpos..]
+    }
+
+    // a line, with the '\n' if @keep_nl, or None at the end of file
+    fn read_line(&mut self, state: &mut ExeState, keep_nl: bool) -> io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        loop {
+            if let Some(i) = self.buffered().iter().position(|&b| b == b'\n') {
+                let end = if keep_nl { i + 1 } else { i };
+                line.extend_from_slice(&self.buffered()[..end]);
+                self.rpos += i + 1;
+                return Ok(Some(line));
+            }
+            line.extend_from_slice(self.buffered());
+            self.rpos = self.rbuf.len();
+            if !self.fill(state)? {
+                return Ok(if line.is_empty() { None } else { Some(line) });
+            }
+        }
+    }
+
+    fn read_all(&mut self, state: &mut ExeState) -> io::Result<Vec<u8>> {
+        while self.fill(state)? {}
+        let all = self.buffered().to_vec();
+        self.rpos = self.rbuf.len();
+        Ok(all)
+    }
+
+    // at most @n bytes, or None at the end of file
+    fn read_bytes(&mut self, state: &mut ExeState, n: usize) -> io::Result<Option<Vec<u8>>> {
+        while self.buffered().len() < n.max(1) && self.fill(state)? {}
+        if self.buffered().is_empty() {
+            return Ok(None);
+        }
+        let n = n.min(self.buffered().len());
+        let bytes = self.buffered()[..n].to_vec();
+        self.rpos += n;
+        Ok(Some(bytes))
+    }
+
+    // the next byte without taking it, or None at the end of file
+    fn peek(&mut self, state: &mut ExeState) -> io::Result<Option<u8>> {
+        if self.buffered().is_empty() && !self.fill(state)? {
+            return Ok(None);
+        }
+        Ok(Some(self.buffered()[0]))
+    }
+
+    // take the next byte into @numeral if it's accepted by @f
+    fn take_if(&mut self, state: &mut ExeState, numeral: &mut Vec<u8>,
+            f: impl Fn(u8) -> bool) -> io::Result<bool> {
+        match self.peek(state)? {
+            Some(b) if f(b) && numeral.len() <= MAX_NUMERAL => {
+                numeral.push(b);
+                self.rpos += 1;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn take_digits(&mut self, state: &mut ExeState, numeral: &mut Vec<u8>,
+            hex: bool) -> io::Result<usize> {
+        let mut count = 0;
+        while self.take_if(state, numeral, |b| if hex { b.is_ascii_hexdigit() } else { b.is_ascii_digit() })? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // A numeral, None if it's not a valid number, same with the official
+    // `l_getn()`: take the longest prefix that may be a numeral, and leave
+    // the following bytes, e.g. "xyz" of "12.5xyz".
+    fn read_number(&mut self, state: &mut ExeState) -> io::Result<Option<Value>> {
+        while self.peek(state)?.is_some_and(|b| b.is_ascii_whitespace()) {
+            self.rpos += 1;
+        }
+        let mut numeral = Vec::new();
+        self.take_if(state, &mut numeral, |b| b == b'+' || b == b'-')?;
+        let mut count = 0;
+        let mut hex = false;
+        if self.take_if(state, &mut numeral, |b| b == b'0')? {
+            if self.take_if(state, &mut numeral, |b| b == b'x' || b == b'X')? {
+                hex = true;
+            } else {
+                count = 1; // the initial '0' is a digit
+            }
+        }
+        count += self.take_digits(state, &mut numeral, hex)?;
+        if self.take_if(state, &mut numeral, |b| b == b'.')? {
+            count += self.take_digits(state, &mut numeral, hex)?;
+        }
+        let exp: &[u8] = if hex { b"pP" } else { b"eE" };
+        if count > 0 && self.take_if(state, &mut numeral, |b| exp.contains(&b))? {
+            self.take_if(state, &mut numeral, |b| b == b'+' || b == b'-')?;
+            self.take_digits(state, &mut numeral, false)?;
+        }
+        if numeral.len() > MAX_NUMERAL {
+            return Ok(None);
+        }
+        Ok(Value::from(numeral).to_number())
+    }
+
+    fn write(&mut self, state: &mut ExeState, buf: &[u8]) -> io::Result<()> {
+        match self.stream() {
+            Stream::Pipe(c) if c.stdin.is_none() => Err(io::Error::from(io::ErrorKind::Unsupported)),
+            Stream::File(_) | Stream::Pipe(_) => {
+                self.discard_read()?;
+                self.wbuf.extend_from_slice(buf);
+                if self.wbuf.len() > BUFFER_SIZE {
+                    self.flush_write()?;
+                }
+                Ok(())
+            }
+            Stream::Stdout => {
+                state.write_output(buf);
+                Ok(())
+            }
+            Stream::Stderr => io::stderr().write_all(buf),
+            Stream::Stdin => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+
+    fn flush_write(&mut self) -> io::Result<()> {
+        if self.wbuf.is_empty() {
+            return Ok(());
+        }
+        let w: &mut dyn Write = match &mut self.stream {
+            Some(Stream::File(f)) => f,
+            Some(Stream::Pipe(Child { stdin: Some(w), .. })) => w,
+            _ => return Ok(()),
+        };
+        w.write_all(&self.wbuf)?;
+        self.wbuf.clear();
+        Ok(())
+    }
+
+    // discard the data read ahead, and move back the position of file
+    fn discard_read(&mut self) -> io::Result<()> {
+        let unread = self.buffered().len();
+        if unread > 0 {
+            if let Some(Stream::File(f)) = &mut self.stream {
+                f.seek(SeekFrom::Current(-(unread as i64)))?;
+            }
+        }
+        self.rbuf.clear();
+        self.rpos = 0;
+        Ok(())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_write()?;
+        self.discard_read()?;
+        match self.stream() {
+            Stream::File(f) => f.seek(pos),
+            _ => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+
+    fn flush(&mut self, state: &mut ExeState) -> io::Result<()> {
+        match self.stream() {
+            Stream::File(_) | Stream::Pipe(_) => self.flush_write(),
+            Stream::Stdout => {
+                state.flush();
+                Ok(())
+            }
+            Stream::Stderr => io::stderr().flush(),
+            Stream::Stdin => Ok(()),
+        }
+    }
+
+    // Close the file, and return the exit status of the command for pipes,
+    // which is waited for.
+    fn close(&mut self) -> io::Result<Option<ExitStatus>> {
+        let result = self.flush_write();
+        let status = match self.stream.take() {
+            Some(Stream::Pipe(mut c)) => {
+                drop(c.stdin.take()); // end of the input
+                Some(c.wait()?)
+            }
+            _ => None,
+        };
+        result.map(|_| status)
+    }
+}
+
+impl Drop for LuaFile {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+// max length of numerals read, same with L_MAXLENNUM
+const MAX_NUMERAL: usize = 200;
+
+fn check_file(state: &ExeState, iarg: usize, fname: &str) -> Rc<RefCell<UserData>> {
+    match state.get::<&Value>(iarg) {
+        Value::UserData(u) if u.borrow().is::<LuaFile>() => u.clone(),
+        v => panic!("bad argument #{iarg} to '{fname}' (file expected, got {})", v.type_name()),
+    }
+}
+
+fn with_file<R>(file: &Rc<RefCell<UserData>>, f: impl FnOnce(&mut LuaFile) -> R) -> R {
+    f(file.borrow_mut().downcast_mut::<LuaFile>().unwrap())
+}
+
+// the message of @e without the "(os error N)" added by Rust
+pub(crate) fn error_message(e: &io::Error) -> String {
+    let msg = e.to_string();
+    match msg.rfind(" (os error ") {
+        Some(i) => msg[..i].to_string(),
+        None => msg,
+    }
+}
+
+// Push the failure: nil, the message and the error number, same with
+// `luaL_fileresult()`, where the message is prefixed by @filename if given.
+pub(crate) fn push_error(state: &mut ExeState, e: &io::Error, filename: Option<&str>) -> i32 {
+    let msg = match filename {
+        Some(name) => format!("{name}: {}", error_message(e)),
+        None => error_message(e),
+    };
+    state.push(());
+    state.push(msg);
+    state.push(e.raw_os_error().unwrap_or(0) as i64);
+    3
+}
+
+// io.open(filename [, mode])
+//
+// Open the file by @mode, which is "r" by default, or "w", "a", "r+",
+// "w+" and "a+", optionally followed by "b" which is ignored. Return the
+// file, or nil and the error message and the error number.
+fn open(state: &mut ExeState) -> i32 {
+    let filename = match state.get::<&Value>(1).as_str() {
+        Some(s) => s.to_string(),
+        None => panic!("bad argument #1 to 'open' (string expected, got {})",
+            state.get::<&Value>(1).type_name()),
+    };
+    let mode = if state.get_top() >= 2 && state.get::<&Value>(2) != &Value::Nil {
+        match state.get::<&Value>(2).as_str() {
+            Some(s) => s.to_string(),
+            None => panic!("bad argument #2 to 'open' (string expected, got {})",
+                state.get::<&Value>(2).type_name()),
+        }
+    } else {
+        "r".to_string()
+    };
+
+    let mut options = OpenOptions::new();
+    match mode.trim_end_matches('b') {
+        "r" => options.read(true),
+        "w" => options.write(true).create(true).truncate(true),
+        "a" => options.append(true).create(true),
+        "r+" => options.read(true).write(true),
+        "w+" => options.read(true).write(true).create(true).truncate(true),
+        "a+" => options.read(true).append(true).create(true),
+        _ => panic!("bad argument #2 to 'open' (invalid mode)"),
+    };
+    match options.open(&filename) {
+        Ok(f) => {
+            state.push(new_file(Stream::File(f)));
+            1
+        }
+        Err(e) => push_error(state, &e, Some(&filename)),
+    }
+}
+
+// io.popen(prog [, mode])
+//
+// Run the command @prog by the shell, and return a file to read its
+// output for @mode "r" which is the default, or to write its input for
+// "w". It's removed by `ExeStateBuilder::allow_subprocess(false)`.
+fn popen(state: &mut ExeState) -> i32 {
+    let prog = match state.get::<&Value>(1).as_str() {
+        Some(s) => s.to_string(),
+        None => panic!("bad argument #1 to 'popen' (string expected, got {})",
+            state.get::<&Value>(1).type_name()),
+    };
+    let mode = if state.get_top() >= 2 { state.get::<&Value>(2).as_str() } else { Some("r") };
+    let mut cmd = shell(state, &prog);
+    match mode {
+        Some("r") => cmd.stdout(Stdio::piped()),
+        Some("w") => cmd.stdin(Stdio::piped()),
+        _ => panic!("bad argument #2 to 'popen' (invalid mode)"),
+    };
+
+    state.flush(); // before the output of the command
+    match cmd.spawn() {
+        Ok(child) => {
+            state.push(new_file(Stream::Pipe(child)));
+            1
+        }
+        Err(e) => push_error(state, &e, Some(&prog)),
+    }
+}
+
+// the command to run @cmd by the shell, same with `system()`, with the
+// environment variables written by `os.env`
+pub(crate) fn shell(state: &ExeState, cmd: &str) -> Command {
+    let mut c;
+    if cfg!(windows) {
+        c = Command::new("cmd");
+        c.arg("/C");
+    } else {
+        c = Command::new("/bin/sh");
+        c.arg("-c");
+    }
+    c.arg(cmd);
+    for (name, v) in state.env_vars() {
+        match v {
+            Some(v) => c.env(name, v),
+            None => c.env_remove(name),
+        };
+    }
+    c
+}
+
+// Push the exit status of a command: true or nil for success or not,
+// then "exit" and the exit code, or "signal" and the signal number if
+// it's killed by a signal, same with `luaL_execresult()`.
+pub(crate) fn push_status(state: &mut ExeState, status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(sig) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        state.push(());
+        state.push("signal");
+        state.push(sig as i64);
+        return 3;
+    }
+    if status.success() {
+        state.push(true);
+    } else {
+        state.push(());
+    }
+    state.push("exit");
+    state.push(status.code().unwrap_or(-1) as i64);
+    3
+}
+
+// io.close([file])
+//
+// Close @file, or the standard output which can not be closed.
+fn io_close(state: &mut ExeState) -> i32 {
+    if state.get_top() == 0 {
+        state.push(());
+        state.push("cannot close standard file");
+        return 2;
+    }
+    close(state)
+}
+
+// io.type(obj)
+//
+// Return "file" or "closed file" for files, otherwise nil.
+fn io_type(state: &mut ExeState) -> i32 {
+    let t = match state.get::<&Value>(1) {
+        Value::UserData(u) => match u.borrow().downcast_ref::<LuaFile>() {
+            Some(f) if f.stream.is_some() => Value::from("file"),
+            Some(_) => Value::from("closed file"),
+            None => Value::Nil,
+        },
+        _ => Value::Nil,
+    };
+    state.push(t);
+    1
+}
+
+// io.lines([filename, ...])
+//
+// Return an iterator of the file by the formats, see read(), which is
+// closed at the end. It's the standard input without @filename.
+fn io_lines(state: &mut ExeState, stdin: &Value) -> i32 {
+    if state.get_top() == 0 || state.get::<&Value>(1) == &Value::Nil {
+        let Value::UserData(file) = stdin else {
+            unreachable!();
+        };
+        return push_lines(state, file.clone(), 2, false);
+    }
+
+    let filename = match state.get::<&Value>(1).as_str() {
+        Some(s) => s.to_string(),
+        None => panic!("bad argument #1 to 'lines' (string expected, got {})",
+            state.get::<&Value>(1).type_name()),
+    };
+    let f = File::open(&filename).unwrap_or_else(|e|
+        panic!("{filename}: {}", error_message(&e)));
+    let Value::UserData(file) = new_file(Stream::File(f)) else {
+        unreachable!();
+    };
+    push_lines(state, file, 2, true)
+}
+
+// file:lines(...)
+//
+// Return an iterator of the file by the formats, see read(), which is
+// "l" by default. The file is not closed at the end.
+fn lines(state: &mut ExeState) -> i32 {
+    let file = check_file(state, 1, "lines");
+    with_file(&file, |f| { f.stream(); });
+    push_lines(state, file, 2, false)
+}
+
+// push the iterator, with the formats from the argument @iarg
+fn push_lines(state: &mut ExeState, file: Rc<RefCell<UserData>>, iarg: usize, to_close: bool) -> i32 {
+    let formats: Vec<Value> = (iarg..=state.get_top())
+        .map(|i| state.get::<&Value>(i).clone())
+        .collect();
+    let file = Value::UserData(file);
+    let f = move |state: &mut ExeState| {
+        let Value::UserData(u) = &file else {
+            unreachable!();
+        };
+        if with_file(u, |f| f.stream.is_none()) {
+            panic!("file is already closed");
+        }
+        let itop = state.get_top();
+        state.push(file.clone());
+        for v in &formats {
+            state.push(v.clone());
+        }
+        let nret = read_file(state, &file, itop + 2) as usize;
+        if to_close && state.get::<&Value>(state.get_top() + 1 - nret) == &Value::Nil {
+            let _ = with_file(u, LuaFile::close);
+        }
+        nret as i32
+    };
+    state.push(rust_closure(f));
+    1
+}
+
+// file:read(...)
+//
+// Read by the formats, and return a value for each, which is nil at the
+// end of file, and the following formats are not read. The formats are:
+//   - "l": a line without the end of line, which is the default;
+//   - "L": a line with the end of line;
+//   - "n": a number;
+//   - "a": all the rest, which is "" at the end of file;
+//   - an integer: a string of at most the number of bytes, and 0 for
+//     testing the end of file.
+// The formats may be prefixed by '*' of Lua 5.1.
+fn read(state: &mut ExeState) -> i32 {
+    let file = check_file(state, 1, "read");
+    read_file(state, &Value::UserData(file), 2)
+}
+
+// read @file by the formats from the argument @iarg
+fn read_file(state: &mut ExeState, file: &Value, iarg: usize) -> i32 {
+    let Value::UserData(u) = file else {
+        unreachable!();
+    };
+    let formats: Vec<Value> = if state.get_top() < iarg {
+        vec!["l".into()]
+    } else {
+        (iarg..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect()
+    };
+
+    let mut u = u.borrow_mut();
+    let f = u.downcast_mut::<LuaFile>().unwrap();
+    f.stream();
+    let mut rets = Vec::new();
+    for (i, format) in formats.iter().enumerate() {
+        let result = match format {
+            Value::Integer(_) | Value::Float(_) => {
+                let n = match *format {
+                    Value::Integer(n) => n,
+                    Value::Float(x) => ftoi(x).unwrap_or_else(||
+                        panic!("bad argument #{} to 'read' (number has no integer representation)", iarg + i)),
+                    _ => unreachable!(),
+                };
+                f.read_bytes(state, n.max(0) as usize).map(|b| b.map_or(Value::Nil, Value::from))
+            }
+            _ => {
+                let fmt = format.as_bytes().unwrap_or_else(||
+                    panic!("bad argument #{} to 'read' (invalid format)", iarg + i));
+                match fmt.strip_prefix(b"*").unwrap_or(fmt).first() {
+                    Some(b'l') => f.read_line(state, false).map(|l| l.map_or(Value::Nil, Value::from)),
+                    Some(b'L') => f.read_line(state, true).map(|l| l.map_or(Value::Nil, Value::from)),
+                    Some(b'n') => f.read_number(state).map(|n| n.unwrap_or(Value::Nil)),
+                    Some(b'a') => f.read_all(state).map(Value::from),
+                    _ => panic!("bad argument #{} to 'read' (invalid format)", iarg + i),
+                }
+            }
+        };
+        match result {
+            Ok(v) => {
+                let end = v == Value::Nil;
+                rets.push(v);
+                if end {
+                    break;
+                }
+            }
+            Err(e) => {
+                drop(u);
+                return push_error(state, &e, None);
+            }
+        }
+    }
+    drop(u);
+    let n = rets.len();
+    for v in rets {
+        state.push(v);
+    }
+    n as i32
+}
+
+// file:write(...)
+//
+// Write the strings or numbers, and return the file, or nil and the
+// error message and the error number.
+fn write(state: &mut ExeState) -> i32 {
+    let file = check_file(state, 1, "write");
+    write_file(state, &Value::UserData(file), 2)
+}
+
+// write the arguments from @iarg to @file
+fn write_file(state: &mut ExeState, file: &Value, iarg: usize) -> i32 {
+    let Value::UserData(u) = file else {
+        unreachable!();
+    };
+    let mut result = Ok(());
+    for i in iarg..=state.get_top() {
+        let v = state.get::<&Value>(i);
+        let bytes = match v {
+            Value::Integer(_) | Value::Float(_) => v.to_string().into_bytes(),
+            _ => match v.as_bytes() {
+                Some(s) => s.to_vec(),
+                None => panic!("bad argument #{} to 'write' (string expected, got {})",
+                    i + 1 - iarg, v.type_name()),
+            }
+        };
+        result = with_file(u, |f| f.write(state, &bytes));
+        if result.is_err() {
+            break;
+        }
+    }
+    match result {
+        Ok(()) => {
+            state.push(file.clone());
+            1
+        }
+        Err(e) => push_error(state, &e, None),
+    }
+}
+
+// file:seek([whence [, offset]])
+//
+// Set the position to @offset from @whence, which is "set" for the
+// beginning, "cur" for the current position which is the default, or
+// "end". Return the position from the beginning.
+fn seek(state: &mut ExeState) -> i32 {
+    let file = check_file(state, 1, "seek");
+    let whence = if state.get_top() >= 2 && state.get::<&Value>(2) != &Value::Nil {
+        state.get::<&Value>(2).as_str().unwrap_or("").to_string()
+    } else {
+        "cur".to_string()
+    };
+    let offset = if state.get_top() >= 3 && state.get::<&Value>(3) != &Value::Nil {
+        match state.get::<&Value>(3).to_number() {
+            Some(Value::Integer(i)) => i,
+            Some(Value::Float(f)) => ftoi(f).unwrap_or_else(||
+                panic!("bad argument #3 to 'seek' (number has no integer representation)")),
+            _ => panic!("bad argument #3 to 'seek' (number expected, got {})",
+                state.get::<&Value>(3).type_name()),
+        }
+    } else {
+        0
+    };
+    let pos = match whence.as_str() {
+        "set" => SeekFrom::Start(offset.max(0) as u64),
+        "cur" => SeekFrom::Current(offset),
+        "end" => SeekFrom::End(offset),
+        _ => panic!("bad argument #2 to 'seek' (invalid option '{whence}')"),
+    };
+    match with_file(&file, |f| f.seek(pos)) {
+        Ok(pos) => {
+            state.push(pos as i64);
+            1
+        }
+        Err(e) => push_error(state, &e, None),
+    }
+}
+
+// file:flush()
+fn flush(state: &mut ExeState) -> i32 {
+    let file = check_file(state, 1, "flush");
+    match with_file(&file, |f| f.flush(state)) {
+        Ok(()) => {
+            state.push(Value::UserData(file));
+            1
+        }
+        Err(e) => push_error(state, &e, None),
+    }
+}
+
+// file:close()
+//
+// Close the file, and return true, or the exit status of the command
+// for pipes, same with `os.execute()`. The standard files can not be
+// closed. Files are also closed when they are collected.
+fn close(state: &mut ExeState) -> i32 {
+    let file = check_file(state, 1, "close");
+    let result = with_file(&file, |f| match f.stream() {
+        Stream::File(_) | Stream::Pipe(_) => Some(f.close()),
+        _ => None,
+    });
+    match result {
+        Some(Ok(None)) => {
+            state.push(true);
+            1
+        }
+        Some(Ok(Some(status))) => push_status(state, status),
+        Some(Err(e)) => push_error(state, &e, None),
+        None => {
+            state.push(());
+            state.push("cannot close standard file");
+            2
+        }
+    }
+}
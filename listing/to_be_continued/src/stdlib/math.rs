@@ -0,0 +1,351 @@
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::utils::ftoi;
+use crate::value::Value;
+use crate::vm::{self, ExeState};
+
+pub fn new_lib() -> Value {
+    let lib = super::new_lib(&[
+        ("abs", abs),
+        ("ceil", ceil),
+        ("cos", cos),
+        ("exp", exp),
+        ("floor", floor),
+        ("fmod", fmod),
+        ("log", log),
+        ("max", max),
+        ("min", min),
+        ("modf", modf),
+        ("random", random),
+        ("randomseed", randomseed),
+        ("sin", sin),
+        ("sqrt", sqrt),
+        ("tointeger", tointeger),
+        ("type", lib_type),
+    ]);
+    lib.new_index("pi".into(), PI.into());
+    lib.new_index("huge".into(), f64::INFINITY.into());
+    lib.new_index("maxinteger".into(), i64::MAX.into());
+    lib.new_index("mininteger".into(), i64::MIN.into());
+    lib
+}
+
+// number argument, where strings are converted, and the subtype is kept
+fn check_number(state: &ExeState, iarg: usize, fname: &str) -> Value {
+    if state.get_top() < iarg {
+        panic!("bad argument #{iarg} to '{fname}' (number expected, got no value)");
+    }
+    let v = state.get::<&Value>(iarg);
+    v.to_number().unwrap_or_else(||
+        panic!("bad argument #{iarg} to '{fname}' (number expected, got {})", v.type_name()))
+}
+
+fn check_float(state: &ExeState, iarg: usize, fname: &str) -> f64 {
+    match check_number(state, iarg, fname) {
+        Value::Integer(i) => i as f64,
+        Value::Float(f) => f,
+        _ => unreachable!(),
+    }
+}
+
+// the integer if @f fits, or the float, e.g. for `math.floor(2^53)`
+// which is integer, and `math.floor(2^63)` which is not
+fn float_to_int(f: f64) -> Value {
+    ftoi(f).map_or(Value::Float(f), Value::Integer)
+}
+
+// math.floor(x), math.ceil(x)
+//
+// Return integers if representable, otherwise floats, e.g. for
+// infinity, NaN and numbers out of the range of integers.
+fn floor(state: &mut ExeState) -> i32 {
+    let v = match check_number(state, 1, "floor") {
+        Value::Float(f) => float_to_int(f.floor()),
+        i => i,
+    };
+    state.push(v);
+    1
+}
+
+fn ceil(state: &mut ExeState) -> i32 {
+    let v = match check_number(state, 1, "ceil") {
+        Value::Float(f) => float_to_int(f.ceil()),
+        i => i,
+    };
+    state.push(v);
+    1
+}
+
+// math.abs(x)
+//
+// `math.abs(math.mininteger)` wraps around to itself, same with the
+// official Lua.
+fn abs(state: &mut ExeState) -> i32 {
+    let v = match check_number(state, 1, "abs") {
+        Value::Integer(i) => Value::Integer(i.wrapping_abs()),
+        Value::Float(f) => Value::Float(f.abs()),
+        _ => unreachable!(),
+    };
+    state.push(v);
+    1
+}
+
+// functions of floats, where integer arguments are converted
+fn sqrt(state: &mut ExeState) -> i32 {
+    let x = check_float(state, 1, "sqrt");
+    state.push(x.sqrt());
+    1
+}
+
+fn sin(state: &mut ExeState) -> i32 {
+    let x = check_float(state, 1, "sin");
+    state.push(x.sin());
+    1
+}
+
+fn cos(state: &mut ExeState) -> i32 {
+    let x = check_float(state, 1, "cos");
+    state.push(x.cos());
+    1
+}
+
+fn exp(state: &mut ExeState) -> i32 {
+    let x = check_float(state, 1, "exp");
+    state.push(x.exp());
+    1
+}
+
+// math.log(x [, base])
+//
+// The natural logarithm by default. Bases 2 and 10 are exact for their
+// powers, e.g. `math.log(1000, 10)` is 3.0.
+fn log(state: &mut ExeState) -> i32 {
+    let x = check_float(state, 1, "log");
+    let r = if state.get_top() < 2 || state.get::<&Value>(2) == &Value::Nil {
+        x.ln()
+    } else {
+        match check_float(state, 2, "log") {
+            2.0 => x.log2(),
+            10.0 => x.log10(),
+            base => x.ln() / base.ln(),
+        }
+    };
+    state.push(r);
+    1
+}
+
+// math.fmod(x, y)
+//
+// The remainder of the division rounding towards zero, which has the
+// sign of @x. It's integer if both arguments are integers.
+fn fmod(state: &mut ExeState) -> i32 {
+    let v = match (check_number(state, 1, "fmod"), check_number(state, 2, "fmod")) {
+        (Value::Integer(_), Value::Integer(0)) =>
+            panic!("bad argument #2 to 'fmod' (zero)"),
+        (Value::Integer(_), Value::Integer(-1)) => Value::Integer(0), // avoid overflow
+        (Value::Integer(a), Value::Integer(b)) => Value::Integer(a % b),
+        (a, b) => Value::Float(to_float(&a) % to_float(&b)),
+    };
+    state.push(v);
+    1
+}
+
+fn to_float(v: &Value) -> f64 {
+    match *v {
+        Value::Integer(i) => i as f64,
+        Value::Float(f) => f,
+        _ => unreachable!(),
+    }
+}
+
+// math.modf(x)
+//
+// Return the integral part and the fractional part of @x. The integral
+// part is float, e.g. 3.0 for 3.7, unless @x is integer.
+fn modf(state: &mut ExeState) -> i32 {
+    match check_number(state, 1, "modf") {
+        Value::Float(f) => {
+            let ip = f.trunc();
+            state.push(ip);
+            state.push(if f.is_infinite() { 0.0 } else { f - ip });
+        }
+        i => {
+            state.push(i);
+            state.push(0.0);
+        }
+    }
+    2
+}
+
+// math.max(x, ...), math.min(x, ...)
+//
+// Return the argument itself, so the subtype is kept, e.g.
+// `math.max(1, 2.0)` is 2.0.
+fn max(state: &mut ExeState) -> i32 {
+    extremum(state, "max", Ordering::Less)
+}
+
+fn min(state: &mut ExeState) -> i32 {
+    extremum(state, "min", Ordering::Greater)
+}
+
+// The result is replaced by the following arguments which it compares
+// as @replace to, i.e. `Less` for max and `Greater` for min, so the
+// first one is kept on ties.
+fn extremum(state: &mut ExeState, fname: &str, replace: Ordering) -> i32 {
+    let mut r = check_number(state, 1, fname);
+    for i in 2..=state.get_top() {
+        let v = check_number(state, i, fname);
+        if vm::compare(&r, &v) == Some(replace) {
+            r = v;
+        }
+    }
+    state.push(r);
+    1
+}
+
+// math.tointeger(x)
+//
+// Return the integer if @x is convertible without loss, e.g. 3.0 and
+// "3", otherwise nil.
+fn tointeger(state: &mut ExeState) -> i32 {
+    if state.get_top() < 1 {
+        panic!("bad argument #1 to 'tointeger' (value expected)");
+    }
+    let v = match state.get::<&Value>(1).to_number() {
+        Some(Value::Integer(i)) => Value::Integer(i),
+        Some(Value::Float(f)) => ftoi(f).map_or(Value::Nil, Value::Integer),
+        _ => Value::Nil,
+    };
+    state.push(v);
+    1
+}
+
+// math.type(x)
+//
+// Return "integer" or "float" for numbers, or nil for others, including
+// strings which are convertible.
+fn lib_type(state: &mut ExeState) -> i32 {
+    if state.get_top() < 1 {
+        panic!("bad argument #1 to 'type' (value expected)");
+    }
+    let t = match state.get::<&Value>(1) {
+        Value::Integer(_) => Value::from("integer"),
+        Value::Float(_) => Value::from("float"),
+        _ => Value::Nil,
+    };
+    state.push(t);
+    1
+}
+
+// Pseudo-random number generator of `math.random()`, which is the
+// xoshiro256** same with the official Lua, so the sequences of the same
+// seeds are the same too. Each state has its own generator, which is
+// seeded by 0 when the state is created, so scripts are deterministic
+// unless they call `math.randomseed()` without arguments.
+pub struct Random {
+    s: [u64; 4],
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new(0, 0)
+    }
+}
+
+impl Random {
+    pub fn new(n1: i64, n2: i64) -> Self {
+        let mut rand = Random { s: [n1 as u64, 0xff, n2 as u64, 0] };
+        for _ in 0..16 { // discard initial values to "spread" the seed
+            rand.next();
+        }
+        rand
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u64 {
+        let [s0, s1, s2, s3] = self.s;
+        let s2 = s2 ^ s0;
+        let s3 = s3 ^ s1;
+        let res = s1.wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        self.s = [s0 ^ s3, s1 ^ s2, s2 ^ (s1 << 17), s3.rotate_left(45)];
+        res
+    }
+
+    // float in [0, 1), by the higher 53 bits
+    pub fn next_float(&mut self) -> f64 {
+        (self.next() >> 11) as f64 * (0.5 / (1u64 << 52) as f64)
+    }
+
+    // integer in [0, @n], by the official `project()`, where the random
+    // bits are masked by the smallest 2^b-1 not less than @n, and retried
+    // if greater than @n, so there is no bias
+    pub fn project(&mut self, n: u64) -> u64 {
+        let mut r = self.next();
+        if n & n.wrapping_add(1) == 0 { // n + 1 is a power of 2
+            return r & n;
+        }
+        let lim = u64::MAX >> n.leading_zeros();
+        loop {
+            r &= lim;
+            if r <= n {
+                return r;
+            }
+            r = self.next();
+        }
+    }
+}
+
+// math.random([m [, n]])
+//
+// Return a float in [0, 1) without arguments, or an integer in [1, @m]
+// or [@m, @n]. `math.random(0)` returns an integer of all random bits.
+fn random(state: &mut ExeState) -> i32 {
+    let (low, up) = match state.get_top() {
+        0 => {
+            let f = state.random().next_float();
+            state.push(f);
+            return 1;
+        }
+        1 => match state.check_integer(1, "random") {
+            0 => { // all bits
+                let i = state.random().next() as i64;
+                state.push(i);
+                return 1;
+            }
+            up => (1, up),
+        }
+        2 => (state.check_integer(1, "random"), state.check_integer(2, "random")),
+        _ => panic!("wrong number of arguments"),
+    };
+    if low > up {
+        let iarg = if state.get_top() == 1 { 1 } else { 2 };
+        panic!("bad argument #{iarg} to 'random' (interval is empty)");
+    }
+    let r = state.random().project(up.wrapping_sub(low) as u64);
+    state.push(Value::Integer((r as i64).wrapping_add(low)));
+    1
+}
+
+// math.randomseed([x [, y]])
+//
+// Seed the generator by the integers @x and @y, or randomly without
+// arguments. Return the two seeds, so the sequence can be repeated.
+fn randomseed(state: &mut ExeState) -> i32 {
+    let (n1, n2) = if state.get_top() == 0 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        (now.as_secs() as i64, now.subsec_nanos() as i64)
+    } else {
+        let n1 = match check_number(state, 1, "randomseed") {
+            Value::Float(f) => f as i64,
+            i => (&i).into(),
+        };
+        let n2 = if state.get_top() < 2 { 0 } else { state.check_integer(2, "randomseed") };
+        (n1, n2)
+    };
+    *state.random() = Random::new(n1, n2);
+    state.push(n1);
+    state.push(n2);
+    2
+}
@@ -0,0 +1,43 @@
+use crate::value::{Value, Table};
+use crate::vm::ExeState;
+
+pub mod table;
+pub mod string;
+pub mod math;
+pub mod package;
+pub mod args;
+pub mod debug;
+pub mod coroutine;
+pub mod channel;
+pub mod io;
+pub mod timer;
+pub mod bytes;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "fs")]
+pub mod fs;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "encoding")]
+pub mod encoding;
+#[cfg(feature = "zlib")]
+pub mod zlib;
+#[cfg(feature = "datetime")]
+pub mod datetime;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "re")]
+pub mod re;
+#[cfg(unix)]
+pub mod os;
+
+pub type LibFunction = fn (&mut ExeState) -> i32;
+
+// create a library table with the functions
+fn new_lib(funcs: &[(&str, LibFunction)]) -> Value {
+    let mut lib = Table::new(0, funcs.len());
+    for &(name, f) in funcs {
+        lib.map.insert(name.into(), Value::RustFunction(f));
+    }
+    Value::from(lib)
+}
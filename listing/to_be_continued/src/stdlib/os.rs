@@ -0,0 +1,475 @@
+use std::env;
+use std::ffi::{c_char, c_int, c_long, CString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::value::{Value, Table};
+use crate::vm::{ExeState, ExecLimit};
+
+// Time conversions are done by the C library, same with the official
+// Lua, because the local time zone and DST rules are known only there.
+// So this library is for Unix only.
+
+// `time_t` of C library, which is `long` on 32-bit systems except musl,
+// whose `time_t` is 64-bit since 1.2
+#[cfg(all(target_pointer_width = "32", not(target_env = "musl")))]
+type TimeT = i32;
+#[cfg(not(all(target_pointer_width = "32", not(target_env = "musl"))))]
+type TimeT = i64;
+
+// `struct tm` of C library, with the extra fields of Linux and BSD,
+// which are written by the C library but not used here
+#[repr(C)]
+struct Tm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    #[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple",
+            target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+    tm_gmtoff: c_long,
+    #[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple",
+            target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+    tm_zone: *const c_char,
+}
+
+extern "C" {
+    fn localtime_r(t: *const TimeT, tm: *mut Tm) -> *mut Tm;
+    fn gmtime_r(t: *const TimeT, tm: *mut Tm) -> *mut Tm;
+    fn mktime(tm: *mut Tm) -> TimeT;
+    fn strftime(s: *mut c_char, max: usize, format: *const c_char, tm: *const Tm) -> usize;
+    fn clock() -> c_long;
+}
+
+// required by POSIX, whatever the resolution is
+const CLOCKS_PER_SEC: f64 = 1_000_000.0;
+
+impl Tm {
+    fn new() -> Self {
+        Tm {
+            tm_sec: 0, tm_min: 0, tm_hour: 0, tm_mday: 0, tm_mon: 0, tm_year: 0,
+            tm_wday: 0, tm_yday: 0, tm_isdst: 0,
+            #[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple",
+                    target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+            tm_gmtoff: 0,
+            #[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple",
+                    target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+            tm_zone: std::ptr::null(),
+        }
+    }
+}
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("time", time),
+        ("date", date),
+        ("difftime", difftime),
+        ("clock", lib_clock),
+        ("getenv", getenv),
+        ("remove", remove),
+        ("rename", rename),
+        ("exit", exit),
+        ("execute", execute),
+    ])
+}
+
+fn now() -> TimeT {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as TimeT)
+}
+
+// convert the time of Lua into `time_t`
+#[allow(clippy::useless_conversion)] // TimeT is i64 on 64-bit
+fn to_time_t(t: i64, iarg: usize, fname: &str) -> TimeT {
+    TimeT::try_from(t).unwrap_or_else(|_| panic!("bad argument #{iarg} to '{fname}' (time out-of-bounds)"))
+}
+
+// is the argument absent or nil
+fn arg_none(state: &ExeState, i: usize) -> bool {
+    state.get_top() < i || state.get::<&Value>(i) == &Value::Nil
+}
+
+// os.time([t])
+//
+// Return the current time, or the time of the date table @t in local
+// time. Fields of @t are normalized by `mktime()`, e.g. `{month=14}`
+// is updated to the February of the next year, same with the official Lua.
+fn time(state: &mut ExeState) -> i32 {
+    let t = if arg_none(state, 1) {
+        now()
+    } else {
+        let table = state.get::<&Value>(1).clone();
+        let Value::Table(_) = table else {
+            panic!("bad argument #1 to 'time' (table expected, got {})", table.type_name());
+        };
+
+        let mut tm = Tm::new();
+        tm.tm_year = get_field(&table, "year", None, 1900);
+        tm.tm_mon = get_field(&table, "month", None, 1);
+        tm.tm_mday = get_field(&table, "day", None, 0);
+        tm.tm_hour = get_field(&table, "hour", Some(12), 0);
+        tm.tm_min = get_field(&table, "min", Some(0), 0);
+        tm.tm_sec = get_field(&table, "sec", Some(0), 0);
+        tm.tm_isdst = match table.index(&"isdst".into()) {
+            Value::Nil => -1, // let C library decide
+            v => bool::from(&v) as c_int,
+        };
+
+        let t = unsafe { mktime(&mut tm) };
+        if t == -1 {
+            panic!("time result cannot be represented in this installation");
+        }
+        set_fields(&table, &tm);
+        t
+    };
+    #[allow(clippy::useless_conversion)] // TimeT is i32 on 32-bit
+    state.push(Value::Integer(i64::from(t)));
+    1
+}
+
+// integer field of the date table, minus @delta to fit `struct tm`
+fn get_field(table: &Value, key: &str, default: Option<c_int>, delta: i64) -> c_int {
+    let n = match table.index(&key.into()) {
+        Value::Nil => match default {
+            Some(d) => return d,
+            None => panic!("field '{key}' missing in date table"),
+        }
+        v => i64::try_from(v).unwrap_or_else(|_| panic!("field '{key}' is not an integer")),
+    };
+    n.checked_sub(delta)
+        .and_then(|n| c_int::try_from(n).ok())
+        .unwrap_or_else(|| panic!("field '{key}' is out-of-bound"))
+}
+
+fn set_fields(table: &Value, tm: &Tm) {
+    for (key, n) in [
+        ("year", i64::from(tm.tm_year) + 1900),
+        ("month", i64::from(tm.tm_mon) + 1),
+        ("day", i64::from(tm.tm_mday)),
+        ("hour", i64::from(tm.tm_hour)),
+        ("min", i64::from(tm.tm_min)),
+        ("sec", i64::from(tm.tm_sec)),
+        ("yday", i64::from(tm.tm_yday) + 1),
+        ("wday", i64::from(tm.tm_wday) + 1),
+    ] {
+        table.new_index(key.into(), Value::Integer(n));
+    }
+    if tm.tm_isdst >= 0 {
+        table.new_index("isdst".into(), Value::Boolean(tm.tm_isdst != 0));
+    }
+}
+
+// os.date([format [, time]])
+//
+// A leading `!` in @format means UTC, otherwise local time. "*t" returns
+// a date table which can be converted back by `os.time()`, and other
+// formats are passed to `strftime()`.
+fn date(state: &mut ExeState) -> i32 {
+    let format = if arg_none(state, 1) {
+        b"%c".to_vec()
+    } else {
+        let v = state.get::<&Value>(1);
+        match v.as_bytes() {
+            Some(s) => s.to_vec(),
+            None => panic!("bad argument #1 to 'date' (string expected, got {})", v.type_name()),
+        }
+    };
+    let t = if arg_none(state, 2) { now() } else { to_time_t(state.get::<i64>(2), 2, "date") };
+
+    let (utc, format) = match format.strip_prefix(b"!") {
+        Some(f) => (true, f),
+        None => (false, &format[..]),
+    };
+    let mut tm = Tm::new();
+    let res = unsafe {
+        if utc { gmtime_r(&t, &mut tm) } else { localtime_r(&t, &mut tm) }
+    };
+    if res.is_null() {
+        panic!("date result cannot be represented in this installation");
+    }
+
+    if format.starts_with(b"*t") {
+        let table = Value::from(Table::new(0, 9));
+        set_fields(&table, &tm);
+        state.push(table);
+    } else {
+        state.push(format_tm(format, &tm));
+    }
+    1
+}
+
+// os.difftime(t2, t1)
+//
+// Return the seconds from @t1 to @t2 as a float.
+fn difftime(state: &mut ExeState) -> i32 {
+    let t2 = check_time(state, 1, "difftime");
+    let t1 = check_time(state, 2, "difftime");
+    state.push(t2 as f64 - t1 as f64);
+    1
+}
+
+fn check_time(state: &ExeState, iarg: usize, fname: &str) -> TimeT {
+    let v = if state.get_top() < iarg { &Value::Nil } else { state.get::<&Value>(iarg) };
+    match v.to_number() {
+        Some(n) => to_time_t(i64::try_from(n).unwrap_or_else(|_|
+            panic!("bad argument #{iarg} to '{fname}' (number has no integer representation)")), iarg, fname),
+        None => panic!("bad argument #{iarg} to '{fname}' (number expected, got {})", v.type_name()),
+    }
+}
+
+// os.clock()
+//
+// Return the CPU time of the program in seconds, e.g. for benchmarking.
+fn lib_clock(state: &mut ExeState) -> i32 {
+    let c = unsafe { clock() };
+    state.push(c as f64 / CLOCKS_PER_SEC);
+    1
+}
+
+// os.getenv(name)
+//
+// Return the environment variable, or nil if not defined.
+fn getenv(state: &mut ExeState) -> i32 {
+    let name = check_string(state, 1, "getenv");
+    let v = var(state, &name);
+    state.push(v);
+    1
+}
+
+// the variable written by `os.env`, or of the process environment
+fn var(state: &ExeState, name: &str) -> Value {
+    match state.env_vars().get(name) {
+        Some(Some(v)) => Value::from(v.as_str()),
+        Some(None) => Value::Nil,
+        None => env::var_os(name).map_or(Value::Nil, |v| Value::from(v.as_bytes())),
+    }
+}
+
+fn check_string(state: &ExeState, iarg: usize, fname: &str) -> String {
+    let v = if state.get_top() < iarg { &Value::Nil } else { state.get::<&Value>(iarg) };
+    match v.as_str() {
+        Some(s) => s.to_string(),
+        None => panic!("bad argument #{iarg} to '{fname}' (string expected, got {})", v.type_name()),
+    }
+}
+
+// os.env
+//
+// Proxy of the process environment, where `os.env.HOME` is same with
+// `os.getenv("HOME")`. The table keeps empty, and its metamethods read
+// and write the environment. It's read-only by default, and assignments
+// raise errors, unless @writable is set by
+// `ExeStateBuilder::writable_env(true)`. Then assigning nil removes the
+// variable. The changes are kept in the state, and seen by `os.getenv()`
+// and the commands of `os.execute()` and `io.popen()`, but not by the
+// process, whose environment is not thread-safe to write, e.g. `TZ` of
+// `os.date()` keeps the same.
+pub fn new_env(writable: bool) -> Value {
+    let mut mt = Table::new(0, 2);
+    mt.map.insert("__index".into(), Value::RustFunction(env_index));
+    mt.map.insert("__newindex".into(), Value::RustFunction(
+        if writable { env_newindex } else { env_readonly }));
+    let Value::Table(mt) = Value::from(mt) else {
+        unreachable!();
+    };
+    let mut t = Table::new(0, 0);
+    t.metatable = Some(mt);
+    Value::from(t)
+}
+
+// __index(t, name)
+fn env_index(state: &mut ExeState) -> i32 {
+    let v = match state.get::<&Value>(2).as_str() {
+        Some(name) => var(state, name),
+        None => Value::Nil,
+    };
+    state.push(v);
+    1
+}
+
+// __newindex(t, name, value)
+fn env_newindex(state: &mut ExeState) -> i32 {
+    let name = check_string(state, 2, "__newindex");
+    if name.is_empty() || name.contains(['=', '\0']) {
+        panic!("invalid environment variable name '{name}'");
+    }
+    let v = match state.get::<&Value>(3) {
+        Value::Nil => None,
+        v @ (Value::Integer(_) | Value::Float(_)) => Some(v.to_string()),
+        v => match v.as_str() {
+            Some(s) if !s.contains('\0') => Some(s.to_string()),
+            _ => panic!("invalid value for environment variable '{name}' (string expected, got {})",
+                v.type_name()),
+        }
+    };
+    state.set_env_var(name, v);
+    0
+}
+
+fn env_readonly(state: &mut ExeState) -> i32 {
+    panic!("attempt to set environment variable '{}' (os.env is read-only)",
+        state.get::<&Value>(2));
+}
+
+// os.remove(filename)
+//
+// Remove the file or the empty directory. Return true, or nil and the
+// error message and the error number, same with `io.open()`.
+fn remove(state: &mut ExeState) -> i32 {
+    let filename = check_string(state, 1, "remove");
+    let result = match fs::symlink_metadata(&filename) {
+        Ok(m) if m.is_dir() => fs::remove_dir(&filename),
+        _ => fs::remove_file(&filename),
+    };
+    match result {
+        Ok(()) => {
+            state.push(true);
+            1
+        }
+        Err(e) => super::io::push_error(state, &e, Some(&filename)),
+    }
+}
+
+// os.rename(oldname, newname)
+fn rename(state: &mut ExeState) -> i32 {
+    let from = check_string(state, 1, "rename");
+    let to = check_string(state, 2, "rename");
+    match fs::rename(&from, to) {
+        Ok(()) => {
+            state.push(true);
+            1
+        }
+        Err(e) => super::io::push_error(state, &e, Some(&from)),
+    }
+}
+
+// os.execute([command])
+//
+// Run the @command by the shell, and return true or nil for success or
+// not, then "exit" and the exit code, or "signal" and the signal number.
+// Without @command, return whether the shell is available. It's removed
+// by `ExeStateBuilder::allow_subprocess(false)`.
+fn execute(state: &mut ExeState) -> i32 {
+    if arg_none(state, 1) {
+        state.push(std::path::Path::new("/bin/sh").exists());
+        return 1;
+    }
+    let command = check_string(state, 1, "execute");
+    state.flush(); // before the output of the command
+    match super::io::shell(state, &command).status() {
+        Ok(status) => super::io::push_status(state, status),
+        Err(e) => super::io::push_error(state, &e, Some(&command)),
+    }
+}
+
+// os.exit([code])
+//
+// Exit the program with @code, which is true for success by default,
+// false for failure, or the number. The output is flushed before.
+fn exit(state: &mut ExeState) -> i32 {
+    let code = if arg_none(state, 1) {
+        0
+    } else {
+        match state.get::<&Value>(1) {
+            &Value::Boolean(b) => if b { 0 } else { 1 },
+            &Value::Integer(i) => i as i32,
+            v => panic!("bad argument #1 to 'exit' (number expected, got {})", v.type_name()),
+        }
+    };
+    state.flush();
+    process::exit(code);
+}
+
+// conversions of `strftime()` in C99, same with LUA_STRFTIMEOPTIONS of
+// the official Lua, where `E` and `O` are modifiers of the following ones
+const CONVERSIONS: &[u8] = b"aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%";
+const E_CONVERSIONS: &[u8] = b"cCxXyY";
+const O_CONVERSIONS: &[u8] = b"deHImMSuUVwWy";
+
+// Check the conversions, because unknown ones are undefined behavior of
+// `strftime()`.
+fn check_format(format: &[u8]) {
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            i += 1;
+            continue;
+        }
+        let len = match &format[i + 1..] {
+            [b'E', c, ..] if E_CONVERSIONS.contains(c) => 2,
+            [b'O', c, ..] if O_CONVERSIONS.contains(c) => 2,
+            [c, ..] if CONVERSIONS.contains(c) => 1,
+            rest => {
+                // the modifier and the following one, or the single one
+                let n = if matches!(rest, [b'E' | b'O', ..]) { 2 } else { 1 };
+                let spec = &format[i..i + 1 + rest.len().min(n)];
+                panic!("bad argument #1 to 'date' (invalid conversion specifier '{}')",
+                    String::from_utf8_lossy(spec));
+            }
+        };
+        i += 1 + len;
+    }
+}
+
+fn format_tm(format: &[u8], tm: &Tm) -> Vec<u8> {
+    if format.is_empty() {
+        return Vec::new();
+    }
+    check_format(format);
+    let Ok(cformat) = CString::new(format) else {
+        panic!("bad argument #1 to 'date' (invalid conversion specifier)");
+    };
+
+    // strftime() returns 0 if the buffer is too small, so grow it
+    let mut buf = vec![0u8; 256];
+    loop {
+        let n = unsafe {
+            strftime(buf.as_mut_ptr() as *mut c_char, buf.len(), cformat.as_ptr(), tm)
+        };
+        if n > 0 || buf.len() >= format.len() * 256 {
+            buf.truncate(n);
+            return buf;
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+// os.timelimit(f, secs, ...)
+//
+// Call @f with the following arguments like `pcall()`, but it fails if
+// running longer than @secs seconds. Return true and the return values
+// of @f, or false and the error object. Too large @secs, e.g. 1e300,
+// are not limited. It's not in the library by
+// default, but enabled by `ExeState::open_timelimit()`.
+pub fn timelimit(state: &mut ExeState) -> i32 {
+    let secs = match state.get::<&Value>(2).to_number() {
+        Some(Value::Integer(i)) if i >= 0 => i as f64,
+        Some(Value::Float(f)) if f >= 0.0 && f.is_finite() => f,
+        _ => panic!("bad argument #2 to 'timelimit' (non-negative number expected)"),
+    };
+
+    // re-arrange as: true, f, args...
+    let f = state.get::<&Value>(1).clone();
+    let args: Vec<Value> = (3..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect();
+    state.push(true);
+    let ifunc = state.get_top() + 1;
+    state.push(f);
+    for a in args {
+        state.push(a);
+    }
+
+    match state.pcall_limit(ifunc, ExecLimit::Time(Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX))) {
+        Ok(nret) => nret as i32 + 1,
+        Err(e) => {
+            state.push(false);
+            state.push(e.into_value());
+            2
+        }
+    }
+}
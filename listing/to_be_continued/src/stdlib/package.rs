@@ -0,0 +1,141 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs;
+use crate::value::{Value, Table};
+use crate::vm::{ExeState, Event};
+use super::LibFunction;
+
+// default search path of Lua modules, same with the official Lua
+const DEFAULT_PATH: &str = "./?.lua;./?/init.lua";
+
+// The `package` table:
+//   - loaded: modules loaded already, checked first by `require`;
+//   - preload: module loaders set by Lua or by
+//     `ExeState::preload_module()`, searched before `path`;
+//   - path: templates of Lua module files, separated by `;`;
+//   - native: module loaders registered from Rust, by
+//     `ExeState::register_module()`, searched after `path`;
+//   - isolate: if true, each Lua module runs with its own `_ENV`, see
+//     isolated_env(). It's false by default.
+pub fn new_lib() -> Value {
+    let mut lib = Table::new(0, 5);
+    lib.map.insert("loaded".into(), new_table());
+    lib.map.insert("preload".into(), new_table());
+    lib.map.insert("path".into(), DEFAULT_PATH.into());
+    lib.map.insert("native".into(), new_table());
+    lib.map.insert("isolate".into(), false.into());
+    Value::from(lib)
+}
+
+fn new_table() -> Value {
+    Value::from(Table::new(0, 0))
+}
+
+fn package_field(state: &ExeState, field: &str) -> Value {
+    state.env().index(&"package".into()).index(&field.into())
+}
+
+// register a Rust module loader into `package.native`
+pub fn register(state: &mut ExeState, name: &str, loader: LibFunction) {
+    package_field(state, "native").new_index(name.into(), Value::RustFunction(loader));
+}
+
+// register a module loader into `package.preload`
+pub fn preload(state: &mut ExeState, name: &str, loader: Value) {
+    package_field(state, "preload").new_index(name.into(), loader);
+}
+
+// require(modname)
+//
+// Search `package.loaded`, then `package.preload`, files in
+// `package.path`, and then `package.native`. The loader is called with
+// the module name, and its return value (or true if nil) is stored into
+// `package.loaded`.
+pub fn require(state: &mut ExeState) -> i32 {
+    let name = state.get::<&Value>(1).clone();
+    let (Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_)) = name else {
+        panic!("bad argument #1 to 'require' (string expected, got {})", name.type_name());
+    };
+    let loaded = package_field(state, "loaded");
+    let module = loaded.index(&name);
+    if module != Value::Nil {
+        state.push(module);
+        return 1;
+    }
+
+    let Some((loader, args)) = search(state, &name) else {
+        panic!("module '{}' not found:{}", name, not_found_message(state, &name));
+    };
+    let module = state.call(loader, &args).into_iter().next().unwrap_or(Value::Nil);
+
+    // the module may set `package.loaded[name]` by itself
+    let module = match (module, loaded.index(&name)) {
+        (Value::Nil, Value::Nil) => Value::Boolean(true),
+        (Value::Nil, m) => m,
+        (m, _) => m,
+    };
+    loaded.new_index(name, module.clone());
+    state.push(module);
+    1
+}
+
+// `_ENV` for isolated modules. Global variables assigned by the module
+// stay in this table, so they do not pollute other modules. Reading
+// falls back to the global table by `__index`.
+fn isolated_env(state: &ExeState) -> Value {
+    let mut meta = Table::new(0, 1);
+    meta.map.insert("__index".into(), state.env());
+    let mut env = Table::new(0, 0);
+    env.metatable = Some(Rc::new(RefCell::new(meta)));
+    Value::from(env)
+}
+
+// the loader and its arguments
+fn search(state: &mut ExeState, name: &Value) -> Option<(Value, Vec<Value>)> {
+    // ":preload:" is the loader data of the official Lua
+    match package_field(state, "preload").index(name) {
+        Value::Nil => (),
+        loader => return Some((loader, vec![name.clone(), ":preload:".into()])),
+    }
+
+    for filename in search_files(state, name) {
+        if let Ok(chunk) = fs::read(&filename) {
+            // the syntax error has its position already
+            let proto = state.try_load(&chunk, &format!("@{filename}"))
+                .unwrap_or_else(|e| state.raise_error(e.into_value(), 0));
+            state.emit(Event::Load { chunk: &filename });
+            // Lua chunk gets `_ENV` as its only parameter
+            let env = if (&package_field(state, "isolate")).into() {
+                isolated_env(state)
+            } else {
+                state.env()
+            };
+            return Some((Value::LuaFunction(Rc::new(proto)), vec![env]));
+        }
+    }
+
+    match package_field(state, "native").index(name) {
+        Value::Nil => None,
+        loader => Some((loader, vec![name.clone()])),
+    }
+}
+
+// file names by replacing `?` in templates of `package.path`, where
+// `.` in module name is replaced by directory separator
+fn search_files(state: &ExeState, name: &Value) -> Vec<String> {
+    let path = package_field(state, "path").to_string();
+    let name = name.to_string().replace('.', "/");
+    path.split(';')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.replace('?', &name))
+        .collect()
+}
+
+fn not_found_message(state: &ExeState, name: &Value) -> String {
+    let mut msg = format!("\n\tno field package.preload['{name}']");
+    for filename in search_files(state, name) {
+        msg += &format!("\n\tno file '{filename}'");
+    }
+    msg += &format!("\n\tno field package.native['{name}']");
+    msg
+}
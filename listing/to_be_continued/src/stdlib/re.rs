@@ -0,0 +1,345 @@
+use std::rc::Rc;
+use regex::bytes::{Captures, RegexBuilder};
+use crate::utils::start_pos;
+use crate::value::{Value, Table};
+use crate::vm::ExeState;
+
+// Regular expressions, for what Lua patterns lack, e.g. alternation and
+// repeated groups:
+//
+//     local date = re.compile([[(?P<y>\d{4})-(?P<m>\d\d)-(?P<d>\d\d)]])
+//     local caps = date:match("on 2024-02-29")
+//     print(caps[0], caps.y, caps[2])  --> 2024-02-29  2024  02
+//     print(re.replace("(cat|dog)s?", "cats and dogs", "<$1>"))
+//         --> <cat> and <dog>  2
+//
+// The regexes are of the regex crate, so is the syntax, see its docs.
+// Matching is in linear time of the subject without backtracking, so
+// it's safe for patterns from users. There are no backreferences or
+// look-around, and the leftmost match wins, where the alternatives are
+// tried in order.
+//
+// The subjects are strings of bytes, matched by `regex::bytes`.
+// Characters are decoded as UTF-8, and invalid bytes are matched only
+// by the escapes of bytes, e.g. `(?-u:\xff)`. Positions are of bytes
+// from 1, same with the string library.
+//
+// Functions take a compiled regex or a pattern, so `re.match(p, s)` is
+// same with `re.compile(p):match(s)`. Invalid patterns raise errors.
+//
+// It's built with the cargo feature "re".
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("compile", compile),
+        ("escape", escape),
+        ("is_match", is_match),
+        ("find", find),
+        ("match", lib_match),
+        ("find_all", find_all),
+        ("gmatch", gmatch),
+        ("replace", replace),
+        ("split", split),
+    ])
+}
+
+pub(crate) fn register(state: &mut ExeState) {
+    state.register_userdata::<Regex>("regex", &[
+        ("is_match", is_match),
+        ("find", find),
+        ("match", lib_match),
+        ("find_all", find_all),
+        ("gmatch", gmatch),
+        ("replace", replace),
+        ("split", split),
+    ]);
+}
+
+// the userdata of compiled regex
+pub struct Regex(Rc<regex::bytes::Regex>);
+
+fn build(pattern: &str, builder: &mut RegexBuilder) -> Result<regex::bytes::Regex, String> {
+    builder.build().map_err(|e| match e {
+        // the last line of the multi-line message, e.g. "error: unclosed group"
+        regex::Error::Syntax(msg) => {
+            let msg = msg.lines().last().unwrap_or_default();
+            format!("{} in '{pattern}'", msg.strip_prefix("error: ").unwrap_or(msg))
+        }
+        e => e.to_string(),
+    })
+}
+
+// the regex of the first argument, which is compiled or a pattern
+fn arg_regex(state: &ExeState, fname: &str) -> Rc<regex::bytes::Regex> {
+    if state.get_top() >= 1 {
+        if let Some(re) = state.get_userdata::<Regex>(1) {
+            return re.0.clone();
+        }
+    }
+    let pattern: String = state.check(1, fname);
+    build(&pattern, &mut RegexBuilder::new(&pattern))
+        .map(Rc::new)
+        .unwrap_or_else(|e| panic!("bad argument #1 to '{fname}' (invalid regex: {e})"))
+}
+
+fn arg_subject(state: &ExeState, fname: &str) -> Vec<u8> {
+    state.check(2, fname)
+}
+
+// the 0-based start position from the optional argument @iarg
+fn arg_init(state: &ExeState, iarg: usize, fname: &str, len: usize) -> Option<usize> {
+    let init = state.check::<Option<i64>>(iarg, fname).unwrap_or(1);
+    let pos = start_pos(init, len) - 1;
+    (pos <= len).then_some(pos)
+}
+
+// The captures as a table: the whole match at 0, groups from 1, and the
+// named groups by names too. Groups not matched are nil.
+fn captures_table(re: &regex::bytes::Regex, caps: &Captures) -> Value {
+    let names = re.capture_names().flatten();
+    let t = Value::from(Table::new(caps.len() - 1, names.clone().count() + 1));
+    for (i, m) in caps.iter().enumerate() {
+        if let Some(m) = m {
+            t.new_index(Value::Integer(i as i64), Value::from(m.as_bytes()));
+        }
+    }
+    for name in names {
+        if let Some(m) = caps.name(name) {
+            t.new_index(name.into(), Value::from(m.as_bytes()));
+        }
+    }
+    t
+}
+
+// The next match from @start, and move @start after it. An empty match
+// right after the previous match ending at @last_end is skipped, same
+// with the iterators of the regex crate, e.g. `a*` matches "ab" at 1-1
+// and 2-2 then.
+fn next_match<'s>(re: &regex::bytes::Regex, s: &'s [u8], start: &mut usize,
+        last_end: &mut Option<usize>) -> Option<Captures<'s>> {
+    while *start <= s.len() {
+        let caps = re.captures_at(s, *start)?;
+        let m = caps.get(0).unwrap();
+        if m.is_empty() && Some(m.end()) == *last_end {
+            if m.start() == s.len() {
+                return None;
+            }
+            // skip a character, or an invalid byte
+            let chunk = s[m.start()..].utf8_chunks().next().unwrap();
+            *start = m.start() + chunk.valid().chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+        *start = m.end();
+        *last_end = Some(m.end());
+        return Some(caps);
+    }
+    None
+}
+
+// call @f with all matches, until it returns false
+fn for_matches<'s>(re: &regex::bytes::Regex, s: &'s [u8], mut f: impl FnMut(Captures<'s>) -> bool) {
+    let (mut start, mut last_end) = (0, None);
+    while let Some(caps) = next_match(re, s, &mut start, &mut last_end) {
+        if !f(caps) {
+            break;
+        }
+    }
+}
+
+// re.compile(pattern [, flags])
+//
+// Compile the @pattern, where @flags are the letters of the flags, e.g.
+// "i" for case-insensitive, same with `(?i)` at the beginning.
+fn compile(state: &mut ExeState) -> i32 {
+    let pattern: String = state.check(1, "compile");
+    let mut builder = RegexBuilder::new(&pattern);
+    for c in state.check::<Option<String>>(2, "compile").unwrap_or_default().chars() {
+        match c {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            _ => panic!("bad argument #2 to 'compile' (invalid flag '{c}')"),
+        };
+    }
+    let re = build(&pattern, &mut builder)
+        .unwrap_or_else(|e| panic!("bad argument #1 to 'compile' (invalid regex: {e})"));
+    let re = state.create_userdata(Regex(Rc::new(re)));
+    state.push(re);
+    1
+}
+
+// re.escape(s)
+//
+// Return @s with the special characters escaped, to match it literally.
+fn escape(state: &mut ExeState) -> i32 {
+    let s: String = state.check(1, "escape");
+    state.push(regex::escape(&s));
+    1
+}
+
+// re.is_match(re, s)
+fn is_match(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "is_match");
+    let s = arg_subject(state, "is_match");
+    state.push(re.is_match(&s));
+    1
+}
+
+// re.find(re, s [, init])
+//
+// Return the start and end positions of the first match from @init, or
+// nil, same with `string.find()`.
+fn find(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "find");
+    let s = arg_subject(state, "find");
+    match arg_init(state, 3, "find", s.len()).and_then(|init| re.find_at(&s, init)) {
+        Some(m) => {
+            state.push(m.start() + 1);
+            state.push(m.end());
+            2
+        }
+        None => {
+            state.push(());
+            1
+        }
+    }
+}
+
+// re.match(re, s [, init])
+//
+// Return the captures of the first match from @init as a table, see
+// captures_table(), or nil.
+fn lib_match(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "match");
+    let s = arg_subject(state, "match");
+    let caps = match arg_init(state, 3, "match", s.len()).and_then(|init| re.captures_at(&s, init)) {
+        Some(caps) => captures_table(&re, &caps),
+        None => Value::Nil,
+    };
+    state.push(caps);
+    1
+}
+
+// re.find_all(re, s [, limit])
+//
+// Return the list of the captures tables of all matches, or the first
+// @limit ones.
+fn find_all(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "find_all");
+    let s = arg_subject(state, "find_all");
+    let limit = state.check::<Option<usize>>(3, "find_all").unwrap_or(usize::MAX);
+    let mut list = Vec::new();
+    for_matches(&re, &s, |caps| {
+        list.push(captures_table(&re, &caps));
+        list.len() < limit
+    });
+    let mut t = Table::new(list.len(), 0);
+    t.array = list;
+    state.push(Value::from(t));
+    1
+}
+
+// re.gmatch(re, s)
+//
+// Return the iterator of the captures tables of all matches, for the
+// generic `for`.
+fn gmatch(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "gmatch");
+    let s = arg_subject(state, "gmatch");
+    let (mut start, mut last_end) = (0, None);
+    let iter = state.create_function(move |state| {
+        match next_match(&re, &s, &mut start, &mut last_end) {
+            Some(caps) => {
+                state.push(captures_table(&re, &caps));
+                1
+            }
+            None => 0,
+        }
+    });
+    state.push(iter);
+    1
+}
+
+// re.replace(re, s, repl [, n])
+//
+// Return a copy of @s with the first @n (all by default) matches
+// replaced by @repl, and the number of the replacements. The @repl is a
+// string, where `$1` or `${1}` is the group 1, `$name` or `${name}` is a
+// named group, `$0` is the whole match and `$$` is `$`. Or it's a
+// function called with the captures table, or a table indexed by the
+// whole match, and the match is kept if they give nil or false.
+fn replace(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "replace");
+    let s = arg_subject(state, "replace");
+    let repl = state.check::<Value>(3, "replace");
+    if !(repl.is_function() || matches!(repl, Value::Table(_)) || repl.as_bytes().is_some()) {
+        panic!("bad argument #3 to 'replace' (string/function/table expected, got {})", repl.type_name());
+    }
+    let limit = state.check::<Option<usize>>(4, "replace").unwrap_or(usize::MAX);
+
+    let mut all = Vec::new();
+    if limit > 0 {
+        for_matches(&re, &s, |caps| {
+            all.push(caps);
+            all.len() < limit
+        });
+    }
+
+    let mut buf = Vec::with_capacity(s.len());
+    let mut last = 0;
+    for caps in &all {
+        let (start, end) = caps.get(0).map(|m| (m.start(), m.end())).unwrap();
+        buf.extend_from_slice(&s[last..start]);
+        last = end;
+        if let Some(r) = repl.as_bytes() {
+            caps.expand(r, &mut buf);
+            continue;
+        }
+        let v = match &repl {
+            Value::Table(_) => repl.index(&Value::from(&s[start..end])),
+            f => {
+                let caps = captures_table(&re, caps);
+                state.call(f.clone(), &[caps]).into_iter().next().unwrap_or(Value::Nil)
+            }
+        };
+        match v {
+            Value::Nil | Value::Boolean(false) => buf.extend_from_slice(&s[start..end]),
+            Value::Integer(_) | Value::Float(_) => buf.extend_from_slice(v.to_string().as_bytes()),
+            v => match v.as_bytes() {
+                Some(r) => buf.extend_from_slice(r),
+                None => panic!("invalid replacement value (a {})", v.type_name()),
+            }
+        }
+    }
+    buf.extend_from_slice(&s[last..]);
+    state.push(buf);
+    state.push(all.len());
+    2
+}
+
+// re.split(re, s [, limit])
+//
+// Return the list of the substrings of @s separated by the matches, at
+// most @limit ones, where the last one is the rest.
+fn split(state: &mut ExeState) -> i32 {
+    let re = arg_regex(state, "split");
+    let s = arg_subject(state, "split");
+    let limit = state.check::<Option<usize>>(3, "split").unwrap_or(usize::MAX);
+
+    let mut pieces = Vec::new();
+    let mut last = 0;
+    if limit > 1 {
+        for_matches(&re, &s, |caps| {
+            let (start, end) = caps.get(0).map(|m| (m.start(), m.end())).unwrap();
+            pieces.push(Value::from(&s[last..start]));
+            last = end;
+            pieces.len() + 1 < limit
+        });
+    }
+    if limit > 0 {
+        pieces.push(Value::from(&s[last..]));
+    }
+    let mut t = Table::new(pieces.len(), 0);
+    t.array = pieces;
+    state.push(Value::from(t));
+    1
+}
@@ -0,0 +1,508 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::rc::Rc;
+use crate::value::{Value, Table, UserData};
+use crate::vm::ExeState;
+use super::bytes::Bytes;
+
+// SQLite databases, by the system library libsqlite3:
+//
+//     local db = sqlite.open("app.db")
+//     db:exec("CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, name TEXT)")
+//     db:prepare("INSERT INTO users (name) VALUES (?)"):bind("alice"):step()
+//     for row in db:rows("SELECT * FROM users WHERE id > ?", 0) do
+//         print(row.id, row.name)
+//     end
+//
+// It's also the example of the userdata of Rust types with methods, see
+// ExeState::register_userdata(). The database and statement handles are
+// closed by Drop, when the userdata is freed, so the scripts need not
+// close them explicitly. A statement keeps its database open, by
+// `sqlite3_close_v2()`.
+//
+// Values are mapped as: nil and NULL, integers and INTEGER, floats and
+// REAL, strings and TEXT, and bytes buffers to BLOB, which is read back
+// as strings. Booleans are bound as 1 and 0.
+//
+// Failures of opening and preparing return nil and the message, same
+// with `io.open()`, while failures of running statements raise errors.
+//
+// It's built with the cargo feature "sqlite".
+
+#[repr(C)]
+struct Sqlite3 {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Sqlite3Stmt {
+    _private: [u8; 0],
+}
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(filename: *const c_char, db: *mut *mut Sqlite3, flags: c_int,
+        vfs: *const c_char) -> c_int;
+    fn sqlite3_close_v2(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+    fn sqlite3_exec(db: *mut Sqlite3, sql: *const c_char, callback: *const c_void,
+        arg: *mut c_void, errmsg: *mut *mut c_char) -> c_int;
+    fn sqlite3_changes(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_last_insert_rowid(db: *mut Sqlite3) -> i64;
+
+    fn sqlite3_prepare_v2(db: *mut Sqlite3, sql: *const c_char, nbyte: c_int,
+        stmt: *mut *mut Sqlite3Stmt, tail: *mut *const c_char) -> c_int;
+    fn sqlite3_finalize(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_reset(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_clear_bindings(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_step(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_db_handle(stmt: *mut Sqlite3Stmt) -> *mut Sqlite3;
+
+    fn sqlite3_bind_parameter_count(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_bind_parameter_index(stmt: *mut Sqlite3Stmt, name: *const c_char) -> c_int;
+    fn sqlite3_bind_null(stmt: *mut Sqlite3Stmt, i: c_int) -> c_int;
+    fn sqlite3_bind_int64(stmt: *mut Sqlite3Stmt, i: c_int, v: i64) -> c_int;
+    fn sqlite3_bind_double(stmt: *mut Sqlite3Stmt, i: c_int, v: f64) -> c_int;
+    fn sqlite3_bind_text(stmt: *mut Sqlite3Stmt, i: c_int, v: *const c_char, n: c_int,
+        destructor: isize) -> c_int;
+    fn sqlite3_bind_blob(stmt: *mut Sqlite3Stmt, i: c_int, v: *const c_void, n: c_int,
+        destructor: isize) -> c_int;
+
+    fn sqlite3_column_count(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_column_name(stmt: *mut Sqlite3Stmt, i: c_int) -> *const c_char;
+    fn sqlite3_column_type(stmt: *mut Sqlite3Stmt, i: c_int) -> c_int;
+    fn sqlite3_column_int64(stmt: *mut Sqlite3Stmt, i: c_int) -> i64;
+    fn sqlite3_column_double(stmt: *mut Sqlite3Stmt, i: c_int) -> f64;
+    fn sqlite3_column_blob(stmt: *mut Sqlite3Stmt, i: c_int) -> *const c_void;
+    fn sqlite3_column_bytes(stmt: *mut Sqlite3Stmt, i: c_int) -> c_int;
+}
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+
+const SQLITE_OPEN_READONLY: c_int = 0x1;
+const SQLITE_OPEN_READWRITE: c_int = 0x2;
+const SQLITE_OPEN_CREATE: c_int = 0x4;
+const SQLITE_OPEN_URI: c_int = 0x40;
+
+const SQLITE_INTEGER: c_int = 1;
+const SQLITE_FLOAT: c_int = 2;
+const SQLITE_TEXT: c_int = 3;
+const SQLITE_BLOB: c_int = 4;
+
+// let SQLite copy the bound text and blobs
+const SQLITE_TRANSIENT: isize = -1;
+
+pub struct Database {
+    db: *mut Sqlite3, // null after closed
+}
+
+pub struct Statement {
+    stmt: *mut Sqlite3Stmt, // null after finalized
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // the statements not finalized yet keep it open
+        unsafe { sqlite3_close_v2(self.db) };
+    }
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.stmt) };
+    }
+}
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("open", open),
+    ])
+}
+
+pub(crate) fn register(state: &mut ExeState) {
+    state.register_userdata::<Database>("sqlite.db", &[
+        ("exec", db_exec),
+        ("prepare", db_prepare),
+        ("rows", db_rows),
+        ("changes", db_changes),
+        ("last_insert_rowid", db_last_insert_rowid),
+        ("close", db_close),
+    ]);
+    state.register_userdata::<Statement>("sqlite.stmt", &[
+        ("bind", stmt_bind),
+        ("step", stmt_step),
+        ("row", stmt_row),
+        ("columns", stmt_columns),
+        ("rows", stmt_rows),
+        ("reset", stmt_reset),
+        ("finalize", stmt_finalize),
+    ]);
+}
+
+fn errmsg(db: *mut Sqlite3) -> String {
+    unsafe { CStr::from_ptr(sqlite3_errmsg(db)) }.to_string_lossy().into_owned()
+}
+
+fn check_db(state: &ExeState, fname: &str) -> *mut Sqlite3 {
+    let db = state.check_userdata::<Database>(1, fname).db;
+    if db.is_null() {
+        panic!("attempt to use a closed database");
+    }
+    db
+}
+
+fn check_stmt(state: &ExeState, fname: &str) -> *mut Sqlite3Stmt {
+    let stmt = state.check_userdata::<Statement>(1, fname).stmt;
+    if stmt.is_null() {
+        panic!("attempt to use a finalized statement");
+    }
+    stmt
+}
+
+fn check_sql(state: &ExeState, iarg: usize, fname: &str) -> CString {
+    let sql: Vec<u8> = state.check(iarg, fname);
+    CString::new(sql).unwrap_or_else(|_| panic!("bad argument #{iarg} to '{fname}' (SQL contains zeros)"))
+}
+
+fn push_failure(state: &mut ExeState, msg: String) -> i32 {
+    state.push(());
+    state.push(msg);
+    2
+}
+
+// sqlite.open(filename [, mode])
+//
+// Open the database file, where @mode is "rwc" to read, write and create
+// it by default, "rw" to not create, or "r" for read-only. The
+// @filename ":memory:" is for the in-memory database, and "file:" ones
+// are URIs. Return the database, or nil and the message.
+fn open(state: &mut ExeState) -> i32 {
+    let filename = check_sql(state, 1, "open");
+    let flags = match state.check::<Option<String>>(2, "open").as_deref() {
+        None | Some("rwc") => SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE,
+        Some("rw") => SQLITE_OPEN_READWRITE,
+        Some("r") => SQLITE_OPEN_READONLY,
+        Some(mode) => panic!("bad argument #2 to 'open' (invalid mode '{mode}')"),
+    };
+
+    let mut db = ptr::null_mut();
+    let code = unsafe {
+        sqlite3_open_v2(filename.as_ptr(), &mut db, flags | SQLITE_OPEN_URI, ptr::null())
+    };
+    // the handle is allocated even on failures, except out of memory
+    let db = Database { db };
+    if code != SQLITE_OK {
+        let msg = if db.db.is_null() { "out of memory".into() } else { errmsg(db.db) };
+        return push_failure(state, format!("{}: {msg}", filename.to_string_lossy()));
+    }
+    let db = state.create_userdata(db);
+    state.push(db);
+    1
+}
+
+// db:exec(sql)
+//
+// Run the SQL statements, which are separated by semicolons and have no
+// parameters, e.g. for schemas. The results are dropped.
+fn db_exec(state: &mut ExeState) -> i32 {
+    let db = check_db(state, "exec");
+    let sql = check_sql(state, 2, "exec");
+    let code = unsafe {
+        sqlite3_exec(db, sql.as_ptr(), ptr::null(), ptr::null_mut(), ptr::null_mut())
+    };
+    if code != SQLITE_OK {
+        panic!("{}", errmsg(db));
+    }
+    0
+}
+
+// db:prepare(sql)
+//
+// Compile the first SQL statement into a statement, which is run by
+// `stmt:step()` or `stmt:rows()`. Return nil and the message for
+// invalid SQL.
+fn db_prepare(state: &mut ExeState) -> i32 {
+    let db = check_db(state, "prepare");
+    let sql = check_sql(state, 2, "prepare");
+    match prepare(db, &sql) {
+        Ok(stmt) => {
+            let stmt = state.create_userdata(stmt);
+            state.push(stmt);
+            1
+        }
+        Err(msg) => push_failure(state, msg),
+    }
+}
+
+fn prepare(db: *mut Sqlite3, sql: &CStr) -> Result<Statement, String> {
+    let mut stmt = ptr::null_mut();
+    let code = unsafe {
+        sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut())
+    };
+    if code != SQLITE_OK {
+        return Err(errmsg(db));
+    }
+    if stmt.is_null() {
+        return Err("no SQL statement".into());
+    }
+    Ok(Statement { stmt })
+}
+
+// db:rows(sql, ...)
+//
+// Prepare the @sql, bind the following arguments, and return the
+// iterator of the rows, same with `stmt:rows()`. Invalid SQL raises
+// the error.
+fn db_rows(state: &mut ExeState) -> i32 {
+    let db = check_db(state, "rows");
+    let sql = check_sql(state, 2, "rows");
+    let stmt = prepare(db, &sql).unwrap_or_else(|msg| panic!("{msg}"));
+    bind_args(state, stmt.stmt, 3, "rows");
+    let Value::UserData(stmt) = state.create_userdata(stmt) else {
+        unreachable!();
+    };
+    let iter = new_rows_iterator(state, stmt);
+    state.push(iter);
+    1
+}
+
+// db:changes()
+//
+// Return the number of rows changed by the last INSERT, UPDATE or
+// DELETE.
+fn db_changes(state: &mut ExeState) -> i32 {
+    let db = check_db(state, "changes");
+    let n = unsafe { sqlite3_changes(db) };
+    state.push(Value::Integer(n.into()));
+    1
+}
+
+// db:last_insert_rowid()
+fn db_last_insert_rowid(state: &mut ExeState) -> i32 {
+    let db = check_db(state, "last_insert_rowid");
+    let rowid = unsafe { sqlite3_last_insert_rowid(db) };
+    state.push(Value::Integer(rowid));
+    1
+}
+
+// db:close()
+//
+// Close the database, which is done when it's freed otherwise. It's
+// closed after all its statements are finalized.
+fn db_close(state: &mut ExeState) -> i32 {
+    let mut db = state.check_userdata::<Database>(1, "close");
+    unsafe { sqlite3_close_v2(db.db) };
+    db.db = ptr::null_mut();
+    0
+}
+
+// bind the arguments from @iarg to the parameters of the statement,
+// which is a table for named parameters
+fn bind_args(state: &ExeState, stmt: *mut Sqlite3Stmt, iarg: usize, fname: &str) {
+    if let (true, Value::Table(t)) = (state.get_top() == iarg, state.check::<Value>(iarg, fname)) {
+        for (name, v) in t.borrow().map.iter() {
+            let Some(name) = name.as_str() else {
+                continue;
+            };
+            let i = [":", "@", "$"].iter()
+                .map(|prefix| {
+                    let cname = CString::new(format!("{prefix}{name}")).unwrap_or_default();
+                    unsafe { sqlite3_bind_parameter_index(stmt, cname.as_ptr()) }
+                })
+                .find(|&i| i > 0)
+                .unwrap_or_else(|| panic!("bad argument #{iarg} to '{fname}' (no parameter '{name}')"));
+            bind(stmt, i, v);
+        }
+        return;
+    }
+
+    let count = unsafe { sqlite3_bind_parameter_count(stmt) } as usize;
+    let nargs = (state.get_top() + 1).saturating_sub(iarg);
+    if nargs > count {
+        panic!("bad argument #{} to '{fname}' (only {count} parameters)", iarg + count);
+    }
+    for i in 0..nargs {
+        bind(stmt, i as c_int + 1, state.get::<&Value>(iarg + i));
+    }
+}
+
+fn bind(stmt: *mut Sqlite3Stmt, i: c_int, v: &Value) {
+    let code = unsafe {
+        match v {
+            Value::Nil => sqlite3_bind_null(stmt, i),
+            &Value::Boolean(b) => sqlite3_bind_int64(stmt, i, b.into()),
+            &Value::Integer(n) => sqlite3_bind_int64(stmt, i, n),
+            &Value::Float(f) => sqlite3_bind_double(stmt, i, f),
+            Value::UserData(u) => match u.borrow().downcast_ref::<Bytes>() {
+                Some(b) => sqlite3_bind_blob(stmt, i, b.0.as_ptr().cast(), b.0.len() as c_int,
+                    SQLITE_TRANSIENT),
+                None => panic!("cannot bind a userdata value"),
+            },
+            v => match v.as_bytes() {
+                Some(s) => sqlite3_bind_text(stmt, i, s.as_ptr().cast(), s.len() as c_int,
+                    SQLITE_TRANSIENT),
+                None => panic!("cannot bind a {} value", v.type_name()),
+            }
+        }
+    };
+    if code != SQLITE_OK {
+        panic!("{}", errmsg(unsafe { sqlite3_db_handle(stmt) }));
+    }
+}
+
+// stmt:bind(...), stmt:bind(params)
+//
+// Bind the arguments to the parameters `?` in order, or the fields of
+// the table @params to the named ones, e.g. `{id = 1}` for `:id`, `@id`
+// or `$id`. Return the statement.
+fn stmt_bind(state: &mut ExeState) -> i32 {
+    let stmt = check_stmt(state, "bind");
+    if state.get_top() >= 2 {
+        bind_args(state, stmt, 2, "bind");
+    }
+    state.set_top(1);
+    1
+}
+
+// run one step, and return whether there is a row
+fn step(stmt: *mut Sqlite3Stmt) -> Result<bool, String> {
+    match unsafe { sqlite3_step(stmt) } {
+        SQLITE_ROW => Ok(true),
+        SQLITE_DONE => Ok(false),
+        _ => Err(errmsg(unsafe { sqlite3_db_handle(stmt) })),
+    }
+}
+
+// stmt:step()
+//
+// Run the statement to the next row. Return true if there is a row,
+// which is read by `stmt:row()`, or false if it's done.
+fn stmt_step(state: &mut ExeState) -> i32 {
+    let stmt = check_stmt(state, "step");
+    let row = step(stmt).unwrap_or_else(|msg| panic!("{msg}"));
+    state.push(row);
+    1
+}
+
+fn column_name(stmt: *mut Sqlite3Stmt, i: c_int) -> Value {
+    let name = unsafe { CStr::from_ptr(sqlite3_column_name(stmt, i)) };
+    Value::from(name.to_bytes())
+}
+
+fn column_value(stmt: *mut Sqlite3Stmt, i: c_int) -> Value {
+    unsafe {
+        match sqlite3_column_type(stmt, i) {
+            SQLITE_INTEGER => Value::Integer(sqlite3_column_int64(stmt, i)),
+            SQLITE_FLOAT => Value::Float(sqlite3_column_double(stmt, i)),
+            SQLITE_TEXT | SQLITE_BLOB => {
+                let p = sqlite3_column_blob(stmt, i);
+                let n = sqlite3_column_bytes(stmt, i) as usize;
+                if p.is_null() {
+                    Value::from(&b""[..])
+                } else {
+                    Value::from(std::slice::from_raw_parts(p as *const u8, n))
+                }
+            }
+            _ => Value::Nil,
+        }
+    }
+}
+
+// the current row as a table of column names to values
+fn row_table(stmt: *mut Sqlite3Stmt) -> Value {
+    let n = unsafe { sqlite3_column_count(stmt) };
+    let row = Value::from(Table::new(0, n as usize));
+    for i in 0..n {
+        row.new_index(column_name(stmt, i), column_value(stmt, i));
+    }
+    row
+}
+
+// stmt:row()
+//
+// Return the current row as a table of column names to values, where
+// NULL columns are absent.
+fn stmt_row(state: &mut ExeState) -> i32 {
+    let stmt = check_stmt(state, "row");
+    state.push(row_table(stmt));
+    1
+}
+
+// stmt:columns()
+//
+// Return the list of the column names.
+fn stmt_columns(state: &mut ExeState) -> i32 {
+    let stmt = check_stmt(state, "columns");
+    let n = unsafe { sqlite3_column_count(stmt) };
+    let mut columns = Table::new(n as usize, 0);
+    columns.array = (0..n).map(|i| column_name(stmt, i)).collect();
+    state.push(Value::from(columns));
+    1
+}
+
+// stmt:rows()
+//
+// Return the iterator of the rows as tables, same with `stmt:row()`, for
+// the generic `for`. The statement is reset when it's done, so it can
+// be run again.
+fn stmt_rows(state: &mut ExeState) -> i32 {
+    check_stmt(state, "rows");
+    let Value::UserData(stmt) = state.get::<&Value>(1).clone() else {
+        unreachable!();
+    };
+    let iter = new_rows_iterator(state, stmt);
+    state.push(iter);
+    1
+}
+
+fn new_rows_iterator(state: &ExeState, stmt: Rc<RefCell<UserData>>) -> Value {
+    state.create_function(move |state| {
+        let stmt = match stmt.borrow().downcast_ref::<Statement>() {
+            Some(s) if !s.stmt.is_null() => s.stmt,
+            _ => panic!("attempt to use a finalized statement"),
+        };
+        match step(stmt) {
+            Ok(true) => {
+                state.push(row_table(stmt));
+                1
+            }
+            Ok(false) => {
+                unsafe { sqlite3_reset(stmt) };
+                0
+            }
+            Err(msg) => {
+                unsafe { sqlite3_reset(stmt) };
+                panic!("{msg}");
+            }
+        }
+    })
+}
+
+// stmt:reset([clear])
+//
+// Reset the statement to run again, and clear the bound parameters to
+// NULL if @clear is true. Return the statement.
+fn stmt_reset(state: &mut ExeState) -> i32 {
+    let stmt = check_stmt(state, "reset");
+    unsafe {
+        sqlite3_reset(stmt);
+        if state.check::<bool>(2, "reset") {
+            sqlite3_clear_bindings(stmt);
+        }
+    }
+    state.set_top(1);
+    1
+}
+
+// stmt:finalize()
+//
+// Free the statement, which is done when it's freed otherwise.
+fn stmt_finalize(state: &mut ExeState) -> i32 {
+    let mut stmt = state.check_userdata::<Statement>(1, "finalize");
+    unsafe { sqlite3_finalize(stmt.stmt) };
+    stmt.stmt = ptr::null_mut();
+    0
+}
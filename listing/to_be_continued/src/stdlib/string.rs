@@ -0,0 +1,812 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::ctype;
+use crate::dump;
+use crate::patterns::{Capture, Captures, LuaPattern};
+use crate::utils::{ftoi, start_pos, end_pos, memmem};
+use crate::value::{Table, Value};
+use crate::vm::ExeState;
+
+// limit of the string length built by library functions, to avoid
+// allocating unbounded memory by huge arguments
+const MAX_STRING_SIZE: usize = i32::MAX as usize;
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("len", len),
+        ("rep", rep),
+        ("sub", sub),
+        ("byte", byte),
+        ("char", char),
+        ("reverse", reverse),
+        ("find", find),
+        ("match", lua_match),
+        ("gmatch", gmatch),
+        ("gsub", gsub),
+        ("upper", upper),
+        ("lower", lower),
+        ("format", format),
+        ("dump", dump),
+    ])
+}
+
+// Metatable shared by all strings, whose `__index` is the library @lib,
+// so `s:upper()` works, see ExeState::metatable_of().
+pub fn new_metatable(lib: &Value) -> Rc<RefCell<Table>> {
+    let mut mt = Table::new(0, 1);
+    mt.map.insert("__index".into(), lib.clone());
+    let Value::Table(mt) = Value::from(mt) else {
+        unreachable!();
+    };
+    mt
+}
+
+// string.len(s)
+fn len(state: &mut ExeState) -> i32 {
+    let n = arg_bytes(state.get(1)).len();
+    state.push(n as i64);
+    1
+}
+
+// string.rep(s, n [, sep])
+fn rep(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let n: i64 = state.get(2);
+    let sep = if state.get_top() >= 3 && state.get::<&Value>(3) != &Value::Nil {
+        arg_bytes(state.get(3))
+    } else {
+        Vec::new()
+    };
+
+    if n <= 0 {
+        state.push("");
+        return 1;
+    }
+
+    // total = #s * n + #sep * (n - 1)
+    let n = n as usize;
+    let total = s.len().checked_mul(n)
+        .and_then(|l| sep.len().checked_mul(n - 1).and_then(|lsep| l.checked_add(lsep)))
+        .filter(|&total| total <= MAX_STRING_SIZE)
+        .unwrap_or_else(|| panic!("resulting string too large"));
+
+    let mut buf = Vec::with_capacity(total);
+    for i in 0..n {
+        if i > 0 {
+            buf.extend_from_slice(&sep);
+        }
+        buf.extend_from_slice(&s);
+    }
+    state.push(buf);
+    1
+}
+
+// string.sub(s [, i [, j]])
+//
+// Return the substring of @s from @i to @j, where negative positions
+// count from the end, see utils::start_pos().
+fn sub(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let i = arg_int_or(state, 2, 1);
+    let j = arg_int_or(state, 3, -1);
+    let (start, end) = (start_pos(i, s.len()), end_pos(j, s.len()));
+    state.push(if start <= end { &s[start-1..end] } else { &[][..] });
+    1
+}
+
+// string.byte(s [, i [, j]])
+//
+// Return the bytes of @s from @i to @j, where @j is @i by default.
+fn byte(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let i = arg_int_or(state, 2, 1);
+    let j = arg_int_or(state, 3, i);
+    let (start, end) = (start_pos(i, s.len()), end_pos(j, s.len()));
+    if start > end {
+        return 0;
+    }
+    let n = i32::try_from(end - start + 1).unwrap_or_else(|_| panic!("string slice too long"));
+    for &b in &s[start-1..end] {
+        state.push(Value::Integer(b.into()));
+    }
+    n
+}
+
+// string.char(...)
+//
+// Return the string of the bytes of the integer arguments.
+fn char(state: &mut ExeState) -> i32 {
+    let s: Vec<u8> = (1..=state.get_top()).map(|i| {
+        u8::try_from(state.get::<i64>(i)).unwrap_or_else(|_| panic!("bad argument #{i} to 'char' (value out of range)"))
+    }).collect();
+    state.push(s);
+    1
+}
+
+// string.reverse(s)
+fn reverse(state: &mut ExeState) -> i32 {
+    let mut s = arg_bytes(state.get(1));
+    s.reverse();
+    state.push(s);
+    1
+}
+
+// string.upper(s), where bytes >= 0x80 are not changed, see ctype
+fn upper(state: &mut ExeState) -> i32 {
+    let s: Vec<u8> = arg_bytes(state.get(1)).into_iter().map(ctype::to_upper).collect();
+    state.push(s);
+    1
+}
+
+// string.lower(s)
+fn lower(state: &mut ExeState) -> i32 {
+    let s: Vec<u8> = arg_bytes(state.get(1)).into_iter().map(ctype::to_lower).collect();
+    state.push(s);
+    1
+}
+
+// string.find(s, pattern [, init [, plain]])
+//
+// Return the start and end positions of the first @pattern in @s from
+// @init, and the captures, or nil if not found. The search is plain if
+// @plain is true or @pattern has no special characters, which is by
+// memmem() but not the pattern matching.
+fn find(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let pattern = arg_bytes(state.get(2));
+    let init = start_pos(arg_int_or(state, 3, 1), s.len());
+    if init > s.len() + 1 {
+        state.push(Value::Nil);
+        return 1;
+    }
+
+    let plain = state.get_top() >= 4 && state.get::<bool>(4);
+    if plain || !pattern.iter().any(|b| SPECIALS.contains(b)) {
+        return match memmem(&s[init-1..], &pattern) {
+            Some(i) => {
+                let start = init + i;
+                state.push(start as i64);
+                state.push((start + pattern.len() - 1) as i64);
+                2
+            }
+            None => {
+                state.push(Value::Nil);
+                1
+            }
+        };
+    }
+
+    let pattern = arg_pattern(state, 2);
+    match pattern.find_at(&s, init - 1) {
+        Some(caps) => {
+            let range = caps.range();
+            state.push(range.start as i64 + 1);
+            state.push(range.end as i64);
+            let mut n = 2;
+            while let Some(cap) = caps.get(n - 1) {
+                state.push(capture_value(cap));
+                n += 1;
+            }
+            n as i32
+        }
+        None => {
+            state.push(Value::Nil);
+            1
+        }
+    }
+}
+
+// string.match(s, pattern [, init])
+//
+// Return the captures of the first @pattern in @s from @init, or the
+// whole match if there is no capture, or nil if not found.
+fn lua_match(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let pattern = arg_pattern(state, 2);
+    let init = start_pos(arg_int_or(state, 3, 1), s.len());
+    match pattern.find_at(&s, init - 1) {
+        Some(caps) => push_captures(state, &caps),
+        None => {
+            state.push(Value::Nil);
+            1
+        }
+    }
+}
+
+// string.gmatch(s, pattern [, init])
+//
+// Return an iterator function of the matches of @pattern in @s, see
+// LuaPattern::captures_iter(), where '^' is not an anchor.
+fn gmatch(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let pattern = arg_pattern(state, 2);
+    let mut pos = start_pos(arg_int_or(state, 3, 1), s.len()).min(s.len() + 1) - 1;
+    let mut last = None;
+    let c = move |state: &mut ExeState| match pattern.next_match(&s, &mut pos, &mut last) {
+        Some(caps) => push_captures(state, &caps),
+        None => 0,
+    };
+    state.push(Value::RustClosure(Rc::new(RefCell::new(Box::new(c)))));
+    1
+}
+
+// string.gsub(s, pattern, repl [, n])
+//
+// Return a copy of @s with the first @n (all by default) matches of
+// @pattern replaced by @repl, and the number of the matches. The @repl
+// may be a string, where `%0` to `%9` are the captures, or a table indexed
+// by the first capture, or a function called with the captures. The match
+// is kept if the table or function gives false or nil.
+fn gsub(state: &mut ExeState) -> i32 {
+    let s = arg_bytes(state.get(1));
+    let pattern = arg_pattern(state, 2);
+    let repl = state.get::<&Value>(3).clone();
+    match repl {
+        Value::Table(_) | Value::Integer(_) | Value::Float(_) => (),
+        _ if repl.is_function() || repl.as_bytes().is_some() => (),
+        _ => panic!("bad argument #3 to 'gsub' (string/function/table expected, got {})",
+            repl.type_name()),
+    }
+    let max_n = arg_int_or(state, 4, s.len() as i64 + 1);
+
+    let mut buf = Vec::with_capacity(s.len());
+    let (mut src, mut last, mut n) = (0, None, 0);
+    while n < max_n {
+        match pattern.match_at(&s, src) {
+            Some(caps) if Some(caps.range().end) != last => {
+                n += 1;
+                add_value(state, &mut buf, &caps, &repl);
+                src = caps.range().end;
+                last = Some(src);
+            }
+            _ if src < s.len() => {
+                buf.push(s[src]);
+                src += 1;
+            }
+            _ => break,
+        }
+        if pattern.is_anchored() {
+            break;
+        }
+    }
+    buf.extend_from_slice(&s[src..]);
+    state.push(buf);
+    state.push(n);
+    2
+}
+
+// append the replacement of the match @caps by @repl, see gsub()
+fn add_value(state: &mut ExeState, buf: &mut Vec<u8>, caps: &Captures, repl: &Value) {
+    let whole = caps.whole();
+    let v = match repl {
+        Value::Table(_) => {
+            let key = capture_value(caps.values()[0]);
+            state.index_meta(repl.clone(), key)
+        }
+        _ if repl.is_function() => {
+            let args: Vec<Value> = caps.values().into_iter().map(capture_value).collect();
+            state.call(repl.clone(), &args).into_iter().next().unwrap_or(Value::Nil)
+        }
+        _ => {
+            add_string(buf, caps, &arg_bytes(repl));
+            return;
+        }
+    };
+    match v {
+        Value::Nil | Value::Boolean(false) => buf.extend_from_slice(whole),
+        Value::Integer(_) | Value::Float(_) => buf.extend_from_slice(v.to_string().as_bytes()),
+        _ => match v.as_bytes() {
+            Some(b) => buf.extend_from_slice(b),
+            None => panic!("invalid replacement value (a {})", v.type_name()),
+        }
+    }
+}
+
+// append the replacement string @repl, where `%d` is the d-th capture,
+// and `%1` is the whole match if there is no capture, same with `%0`
+fn add_string(buf: &mut Vec<u8>, caps: &Captures, repl: &[u8]) {
+    let mut i = 0;
+    while i < repl.len() {
+        let c = repl[i];
+        i += 1;
+        if c != b'%' {
+            buf.push(c);
+            continue;
+        }
+        match repl.get(i) {
+            Some(b'%') => buf.push(b'%'),
+            Some(&d) if d.is_ascii_digit() => {
+                let d = (d - b'0') as usize;
+                let cap = match d {
+                    0 => caps.get(0),
+                    _ => caps.values().get(d - 1).copied(),
+                };
+                match cap {
+                    Some(Capture::Bytes(b)) => buf.extend_from_slice(b),
+                    Some(Capture::Position(p)) => buf.extend_from_slice((p + 1).to_string().as_bytes()),
+                    None => panic!("invalid capture index %{d} in replacement string"),
+                }
+            }
+            _ => panic!("invalid use of '%' in replacement string"),
+        }
+        i += 1;
+    }
+}
+
+fn arg_pattern(state: &ExeState, iarg: usize) -> LuaPattern {
+    LuaPattern::new(arg_bytes(state.get(iarg))).unwrap_or_else(|e| panic!("{e}"))
+}
+
+// push the captures, or the whole match if there is none
+fn push_captures(state: &mut ExeState, caps: &Captures) -> i32 {
+    let values = caps.values();
+    let n = values.len();
+    for cap in values {
+        state.push(capture_value(cap));
+    }
+    n as i32
+}
+
+// the position capture is from 1 for Lua
+fn capture_value(cap: Capture) -> Value {
+    match cap {
+        Capture::Bytes(b) => b.into(),
+        Capture::Position(i) => Value::Integer(i as i64 + 1),
+    }
+}
+
+// special characters of patterns, same with `SPECIALS` in lstrlib.c
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+// optional integer argument
+fn arg_int_or(state: &ExeState, iarg: usize, default: i64) -> i64 {
+    if state.get_top() >= iarg && state.get::<&Value>(iarg) != &Value::Nil {
+        state.get(iarg)
+    } else {
+        default
+    }
+}
+
+// string.dump(f [, strip])
+//
+// Return the binary chunk of the Lua function @f, see dump::dump().
+// Upvalues are not saved, so they are fresh when the chunk is loaded.
+fn dump(state: &mut ExeState) -> i32 {
+    let strip = state.get_top() >= 2 && state.get::<bool>(2);
+    let chunk = match state.get::<&Value>(1) {
+        Value::LuaFunction(f) => dump::dump(f, strip),
+        Value::LuaClosure(c) => dump::dump(&c.proto, strip),
+        Value::RustFunction(_) | Value::RustClosure(_) => panic!("unable to dump given function"),
+        v => panic!("bad argument #1 to 'dump' (function expected, got {})", v.type_name()),
+    };
+    state.push(chunk);
+    1
+}
+
+// string argument, where numbers are converted to strings
+fn arg_bytes(v: &Value) -> Vec<u8> {
+    match v {
+        Value::Integer(_) | Value::Float(_) => v.to_string().into_bytes(),
+        _ => match v.as_bytes() {
+            Some(s) => s.to_vec(),
+            None => panic!("bad argument (string expected, got {})", v.type_name()),
+        }
+    }
+}
+
+// limit of the length of a conversion specification, including flags,
+// width, precision and the conversion, same with the official Lua
+const MAX_FORMAT_SPEC: usize = 22;
+
+// flags allowed by conversions
+const FLAGS_FLOAT: &[u8] = b"-+ #0";
+const FLAGS_HEX: &[u8] = b"-#0";
+const FLAGS_INT: &[u8] = b"-+ 0";
+const FLAGS_UINT: &[u8] = b"-0";
+const FLAGS_CHAR: &[u8] = b"-";
+
+// conversion specification of string.format, e.g. `%-+8.3f`
+#[derive(Default)]
+struct Spec {
+    left: bool,  // '-'
+    plus: bool,  // '+'
+    space: bool, // ' '
+    alt: bool,   // '#'
+    zero: bool,  // '0'
+    width: usize,
+    precision: Option<usize>,
+}
+
+// string.format(formatstring, ...)
+//
+// Same with the official Lua, which follows C's `sprintf()`: conversions
+// `c d i u o x X a A e E f F g G p s`, with flags `-+ #0`, width and
+// precision of 2 digits at most. Floats are formatted here but not by
+// the C library, while the results are same with glibc, including the
+// rounding, the hexadecimal `%a`, and "inf" and "nan". And `%q` for the
+// Lua literal of the value, see add_literal().
+fn format(state: &mut ExeState) -> i32 {
+    let fmt = arg_bytes(state.get(1));
+    let top = state.get_top();
+    let mut buf = Vec::with_capacity(fmt.len());
+    let mut iarg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        let b = fmt[i];
+        i += 1;
+        if b != b'%' {
+            buf.push(b);
+            continue;
+        }
+        if fmt.get(i) == Some(&b'%') {
+            buf.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        iarg += 1;
+        if iarg > top {
+            panic!("bad argument #{iarg} to 'format' (no value)");
+        }
+        let v = state.get::<&Value>(iarg).clone();
+
+        // flags, width and precision, followed by the conversion
+        let len = fmt[i..].iter().take_while(|b| b"-+ #0123456789.".contains(b)).count() + 1;
+        if len >= MAX_FORMAT_SPEC {
+            panic!("invalid format string to 'format'");
+        }
+        let form = &fmt[i..(i + len).min(fmt.len())];
+        let conv = fmt.get(i + len - 1).copied().unwrap_or(0);
+        i += len;
+
+        match conv {
+            b'c' => {
+                let spec = parse_spec(form, FLAGS_CHAR, false);
+                let c = arg_integer(&v, iarg) as u8;
+                pad(&mut buf, &spec, false, b"", &[c]);
+            }
+            b'd' | b'i' => {
+                let spec = parse_spec(form, FLAGS_INT, true);
+                let n = arg_integer(&v, iarg);
+                let sign = sign_str(n < 0, &spec);
+                format_integer(&mut buf, &spec, sign, n.unsigned_abs().to_string());
+            }
+            b'u' => {
+                let spec = parse_spec(form, FLAGS_UINT, true);
+                let n = arg_integer(&v, iarg) as u64;
+                format_integer(&mut buf, &spec, "", n.to_string());
+            }
+            b'o' | b'x' | b'X' => {
+                let spec = parse_spec(form, FLAGS_HEX, true);
+                let n = arg_integer(&v, iarg) as u64;
+                let (prefix, digits) = match conv {
+                    b'o' => ("", format!("{n:o}")),
+                    b'x' => (if spec.alt && n != 0 { "0x" } else { "" }, format!("{n:x}")),
+                    _ => (if spec.alt && n != 0 { "0X" } else { "" }, format!("{n:X}")),
+                };
+                format_integer(&mut buf, &spec, prefix, digits);
+            }
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let spec = parse_spec(form, FLAGS_FLOAT, true);
+                let x = arg_number(&v, iarg);
+                format_float(&mut buf, &spec, conv, x);
+            }
+            b'p' => {
+                let spec = parse_spec(form, FLAGS_CHAR, false);
+                let p = match &v {
+                    Value::Table(t) => format!("{:p}", Rc::as_ptr(t)),
+                    Value::RustFunction(f) => format!("{:p}", *f as *const ()),
+                    Value::RustClosure(c) => format!("{:p}", Rc::as_ptr(c)),
+                    Value::LuaFunction(f) => format!("{:p}", Rc::as_ptr(f)),
+                    Value::LuaClosure(c) => format!("{:p}", Rc::as_ptr(c)),
+                    Value::Coroutine(c) => format!("{:p}", Rc::as_ptr(c)),
+                    _ => String::from("(null)"),
+                };
+                pad(&mut buf, &spec, false, b"", p.as_bytes());
+            }
+            b's' => {
+                let s = match v.as_bytes() {
+                    Some(s) => s.to_vec(),
+                    None => v.to_string().into_bytes(),
+                };
+                if form.len() == 1 {
+                    // no modifiers
+                    buf.extend_from_slice(&s);
+                    continue;
+                }
+                if s.contains(&0) {
+                    panic!("bad argument #{iarg} to 'format' (string contains zeros)");
+                }
+                let spec = parse_spec(form, FLAGS_CHAR, true);
+                let s = match spec.precision {
+                    Some(p) if p < s.len() => &s[..p],
+                    _ => &s[..],
+                };
+                pad(&mut buf, &spec, false, b"", s);
+            }
+            b'q' => {
+                if form.len() > 1 {
+                    panic!("specifier '%q' cannot have modifiers");
+                }
+                add_literal(&mut buf, &v, iarg);
+            }
+            _ => panic!("invalid conversion '%{}' to 'format'", String::from_utf8_lossy(form)),
+        }
+    }
+    state.push(buf);
+    1
+}
+
+// Parse the specification @form, e.g. `-08.3f`, where the flags must be
+// in @flags, and the precision is allowed only if @precision. Width and
+// precision have 2 digits at most, and the width can not start with '0'
+// which is taken as a flag.
+// Append @v as a Lua literal for `%q`, which is read back by the lexer
+// as the same value, same with `addliteral()` in lstrlib.c. Floats are
+// in hexadecimal for exactness, and integers in decimal except the
+// minimum one, which would be read as a float.
+fn add_literal(buf: &mut Vec<u8>, v: &Value, iarg: usize) {
+    match v {
+        Value::Integer(i64::MIN) => buf.extend_from_slice(b"0x8000000000000000"),
+        Value::Integer(n) => buf.extend_from_slice(n.to_string().as_bytes()),
+        Value::Float(x) if x.is_nan() => buf.extend_from_slice(b"(0/0)"),
+        Value::Float(x) if x.is_infinite() => {
+            buf.extend_from_slice(if *x > 0.0 { b"1e9999" } else { b"-1e9999" });
+        }
+        Value::Float(x) => format_float(buf, &Spec::default(), b'a', *x),
+        Value::Nil | Value::Boolean(_) => buf.extend_from_slice(v.to_string().as_bytes()),
+        _ => match v.as_bytes() {
+            Some(s) => add_quoted(buf, s),
+            None => panic!("bad argument #{iarg} to 'format' (value has no literal form)"),
+        }
+    }
+}
+
+// Append the string @s quoted. Control bytes are escaped in decimal,
+// with 3 digits if followed by a digit, e.g. `\0` and `\0001`, while
+// the newline is escaped by a backslash.
+fn add_quoted(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.push(b'"');
+    for (i, &b) in s.iter().enumerate() {
+        match b {
+            b'"' | b'\\' | b'\n' => buf.extend_from_slice(&[b'\\', b]),
+            _ if b.is_ascii_control() => {
+                let escaped = if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    format!("\\{b:03}")
+                } else {
+                    format!("\\{b}")
+                };
+                buf.extend_from_slice(escaped.as_bytes());
+            }
+            _ => buf.push(b),
+        }
+    }
+    buf.push(b'"');
+}
+
+fn parse_spec(form: &[u8], flags: &[u8], precision: bool) -> Spec {
+    let invalid = || -> ! {
+        panic!("invalid conversion specification: '%{}'", String::from_utf8_lossy(form))
+    };
+    let digits = |i: usize| form[i..].iter().take(2).take_while(|b| b.is_ascii_digit()).count();
+    let number = |s: &[u8]| s.iter().fold(0, |n, b| n * 10 + (b - b'0') as usize);
+
+    let mut spec = Spec::default();
+    let mut i = 0;
+    while let Some(&b) = form.get(i).filter(|b| flags.contains(b)) {
+        match b {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alt = true,
+            _ => spec.zero = true,
+        }
+        i += 1;
+    }
+    if form.get(i) != Some(&b'0') {
+        let n = digits(i);
+        spec.width = number(&form[i..i+n]);
+        i += n;
+        if precision && form.get(i) == Some(&b'.') {
+            let n = digits(i + 1);
+            spec.precision = Some(number(&form[i+1..i+1+n]));
+            i += 1 + n;
+        }
+    }
+    if !form.get(i).is_some_and(u8::is_ascii_alphabetic) {
+        invalid();
+    }
+    spec
+}
+
+fn sign_str(neg: bool, spec: &Spec) -> &'static str {
+    if neg {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+// Write @prefix and @body into @buf, padded to the width by spaces, or
+// by zeros between them if @zero.
+fn pad(buf: &mut Vec<u8>, spec: &Spec, zero: bool, prefix: &[u8], body: &[u8]) {
+    let fill = spec.width.saturating_sub(prefix.len() + body.len());
+    if spec.left {
+        buf.extend_from_slice(prefix);
+        buf.extend_from_slice(body);
+        buf.resize(buf.len() + fill, b' ');
+    } else if zero {
+        buf.extend_from_slice(prefix);
+        buf.resize(buf.len() + fill, b'0');
+        buf.extend_from_slice(body);
+    } else {
+        buf.resize(buf.len() + fill, b' ');
+        buf.extend_from_slice(prefix);
+        buf.extend_from_slice(body);
+    }
+}
+
+// The precision is the minimum number of digits, and the '0' flag is
+// ignored if the precision is given.
+fn format_integer(buf: &mut Vec<u8>, spec: &Spec, prefix: &str, mut digits: String) {
+    if let Some(p) = spec.precision {
+        if p == 0 && digits == "0" {
+            digits.clear();
+        }
+        if digits.len() < p {
+            digits.insert_str(0, &"0".repeat(p - digits.len()));
+        }
+    }
+    // `%#o` makes the first digit be 0
+    if spec.alt && prefix.is_empty() && !digits.starts_with('0') {
+        digits.insert(0, '0');
+    }
+    let zero = spec.zero && spec.precision.is_none();
+    pad(buf, spec, zero, prefix.as_bytes(), digits.as_bytes());
+}
+
+fn format_float(buf: &mut Vec<u8>, spec: &Spec, conv: u8, x: f64) {
+    let sign = sign_str(x.is_sign_negative(), spec);
+
+    // the '0' flag is ignored for "inf" and "nan"
+    if !x.is_finite() {
+        let body = match (x.is_nan(), conv.is_ascii_uppercase()) {
+            (true, false) => "nan",
+            (true, true) => "NAN",
+            (false, false) => "inf",
+            (false, true) => "INF",
+        };
+        pad(buf, spec, false, sign.as_bytes(), body.as_bytes());
+        return;
+    }
+
+    let x = x.abs();
+    let p = spec.precision.unwrap_or(6);
+    let (prefix, mut body) = match conv {
+        b'a' | b'A' => ("0x", hex_float(x, spec.precision, spec.alt)),
+        b'e' | b'E' => ("", exp_float(x, p, spec.alt)),
+        b'f' | b'F' => ("", fixed_float(x, p, spec.alt)),
+        _ => ("", general_float(x, p, spec.alt)),
+    };
+    let mut prefix = format!("{sign}{prefix}");
+    if conv.is_ascii_uppercase() {
+        prefix.make_ascii_uppercase();
+        body.make_ascii_uppercase();
+    }
+    pad(buf, spec, spec.zero, prefix.as_bytes(), body.as_bytes());
+}
+
+// `%f`, where the Rust's formatting rounds correctly as glibc
+fn fixed_float(x: f64, p: usize, alt: bool) -> String {
+    let mut s = format!("{x:.p$}");
+    if alt && p == 0 {
+        s.push('.');
+    }
+    s
+}
+
+// `%e`, with at least 2 digits in the exponent, e.g. `1.5e+00`
+fn exp_float(x: f64, p: usize, alt: bool) -> String {
+    let s = format!("{x:.p$e}");
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let dot = if alt && p == 0 { "." } else { "" };
+    let exp_sign = if exp < 0 { '-' } else { '+' };
+    format!("{mantissa}{dot}e{exp_sign}{:02}", exp.abs())
+}
+
+// `%g`, which is `%e` if the exponent X is less than -4 or not less than
+// the precision P, or `%f` otherwise, with precision P-1 or P-1-X, where
+// trailing zeros are removed unless the '#' flag is given
+fn general_float(x: f64, p: usize, alt: bool) -> String {
+    let p = p.max(1);
+    // the exponent after rounding
+    let exp = match x {
+        0.0 => 0,
+        _ => {
+            let s = format!("{x:.*e}", p - 1);
+            s[s.find('e').unwrap() + 1..].parse().unwrap()
+        }
+    };
+    let s = if exp >= -4 && exp < p as i32 {
+        fixed_float(x, (p as i32 - 1 - exp) as usize, alt)
+    } else {
+        exp_float(x, p - 1, alt)
+    };
+    if alt {
+        return s;
+    }
+
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap_or(s.len()));
+    if mantissa.contains('.') {
+        format!("{}{exp}", mantissa.trim_end_matches('0').trim_end_matches('.'))
+    } else {
+        s
+    }
+}
+
+// `%a` without the "0x" prefix, e.g. `1.8p+0` for 1.5. Without precision,
+// the fraction is exact without trailing zeros. Otherwise it is rounded
+// half to even, which may carry into the leading digit, e.g. `2p+0` for
+// 1.5 with precision 0, same with glibc.
+fn hex_float(x: f64, precision: Option<usize>, alt: bool) -> String {
+    const FRAC_DIGITS: usize = 13; // 52 bits
+    let bits = x.to_bits();
+    let biased_exp = (bits >> 52) as i32 & 0x7ff;
+    let frac = bits & ((1 << 52) - 1);
+    let (lead, exp) = match biased_exp {
+        0 if frac == 0 => (0, 0),
+        0 => (0, -1022), // subnormal
+        _ => (1, biased_exp - 1023),
+    };
+
+    let (lead, digits) = match precision {
+        None => {
+            let digits = format!("{frac:013x}");
+            (lead, digits.trim_end_matches('0').to_string())
+        }
+        Some(p) if p >= FRAC_DIGITS => (lead, format!("{frac:013x}{}", "0".repeat(p - FRAC_DIGITS))),
+        Some(p) => {
+            let shift = 4 * (FRAC_DIGITS - p);
+            let rest = frac & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            let mut kept = (lead << (4 * p)) | (frac >> shift);
+            if rest > half || (rest == half && kept & 1 == 1) {
+                kept += 1;
+            }
+            let digits = match p {
+                0 => String::new(),
+                _ => format!("{:0p$x}", kept & ((1 << (4 * p)) - 1)),
+            };
+            (kept >> (4 * p), digits)
+        }
+    };
+
+    let dot = if !digits.is_empty() || alt { "." } else { "" };
+    let exp_sign = if exp < 0 { '-' } else { '+' };
+    format!("{lead}{dot}{digits}p{exp_sign}{}", exp.abs())
+}
+
+// integer argument, where floats with exact integer values and strings
+// are converted
+fn arg_integer(v: &Value, iarg: usize) -> i64 {
+    match v.to_number() {
+        Some(Value::Integer(i)) => i,
+        Some(Value::Float(f)) => ftoi(f).unwrap_or_else(||
+            panic!("bad argument #{iarg} to 'format' (number has no integer representation)")),
+        _ => panic!("bad argument #{iarg} to 'format' (number expected, got {})", v.type_name()),
+    }
+}
+
+// float argument, where integers and strings are converted
+fn arg_number(v: &Value, iarg: usize) -> f64 {
+    match v.to_number() {
+        Some(Value::Integer(i)) => i as f64,
+        Some(Value::Float(f)) => f,
+        _ => panic!("bad argument #{iarg} to 'format' (number expected, got {})", v.type_name()),
+    }
+}
@@ -0,0 +1,388 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::value::{Value, Table};
+use crate::vm::{self, ExeState};
+
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("insert", insert),
+        ("remove", remove),
+        ("concat", concat),
+        ("pack", pack),
+        ("unpack", unpack),
+        ("move", lib_move),
+        ("sort", sort),
+        ("entries", entries),
+    ])
+}
+
+fn check_table(state: &ExeState, fname: &str) -> Rc<RefCell<Table>> {
+    match state.get::<&Value>(1) {
+        Value::Table(t) => t.clone(),
+        v => panic!("bad argument #1 to '{fname}' (table expected, got {})", v.type_name()),
+    }
+}
+
+// optional integer argument, where floats with exact integer values and
+// strings are converted
+fn opt_integer(state: &ExeState, iarg: usize, fname: &str, default: i64) -> i64 {
+    if state.get_top() < iarg || state.get::<&Value>(iarg) == &Value::Nil {
+        default
+    } else {
+        state.check_integer(iarg, fname)
+    }
+}
+
+// table.insert(list, [pos,] value)
+//
+// Insert @value at @pos of @list, shifting up the elements at @pos and
+// after, or append it if @pos is absent.
+fn insert(state: &mut ExeState) -> i32 {
+    let t = check_table(state, "insert");
+    let n = t.borrow().array.len();
+    let (pos, value) = match state.get_top() {
+        2 => (n + 1, state.get::<&Value>(2).clone()),
+        3 => {
+            let pos = opt_integer(state, 2, "insert", 0);
+            // only positions in [1, n + 1] are allowed, same with the official Lua
+            if pos < 1 || pos as u64 > n as u64 + 1 {
+                panic!("bad argument #2 to 'insert' (position out of bounds)");
+            }
+            (pos as usize, state.get::<&Value>(3).clone())
+        }
+        _ => panic!("wrong number of arguments to 'insert'"),
+    };
+    t.borrow_mut().insert_array(pos, value);
+    0
+}
+
+// table.remove(list [, pos])
+//
+// Remove and return the element at @pos of @list, shifting down the
+// elements after it. @pos is `#list` by default, and may be `#list + 1`,
+// or 0 if `#list` is 0, where no element is shifted.
+fn remove(state: &mut ExeState) -> i32 {
+    let t = check_table(state, "remove");
+    let n = t.borrow().array.len() as i64;
+    let pos = opt_integer(state, 2, "remove", n);
+    if pos != n && (pos < 1 || pos > n + 1) {
+        panic!("bad argument #2 to 'remove' (position out of bounds)");
+    }
+
+    let mut t = t.borrow_mut();
+    let value = if pos >= 1 && pos <= n {
+        t.remove_array(pos as usize)
+    } else {
+        let value = t.index_array(pos).clone();
+        t.new_index_array(pos, Value::Nil);
+        value
+    };
+    drop(t);
+    state.push(value);
+    1
+}
+
+// table.concat(list [, sep [, i [, j]]])
+//
+// Return the string of list[i] .. sep .. list[i+1] ... sep .. list[j],
+// where @sep is "" by default, @i is 1 and @j is `#list`. All the
+// elements must be strings or numbers.
+fn concat(state: &mut ExeState) -> i32 {
+    let t = check_table(state, "concat");
+    let sep = match state.get_top() {
+        0 | 1 => Vec::new(),
+        _ => match state.get::<&Value>(2) {
+            Value::Nil => Vec::new(),
+            v@(Value::Integer(_) | Value::Float(_)) => v.to_string().into_bytes(),
+            v => match v.as_bytes() {
+                Some(s) => s.to_vec(),
+                None => panic!("bad argument #2 to 'concat' (string expected, got {})", v.type_name()),
+            }
+        }
+    };
+    let t = t.borrow();
+    let i = opt_integer(state, 3, "concat", 1);
+    let j = opt_integer(state, 4, "concat", t.array.len() as i64);
+
+    let mut buf = Vec::new();
+    let mut k = i;
+    while k <= j {
+        match t.index_array(k) {
+            v@(Value::Integer(_) | Value::Float(_)) => buf.extend_from_slice(v.to_string().as_bytes()),
+            v => match v.as_bytes() {
+                Some(s) => buf.extend_from_slice(s),
+                None => panic!("invalid value (at index {k}) in table for 'concat'"),
+            }
+        }
+        if k == j { // avoid overflow of k at i64::MAX
+            break;
+        }
+        buf.extend_from_slice(&sep);
+        k += 1;
+    }
+    drop(t);
+    state.push(buf);
+    1
+}
+
+// table.unpack(list [, i [, j]])
+//
+// Return list[i], list[i+1], ... list[j], where @i is 1 by default and
+// @j is `#list`.
+fn unpack(state: &mut ExeState) -> i32 {
+    let t = check_table(state, "unpack");
+    let i = opt_integer(state, 2, "unpack", 1);
+    let j = opt_integer(state, 3, "unpack", t.borrow().array.len() as i64);
+    if i > j {
+        return 0;
+    }
+    let n = (j as i128 - i as i128 + 1) as u128;
+    if n >= i32::MAX as u128 || !state.check_stack(n as usize) {
+        panic!("too many results to unpack");
+    }
+    let t = t.borrow();
+    for k in i..=j {
+        state.push(t.index_array(k).clone());
+    }
+    n as i32
+}
+
+// table.move(a1, f, e, t [, a2])
+//
+// Move a1[f], ... a1[e] into a2[t], ... a2[t+e-f], and return @a2, which
+// is @a1 by default. The ranges may overlap, e.g. for shifting elements
+// of a list, so it copies backward if the destination is after the
+// source in the same table. Metamethods are not called, same with other
+// functions in this library.
+fn lib_move(state: &mut ExeState) -> i32 {
+    let a1 = check_table(state, "move");
+    let f = state.check_integer(2, "move");
+    let e = state.check_integer(3, "move");
+    let t = state.check_integer(4, "move");
+    let a2 = match state.get::<&Value>(5) {
+        Value::Nil => a1.clone(),
+        Value::Table(a2) => a2.clone(),
+        v => panic!("bad argument #5 to 'move' (table expected, got {})", v.type_name()),
+    };
+
+    if e >= f {
+        if f <= 0 && e >= i64::MAX + f {
+            panic!("bad argument #3 to 'move' (too many elements to move)");
+        }
+        let n = e - f + 1;
+        if t > i64::MAX - n + 1 {
+            panic!("bad argument #4 to 'move' (destination wrap around)");
+        }
+        let mut move_one = |i: i64| {
+            let v = a1.borrow().index_array(f + i).clone();
+            a2.borrow_mut().new_index_array(t + i, v);
+        };
+        if t > e || t <= f || !Rc::ptr_eq(&a1, &a2) {
+            (0..n).for_each(&mut move_one);
+        } else {
+            (0..n).rev().for_each(&mut move_one);
+        }
+    }
+    state.push(Value::Table(a2));
+    1
+}
+
+// table.entries(t)
+//
+// Return an iterator function of the keys and values of @t, same with
+// `next()`, which keeps the position itself. So it's a generator needing
+// no arguments, e.g. for `coroutine.wrap()` or passing around, while
+// `pairs()` returns the state and control values too.
+fn entries(state: &mut ExeState) -> i32 {
+    let t = check_table(state, "entries");
+    let mut key = Some(Value::Nil); // None after the end
+    let c = move |state: &mut ExeState| {
+        let Some(k) = &key else {
+            return 0;
+        };
+        let entry = t.borrow_mut().next(k);
+        match entry {
+            Some((k, v)) => {
+                key = Some(k.clone());
+                state.push(k);
+                state.push(v);
+                2
+            }
+            None => {
+                key = None;
+                0
+            }
+        }
+    };
+    state.push(Value::RustClosure(Rc::new(RefCell::new(Box::new(c)))));
+    1
+}
+
+// table.pack(...)
+//
+// Return a new table with all arguments stored into keys 1, 2, etc. and
+// with a field "n" with the total number of arguments. Trailing nils are
+// counted in "n" but not in `#`, because the array part never ends with nil.
+fn pack(state: &mut ExeState) -> i32 {
+    let n = state.get_top();
+    let mut t = Table::new(n, 1);
+    t.extend_array((1..=n).map(|i| state.get::<&Value>(i).clone()));
+    t.map.insert("n".into(), n.into());
+
+    state.push(Value::from(t));
+    1
+}
+
+// table.sort(list [, comp])
+//
+// Sort the array part of @list in place, by @comp(a, b) which returns
+// true if a < b, or by the `<` operator. The sort is not stable.
+//
+// The algorithm is the quicksort of the official Lua, but not Rust's
+// sort, so invalid comparators, e.g. `function(a, b) return true end`,
+// raise "invalid order function for sorting" as the official Lua
+// does, when they are detected. The elements are sorted in a copy, so
+// the list is unchanged if the comparator raises any error.
+fn sort(state: &mut ExeState) -> i32 {
+    let t = check_table(state, "sort");
+    let comp = if state.get_top() >= 2 {
+        match state.get::<&Value>(2) {
+            Value::Nil => None,
+            f@(Value::LuaFunction(_) | Value::LuaClosure(_) |
+                Value::RustFunction(_) | Value::RustClosure(_)) => Some(f.clone()),
+            v => panic!("bad argument #2 to 'sort' (function expected, got {})", v.type_name()),
+        }
+    } else {
+        None
+    };
+
+    let mut sorter = Sorter {
+        a: t.borrow().array.clone(),
+        state,
+        comp,
+    };
+    if sorter.a.len() > 1 {
+        let up = sorter.a.len() - 1;
+        sorter.auxsort(0, up, 0);
+    }
+
+    // the comparator may have changed the list
+    let mut t = t.borrow_mut();
+    for (i, v) in sorter.a.into_iter().enumerate() {
+        t.new_index_array(i as i64 + 1, v);
+    }
+    0
+}
+
+// intervals longer than this use randomized pivots
+const RANLIMIT: usize = 100;
+
+struct Sorter<'a> {
+    a: Vec<Value>,
+    state: &'a mut ExeState,
+    comp: Option<Value>,
+}
+
+impl Sorter<'_> {
+    // a[i] < a[j]
+    fn less(&mut self, i: usize, j: usize) -> bool {
+        self.less_value(&self.a[i].clone(), &self.a[j].clone())
+    }
+    fn less_value(&mut self, v1: &Value, v2: &Value) -> bool {
+        let Some(comp) = &self.comp else {
+            return vm::compare(v1, v2) == Some(Ordering::Less);
+        };
+        let ifunc = self.state.get_top() + 1;
+        self.state.push(comp.clone());
+        self.state.push(v1.clone());
+        self.state.push(v2.clone());
+        let nret = self.state.call_at(ifunc);
+        let lt = nret > 0 && self.state.get::<bool>(ifunc);
+        self.state.set_top(ifunc - 1);
+        lt
+    }
+
+    // sort a[lo..=up], same with `auxsort()` in ltablib.c
+    fn auxsort(&mut self, mut lo: usize, mut up: usize, mut rnd: usize) {
+        while lo < up { // loop for tail recursion
+            // sort elements a[lo], a[p], and a[up]
+            if self.less(up, lo) {
+                self.a.swap(lo, up);
+            }
+            if up - lo == 1 {
+                break;
+            }
+            let mut p = if up - lo < RANLIMIT || rnd == 0 {
+                (lo + up) / 2
+            } else {
+                let r4 = (up - lo) / 4;
+                rnd % (r4 * 2) + (lo + r4)
+            };
+            if self.less(p, lo) {
+                self.a.swap(p, lo);
+            } else if self.less(up, p) {
+                self.a.swap(p, up);
+            }
+            if up - lo == 2 {
+                break;
+            }
+
+            // a[lo] <= P == a[up - 1] <= a[up], so only a[lo + 1 .. up - 2]
+            // needs partitioning
+            self.a.swap(p, up - 1);
+            p = self.partition(lo, up);
+
+            // recurse into the smaller interval, and loop for the larger one
+            let n;
+            if p - lo < up - p {
+                self.auxsort(lo, p - 1, rnd);
+                n = p - lo;
+                lo = p + 1;
+            } else {
+                self.auxsort(p + 1, up, rnd);
+                n = up - p;
+                up = p - 1;
+            }
+            if (up - lo) / 128 > n { // partition too imbalanced
+                rnd = randomize_pivot();
+            }
+        }
+    }
+
+    // Partition a[lo..=up] by the pivot P at a[up - 1], and return the
+    // final index of P, where a[lo .. p - 1] <= a[p] == P <= a[p + 1 .. up].
+    fn partition(&mut self, lo: usize, up: usize) -> usize {
+        let pivot = self.a[up - 1].clone();
+        let (mut i, mut j) = (lo, up - 1);
+        loop {
+            // repeat ++i while a[i] < P
+            i += 1;
+            while self.less_value(&self.a[i].clone(), &pivot) {
+                if i == up - 1 { // a[i] < P but a[up - 1] == P
+                    panic!("invalid order function for sorting");
+                }
+                i += 1;
+            }
+            // repeat --j while P < a[j]
+            j -= 1;
+            while self.less_value(&pivot, &self.a[j].clone()) {
+                if j < i { // j < i but a[j] > P
+                    panic!("invalid order function for sorting");
+                }
+                j -= 1;
+            }
+            if j < i {
+                self.a.swap(up - 1, i);
+                return i;
+            }
+            self.a.swap(i, j);
+        }
+    }
+}
+
+fn randomize_pivot() -> usize {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.subsec_nanos() as usize ^ now.as_secs() as usize
+}
@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+use crate::value::Value;
+use crate::vm::ExeState;
+
+// Timers calling back Lua functions, e.g. for game scripting and bots:
+//
+//     timer.after(1.5, function() print("once") end)
+//     local id = timer.every(0.1, function() return update() end)
+//     timer.cancel(id)
+//
+// The timers are driven by the host, which calls `ExeState::poll_timers()`
+// with the current time, e.g. once a frame of the game, and the due
+// callbacks are called there. The standalone interpreter runs a loop
+// after the script, until there is no timer, see `main.rs`.
+//
+// Timers are scheduled from the time of the last polling but not the
+// real clock, so a game may pass its frame time. The module is not opened
+// by default, but by `ExeState::open_timer()`.
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("after", after),
+        ("every", every),
+        ("cancel", cancel),
+    ])
+}
+
+pub struct Timers {
+    now: Instant, // of the last polling, or when created
+    next_id: i64,
+    timers: Vec<Timer>,
+}
+
+struct Timer {
+    id: i64,
+    due: Instant,
+    interval: Option<Duration>, // None for one-shot
+    callback: Value,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Timers { now: Instant::now(), next_id: 1, timers: Vec::new() }
+    }
+}
+
+impl Timers {
+    fn add(&mut self, delay: Duration, interval: Option<Duration>, callback: Value) -> i64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.push(Timer { id, due: self.now + delay, interval, callback });
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: i64) -> bool {
+        let n = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() < n
+    }
+
+    // Set the time to @now, and return the timers due, in the order of
+    // due time and then creation.
+    pub(crate) fn start_polling(&mut self, now: Instant) -> Vec<i64> {
+        self.now = now;
+        let mut due: Vec<_> = self.timers.iter()
+            .filter(|t| t.due <= now)
+            .map(|t| (t.due, t.id))
+            .collect();
+        due.sort();
+        due.into_iter().map(|(_, id)| id).collect()
+    }
+
+    // Take the callback of the timer @id to call, which is removed if
+    // it's one-shot, or rescheduled by its interval. Missed ticks of the
+    // repeating timers are skipped, but not called in a burst. Return
+    // None if it's cancelled.
+    pub(crate) fn fire(&mut self, id: i64) -> Option<(Value, bool)> {
+        let i = self.timers.iter().position(|t| t.id == id)?;
+        let t = &mut self.timers[i];
+        match t.interval {
+            Some(interval) => {
+                t.due += interval;
+                if t.due <= self.now {
+                    t.due = self.now + interval;
+                }
+                Some((t.callback.clone(), true))
+            }
+            None => Some((self.timers.remove(i).callback, false)),
+        }
+    }
+
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.timers.iter().map(|t| t.due).min()
+    }
+}
+
+fn check_delay(state: &ExeState, fname: &str) -> Duration {
+    let secs = match state.get::<&Value>(1).to_number() {
+        Some(Value::Integer(i)) if i >= 0 => i as f64,
+        Some(Value::Float(f)) if f >= 0.0 && f.is_finite() => f,
+        _ => panic!("bad argument #1 to '{fname}' (non-negative number expected)"),
+    };
+    Duration::from_secs_f64(secs)
+}
+
+fn check_callback(state: &ExeState, fname: &str) -> Value {
+    let v = if state.get_top() < 2 { &Value::Nil } else { state.get::<&Value>(2) };
+    match v {
+        Value::LuaFunction(_) | Value::LuaClosure(_) | Value::RustFunction(_)
+            | Value::RustClosure(_) => v.clone(),
+        _ => panic!("bad argument #2 to '{fname}' (function expected, got {})", v.type_name()),
+    }
+}
+
+// timer.after(secs, f)
+//
+// Call @f once after @secs seconds. Return the timer id for cancel().
+fn after(state: &mut ExeState) -> i32 {
+    let delay = check_delay(state, "after");
+    let f = check_callback(state, "after");
+    let id = state.timers().add(delay, None, f);
+    state.push(id);
+    1
+}
+
+// timer.every(secs, f)
+//
+// Call @f every @secs seconds, until it's cancelled or @f returns false.
+// Return the timer id for cancel(). It's called at most once in each
+// polling, even if @secs is 0.
+fn every(state: &mut ExeState) -> i32 {
+    let interval = check_delay(state, "every");
+    let f = check_callback(state, "every");
+    let id = state.timers().add(interval, Some(interval), f);
+    state.push(id);
+    1
+}
+
+// timer.cancel(id)
+//
+// Cancel the timer, and return whether it's pending.
+fn cancel(state: &mut ExeState) -> i32 {
+    let cancelled = match state.get::<&Value>(1) {
+        &Value::Integer(id) => state.timers().cancel(id),
+        v => panic!("bad argument #1 to 'cancel' (number expected, got {})", v.type_name()),
+    };
+    state.push(cancelled);
+    1
+}
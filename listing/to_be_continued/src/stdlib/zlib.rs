@@ -0,0 +1,262 @@
+use std::io::Write;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use crate::value::Value;
+use crate::vm::ExeState;
+use super::bytes::arg_data;
+
+// Compression of the zlib (RFC 1950) and gzip (RFC 1952) formats, e.g.
+// for save files and HTTP bodies:
+//
+//     local packed = zlib.deflate(save_data)
+//     local data, err = zlib.inflate(packed)
+//     local body = zlib.gunzip(resp.body)
+//
+// DEFLATE (RFC 1951) is by the flate2 crate. The zlib and gzip headers
+// and trailers are checked here, to report which part is invalid.
+//
+// The data is a string or a bytes buffer. Invalid compressed data is
+// returned as nil and the message, same with `io.open()`, since it's
+// often from outside.
+//
+// It's built with the cargo feature "zlib".
+pub fn new_lib() -> Value {
+    super::new_lib(&[
+        ("deflate", lib_deflate),
+        ("inflate", lib_inflate),
+        ("gzip", lib_gzip),
+        ("gunzip", lib_gunzip),
+        ("crc32", lib_crc32),
+        ("adler32", lib_adler32),
+    ])
+}
+
+// same with the strings built by the string library, and the default
+// limit of decompressed data
+const MAX_SIZE: usize = i32::MAX as usize;
+
+fn arg_level(state: &ExeState, fname: &str) -> Compression {
+    let level = state.check::<Option<i64>>(2, fname).unwrap_or(6);
+    match u32::try_from(level) {
+        Ok(level) if level <= 9 => Compression::new(level),
+        _ => panic!("bad argument #2 to '{fname}' (level should be in [0, 9])"),
+    }
+}
+
+fn arg_max_size(state: &ExeState, fname: &str) -> usize {
+    state.check::<Option<usize>>(2, fname).unwrap_or(MAX_SIZE)
+}
+
+fn push_result(state: &mut ExeState, result: Result<Vec<u8>, String>) -> i32 {
+    match result {
+        Ok(data) => {
+            state.push(data);
+            1
+        }
+        Err(msg) => {
+            state.push(());
+            state.push(msg);
+            2
+        }
+    }
+}
+
+// zlib.deflate(data [, level])
+//
+// Compress @data into the zlib format. The @level is from 0 for no
+// compression, to 9 for the best compression, and 6 by default.
+fn lib_deflate(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "deflate");
+    let level = arg_level(state, "deflate");
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+    encoder.write_all(&data).unwrap();
+    state.push(encoder.finish().unwrap());
+    1
+}
+
+// zlib.inflate(data [, max_size])
+//
+// Decompress the zlib format. It fails if the output is more than
+// @max_size bytes, e.g. to limit the data from network.
+fn lib_inflate(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "inflate");
+    let max_size = arg_max_size(state, "inflate");
+    push_result(state, unzlib(&data, max_size))
+}
+
+fn unzlib(data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    let &[cmf, flg, ..] = data else {
+        return Err("truncated data".into());
+    };
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err("invalid zlib header".into());
+    }
+    if flg & 0x20 != 0 {
+        return Err("preset dictionary is not supported".into());
+    }
+    let (out, n) = inflate(&data[2..], max_size)?;
+    let trailer = &data[2+n..];
+    if trailer.len() < 4 {
+        return Err("truncated data".into());
+    }
+    if trailer[..4] != adler32(1, &out).to_be_bytes() {
+        return Err("adler32 mismatch".into());
+    }
+    if trailer.len() > 4 {
+        return Err("extra bytes after data".into());
+    }
+    Ok(out)
+}
+
+// zlib.gzip(data [, level])
+//
+// Compress @data into the gzip format, without the file name and time.
+fn lib_gzip(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "gzip");
+    let level = arg_level(state, "gzip");
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    encoder.write_all(&data).unwrap();
+    state.push(encoder.finish().unwrap());
+    1
+}
+
+// zlib.gunzip(data [, max_size])
+//
+// Decompress the gzip format. Concatenated members are decompressed
+// into one, same with the `gunzip` command.
+fn lib_gunzip(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "gunzip");
+    let max_size = arg_max_size(state, "gunzip");
+    push_result(state, gunzip(&data, max_size))
+}
+
+fn gunzip(mut data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let member;
+        (member, data) = gunzip_member(data, max_size - out.len())?;
+        out.extend(member);
+        if data.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+// Return the data of the first member, and the rest.
+fn gunzip_member(data: &[u8], max_size: usize) -> Result<(Vec<u8>, &[u8]), String> {
+    let truncated = || String::from("truncated data");
+    if data.len() < 10 {
+        return Err(truncated());
+    }
+    if data[..3] != [0x1f, 0x8b, 8] {
+        return Err("invalid gzip header".into());
+    }
+    let flg = data[3];
+    let mut pos = 10;
+    if flg & 0x04 != 0 { // FEXTRA
+        let xlen = data.get(pos..pos+2).ok_or_else(truncated)?;
+        pos += 2 + u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+    }
+    for flag in [0x08, 0x10] { // FNAME, FCOMMENT, zero-terminated
+        if flg & flag != 0 {
+            let len = data.get(pos..).and_then(|s| s.iter().position(|&b| b == 0)).ok_or_else(truncated)?;
+            pos += len + 1;
+        }
+    }
+    if flg & 0x02 != 0 { // FHCRC
+        pos += 2;
+    }
+    let body = data.get(pos..).ok_or_else(truncated)?;
+
+    let (out, n) = inflate(body, max_size)?;
+    let trailer = body.get(n..n+8).ok_or_else(truncated)?;
+    if trailer[..4] != crc32(0, &out).to_le_bytes() {
+        return Err("crc32 mismatch".into());
+    }
+    if trailer[4..] != (out.len() as u32).to_le_bytes() {
+        return Err("size mismatch".into());
+    }
+    Ok((out, &body[n+8..]))
+}
+
+// zlib.crc32(data [, crc]), zlib.adler32(data [, adler])
+//
+// Return the checksum of @data, continued from the previous checksum of
+// the preceding data if given.
+fn lib_crc32(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "crc32");
+    let crc = state.check::<Option<u32>>(2, "crc32").unwrap_or(0);
+    state.push(crc32(crc, &data));
+    1
+}
+
+fn lib_adler32(state: &mut ExeState) -> i32 {
+    let data = arg_data(state, 1, "adler32");
+    let adler = state.check::<Option<u32>>(2, "adler32").unwrap_or(1);
+    state.push(adler32(adler, &data));
+    1
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+fn adler32(adler: u32, data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    // 5552 is the most bytes before b overflows, same with zlib
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+// Decompress the raw DEFLATE blocks at the start of @data, and return
+// the output and the number of bytes consumed.
+fn inflate(data: &[u8], max_size: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut d = Decompress::new(false);
+    let mut out = Vec::new();
+    loop {
+        // one more byte than @max_size, to know if it's too large
+        if out.len() == out.capacity() {
+            if out.len() > max_size {
+                return Err("output too large".into());
+            }
+            out.reserve((out.len() + 1024).min(max_size + 1 - out.len()));
+        }
+        let (total_in, total_out) = (d.total_in(), d.total_out());
+        let status = d.decompress_vec(&data[total_in as usize..], &mut out, FlushDecompress::None)
+            .map_err(|e| e.message().unwrap_or("invalid deflate data").to_string())?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        if (d.total_in(), d.total_out()) == (total_in, total_out) {
+            return Err("truncated data".into());
+        }
+    }
+    if out.len() > max_size {
+        return Err("output too large".into());
+    }
+    Ok((out, d.total_in() as usize))
+}
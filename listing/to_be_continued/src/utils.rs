@@ -2,14 +2,66 @@ use std::cmp::Ordering;
 use crate::value::Value;
 
 pub fn ftoi(f: f64) -> Option<i64> {
-    let i = f as i64;
-    if i as f64 != f {
+    // `as` saturates, so check the range first, where 2^63 is exact
+    if f.fract() != 0.0 || !(-9223372036854775808.0..9223372036854775808.0).contains(&f) {
         None
     } else {
-        Some(i)
+        Some(f as i64)
     }
 }
 
+// Floor division and modulo of Lua, which round towards minus infinity
+// while Rust's `/` and `%` truncate, e.g. `-7 // 2` is -4 and `-7 % 2`
+// is 1. Integers wrap around on overflow, e.g. `math.mininteger // -1`.
+pub fn int_div(a: i64, b: i64) -> i64 {
+    match b {
+        0 => panic!("attempt to perform 'n//0'"),
+        -1 => a.wrapping_neg(),
+        _ => {
+            let q = a / b;
+            if a % b != 0 && (a ^ b) < 0 { q - 1 } else { q }
+        }
+    }
+}
+
+pub fn int_mod(a: i64, b: i64) -> i64 {
+    match b {
+        0 => panic!("attempt to perform 'n%0'"),
+        -1 => 0,
+        _ => {
+            let r = a % b;
+            if r != 0 && (r ^ b) < 0 { r + b } else { r }
+        }
+    }
+}
+
+// Logical shifts of Lua, same with `luaV_shiftl()`, where shifting by
+// 64 or more bits gives 0, and negative shifts go the other way, e.g.
+// `1 << 64` is 0 and `-1 >> 1` is math.maxinteger.
+pub fn shift_left(a: i64, b: i64) -> i64 {
+    if b <= -64 || b >= 64 {
+        0
+    } else if b < 0 {
+        ((a as u64) >> -b) as i64
+    } else {
+        ((a as u64) << b) as i64
+    }
+}
+
+// math.mininteger negates to itself, which shifts left out all bits too
+pub fn shift_right(a: i64, b: i64) -> i64 {
+    shift_left(a, b.wrapping_neg())
+}
+
+pub fn float_div(a: f64, b: f64) -> f64 {
+    (a / b).floor()
+}
+
+pub fn float_mod(a: f64, b: f64) -> f64 {
+    let r = a % b;
+    if if r > 0.0 { b < 0.0 } else { r < 0.0 && b != r } { r + b } else { r }
+}
+
 pub fn set_vec(vec: &mut Vec<Value>, i: usize, value: Value) {
     match i.cmp(&vec.len()) {
         Ordering::Less => vec[i] = value,
@@ -20,3 +72,244 @@ pub fn set_vec(vec: &mut Vec<Value>, i: usize, value: Value) {
         }
     }
 }
+
+// Relative positions of Lua, e.g. `string.sub(s, i, j)`, where positive
+// ones are 1-based, and negative ones count from the end, -1 for the
+// last one. Both are clamped, so the results are never out of range
+// on the left, and slicing by `start_pos()..=end_pos()` needs only the
+// check of `start <= end`:
+//
+//     let (start, end) = (start_pos(i, len), end_pos(j, len));
+//     let sub = if start <= end { &s[start-1..end] } else { &[] };
+//
+// The start position, which is at least 1 but may be beyond @len, same
+// with `posrelatI()` in lstrlib.c.
+pub fn start_pos(pos: i64, len: usize) -> usize {
+    if pos > 0 {
+        usize::try_from(pos).unwrap_or(usize::MAX)
+    } else if pos == 0 {
+        1
+    } else {
+        match usize::try_from(pos.unsigned_abs()) {
+            Ok(back) if back <= len => len - back + 1,
+            _ => 1,
+        }
+    }
+}
+
+// The end position, in 0..=@len, same with `getendpos()` in lstrlib.c.
+pub fn end_pos(pos: i64, len: usize) -> usize {
+    if pos >= 0 {
+        usize::try_from(pos).map_or(len, |pos| pos.min(len))
+    } else {
+        match usize::try_from(pos.unsigned_abs()) {
+            Ok(back) if back <= len => len - back + 1,
+            _ => 0,
+        }
+    }
+}
+
+// Convert a string into a number, following Lua's syntax: leading and
+// trailing blanks, an optional sign, and decimal or hexadecimal integers
+// or floats. Decimal integers that overflow are converted into floats,
+// while hexadecimal integers wrap around.
+//
+// This is shared by the lexer, `tonumber()` and coercions of strings in
+// arithmetic operations. It does not depend on locale, and does not
+// allocate memory.
+pub fn str_to_number(s: &[u8]) -> Option<Value> {
+    let s = trim_lua_blanks(s);
+
+    let (neg, s) = match s.first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    if s.len() > 2 && s[0] == b'0' && (s[1] == b'x' || s[1] == b'X') {
+        scan_hex(&s[2..], neg)
+    } else {
+        scan_decimal(s, neg)
+    }
+}
+
+// blanks defined by C's isspace() in "C" locale
+fn trim_lua_blanks(s: &[u8]) -> &[u8] {
+    let is_blank = |b: &u8| matches!(b, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c);
+    let begin = s.iter().position(|b| !is_blank(b)).unwrap_or(s.len());
+    let end = s.iter().rposition(|b| !is_blank(b)).map_or(begin, |i| i + 1);
+    &s[begin..end]
+}
+
+// skip digits of the radix, and return the number of them
+fn skip_digits(s: &[u8], radix: u32) -> usize {
+    s.iter().take_while(|&&b| (b as char).is_digit(radix)).count()
+}
+
+fn scan_decimal(s: &[u8], neg: bool) -> Option<Value> {
+    // syntax check: digits [`.` digits] [(`e`|`E`) [sign] digits]
+    let nint = skip_digits(s, 10);
+    let mut i = nint;
+    let mut is_float = false;
+    if s.get(i) == Some(&b'.') {
+        is_float = true;
+        i += 1;
+        let nfrac = skip_digits(&s[i..], 10);
+        if nint + nfrac == 0 {
+            return None;
+        }
+        i += nfrac;
+    } else if nint == 0 {
+        return None;
+    }
+    if matches!(s.get(i), Some(b'e' | b'E')) {
+        is_float = true;
+        i += 1;
+        if matches!(s.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        let nexp = skip_digits(&s[i..], 10);
+        if nexp == 0 {
+            return None;
+        }
+        i += nexp;
+    }
+    if i != s.len() {
+        return None;
+    }
+
+    // integer, if not overflow
+    if !is_float {
+        let mut n: u64 = 0;
+        let limit = if neg { i64::MIN.unsigned_abs() } else { i64::MAX as u64 };
+        let overflow = s.iter().any(|&b| {
+            match n.checked_mul(10).and_then(|n| n.checked_add((b - b'0') as u64)) {
+                Some(m) if m <= limit => { n = m; false }
+                _ => true,
+            }
+        });
+        if !overflow {
+            let n = if neg { (n as i64).wrapping_neg() } else { n as i64 };
+            return Some(Value::Integer(n));
+        }
+    }
+
+    // float. The syntax has been checked, so the standard parser will
+    // not meet any surprise, e.g. "inf" or "nan".
+    let f = std::str::from_utf8(s).ok()?.parse::<f64>().ok()?;
+    Some(Value::Float(if neg { -f } else { f }))
+}
+
+// hexadecimal: hexdigits [`.` hexdigits] [(`p`|`P`) [sign] digits]
+fn scan_hex(s: &[u8], neg: bool) -> Option<Value> {
+    let mut mantissa: f64 = 0.0;
+    let mut exp: i64 = 0; // binary exponent
+    let mut n: i64 = 0; // integer value, wrap around
+    let mut ndigit = 0;
+    let mut nsigdig = 0; // significant digits
+    let mut is_float = false;
+
+    let mut i = 0;
+    let mut dot = false;
+    while i < s.len() {
+        let b = s[i];
+        if b == b'.' {
+            if dot {
+                return None;
+            }
+            dot = true;
+            is_float = true;
+        } else if let Some(d) = (b as char).to_digit(16) {
+            ndigit += 1;
+            n = n.wrapping_mul(16).wrapping_add(d as i64);
+            if nsigdig == 0 && d == 0 {
+                // leading zeros, not significant
+                if dot {
+                    exp -= 4;
+                }
+            } else if nsigdig < 30 {
+                nsigdig += 1;
+                mantissa = mantissa * 16.0 + d as f64;
+                if dot {
+                    exp -= 4;
+                }
+            } else if !dot {
+                exp += 4; // too many digits, ignore but still count exponent
+            }
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    if ndigit == 0 {
+        return None;
+    }
+
+    if matches!(s.get(i), Some(b'p' | b'P')) {
+        is_float = true;
+        i += 1;
+        let eneg = match s.get(i) {
+            Some(b'-') => { i += 1; true }
+            Some(b'+') => { i += 1; false }
+            _ => false,
+        };
+        let nexp = skip_digits(&s[i..], 10);
+        if nexp == 0 {
+            return None;
+        }
+        let e = s[i..i+nexp].iter().fold(0_i64, |e, &b| (e * 10 + (b - b'0') as i64).min(1 << 20));
+        exp += if eneg { -e } else { e };
+        i += nexp;
+    }
+    if i != s.len() {
+        return None;
+    }
+
+    if is_float {
+        let f = ldexp(mantissa, exp);
+        Some(Value::Float(if neg { -f } else { f }))
+    } else {
+        Some(Value::Integer(if neg { n.wrapping_neg() } else { n }))
+    }
+}
+
+// x * 2^exp, by steps to avoid overflow of the intermediate powers
+fn ldexp(mut x: f64, mut exp: i64) -> f64 {
+    while exp > 1000 && x.is_finite() {
+        x *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 && x != 0.0 {
+        x *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    x * 2f64.powi(exp as i32)
+}
+
+// Position of the first @needle in @haystack, by the Boyer-Moore-Horspool
+// algorithm, which skips by the last byte of each window, so it's fast
+// for log lines and other texts.
+pub fn memmem(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    match n {
+        0 => return Some(0),
+        1 => return haystack.iter().position(|&b| b == needle[0]),
+        _ if n > haystack.len() => return None,
+        _ => (),
+    }
+
+    // distance from the last occurrence of each byte to the end
+    let mut skips = [n; 256];
+    for (i, &b) in needle[..n-1].iter().enumerate() {
+        skips[b as usize] = n - 1 - i;
+    }
+
+    let mut i = 0;
+    while i + n <= haystack.len() {
+        let last = haystack[i + n - 1];
+        if last == needle[n - 1] && haystack[i..i + n - 1] == needle[..n - 1] {
+            return Some(i);
+        }
+        i += skips[last as usize];
+    }
+    None
+}
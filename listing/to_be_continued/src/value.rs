@@ -1,16 +1,38 @@
+use std::any::Any;
 use std::fmt;
 use std::mem;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::hash::{Hash, Hasher};
 use std::collections::HashMap;
+use crate::gc;
 use crate::parse::FuncProto;
-use crate::vm::{ExeState, LuaClosure};
-use crate::utils::{ftoi, set_vec};
+use crate::vm::{Coroutine, ExeState, LuaClosure};
+use crate::utils::{ftoi, set_vec, str_to_number};
 
 const SHORT_STR_MAX: usize = 14; // sizeof(Value) - 1(tag) - 1(len)
 const MID_STR_MAX: usize = 48 - 1;
 
+pub type RustClosureFn = Box<dyn FnMut (&mut ExeState) -> i32>;
+
+// Numbers of tables and strings allocated in this thread, for the
+// statistics of ExeState, see ExeState::stats(). They are counted here
+// but not by the state, because values are created without the state,
+// e.g. by `From`. Short strings are not allocated, so not counted.
+thread_local! {
+    static NEW_TABLES: Cell<u64> = const { Cell::new(0) };
+    static NEW_STRINGS: Cell<u64> = const { Cell::new(0) };
+}
+
+fn count_string() {
+    NEW_STRINGS.with(|n| n.set(n.get() + 1));
+}
+
+// (tables, strings) allocated in this thread
+pub(crate) fn allocations() -> (u64, u64) {
+    (NEW_TABLES.with(Cell::get), NEW_STRINGS.with(Cell::get))
+}
+
 #[derive(Clone)]
 pub enum Value {
     Nil,
@@ -22,56 +44,352 @@ pub enum Value {
     LongStr(Rc<Vec<u8>>),
     Table(Rc<RefCell<Table>>),
     RustFunction(fn (&mut ExeState) -> i32),
-    RustClosure(Rc<RefCell<Box<dyn FnMut (&mut ExeState) -> i32>>>),
+    RustClosure(Rc<RefCell<RustClosureFn>>),
     LuaFunction(Rc<FuncProto>),
     LuaClosure(Rc<LuaClosure>),
+    Coroutine(Rc<RefCell<Coroutine>>),
+    UserData(Rc<RefCell<UserData>>),
 }
 
+// Lua table, with an array part and a hash part.
+//
+// Policy of nils:
+// - the array part may contain nils as holes, but never ends with nil,
+//   so `#t` is just the length of the array part;
+// - the hash part never contains nil values, assigning nil to a key
+//   removes the entry.
 pub struct Table {
     pub array: Vec<Value>,
     pub map: HashMap<Value, Value>,
+    pub metatable: Option<Rc<RefCell<Table>>>,
+
+    // snapshot of the keys of the hash part during traversal, see next()
+    keys: Vec<Value>,
+    ikey: usize, // index in @keys of the last key returned by next()
 }
 
 impl Table {
     pub fn new(narray: usize, nmap: usize) -> Self {
+        NEW_TABLES.with(|n| n.set(n.get() + 1));
         Table {
             array: Vec::with_capacity(narray),
             map: HashMap::with_capacity(nmap),
+            metatable: None,
+            keys: Vec::new(),
+            ikey: 0,
+        }
+    }
+
+    // Call @f with all values in the table, including the snapshot of
+    // keys for traversal but not the metatable, for the collector.
+    pub(crate) fn for_each_value(&self, mut f: impl FnMut(&Value)) {
+        self.array.iter().for_each(&mut f);
+        for (k, v) in &self.map {
+            f(k);
+            f(v);
         }
+        self.keys.iter().for_each(f);
     }
 
+    // Take all contents out and leave an empty table, by the collector
+    // to break reference cycles. It's not counted as a new table.
+    pub(crate) fn take_all(&mut self) -> Table {
+        mem::replace(self, Table {
+            array: Vec::new(),
+            map: HashMap::new(),
+            metatable: None,
+            keys: Vec::new(),
+            ikey: 0,
+        })
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn index(&self, key: &Value) -> &Value {
-        match key {
-            // TODO float
-            &Value::Integer(i) => self.index_array(i),
+        match *key {
+            Value::Integer(i) => self.index_array(i),
+            Value::Float(f) => match ftoi(f) {
+                Some(i) => self.index_array(i),
+                None => self.map.get(key).unwrap_or(&Value::Nil),
+            }
             _ => self.map.get(key).unwrap_or(&Value::Nil),
         }
     }
     pub fn index_array(&self, i: i64) -> &Value {
-        self.array.get(i as usize - 1)
-            .unwrap_or_else(|| self.map.get(&Value::Integer(i as i64))
+        (i as usize).checked_sub(1).and_then(|i| self.array.get(i))
+            .unwrap_or_else(|| self.map.get(&Value::Integer(i))
                 .unwrap_or(&Value::Nil))
     }
 
     pub fn new_index(&mut self, key: Value, value: Value) {
         match key {
-            // TODO float
             Value::Integer(i) => self.new_index_array(i, value),
-            _ => {
-                self.map.insert(key, value);
+            Value::Float(f) => match ftoi(f) {
+                Some(i) => self.new_index_array(i, value),
+                None => self.new_index_map(key, value),
             }
+            _ => self.new_index_map(key, value),
         }
     }
     pub fn new_index_array(&mut self, i: i64, value: Value) {
-        // this is not same with Lua's official implement
-        if i > 0 && (i < 4 || i < self.array.capacity() as i64 * 2) {
-            set_vec(&mut self.array, i as usize - 1, value);
+        let len = self.array.len() as i64;
+        if i > 0 && i <= len {
+            self.array[i as usize - 1] = value;
+            if i == len {
+                self.trim_array();
+            }
+        } else if i == len + 1 || (i > 0 && (i < 4 || i < self.array.capacity() as i64 * 2)) {
+            // this is not same with Lua's official implement
+            self.map.remove(&Value::Integer(i));
+            if !matches!(value, Value::Nil) {
+                set_vec(&mut self.array, i as usize - 1, value);
+                self.absorb_gap(len);
+                self.absorb_map();
+            }
         } else {
-            self.map.insert(Value::Integer(i), value);
+            self.new_index_map(Value::Integer(i), value);
+        }
+    }
+    fn new_index_map(&mut self, key: Value, value: Value) {
+        if matches!(value, Value::Nil) {
+            self.map.remove(&key);
+        } else {
+            self.map.insert(key, value);
+        }
+    }
+
+    // Return the field @event of the metatable, e.g. "__index", or nil
+    // if no metatable or no such field.
+    pub fn metamethod(&self, event: &str) -> Value {
+        match &self.metatable {
+            Some(mt) => mt.borrow().map.get(&event.into()).cloned().unwrap_or(Value::Nil),
+            None => Value::Nil,
+        }
+    }
+
+    // append values to the array part, e.g. by table constructor
+    pub fn extend_array(&mut self, values: impl IntoIterator<Item = Value>) {
+        self.array.extend(values);
+        self.trim_array();
+        self.absorb_map();
+    }
+
+    // Return the entry following @key, or the first one if @key is nil,
+    // or None at the end, same with Lua's `next()`. The array part is
+    // traversed in order, and then the hash part in arbitrary order.
+    //
+    // Same with the official Lua, assigning nil to existing fields during
+    // the traversal is allowed, while adding new fields is undefined but
+    // safe: the new fields may be missed. The keys of the hash part are
+    // snapshotted when the traversal enters it, since HashMap can not
+    // find the key following a given one, and the removed keys are
+    // skipped. Keys missing in the snapshot, e.g. by nested traversals
+    // of the same table, are searched again after re-snapshotting.
+    pub fn next(&mut self, key: &Value) -> Option<(Value, Value)> {
+        let key = match *key {
+            Value::Float(f) => ftoi(f).map_or(key.clone(), Value::Integer),
+            _ => key.clone(),
+        };
+
+        let (iarray, ikey) = match key {
+            Value::Nil => (0, 0),
+            Value::Integer(i) if i >= 1 && i as usize <= self.array.len() => (i as usize, 0),
+            _ => match self.key_position(&key) {
+                Some(ikey) => (self.array.len(), ikey + 1),
+                // integer keys removed from the end of the array part
+                None if matches!(key, Value::Integer(i) if i >= 1) => (self.array.len(), 0),
+                None => panic!("invalid key to 'next'"),
+            }
+        };
+
+        if let Some((i, v)) = self.array.iter().enumerate().skip(iarray)
+                .find(|(_, v)| !matches!(v, Value::Nil)) {
+            return Some((Value::Integer(i as i64 + 1), v.clone()));
+        }
+        if ikey == 0 {
+            self.snapshot_keys();
+        }
+        for (i, k) in self.keys.iter().enumerate().skip(ikey) {
+            if let Some(v) = self.map.get(k) {
+                self.ikey = i;
+                return Some((k.clone(), v.clone()));
+            }
+        }
+        self.keys = Vec::new(); // free it at the end
+        None
+    }
+    fn snapshot_keys(&mut self) {
+        self.keys.clear();
+        self.keys.extend(self.map.keys().cloned());
+        self.ikey = 0;
+    }
+    // index of @key in the snapshot, which is the last returned one mostly
+    fn key_position(&mut self, key: &Value) -> Option<usize> {
+        if self.keys.get(self.ikey) == Some(key) {
+            return Some(self.ikey);
+        }
+        if let Some(i) = self.keys.iter().position(|k| k == key) {
+            return Some(i);
+        }
+        self.snapshot_keys();
+        self.keys.iter().position(|k| k == key)
+    }
+
+    // remove trailing nils from the array part
+    pub fn trim_array(&mut self) {
+        while matches!(self.array.last(), Some(Value::Nil)) {
+            self.array.pop();
+        }
+    }
+
+    // Insert @value at @i of the array part, shifting up the following
+    // elements, where @i is in [1, #t + 1]. This is `table.insert()`.
+    pub fn insert_array(&mut self, i: usize, value: Value) {
+        self.array.insert(i - 1, value);
+        self.trim_array();
+        self.absorb_map();
+    }
+
+    // Remove and return the element at @i of the array part, shifting
+    // down the following elements, where @i is in [1, #t]. This is
+    // `table.remove()`.
+    pub fn remove_array(&mut self, i: usize) -> Value {
+        let value = self.array.remove(i - 1);
+        self.trim_array();
+        value
+    }
+
+    // Move the integer keys in the hash part into the nil padding of the
+    // array part, which is grown from the length @len, otherwise they
+    // would be hidden by the padding.
+    fn absorb_gap(&mut self, len: i64) {
+        if self.map.is_empty() {
+            return;
+        }
+        for i in len + 1 .. self.array.len() as i64 {
+            if let Some(v) = self.map.remove(&Value::Integer(i)) {
+                self.array[i as usize - 1] = v;
+            }
+        }
+    }
+
+    // move following integer keys from the hash part into the array part,
+    // to keep `#t` a border
+    fn absorb_map(&mut self) {
+        if self.map.is_empty() {
+            return;
+        }
+        while let Some(v) = self.map.remove(&Value::Integer(self.array.len() as i64 + 1)) {
+            self.array.push(v);
         }
     }
 }
 
+// Userdata, a Rust value exposed to Lua, e.g. file handles of the `io`
+// library. The metatable gives the methods by `__index`, and other
+// metamethods. It's shared by the values of a type registered by
+// `ExeState::register_userdata()`, or each value has its own, e.g. files.
+// The Rust functions get the value back by downcasting.
+pub struct UserData {
+    value: Box<dyn Any>,
+    pub metatable: Option<Rc<RefCell<Table>>>,
+}
+
+impl UserData {
+    pub fn new(value: impl Any, metatable: Option<Rc<RefCell<Table>>>) -> Self {
+        UserData { value: Box::new(value), metatable }
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.value.downcast_mut()
+    }
+}
+
+impl From<UserData> for Value {
+    fn from(u: UserData) -> Self {
+        Value::UserData(Rc::new(RefCell::new(u)))
+    }
+}
+
+// Handle of a table for the host, e.g. the global table by
+// ExeState::globals(). Keys and values are converted by `Into<Value>`,
+// so `t.set("x", 1)` works.
+//
+// The iteration is over a snapshot of the entries, so the host can call
+// Lua functions or change the table in the loop, e.g. to call all
+// `test_*` functions defined by a script.
+#[derive(Clone)]
+pub struct TableHandle(Rc<RefCell<Table>>);
+
+impl TableHandle {
+    pub fn new(t: Rc<RefCell<Table>>) -> Self {
+        TableHandle(t)
+    }
+
+    pub fn get(&self, key: impl Into<Value>) -> Value {
+        self.0.borrow().index(&key.into()).clone()
+    }
+
+    // assigning nil removes the entry, same with Lua
+    pub fn set(&self, key: impl Into<Value>, value: impl Into<Value>) {
+        self.0.borrow_mut().new_index(key.into(), value.into());
+    }
+
+    // remove the entry, and return the old value, or nil if missing
+    pub fn remove(&self, key: impl Into<Value>) -> Value {
+        let key = key.into();
+        let old = self.get(key.clone());
+        self.set(key, Value::Nil);
+        old
+    }
+
+    pub fn contains_key(&self, key: impl Into<Value>) -> bool {
+        self.get(key) != Value::Nil
+    }
+
+    // number of entries, in both the array and the hash parts
+    pub fn len(&self) -> usize {
+        let t = self.0.borrow();
+        t.array.iter().filter(|v| !matches!(v, Value::Nil)).count() + t.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // entries of the array part in order, followed by the hash part
+    // in arbitrary order
+    pub fn iter(&self) -> std::vec::IntoIter<(Value, Value)> {
+        let t = self.0.borrow();
+        let array = t.array.iter().enumerate()
+            .filter(|(_, v)| !matches!(v, Value::Nil))
+            .map(|(i, v)| (Value::Integer(i as i64 + 1), v.clone()));
+        let map = t.map.iter().map(|(k, v)| (k.clone(), v.clone()));
+        array.chain(map).collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl From<TableHandle> for Value {
+    fn from(t: TableHandle) -> Self {
+        Value::Table(t.0)
+    }
+}
+
+impl IntoIterator for &TableHandle {
+    type Item = (Value, Value);
+    type IntoIter = std::vec::IntoIter<(Value, Value)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
@@ -87,6 +405,8 @@ impl fmt::Display for Value {
             Value::RustClosure(_) => write!(f, "function"),
             Value::LuaFunction(l) => write!(f, "function: {:?}", Rc::as_ptr(l)),
             Value::LuaClosure(l) => write!(f, "function: {:?}", Rc::as_ptr(l)),
+            Value::Coroutine(c) => write!(f, "thread: {:?}", Rc::as_ptr(c)),
+            Value::UserData(u) => write!(f, "userdata: {:?}", Rc::as_ptr(u)),
         }
     }
 }
@@ -109,6 +429,8 @@ impl fmt::Debug for Value {
             Value::RustClosure(_) => write!(f, "rust closure"),
             Value::LuaFunction(_) => write!(f, "Lua function"),
             Value::LuaClosure(_) => write!(f, "Lua closure"),
+            Value::Coroutine(c) => write!(f, "thread:{}", c.borrow().status().name()),
+            Value::UserData(_) => write!(f, "userdata"),
         }
     }
 }
@@ -130,6 +452,8 @@ impl PartialEq for Value {
             (Value::RustClosure(f1), Value::RustClosure(f2)) => Rc::as_ptr(f1) == Rc::as_ptr(f2),
             (Value::LuaFunction(f1), Value::LuaFunction(f2)) => Rc::as_ptr(f1) == Rc::as_ptr(f2),
             (Value::LuaClosure(f1), Value::LuaClosure(f2)) => Rc::as_ptr(f1) == Rc::as_ptr(f2),
+            (Value::Coroutine(c1), Value::Coroutine(c2)) => Rc::as_ptr(c1) == Rc::as_ptr(c2),
+            (Value::UserData(u1), Value::UserData(u2)) => Rc::as_ptr(u1) == Rc::as_ptr(u2),
             (_, _) => false,
         }
     }
@@ -169,69 +493,178 @@ impl Value {
         // eliminate Integer and Float with same number value
         mem::discriminant(self) == mem::discriminant(other) && self == other
     }
-    pub fn ty(&self) -> &'static str {
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) => "number",
+            Value::Float(_) => "number",
+            Value::ShortStr(_, _) => "string",
+            Value::MidStr(_) => "string",
+            Value::LongStr(_) => "string",
+            Value::Table(_) => "table",
+            Value::RustFunction(_) => "function",
+            Value::RustClosure(_) => "function",
+            Value::LuaFunction(_) => "function",
+            Value::LuaClosure(_) => "function",
+            Value::Coroutine(_) => "thread",
+            Value::UserData(_) => "userdata",
+        }
+    }
+
+    // bytes of string values, or None for other types
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::ShortStr(len, buf) => Some(&buf[..*len as usize]),
+            Value::MidStr(s) => Some(&s.1[..s.0 as usize]),
+            Value::LongStr(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // Lua strings may hold binary data, so this returns None for
+    // invalid UTF-8, besides non-string values
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|s| std::str::from_utf8(s).ok())
+    }
+
+    // convert strings into numbers, used by coercions in arithmetic
+    // operations and `tonumber()`
+    pub fn to_number(&self) -> Option<Value> {
         match self {
-            &Value::Nil => "nil",
-            &Value::Boolean(_) => "boolean",
-            &Value::Integer(_) => "number",
-            &Value::Float(_) => "number",
-            &Value::ShortStr(_, _) => "string",
-            &Value::MidStr(_) => "string",
-            &Value::LongStr(_) => "string",
-            &Value::Table(_) => "table",
-            &Value::RustFunction(_) => "function",
-            &Value::RustClosure(_) => "function",
-            &Value::LuaFunction(_) => "function",
-            &Value::LuaClosure(_) => "function",
+            Value::Integer(_) | Value::Float(_) => Some(self.clone()),
+            _ => self.as_bytes().and_then(str_to_number),
         }
     }
 
+    pub fn is_function(&self) -> bool {
+        matches!(self, Value::RustFunction(_) | Value::RustClosure(_) |
+            Value::LuaFunction(_) | Value::LuaClosure(_))
+    }
+
+    // the field @event of the metatable, see Table::metamethod(), of
+    // tables and userdata
+    pub fn metamethod(&self, event: &str) -> Value {
+        match self {
+            Value::Table(t) => t.borrow().metamethod(event),
+            Value::UserData(u) => match &u.borrow().metatable {
+                Some(mt) => mt.borrow().map.get(&event.into()).cloned().unwrap_or(Value::Nil),
+                None => Value::Nil,
+            }
+            _ => Value::Nil,
+        }
+    }
+
+    // Raw accesses without metamethods, for the libraries and the host.
+    // The VM uses try_index() and ExeState::index_meta() instead.
     pub fn index(&self, key: &Value) -> Value {
         match self {
             Value::Table(t) => t.borrow().index(key).clone(),
-            _ => todo!("meta __index"),
+            _ => panic!("attempt to index a {} value", self.type_name()),
         }
     }
     pub fn index_array(&self, i: i64) -> Value {
         match self {
             Value::Table(t) => t.borrow().index_array(i).clone(),
-            _ => todo!("meta __index"),
+            _ => panic!("attempt to index a {} value", self.type_name()),
         }
     }
 
     pub fn new_index(&self, key: Value, value: Value) {
         match self {
             Value::Table(t) => t.borrow_mut().new_index(key, value),
-            _ => todo!("meta __index"),
+            _ => panic!("attempt to index a {} value", self.type_name()),
         }
     }
     pub fn new_index_array(&self, i: i64, value: Value) {
         match self {
             Value::Table(t) => t.borrow_mut().new_index_array(i, value),
-            _ => todo!("meta __newindex"),
+            _ => panic!("attempt to index a {} value", self.type_name()),
         }
     }
 
+    // Fast paths of the VM's table accesses, which return None (or give
+    // back the key and value) if metamethods may be involved: the value
+    // is not a table, or the key is missing in a table with metatable.
+    pub(crate) fn try_index(&self, key: &Value) -> Option<Value> {
+        let Value::Table(t) = self else {
+            return None;
+        };
+        let t = t.borrow();
+        let v = t.index(key);
+        if *v == Value::Nil && t.metatable.is_some() {
+            return None;
+        }
+        Some(v.clone())
+    }
+    pub(crate) fn try_index_array(&self, i: i64) -> Option<Value> {
+        let Value::Table(t) = self else {
+            return None;
+        };
+        let t = t.borrow();
+        let v = t.index_array(i);
+        if *v == Value::Nil && t.metatable.is_some() {
+            return None;
+        }
+        Some(v.clone())
+    }
+    pub(crate) fn try_new_index(&self, key: Value, value: Value) -> Result<(), (Value, Value)> {
+        let Value::Table(t) = self else {
+            return Err((key, value));
+        };
+        let mut t = t.borrow_mut();
+        if t.metatable.is_some() && *t.index(&key) == Value::Nil {
+            return Err((key, value));
+        }
+        t.new_index(key, value);
+        Ok(())
+    }
+
     pub fn concat(&self, v2: &Self) -> Self {
-        let s1: &[u8] = self.as_ref();
-
-        match v2 {
-            Value::Integer(_) => todo!("int"),
-            Value::Float(_) => todo!("float"),
-            _ => {
-                let s2: &[u8] = v2.as_ref();
-                let l1 = s1.len();
-                let l2 = s2.len();
-                if l1 + l2 < MID_STR_MAX {
-                    let mut buf = [0; MID_STR_MAX];
-                    buf[..l1].copy_from_slice(s1);
-                    buf[l1..l1+l2].copy_from_slice(s2);
-                    buf[..l1+l2].into()
-                } else {
-                    [s1, s2].concat().into()
-                }
-            }
+        let (mut n1, mut n2) = (String::new(), String::new());
+        let s1 = concat_operand(self, &mut n1);
+        let s2 = concat_operand(v2, &mut n2);
+        let (l1, l2) = (s1.len(), s2.len());
+        let len = l1 + l2;
+
+        // assemble short and middle strings in place directly, to avoid
+        // allocating a temprary buffer
+        if len <= SHORT_STR_MAX {
+            let mut buf = [0; SHORT_STR_MAX];
+            buf[..l1].copy_from_slice(s1);
+            buf[l1..len].copy_from_slice(s2);
+            Value::ShortStr(len as u8, buf)
+
+        } else if len <= MID_STR_MAX {
+            count_string();
+            let mut s = Rc::new((len as u8, [0; MID_STR_MAX]));
+            let buf = &mut Rc::get_mut(&mut s).unwrap().1;
+            buf[..l1].copy_from_slice(s1);
+            buf[l1..len].copy_from_slice(s2);
+            Value::MidStr(s)
+
+        } else {
+            count_string();
+            let mut buf = Vec::with_capacity(len);
+            buf.extend_from_slice(s1);
+            buf.extend_from_slice(s2);
+            Value::LongStr(Rc::new(buf))
+        }
+    }
+}
+
+// strings and numbers can be concatenated, while numbers are formatted
+// into @buf
+fn concat_operand<'a>(v: &'a Value, buf: &'a mut String) -> &'a [u8] {
+    if let Some(s) = v.as_bytes() {
+        return s;
+    }
+    match v {
+        Value::Integer(_) | Value::Float(_) => {
+            *buf = v.to_string();
+            buf.as_bytes()
         }
+        _ => panic!("attempt to concatenate a {} value", v.type_name()),
     }
 }
 
@@ -255,10 +688,21 @@ impl Hash for Value {
             Value::RustClosure(f) => Rc::as_ptr(f).hash(state),
             Value::LuaFunction(f) => Rc::as_ptr(f).hash(state),
             Value::LuaClosure(f) => Rc::as_ptr(f).hash(state),
+            Value::Coroutine(c) => Rc::as_ptr(c).hash(state),
+            Value::UserData(u) => Rc::as_ptr(u).hash(state),
         }
     }
 }
 
+// new table, which is tracked by the collector, see gc.rs
+impl From<Table> for Value {
+    fn from(t: Table) -> Self {
+        let t = Rc::new(RefCell::new(t));
+        gc::track_table(&t);
+        Value::Table(t)
+    }
+}
+
 impl From<()> for Value {
     fn from(_: ()) -> Self {
         Value::Nil
@@ -282,11 +726,31 @@ impl From<i64> for Value {
         Value::Integer(n)
     }
 }
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Integer(n.into())
+    }
+}
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Integer(n.into())
+    }
+}
+// sizes larger than i64::MAX, which are not possible in practice, are
+// converted to float, as Lua does for unsigned integers out of range
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        i64::try_from(n).map_or(Value::Float(n as f64), Value::Integer)
+    }
+}
 
 // convert &[u8], Vec<u8>, &str and String into Value
 impl From<&[u8]> for Value {
     fn from(v: &[u8]) -> Self {
-        vec_to_short_mid_str(v).unwrap_or_else(||Value::LongStr(Rc::new(v.to_vec())))
+        vec_to_short_mid_str(v).unwrap_or_else(|| {
+            count_string();
+            Value::LongStr(Rc::new(v.to_vec()))
+        })
     }
 }
 impl From<&str> for Value {
@@ -297,7 +761,10 @@ impl From<&str> for Value {
 
 impl From<Vec<u8>> for Value {
     fn from(v: Vec<u8>) -> Self {
-        vec_to_short_mid_str(&v).unwrap_or_else(||Value::LongStr(Rc::new(v)))
+        vec_to_short_mid_str(&v).unwrap_or_else(|| {
+            count_string();
+            Value::LongStr(Rc::new(v))
+        })
     }
 }
 impl From<String> for Value {
@@ -314,6 +781,7 @@ fn vec_to_short_mid_str(v: &[u8]) -> Option<Value> {
         Some(Value::ShortStr(len as u8, buf))
 
     } else if len <= MID_STR_MAX {
+        count_string();
         let mut buf = [0; MID_STR_MAX];
         buf[..len].copy_from_slice(v);
         Some(Value::MidStr(Rc::new((len as u8, buf))))
@@ -325,18 +793,7 @@ fn vec_to_short_mid_str(v: &[u8]) -> Option<Value> {
 
 impl AsRef<[u8]> for Value {
     fn as_ref(&self) -> &[u8] {
-        match self {
-            Value::ShortStr(len, buf) => &buf[..*len as usize],
-            Value::MidStr(s) => &s.1[..s.0 as usize],
-            Value::LongStr(s) => s,
-            _ => panic!("invalid string Value"),
-        }
-    }
-}
-
-impl AsRef<str> for Value {
-    fn as_ref(&self) -> &str {
-        std::str::from_utf8(self.as_ref()).unwrap()
+        self.as_bytes().expect("invalid string Value")
     }
 }
 
@@ -350,11 +807,125 @@ impl From<&Value> for i64 {
     fn from(v: &Value) -> Self {
         match v {
             Value::Integer(i) => *i,
-            Value::Float(f) => *f as i64,
-            Value::ShortStr(_, _) => todo!("tonumber"),
-            Value::MidStr(_) => todo!("tonumber"),
-            Value::LongStr(_) => todo!("tonumber"),
-            _ => panic!("invalid string Value"),
+            Value::Float(f) => ftoi(*f)
+                .unwrap_or_else(|| panic!("number has no integer representation")),
+            Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_) =>
+                match v.to_number() {
+                    Some(n) => (&n).into(),
+                    None => panic!("invalid number string"),
+                }
+            _ => panic!("invalid number Value"),
+        }
+    }
+}
+
+// Error of converting Value into Rust types by TryFrom, e.g.
+// `i32::try_from(v)`, for embedders and library functions to avoid
+// silent truncation by `as`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    // the type of the value can not be converted, e.g. table into i64
+    Type { from: &'static str, to: &'static str },
+
+    // the number is not integral, or is out of the range of the target
+    Range { to: &'static str },
+
+    // the string is not valid UTF-8, for String
+    Utf8,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConversionError::Type { from, to } => write!(f, "{from} can not be converted to {to}"),
+            ConversionError::Range { to } => write!(f, "number has no {to} representation"),
+            ConversionError::Utf8 => write!(f, "string is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+// Integers are converted from integers, floats with exact integer values
+// and strings convertible to them, same with `lua_tointegerx()`.
+fn to_integer(v: &Value, to: &'static str) -> Result<i64, ConversionError> {
+    match v.to_number() {
+        Some(Value::Integer(i)) => Ok(i),
+        Some(Value::Float(f)) => ftoi(f).ok_or(ConversionError::Range { to }),
+        _ => Err(ConversionError::Type { from: v.type_name(), to }),
+    }
+}
+
+macro_rules! impl_try_from_integer {
+    ($($t:ty),*) => { $(
+        impl TryFrom<Value> for $t {
+            type Error = ConversionError;
+            fn try_from(v: Value) -> Result<Self, Self::Error> {
+                let to = stringify!($t);
+                let i = to_integer(&v, to)?;
+                <$t>::try_from(i).map_err(|_| ConversionError::Range { to })
+            }
+        }
+    )* }
+}
+impl_try_from_integer!(i64, i32, u32, usize);
+
+// floats from numbers and strings convertible to numbers
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.to_number() {
+            Some(Value::Integer(i)) => Ok(i as f64),
+            Some(Value::Float(f)) => Ok(f),
+            _ => Err(ConversionError::Type { from: v.type_name(), to: "f64" }),
+        }
+    }
+}
+
+// any value, where only nil and false are false
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        Ok((&v).into())
+    }
+}
+
+// strings, and numbers converted to strings, same with `lua_tolstring()`
+impl TryFrom<Value> for Vec<u8> {
+    type Error = ConversionError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v {
+            Value::Integer(_) | Value::Float(_) => Ok(v.to_string().into_bytes()),
+            Value::LongStr(s) => Ok(Rc::try_unwrap(s).unwrap_or_else(|s| s.to_vec())),
+            _ => match v.as_bytes() {
+                Some(s) => Ok(s.to_vec()),
+                None => Err(ConversionError::Type { from: v.type_name(), to: "Vec<u8>" }),
+            }
         }
     }
 }
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        let from = v.type_name();
+        let s = Vec::try_from(v).map_err(|_| ConversionError::Type { from, to: "String" })?;
+        String::from_utf8(s).map_err(|_| ConversionError::Utf8)
+    }
+}
+
+// borrowed strings, without the conversion of numbers
+impl<'a> TryFrom<&'a Value> for &'a [u8] {
+    type Error = ConversionError;
+    fn try_from(v: &'a Value) -> Result<Self, Self::Error> {
+        v.as_bytes().ok_or(ConversionError::Type { from: v.type_name(), to: "&[u8]" })
+    }
+}
+
+impl<'a> TryFrom<&'a Value> for &'a str {
+    type Error = ConversionError;
+    fn try_from(v: &'a Value) -> Result<Self, Self::Error> {
+        let s = v.as_bytes().ok_or(ConversionError::Type { from: v.type_name(), to: "&str" })?;
+        std::str::from_utf8(s).map_err(|_| ConversionError::Utf8)
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use crate::bytecode::ByteCode;
+use crate::parse::FuncProto;
+use crate::value::Value;
+
+// Static verifier for chunks from untrusted users, which rejects the
+// chunks using denied global variables or constructs before running
+// them, and reports all the violations with line numbers, e.g.
+//
+//     let verifier = Verifier::sandbox().deny_global_assignment();
+//     if let Err(violations) = verifier.verify(&proto) {
+//         for v in violations {
+//             println!("{v}"); // "line 3: access to global 'load'"
+//         }
+//     }
+//
+// This is an addition to the runtime sandboxing, e.g. a restricted
+// global table, but not a replacement of it. The checks are by names:
+//   - denied globals are rejected in any access, read or assignment;
+//   - denied fields, e.g. `string.dump`, are rejected when indexing a
+//     table by the field name, unless the table is known to be another
+//     global, e.g. `t.dump` is rejected but `io.dump` is not. Dynamic
+//     indexing of these globals, e.g. `string[k]`, is rejected too;
+//   - using `_ENV` explicitly is rejected, since it may access globals
+//     by computed names.
+//
+// Values that escape the tracking, e.g. a denied field got by a
+// computed key of an unknown table, are not detected. Chunks without
+// debug information are rejected, since globals are found by names.
+#[derive(Debug, Default)]
+pub struct Verifier {
+    globals: Vec<String>,
+    fields: Vec<(String, String)>,
+    global_assignment: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub line: usize, // 0 for the chunk
+    pub msg: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+// where the value in a register comes from
+enum Origin<'a> {
+    Global(&'a str),
+    Unknown,
+}
+
+impl Verifier {
+    // verifier denying nothing but explicit `_ENV`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // verifier for scripts which should only compute, denying loading
+    // code, metatables, raw accesses, and the libraries of files,
+    // processes, network and debugging
+    pub fn sandbox() -> Self {
+        let mut v = Self::new();
+        for name in ["load", "loadstring", "loadfile", "dofile", "require",
+                "setmetatable", "getmetatable", "rawget", "rawset", "rawequal",
+                "collectgarbage", "debug", "io", "os", "package", "http", "fs"] {
+            v = v.deny_global(name);
+        }
+        v.deny_field("string", "dump")
+    }
+
+    pub fn deny_global(mut self, name: &str) -> Self {
+        self.globals.push(name.to_string());
+        self
+    }
+
+    // deny the field of the global table, e.g. ("string", "dump")
+    pub fn deny_field(mut self, global: &str, field: &str) -> Self {
+        self.fields.push((global.to_string(), field.to_string()));
+        self
+    }
+
+    // deny assigning global variables, so scripts can not pollute the
+    // global table, while reading is allowed
+    pub fn deny_global_assignment(mut self) -> Self {
+        self.global_assignment = true;
+        self
+    }
+
+    // Check the function and its inner functions, and return all the
+    // violations in the order of lines.
+    pub fn verify(&self, proto: &FuncProto) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        self.verify_function(proto, &mut violations);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        violations.sort_by_key(|v| v.line);
+        Err(violations)
+    }
+
+    fn verify_function(&self, proto: &FuncProto, violations: &mut Vec<Violation>) {
+        if proto.lines.len() != proto.byte_codes.len() {
+            violations.push(Violation { line: 0, msg: "no debug information".into() });
+            return;
+        }
+
+        let const_str = |k: u8| proto.constants.get(k as usize)
+            .and_then(Value::as_str).unwrap_or("?");
+        let is_env_up = |up: u8| proto.upvalue_names.get(up as usize).is_some_and(|n| n == "_ENV");
+        let is_env_reg = |reg: u8, icode: usize| proto.local_name(reg, icode) == Some("_ENV");
+
+        for (icode, &code) in proto.byte_codes.iter().enumerate() {
+            let mut report = |msg: String| violations.push(Violation {
+                line: proto.lines[icode] as usize,
+                msg,
+            });
+
+            // global accesses, and fields of tables
+            match code {
+                ByteCode::GetUpField(_, t, k) if is_env_up(t) =>
+                    self.check_global(const_str(k), false, &mut report),
+                ByteCode::SetUpField(t, k, _) | ByteCode::SetUpFieldConst(t, k, _) if is_env_up(t) =>
+                    self.check_global(const_str(k), true, &mut report),
+                ByteCode::GetField(_, t, k) if is_env_reg(t, icode) =>
+                    self.check_global(const_str(k), false, &mut report),
+                ByteCode::SetField(t, k, _) | ByteCode::SetFieldConst(t, k, _) if is_env_reg(t, icode) =>
+                    self.check_global(const_str(k), true, &mut report),
+
+                ByteCode::GetUpField(_, _, k) | ByteCode::SetUpField(_, k, _) |
+                        ByteCode::SetUpFieldConst(_, k, _) =>
+                    self.check_field(Origin::Unknown, const_str(k), &mut report),
+                ByteCode::GetField(_, t, k) | ByteCode::GetFieldSelf(_, t, k) |
+                        ByteCode::SetField(t, k, _) | ByteCode::SetFieldConst(t, k, _) =>
+                    self.check_field(origin(proto, t, icode), const_str(k), &mut report),
+                ByteCode::GetTable(_, t, _) | ByteCode::SetTable(t, _, _) |
+                        ByteCode::SetTableConst(t, _, _) =>
+                    if let Origin::Global(g) = origin(proto, t, icode) {
+                        if self.fields.iter().any(|(fg, _)| fg == g) {
+                            report(format!("dynamic index of global '{g}'"));
+                        }
+                    }
+                _ => (),
+            }
+
+            // explicit `_ENV`, as a value but not for global accesses
+            let explicit = match code {
+                ByteCode::GetUpvalue(_, up) | ByteCode::SetUpvalue(up, _) |
+                    ByteCode::SetUpvalueConst(up, _) => is_env_up(up),
+                ByteCode::GetField(dst, _, _) => is_env_reg(dst, icode),
+                ByteCode::SetField(_, _, v) => is_env_reg(v, icode),
+                ByteCode::SetFieldConst(_, _, _) => false,
+                _ => code.registers().into_iter().any(|r| is_env_reg(r, icode)),
+            };
+            if explicit {
+                report("use of '_ENV'".into());
+            }
+        }
+
+        // locals named `_ENV` but the main function's parameter, which
+        // redirect the global accesses
+        let start = if proto.nparam > 0 && proto.locals.first().is_some_and(|v| v.name == "_ENV") {
+            1
+        } else {
+            0
+        };
+        for var in proto.locals[start..].iter().filter(|v| v.name == "_ENV") {
+            // the local is in scope after the byte code initializing it
+            let line = proto.lines.get(var.icode_start.saturating_sub(1)).copied().unwrap_or(0);
+            violations.push(Violation { line: line as usize, msg: "use of '_ENV'".into() });
+        }
+
+        for c in &proto.constants {
+            if let Value::LuaFunction(f) = c {
+                self.verify_function(f, violations);
+            }
+        }
+    }
+
+    fn check_global(&self, name: &str, assign: bool, report: &mut impl FnMut(String)) {
+        if self.globals.iter().any(|g| g == name) {
+            report(format!("access to global '{name}'"));
+        } else if assign && self.global_assignment {
+            report(format!("assignment to global '{name}'"));
+        }
+    }
+
+    fn check_field(&self, table: Origin, field: &str, report: &mut impl FnMut(String)) {
+        for (g, f) in &self.fields {
+            if f != field {
+                continue;
+            }
+            match table {
+                Origin::Global(name) if name != g => (),
+                _ => report(format!("access to '{g}.{f}'")),
+            }
+        }
+    }
+}
+
+// Track where the value in register @reg before byte code @icode comes
+// from, backward until any jump, same with FuncProto::describe_reg().
+fn origin(proto: &FuncProto, reg: u8, icode: usize) -> Origin<'_> {
+    let const_str = |k: u8| proto.constants.get(k as usize).and_then(Value::as_str);
+    for i in (0..icode).rev() {
+        let code = proto.byte_codes[i];
+        if code.is_jump() {
+            break;
+        }
+        if code.dst() != Some(reg) {
+            continue;
+        }
+        let global = match code {
+            ByteCode::GetUpField(_, t, k) if proto.upvalue_names.get(t as usize)
+                .is_some_and(|n| n == "_ENV") => const_str(k),
+            ByteCode::GetField(_, t, k) if proto.local_name(t, i) == Some("_ENV") => const_str(k),
+            _ => None,
+        };
+        return global.map_or(Origin::Unknown, Origin::Global);
+    }
+    Origin::Unknown
+}
@@ -1,27 +1,73 @@
+use std::any::{self, Any, TypeId};
+use std::collections::HashMap;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell, RefMut};
 use std::cmp::Ordering;
+use std::mem;
+use std::io::{self, BufWriter, Write};
+use std::fs;
+use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::{Duration, Instant};
 use crate::bytecode::ByteCode;
-use crate::value::{Value, Table};
-use crate::parse::{FuncProto, UpIndex};
-use crate::utils::{ftoi, set_vec};
+use crate::value::{self, Value, Table, TableHandle, UserData};
+use crate::parse::{self, FuncProto, UpIndex};
+use crate::compiler::{Compiler, DefaultCompiler};
+use crate::convert::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti};
+use crate::error::LuaError;
+use crate::utils::{ftoi, set_vec, int_div, int_mod, float_div, float_mod, shift_left, shift_right};
+use crate::stdlib::{self, math::Random, timer::Timers};
+use crate::memory;
+use crate::gc;
 
 // TODO move these library functions out
 fn lib_print(state: &mut ExeState) -> i32 {
     for i in 1 ..= state.get_top() {
         if i != 1 {
-            print!("\t");
+            state.write_output(b"\t");
         }
-        print!("{}", state.get::<&Value>(i).to_string());
+        state.write_value(i);
     }
-    println!("");
+    state.write_output(b"\n");
     0
 }
 fn lib_type(state: &mut ExeState) -> i32 {
-    let ty = state.get::<&Value>(1).ty();
+    let ty = state.get::<&Value>(1).type_name();
     state.push(ty);
     1
 }
+fn lib_tonumber(state: &mut ExeState) -> i32 {
+    let v = state.get::<&Value>(1);
+    let n = if state.get_top() >= 2 {
+        let base: i64 = state.get(2);
+        let Some(s) = v.as_bytes() else {
+            panic!("bad argument #1 to 'tonumber' (string expected, got {})", v.type_name());
+        };
+        str_to_int_base(s, base as u32).map(Value::Integer)
+    } else {
+        v.to_number()
+    };
+    state.push(n.unwrap_or(Value::Nil));
+    1
+}
+// integer in base 2..36, for `tonumber(e, base)`
+fn str_to_int_base(s: &[u8], base: u32) -> Option<i64> {
+    assert!((2..=36).contains(&base), "base out of range");
+    let s = std::str::from_utf8(s).ok()?.trim_matches([' ', '\t', '\n', '\r', '\x0b', '\x0c']);
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    if s.is_empty() {
+        return None;
+    }
+    let mut n: i64 = 0;
+    for ch in s.chars() {
+        n = n.wrapping_mul(base as i64).wrapping_add(ch.to_digit(base)? as i64);
+    }
+    Some(if neg { n.wrapping_neg() } else { n })
+}
 fn test_new_counter(state: &mut ExeState) -> i32 {
     let mut i = 0_i32;
     let c = move |_: &mut ExeState| {
@@ -38,15 +84,15 @@ fn ipairs_aux(state: &mut ExeState) -> i32 {
         _ => panic!("ipairs non-table"),
     };
 
-    let i: i64 = state.get(2);
-    if i < 0 || i as usize >= table.array.len() {
+    // stop at the first nil, which may be a hole in the array part
+    let i = state.get::<i64>(2) + 1;
+    let v = table.index_array(i).clone();
+    drop(table);
+    if v == Value::Nil {
         return 0;
     }
 
-    let v = table.array[i as usize].clone();
-    drop(table);
-
-    state.push(i + 1);
+    state.push(i);
     state.push(v);
     2
 }
@@ -58,6 +104,421 @@ fn ipairs(state: &mut ExeState) -> i32 {
     3
 }
 
+// range(stop) or range(start, stop [, step])
+//
+// Return an iterator function of the integers from @start, 1 by default,
+// to @stop by @step, same with the numeric `for`. Unlike `ipairs()`, it
+// keeps the position itself, so it's a generator needing no arguments,
+// e.g. for `coroutine.wrap()`.
+fn lib_range(state: &mut ExeState) -> i32 {
+    let (start, stop) = if state.get_top() >= 2 {
+        (state.get::<i64>(1), state.get::<i64>(2))
+    } else {
+        (1, state.get::<i64>(1))
+    };
+    let step = if state.get_top() >= 3 { state.get::<i64>(3) } else { 1 };
+    if step == 0 {
+        panic!("bad argument #3 to 'range' (step is zero)");
+    }
+
+    // None after overflowing
+    let mut next = Some(start);
+    let c = move |state: &mut ExeState| match next {
+        Some(i) if (step > 0 && i <= stop) || (step < 0 && i >= stop) => {
+            next = i.checked_add(step);
+            state.push(i);
+            1
+        }
+        _ => 0,
+    };
+    state.push(Value::RustClosure(Rc::new(RefCell::new(Box::new(c)))));
+    1
+}
+
+// next(table [, key]), see Table::next()
+fn lib_next(state: &mut ExeState) -> i32 {
+    let Value::Table(t) = state.get::<&Value>(1).clone() else {
+        panic!("bad argument #1 to 'next' (table expected, got {})",
+            state.get::<&Value>(1).type_name());
+    };
+    let key = if state.get_top() >= 2 {
+        state.get::<&Value>(2).clone()
+    } else {
+        Value::Nil
+    };
+    let entry = t.borrow_mut().next(&key);
+    match entry {
+        Some((k, v)) => {
+            state.push(k);
+            state.push(v);
+            2
+        }
+        None => {
+            state.push(Value::Nil);
+            1
+        }
+    }
+}
+
+fn pairs(state: &mut ExeState) -> i32 {
+    let t = state.get::<&Value>(1).clone();
+    if !matches!(t, Value::Table(_)) {
+        panic!("bad argument #1 to 'for iterator' (table expected, got {})", t.type_name());
+    }
+    state.push(Value::RustFunction(lib_next));
+    state.push(t);
+    state.push(Value::Nil);
+    3
+}
+
+// setmetatable(table, metatable)
+//
+// The metatable may be nil to remove it. Same with the official Lua,
+// protected metatables, with the `__metatable` field, can not be changed.
+fn lib_setmetatable(state: &mut ExeState) -> i32 {
+    let Value::Table(t) = state.get::<&Value>(1).clone() else {
+        panic!("bad argument #1 to 'setmetatable' (table expected, got {})",
+            state.get::<&Value>(1).type_name());
+    };
+    if state.get_top() < 2 {
+        panic!("bad argument #2 to 'setmetatable' (nil or table expected)");
+    }
+    let mt = match state.get::<&Value>(2) {
+        Value::Table(mt) => Some(mt.clone()),
+        Value::Nil => None,
+        v => panic!("bad argument #2 to 'setmetatable' (nil or table expected, got {})",
+            v.type_name()),
+    };
+    if t.borrow().metamethod("__metatable") != Value::Nil {
+        panic!("cannot change a protected metatable");
+    }
+    t.borrow_mut().metatable = mt;
+    state.set_top(1);
+    1
+}
+
+// getmetatable(object)
+//
+// Return the `__metatable` field if any, or the metatable, or nil.
+fn lib_getmetatable(state: &mut ExeState) -> i32 {
+    let mt = state.metatable_of(state.get(1));
+    let v = match mt {
+        Some(mt) => match mt.borrow().map.get(&"__metatable".into()) {
+            Some(protected) => protected.clone(),
+            None => Value::Table(mt.clone()),
+        }
+        None => Value::Nil,
+    };
+    state.push(v);
+    1
+}
+
+// select(n, ...)
+//
+// Return the arguments after the @n-th one, or from the end if @n is
+// negative, or the number of the arguments if @n is "#".
+fn lib_select(state: &mut ExeState) -> i32 {
+    let n = state.get_top() as i64 - 1;
+    let v = state.get::<&Value>(1);
+    if v.as_bytes() == Some(b"#") {
+        state.push(n);
+        return 1;
+    }
+    let i = state.check_integer(1, "select");
+    if let Some(neg) = i.checked_neg().filter(|&neg| neg > 0 && neg <= n) {
+        neg as i32
+    } else if i > 0 {
+        (n - i + 1).max(0) as i32
+    } else {
+        panic!("bad argument #1 to 'select' (index out of range)");
+    }
+}
+
+// pcall(f, ...)
+//
+// Call @f with the arguments in protected mode. Return true and the
+// return values, or false and the error value. Same with Lua 5.1, it's
+// not allowed to yield inside, see check_yield().
+fn lib_pcall(state: &mut ExeState) -> i32 {
+    if state.get_top() == 0 {
+        panic!("bad argument #1 to 'pcall' (value expected)");
+    }
+    state.stack.insert(state.base, Value::Boolean(true));
+    match state.pcall_limit(2, ExecLimit::Instructions(u64::MAX)) {
+        Ok(nret) => nret as i32 + 1,
+        Err(err) => {
+            state.push(false);
+            state.push(err.into_value());
+            2
+        }
+    }
+}
+
+// xpcall(f, msgh, ...)
+//
+// Same with pcall(), but the error value is passed to the message
+// handler @msgh, whose return value is returned instead. The handler is
+// called after the stack is unwound, so it can not get the traceback.
+fn lib_xpcall(state: &mut ExeState) -> i32 {
+    if state.get_top() < 2 {
+        panic!("bad argument #2 to 'xpcall' (value expected)");
+    }
+    let handler = state.stack.remove(state.base + 1);
+    state.stack.insert(state.base, Value::Boolean(true));
+    match state.pcall_limit(2, ExecLimit::Instructions(u64::MAX)) {
+        Ok(nret) => nret as i32 + 1,
+        Err(err) => {
+            let v = match state.pcall(handler, &[err.into_value()]) {
+                Ok(rets) => rets.into_iter().next().unwrap_or(Value::Nil),
+                Err(_) => "error in error handling".into(),
+            };
+            state.push(false);
+            state.push(v);
+            2
+        }
+    }
+}
+
+// error(message [, level])
+//
+// Raise an error with any value as the error object. If it's a string,
+// the position where the error is raised is added at the beginning,
+// which is of the function calling error() by @level 1 as default, or
+// of its caller by 2, and so on. Level 0 adds no position.
+fn lib_error(state: &mut ExeState) -> i32 {
+    let value = if state.get_top() >= 1 {
+        state.get::<&Value>(1).clone()
+    } else {
+        Value::Nil
+    };
+    let level = match state.get_top() {
+        0 | 1 => 1,
+        _ if state.get::<&Value>(2) == &Value::Nil => 1,
+        _ => state.get::<i64>(2),
+    };
+    state.raise_error(value, usize::try_from(level).unwrap_or(0))
+}
+
+// load(chunk [, chunkname [, mode [, env]]])
+//
+// Compile the @chunk into a function, without running it. The @chunk is
+// a string, or a function returning the pieces of it until nil or an
+// empty string. The @mode is "t" for source code only, "b" for binary
+// chunks only, or "bt" for both by default, while the compiler of the
+// state may limit it more, see ExeStateBuilder::compiler(). The function
+// runs with @env as `_ENV`, which is the global table by default.
+//
+// Return the function, or nil and the message of the syntax error.
+fn lib_load(state: &mut ExeState) -> i32 {
+    let (chunk, default_name) = match state.check::<Value>(1, "load") {
+        f @ (Value::LuaFunction(_) | Value::LuaClosure(_) | Value::RustFunction(_) |
+                Value::RustClosure(_)) => (read_pieces(state, f), "=(load)".into()),
+        v => match v.as_bytes() {
+            Some(s) => (s.to_vec(), String::from_utf8_lossy(s).into_owned()),
+            None => panic!("bad argument #1 to 'load' (string expected, got {})", v.type_name()),
+        }
+    };
+    let chunk_name = state.check::<Option<String>>(2, "load").unwrap_or(default_name);
+    let mode = state.check::<Option<String>>(3, "load").unwrap_or_else(|| "bt".into());
+    let env = if state.get_top() >= 4 { state.get::<&Value>(4).clone() } else { state.env() };
+    let result = load_chunk(state, &chunk, &chunk_name, &mode);
+    push_loaded(state, result, env)
+}
+
+// the chunk of the pieces returned by the reader function @f
+fn read_pieces(state: &mut ExeState, f: Value) -> Vec<u8> {
+    let mut chunk = Vec::new();
+    loop {
+        let piece = state.call(f.clone(), &[]).into_iter().next().unwrap_or(Value::Nil);
+        match piece.as_bytes() {
+            Some(b"") => break,
+            Some(s) => chunk.extend_from_slice(s),
+            None if piece == Value::Nil => break,
+            None => panic!("reader function must return a string"),
+        }
+    }
+    chunk
+}
+
+fn load_chunk(state: &ExeState, chunk: &[u8], chunk_name: &str, mode: &str) -> Result<FuncProto, String> {
+    let kind = if crate::dump::is_binary(chunk) { "binary" } else { "text" };
+    if !mode.contains(&kind[..1]) {
+        return Err(format!("attempt to load a {kind} chunk (mode is '{mode}')"));
+    }
+    state.try_load(chunk, chunk_name).map_err(|e| e.to_string())
+}
+
+// push the function of the loaded chunk, or nil and the message
+fn push_loaded(state: &mut ExeState, result: Result<FuncProto, String>, env: Value) -> i32 {
+    match result {
+        Ok(proto) => {
+            let f = chunk_function(state, Rc::new(proto), env);
+            state.push(f);
+            1
+        }
+        Err(msg) => {
+            state.push(());
+            state.push(msg);
+            2
+        }
+    }
+}
+
+// The function running the main chunk @proto, which gets `_ENV` as its
+// first parameter, followed by the arguments as the varargs.
+fn chunk_function(state: &ExeState, proto: Rc<FuncProto>, env: Value) -> Value {
+    state.create_function(move |state| {
+        state.stack.insert(state.base, Value::LuaFunction(proto.clone()));
+        state.stack.insert(state.base + 1, env.clone());
+        state.call_at(1) as i32
+    })
+}
+
+// loadstring(s [, chunkname])
+//
+// Same with `load()` of a string, for Lua 5.1.
+fn lib_loadstring(state: &mut ExeState) -> i32 {
+    let s: Vec<u8> = state.check(1, "loadstring");
+    let chunk_name = state.check::<Option<String>>(2, "loadstring")
+        .unwrap_or_else(|| String::from_utf8_lossy(&s).into_owned());
+    let result = load_chunk(state, &s, &chunk_name, "bt");
+    let env = state.env();
+    push_loaded(state, result, env)
+}
+
+// the content of the file, or the standard input if no @filename
+fn read_chunk(filename: Option<&str>) -> Result<(Vec<u8>, String), String> {
+    match filename {
+        Some(filename) => fs::read(filename)
+            .map(|chunk| (chunk, format!("@{filename}")))
+            .map_err(|e| format!("cannot open {filename}: {}", stdlib::io::error_message(&e))),
+        None => {
+            let mut chunk = Vec::new();
+            io::Read::read_to_end(&mut io::stdin(), &mut chunk)
+                .map(|_| (chunk, "=stdin".into()))
+                .map_err(|e| format!("cannot read stdin: {}", stdlib::io::error_message(&e)))
+        }
+    }
+}
+
+// loadfile([filename [, mode [, env]]])
+//
+// Same with `load()`, but the chunk is the content of the file, or the
+// standard input if no @filename.
+fn lib_loadfile(state: &mut ExeState) -> i32 {
+    let filename = state.check::<Option<String>>(1, "loadfile");
+    let mode = state.check::<Option<String>>(2, "loadfile").unwrap_or_else(|| "bt".into());
+    let env = if state.get_top() >= 3 { state.get::<&Value>(3).clone() } else { state.env() };
+    let result = read_chunk(filename.as_deref())
+        .and_then(|(chunk, chunk_name)| load_chunk(state, &chunk, &chunk_name, &mode));
+    push_loaded(state, result, env)
+}
+
+// dofile([filename])
+//
+// Run the file, or the standard input if no @filename, and return its
+// return values. Errors are raised, including the syntax errors, same
+// with the official Lua.
+fn lib_dofile(state: &mut ExeState) -> i32 {
+    let filename = state.check::<Option<String>>(1, "dofile");
+    let proto = read_chunk(filename.as_deref())
+        .and_then(|(chunk, chunk_name)| load_chunk(state, &chunk, &chunk_name, "bt"))
+        .unwrap_or_else(|msg| state.raise_error(msg.into(), 0));
+    state.set_top(0);
+    state.push(Value::LuaFunction(Rc::new(proto)));
+    let env = state.env();
+    state.push(env);
+    state.call_at(1) as i32
+}
+
+// collectgarbage([opt])
+//
+// Control the collector of reference cycles, see gc.rs, by @opt:
+// "collect" (default) and "step" run a full collection, "count" returns
+// the memory in use in Kbytes, see ExeState::memory_used(), and "stop",
+// "restart" and "isrunning" for the automatic collections.
+fn lib_collectgarbage(state: &mut ExeState) -> i32 {
+    let opt = if state.get_top() >= 1 && state.get::<&Value>(1) != &Value::Nil {
+        match state.get::<&Value>(1).as_bytes() {
+            Some(opt) => String::from_utf8_lossy(opt).into_owned(),
+            None => panic!("bad argument #1 to 'collectgarbage' (string expected, got {})",
+                state.get::<&Value>(1).type_name()),
+        }
+    } else {
+        String::from("collect")
+    };
+    let v = match opt.as_str() {
+        "collect" => {
+            state.collect_garbage();
+            Value::Integer(0)
+        }
+        "step" => {
+            state.collect_garbage();
+            Value::Boolean(true)
+        }
+        "count" => Value::Float(state.memory_used() as f64 / 1024.0),
+        "stop" | "restart" => {
+            gc::set_stopped(opt == "stop");
+            Value::Integer(0)
+        }
+        "isrunning" => Value::Boolean(gc::is_running()),
+        _ => panic!("bad argument #1 to 'collectgarbage' (invalid option '{opt}')"),
+    };
+    state.push(v);
+    1
+}
+
+// vmstats()
+//
+// Return a table of the statistics, see ExeStats.
+fn lib_vmstats(state: &mut ExeState) -> i32 {
+    let stats = state.stats();
+    let mut t = Table::new(0, 7);
+    for (name, n) in [
+        ("instructions", stats.instructions),
+        ("calls", stats.calls),
+        ("peak_call_depth", stats.peak_call_depth as u64),
+        ("peak_stack_size", stats.peak_stack_size as u64),
+        ("tables", stats.tables),
+        ("strings", stats.strings),
+        ("gc_cycles", stats.gc_cycles),
+    ] {
+        t.map.insert(name.into(), Value::Integer(n as i64));
+    }
+    state.push(Value::from(t));
+    1
+}
+
+// rawget(table, index)
+fn lib_rawget(state: &mut ExeState) -> i32 {
+    state.set_top(2);
+    let Value::Table(t) = state.get::<&Value>(1) else {
+        panic!("bad argument #1 to 'rawget' (table expected, got {})",
+            state.get::<&Value>(1).type_name());
+    };
+    let v = t.borrow().index(state.get::<&Value>(2)).clone();
+    state.push(v);
+    1
+}
+
+// rawset(table, index, value)
+fn lib_rawset(state: &mut ExeState) -> i32 {
+    state.set_top(3);
+    let Value::Table(t) = state.get::<&Value>(1).clone() else {
+        panic!("bad argument #1 to 'rawset' (table expected, got {})",
+            state.get::<&Value>(1).type_name());
+    };
+    let key = state.get::<&Value>(2).clone();
+    match key {
+        Value::Nil => panic!("index is nil"),
+        Value::Float(f) if f.is_nan() => panic!("index is NaN"),
+        _ => (),
+    }
+    t.borrow_mut().new_index(key, state.get::<&Value>(3).clone());
+    state.set_top(1);
+    1
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Upvalue {
     Open(usize),
@@ -65,13 +526,13 @@ pub enum Upvalue {
 }
 
 impl Upvalue {
-    fn get<'a>(&'a self, stack: &'a Vec<Value>) -> &'a Value {
+    fn get<'a>(&'a self, stack: &'a [Value]) -> &'a Value {
         match self {
             Upvalue::Open(i) => &stack[*i],
-            Upvalue::Closed(v) => &v,
+            Upvalue::Closed(v) => v,
         }
     }
-    fn set(&mut self, stack: &mut Vec<Value>, value: Value) {
+    fn set(&mut self, stack: &mut [Value], value: Value) {
         match self {
             Upvalue::Open(i) => stack[*i] = value,
             Upvalue::Closed(v) => *v = value,
@@ -89,74 +550,851 @@ struct OpenBroker {
 
 impl From<usize> for OpenBroker {
     fn from(ilocal: usize) -> Self {
-        OpenBroker {
-            ilocal,
-            broker: Rc::new(RefCell::new(Upvalue::Open(ilocal))),
-        }
+        let broker = Rc::new(RefCell::new(Upvalue::Open(ilocal)));
+        gc::track_upvalue(&broker);
+        OpenBroker { ilocal, broker }
     }
 }
 
 pub struct LuaClosure {
-    proto: Rc<FuncProto>,
-    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub(crate) proto: Rc<FuncProto>,
+    pub(crate) upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+// Function of a call frame. The entry function of execute() is borrowed
+// from the caller, while others are owned by the frames.
+#[derive(Clone)]
+enum FrameFunc<'a> {
+    Borrowed(&'a FuncProto, &'a [Rc<RefCell<Upvalue>>]),
+    Function(Rc<FuncProto>),
+    Closure(Rc<LuaClosure>),
+}
+
+impl FrameFunc<'_> {
+    fn parts(&self) -> (&FuncProto, &[Rc<RefCell<Upvalue>>]) {
+        match self {
+            FrameFunc::Borrowed(proto, upvalues) => (proto, upvalues),
+            FrameFunc::Function(f) => (f, &[]),
+            FrameFunc::Closure(c) => (&c.proto, &c.upvalues),
+        }
+    }
+}
+
+// Call frame of a Lua function.
+//
+// Lua functions called by Lua functions are run in the same loop by
+// run(), by pushing and popping frames but not by recursive Rust calls,
+// so the frames can be saved and continued later, see Coroutine. Rust
+// functions calling Lua, e.g. by ExeState::call(), start new loops.
+struct Frame<'a> {
+    func: FrameFunc<'a>,
+    base: usize,
+    pc: usize, // the running byte code, or calling the upper frame
+    varargs: Vec<Value>,
+    nmeta: usize, // number of `__call` handlers, see start_call()
+}
+
+// result of start_call()
+enum CallStart<'a> {
+    Rust(usize), // finished, with the number of return values
+    Lua(Frame<'a>), // to run
+}
+
+// result of exec_frame()
+enum Step<'a> {
+    Return(usize),
+    Call(Frame<'a>),
+    Yield(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoStatus {
+    Suspended, // not started, or yielded
+    Running,
+    Normal, // resuming another coroutine
+    Dead, // returned, or failed by error
+}
+
+impl CoStatus {
+    // name for `coroutine.status()`
+    pub fn name(self) -> &'static str {
+        match self {
+            CoStatus::Suspended => "suspended",
+            CoStatus::Running => "running",
+            CoStatus::Normal => "normal",
+            CoStatus::Dead => "dead",
+        }
+    }
+}
+
+// Coroutine, with its own stack and call frames, see ExeState::resume().
+//
+// The stack of the running thread is ExeState::stack, and the stacks of
+// others are saved, here or by resume() for the main thread. Since open
+// upvalues refer to the running stack by index, the open upvalues of the
+// thread switched out are closed by the values of their locals, and they
+// are reopened when the thread is switched in again.
+pub struct Coroutine {
+    status: CoStatus,
+    func: Option<Value>, // the function to start, None after started
+    stack: Vec<Value>,
+    frames: Vec<Frame<'static>>,
+    open_brokers: Vec<OpenBroker>,
+    depth: usize, // call depth of the frames
+}
+
+impl Coroutine {
+    pub fn new(f: Value) -> Self {
+        assert!(f.is_function(), "coroutine of {} value", f.type_name());
+        Coroutine {
+            status: CoStatus::Suspended,
+            func: Some(f),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_brokers: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    // the main thread, whose stack is always running or saved by resume()
+    fn main() -> Self {
+        Coroutine {
+            status: CoStatus::Running,
+            func: None,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_brokers: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    pub fn status(&self) -> CoStatus {
+        self.status
+    }
 }
 
 // global execute state
 pub struct ExeState {
     stack: Vec::<Value>,
     base: usize, // stack base of current function
+
+    // limits, see ExeStateBuilder. The @stack_limit is of the running
+    // thread, which is the max_stack_size() for the main thread, or
+    // @max_coroutine_stack_size for coroutines.
+    stack_limit: usize,
+    coroutine_stack_size: usize,
+    max_coroutine_stack_size: usize,
+    max_call_depth: usize,
+    call_depth: usize,
+
+    // nesting of the calls into Lua by Rust, by call_at() and resume(),
+    // which recurse in the Rust stack, and are limited by
+    // @max_call_depth, while Lua calls Lua in a loop
+    rust_depth: usize,
+
+    // number of Rust functions on the stack which are calling Lua by
+    // call_at(), whose Rust frames can not be suspended, so yielding
+    // is not allowed, same with `nny` in the official Lua
+    nny: usize,
+
+    // output of `print()`, flushed at the end of the chunk
+    output: BufWriter<Box<dyn Write>>,
+
+    // execution limits, see ExecLimit. The @countdown is decreased by
+    // each byte code, and check_limits() is called when it reaches 0.
+    // It runs @slice byte codes from the last checking, which are not
+    // deducted from @budget yet.
+    countdown: u64,
+    slice: u64,
+    budget: u64,
+    deadline: Option<Instant>,
+
+    hook: Option<Hook>,
+
+    // memory limit and watermarks, see ExeStateBuilder::max_memory().
+    // The used memory is counted from @memory_base, which is of the
+    // memory::allocated() when the state is created.
+    max_memory: usize,
+    watermarks: Vec<Watermark>,
+    memory_base: isize,
+
+    // statistics, see ExeStats. The @instructions are of the finished
+    // slices of @countdown, and the tables and strings are counted
+    // from @allocations when the state is created.
+    instructions: u64,
+    calls: u64,
+    peak_call_depth: usize,
+    peak_stack_size: usize,
+    allocations: (u64, u64),
+    gc_cycles: u64,
+
+    // front end of exec_file() and `require`, see ExeStateBuilder
+    compiler: Box<dyn Compiler>,
+
+    // open upvalues of all frames of the running thread, sorted by the
+    // locals, which are closed when the frames return
+    open_brokers: Vec<OpenBroker>,
+
+    // the error raised by raise_error(), until it's caught
+    raised: Option<RaisedError>,
+
+    // the running thread, which is @main out of coroutines, and whether
+    // it's yielding after the current Rust function, see yield_on_return()
+    main: Rc<RefCell<Coroutine>>,
+    current: Rc<RefCell<Coroutine>>,
+    yielding: bool,
+
+    // the coroutine to switch to, by transfer_on_return()
+    #[cfg(feature = "transfer")]
+    transfer: Option<Rc<RefCell<Coroutine>>>,
+
+    // metatable of all strings, see metatable_of()
+    string_meta: Rc<RefCell<Table>>,
+
+    // generator of `math.random()`, see random()
+    random: Random,
+
+    // timers of the `timer` module, see poll_timers()
+    timers: Timers,
+
+    // metatables of the userdata types, see register_userdata()
+    userdata_metas: HashMap<TypeId, Rc<RefCell<Table>>>,
+
+    // spans of the running calls with their depths, see trace()
+    #[cfg(feature = "tracing")]
+    spans: Vec<(usize, tracing::span::EnteredSpan)>,
+
+    // environment variables set (Some) or removed (None) by `os.env`,
+    // over the process environment, see env_vars()
+    env_vars: HashMap<String, Option<String>>,
+}
+
+// Limit of a protected call, by `pcall_with_limit()` or `os.timelimit()`.
+// The deadline of Time is checked every CHECK_INTERVAL byte codes,
+// so a long-running Rust function is not interrupted. Nested limits
+// can not extend the outer ones.
+#[derive(Debug, Clone, Copy)]
+pub enum ExecLimit {
+    Instructions(u64),
+    Time(Duration),
+}
+
+// byte codes between checking the deadline and the memory
+const CHECK_INTERVAL: u64 = 1000;
+
+// Statistics of the execution since the state is created, by
+// `ExeState::stats()` or `vmstats()`, e.g. for capacity planning and
+// regression tracking.
+//
+// The tables, strings and collections of garbage are counted by the
+// thread but not the state, see value::allocations() and gc.rs, so they
+// include the ones of other states in the same thread. Short strings
+// are not allocated, so not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExeStats {
+    pub instructions: u64, // byte codes executed
+    pub calls: u64, // function calls, both Lua and Rust ones
+    pub peak_call_depth: usize,
+    pub peak_stack_size: usize, // number of values
+    pub tables: u64,
+    pub strings: u64,
+    pub gc_cycles: u64, // collections of reference cycles
+}
+
+// limit of `__index` and `__newindex` chains, same with MAXTAGLOOP
+const MAX_META_LOOP: usize = 2000;
+
+// Events of the VM, sent to the hook set by `ExeStateBuilder::hook()`,
+// e.g. to forward into a logging or tracing system. With the `tracing`
+// feature, they are forwarded into the tracing crate too, see trace().
+//
+// A Call is followed by its Return, unless an Error is raised inside.
+// Then the calls deeper than the depth where the error is caught are
+// aborted without Return, which is the depth of the next Call minus 1.
+#[derive(Debug)]
+pub enum Event<'a> {
+    // a Lua chunk is loaded from file, by `exec_file()` or `require`
+    Load { chunk: &'a str },
+
+    // a function is called, at @depth of nested calls from 1
+    Call { depth: usize },
+    Return { depth: usize, nret: usize },
+
+    // an error is raised at @depth, and is caught by a protected call
+    // or is thrown out of the main chunk
+    Error { depth: usize, message: &'a str },
+
+    // a collection of reference cycles is done, see gc.rs, which frees
+    // @collected objects
+    Gc { collected: usize },
+}
+
+type Hook = Box<dyn FnMut(&Event)>;
+type MemoryCallback = Box<dyn FnMut(usize)>;
+
+// An error raised by ExeState::raise_error(). The value is not `Send`,
+// so it can not be the panic payload, which is the message instead. It's
+// kept in the state until the panic is caught, and is matched by the
+// message, in case it's caught by others.
+struct RaisedError {
+    value: Value,
+    message: String,
+
+    // call depth of the function whose position is to be added to the
+    // message, which is found when unwinding its frame, see run()
+    depth: Option<usize>,
+
+    // lines of the Lua functions unwound, innermost first, and the
+    // number of them, which are more than the lines if it's too deep
+    traceback: String,
+    levels: usize,
+}
+
+// lines of the traceback, which are of the innermost functions, as the
+// outer ones are much the same, e.g. of a deep recursion
+const TRACEBACK_LEVELS: usize = 20;
+
+impl RaisedError {
+    fn new(value: Value, message: String, depth: Option<usize>) -> Self {
+        RaisedError { value, message, depth, traceback: String::new(), levels: 0 }
+    }
+
+    // Add the lines of the @frames being unwound, from the top one, same
+    // with the official Lua, e.g. "test.lua:3: in local 'f'". The name is
+    // of the calling byte code of the caller frame, so unknown for the
+    // bottom one, which is called by Rust, unless it's the main chunk.
+    fn add_traceback(&mut self, frames: &[Frame]) {
+        for (i, frame) in frames.iter().enumerate().rev() {
+            self.levels += 1;
+            if self.levels > TRACEBACK_LEVELS {
+                self.levels += i;
+                break;
+            }
+            let (proto, _) = frame.func.parts();
+            let source = parse::short_source(&proto.chunk_name);
+            let line = proto.lines.get(frame.pc).map_or("?".into(), u32::to_string);
+            let name = match i.checked_sub(1).map(|i| &frames[i]) {
+                Some(caller) => {
+                    let (cproto, _) = caller.func.parts();
+                    match cproto.byte_codes[caller.pc] {
+                        ByteCode::Call(func, _, _) | ByteCode::CallSet(_, func, _) |
+                        ByteCode::TailCall(func, _) => cproto.describe_reg(func, caller.pc),
+                        _ => None, // by metamethods
+                    }
+                }
+                None => None,
+            };
+            let what = match name {
+                Some(name) => match name.strip_prefix("global ") {
+                    Some(name) => format!("function {name}"),
+                    None => name,
+                },
+                None if proto.local_name(0, 0) == Some("_ENV") => "main chunk".into(),
+                None => "?".into(),
+            };
+            self.traceback.push_str(&format!("\n\t{source}:{line}: in {what}"));
+        }
+    }
+
+    // the traceback for LuaError, see add_traceback()
+    fn traceback(&self) -> String {
+        if self.levels == 0 {
+            return String::new();
+        }
+        let mut traceback = format!("stack traceback:{}", self.traceback);
+        if self.levels > TRACEBACK_LEVELS {
+            traceback.push_str(&format!("\n\t...\t(skipping {} levels)",
+                self.levels - TRACEBACK_LEVELS));
+        }
+        traceback
+    }
+}
+
+// a soft memory limit, see ExeStateBuilder::memory_watermark()
+struct Watermark {
+    level: usize, // in bytes
+    reached: bool,
+    callback: MemoryCallback,
+}
+
+impl Default for ExeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Configuration of ExeState.
+//
+// The stack grows geometrically as a Vec from the initial size. It's
+// safe because open upvalues refer to the stack by index but not by
+// reference, so they need no relocation. "stack overflow" is raised when
+// calling a function if the stack is larger than the maximum size. Lua
+// functions calling Lua are executed in a loop, so the recursion of Lua
+// is limited by the stack size only, while Rust functions calling Lua,
+// and resuming coroutines, are executed by recursive Rust calls. So
+// their nesting is limited by max_call_depth(), for the Rust thread's
+// stack, and "C stack overflow" is raised if exceeded.
+//
+// Coroutines have their own stacks, which are allocated when resumed
+// for the first time, by coroutine_stack_size(), and limited by
+// max_coroutine_stack_size(), so scripts may spawn lots of them, e.g.
+// one for each actor. The stack and call frames of a suspended coroutine
+// are shrunk if they are mostly unused, e.g. after deep recursions, and
+// they are freed when the coroutine is dead.
+//
+// The memory is limited by max_memory(), in bytes allocated by the
+// thread since the state is created. It's counted by the global allocator
+// memory::CountingAlloc, which must be set by the program, or there is
+// no limit. So other states in the same thread are counted too. It's
+// checked every CHECK_INTERVAL byte codes and after Rust functions
+// return, and "not enough memory" is raised if exceeded, which is
+// returned as LuaError::MemoryError by the protected calls.
+//
+// Before reaching the limit, the callbacks set by memory_watermark()
+// are called with the used memory, when it crosses the watermarks in
+// percent of the limit, e.g. to shed load. A callback is called again
+// only after the memory drops below its watermark.
+//
+// The output of `print()` is written into stdout by default.
+//
+// Chunks loaded by `exec_file()` and `require` are compiled by the
+// compiler, which is compiler::DefaultCompiler for Lua source code and
+// binary chunks by default. Set another one for other front ends.
+//
+// Scripts can run commands by `os.execute()` and `io.popen()`, which are
+// removed by allow_subprocess(false), e.g. for sandboxes. They can read
+// the environment variables by `os.env`, and write them only after
+// writable_env(true). The writes are seen by this state and its commands
+// only, and the process environment is never changed.
+pub struct ExeStateBuilder {
+    stack_size: usize,
+    max_stack_size: usize,
+    coroutine_stack_size: usize,
+    max_coroutine_stack_size: usize,
+    max_call_depth: usize,
+    output: Option<Box<dyn Write>>,
+    hook: Option<Hook>,
+    max_memory: usize,
+    watermarks: Vec<(u8, MemoryCallback)>,
+    compiler: Box<dyn Compiler>,
+    allow_subprocess: bool,
+    writable_env: bool,
+}
+
+impl Default for ExeStateBuilder {
+    fn default() -> Self {
+        ExeStateBuilder {
+            stack_size: 256,
+            max_stack_size: 1_000_000, // same with LUAI_MAXSTACK
+            coroutine_stack_size: 32,
+            max_coroutine_stack_size: 1_000_000,
+            max_call_depth: 200, // same with LUAI_MAXCCALLS, of Rust calls only
+            output: None,
+            hook: None,
+            max_memory: usize::MAX,
+            watermarks: Vec::new(),
+            compiler: Box::new(DefaultCompiler),
+            allow_subprocess: true,
+            writable_env: false,
+        }
+    }
+}
+
+impl ExeStateBuilder {
+    pub fn stack_size(mut self, n: usize) -> Self {
+        self.stack_size = n;
+        self
+    }
+    pub fn max_stack_size(mut self, n: usize) -> Self {
+        self.max_stack_size = n;
+        self
+    }
+    pub fn coroutine_stack_size(mut self, n: usize) -> Self {
+        self.coroutine_stack_size = n;
+        self
+    }
+    pub fn max_coroutine_stack_size(mut self, n: usize) -> Self {
+        self.max_coroutine_stack_size = n;
+        self
+    }
+    pub fn max_call_depth(mut self, n: usize) -> Self {
+        self.max_call_depth = n;
+        self
+    }
+    pub fn output(mut self, w: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(w));
+        self
+    }
+    pub fn hook(mut self, f: impl FnMut(&Event) + 'static) -> Self {
+        self.hook = Some(Box::new(f));
+        self
+    }
+    pub fn max_memory(mut self, n: usize) -> Self {
+        self.max_memory = n;
+        self
+    }
+    pub fn memory_watermark(mut self, percent: u8, f: impl FnMut(usize) + 'static) -> Self {
+        self.watermarks.push((percent, Box::new(f)));
+        self
+    }
+    pub fn compiler(mut self, c: impl Compiler + 'static) -> Self {
+        self.compiler = Box::new(c);
+        self
+    }
+    pub fn allow_subprocess(mut self, allow: bool) -> Self {
+        self.allow_subprocess = allow;
+        self
+    }
+    pub fn writable_env(mut self, writable: bool) -> Self {
+        self.writable_env = writable;
+        self
+    }
+    pub fn build(self) -> ExeState {
+        ExeState::with_builder(self)
+    }
 }
 
 impl ExeState {
     pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> ExeStateBuilder {
+        ExeStateBuilder::default()
+    }
+
+    fn with_builder(builder: ExeStateBuilder) -> Self {
+        // including the standard library
+        let memory_base = memory::allocated();
+
         // TODO initilize the standard library outside
         let mut env = Table::new(0, 0);
         env.map.insert("print".into(), Value::RustFunction(lib_print));
         env.map.insert("type".into(), Value::RustFunction(lib_type));
         env.map.insert("ipairs".into(), Value::RustFunction(ipairs));
+        env.map.insert("next".into(), Value::RustFunction(lib_next));
+        env.map.insert("range".into(), Value::RustFunction(lib_range));
+        env.map.insert("pairs".into(), Value::RustFunction(pairs));
+        env.map.insert("tonumber".into(), Value::RustFunction(lib_tonumber));
+        env.map.insert("setmetatable".into(), Value::RustFunction(lib_setmetatable));
+        env.map.insert("getmetatable".into(), Value::RustFunction(lib_getmetatable));
+        env.map.insert("rawget".into(), Value::RustFunction(lib_rawget));
+        env.map.insert("rawset".into(), Value::RustFunction(lib_rawset));
+        env.map.insert("select".into(), Value::RustFunction(lib_select));
+        env.map.insert("pcall".into(), Value::RustFunction(lib_pcall));
+        env.map.insert("xpcall".into(), Value::RustFunction(lib_xpcall));
+        env.map.insert("error".into(), Value::RustFunction(lib_error));
+        env.map.insert("load".into(), Value::RustFunction(lib_load));
+        env.map.insert("loadstring".into(), Value::RustFunction(lib_loadstring));
+        env.map.insert("loadfile".into(), Value::RustFunction(lib_loadfile));
+        env.map.insert("dofile".into(), Value::RustFunction(lib_dofile));
+        env.map.insert("collectgarbage".into(), Value::RustFunction(lib_collectgarbage));
+        env.map.insert("vmstats".into(), Value::RustFunction(lib_vmstats));
         env.map.insert("new_counter".into(), Value::RustFunction(test_new_counter));
+        env.map.insert("require".into(), Value::RustFunction(stdlib::package::require));
 
-        ExeState {
-            // 0: un-used entry function, 1: `_ENV` argument
-            stack: vec![Value::Nil, Value::Table(Rc::new(RefCell::new(env)))],
+        let package = stdlib::package::new_lib();
+        let native = package.index(&"native".into());
+        native.new_index("args".into(), Value::RustFunction(stdlib::args::load));
 
-            // always an entry function, even not used
-            base: 1,
+        let string = stdlib::string::new_lib();
+        let string_meta = stdlib::string::new_metatable(&string);
+
+        let loaded = package.index(&"loaded".into());
+        for (name, lib) in [
+            ("table", stdlib::table::new_lib()),
+            ("string", string),
+            ("math", stdlib::math::new_lib()),
+            ("debug", stdlib::debug::new_lib()),
+            ("coroutine", stdlib::coroutine::new_lib()),
+            ("channel", stdlib::channel::new_lib()),
+            ("io", stdlib::io::new_lib()),
+            ("bytes", stdlib::bytes::new_lib()),
+            #[cfg(feature = "http")]
+            ("http", stdlib::http::new_lib()),
+            #[cfg(feature = "fs")]
+            ("fs", stdlib::fs::new_lib()),
+            #[cfg(feature = "crypto")]
+            ("crypto", stdlib::crypto::new_lib()),
+            #[cfg(feature = "encoding")]
+            ("encoding", stdlib::encoding::new_lib()),
+            #[cfg(feature = "zlib")]
+            ("zlib", stdlib::zlib::new_lib()),
+            #[cfg(feature = "datetime")]
+            ("datetime", stdlib::datetime::new_lib()),
+            #[cfg(feature = "sqlite")]
+            ("sqlite", stdlib::sqlite::new_lib()),
+            #[cfg(feature = "re")]
+            ("re", stdlib::re::new_lib()),
+            #[cfg(unix)]
+            ("os", stdlib::os::new_lib()),
+            ("package", package),
+        ] {
+            loaded.new_index(name.into(), lib.clone());
+            env.map.insert(name.into(), lib);
+        }
+        if !builder.allow_subprocess {
+            for (lib, name) in [("os", "execute"), ("io", "popen")] {
+                if let Some(lib) = env.map.get(&lib.into()) {
+                    lib.new_index(name.into(), Value::Nil);
+                }
+            }
+        }
+        #[cfg(unix)]
+        if let Some(os) = env.map.get(&"os".into()) {
+            os.new_index("env".into(), stdlib::os::new_env(builder.writable_env));
         }
-    }
 
-    pub fn execute(&mut self, proto: &FuncProto, upvalues: &Vec<Rc<RefCell<Upvalue>>>) -> usize {
+        // 0: un-used entry function, 1: `_ENV` argument
+        let mut stack = Vec::with_capacity(builder.stack_size.max(2));
+        stack.push(Value::Nil);
+        stack.push(Value::from(env));
 
-        // open brokers between local variables and upvalues
-        let mut open_brokers: Vec<OpenBroker> = Vec::new();
+        let main = Rc::new(RefCell::new(Coroutine::main()));
 
-        // fill nil if #argument < #parameter
-        if self.stack.len() - self.base < proto.nparam {
-            self.fill_stack_nil(0, proto.nparam);
-        }
+        let mut state = ExeState {
+            stack,
 
-        // move varargs out from stack
-        let varargs = if proto.has_varargs {
-            self.stack.drain(self.base + proto.nparam ..).collect()
-        } else {
-            Vec::new()
-        };
+            // always an entry function, even not used
+            base: 1,
 
-        let mut pc = 0;
-        loop {
-            println!("  [{pc}]\t{:?}", proto.byte_codes[pc]);
+            stack_limit: builder.max_stack_size,
+            coroutine_stack_size: builder.coroutine_stack_size,
+            max_coroutine_stack_size: builder.max_coroutine_stack_size,
+            max_call_depth: builder.max_call_depth,
+            call_depth: 0,
+            rust_depth: 0,
+            nny: 0,
+
+            output: BufWriter::new(builder.output.unwrap_or_else(|| Box::new(io::stdout()))),
+
+            countdown: u64::MAX,
+            slice: u64::MAX,
+            budget: u64::MAX,
+            deadline: None,
+
+            hook: builder.hook,
+
+            max_memory: builder.max_memory,
+            watermarks: builder.watermarks.into_iter().map(|(percent, callback)| Watermark {
+                level: builder.max_memory / 100 * percent as usize,
+                reached: false,
+                callback,
+            }).collect(),
+            memory_base,
+
+            instructions: 0,
+            calls: 0,
+            peak_call_depth: 0,
+            peak_stack_size: 0,
+            allocations: value::allocations(),
+            gc_cycles: gc::cycles(),
+
+            compiler: builder.compiler,
+
+            open_brokers: Vec::new(),
+            main: main.clone(),
+            current: main,
+            yielding: false,
+            #[cfg(feature = "transfer")]
+            transfer: None,
+            raised: None,
+            string_meta,
+            random: Random::default(),
+            timers: Timers::default(),
+            userdata_metas: HashMap::new(),
+            #[cfg(feature = "tracing")]
+            spans: Vec::new(),
+            env_vars: HashMap::new(),
+        };
+        stdlib::bytes::register(&mut state);
+        #[cfg(feature = "sqlite")]
+        stdlib::sqlite::register(&mut state);
+        #[cfg(feature = "re")]
+        stdlib::re::register(&mut state);
+        state.reset_countdown(); // for memory checking
+        state
+    }
+
+    // execute a chunk, and flush the output at end
+    pub fn execute(&mut self, proto: &FuncProto, upvalues: &[Rc<RefCell<Upvalue>>]) -> usize {
+        let nret = if self.call_depth == 0 && self.hook.is_some() {
+            // catch the error to report, and then re-throw it
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.do_execute(proto, upvalues)));
+            result.unwrap_or_else(|e| {
+                let message = panic_message(&*e);
+                self.emit(Event::Error { depth: self.call_depth, message: &message });
+                panic::resume_unwind(e)
+            })
+        } else {
+            self.do_execute(proto, upvalues)
+        };
+        if self.call_depth == 0 {
+            self.flush();
+        }
+        nret
+    }
+
+    fn do_execute(&mut self, proto: &FuncProto, upvalues: &[Rc<RefCell<Upvalue>>]) -> usize {
+        let frame = self.new_frame(FrameFunc::Borrowed(proto, upvalues), 0);
+        self.run(&mut vec![frame], None)
+    }
+
+    // new frame of the Lua function, whose arguments are at @self.base
+    fn new_frame<'a>(&mut self, func: FrameFunc<'a>, nmeta: usize) -> Frame<'a> {
+        let (proto, _) = func.parts();
+
+        // fill nil if #argument < #parameter
+        if self.stack.len() - self.base < proto.nparam {
+            self.fill_stack_nil(0, proto.nparam);
+        }
+
+        // move varargs out from stack
+        let varargs = if proto.has_varargs {
+            self.stack.drain(self.base + proto.nparam ..).collect()
+        } else {
+            Vec::new()
+        };
+
+        // Reserve the call frame once, so the stack does not reallocate
+        // for the registers of this function. It's reserved as capacity
+        // but not length, because the length is the stack top, which is
+        // used for variable number of values. So register accesses still
+        // compare with the length, see get_stack() and set_stack().
+        let frame_top = self.base + proto.max_stack_size;
+        if frame_top > self.stack_limit {
+            panic!("stack overflow");
+        }
+        self.peak_stack_size = self.peak_stack_size.max(frame_top);
+        self.stack.reserve(frame_top.saturating_sub(self.stack.len()));
+
+        Frame { func, base: self.base, pc: 0, varargs, nmeta }
+    }
+
+    // Run the Lua functions of @frames, until the bottom one returns, or
+    // the running coroutine yields, see resume(). Calls of Lua functions
+    // push new frames, and the top frame runs.
+    //
+    // If @finished is some, the top frame continues after its calling
+    // byte code, whose callee has returned or yielded, with the number
+    // of the return values, or of the values passed by resume().
+    //
+    // Return the number of return values at the stack top.
+    fn run<'a>(&mut self, frames: &mut Vec<Frame<'a>>, finished: Option<usize>) -> usize {
+        // call depth of the bottom frame
+        let depth0 = self.call_depth + 1 - frames.len();
+        panic::catch_unwind(AssertUnwindSafe(|| self.run_frames(frames, finished)))
+            .unwrap_or_else(|e| panic::resume_unwind(self.locate_error(frames, depth0, e)))
+    }
+
+    fn run_frames<'a>(&mut self, frames: &mut Vec<Frame<'a>>, mut finished: Option<usize>) -> usize {
+        loop {
+            match self.exec_frame(frames.last_mut().unwrap(), finished.take()) {
+                Step::Call(callee) => frames.push(callee),
+                Step::Yield(nret) => return nret,
+                Step::Return(nret) => {
+                    let frame = frames.pop().unwrap();
+                    if frames.is_empty() {
+                        return nret;
+                    }
+                    self.end_call(frame.nmeta, nret);
+                    finished = Some(nret);
+                }
+            }
+        }
+    }
+
+    // Add the position to the message of the error, same with the
+    // official Lua. For the error raised by raise_error(), it's of the
+    // function at its level if it is one of the @frames, whose bottom
+    // one is at @depth0. For other errors, e.g. "attempt to call a nil
+    // value" by the VM or "bad argument" by Rust functions, it's of the
+    // top frame, which is running or calling the Rust function. Errors
+    // of the limits and memory get no position. The @frames are added to
+    // the traceback too, before they are unwound. Return the panic
+    // payload to continue unwinding.
+    fn locate_error(&mut self, frames: &[Frame], depth0: usize, e: Box<dyn Any + Send>)
+        -> Box<dyn Any + Send>
+    {
+        let msg = panic_message(&*e);
+        let (raised, caught) = match self.raised.take() {
+            Some(raised) if raised.message == msg => (raised, true),
+            // only once, by the innermost run()
+            _ => (RaisedError::new(msg.as_str().into(), msg.clone(), None), false),
+        };
+        let raised = self.raised.insert(raised);
+        raised.add_traceback(frames);
+
+        let frame = if caught {
+            let Some(depth) = raised.depth.filter(|&depth| depth >= depth0) else {
+                return e; // located already, or no position
+            };
+            // not found if it's a Rust function, then no position
+            raised.depth = None;
+            frames.get(depth - depth0)
+        } else if is_limit_error(&msg) {
+            return e;
+        } else {
+            frames.last()
+        };
+        let Some(frame) = frame else {
+            return e;
+        };
+        let (proto, _) = frame.func.parts();
+        let Some(line) = proto.lines.get(frame.pc) else {
+            return e;
+        };
+        let message = format!("{}:{line}: {msg}", parse::short_source(&proto.chunk_name));
+        if let Some(raised) = &mut self.raised {
+            raised.value = message.as_str().into();
+            raised.message = message.clone();
+        }
+        Box::new(message)
+    }
+
+    // execute the top frame until it returns, calls a Lua function, or
+    // yields, see run()
+    fn exec_frame<'a>(&mut self, frame: &mut Frame<'a>, finished: Option<usize>) -> Step<'a> {
+        let func = frame.func.clone();
+        let (proto, upvalues) = func.parts();
+        let mut pc = frame.pc;
+        self.base = frame.base;
+
+        if let Some(nret) = finished {
+            if let ByteCode::TailCall(_, _) = proto.byte_codes[pc] {
+                return Step::Return(nret);
+            }
+            self.finish_call(proto, &mut pc, nret);
+            pc = pc.wrapping_add(1);
+        }
+
+        loop {
+            if self.countdown == 0 {
+                self.check_limits();
+            }
+            self.countdown -= 1;
+            frame.pc = pc; // for the positions of errors
+
+            #[cfg(feature = "trace")]
+            eprintln!("  [{pc}]\t{:?}", proto.byte_codes[pc]);
             match proto.byte_codes[pc] {
                 // local variable
                 ByteCode::LoadConst(dst, c) => {
                     let v = proto.constants[c as usize].clone();
                     self.set_stack(dst, v);
                 }
+                ByteCode::LoadConstX(dst) => {
+                    pc += 1;
+                    let v = proto.constants[proto.byte_codes[pc].ax()].clone();
+                    self.set_stack(dst, v);
+                }
                 ByteCode::LoadNil(dst, n) => {
                     let begin = self.base + dst as usize;
-                    if begin < self.stack.len() {
-                        self.stack[begin..].fill(Value::Nil);
+                    let end = begin + n as usize;
+                    if end > self.stack.len() {
+                        self.stack.resize(end, Value::Nil);
                     }
-                    self.fill_stack_nil(dst, n as usize);
+                    self.stack[begin..end].fill(Value::Nil);
                 }
                 ByteCode::LoadBool(dst, b) => {
                     self.set_stack(dst, Value::Boolean(b));
@@ -168,6 +1406,13 @@ impl ExeState {
                     let v = self.get_stack(src).clone();
                     self.set_stack(dst, v);
                 }
+                ByteCode::MoveN(dst, src, n) => {
+                    // the ranges never overlap, see merge_last_codes() in parse.rs
+                    for i in 0..n {
+                        let v = self.get_stack(src + i).clone();
+                        self.set_stack(dst + i, v);
+                    }
+                }
 
                 // upvalues
                 ByteCode::GetUpvalue(dst, src) => {
@@ -183,44 +1428,44 @@ impl ExeState {
                     upvalues[dst as usize].borrow_mut().set(&mut self.stack, v);
                 }
                 ByteCode::Close(ilocal) => {
-                    let ilocal = self.base + ilocal as usize;
-                    let from = open_brokers.binary_search_by_key(&ilocal, |b| b.ilocal)
-                        .unwrap_or_else(|i| i);
-                    self.close_brokers(open_brokers.drain(from..));
+                    self.close_brokers(self.base + ilocal as usize);
                 }
 
                 // table
                 ByteCode::NewTable(dst, narray, nmap) => {
                     let table = Table::new(narray as usize, nmap as usize);
-                    self.set_stack(dst, Value::Table(Rc::new(RefCell::new(table))));
+                    self.set_stack(dst, Value::from(table));
+                    if gc::should_collect() {
+                        self.collect_garbage();
+                    }
                 }
                 ByteCode::SetTable(t, k, v) => {
                     let key = self.get_stack(k).clone();
                     let value = self.get_stack(v).clone();
-                    self.get_stack(t).new_index(key, value);
+                    self.set_table(proto, pc, t, key, value);
                 }
                 ByteCode::SetField(t, k, v) => {
                     let key = proto.constants[k as usize].clone();
                     let value = self.get_stack(v).clone();
-                    self.get_stack(t).new_index(key, value);
+                    self.set_table(proto, pc, t, key, value);
                 }
                 ByteCode::SetInt(t, i, v) => {
                     let value = self.get_stack(v).clone();
-                    self.get_stack(t).new_index_array(i as i64, value);
+                    self.set_table(proto, pc, t, Value::Integer(i as i64), value);
                 }
                 ByteCode::SetTableConst(t, k, v) => {
                     let key = self.get_stack(k).clone();
                     let value = proto.constants[v as usize].clone();
-                    self.get_stack(t).new_index(key, value);
+                    self.set_table(proto, pc, t, key, value);
                 }
                 ByteCode::SetFieldConst(t, k, v) => {
                     let key = proto.constants[k as usize].clone();
                     let value = proto.constants[v as usize].clone();
-                    self.get_stack(t).new_index(key, value);
+                    self.set_table(proto, pc, t, key, value);
                 }
                 ByteCode::SetIntConst(t, i, v) => {
                     let value = proto.constants[v as usize].clone();
-                    self.get_stack(t).new_index_array(i as i64, value);
+                    self.set_table(proto, pc, t, Value::Integer(i as i64), value);
                 }
                 ByteCode::SetList(table, n) => {
                     let ivalue = self.base + table as usize + 1;
@@ -231,29 +1476,42 @@ impl ExeState {
                         // 0 is special, means all following values in stack
                         self.stack.len()
                     } else {
-                        ivalue + n as usize
+                        // the missing values are by corrupted binary chunks
+                        (ivalue + n as usize).min(self.stack.len())
                     };
-                    let values = self.stack.drain(ivalue .. end);
-                    table.borrow_mut().array.extend(values);
+                    let values = self.stack.drain(ivalue.min(end) .. end);
+                    table.borrow_mut().extend_array(values);
                 }
                 ByteCode::GetTable(dst, t, k) => {
                     let key = self.get_stack(k);
-                    let value = self.get_stack(t).index(key);
+                    let value = match *key {
+                        // integer keys, mostly `t[i]` in loops, go to
+                        // the array part directly
+                        Value::Integer(i) => self.get_stack(t).try_index_array(i),
+                        _ => self.get_stack(t).try_index(key),
+                    };
+                    let value = value.unwrap_or_else(|| {
+                        let key = self.get_stack(k).clone();
+                        self.get_table_meta(proto, pc, t, key)
+                    });
                     self.set_stack(dst, value);
                 }
                 ByteCode::GetField(dst, t, k) => {
                     let key = &proto.constants[k as usize];
-                    let value = self.get_stack(t).index(key);
+                    let value = self.get_stack(t).try_index(key)
+                        .unwrap_or_else(|| self.get_table_meta(proto, pc, t, key.clone()));
                     self.set_stack(dst, value);
                 }
                 ByteCode::GetInt(dst, t, k) => {
-                    let value = self.get_stack(t).index_array(k as i64);
+                    let value = self.get_stack(t).try_index_array(k as i64)
+                        .unwrap_or_else(|| self.get_table_meta(proto, pc, t, Value::Integer(k as i64)));
                     self.set_stack(dst, value);
                 }
                 ByteCode::GetFieldSelf(dst, t, k) => {
                     let table = self.get_stack(t).clone();
                     let key = &proto.constants[k as usize];
-                    let value = table.index(key).clone();
+                    let value = table.try_index(key)
+                        .unwrap_or_else(|| self.get_table_meta(proto, pc, t, key.clone()));
                     self.set_stack(dst, value);
                     self.set_stack(dst+1, table);
                 }
@@ -267,19 +1525,31 @@ impl ExeState {
                 ByteCode::SetUpField(t, k, v) => {
                     let key = proto.constants[k as usize].clone();
                     let value = self.get_stack(v).clone();
-                    upvalues[t as usize].borrow().get(&self.stack)
-                        .new_index(key, value);
+                    let result = upvalues[t as usize].borrow().get(&self.stack)
+                        .try_new_index(key, value);
+                    if let Err((key, value)) = result {
+                        let table = self.get_upvalue_table(proto, upvalues, t);
+                        self.new_index_meta(table, key, value);
+                    }
                 }
                 ByteCode::SetUpFieldConst(t, k, v) => {
                     let key = proto.constants[k as usize].clone();
                     let value = proto.constants[v as usize].clone();
-                    upvalues[t as usize].borrow().get(&self.stack)
-                        .new_index(key, value);
+                    let result = upvalues[t as usize].borrow().get(&self.stack)
+                        .try_new_index(key, value);
+                    if let Err((key, value)) = result {
+                        let table = self.get_upvalue_table(proto, upvalues, t);
+                        self.new_index_meta(table, key, value);
+                    }
                 }
                 ByteCode::GetUpField(dst, t, k) => {
                     let key = &proto.constants[k as usize];
                     let value = upvalues[t as usize].borrow().get(&self.stack)
-                        .index(key).clone();
+                        .try_index(key);
+                    let value = value.unwrap_or_else(|| {
+                        let table = self.get_upvalue_table(proto, upvalues, t);
+                        self.index_meta(table, key.clone())
+                    });
                     self.set_stack(dst, value);
                 }
 
@@ -322,9 +1592,9 @@ impl ExeState {
                         if step == 0 {
                             panic!("0 step in numerical for");
                         }
-                        let limit = match self.get_stack(dst + 1) {
-                            &Value::Integer(limit) => limit,
-                            &Value::Float(limit) => {
+                        let limit = match *self.get_stack(dst + 1) {
+                            Value::Integer(limit) => limit,
+                            Value::Float(limit) => {
                                 let limit = for_int_limit(limit, step>0, &mut i);
                                 self.set_stack(dst+1, Value::Integer(limit));
                                 limit
@@ -373,54 +1643,43 @@ impl ExeState {
                     }
                 }
 
-                ByteCode::ForCallLoop(iter, nvar, jmp) => {
-                    // stack:
-                    // - before call:
-                    //     iter-func, state, ctrl-var
-                    // - after call:
-                    //     iter-func, state, ctrl-var, ..., return-values
-                    // - update ctrl-var, and clear middle values
-                    //     iter-func, state, ctrl-var*, return-values
-                    let nret = self.call_function(iter, 2+1);
-                    let iret = self.stack.len() - nret;
-
-                    if nret > 0 && self.stack[iret] != Value::Nil {
-                        // continue the loop
-                        // duplicate the first return value as ctrl-var,
-                        // so it could be changed during loop.
-                        let first_ret = self.stack[iret].clone();
-                        self.set_stack(iter + 2, first_ret);
-
-                        // move return values to @iter+3
-                        self.stack.drain(self.base + iter as usize + 3 .. iret);
-                        self.fill_stack_nil(iter + 3, nvar as usize);
-
-                        // jump back to loop
-                        pc -= jmp as usize;
-
-                    } else if jmp == 0 {
-                        // skip the following Jump
-                        pc += 1;
+                ByteCode::ForCallLoop(iter, _, _) => {
+                    self.check_callable(proto, pc, iter);
+                    match self.call_function(iter, 2+1) {
+                        CallStart::Rust(nret) => {
+                            if self.yielding {
+                                return Step::Yield(nret);
+                            }
+                            self.finish_call(proto, &mut pc, nret);
+                        }
+                        CallStart::Lua(callee) => return Step::Call(callee),
                     }
                 }
 
                 // define closure
-                ByteCode::Closure(dst, inner) => {
-                    let Value::LuaFunction(inner_proto) = proto.constants[inner as usize].clone() else {
+                ByteCode::Closure(dst, _) | ByteCode::ClosureX(dst) => {
+                    let inner = match proto.byte_codes[pc] {
+                        ByteCode::Closure(_, inner) => inner as usize,
+                        _ => {
+                            pc += 1;
+                            proto.byte_codes[pc].ax()
+                        }
+                    };
+                    let Value::LuaFunction(inner_proto) = proto.constants[inner].clone() else {
                         panic!("must be funcproto");
                     };
 
                     // generate upvalues
-                    let inner_upvalues = inner_proto.upindexes.iter().map(|up| match up {
-                        &UpIndex::Upvalue(iup) => upvalues[iup].clone(),
-                        &UpIndex::Local(ilocal) => {
+                    let inner_upvalues = inner_proto.upindexes.iter().map(|up| match *up {
+                        UpIndex::Upvalue(iup) => upvalues[iup].clone(),
+                        UpIndex::Local(ilocal) => {
                             let ilocal = self.base + ilocal;
-                            let iob = open_brokers.binary_search_by_key(&ilocal, |b|b.ilocal)
+                            let iob = self.open_brokers.binary_search_by_key(&ilocal, |b|b.ilocal)
                                 .unwrap_or_else(|i| {
-                                    open_brokers.insert(i, OpenBroker::from(ilocal));
+                                    self.open_brokers.insert(i, OpenBroker::from(ilocal));
                                     i
                                 });
-                            open_brokers[iob].broker.clone()
+                            self.open_brokers[iob].broker.clone()
                         }
                     }).collect();
 
@@ -428,52 +1687,48 @@ impl ExeState {
                         upvalues: inner_upvalues,
                         proto: inner_proto,
                     };
-                    self.set_stack(dst, Value::LuaClosure(Rc::new(c)))
-                }
-
-                // function call
-                ByteCode::Call(func, narg_plus, want_nret) => {
-                    let nret = self.call_function(func, narg_plus);
-
-                    // move return values to @func
-                    let iret = self.stack.len() - nret;
-                    self.stack.drain(self.base+func as usize .. iret);
-
-                    // want_nret==0 means 1.want all return values or 2.want no
-                    // return values, while we do not need handle in both cases;
-                    // otherwise, means @want_nret return values are need, and
-                    // we need to fill nil if necessary.
-                    let want_nret = want_nret as usize;
-                    if nret < want_nret {
-                        self.fill_stack_nil(func, want_nret);
+                    let c = Rc::new(c);
+                    gc::track_closure(&c);
+                    self.set_stack(dst, Value::LuaClosure(c));
+                    if gc::should_collect() {
+                        self.collect_garbage();
                     }
                 }
-                ByteCode::CallSet(dst, func, narg_plus) => {
-                    let nret = self.call_function(func, narg_plus);
 
-                    // set first return value to @dst directly
-                    if nret == 0 {
-                        self.set_stack(dst, Value::Nil);
-                    } else {
-                        // use swap() to avoid clone()
-                        let iret = self.stack.len() - nret;
-                        self.stack.swap(self.base+dst as usize, iret);
+                // function call
+                ByteCode::Call(func, narg_plus, _) | ByteCode::CallSet(_, func, narg_plus) => {
+                    self.check_callable(proto, pc, func);
+                    match self.call_function(func, narg_plus) {
+                        CallStart::Rust(nret) => {
+                            if self.yielding {
+                                return Step::Yield(nret);
+                            }
+                            self.finish_call(proto, &mut pc, nret);
+                        }
+                        CallStart::Lua(callee) => return Step::Call(callee),
                     }
-                    self.stack.truncate(self.base + func as usize + 1);
                 }
 
                 ByteCode::TailCall(func, narg_plus) => {
-                    self.close_brokers(open_brokers);
+                    self.check_callable(proto, pc, func);
+                    self.close_brokers(self.base);
 
                     // clear current call-frame, and move new function entry and
                     // arguments (self.stack[@func ..]) into current call-frame
                     self.stack.drain(self.base-1 .. self.base+func as usize);
 
-                    return self.do_call_function(narg_plus);
+                    // The callee runs at the same base, and then this frame
+                    // returns its return values, see exec_frame(). So it's
+                    // not a real tail call, but the stack does not grow.
+                    match self.start_call(narg_plus) {
+                        CallStart::Rust(nret) if self.yielding => return Step::Yield(nret),
+                        CallStart::Rust(nret) => return Step::Return(nret),
+                        CallStart::Lua(callee) => return Step::Call(callee),
+                    }
                 }
 
                 ByteCode::Return(iret, nret) => {
-                    self.close_brokers(open_brokers);
+                    self.close_brokers(self.base);
 
                     // if nret==0, return stack[iret .. ];
                     // otherwise, return stack[iret .. iret+nret] and truncate
@@ -485,17 +1740,21 @@ impl ExeState {
                     // - the following byte code, including Return(_,0),
                     //   Call(_,_,0) or SetList(_,0), can get the
                     //   #return-values by stack top.
+                    //
+                    // The stack is filled by nil if it's shorter, only by
+                    // corrupted binary chunks.
                     let iret = self.base + iret as usize;
                     if nret == 0 {
-                        return self.stack.len() - iret;
+                        self.stack.resize(self.stack.len().max(iret), Value::Nil);
+                        return Step::Return(self.stack.len() - iret);
                     } else {
-                        self.stack.truncate(iret + nret as usize);
-                        return nret as usize;
+                        self.stack.resize(iret + nret as usize, Value::Nil);
+                        return Step::Return(nret as usize);
                     }
                 }
                 ByteCode::Return0 => {
-                    self.close_brokers(open_brokers);
-                    return 0;
+                    self.close_brokers(self.base);
+                    return Step::Return(0);
                 }
 
                 ByteCode::VarArgs(dst, want) => {
@@ -505,12 +1764,13 @@ impl ExeState {
                     // can get the #varargs by stack top.
                     self.stack.truncate(self.base + dst as usize);
 
+                    let varargs = &frame.varargs;
                     let len = varargs.len();
                     let want = want as usize;
                     if want == 0 { // 0 means all
-                        self.stack.extend_from_slice(&varargs);
+                        self.stack.extend_from_slice(varargs);
                     } else if want > len {
-                        self.stack.extend_from_slice(&varargs);
+                        self.stack.extend_from_slice(varargs);
                         self.fill_stack_nil(dst, want);
                     } else {
                         self.stack.extend_from_slice(&varargs[..want]);
@@ -519,10 +1779,10 @@ impl ExeState {
 
                 // unops
                 ByteCode::Neg(dst, src) => {
-                    let value = match &self.get_stack(src) {
-                        Value::Integer(i) => Value::Integer(-i),
-                        Value::Float(f) => Value::Float(-f),
-                        _ => panic!("invalid -"),
+                    let value = match self.get_stack(src).to_number() {
+                        Some(Value::Integer(i)) => Value::Integer(i.wrapping_neg()),
+                        Some(Value::Float(f)) => Value::Float(-f),
+                        _ => self.arith_meta(proto, pc),
                     };
                     self.set_stack(dst, value);
                 }
@@ -535,9 +1795,9 @@ impl ExeState {
                     self.set_stack(dst, value);
                 }
                 ByteCode::BitNot(dst, src) => {
-                    let value = match &self.get_stack(src) {
-                        Value::Integer(i) => Value::Integer(!i),
-                        _ => panic!("invalid ~"),
+                    let value = match self.get_stack(src).to_number() {
+                        Some(n) => Value::Integer(!to_bit_int(&n)),
+                        None => self.arith_meta(proto, pc),
                     };
                     self.set_stack(dst, value);
                 }
@@ -554,147 +1814,183 @@ impl ExeState {
 
                 // binops
                 ByteCode::Add(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a+b, |a,b|a+b);
+                    let r = exe_binop(self.get_stack(a), self.get_stack(b), i64::wrapping_add, |a,b|a+b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::AddConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a+b, |a,b|a+b);
+                    let r = exe_binop(self.get_stack(a), &proto.constants[b as usize], i64::wrapping_add, |a,b|a+b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::AddInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a+b, |a,b|a+b);
+                    let r = exe_binop_int(self.get_stack(a), i, i64::wrapping_add, |a,b|a+b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::Sub(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a-b, |a,b|a-b);
+                    let r = exe_binop(self.get_stack(a), self.get_stack(b), i64::wrapping_sub, |a,b|a-b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::SubConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a-b, |a,b|a-b);
+                    let r = exe_binop(self.get_stack(a), &proto.constants[b as usize], i64::wrapping_sub, |a,b|a-b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::SubInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a-b, |a,b|a-b);
+                    let r = exe_binop_int(self.get_stack(a), i, i64::wrapping_sub, |a,b|a-b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::Mul(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a*b, |a,b|a*b);
+                    let r = exe_binop(self.get_stack(a), self.get_stack(b), i64::wrapping_mul, |a,b|a*b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::MulConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a*b, |a,b|a*b);
+                    let r = exe_binop(self.get_stack(a), &proto.constants[b as usize], i64::wrapping_mul, |a,b|a*b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::MulInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a*b, |a,b|a*b);
+                    let r = exe_binop_int(self.get_stack(a), i, i64::wrapping_mul, |a,b|a*b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::Mod(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a%b, |a,b|a%b);
+                    let r = exe_binop(self.get_stack(a), self.get_stack(b), int_mod, float_mod)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ModConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a%b, |a,b|a%b);
+                    let r = exe_binop(self.get_stack(a), &proto.constants[b as usize], int_mod, float_mod)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ModInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a%b, |a,b|a%b);
+                    let r = exe_binop_int(self.get_stack(a), i, int_mod, float_mod)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::Idiv(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a/b, |a,b|a/b);
+                    let r = exe_binop(self.get_stack(a), self.get_stack(b), int_div, float_div)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::IdivConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a/b, |a,b|a/b);
+                    let r = exe_binop(self.get_stack(a), &proto.constants[b as usize], int_div, float_div)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::IdivInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a/b, |a,b|a/b);
+                    let r = exe_binop_int(self.get_stack(a), i, int_div, float_div)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::Div(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &self.get_stack(b), |a,b|a/b);
+                    let r = exe_binop_f(self.get_stack(a), self.get_stack(b), |a,b|a/b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::DivConst(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &proto.constants[b as usize], |a,b|a/b);
+                    let r = exe_binop_f(self.get_stack(a), &proto.constants[b as usize], |a,b|a/b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::DivInt(dst, a, i) => {
-                    let r = exe_binop_int_f(&self.get_stack(a), i, |a,b|a/b);
+                    let r = exe_binop_int_f(self.get_stack(a), i, |a,b|a/b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::Pow(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &self.get_stack(b), |a,b|a.powf(b));
+                    let r = exe_binop_f(self.get_stack(a), self.get_stack(b), |a,b|a.powf(b))
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::PowConst(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &proto.constants[b as usize], |a,b|a.powf(b));
+                    let r = exe_binop_f(self.get_stack(a), &proto.constants[b as usize], |a,b|a.powf(b))
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::PowInt(dst, a, i) => {
-                    let r = exe_binop_int_f(&self.get_stack(a), i, |a,b|a.powf(b));
+                    let r = exe_binop_int_f(self.get_stack(a), i, |a,b|a.powf(b))
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitAnd(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a&b);
+                    let r = exe_binop_i(self.get_stack(a), self.get_stack(b), |a,b|a&b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitAndConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a&b);
+                    let r = exe_binop_i(self.get_stack(a), &proto.constants[b as usize], |a,b|a&b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitAndInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a&b);
+                    let r = exe_binop_int_i(self.get_stack(a), i, |a,b|a&b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitOr(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a|b);
+                    let r = exe_binop_i(self.get_stack(a), self.get_stack(b), |a,b|a|b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitOrConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a|b);
+                    let r = exe_binop_i(self.get_stack(a), &proto.constants[b as usize], |a,b|a|b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitOrInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a|b);
+                    let r = exe_binop_int_i(self.get_stack(a), i, |a,b|a|b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitXor(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a^b);
+                    let r = exe_binop_i(self.get_stack(a), self.get_stack(b), |a,b|a^b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitXorConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a^b);
+                    let r = exe_binop_i(self.get_stack(a), &proto.constants[b as usize], |a,b|a^b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::BitXorInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a^b);
+                    let r = exe_binop_int_i(self.get_stack(a), i, |a,b|a^b)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ShiftL(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a<<b);
+                    let r = exe_binop_i(self.get_stack(a), self.get_stack(b), shift_left)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ShiftLConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a<<b);
+                    let r = exe_binop_i(self.get_stack(a), &proto.constants[b as usize], shift_left)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ShiftLInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a<<b);
+                    let r = exe_binop_int_i(self.get_stack(a), i, shift_left)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ShiftR(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a>>b);
+                    let r = exe_binop_i(self.get_stack(a), self.get_stack(b), shift_right)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ShiftRConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a>>b);
+                    let r = exe_binop_i(self.get_stack(a), &proto.constants[b as usize], shift_right)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
                 ByteCode::ShiftRInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a>>b);
+                    let r = exe_binop_int_i(self.get_stack(a), i, shift_right)
+                        .unwrap_or_else(|| self.arith_meta(proto, pc));
                     self.set_stack(dst, r);
                 }
 
@@ -733,90 +2029,74 @@ impl ExeState {
                     }
                 }
                 ByteCode::LesEq(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if !matches!(cmp, Ordering::Greater) == r {
+                    let cmp = compare(self.get_stack(a), self.get_stack(b));
+                    if matches!(cmp, Some(Ordering::Less | Ordering::Equal)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::LesEqConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if !matches!(cmp, Ordering::Greater) == r {
+                    let cmp = compare(self.get_stack(a), &proto.constants[b as usize]);
+                    if matches!(cmp, Some(Ordering::Less | Ordering::Equal)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::LesEqInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a <= i as i64) == r {
+                    let cmp = compare(self.get_stack(a), &Value::Integer(i as i64));
+                    if matches!(cmp, Some(Ordering::Less | Ordering::Equal)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::GreEq(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if !matches!(cmp, Ordering::Less) == r {
+                    let cmp = compare(self.get_stack(b), self.get_stack(a));
+                    if matches!(cmp, Some(Ordering::Less | Ordering::Equal)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::GreEqConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if !matches!(cmp, Ordering::Less) == r {
+                    let cmp = compare(&proto.constants[b as usize], self.get_stack(a));
+                    if matches!(cmp, Some(Ordering::Less | Ordering::Equal)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::GreEqInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a >= i as i64) == r {
+                    let cmp = compare(&Value::Integer(i as i64), self.get_stack(a));
+                    if matches!(cmp, Some(Ordering::Less | Ordering::Equal)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::Less(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if matches!(cmp, Ordering::Less) == r {
+                    let cmp = compare(self.get_stack(a), self.get_stack(b));
+                    if matches!(cmp, Some(Ordering::Less)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::LessConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if matches!(cmp, Ordering::Less) == r {
+                    let cmp = compare(self.get_stack(a), &proto.constants[b as usize]);
+                    if matches!(cmp, Some(Ordering::Less)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::LessInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a < i as i64) == r {
+                    let cmp = compare(self.get_stack(a), &Value::Integer(i as i64));
+                    if matches!(cmp, Some(Ordering::Less)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::Greater(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if matches!(cmp, Ordering::Greater) == r {
+                    let cmp = compare(self.get_stack(b), self.get_stack(a));
+                    if matches!(cmp, Some(Ordering::Less)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::GreaterConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if matches!(cmp, Ordering::Greater) == r {
+                    let cmp = compare(&proto.constants[b as usize], self.get_stack(a));
+                    if matches!(cmp, Some(Ordering::Less)) == r {
                         pc += 1;
                     }
                 }
                 ByteCode::GreaterInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a > i as i64) == r {
+                    let cmp = compare(&Value::Integer(i as i64), self.get_stack(a));
+                    if matches!(cmp, Some(Ordering::Less)) == r {
                         pc += 1;
                     }
                 }
@@ -830,14 +2110,22 @@ impl ExeState {
                     let r = self.get_stack(a).concat(self.get_stack(b));
                     self.set_stack(dst, r);
                 }
+
+                // skipped by LoadConstX and ClosureX
+                ByteCode::ExtraArg(_, _) => panic!("unexpected ExtraArg"),
             }
 
-            pc += 1;
+            // wrapping because jumping back to the first byte code
+            // makes pc -1, e.g. `while true do end`
+            pc = pc.wrapping_add(1);
         }
     }
 
+    // Registers above the top are nil. They are read before written only
+    // by corrupted binary chunks, whose operands are checked but not the
+    // flow of values.
     fn get_stack(&self, dst: u8) -> &Value {
-        &self.stack[self.base + dst as usize]
+        self.stack.get(self.base + dst as usize).unwrap_or(&Value::Nil)
     }
     fn get_stack_mut(&mut self, dst: u8) -> &mut Value {
         &mut self.stack[self.base + dst as usize]
@@ -849,13 +2137,80 @@ impl ExeState {
         self.stack.resize(self.base + base as usize + to, Value::Nil);
     }
 
+    // Finish the calling byte code at @pc, whose callee has returned
+    // @nret values at the stack top, e.g. moving them to the registers.
+    // For Lua callees, this is called after their frames are popped,
+    // see run().
+    fn finish_call(&mut self, proto: &FuncProto, pc: &mut usize, nret: usize) {
+        match proto.byte_codes[*pc] {
+            ByteCode::ForCallLoop(iter, nvar, jmp) => {
+                // stack:
+                // - before call:
+                //     iter-func, state, ctrl-var
+                // - after call:
+                //     iter-func, state, ctrl-var, ..., return-values
+                // - update ctrl-var, and clear middle values
+                //     iter-func, state, ctrl-var*, return-values
+                let iret = self.stack.len() - nret;
+
+                if nret > 0 && self.stack[iret] != Value::Nil {
+                    // continue the loop
+                    // duplicate the first return value as ctrl-var,
+                    // so it could be changed during loop.
+                    let first_ret = self.stack[iret].clone();
+                    self.set_stack(iter + 2, first_ret);
+
+                    // move return values to @iter+3
+                    self.stack.drain(self.base + iter as usize + 3 .. iret);
+                    self.fill_stack_nil(iter + 3, nvar as usize);
+
+                    // jump back to loop
+                    *pc -= jmp as usize;
+
+                } else if jmp == 0 {
+                    // skip the following Jump
+                    *pc += 1;
+                }
+            }
+            ByteCode::Call(func, _, want_nret) => {
+                // move return values to @func
+                let iret = self.stack.len() - nret;
+                self.stack.drain(self.base+func as usize .. iret);
+
+                // want_nret==0 means 1.want all return values or 2.want no
+                // return values, while we do not need handle in both cases;
+                // otherwise, means @want_nret return values are need, and
+                // we need to fill nil if necessary.
+                let want_nret = want_nret as usize;
+                if nret < want_nret {
+                    self.fill_stack_nil(func, want_nret);
+                }
+            }
+            ByteCode::CallSet(dst, func, _) => {
+                // set first return value to @dst directly
+                if nret == 0 {
+                    self.set_stack(dst, Value::Nil);
+                } else {
+                    // use swap() to avoid clone()
+                    let iret = self.stack.len() - nret;
+                    self.stack.swap(self.base+dst as usize, iret);
+                }
+                self.stack.truncate(self.base + func as usize + 1);
+            }
+            code => panic!("invalid calling byte code: {code:?}"),
+        }
+    }
+
     // call function
-    // return the number of return values which are at the stack end
-    fn call_function(&mut self, func: u8, narg_plus: u8) -> usize {
+    // Rust functions are finished, with the return values at the stack
+    // end, while Lua functions are returned as frames to run.
+    fn call_function<'a>(&mut self, func: u8, narg_plus: u8) -> CallStart<'a> {
         self.base += func as usize + 1; // get into new world
-        let nret = self.do_call_function(narg_plus);
-        self.base -= func as usize + 1; // come back
-        nret
+        let start = self.start_call(narg_plus);
+        if let CallStart::Rust(_) = start {
+            self.base -= func as usize + 1; // come back
+        }
+        start
     }
 
     // Before calling, the function entry is at @self.base-1, and the
@@ -865,31 +2220,425 @@ impl ExeState {
     // - otherwise means (narg_plus-1) fixed arguments, and there may
     //   be temprary values following which need be truncated sometime.
     //
-    // After calling, the return values lay at the top of stack.
-    //
-    // Return the number of return values.
-    fn do_call_function(&mut self, narg_plus: u8) -> usize {
+    // Rust functions are called here. Lua functions are returned as new
+    // frames, which are run by run(), and then end_call() is called
+    // after they return.
+    fn start_call<'a>(&mut self, narg_plus: u8) -> CallStart<'a> {
         // drop potential temprary stack usage, for get_top()
         if narg_plus != 0 {
             self.stack.truncate(self.base + narg_plus as usize - 1);
         }
 
-        match self.stack[self.base - 1].clone() {
+        // Callable objects by the `__call` metamethods, which are called
+        // with the objects as the first arguments. The metamethods are
+        // removed after the call, so the callers, e.g. ForCallLoop, find
+        // their registers not moved.
+        let mut nmeta = 0;
+        while !self.stack[self.base - 1].is_function() {
+            let v = &self.stack[self.base - 1];
+            let h = v.metamethod("__call");
+            if h == Value::Nil {
+                panic!("attempt to call a {} value", v.type_name());
+            }
+            self.stack.insert(self.base - 1, h);
+            nmeta += 1;
+        }
+
+        if self.stack.len() > self.stack_limit {
+            panic!("stack overflow");
+        }
+
+        self.call_depth += 1;
+        self.calls += 1;
+        self.peak_call_depth = self.peak_call_depth.max(self.call_depth);
+        self.peak_stack_size = self.peak_stack_size.max(self.stack.len());
+        self.emit(Event::Call { depth: self.call_depth });
+        let nret = match self.stack[self.base - 1].clone() {
             Value::RustFunction(f) => f(self) as usize,
-            Value::RustClosure(c) => c.borrow_mut()(self) as usize,
-            Value::LuaFunction(f) => self.execute(&f, &Vec::new()),
-            Value::LuaClosure(c) => self.execute(&c.proto, &c.upvalues),
-            v => panic!("invalid function: {v:?}"),
+            Value::RustClosure(c) => match c.try_borrow_mut() {
+                Ok(mut f) => f(self) as usize,
+                Err(_) => panic!("attempt to call a running Rust closure"),
+            }
+            Value::LuaFunction(f) => return CallStart::Lua(self.new_frame(FrameFunc::Function(f), nmeta)),
+            Value::LuaClosure(c) => return CallStart::Lua(self.new_frame(FrameFunc::Closure(c), nmeta)),
+            _ => panic!("impossible"),
+        };
+        if self.checks_memory() {
+            self.check_memory();
         }
+        self.end_call(nmeta, nret);
+        CallStart::Rust(nret)
     }
 
-    fn close_brokers(&self, open_brokers: impl IntoIterator<Item = OpenBroker>) {
-        for OpenBroker { ilocal, broker } in open_brokers {
+    // the function at @self.base-1 has returned @nret values
+    fn end_call(&mut self, nmeta: usize, nret: usize) {
+        self.emit(Event::Return { depth: self.call_depth, nret });
+        self.call_depth -= 1;
+        if nmeta > 0 {
+            self.stack.drain(self.base - 1 .. self.base - 1 + nmeta);
+        }
+    }
+
+    // Call the function at @self.base-1, see start_call(), and run it
+    // to the end if it's a Lua function.
+    //
+    // After calling, the return values lay at the top of stack.
+    //
+    // Return the number of return values.
+    fn do_call_function(&mut self, narg_plus: u8) -> usize {
+        match self.start_call(narg_plus) {
+            CallStart::Rust(nret) => nret,
+            CallStart::Lua(frame) => {
+                let nmeta = frame.nmeta;
+                let nret = self.run(&mut vec![frame], None);
+                self.end_call(nmeta, nret);
+                nret
+            }
+        }
+    }
+
+    fn check_limits(&mut self) {
+        self.instructions += self.slice;
+        self.budget -= self.slice;
+        self.slice = 0;
+        if self.budget == 0 {
+            panic!("instruction limit exceeded");
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            panic!("time limit exceeded");
+        }
+        if self.checks_memory() {
+            self.check_memory();
+        }
+        self.reset_countdown();
+    }
+
+    fn checks_memory(&self) -> bool {
+        self.max_memory != usize::MAX || !self.watermarks.is_empty()
+    }
+
+    // call the watermarks' callbacks, and raise error if over the limit
+    fn check_memory(&mut self) {
+        let used = self.memory_used();
+        for w in &mut self.watermarks {
+            let reached = used >= w.level;
+            if reached && !w.reached {
+                (w.callback)(used);
+            }
+            w.reached = reached;
+        }
+        if used > self.max_memory {
+            panic!("{}", LuaError::MemoryError);
+        }
+    }
+
+    fn reset_countdown(&mut self) {
+        self.instructions += self.slice - self.countdown;
+        self.slice = if self.deadline.is_some() || self.checks_memory() {
+            self.budget.min(CHECK_INTERVAL)
+        } else {
+            self.budget
+        };
+        self.countdown = self.slice;
+    }
+
+    // remaining budget, including the current slice
+    fn remaining_budget(&self) -> u64 {
+        self.budget - (self.slice - self.countdown)
+    }
+
+    // Call the function at @func (1-based) under @limit. Errors are
+    // caught by unwinding, and then the stack and call depth are
+    // restored, and open upvalues of the aborted Lua functions are
+    // closed.
+    pub(crate) fn pcall_limit(&mut self, func: usize, limit: ExecLimit) -> Result<usize, LuaError> {
+        let (old_budget, old_deadline) = (self.remaining_budget(), self.deadline);
+        let new_budget = match limit {
+            ExecLimit::Instructions(n) => old_budget.min(n),
+            ExecLimit::Time(d) => {
+                // no new deadline if it's too far to be represented
+                if let Some(deadline) = Instant::now().checked_add(d) {
+                    self.deadline = Some(old_deadline.map_or(deadline, |od| od.min(deadline)));
+                }
+                old_budget
+            }
+        };
+        self.budget = new_budget;
+        self.reset_countdown();
+
+        let (base, call_depth, rust_depth, nny) = (self.base, self.call_depth, self.rust_depth, self.nny);
+        let result = catch_error(|| self.call_at(func));
+
+        // deduct the used budget from the outer one
+        let used = new_budget - self.remaining_budget();
+        self.budget = old_budget.saturating_sub(used);
+        self.deadline = old_deadline;
+        self.reset_countdown();
+
+        result.map_err(|e| {
+            let (msg, err) = self.caught_error(&*e);
+            self.emit(Event::Error { depth: self.call_depth, message: &msg });
+
+            self.base = base;
+            self.call_depth = call_depth;
+            self.rust_depth = rust_depth;
+            self.nny = nny;
+            #[cfg(feature = "tracing")]
+            self.exit_spans(call_depth);
+            self.close_brokers(self.base + func - 1);
+            self.stack.truncate(self.base + func - 1);
+            if msg == LuaError::MemoryError.to_string() {
+                return LuaError::MemoryError;
+            }
+            err
+        })
+    }
+
+    // slow path of getting table in register @t, by metamethods
+    fn get_table_meta(&mut self, proto: &FuncProto, pc: usize, t: u8, key: Value) -> Value {
+        let table = self.get_stack(t).clone();
+        if !matches!(table, Value::Table(_)) && self.metatable_of(&table).is_none() {
+            index_error(&table, proto.describe_reg(t, pc));
+        }
+        self.index_meta(table, key)
+    }
+
+    // set table in register @t, by metamethods if need
+    fn set_table(&mut self, proto: &FuncProto, pc: usize, t: u8, key: Value, value: Value) {
+        if let Err((key, value)) = self.get_stack(t).try_new_index(key, value) {
+            let table = self.get_stack(t).clone();
+            if !matches!(table, Value::Table(_)) {
+                index_error(&table, proto.describe_reg(t, pc));
+            }
+            self.new_index_meta(table, key, value);
+        }
+    }
+
+    // the value of upvalue @t for indexing, e.g. not-table `_ENV`
+    fn get_upvalue_table(&self, proto: &FuncProto, upvalues: &[Rc<RefCell<Upvalue>>], t: u8) -> Value {
+        let table = upvalues[t as usize].borrow().get(&self.stack).clone();
+        if !matches!(table, Value::Table(_)) {
+            index_error(&table, proto.upvalue_names.get(t as usize)
+                .map(|name| format!("upvalue '{name}'")));
+        }
+        table
+    }
+
+    // generator of `math.random()`, which is per state, so the sequences
+    // are not affected by other states in the same thread
+    pub(crate) fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    pub(crate) fn timers(&mut self) -> &mut Timers {
+        &mut self.timers
+    }
+
+    // environment variables written by `os.env`, which are per state and
+    // passed to the commands only, but not set in the process, because
+    // other threads may be reading the environment at the same time
+    pub(crate) fn env_vars(&self) -> &HashMap<String, Option<String>> {
+        &self.env_vars
+    }
+
+    pub(crate) fn set_env_var(&mut self, name: String, v: Option<String>) {
+        self.env_vars.insert(name, v);
+    }
+
+    // Metatable of @v. Tables and userdata have their own ones, and all
+    // strings share the one whose `__index` is the string library, same
+    // with the official Lua. Other types have none.
+    fn metatable_of(&self, v: &Value) -> Option<Rc<RefCell<Table>>> {
+        match v {
+            Value::Table(t) => t.borrow().metatable.clone(),
+            Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_) =>
+                Some(self.string_meta.clone()),
+            Value::UserData(u) => u.borrow().metatable.clone(),
+            _ => None,
+        }
+    }
+
+    // Get @t[@key] by the `__index` metamethods, which may be a table
+    // to index again, or a function called with @t and @key. The chain
+    // is limited by MAX_META_LOOP, same with the official Lua.
+    pub(crate) fn index_meta(&mut self, mut t: Value, key: Value) -> Value {
+        for _ in 0..MAX_META_LOOP {
+            let h = match &t {
+                Value::Table(table) => {
+                    let table = table.borrow();
+                    let v = table.index(&key);
+                    if *v != Value::Nil {
+                        return v.clone();
+                    }
+                    table.metamethod("__index")
+                }
+                _ => match self.metatable_of(&t) {
+                    Some(mt) => mt.borrow().map.get(&"__index".into()).cloned().unwrap_or(Value::Nil),
+                    None => Value::Nil,
+                }
+            };
+            if h == Value::Nil && !matches!(t, Value::Table(_)) {
+                index_error(&t, None);
+            }
+            if h == Value::Nil {
+                return Value::Nil;
+            }
+            if h.is_function() {
+                return self.call(h, &[t, key]).into_iter().next().unwrap_or(Value::Nil);
+            }
+            t = h;
+        }
+        panic!("'__index' chain too long; possible loop");
+    }
+
+    // Set @t[@key]=@value by the `__newindex` metamethods, only if the
+    // key is absent, same with index_meta().
+    pub(crate) fn new_index_meta(&mut self, mut t: Value, key: Value, value: Value) {
+        for _ in 0..MAX_META_LOOP {
+            let h = match &t {
+                Value::Table(table) => {
+                    // the metatable may be the table itself, so do not
+                    // borrow it mutably before getting the metamethod
+                    let h = {
+                        let table = table.borrow();
+                        if *table.index(&key) != Value::Nil {
+                            Value::Nil
+                        } else {
+                            table.metamethod("__newindex")
+                        }
+                    };
+                    if h == Value::Nil {
+                        table.borrow_mut().new_index(key, value);
+                        return;
+                    }
+                    h
+                }
+                _ => index_error(&t, None),
+            };
+            if h.is_function() {
+                self.call(h, &[t, key, value]);
+                return;
+            }
+            t = h;
+        }
+        panic!("'__newindex' chain too long; possible loop");
+    }
+
+    // Slow path of the arithmetic and bitwise byte code at @pc, whose
+    // operands are not numbers: call the metamethod of the first operand,
+    // or else of the second one, with both operands, same with the
+    // official Lua. The unary `-` and `~` pass the operand twice.
+    fn arith_meta(&mut self, proto: &FuncProto, pc: usize) -> Value {
+        use ByteCode::*;
+        let (event, a, b) = match proto.byte_codes[pc] {
+            Add(_, a, b) => ("__add", a, Rhs::Reg(b)),
+            AddConst(_, a, k) => ("__add", a, Rhs::Const(k)),
+            AddInt(_, a, i) => ("__add", a, Rhs::Int(i)),
+            Sub(_, a, b) => ("__sub", a, Rhs::Reg(b)),
+            SubConst(_, a, k) => ("__sub", a, Rhs::Const(k)),
+            SubInt(_, a, i) => ("__sub", a, Rhs::Int(i)),
+            Mul(_, a, b) => ("__mul", a, Rhs::Reg(b)),
+            MulConst(_, a, k) => ("__mul", a, Rhs::Const(k)),
+            MulInt(_, a, i) => ("__mul", a, Rhs::Int(i)),
+            Mod(_, a, b) => ("__mod", a, Rhs::Reg(b)),
+            ModConst(_, a, k) => ("__mod", a, Rhs::Const(k)),
+            ModInt(_, a, i) => ("__mod", a, Rhs::Int(i)),
+            Idiv(_, a, b) => ("__idiv", a, Rhs::Reg(b)),
+            IdivConst(_, a, k) => ("__idiv", a, Rhs::Const(k)),
+            IdivInt(_, a, i) => ("__idiv", a, Rhs::Int(i)),
+            Div(_, a, b) => ("__div", a, Rhs::Reg(b)),
+            DivConst(_, a, k) => ("__div", a, Rhs::Const(k)),
+            DivInt(_, a, i) => ("__div", a, Rhs::Int(i)),
+            Pow(_, a, b) => ("__pow", a, Rhs::Reg(b)),
+            PowConst(_, a, k) => ("__pow", a, Rhs::Const(k)),
+            PowInt(_, a, i) => ("__pow", a, Rhs::Int(i)),
+            BitAnd(_, a, b) => ("__band", a, Rhs::Reg(b)),
+            BitAndConst(_, a, k) => ("__band", a, Rhs::Const(k)),
+            BitAndInt(_, a, i) => ("__band", a, Rhs::Int(i)),
+            BitOr(_, a, b) => ("__bor", a, Rhs::Reg(b)),
+            BitOrConst(_, a, k) => ("__bor", a, Rhs::Const(k)),
+            BitOrInt(_, a, i) => ("__bor", a, Rhs::Int(i)),
+            BitXor(_, a, b) => ("__bxor", a, Rhs::Reg(b)),
+            BitXorConst(_, a, k) => ("__bxor", a, Rhs::Const(k)),
+            BitXorInt(_, a, i) => ("__bxor", a, Rhs::Int(i)),
+            ShiftL(_, a, b) => ("__shl", a, Rhs::Reg(b)),
+            ShiftLConst(_, a, k) => ("__shl", a, Rhs::Const(k)),
+            ShiftLInt(_, a, i) => ("__shl", a, Rhs::Int(i)),
+            ShiftR(_, a, b) => ("__shr", a, Rhs::Reg(b)),
+            ShiftRConst(_, a, k) => ("__shr", a, Rhs::Const(k)),
+            ShiftRInt(_, a, i) => ("__shr", a, Rhs::Int(i)),
+            Neg(_, a) => ("__unm", a, Rhs::Reg(a)),
+            BitNot(_, a) => ("__bnot", a, Rhs::Reg(a)),
+            _ => panic!("impossible"),
+        };
+
+        let v1 = self.get_stack(a).clone();
+        let v2 = match b {
+            Rhs::Reg(b) => self.get_stack(b).clone(),
+            Rhs::Const(k) => proto.constants[k as usize].clone(),
+            Rhs::Int(i) => Value::Integer(i as i64),
+        };
+        let h = match v1.metamethod(event) {
+            Value::Nil => v2.metamethod(event),
+            h => h,
+        };
+        if h == Value::Nil {
+            // the culprit is the first operand which is not a number,
+            // and only registers have names
+            let (culprit, reg) = match v1.to_number() {
+                None => (&v1, Some(a)),
+                Some(_) => (&v2, if let Rhs::Reg(b) = b { Some(b) } else { None }),
+            };
+            let op = match event {
+                "__band" | "__bor" | "__bxor" | "__shl" | "__shr" | "__bnot" => "perform bitwise operation on",
+                _ => "perform arithmetic on",
+            };
+            match reg.and_then(|r| proto.describe_reg(r, pc)) {
+                Some(name) => panic!("attempt to {op} a {} value ({name})", culprit.type_name()),
+                None => panic!("attempt to {op} a {} value", culprit.type_name()),
+            }
+        }
+        self.call(h, &[v1, v2]).into_iter().next().unwrap_or(Value::Nil)
+    }
+
+    fn check_callable(&self, proto: &FuncProto, pc: usize, func: u8) {
+        let v = self.get_stack(func);
+        if !v.is_function() && v.metamethod("__call") == Value::Nil {
+            match proto.describe_reg(func, pc) {
+                Some(name) => panic!("attempt to call a {} value ({name})", v.type_name()),
+                None => panic!("attempt to call a {} value", v.type_name()),
+            }
+        }
+    }
+
+    // close the open upvalues of locals from @ilocal
+    fn close_brokers(&mut self, ilocal: usize) {
+        let from = self.open_brokers.partition_point(|b| b.ilocal < ilocal);
+        for OpenBroker { ilocal, broker } in self.open_brokers.drain(from..) {
             let openi = broker.replace(Upvalue::Closed(self.stack[ilocal].clone()));
             debug_assert_eq!(openi, Upvalue::Open(ilocal));
         }
     }
 
+    // Close the open upvalues by the values of their locals, before
+    // switching out the running thread. They are still in @open_brokers,
+    // and are reopened by reopen_brokers() when switched in again.
+    fn suspend_brokers(&self) {
+        for OpenBroker { ilocal, broker } in &self.open_brokers {
+            broker.replace(Upvalue::Closed(self.stack[*ilocal].clone()));
+        }
+    }
+
+    // Reopen the upvalues closed by suspend_brokers(), whose values may
+    // be changed by other threads.
+    fn reopen_brokers(&mut self) {
+        for OpenBroker { ilocal, broker } in &self.open_brokers {
+            if let Upvalue::Closed(v) = broker.replace(Upvalue::Open(*ilocal)) {
+                self.stack[*ilocal] = v;
+            }
+        }
+    }
+
     fn make_float(&mut self, dst: u8) -> f64 {
         match self.get_stack(dst) {
             &Value::Float(f) => f,
@@ -909,67 +2658,876 @@ impl<'a> ExeState {
     pub fn get_top(&self) -> usize {
         self.stack.len() - self.base
     }
+    // the argument @i, where missing ones are nil
     pub fn get<T>(&'a self, i: usize) -> T where T: From<&'a Value> {
-        (&self.stack[self.base + i - 1]).into()
+        self.stack[self.base..].get(i - 1).unwrap_or(&Value::Nil).into()
     }
     pub fn push(&mut self, v: impl Into<Value>) {
         self.stack.push(v.into());
     }
+
+    // Integer argument @i of the library function @fname, where floats
+    // with exact integer values and numeric strings are converted, same
+    // with `luaL_checkinteger()`.
+    pub fn check_integer(&self, i: usize, fname: &str) -> i64 {
+        if self.get_top() < i {
+            panic!("bad argument #{i} to '{fname}' (number expected, got no value)");
+        }
+        let v = self.get::<&Value>(i);
+        match v.to_number() {
+            Some(Value::Integer(n)) => n,
+            Some(Value::Float(f)) => ftoi(f).unwrap_or_else(||
+                panic!("bad argument #{i} to '{fname}' (number has no integer representation)")),
+            _ => panic!("bad argument #{i} to '{fname}' (number expected, got {})", v.type_name()),
+        }
+    }
+
+    // Whether @n more values can be pushed under the stack limit, same
+    // with `lua_checkstack()`, e.g. before pushing all items of a list.
+    pub fn check_stack(&self, n: usize) -> bool {
+        self.stack.len().checked_add(n).is_some_and(|top| top <= self.stack_limit)
+    }
+
+    // Set the top to @top, same with `lua_settop()`, where values above
+    // it are removed and missing ones are filled by nil.
+    pub fn set_top(&mut self, top: usize) {
+        self.stack.resize(self.base + top, Value::Nil);
+    }
+
+    // value of the upvalue, which may be open on the stack
+    pub(crate) fn get_upvalue(&self, up: &Upvalue) -> Value {
+        up.get(&self.stack).clone()
+    }
+
+    // Execute the Lua source file as the main chunk, and return its
+    // return values, e.g. the table returned by a configuration file.
+    // Syntax and runtime errors are returned too, and the state is still
+    // usable after them.
+    pub fn exec_file(&mut self, path: impl AsRef<Path>) -> Result<Vec<Value>, LuaError> {
+        let chunk = fs::read(&path)?;
+        let chunk_name = format!("@{}", path.as_ref().display());
+        let proto = self.try_load(&chunk, &chunk_name)?;
+        self.emit(Event::Load { chunk: &path.as_ref().to_string_lossy() });
+        self.try_exec_main(Rc::new(proto), &[])
+    }
+
+    // Compile the chunk by the compiler set in ExeStateBuilder, without
+    // running it.
+    pub fn load(&self, chunk: &[u8], chunk_name: &str) -> FuncProto {
+        self.compiler.compile(chunk, chunk_name)
+    }
+
+    // Same with load(), but return the syntax error instead of raising
+    // it, e.g. for the host to report the bad scripts of users.
+    pub fn try_load(&self, chunk: &[u8], chunk_name: &str) -> Result<FuncProto, LuaError> {
+        catch_error(|| self.load(chunk, chunk_name))
+            .map_err(|e| LuaError::from_compile_error(panic_message(&*e)))
+    }
+
+    // Execute the main chunk and return its return values. The stack
+    // is cleared after, so the state can run more chunks.
+    pub fn exec_main(&mut self, proto: &FuncProto) -> Vec<Value> {
+        // a tail call at the end of the chunk drains the entry function
+        // and `_ENV`, so save them to restore
+        let entry = self.stack[..2].to_vec();
+        let nret = self.execute(proto, &[]);
+        let rets = self.stack.split_off(self.stack.len() - nret);
+        self.stack.clear();
+        self.stack.extend(entry);
+        rets
+    }
+
+    // Same with exec_main(), but with @args as the varargs `...`, and
+    // return the error instead of raising it. The state is still usable
+    // after errors, so the host can run more chunks.
+    pub fn try_exec_main(&mut self, proto: Rc<FuncProto>, args: &[Value]) -> Result<Vec<Value>, LuaError> {
+        // the main chunk gets `_ENV` as its first parameter
+        let args: Vec<Value> = [self.env()].into_iter()
+            .chain(args.iter().cloned())
+            .collect();
+        let rets = self.pcall(Value::LuaFunction(proto), &args);
+        self.flush();
+        rets
+    }
+
+    // Run a configuration file in a restricted environment, and convert
+    // the table it returns into @T. The environment has only library
+    // functions without side effects, so no `print` or `require`.
+    pub fn eval_config<T>(path: impl AsRef<Path>) -> Result<T, LuaError>
+        where T: TryFrom<Value>, LuaError: From<T::Error>
+    {
+        Ok(T::try_from(Self::exec_config(path)?)?)
+    }
+
+    // the table returned by the configuration file, see eval_config()
+    pub(crate) fn exec_config(path: impl AsRef<Path>) -> Result<Value, LuaError> {
+        let mut state = ExeState::new();
+        state.stack[1] = state.config_env();
+
+        match state.exec_file(path)?.into_iter().next() {
+            Some(t@Value::Table(_)) => Ok(t),
+            v => Err(LuaError::runtime(format!("config should return a table, got {}",
+                v.as_ref().map_or("no value", Value::type_name)))),
+        }
+    }
+
+    fn config_env(&self) -> Value {
+        let globals = self.env();
+        let mut env = Table::new(0, 0);
+        for name in ["type", "tonumber", "ipairs", "string", "table", "math"] {
+            env.map.insert(name.into(), globals.index(&name.into()));
+        }
+        Value::from(env)
+    }
+
+    // register a module loader written in Rust, for `require`
+    pub fn register_module(&mut self, name: &str, loader: stdlib::LibFunction) {
+        stdlib::package::register(self, name, loader);
+    }
+
+    // Set a module loader into `package.preload`, which is searched by
+    // `require` before the Lua files, while register_module() is after.
+    // The loader is called with the module name and ":preload:", and
+    // returns the module, e.g.:
+    //
+    //     state.preload_module("config", move |state| {
+    //         state.push(config.clone());
+    //         1
+    //     });
+    pub fn preload_module(&mut self, name: &str, loader: impl FnMut(&mut ExeState) -> i32 + 'static) {
+        let loader = self.create_function(loader);
+        stdlib::package::preload(self, name, loader);
+    }
+
+    // the global table `_ENV`, which is the argument of the entry function
+    pub(crate) fn env(&self) -> Value {
+        self.stack[1].clone()
+    }
+
+    // Handle of the global table, for the host to read, change and
+    // enumerate global variables, e.g. to find the functions defined by
+    // a script.
+    pub fn globals(&self) -> TableHandle {
+        match &self.stack[1] {
+            Value::Table(t) => TableHandle::new(t.clone()),
+            _ => panic!("no global table"),
+        }
+    }
+
+    // Make a Lua function of the Rust closure @f, which may capture the
+    // host's data, e.g. configuration or a channel to send events to:
+    //
+    //     let log = events.clone();
+    //     let emit = state.create_function(move |state| {
+    //         log.borrow_mut().push(state.get::<&Value>(1).to_string());
+    //         0
+    //     });
+    //     state.globals().set("emit", emit);
+    //
+    // It's called same with `fn` functions, see Value::RustFunction, but
+    // can not be called again while it's running, e.g. recursively.
+    pub fn create_function(&self, f: impl FnMut(&mut ExeState) -> i32 + 'static) -> Value {
+        Value::RustClosure(Rc::new(RefCell::new(Box::new(f))))
+    }
+
+    // Same with create_function(), but the arguments and return values
+    // are converted by FromLuaMulti and IntoLuaMulti, where the bad
+    // arguments raise errors with @name, e.g.:
+    //
+    //     let add = state.create_typed_function("add", |(a, b): (i64, i64)| a + b);
+    pub fn create_typed_function<A, R>(&self, name: &str, mut f: impl FnMut(A) -> R + 'static) -> Value
+        where A: FromLuaMulti, R: IntoLuaMulti
+    {
+        let name = name.to_string();
+        self.create_function(move |state| {
+            let args = state.args(&name);
+            let rets = f(args);
+            state.push_multi(rets)
+        })
+    }
+
+    // Convert the argument @i into @T, or raise the error of bad argument
+    // for the function @fname, e.g. `let n: i64 = state.check(1, "f")`.
+    pub fn check<T: FromLua>(&self, i: usize, fname: &str) -> T {
+        let v = if i <= self.get_top() { Some(self.get::<&Value>(i)) } else { None };
+        T::from_lua(v).unwrap_or_else(|e| panic!("bad argument #{i} to '{fname}' ({e})"))
+    }
+
+    // Convert all the arguments, e.g. into a tuple of @T.
+    pub fn args<T: FromLuaMulti>(&self, fname: &str) -> T {
+        T::from_lua_multi(&self.stack[self.base..])
+            .unwrap_or_else(|(i, e)| panic!("bad argument #{i} to '{fname}' ({e})"))
+    }
+
+    // Push the return values, and return the number of them, e.g.
+    // `return state.push_multi((true, "ok"))` in Rust functions.
+    pub fn push_multi(&mut self, rets: impl IntoLuaMulti) -> i32 {
+        let rets = rets.into_lua_multi();
+        let n = rets.len();
+        self.stack.extend(rets);
+        n as i32
+    }
+
+    // Make a Lua iterator of the Rust iterator @iter, to be used in
+    // generic-for directly, e.g. `for row in rows do ... end` where `rows`
+    // is set to the host's dataset. The items are converted into values
+    // one by one when the loop asks, so the dataset is not collected into
+    // a table. The items are converted by IntoLua, same with the return
+    // values of create_typed_function(). The loop ends when @iter ends, or
+    // when an item converts into nil, e.g. None, same with other Lua
+    // iterators.
+    //
+    // The iterator is a Rust closure, so it's consumed once even if passed
+    // around, and it's dropped with the value, e.g. after a `break`.
+    pub fn create_iterator<I>(&self, iter: I) -> Value
+        where I: IntoIterator + 'static, I::Item: IntoLua
+    {
+        let mut iter = iter.into_iter();
+        self.create_function(move |state| match iter.next() {
+            Some(item) => {
+                state.push(item.into_lua());
+                1
+            }
+            None => 0,
+        })
+    }
+
+    // Register the Rust type @T to be exposed to Lua as userdata, e.g. game
+    // objects, by create_userdata(). The @methods are called by scripts as
+    // `obj:method(...)`, where the Rust functions get the value back by
+    // check_userdata(). The names starting with "__" are metamethods
+    // instead, e.g. "__add" and "__call". The @name is for error messages.
+    //
+    // All values of @T share the metatable. Registering again replaces it
+    // for the values created after.
+    pub fn register_userdata<T: Any>(&mut self, name: &str, methods: &[(&str, stdlib::LibFunction)]) {
+        let mut mt = Table::new(0, 2);
+        let mut index = Table::new(0, methods.len());
+        for &(mname, f) in methods {
+            let t = if mname.starts_with("__") { &mut mt } else { &mut index };
+            t.map.insert(mname.into(), Value::RustFunction(f));
+        }
+        mt.map.insert("__name".into(), name.into());
+        if !index.map.is_empty() {
+            mt.map.insert("__index".into(), Value::from(index));
+        }
+        let Value::Table(mt) = Value::from(mt) else {
+            unreachable!();
+        };
+        self.userdata_metas.insert(TypeId::of::<T>(), mt);
+    }
+
+    // Wrap @value as userdata, with the metatable of @T if registered,
+    // otherwise it has no metatable, and scripts can only pass it around.
+    pub fn create_userdata<T: Any>(&self, value: T) -> Value {
+        let mt = self.userdata_metas.get(&TypeId::of::<T>()).cloned();
+        Value::from(UserData::new(value, mt))
+    }
+
+    // The Rust value of the userdata at @i (1-based, same with get()), or
+    // None if it's not a userdata of @T. It's borrowed until the returned
+    // reference is dropped, so do not keep it while calling Lua.
+    pub fn get_userdata<T: Any>(&self, i: usize) -> Option<RefMut<'_, T>> {
+        match &self.stack[self.base + i - 1] {
+            Value::UserData(u) => RefMut::filter_map(u.borrow_mut(), UserData::downcast_mut).ok(),
+            _ => None,
+        }
+    }
+
+    // Same with get_userdata(), but raise the error of bad argument if
+    // it's not a userdata of @T, e.g. for the methods:
+    //
+    //     fn get_x(state: &mut ExeState) -> i32 {
+    //         let x = state.check_userdata::<Point>(1, "get_x").x;
+    //         state.push(x);
+    //         1
+    //     }
+    pub fn check_userdata<T: Any>(&self, i: usize, fname: &str) -> RefMut<'_, T> {
+        if i <= self.get_top() {
+            if let Some(v) = self.get_userdata(i) {
+                return v;
+            }
+        }
+        let expected = match self.userdata_metas.get(&TypeId::of::<T>()) {
+            Some(mt) => mt.borrow().map.get(&"__name".into()).map_or_else(String::new, Value::to_string),
+            None => any::type_name::<T>().to_string(),
+        };
+        let got = if i <= self.get_top() { self.get::<&Value>(i).type_name() } else { "no value" };
+        panic!("bad argument #{i} to '{fname}' ({expected} expected, got {got})");
+    }
+
+    // Call the function at @func (1-based, same with get()) with all
+    // following values as arguments. The return values are moved to
+    // @func, and the number of them is returned.
+    //
+    // This is reentrant: the callee gets a new frame above the current
+    // one, which may call back into Rust and then Lua again, and the
+    // base and call depth are restored after it. Unlike call_function(),
+    // @func is not limited to registers, so Rust functions with many
+    // stack values can call too.
+    pub(crate) fn call_at(&mut self, func: usize) -> usize {
+        if self.rust_depth >= self.max_call_depth {
+            panic!("C stack overflow");
+        }
+        // calls by the host, but not by Rust functions, are yieldable
+        let nny = (self.call_depth > 0) as usize;
+
+        self.base += func; // get into new world
+        self.nny += nny;
+        self.rust_depth += 1;
+        let nret = self.do_call_function(0);
+        self.rust_depth -= 1;
+        self.nny -= nny;
+        self.base -= func; // come back
+        let iret = self.stack.len() - nret;
+        self.stack.drain(self.base + func - 1 .. iret);
+        nret
+    }
+
+    // Check before yielding, by the functions which suspend the running
+    // coroutine. Rust functions can not be suspended in the middle, so
+    // yielding is not allowed if any of them is calling Lua, e.g. in a
+    // comparator of `table.sort()`, rather than breaking their frames.
+    // Rust functions which yield by themselves are fine, since they
+    // return before the coroutine is suspended.
+    pub fn check_yield(&self) {
+        if self.nny > 0 {
+            panic!("attempt to yield across a C-call boundary");
+        }
+    }
+
+    // Yield the running coroutine after the current Rust function
+    // returns, with its return values, which are returned by resume().
+    // The values passed by the next resume() are returned to the caller
+    // of the Rust function then. See `coroutine.yield()`.
+    pub fn yield_on_return(&mut self) {
+        if Rc::ptr_eq(&self.current, &self.main) {
+            panic!("attempt to yield from outside a coroutine");
+        }
+        self.check_yield();
+        self.yielding = true;
+    }
+
+    // Suspend the running coroutine like yield_on_return(), and then the
+    // resumer switches to @co with the return values, but does not return
+    // to its caller, see resume(). It's the symmetric transfer, which is
+    // not standard, see `coroutine.transfer()`.
+    #[cfg(feature = "transfer")]
+    pub fn transfer_on_return(&mut self, co: Rc<RefCell<Coroutine>>) {
+        self.yield_on_return();
+        self.transfer = Some(co);
+    }
+
+    // the running thread, and whether it's the main thread
+    pub fn running(&self) -> (Rc<RefCell<Coroutine>>, bool) {
+        (self.current.clone(), Rc::ptr_eq(&self.current, &self.main))
+    }
+
+    pub fn is_yieldable(&self) -> bool {
+        !Rc::ptr_eq(&self.current, &self.main) && self.nny == 0
+    }
+
+    // Start or continue the coroutine @co with @args, until it yields
+    // or returns, and return the values yielded or returned. Errors are
+    // caught, and then the coroutine is dead.
+    //
+    // The coroutine runs on its own stack, which is switched in as
+    // @self.stack, while the stack of the resuming thread is saved here.
+    // Its call frames are run by run() in this Rust call, so the frames
+    // of the resuming thread are suspended in the Rust stack, and it
+    // continues after the coroutine yields.
+    //
+    // If the coroutine transfers to another one, it's resumed here in
+    // turn, so the transfers do not nest in the Rust stack. The values
+    // are returned when any coroutine of the chain yields or returns.
+    pub fn resume(&mut self, co: &Rc<RefCell<Coroutine>>, args: &[Value]) -> Result<Vec<Value>, LuaError> {
+        let result = self.switch_to(co, args);
+
+        #[cfg(feature = "transfer")]
+        let result = {
+            let mut result = result;
+            while let (Ok(values), Some(next)) = (&result, self.transfer.take()) {
+                result = self.switch_to(&next, &values.clone());
+            }
+            result
+        };
+
+        result
+    }
+
+    fn switch_to(&mut self, co: &Rc<RefCell<Coroutine>>, args: &[Value]) -> Result<Vec<Value>, LuaError> {
+        if self.rust_depth >= self.max_call_depth {
+            return Err(LuaError::runtime("C stack overflow"));
+        }
+        let (func, mut frames, stack, open_brokers, depth) = {
+            let mut c = co.borrow_mut();
+            match c.status {
+                CoStatus::Suspended => (),
+                CoStatus::Dead => return Err(LuaError::runtime("cannot resume dead coroutine")),
+                _ => return Err(LuaError::runtime("cannot resume non-suspended coroutine")),
+            }
+            c.status = CoStatus::Running;
+            (c.func.take(), mem::take(&mut c.frames), mem::take(&mut c.stack),
+                mem::take(&mut c.open_brokers), c.depth)
+        };
+
+        // switch in
+        let env = self.env();
+        self.suspend_brokers();
+        let stack = mem::replace(&mut self.stack, stack);
+        let open_brokers = mem::replace(&mut self.open_brokers, open_brokers);
+        let (base, nny, call_depth, stack_limit) = (self.base, self.nny, self.call_depth, self.stack_limit);
+        let rust_depth = self.rust_depth;
+        let prev = mem::replace(&mut self.current, co.clone());
+        prev.borrow_mut().status = CoStatus::Normal;
+        self.reopen_brokers();
+        self.nny = 0;
+        self.call_depth += depth;
+        self.rust_depth += 1;
+        self.stack_limit = self.max_coroutine_stack_size;
+
+        let result = catch_error(|| {
+            if let Some(f) = func {
+                // 0: un-used entry function, 1: `_ENV`, same with the
+                // main stack, 2: the function, and then the arguments
+                self.stack = Vec::with_capacity(self.coroutine_stack_size.max(3 + args.len()));
+                self.stack.extend([Value::Nil, env, f]);
+                self.stack.extend_from_slice(args);
+                self.base = 3;
+                match self.start_call(0) {
+                    CallStart::Rust(nret) => nret,
+                    CallStart::Lua(frame) => {
+                        frames.push(frame);
+                        self.run_coroutine(&mut frames, None)
+                    }
+                }
+            } else {
+                self.stack.extend_from_slice(args);
+                if frames.is_empty() {
+                    // yielded by the Rust function as the coroutine,
+                    // which returns the arguments now
+                    args.len()
+                } else {
+                    self.run_coroutine(&mut frames, Some(args.len()))
+                }
+            }
+        });
+
+        let yielded = mem::take(&mut self.yielding);
+        let result = match result {
+            Ok(nret) => Ok(self.stack.split_off(self.stack.len() - nret)),
+            Err(e) => {
+                let (msg, err) = self.caught_error(&*e);
+                self.emit(Event::Error { depth: self.call_depth, message: &msg });
+                Err(err)
+            }
+        };
+
+        // switch out
+        let mut c = co.borrow_mut();
+        if yielded && result.is_ok() {
+            self.suspend_brokers();
+            c.status = CoStatus::Suspended;
+            if frames.capacity() > frames.len() * 4 {
+                frames.shrink_to(frames.len() * 2);
+            }
+            c.frames = frames;
+            c.depth = self.call_depth - call_depth;
+            c.open_brokers = mem::replace(&mut self.open_brokers, open_brokers);
+            c.stack = mem::replace(&mut self.stack, stack);
+            let len = c.stack.len();
+            if c.stack.capacity() > len * 4 {
+                c.stack.shrink_to((len * 2).max(self.coroutine_stack_size));
+            }
+        } else {
+            self.close_brokers(0);
+            c.status = CoStatus::Dead;
+            self.open_brokers = open_brokers;
+            self.stack = stack;
+        }
+        drop(c);
+        self.base = base;
+        self.nny = nny;
+        self.call_depth = call_depth;
+        self.rust_depth = rust_depth;
+        self.stack_limit = stack_limit;
+        #[cfg(feature = "tracing")]
+        self.exit_spans(call_depth);
+        prev.borrow_mut().status = CoStatus::Running;
+        self.current = prev;
+        self.reopen_brokers();
+        result
+    }
+
+    // run the frames of the coroutine, and end the call of its function
+    // if it returns but not yields
+    fn run_coroutine(&mut self, frames: &mut Vec<Frame<'static>>, finished: Option<usize>) -> usize {
+        let nret = self.run(frames, finished);
+        if !self.yielding {
+            self.end_call(0, nret);
+        }
+        nret
+    }
+
+    // Raise an error with @value as the error object, e.g. by `error()`.
+    // If it's a string, the position of the function at @level of calls
+    // is added, where 1 is the caller of the running Rust function, and
+    // 0 means no position.
+    pub fn raise_error(&mut self, value: Value, level: usize) -> ! {
+        let depth = match value.as_bytes() {
+            Some(_) if level > 0 => self.call_depth.checked_sub(level),
+            _ => None,
+        };
+        let message = LuaError::runtime(value.clone()).to_string();
+        self.raised = Some(RaisedError::new(value, message.clone(), depth));
+        panic::panic_any(message)
+    }
+
+    // the message and the runtime error of the caught error, with the
+    // traceback of the unwound Lua functions, see raise_error()
+    fn caught_error(&mut self, e: &(dyn Any + Send)) -> (String, LuaError) {
+        let msg = panic_message(e);
+        let err = match self.raised.take() {
+            Some(raised) if raised.message == msg => {
+                LuaError::RuntimeError { traceback: raised.traceback(), value: raised.value }
+            }
+            _ => LuaError::runtime(msg.as_str()),
+        };
+        (msg, err)
+    }
+
+    // Call @f with @args, and return the return values. This is for Rust
+    // functions to call back into Lua, e.g. a comparator passed to them.
+    // Errors are propagated to the enclosing protected call, same with
+    // the official `lua_call()`. See pcall() to catch them.
+    pub fn call(&mut self, f: Value, args: &[Value]) -> Vec<Value> {
+        let ifunc = self.get_top() + 1;
+        self.push(f);
+        self.stack.extend_from_slice(args);
+        let nret = self.call_at(ifunc);
+        self.stack.split_off(self.stack.len() - nret)
+    }
+
+    // Call @f with @args under @limit, and return the return values or
+    // the error, e.g. "instruction limit exceeded".
+    pub fn pcall_with_limit(&mut self, f: Value, args: &[Value], limit: ExecLimit)
+        -> Result<Vec<Value>, LuaError>
+    {
+        let ifunc = self.get_top() + 1;
+        self.push(f);
+        self.stack.extend_from_slice(args);
+        let nret = self.pcall_limit(ifunc, limit)?;
+        Ok(self.stack.split_off(self.stack.len() - nret))
+    }
+
+    // Call @f with @args, and return the return values or the error.
+    pub fn pcall(&mut self, f: Value, args: &[Value]) -> Result<Vec<Value>, LuaError> {
+        self.pcall_with_limit(f, args, ExecLimit::Instructions(u64::MAX))
+    }
+
+    // Run a full collection of reference cycles, see gc.rs, and return
+    // the number of objects collected. It's also triggered by allocating
+    // tables and closures.
+    pub fn collect_garbage(&mut self) -> usize {
+        let collected = gc::collect();
+        self.emit(Event::Gc { collected });
+        collected
+    }
+
+    // bytes allocated since the state is created, see ExeStateBuilder
+    pub fn memory_used(&self) -> usize {
+        (memory::allocated() - self.memory_base).max(0) as usize
+    }
+
+    pub fn stats(&self) -> ExeStats {
+        let (tables, strings) = value::allocations();
+        ExeStats {
+            instructions: self.instructions + (self.slice - self.countdown),
+            calls: self.calls,
+            peak_call_depth: self.peak_call_depth,
+            peak_stack_size: self.peak_stack_size,
+            tables: tables - self.allocations.0,
+            strings: strings - self.allocations.1,
+            gc_cycles: gc::cycles() - self.gc_cycles,
+        }
+    }
+
+    // Set the global table `arg` with the command line arguments, same
+    // with the standalone Lua, where `arg[0]` is the script name.
+    pub fn set_arg(&mut self, script: &str, args: &[String]) {
+        let mut t = Table::new(args.len(), 1);
+        t.extend_array(args.iter().map(|a| a.as_str().into()));
+        t.map.insert(Value::Integer(0), script.into());
+        self.env().new_index("arg".into(), Value::from(t));
+    }
+
+    // enable the opt-in `os.timelimit()`
+    #[cfg(unix)]
+    pub fn open_timelimit(&mut self) {
+        let os = self.env().index(&"os".into());
+        os.new_index("timelimit".into(), Value::RustFunction(stdlib::os::timelimit));
+    }
+
+    // enable the opt-in `timer` module, which is driven by poll_timers()
+    pub fn open_timer(&mut self) {
+        let timer = stdlib::timer::new_lib();
+        let package = self.env().index(&"package".into());
+        package.index(&"loaded".into()).new_index("timer".into(), timer.clone());
+        self.env().new_index("timer".into(), timer);
+    }
+
+    // Call the callbacks of the timers due at @now, in the order of the
+    // due time, and return the number of them. Timers created by the
+    // callbacks are not called until the next polling, even if they are
+    // due. If a callback fails, the error is returned, and the rest due
+    // timers are called by the next polling.
+    pub fn poll_timers(&mut self, now: Instant) -> Result<usize, LuaError> {
+        let due = self.timers.start_polling(now);
+        let mut n = 0;
+        for id in due {
+            // cancelled by the previous callbacks
+            let Some((f, repeating)) = self.timers.fire(id) else {
+                continue;
+            };
+            n += 1;
+            let rets = match self.pcall(f, &[]) {
+                Ok(rets) => rets,
+                Err(e) => {
+                    self.flush(); // the output before the error
+                    return Err(e);
+                }
+            };
+            if repeating && rets.first() == Some(&Value::Boolean(false)) {
+                self.timers.cancel(id);
+            }
+        }
+        self.flush();
+        Ok(n)
+    }
+
+    // the earliest due time of the timers, or None if there is no timer,
+    // e.g. for the host to sleep until
+    pub fn next_timer(&self) -> Option<Instant> {
+        self.timers.next_due()
+    }
+
+    // Flush the buffered output. It's called at the end of the chunk
+    // and when dropping, so call this only for output in the middle.
+    pub fn flush(&mut self) {
+        // ignore the error, same with the official `print()`
+        let _ = self.output.flush();
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        #[cfg(feature = "tracing")]
+        self.trace(&event);
+        if let Some(hook) = &mut self.hook {
+            hook(&event);
+        }
+    }
+
+    // Forward the event into the tracing crate. Each call is a span of
+    // TRACE level named "call", which is entered until it returns, and
+    // the other events are in the span of the running call. Calls aborted
+    // by errors or suspended by yielding do not return, so their spans
+    // are exited by the protected call or resume() at a lower depth.
+    #[cfg(feature = "tracing")]
+    fn trace(&mut self, event: &Event) {
+        match *event {
+            Event::Load { chunk } => tracing::debug!(chunk, "load"),
+            Event::Call { depth } => {
+                self.exit_spans(depth - 1);
+                self.spans.push((depth, tracing::trace_span!("call", depth).entered()));
+            }
+            Event::Return { depth, nret } => {
+                tracing::trace!(nret, "return");
+                self.exit_spans(depth - 1);
+            }
+            Event::Error { depth, message } => tracing::debug!(depth, error = message, "error"),
+            Event::Gc { collected } => tracing::debug!(collected, "gc"),
+        }
+    }
+
+    // exit the spans deeper than @depth, from the innermost one
+    #[cfg(feature = "tracing")]
+    fn exit_spans(&mut self, depth: usize) {
+        while self.spans.last().is_some_and(|&(d, _)| d > depth) {
+            self.spans.pop();
+        }
+    }
+
+    pub(crate) fn write_output(&mut self, buf: &[u8]) {
+        let _ = self.output.write_all(buf);
+    }
+
+    // write the value at @i, where strings are written as raw bytes
+    pub(crate) fn write_value(&mut self, i: usize) {
+        let v = &self.stack[self.base + i - 1];
+        let _ = match v.as_bytes() {
+            Some(s) => self.output.write_all(s),
+            None => write!(self.output, "{v}"),
+        };
+    }
 }
 
-fn exe_binop(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Value {
-    match (v1, v2) {
+impl Drop for ExeState {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// errors raised by the limits of ExeStateBuilder, see check_limits()
+fn is_limit_error(msg: &str) -> bool {
+    msg == "instruction limit exceeded" || msg == "time limit exceeded"
+        || msg == LuaError::MemoryError.to_string()
+}
+
+// the message of an error raised by panic
+pub fn panic_message(e: &(dyn Any + Send)) -> String {
+    match e.downcast_ref::<String>() {
+        Some(s) => s.clone(),
+        None => e.downcast_ref::<&str>().map_or("unknown error", |s| s).to_string(),
+    }
+}
+
+thread_local! {
+    // number of the running catch_error() of this thread
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+}
+
+// Same with panic::catch_unwind(), for the protected calls, e.g. pcall()
+// and try_load(). Lua errors are raised by panics, and the panic hook
+// prints "thread 'main' panicked at ..." for each of them to stderr by
+// default, although they are caught. So a hook is installed at the
+// first call, which calls the previous hook only for the panics out of
+// catch_error(). A hook set by the host after that replaces it, and
+// the errors are printed again. Since errors are panics, the crate
+// does not work with `panic = "abort"`.
+pub(crate) fn catch_error<R>(f: impl FnOnce() -> R) -> std::thread::Result<R> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) == 0 {
+                prev(info);
+            }
+        }));
+    });
+
+    CATCHING.with(|n| n.set(n.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|n| n.set(n.get() - 1));
+    result
+}
+
+// The exe_binop*() return None if the operands are not numbers nor
+// strings convertible to numbers, and then the metamethods are tried
+// by ExeState::arith_meta().
+fn exe_binop(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Option<Value> {
+    let r = match (v1, v2) {
         (&Value::Integer(i1), &Value::Integer(i2)) => Value::Integer(arith_i(i1, i2)),
         (&Value::Integer(i1), &Value::Float(f2)) => Value::Float(arith_f(i1 as f64, f2)),
         (&Value::Float(f1), &Value::Float(f2)) => Value::Float(arith_f(f1, f2)),
         (&Value::Float(f1), &Value::Integer(i2)) => Value::Float(arith_f(f1, i2 as f64)),
-        (_, _) => todo!("meta"),
-    }
+        (_, _) => {
+            // coerce strings into numbers
+            let (n1, n2) = (v1.to_number()?, v2.to_number()?);
+            return exe_binop(&n1, &n2, arith_i, arith_f);
+        }
+    };
+    Some(r)
 }
-fn exe_binop_int(v1: &Value, i2: u8, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Value {
-    match v1 {
-        &Value::Integer(i1) => Value::Integer(arith_i(i1, i2 as i64)),
-        &Value::Float(f1) => Value::Float(arith_f(f1, i2 as f64)),
-        _ => todo!("meta"),
-    }
+fn exe_binop_int(v1: &Value, i2: u8, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Option<Value> {
+    let r = match *v1 {
+        Value::Integer(i1) => Value::Integer(arith_i(i1, i2 as i64)),
+        Value::Float(f1) => Value::Float(arith_f(f1, i2 as f64)),
+        _ => return exe_binop_int(&v1.to_number()?, i2, arith_i, arith_f),
+    };
+    Some(r)
 }
 
-fn exe_binop_f(v1: &Value, v2: &Value, arith_f: fn(f64,f64)->f64) -> Value {
+fn exe_binop_f(v1: &Value, v2: &Value, arith_f: fn(f64,f64)->f64) -> Option<Value> {
     let (f1, f2) = match (v1, v2) {
         (&Value::Integer(i1), &Value::Integer(i2)) => (i1 as f64, i2 as f64),
         (&Value::Integer(i1), &Value::Float(f2)) => (i1 as f64, f2),
         (&Value::Float(f1), &Value::Float(f2)) => (f1, f2),
         (&Value::Float(f1), &Value::Integer(i2)) => (f1, i2 as f64),
-        (_, _) => todo!("meta"),
+        (_, _) => {
+            let (n1, n2) = (v1.to_number()?, v2.to_number()?);
+            return exe_binop_f(&n1, &n2, arith_f);
+        }
     };
-    Value::Float(arith_f(f1, f2))
+    Some(Value::Float(arith_f(f1, f2)))
 }
-fn exe_binop_int_f(v1: &Value, i2: u8, arith_f: fn(f64,f64)->f64) -> Value {
-    let f1 = match v1 {
-        &Value::Integer(i1) => i1 as f64,
-        &Value::Float(f1) => f1,
-        _ => todo!("meta"),
+fn exe_binop_int_f(v1: &Value, i2: u8, arith_f: fn(f64,f64)->f64) -> Option<Value> {
+    let f1 = match *v1 {
+        Value::Integer(i1) => i1 as f64,
+        Value::Float(f1) => f1,
+        _ => return exe_binop_int_f(&v1.to_number()?, i2, arith_f),
     };
-    Value::Float(arith_f(f1, i2 as f64))
+    Some(Value::Float(arith_f(f1, i2 as f64)))
 }
 
-fn exe_binop_i(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64) -> Value {
+fn exe_binop_i(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64) -> Option<Value> {
     let (i1, i2) = match (v1, v2) {
         (&Value::Integer(i1), &Value::Integer(i2)) => (i1, i2),
-        (&Value::Integer(i1), &Value::Float(f2)) => (i1, ftoi(f2).unwrap()),
-        (&Value::Float(f1), &Value::Float(f2)) => (ftoi(f1).unwrap(), ftoi(f2).unwrap()),
-        (&Value::Float(f1), &Value::Integer(i2)) => (ftoi(f1).unwrap(), i2),
-        (_, _) => todo!("meta"),
+        (_, _) => {
+            let (n1, n2) = (v1.to_number()?, v2.to_number()?);
+            (to_bit_int(&n1), to_bit_int(&n2))
+        }
     };
-    Value::Integer(arith_i(i1, i2))
+    Some(Value::Integer(arith_i(i1, i2)))
 }
-fn exe_binop_int_i(v1: &Value, i2: u8, arith_i: fn(i64,i64)->i64) -> Value {
-    let i1 = match v1 {
-        &Value::Integer(i1) => i1,
-        &Value::Float(f1) => ftoi(f1).unwrap(),
-        _ => todo!("meta"),
+fn exe_binop_int_i(v1: &Value, i2: u8, arith_i: fn(i64,i64)->i64) -> Option<Value> {
+    let i1 = match *v1 {
+        Value::Integer(i1) => i1,
+        _ => to_bit_int(&v1.to_number()?),
     };
-    Value::Integer(arith_i(i1, i2 as i64))
+    Some(Value::Integer(arith_i(i1, i2 as i64)))
+}
+
+// integer operand of bitwise operators, from a number
+fn to_bit_int(n: &Value) -> i64 {
+    match *n {
+        Value::Integer(i) => i,
+        Value::Float(f) => ftoi(f).unwrap_or_else(|| panic!("number has no integer representation")),
+        _ => panic!("impossible"),
+    }
+}
+
+// compare for `<` and `<=`, while `>` and `>=` are done by swapping
+// the operands, as Lua does, to get the same error message.
+// Return None for NaN that makes all comparisons false.
+pub(crate) fn compare(v1: &Value, v2: &Value) -> Option<Ordering> {
+    match (v1, v2) {
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_)) |
+        (Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_),
+            Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_)) =>
+            v1.partial_cmp(v2),
+        _ => {
+            let (t1, t2) = (v1.type_name(), v2.type_name());
+            if t1 == t2 {
+                panic!("attempt to compare two {t1} values");
+            } else {
+                panic!("attempt to compare {t1} with {t2}");
+            }
+        }
+    }
+}
+
+// the second operand of arithmetic byte codes, see ExeState::arith_meta()
+enum Rhs {
+    Reg(u8),
+    Const(u8),
+    Int(u8),
+}
+
+// @name is the description of the variable, e.g. "global 'x'"
+fn index_error(v: &Value, name: Option<String>) -> ! {
+    match name {
+        Some(name) => panic!("attempt to index a {} value ({name})", v.type_name()),
+        None => panic!("attempt to index a {} value", v.type_name()),
+    }
 }
 
 fn for_check<T: PartialOrd>(i: T, limit: T, is_step_positive: bool) -> bool {
@@ -0,0 +1,5 @@
+print(string.rep("\xff\xfe", 2) == "\xff\xfe\xff\xfe")
+print(#string.rep("\x80", 3, "\0"))
+print(tonumber("\xff", 16))
+print(tonumber("\xff1"))
+print(("\xe4\xb8" .. 1) == "\xe4\xb81")
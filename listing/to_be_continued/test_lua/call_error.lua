@@ -0,0 +1,4 @@
+print("before")
+local x = nil
+x()
+print("after")
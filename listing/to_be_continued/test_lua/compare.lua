@@ -0,0 +1,9 @@
+local i, f, s = 2, 2.5, "b"
+print(i < f, i <= f, i > f, i >= f)
+print(f < 3, f <= 2, f > 2, f >= 3)
+print(3 < f, 2 <= f, 2 > f, 3 >= f)
+print(s < "c", s <= "a", s > "a", s >= "b", "a" < s)
+
+local nan = 0/0
+print(nan < 1, nan <= 1, nan > 1, nan >= 1, nan < nan, 1 < nan, nan > 0.5)
+print(i < 2.0, i <= 2.0, f > i, -i < -f)
@@ -0,0 +1,14 @@
+local short = "abc" .. "defghijklmn"
+local mid = short .. short .. short
+local long = mid .. mid
+print(short, #short)
+print(mid, #mid)
+print(long, #long)
+print(#("12345678901234" .. ""), #("12345678901234" .. "5"))
+print(#(mid .. "12345678901234567890123456789012"))
+print(1 .. 2, "n=" .. 10, -3 .. "")
+print(short == "abcdefghijklmn", mid == short .. short .. short)
+local t = {}
+t[short .. ""] = 1
+t[mid .. ""] = 2
+print(t.abcdefghijklmn, t[mid])
@@ -0,0 +1,40 @@
+local N <const> = 10
+local S <const> = "hello"
+local F <const> = 0.5
+local B <const> = false
+local Z <const> = nil
+print(N, S, F, B, Z)
+print(N + 1, N * F, S .. " world", not B)
+
+-- no register is taken by folded constants
+local a = 1
+print(a, N)
+
+-- shadowing
+do
+    local N = 20
+    print(N)
+    local N <const> = 30
+    print(N)
+end
+print(N)
+
+-- folded in inner functions, without upvalues
+local function f() return N * 2, S end
+print(f())
+
+-- not constant initializer, so a read-only local
+local T <const> = {1, 2}
+print(#T)
+
+-- only the last one is folded
+local x <const>, y <const> = 3, 4
+print(x, y)
+
+for i = 1, 3 do
+    local K <const> = i * N -- not folded
+    print(K)
+end
+
+local t = {[N] = S}
+print(t[10])
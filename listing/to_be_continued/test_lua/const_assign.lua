@@ -0,0 +1,3 @@
+local N <const> = 10
+print(N)
+N = 11
@@ -0,0 +1,21 @@
+local a, b, c = 1, 2, nil
+print(a == 1 or b, a == 2 or b, a == 1 and b, a == 2 and b)
+print(a < b or c, c or a > b, c or a < b, b and a ~= 1)
+print(a == 1 and b == 2, a == 1 and b == 3, a == 2 or b == 2, a == 2 or b == 3)
+print(c or a == 1 and b, (c or a) == 1, c and c == 1 or b)
+print(a == 2 and b or c, a == 1 and "yes" or "no", a == 2 and "yes" or "no")
+
+local x = c or b
+print(x)
+x = c or a
+print(x)
+local t = {}
+t.k = c or 3
+print(t.k)
+local function default(o) o = o or {} return o end
+print(type(default()), default(t) == t)
+
+local y = b > 1 or c
+print(y)
+if a == 1 or c then print("then") end
+while c or a > 5 do end
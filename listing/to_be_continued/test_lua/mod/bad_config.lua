@@ -0,0 +1 @@
+return { name = "server", port = "8080" }
@@ -0,0 +1,2 @@
+local port = 8000
+return { name = "server", port = port + 80 }, "v1"
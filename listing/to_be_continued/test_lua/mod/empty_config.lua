@@ -0,0 +1 @@
+local port = 8080
@@ -0,0 +1,8 @@
+print("loading greet")
+local M = {}
+M.count = 0
+function M.hello(name)
+    M.count = M.count + 1
+    print("hello, " .. name)
+end
+return M
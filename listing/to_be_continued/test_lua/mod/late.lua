@@ -0,0 +1,4 @@
+-- read a global variable assigned after loading
+return function()
+    return late
+end
@@ -0,0 +1 @@
+print("loading noreturn")
@@ -0,0 +1,3 @@
+-- assign a global variable, and read a global library
+polluted = string.rep("x", 3)
+return polluted
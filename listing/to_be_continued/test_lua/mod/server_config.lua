@@ -0,0 +1,8 @@
+-- configuration for tests/exec.rs
+local base = 8000
+name = "server"
+return {
+    name = name,
+    port = base + tonumber("80"),
+    tags = { "a", "b" },
+}
@@ -0,0 +1,4 @@
+return {
+    name = "x"
+    port = 80,
+}
@@ -0,0 +1,29 @@
+local a, b, c, d
+print(a, b, c, d)
+
+local x, y, z = 1, 2, 3
+local p, q, r = x, y, z
+print(p, q, r)
+
+p, q, r = r, q, p
+print(p, q, r)
+
+local n1, n2, n3 = nil, nil
+print(n1, n2, n3)
+
+local m1, m2, m3, m4 = x, nil
+print(m1, m2, m3, m4)
+
+-- assigning nil keeps the registers above
+x = nil
+print(x, y, z)
+
+local function f(...) return table.pack(...).n, ... end
+print(f(p, q, r))
+print(f(nil, nil, nil))
+
+-- jumps land in the middle of explist
+local t1, t2, t3 = y and z, z, x or y
+print(t1, t2, t3)
+local u1, u2 = y > 1, z
+print(u1, u2)
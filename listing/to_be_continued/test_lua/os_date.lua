@@ -0,0 +1,24 @@
+print(os.date("!%Y-%m-%d %H:%M:%S", 0))
+print(os.date("!%A %B %j", 1600000000))
+
+local u = os.date("!*t", 86400 * 365)
+print(u.year, u.month, u.day, u.hour, u.min, u.sec, u.wday, u.yday, u.isdst)
+
+-- normalized: 2020-02-30 is March 1
+local t = os.time{year=2020, month=2, day=30, hour=10}
+local d = os.date("*t", t)
+print(d.year, d.month, d.day, d.hour, d.min, d.sec, d.wday, d.yday)
+print(os.time(d) == t)
+print(os.date("%Y-%m-%d %H:%M", t))
+
+-- os.time() updates the fields
+local tt = {year=2021, month=14, day=1, min=-30}
+os.time(tt)
+print(tt.year, tt.month, tt.day, tt.hour, tt.min, tt.sec, tt.yday)
+
+-- default hour is 12
+print(os.date("%H", os.time{year=2000, month=1, day=1}))
+print(os.time{year=2000, month=1, day=1} == os.time{year=2000, month=1, day=1.0, hour="12"})
+
+print(type(os.time()), type(os.date()))
+print(os.date("", 0) == "")
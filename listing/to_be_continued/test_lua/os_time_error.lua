@@ -0,0 +1 @@
+print(os.time{year=2020, month=1})
@@ -0,0 +1,12 @@
+local greet = require "test_lua.mod.greet"
+greet.hello("world")
+local again = require("test_lua.mod.greet")
+again.hello("lua")
+print(greet == again, greet.count)
+print(package.loaded["test_lua.mod.greet"] == greet)
+
+print(require "test_lua.mod.noreturn", require "test_lua.mod.noreturn")
+print(require "string" == string, package.loaded.table == table)
+
+package.loaded.fake = "fake module"
+print(require "fake")
@@ -0,0 +1,8 @@
+print(string.rep("ab", 3))
+print(string.rep("ab", 3, ", "))
+print(string.rep("ab", 1, ", "))
+print(string.rep("ab", 0, ", "))
+print(string.rep("ab", -1))
+print(string.rep("", 5, "-"))
+print(string.rep(12, 2, 3))
+print(#string.rep("x", 100, "yy"))
@@ -0,0 +1,2 @@
+-- raise error instead of allocating unbounded memory
+print(string.rep("x", 1 << 62, ","))
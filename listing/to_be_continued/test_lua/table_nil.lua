@@ -0,0 +1,44 @@
+-- trailing nils are not counted by `#`
+local t = {1, 2, 3}
+print(#t)
+t[3] = nil
+print(#t)
+t[2] = nil
+t[1] = nil
+print(#t)
+
+-- holes in the array part
+t = {1, nil, 3}
+print(#t, t[2])
+t = {1, 2, nil, nil}
+print(#t)
+
+-- appending through the hash part
+t = {}
+t[2] = 'b'
+t[1] = 'a'
+print(#t, t[1], t[2])
+
+-- float keys with integral values are normalized
+t[3.0] = 'c'
+print(#t, t[3])
+
+-- ipairs stops at the first nil
+t = {1, 2, nil, 4}
+for i, v in ipairs(t) do
+    print(i, v)
+end
+
+-- table.pack counts trailing nils in `n`
+local p = table.pack(1, nil, 3, nil)
+print(p.n, p[1], p[2], p[3], p[4])
+p = table.pack()
+print(p.n, #p)
+
+-- integer keys of the hash part inside a grown array part
+t = {}
+t[1] = 1
+t[10] = 10
+for i = 2, 5 do t[i] = i end
+t[11] = 11
+print(t[10], t[11])
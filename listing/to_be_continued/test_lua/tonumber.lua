@@ -0,0 +1,17 @@
+-- lexer
+print(0x10, 0xff, 0XA, 0x7fffffffffffffff, 0xffffffffffffffff)
+print(1e2, 1E-2, .5, 3., 0x.8, 0x1p4, 0x1P-2, 0xA.8p1)
+print(9223372036854775807, 9223372036854775808 == 2^63)
+print(1-2, 3e1-1)
+
+-- tonumber
+print(tonumber("10"), tonumber("  10  "), tonumber("\t-7\n"), tonumber("+3"))
+print(tonumber("1e1"), tonumber("0x10"), tonumber("-0x10"), tonumber(".5"), tonumber("5."))
+print(tonumber(""), tonumber(" "), tonumber("1e"), tonumber("0x"), tonumber("1 2"), tonumber("inf"), tonumber("nan"))
+print(tonumber("9223372036854775807"), tonumber("-9223372036854775808"))
+print(tonumber(12), tonumber(1.5))
+print(tonumber("ff", 16), tonumber("zz", 36), tonumber("777", 8), tonumber(" -101 ", 2), tonumber("8", 8))
+
+-- coercion in arithmetic
+print("10" + 1, "3" * "4", "0x10" + 0, "1.5" + 1, 10 - "2", -"2", "9" // 2, "2" ^ 2)
+
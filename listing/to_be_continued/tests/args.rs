@@ -0,0 +1,67 @@
+use lua_rs::parse;
+use lua_rs::repl::pretty;
+use lua_rs::vm::ExeState;
+
+// run @source with the command line @args, and return the pretty-printed
+// return values
+fn run(source: &str, args: &[&str]) -> String {
+    let mut state = ExeState::new();
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    state.set_arg("script.lua", &args);
+    let proto = parse::load(source.as_bytes());
+    let rets = state.exec_main(&proto);
+    rets.iter().map(pretty).collect::<Vec<_>>().join(" ")
+}
+
+const GETOPT: &str = r#"
+    local args = require "args"
+    return args.getopt(arg, "hvo:n:", {"help", "output=", "dry-run"})
+"#;
+
+#[test]
+fn arg_table() {
+    assert_eq!(run("return arg[0], #arg, arg[1], arg[2]", &["a", "b"]), r#""script.lua" 2 "a" "b""#);
+}
+
+#[test]
+fn short_options() {
+    assert_eq!(run(GETOPT, &["-v", "-o", "out.txt", "in.txt"]),
+        r#"{ o = "out.txt", v = true } { "in.txt" }"#);
+
+    // grouped, and the argument in the same word
+    assert_eq!(run(GETOPT, &["-hvoout.txt", "-n3"]), r#"{ h = true, n = "3", o = "out.txt", v = true } {}"#);
+}
+
+#[test]
+fn long_options() {
+    assert_eq!(run(GETOPT, &["--help", "--output=a", "--dry-run", "x", "-v"]),
+        r#"{ ["dry-run"] = true, help = true, output = "a" } { "x", "-v" }"#);
+    assert_eq!(run(GETOPT, &["--output", "a", "--", "-v"]), r#"{ output = "a" } { "-v" }"#);
+
+    // `-` is not an option
+    assert_eq!(run(GETOPT, &["-", "-v"]), r#"{} { "-", "-v" }"#);
+}
+
+#[test]
+#[should_panic(expected = "unknown option '-x'")]
+fn unknown_short_option() {
+    run(GETOPT, &["-vx"]);
+}
+
+#[test]
+#[should_panic(expected = "unknown option '--verbose'")]
+fn unknown_long_option() {
+    run(GETOPT, &["--verbose"]);
+}
+
+#[test]
+#[should_panic(expected = "option '-o' requires an argument")]
+fn missing_argument() {
+    run(GETOPT, &["-v", "-o"]);
+}
+
+#[test]
+#[should_panic(expected = "option '--help' takes no argument")]
+fn unexpected_argument() {
+    run(GETOPT, &["--help=yes"]);
+}
@@ -0,0 +1,144 @@
+use std::rc::Rc;
+use lua_rs::builder::ProtoBuilder;
+use lua_rs::bytecode::ByteCode::*;
+use lua_rs::parse::{FuncProto, UpIndex};
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn call(proto: FuncProto, args: &[Value]) -> Vec<Value> {
+    ExeState::new().pcall(Value::LuaFunction(Rc::new(proto)), args).unwrap()
+}
+
+// the main function, with globals by `_ENV`
+#[test]
+fn main_function() {
+    let mut b = ProtoBuilder::new().param("_ENV");
+    let x = b.constant("x");
+    let ten = b.constant(10);
+    b.emit(GetField(1, 0, x as u8));
+    b.emit(AddConst(1, 1, ten as u8));
+    b.emit(Return(1, 1));
+    let proto = b.finish().unwrap();
+    assert_eq!(proto.max_stack_size, 2);
+
+    let mut state = ExeState::new();
+    state.globals().set("x", 5);
+    assert_eq!(state.exec_main(&proto), [Value::Integer(15)]);
+
+    // constants are deduplicated, but integers and floats are different
+    let mut b = ProtoBuilder::new();
+    assert_eq!(b.constant("k"), b.constant("k"));
+    assert_ne!(b.constant(1), b.constant(1.0));
+}
+
+// sum(n): the sum of 1..n by numerical for-loop
+#[test]
+fn for_loop() {
+    let mut b = ProtoBuilder::new().param("n");
+    let sum = b.local("sum");
+    b.emit(LoadInt(sum, 0));
+    b.emit(LoadInt(2, 1));
+    b.emit(Move(3, 0));
+    b.emit(LoadInt(4, 1));
+    let prepare = b.emit(ForPrepare(2, 0)); // patched below
+    let body = b.emit(Add(sum, sum, 2));
+    let end = b.emit(ForLoop(2, 0));
+    b.patch(end, ForLoop(2, (end + 1 - body) as u16));
+    b.patch(prepare, ForPrepare(2, (end - prepare) as u16));
+    b.emit(Return(sum, 1));
+    let proto = b.finish().unwrap();
+
+    assert_eq!(call(proto, &[Value::Integer(100)]), [Value::Integer(5050)]);
+}
+
+// counter(): return a closure which counts by the captured local
+#[test]
+fn closure() {
+    let mut inner = ProtoBuilder::new();
+    let up = inner.upvalue("count", UpIndex::Local(0));
+    inner.emit(GetUpvalue(0, up));
+    inner.emit(AddInt(0, 0, 1));
+    inner.emit(SetUpvalue(up, 0));
+    inner.emit(Return(0, 1));
+
+    let mut b = ProtoBuilder::new();
+    let count = b.local("count");
+    b.emit(LoadInt(count, 0));
+    let k = b.function(inner.finish().unwrap());
+    b.closure(1, k);
+    b.emit(Return(1, 1));
+    let proto = b.finish().unwrap();
+
+    let mut state = ExeState::new();
+    let f = Value::LuaFunction(Rc::new(proto));
+    let counter = state.pcall(f, &[]).unwrap().remove(0);
+    for i in 1..=3 {
+        assert_eq!(state.pcall(counter.clone(), &[]).unwrap(), [Value::Integer(i)]);
+    }
+}
+
+// debug information in error messages
+#[test]
+fn debug_info() {
+    let mut b = ProtoBuilder::new().chunk_name("=gen");
+    let f = b.local("f");
+    b.line(7);
+    b.emit(LoadNil(f, 1));
+    b.emit(Call(f, 1, 0));
+    b.emit(Return0);
+    let proto = b.finish().unwrap();
+
+    let err = ExeState::new().pcall(Value::LuaFunction(Rc::new(proto)), &[]).unwrap_err();
+    assert!(err.to_string().contains("attempt to call a nil value (local 'f')"), "{err}");
+}
+
+// many constants by LoadConstX
+#[test]
+fn big_constants() {
+    let mut b = ProtoBuilder::new();
+    for i in 0..70000 {
+        b.constant(i);
+    }
+    let k = b.constant("last");
+    assert_eq!(k, 70000);
+    b.load_const(0, k);
+    b.emit(Return(0, 1));
+    assert_eq!(call(b.finish().unwrap(), &[]), ["last".into()]);
+}
+
+#[test]
+fn errors() {
+    let check = |build: &dyn Fn(&mut ProtoBuilder), expect: &str| {
+        let mut b = ProtoBuilder::new();
+        build(&mut b);
+        let err = b.finish().unwrap_err();
+        assert_eq!(err.to_string(), expect);
+    };
+
+    check(&|_| (), "byte code 0: missing return at the end");
+    check(&|b| { b.emit(LoadInt(0, 1)); }, "byte code 1: missing return at the end");
+    check(&|b| { b.emit(LoadConst(0, 3)); b.emit(Return0); },
+        "byte code 0: constant 3 out of range");
+    check(&|b| { b.emit(GetUpField(0, 0, 0)); b.emit(Return0); },
+        "byte code 0: upvalue 0 out of range");
+    check(&|b| { b.emit(Jump(5)); b.emit(Return0); },
+        "byte code 0: jump to 6 out of range");
+    check(&|b| { b.emit(Jump(-2)); b.emit(Return0); },
+        "byte code 0: jump to -1 out of range");
+    check(&|b| { b.emit(Return0); b.emit(LessInt(0, 1, true)); b.emit(Return0); },
+        "byte code 1: jump to 3 out of range");
+    check(&|b| { b.emit(LoadConstX(0)); b.emit(Return0); },
+        "byte code 0: ExtraArg expected");
+    check(&|b| { b.emit(ExtraArg(0, 0)); b.emit(Return0); },
+        "byte code 0: unexpected ExtraArg");
+    check(&|b| { let k = b.constant(1); b.closure(0, k); b.emit(Return0); },
+        "byte code 0: constant 0 is not a function");
+    check(&|b| {
+        let mut inner = ProtoBuilder::new();
+        inner.upvalue("x", UpIndex::Upvalue(2));
+        inner.emit(Return0);
+        let k = b.function(inner.finish().unwrap());
+        b.closure(0, k);
+        b.emit(Return0);
+    }, "byte code 0: captured upvalue 2 out of range");
+}
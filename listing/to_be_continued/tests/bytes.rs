@@ -0,0 +1,61 @@
+mod common;
+
+use lua_rs::parse;
+use lua_rs::stdlib::bytes::Bytes;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::{eval, error};
+
+#[test]
+fn edit() {
+    assert_eq!(eval(r#"
+        local b = bytes.new(4)
+        b:set(1, 0x89, "PN"):set(-1, "G")
+        b:append("\r\n", bytes.new("!"))
+        return b:len(), b:tostring(), b:get(1), b:get(-3, -1)
+    "#), [Value::Integer(7), b"\x89PNG\r\n!".as_slice().into(), Value::Integer(0x89),
+        Value::Integer(13), Value::Integer(10), Value::Integer(33)]);
+
+    assert_eq!(eval(r#"
+        local b = bytes.new(6, 120)
+        b:fill(0, 2, 3)
+        local s = b:slice(2, -3)
+        s:set(1, 65)  -- a copy
+        b:append(b)
+        return s:tostring(), b:len(), b:tostring(7, 8), type(b), b:get(1, 4)
+    "#), [b"A\0x".as_slice().into(), Value::Integer(12), b"x\0".as_slice().into(), "userdata".into(),
+        Value::Integer(120), Value::Integer(0), Value::Integer(0), Value::Integer(120)]);
+
+    // empty ranges
+    assert_eq!(eval("local b = bytes.new('abc') return b:tostring(3, 2), b:slice(10):len(), b:get(4)"),
+        ["".into(), Value::Integer(0)]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("bytes.new(2):set(2, 'ab')"), "[string \"?\"]:1: bad argument #2 to 'set' (index out of range)");
+    assert_eq!(error("bytes.new(2):set(-3, 1)"), "[string \"?\"]:1: bad argument #2 to 'set' (index out of range)");
+    assert_eq!(error("bytes.new(2):set(1, 256)"), "[string \"?\"]:1: bad argument #3 to 'set' (value out of range)");
+    assert_eq!(error("bytes.new(2):append({})"),
+        "[string \"?\"]:1: bad argument #2 to 'append' (string or bytes expected, got table)");
+    assert_eq!(error("bytes.new(2).len('x')"), "[string \"?\"]:1: bad argument #1 to 'len' (bytes expected, got string)");
+    assert_eq!(error("bytes.new(-1)"), "[string \"?\"]:1: bad argument #1 to 'new' (number has no usize representation)");
+}
+
+// the host reads and writes the buffer in place
+#[test]
+fn host() {
+    fn checksum(state: &mut ExeState) -> i32 {
+        let sum = {
+            let mut b = state.check_userdata::<Bytes>(1, "checksum");
+            b.0.push(0);
+            b.0.iter().map(|&x| x as i64).sum::<i64>()
+        };
+        state.push(sum);
+        1
+    }
+    let mut state = ExeState::new();
+    state.globals().set("checksum", Value::RustFunction(checksum));
+    let rets = state.exec_main(&parse::load(b"local b = bytes.new('ab') return checksum(b), b:len()".as_slice()));
+    assert_eq!(rets, [Value::Integer(97 + 98), Value::Integer(3)]);
+}
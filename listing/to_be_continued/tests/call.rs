@@ -0,0 +1,241 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+// apply(f, ...): call @f with the following arguments from Rust, and
+// return its return values
+fn apply(state: &mut ExeState) -> i32 {
+    let f = state.get::<&Value>(1).clone();
+    let args: Vec<Value> = (2..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect();
+    let rets = state.call(f, &args);
+    let n = rets.len();
+    for v in rets {
+        state.push(v);
+    }
+    n as i32
+}
+
+// Lua -> Rust -> Lua -> Rust -> Lua
+#[test]
+fn three_levels() {
+    let mut state = ExeState::new();
+    state.globals().set("apply", Value::RustFunction(apply));
+    let rets = exec(&mut state, r#"
+        local function inner(a, b)
+            return a * b, "inner"
+        end
+        local function middle(x)
+            local p, s = apply(inner, x, 10)
+            return p + 1, s .. "+middle"
+        end
+        local before = 7
+        local n, s = apply(middle, 3)
+        return before, n, s, apply(middle, 4)
+    "#);
+    assert_eq!(rets, [Value::Integer(7), Value::Integer(31), "inner+middle".into(),
+        Value::Integer(41), "inner+middle".into()]);
+
+    // the frames are restored, so the state is usable after
+    assert_eq!(exec(&mut state, "return apply(apply, apply, type, 1)"), ["number".into()]);
+}
+
+// deep recursion through Rust and Lua alternately
+#[test]
+fn recursion() {
+    let mut state = ExeState::new();
+    state.globals().set("apply", Value::RustFunction(apply));
+    let rets = exec(&mut state, r#"
+        local function sum(n)
+            if n == 0 then return 0 end
+            return n + apply(sum, n - 1)
+        end
+        return sum(50)
+    "#);
+    assert_eq!(rets, [Value::Integer(1275)]);
+}
+
+// the comparator of table.sort calls table.sort
+#[test]
+fn nested_sort() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local g1, g2, g3 = {3, 1, 2}, {9, 7}, {5, 4, 6, 0}
+        local groups = { g1, g2, g3 }
+        table.sort(groups, function (a, b)
+            table.sort(a, function (x, y) return x > y end)
+            table.sort(b, function (x, y) return x > y end)
+            return a[1] < b[1]
+        end)
+        local out = ""
+        for _, g in ipairs(groups) do
+            for _, v in ipairs(g) do
+                out = out .. v
+            end
+            out = out .. " "
+        end
+        return out
+    "#);
+    assert_eq!(rets, ["321 6540 97 ".into()]);
+}
+
+// errors in the innermost level unwind through all levels, and are
+// caught by the outermost protected call
+#[test]
+fn error_through_levels() {
+    let mut state = ExeState::new();
+    state.globals().set("apply", Value::RustFunction(apply));
+    exec(&mut state, r#"
+        function fail(x) error_here(x) end
+        function middle(x) return apply(fail, x) end
+        function outer(x) return apply(middle, x) end
+    "#);
+    let outer = state.globals().get("outer");
+    let top = state.get_top();
+    let err = state.pcall(outer.clone(), &[Value::Integer(1)]).unwrap_err();
+    assert!(err.to_string().contains("attempt to call a nil value"), "{err}");
+
+    // the stack is restored
+    assert_eq!(state.get_top(), top);
+    state.globals().set("error_here", Value::RustFunction(|_| 0));
+    assert_eq!(state.pcall(outer, &[Value::Integer(1)]).unwrap(), []);
+}
+
+// call from Rust functions with more stack values than registers
+#[test]
+fn many_stack_values() {
+    let mut state = ExeState::new();
+    state.globals().set("spread", Value::RustFunction(|state| {
+        let f = state.get::<&Value>(1).clone();
+        for i in 0..300 {
+            state.push(Value::Integer(i));
+        }
+        let args: Vec<Value> = (0..300).map(Value::Integer).collect();
+        let rets = state.call(f, &args);
+        state.push(rets[0].clone());
+        1
+    }));
+    let rets = exec(&mut state, "return spread(function (...) local t = {...} return #t end)");
+    assert_eq!(rets, [Value::Integer(300)]);
+}
+
+// Rust closures capture the host's data, and each has its own state
+#[test]
+fn closures() {
+    let mut state = ExeState::new();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let log = events.clone();
+    let emit = state.create_function(move |state| {
+        log.borrow_mut().push(state.get::<&Value>(1).to_string());
+        0
+    });
+    state.globals().set("emit", emit);
+    for (name, step) in [("by1", 1), ("by10", 10)] {
+        let mut n = 0;
+        let counter = state.create_function(move |state| {
+            n += step;
+            state.push(n);
+            1
+        });
+        state.globals().set(name, counter);
+    }
+
+    let rets = exec(&mut state, r#"
+        emit("start")
+        by1() by10()
+        emit("end")
+        return by1(), by10()
+    "#);
+    assert_eq!(rets, [Value::Integer(2), Value::Integer(20)]);
+    assert_eq!(*events.borrow(), ["start", "end"]);
+}
+
+// a Rust closure can not be called while it is running
+#[test]
+fn running_closure() {
+    let mut state = ExeState::new();
+    let again = |state: &mut ExeState| {
+        let f = state.get::<&Value>(1).clone();
+        state.call(f.clone(), &[f]);
+        0
+    };
+    state.globals().set("again", Value::RustClosure(Rc::new(RefCell::new(Box::new(again)))));
+    exec(&mut state, "function f(g) again(g) end");
+
+    let f = state.globals().get("f");
+    let err = state.pcall(f.clone(), &[f]).unwrap_err();
+    assert!(err.to_string().contains("attempt to call a running Rust closure"), "{err}");
+}
+
+// yielding is not allowed while Rust functions are calling Lua
+#[test]
+fn yield_boundary() {
+    let mut state = ExeState::new();
+    state.globals().set("apply", Value::RustFunction(apply));
+    state.globals().set("check", Value::RustFunction(|state| {
+        state.check_yield();
+        0
+    }));
+
+    let run = |state: &mut ExeState, source: &str| {
+        let f = Value::LuaFunction(parse::load(source.as_bytes()).into());
+        let env = state.globals().into(); // the main function's `_ENV`
+        state.pcall(f, &[env])
+    };
+
+    // called by Lua directly, or by the host
+    assert!(run(&mut state, "check()").is_ok());
+    assert!(state.pcall(state.globals().get("check"), &[]).is_ok());
+
+    for source in [
+        "apply(check)",
+        "apply(function () check() end)",
+        "table.sort({2, 1}, function (a, b) check() return a < b end)",
+    ] {
+        let err = run(&mut state, source).unwrap_err();
+        assert!(err.to_string().contains("attempt to yield across a C-call boundary"), "{err}");
+    }
+
+    // restored after the errors
+    assert!(run(&mut state, "check()").is_ok());
+}
+
+// `...` and select()
+#[test]
+fn varargs() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local function count(...) return select('#', ...) end
+        local function pack(...) return { n = count(...), ... } end
+        local function pass(...) return ... end
+        local t = pack(1, nil, 3, nil)
+        local a, b = pass(10, 20, 30)
+        return count(), count(nil, nil), t.n, t[3], a, b, select(2, pass(1, 2, 3))
+    "#);
+    assert_eq!(rets, [0, 2, 4, 3, 10, 20, 2, 3].map(Value::Integer));
+
+    let rets = exec(&mut state, r#"
+        return select(-1, "a", "b", "c"), select(-3, "a", "b", "c")
+    "#);
+    assert_eq!(rets, ["c".into(), "a".into(), "b".into(), "c".into()]);
+    assert_eq!(exec(&mut state, "return select(5, 1, 2)"), []);
+    assert_eq!(exec(&mut state, "return select(2.0, 1, 2)"), [Value::Integer(2)]);
+    assert_eq!(exec(&mut state, "return select('2', 1, 2)"), [Value::Integer(2)]);
+
+    for (source, msg) in [
+        ("select(0, 1)", "bad argument #1 to 'select' (index out of range)"),
+        ("select(-2, 1)", "bad argument #1 to 'select' (index out of range)"),
+        ("select(nil)", "bad argument #1 to 'select' (number expected, got nil)"),
+        ("select()", "bad argument #1 to 'select' (number expected, got no value)"),
+        ("select(math.mininteger, 1)", "bad argument #1 to 'select' (index out of range)"),
+    ] {
+        let f = Value::LuaFunction(parse::load(source.as_bytes()).into());
+        let err = state.pcall(f, &[state.globals().into()]).unwrap_err();
+        assert!(err.to_string().contains(msg), "{err}");
+    }
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use std::thread;
+use lua_rs::parse;
+use lua_rs::send::SendValue;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::{eval, error};
+
+#[test]
+fn send_recv() {
+    assert_eq!(eval("
+        local ch = channel.new()
+        ch:send(1) ch:send('two') ch:send({3})
+        local n = ch:len()
+        return n, ch:recv(), ch:recv(), ch:recv()[1], ch:try_recv()
+    "), [Value::Integer(3), Value::Integer(1), "two".into(), Value::Integer(3), Value::Nil]);
+
+    // bounded
+    assert_eq!(eval("
+        local ch = channel.new(2)
+        return ch:try_send(1), ch:try_send(2), ch:try_send(3), ch:recv(), ch:try_send(3), ch:len()
+    "), [Value::Boolean(true), Value::Boolean(true), Value::Boolean(false), Value::Integer(1),
+        Value::Boolean(true), Value::Integer(2)]);
+
+    // the queued messages are received after closing
+    assert_eq!(eval("
+        local ch = channel.new()
+        ch:send(1) ch:send(2) ch:close()
+        local sum = 0
+        for msg in ch.recv, ch do sum = sum + msg end
+        return sum, ch:recv(), ch:try_recv()
+    "), [Value::Integer(3), Value::Nil, Value::Nil]);
+}
+
+// tables are deep copied, with the shared references and cycles, while
+// channels are shared
+#[test]
+fn deep_copy() {
+    assert_eq!(eval("
+        local ch = channel.new()
+        local shared = {x = 1}
+        local t = {a = shared, b = shared, list = {1, 2, 3}, [shared] = 'key', ch = ch}
+        t.self = t
+        ch:send(t)
+        local c = ch:recv()
+        return c ~= t, c.a == c.b, c.a ~= shared, c.self == c, c.list[3], c[c.a], c.ch == ch
+    "), vec![Value::Boolean(true), Value::Boolean(true), Value::Boolean(true), Value::Boolean(true),
+        Value::Integer(3), "key".into(), Value::Boolean(true)]);
+
+    // to and from Rust
+    let mut state = ExeState::new();
+    let v = state.exec_main(&parse::load(&b"local t = {1, 'x', k = {true}} t.t = t return t"[..])).remove(0);
+    let msg = SendValue::new(&v).unwrap();
+    let rets = thread::spawn(move || {
+        let mut state = ExeState::new();
+        state.globals().set("t", msg.into_value());
+        state.exec_main(&parse::load(&b"return t[1], t[2], t.k[1], t.t.t == t"[..]))
+            .into_iter().map(|v| v.to_string()).collect::<Vec<_>>()
+    }).join().unwrap();
+    assert_eq!(rets, ["1", "x", "true", "true"]);
+
+    let f = state.exec_main(&parse::load(&b"return {print}"[..])).remove(0);
+    assert_eq!(SendValue::new(&f).err(), Some("function"));
+}
+
+#[test]
+fn workers() {
+    assert_eq!(eval("
+        local jobs, results = channel.new(), channel.new(1)
+        for id = 1, 4 do
+            channel.spawn([[
+                local id, jobs, results = ...
+                for job in jobs.recv, jobs do
+                    results:send({id = id, n = job.n, square = job.n * job.n})
+                end
+                results:send({id = id, done = true})
+            ]], id, jobs, results)
+        end
+        for n = 1, 100 do jobs:send({n = n}) end
+        jobs:close()
+
+        local sum, done, seen = 0, 0, {}
+        while done < 4 do
+            local r = results:recv()
+            if r.done then
+                done = done + 1
+            else
+                sum = sum + r.square
+                seen[r.n] = true
+            end
+        end
+        return sum, #seen
+    "), [Value::Integer(338350), Value::Integer(100)]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("channel.new(0)"), "[string \"?\"]:1: bad argument #1 to 'new' (capacity must be positive)");
+    assert_eq!(error("channel.new('x')"), "[string \"?\"]:1: bad argument #1 to 'new' (number expected, got string)");
+    assert_eq!(error("local ch = channel.new() ch:send(nil)"),
+        "[string \"?\"]:1: bad argument #2 to 'send' (message expected, got nil)");
+    assert_eq!(error("local ch = channel.new() ch:send({f = print})"),
+        "[string \"?\"]:1: bad argument #2 to 'send' (can not send function values)");
+    assert_eq!(error("local ch = channel.new() ch.send(1, 2)"),
+        "[string \"?\"]:1: bad argument #1 to 'send' (channel expected, got number)");
+    assert_eq!(error("local ch = channel.new() ch.recv({})"),
+        "[string \"?\"]:1: bad argument #1 to 'recv' (channel expected, got table)");
+    assert_eq!(error("local ch = channel.new() ch:close() ch:send(1)"), "[string \"?\"]:1: send on a closed channel");
+    assert_eq!(error("channel.spawn('', coroutine.create(print))"),
+        "[string \"?\"]:1: bad argument #2 to 'spawn' (can not send thread values)");
+
+    // the channel is still usable after the failed sending
+    assert_eq!(eval("
+        local ch = channel.new()
+        ch:send(1) ch:send(2) ch:close()
+        local ok1, msg = pcall(ch.send, ch, 3)
+        local ok2 = pcall(ch.try_send, ch, 3)
+        return ok1, msg, ok2, ch:len(), ch:recv(), ch:try_recv(), ch:recv()
+    "), [Value::Boolean(false), "send on a closed channel".into(), Value::Boolean(false), Value::Integer(2),
+        Value::Integer(1), Value::Integer(2), Value::Nil]);
+}
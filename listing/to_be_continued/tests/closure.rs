@@ -0,0 +1,110 @@
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(source: &str) -> Vec<Value> {
+    ExeState::new().exec_main(&parse::load(source.as_bytes()))
+}
+
+fn ints(v: &[i64]) -> Vec<Value> {
+    v.iter().map(|&i| Value::Integer(i)).collect()
+}
+
+#[test]
+fn capture() {
+    let rets = exec(r#"
+        -- shared by closures created together
+        local function counter()
+            local n = 0
+            return function () n = n + 1 return n end, function () return n end
+        end
+        local inc, get = counter()
+        inc()
+        inc()
+        local inc2 = counter()
+
+        -- through levels of functions
+        local function outer()
+            local x = 10
+            return function ()
+                return function () x = x + 1 return x end
+            end
+        end
+        local f = outer()()
+        f()
+
+        -- closed when the block ends, while the register is reused
+        local g
+        do
+            local y = 100
+            g = function () return y end
+        end
+        local z = 200
+        return get(), inc2(), f(), g(), z
+    "#);
+    assert_eq!(rets, ints(&[2, 1, 12, 100, 200]));
+}
+
+// each iteration has its own locals
+#[test]
+fn loops() {
+    let rets = exec(r#"
+        local fs = {}
+        for i = 1, 2 do
+            fs[#fs + 1] = function () return i end
+        end
+        for _, v in ipairs({ 3, 4 }) do
+            fs[#fs + 1] = function () return v end
+        end
+        local n = 4
+        while n < 6 do
+            n = n + 1
+            local m = n
+            fs[#fs + 1] = function () return m end
+        end
+        repeat
+            n = n + 1
+            local m = n
+            fs[#fs + 1] = function () return m end
+        until n == 8
+        return fs[1](), fs[2](), fs[3](), fs[4](), fs[5](), fs[6](), fs[7](), fs[8]()
+    "#);
+    assert_eq!(rets, ints(&[1, 2, 3, 4, 5, 6, 7, 8]));
+}
+
+// `break`, `continue` and `goto` skip the end of the blocks, where the
+// captured locals are closed
+#[test]
+fn jumps() {
+    let rets = exec(r#"
+        local fs = {}
+        local n = 0
+        while true do
+            n = n + 1
+            local m = n
+            fs[#fs + 1] = function () return m end
+            if n == 2 then break end
+        end
+        for i = 3, 5 do
+            fs[#fs + 1] = function () return i end
+            if i < 5 then continue end
+        end
+        repeat
+            local m = #fs + 1
+            fs[m] = function () return m end
+            if m == 6 then continue end
+        until m == 7
+
+        ::again::
+        do
+            local m = #fs + 1
+            fs[m] = function () return m end
+            if m < 9 then goto again end
+            goto out
+        end
+        ::out::
+        local reuse = 0
+        return fs[1](), fs[2](), fs[3](), fs[4](), fs[5](), fs[6](), fs[7](), fs[8](), fs[9]()
+    "#);
+    assert_eq!(rets, ints(&[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+}
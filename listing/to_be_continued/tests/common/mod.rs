@@ -0,0 +1,124 @@
+// Helpers shared by the integration tests: eval() and error() to run
+// sources, script() to run the scripts of test_lua/, and Chunk to assemble small FuncProtos directly from constants
+// and byte codes, so the semantics of byte codes can be tested without
+// going through the parser.
+
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use lua_rs::bytecode::ByteCode;
+use lua_rs::parse::{self, FuncProto};
+use lua_rs::value::Value;
+use lua_rs::vm::{self, ExeState};
+
+// run the source as the main chunk of a new state, and return its
+// return values
+pub fn eval(source: impl AsRef<[u8]>) -> Vec<Value> {
+    ExeState::new().exec_main(&parse::load(source.as_ref()))
+}
+
+// the message of the error raised by eval()
+pub fn error(source: impl AsRef<[u8]>) -> String {
+    let err = panic::catch_unwind(AssertUnwindSafe(|| eval(source))).unwrap_err();
+    vm::panic_message(&*err)
+}
+
+// output of print() shared with the test
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Run the script test_lua/@name in a new state, and return its output
+// by print(), or the error message.
+pub fn script(name: &str) -> Result<String, String> {
+    let sink = Sink::default();
+    let mut state = ExeState::builder().output(sink.clone()).build();
+    match panic::catch_unwind(AssertUnwindSafe(|| state.exec_file(format!("test_lua/{name}")))) {
+        Ok(Ok(_)) => Ok(String::from_utf8_lossy(&sink.0.borrow()).into_owned()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(vm::panic_message(&*e)),
+    }
+}
+
+pub struct Chunk {
+    proto: FuncProto,
+}
+
+impl Chunk {
+    pub fn new(byte_codes: Vec<ByteCode>) -> Self {
+        Chunk {
+            proto: FuncProto {
+                byte_codes,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn constants(mut self, constants: Vec<Value>) -> Self {
+        self.proto.constants = constants;
+        self
+    }
+
+    // Run the chunk and return the snapshot of its stack (from register 0)
+    // at the final Return0, which is appended if missing.
+    pub fn run(mut self) -> Vec<Value> {
+        if !matches!(self.proto.byte_codes.last(), Some(ByteCode::Return0)) {
+            self.proto.byte_codes.push(ByteCode::Return0);
+        }
+
+        let mut state = ExeState::new();
+        state.execute(&self.proto, &[]);
+        (1..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect()
+    }
+}
+
+// Compare values strictly, so Integer(1) does not match Float(1.0).
+// `None` matches any value.
+pub fn check_stack(stack: &[Value], expect: &[Option<Value>]) {
+    assert_eq!(stack.len(), expect.len(), "stack size: {stack:?}");
+    for (i, (v, e)) in stack.iter().zip(expect).enumerate() {
+        if let Some(e) = e {
+            assert!(v.same(e), "register {i}: expect {e:?}, got {v:?}, stack: {stack:?}");
+        }
+    }
+}
+
+// Expected stack snapshot, e.g. `stack![(), 1, 2.0, "str", _]`,
+// where `()` is nil and `_` matches any value.
+#[macro_export]
+macro_rules! stack {
+    (@ [$($out:expr),*]) => { [$($out),*] };
+    (@ [$($out:expr),*] _ $(, $($rest:tt)*)?) => {
+        stack!(@ [$($out,)* None] $($($rest)*)?)
+    };
+    (@ [$($out:expr),*] $v:expr $(, $($rest:tt)*)?) => {
+        stack!(@ [$($out,)* Some(lua_rs::value::Value::from($v))] $($($rest)*)?)
+    };
+    ($($t:tt)*) => { stack!(@ [] $($t)*) };
+}
+
+// Run the byte codes (with optional constants) and check the stack.
+#[macro_export]
+macro_rules! assert_codes {
+    ([$($code:expr),* $(,)?], [$($k:expr),* $(,)?] => [$($t:tt)*]) => {
+        let stack = common::Chunk::new(vec![$($code),*])
+            .constants(vec![$(lua_rs::value::Value::from($k)),*])
+            .run();
+        common::check_stack(&stack, &stack![$($t)*]);
+    };
+    ([$($code:expr),* $(,)?] => [$($t:tt)*]) => {
+        assert_codes!([$($code),*], [] => [$($t)*])
+    };
+}
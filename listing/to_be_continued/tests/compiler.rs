@@ -0,0 +1,80 @@
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use lua_rs::compiler::{BinaryCompiler, Compiler, DefaultCompiler};
+use lua_rs::dump;
+use lua_rs::error::LuaError;
+use lua_rs::parse::{self, FuncProto};
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+// a toy front end: Lua with `!=` for `~=`, by rewriting the source
+struct BangLua;
+
+impl Compiler for BangLua {
+    fn compile(&self, chunk: &[u8], chunk_name: &str) -> FuncProto {
+        let source = String::from_utf8_lossy(chunk).replace("!=", "~=");
+        parse::load_named(source.as_bytes(), chunk_name)
+    }
+}
+
+// a directory for the test files, removed at the end
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("lua_rs_{name}_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+    fn file(&self, name: &str, content: &[u8]) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn custom() {
+    let dir = TempDir::new("compiler_custom");
+    let main = dir.file("main.bang", b"package.path = dir .. '/?.bang'
+        local m = require 'm'
+        return 1 != 2, m.ne(3, 3)");
+    dir.file("m.bang", b"return { ne = function (a, b) return a != b end }");
+
+    let mut state = ExeState::builder().compiler(BangLua).build();
+    state.globals().set("dir", dir.0.display().to_string());
+    let rets = state.exec_file(&main).unwrap();
+    assert_eq!(rets, [Value::Boolean(true), Value::Boolean(false)]);
+
+    // the default one is plain Lua
+    let err = ExeState::new().exec_file(&main).unwrap_err();
+    assert!(matches!(err, LuaError::SyntaxError { line: 3, .. }), "{err}");
+}
+
+#[test]
+fn binary() {
+    let dir = TempDir::new("compiler_binary");
+    let proto = parse::load_named("return 'from binary'".as_bytes(), "=test");
+    let binary = dir.file("main.luac", &dump::dump(&proto, false));
+    let source = dir.file("main.lua", b"return 'from source'");
+
+    // both by default
+    let mut state = ExeState::new();
+    assert_eq!(state.exec_file(&binary).unwrap(), ["from binary".into()]);
+    assert_eq!(state.exec_file(&source).unwrap(), ["from source".into()]);
+    assert_eq!(state.load(b"return 1", "=x").byte_codes,
+        DefaultCompiler.compile(b"return 1", "=x").byte_codes);
+
+    // binary chunks only
+    let mut state = ExeState::builder().compiler(BinaryCompiler).build();
+    assert_eq!(state.exec_file(&binary).unwrap(), ["from binary".into()]);
+    let err = panic::catch_unwind(|| BinaryCompiler.compile(b"return 1", "@main.lua")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(), "main.lua: attempt to load a text chunk");
+}
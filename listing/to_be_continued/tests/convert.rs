@@ -0,0 +1,75 @@
+use std::panic::{self, AssertUnwindSafe};
+use lua_rs::parse;
+use lua_rs::value::{TableHandle, Value};
+use lua_rs::vm::ExeState;
+
+fn new_state() -> ExeState {
+    let state = ExeState::new();
+    let globals = state.globals();
+    globals.set("rep", state.create_typed_function("rep",
+        |(s, n): (String, Option<usize>)| s.repeat(n.unwrap_or(2))));
+    globals.set("divmod", state.create_typed_function("divmod",
+        |(a, b): (i64, i64)| -> Result<(i64, i64), String> {
+            if b == 0 {
+                return Err("divide by zero".into());
+            }
+            Ok((a / b, a % b))
+        }));
+    globals.set("count", state.create_typed_function("count",
+        |args: Vec<Value>| args.len()));
+    globals.set("size", state.create_typed_function("size",
+        |t: TableHandle| t.len()));
+    globals.set("find", state.create_typed_function("find",
+        |(t, key): (TableHandle, Value)| {
+            let v = t.get(key);
+            if v == Value::Nil { None } else { Some(v) }
+        }));
+    state
+}
+
+fn eval(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn typed_function() {
+    let mut state = new_state();
+    let rets = eval(&mut state, r#"
+        local q, r = divmod(17, "5")
+        return rep("ab"), rep("ab", 3), rep(12, 2.0), q, r,
+            count(), count(nil, nil), size({1, 2, x = 3}), find({x = 1}, "x"), find({}, "x")
+    "#);
+    assert_eq!(rets, ["abab".into(), "ababab".into(), "1212".into(), Value::Integer(3),
+        Value::Integer(2), Value::Integer(0), Value::Integer(2), Value::Integer(3),
+        Value::Integer(1), Value::Nil]);
+}
+
+#[test]
+fn errors() {
+    let error = |source: &str| {
+        let mut state = new_state();
+        let err = panic::catch_unwind(AssertUnwindSafe(|| eval(&mut state, source))).unwrap_err();
+        err.downcast_ref::<String>().unwrap().clone()
+    };
+    assert_eq!(error("rep({})"), "[string \"?\"]:1: bad argument #1 to 'rep' (string expected, got table)");
+    assert_eq!(error("rep('a', 'x')"), "[string \"?\"]:1: bad argument #2 to 'rep' (number expected, got string)");
+    assert_eq!(error("rep('a', 1.5)"), "[string \"?\"]:1: bad argument #2 to 'rep' (number has no integer representation)");
+    assert_eq!(error("rep('a', -1)"), "[string \"?\"]:1: bad argument #2 to 'rep' (number has no usize representation)");
+    assert_eq!(error("divmod(1)"), "[string \"?\"]:1: bad argument #2 to 'divmod' (number expected, got no value)");
+    assert_eq!(error("divmod(1, 0)"), "[string \"?\"]:1: divide by zero");
+    assert_eq!(error("size()"), "[string \"?\"]:1: bad argument #1 to 'size' (table expected, got no value)");
+}
+
+// check() and push_multi() in plain Rust functions
+#[test]
+fn check() {
+    fn swap(state: &mut ExeState) -> i32 {
+        let a: i64 = state.check(1, "swap");
+        let b: Option<String> = state.check(2, "swap");
+        state.push_multi((b, a))
+    }
+    let mut state = ExeState::new();
+    state.globals().set("swap", Value::RustFunction(swap));
+    let rets = eval(&mut state, "return swap(1, 'x'), swap(2)");
+    assert_eq!(rets, ["x".into(), Value::Nil, Value::Integer(2)]);
+}
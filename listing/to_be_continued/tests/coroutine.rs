@@ -0,0 +1,286 @@
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(source: &str) -> Vec<Value> {
+    ExeState::new().exec_main(&parse::load(source.as_bytes()))
+}
+
+fn exec_err(source: &str) -> String {
+    let mut state = ExeState::new();
+    let f = Value::LuaFunction(parse::load(source.as_bytes()).into());
+    let env = state.globals().into(); // the main function's `_ENV`
+    state.pcall(f, &[env]).unwrap_err().to_string()
+}
+
+fn ints(v: &[i64]) -> Vec<Value> {
+    v.iter().map(|&i| Value::Integer(i)).collect()
+}
+
+#[test]
+fn generator() {
+    let rets = exec(r#"
+        local function range(n)
+            return coroutine.wrap(function ()
+                for i = 1, n do
+                    coroutine.yield(i)
+                end
+            end)
+        end
+        local sum = 0
+        for i in range(4) do
+            sum = sum + i
+        end
+
+        -- yield in nested Lua calls
+        local function walk(t)
+            for _, v in ipairs(t) do
+                if type(v) == "table" then
+                    walk(v)
+                else
+                    coroutine.yield(v)
+                end
+            end
+        end
+        local list = {}
+        local inner = { 2, 3 }
+        for v in coroutine.wrap(function () walk({ 1, inner, 4 }) end) do
+            list[#list + 1] = v
+        end
+        return sum, #list, list[1], list[2], list[3], list[4]
+    "#);
+    assert_eq!(rets, ints(&[10, 4, 1, 2, 3, 4]));
+}
+
+// values are passed by resume() and yield() in both directions
+#[test]
+fn pass_values() {
+    let rets = exec(r#"
+        local co = coroutine.create(function (a, b)
+            local c, d = coroutine.yield(a + b)
+            local e = coroutine.yield(c * d)
+            return e, "end"
+        end)
+        local _, r1 = coroutine.resume(co, 1, 2)
+        local _, r2 = coroutine.resume(co, 3, 4)
+        local ok, r3, r4 = coroutine.resume(co, 5)
+        return r1, r2, r3, r4, ok
+    "#);
+    assert_eq!(rets, [Value::Integer(3), Value::Integer(12), Value::Integer(5),
+        "end".into(), Value::Boolean(true)]);
+
+    // a Rust function as the coroutine
+    let rets = exec(r#"
+        local co = coroutine.create(coroutine.yield)
+        local _, a, b = coroutine.resume(co, 1, 2)
+        local _, c = coroutine.resume(co, 3)
+        return a, b, c, coroutine.status(co)
+    "#);
+    assert_eq!(rets, [Value::Integer(1), Value::Integer(2), Value::Integer(3), "dead".into()]);
+}
+
+#[test]
+fn status() {
+    let rets = exec(r#"
+        local co
+        local main, ismain = coroutine.running()
+        co = coroutine.create(function ()
+            local inner = coroutine.create(function ()
+                coroutine.yield(coroutine.status(co), coroutine.status(main))
+            end)
+            local _, s1, s2 = coroutine.resume(inner)
+            local me, ismain = coroutine.running()
+            coroutine.yield(s1, s2, coroutine.status(inner), coroutine.status(co),
+                me == co, ismain, coroutine.isyieldable())
+        end)
+        local s0 = coroutine.status(co)
+        local _, s1, s2, s3, s4, same, ismain2, yieldable = coroutine.resume(co)
+        local s5 = coroutine.status(co)
+        coroutine.resume(co)
+        return s0, s1, s2, s3, s4, same, ismain2, yieldable, s5, coroutine.status(co),
+            ismain, coroutine.isyieldable(), type(co)
+    "#);
+    assert_eq!(rets, ["suspended".into(), "normal".into(), "normal".into(),
+        "suspended".into(), "running".into(), Value::Boolean(true), Value::Boolean(false),
+        Value::Boolean(true), "suspended".into(), "dead".into(),
+        Value::Boolean(true), Value::Boolean(false), "thread".into()]);
+}
+
+// upvalues are shared by the threads, even when the locals are alive
+// on the stack of a suspended thread
+#[test]
+fn upvalues() {
+    let rets = exec(r#"
+        local n = 0
+        local get
+        local co = coroutine.create(function ()
+            local m = 10
+            get = function () return m end
+            while true do
+                n = n + 1
+                m = m + 1
+                coroutine.yield()
+            end
+        end)
+        coroutine.resume(co)
+        coroutine.resume(co)
+        local r1, r2 = n, get()
+
+        -- changed out of the coroutine, and seen inside
+        local set
+        local co2 = coroutine.wrap(function ()
+            local x = 1
+            set = function (v) x = v end
+            coroutine.yield()
+            return x
+        end)
+        co2()
+        set(42)
+        return r1, r2, co2()
+    "#);
+    assert_eq!(rets, ints(&[2, 12, 42]));
+}
+
+#[test]
+fn errors() {
+    let rets = exec(r#"
+        local co = coroutine.create(function () error_here() end)
+        local ok, msg = coroutine.resume(co)
+        local ok2, msg2 = coroutine.resume(co)
+        local ok3, msg3 = coroutine.resume(coroutine.running())
+        return ok, msg, ok2, msg2, ok3, msg3, coroutine.status(co)
+    "#);
+    assert_eq!(rets[0], Value::Boolean(false));
+    assert!(rets[1].to_string().contains("attempt to call a nil value (global 'error_here')"), "{}", rets[1]);
+    assert_eq!(rets[2..], [Value::Boolean(false), "cannot resume dead coroutine".into(),
+        Value::Boolean(false), "cannot resume non-suspended coroutine".into(), "dead".into()]);
+
+    // wrap() propagates the errors
+    let err = exec_err("coroutine.wrap(function () local t = nil; t.x = 1 end)()");
+    assert!(err.contains("attempt to index a nil value (local 't')"), "{err}");
+
+    let err = exec_err("coroutine.yield(1)");
+    assert!(err.contains("attempt to yield from outside a coroutine"), "{err}");
+
+    // Rust functions calling Lua can not be suspended
+    let err = exec_err(r#"
+        coroutine.wrap(function ()
+            table.sort({ 3, 2, 1 }, function (a, b) coroutine.yield() return a < b end)
+        end)()
+    "#);
+    assert!(err.contains("attempt to yield across a C-call boundary"), "{err}");
+
+    let err = exec_err("coroutine.resume(1)");
+    assert!(err.contains("bad argument #1 to 'resume' (coroutine expected, got number)"), "{err}");
+
+    // the state still works after the errors in coroutines
+    let rets = exec(r#"
+        local co = coroutine.wrap(function (x)
+            local ok = coroutine.resume(coroutine.create(function () error_here() end))
+            return x, ok
+        end)
+        return co(1)
+    "#);
+    assert_eq!(rets, [Value::Integer(1), Value::Boolean(false)]);
+
+    // error objects which are not strings are kept
+    let rets = exec(r#"
+        local e = {}
+        local ok, v = coroutine.resume(coroutine.create(function () error(e) end))
+        local ok2, v2 = pcall(coroutine.wrap(function () error(e) end))
+        local ok3, v3 = coroutine.resume(coroutine.create(function () error(42, 2) end))
+        return ok, v == e, ok2, v2 == e, v3
+    "#);
+    assert_eq!(rets, [Value::Boolean(false), Value::Boolean(true),
+        Value::Boolean(false), Value::Boolean(true), Value::Integer(42)]);
+}
+
+// resumed by the host
+#[test]
+fn host() {
+    let mut state = ExeState::new();
+    let f = state.exec_main(&parse::load(br#"
+        return function (a)
+            local b = coroutine.yield(a * 2)
+            return a + b
+        end
+    "#.as_slice())).remove(0);
+    let co = state.call(state.globals().get("coroutine").index(&"create".into()), &[f]).remove(0);
+    let Value::Coroutine(co) = co else { panic!("not a coroutine") };
+
+    assert_eq!(state.resume(&co, &[Value::Integer(5)]).unwrap(), ints(&[10]));
+    assert_eq!(state.resume(&co, &[Value::Integer(1)]).unwrap(), ints(&[6]));
+    let err = state.resume(&co, &[]).unwrap_err();
+    assert_eq!(err.to_string(), "cannot resume dead coroutine");
+}
+
+#[test]
+fn native_generators() {
+    assert_eq!(exec("
+        local t = {}
+        for i in range(3) do t[#t+1] = i end
+        for i in range(10, 1, -4) do t[#t+1] = i end
+        for i in range(5, 4) do t[#t+1] = i end
+        local max = 9223372036854775807 -- not overflowing
+        for i in range(max - 1, max) do t[#t+1] = i - max end
+        return t[1], t[2], t[3], t[4], t[5], t[6], t[7], t[8], t[9]
+    "), [ints(&[1, 2, 3, 10, 6, 2, -1, 0]), vec![Value::Nil]].concat());
+
+    // keys and values, where the array part goes first
+    assert_eq!(exec("
+        local t = {10, 20, x = 30}
+        local sum, n = 0, 0
+        for k, v in table.entries(t) do sum = sum + v; n = n + 1 end
+        local next_entry = table.entries({})
+        return sum, n, next_entry()
+    "), ints(&[60, 3]));
+
+    // generators driven by coroutines, and yields across the iterators
+    assert_eq!(exec("
+        local squares = coroutine.wrap(function()
+            for i in range(4) do coroutine.yield(i * i) end
+        end)
+        local t = {}
+        for sq in squares do t[#t+1] = sq end
+
+        local co = coroutine.wrap(function(gen)
+            local sum = 0
+            for k, v in gen do sum = sum + coroutine.yield(k) * v end
+            return sum
+        end)
+        local k = co(table.entries({2, 3}))
+        k = co(k)
+        t[#t+1] = co(k)
+        return t[1], t[2], t[3], t[4], t[5]
+    "), ints(&[1, 4, 9, 16, 8]));
+
+    assert!(exec_err("for i in range(1, 2, 0) do end").contains("bad argument #3 to 'range' (step is zero)"));
+    assert!(exec_err("table.entries(1)").contains("bad argument #1 to 'entries' (table expected, got number)"));
+}
+
+#[test]
+fn stack_limit() {
+    let mut state = ExeState::builder().max_coroutine_stack_size(200).build();
+    let rets = state.exec_main(&parse::load("
+        local function depth(n)
+            if n > 0 then return 1 + depth(n - 1) end
+            return 0
+        end
+        local co = coroutine.wrap(function(n)
+            local ok, err = pcall(depth, n)
+            coroutine.yield(ok, err)
+            return depth(10)
+        end)
+        local ok, err = co(150)
+        return depth(150), ok, string.find(err, 'stack overflow', 1, true) ~= nil, co()
+    ".as_bytes()));
+    // the main thread has its own limit, and the coroutine is still usable
+    assert_eq!(rets, [Value::Integer(150), Value::Boolean(false), Value::Boolean(true), Value::Integer(10)]);
+}
+
+// not standard, see tests/transfer.rs
+#[cfg(not(feature = "transfer"))]
+#[test]
+fn no_transfer() {
+    assert_eq!(exec("return coroutine.transfer"), [Value::Nil]);
+}
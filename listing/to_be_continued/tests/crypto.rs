@@ -0,0 +1,60 @@
+#![cfg(all(feature = "crypto", feature = "encoding"))]
+
+mod common;
+
+use lua_rs::value::Value;
+use common::eval;
+
+#[test]
+fn hashes() {
+    assert_eq!(eval(r#"
+        return crypto.sha256(""), crypto.sha256("abc"),
+            crypto.sha256("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            crypto.md5(""), crypto.md5("The quick brown fox jumps over the lazy dog"),
+            crypto.md5(string.rep("a", 1000))
+    "#), [
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".into(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into(),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1".into(),
+        "d41d8cd98f00b204e9800998ecf8427e".into(),
+        "9e107d9d372bb6826bd81d3542a419d6".into(),
+        "cabe45dcc9ae5b66ba86600cca6b8ba8".into(),
+    ]);
+
+    // raw digests, and bytes buffers
+    assert_eq!(eval(r#"
+        return #crypto.sha256("abc", true), encoding.hex(crypto.md5("", true)),
+            crypto.md5(bytes.new("abc")) == crypto.md5("abc")
+    "#), [Value::Integer(32), "d41d8cd98f00b204e9800998ecf8427e".into(), Value::Boolean(true)]);
+}
+
+#[test]
+fn base64() {
+    assert_eq!(eval(r#"
+        local e = encoding.base64_encode
+        return e(""), e("f"), e("fo"), e("foo"), e("foob"), e("fooba"), e("foobar"),
+            e("\251\255", "url"), e("\251\255")
+    "#), ["".into(), "Zg==".into(), "Zm8=".into(), "Zm9v".into(), "Zm9vYg==".into(),
+        "Zm9vYmE=".into(), "Zm9vYmFy".into(), "-_8".into(), "+/8=".into()]);
+
+    assert_eq!(eval(r#"
+        local d = encoding.base64_decode
+        return d("Zm9vYg=="), d("Zm9vYg"), d("-_8", "url"), d(""), d("+/8=")
+    "#), ["foob".into(), "foob".into(), b"\xfb\xff".as_slice().into(), "".into(),
+        b"\xfb\xff".as_slice().into()]);
+
+    for (s, msg) in [("Zm9vY", "invalid base64 length"), ("Zm=vYg==", "invalid base64 padding"),
+        ("Zm9vYg=", "invalid base64 padding"), ("Zm9v!g==", "invalid base64 character")]
+    {
+        assert_eq!(eval(format!("return encoding.base64_decode('{s}')")), [Value::Nil, msg.into()]);
+    }
+}
+
+#[test]
+fn hex() {
+    assert_eq!(eval(r#"
+        return encoding.hex("\0\1\171\255"), encoding.hex_decode("0001AbfF"), encoding.hex(12)
+    "#), ["0001abff".into(), b"\x00\x01\xab\xff".as_slice().into(), "3132".into()]);
+    assert_eq!(eval("return encoding.hex_decode('abc')"), [Value::Nil, "invalid hex length".into()]);
+    assert_eq!(eval("return encoding.hex_decode('+1')"), [Value::Nil, "invalid hex digit".into()]);
+}
@@ -0,0 +1,47 @@
+use lua_rs::ctype::{in_class, to_lower, to_upper};
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+// the classes of all bytes, against the definitions of the "C" locale
+#[test]
+fn classes() {
+    for b in 0..=255u8 {
+        let c = b as char;
+        let ascii = b.is_ascii();
+        for (class, expect) in [
+            (b'a', c.is_ascii_alphabetic()),
+            (b'd', c.is_ascii_digit()),
+            (b'l', c.is_ascii_lowercase()),
+            (b'u', c.is_ascii_uppercase()),
+            (b's', matches!(b, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c)),
+            (b'w', c.is_ascii_alphanumeric()),
+            (b'x', c.is_ascii_hexdigit()),
+            (b'p', ascii && (0x21..=0x7e).contains(&b) && !c.is_ascii_alphanumeric()),
+            (b'c', b < 0x20 || b == 0x7f),
+            (b'g', (0x21..=0x7e).contains(&b)),
+        ] {
+            assert_eq!(in_class(class, b), Some(expect), "%{} of {b:#x}", class as char);
+            assert_eq!(in_class(class.to_ascii_uppercase(), b), Some(!expect),
+                "%{} of {b:#x}", class.to_ascii_uppercase() as char);
+        }
+    }
+    assert_eq!(in_class(b'z', b'a'), None);
+    assert_eq!(in_class(b'.', b'a'), None);
+}
+
+#[test]
+fn cases() {
+    assert_eq!(to_upper(b'a'), b'A');
+    assert_eq!(to_upper(b'Z'), b'Z');
+    assert_eq!(to_lower(b'Z'), b'z');
+    assert_eq!(to_lower(b'1'), b'1');
+    for b in 0x80..=0xff {
+        assert_eq!((to_upper(b), to_lower(b)), (b, b));
+    }
+
+    let rets = ExeState::new().exec_main(&parse::load(r#"
+        return string.upper("Hello, World! é"), string.lower("Hello, World! É"), string.upper(12)
+    "#.as_bytes()));
+    assert_eq!(rets, [Value::from("HELLO, WORLD! é"), Value::from("hello, world! É"), Value::from("12")]);
+}
@@ -0,0 +1,82 @@
+use std::panic;
+use lua_rs::data;
+use lua_rs::value::{Value, TableHandle};
+
+fn load(source: &str) -> Value {
+    data::load(source.as_bytes(), "=data")
+}
+
+fn table(v: Value) -> TableHandle {
+    match v {
+        Value::Table(t) => TableHandle::new(t),
+        v => panic!("not table: {v:?}"),
+    }
+}
+
+fn error(source: &str) -> String {
+    let e = panic::catch_unwind(|| load(source)).unwrap_err();
+    *e.downcast::<String>().unwrap()
+}
+
+#[test]
+fn values() {
+    assert_eq!(load("return 1"), Value::Integer(1));
+    assert_eq!(load("-2.5;"), Value::Float(-2.5));
+    assert_eq!(load("return -9223372036854775807"), Value::Integer(-i64::MAX));
+    assert_eq!(load("'str'"), "str".into());
+    assert_eq!(load("return [[long]]"), "long".into());
+    assert_eq!(load("nil"), Value::Nil);
+    assert_eq!(load("true"), Value::Boolean(true));
+}
+
+#[test]
+fn tables() {
+    let t = table(load(r#"
+        -- comments are allowed
+        return {
+            { id = 1, name = "sword", tags = { "melee", "iron" } },
+            { id = 2, name = "bow", ["range"] = 30, [0.5] = "half"; },
+            [10] = "ten",
+        }"#));
+    assert_eq!(t.len(), 3);
+    assert_eq!(t.get(10), "ten".into());
+
+    let sword = table(t.get(1));
+    assert_eq!(sword.get("id"), Value::Integer(1));
+    assert_eq!(sword.get("name"), "sword".into());
+    let tags = table(sword.get("tags"));
+    assert_eq!((tags.get(1), tags.get(2), tags.len()), ("melee".into(), "iron".into(), 2));
+
+    let bow = table(t.get(2));
+    assert_eq!(bow.get("range"), Value::Integer(30));
+    assert_eq!(bow.get(0.5), "half".into());
+
+    // positional fields win over explicit integer keys
+    let t = table(load("{ [1] = 'a', 'b', [2] = 'c', nil, 'd' }"));
+    assert_eq!((t.get(1), t.get(2), t.get(3)), ("b".into(), Value::Nil, "d".into()));
+
+    assert!(table(load("{}")).is_empty());
+}
+
+#[test]
+fn large() {
+    let mut source = String::from("return {\n");
+    for i in 0..100000 {
+        source += &format!("  {{ id = {i}, name = \"item{i}\", weight = {i}.5 }},\n");
+    }
+    source += "}";
+    let t = table(load(&source));
+    assert_eq!(t.len(), 100000);
+    let last = table(t.get(100000));
+    assert_eq!((last.get("id"), last.get("name")), (Value::Integer(99999), "item99999".into()));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("return 1 + 2"), "data:1: <eof> expected near '+'");
+    assert_eq!(error("return { x = f() }"), "data:1: constant expected near 'f'");
+    assert_eq!(error("return { a = 1 b = 2 }"), "data:1: constant expected near 'b'");
+    assert_eq!(error("return {\n[nil] = 1 }"), "data:2: nil can not be table key");
+    assert_eq!(error("return { 1, 2"), "data:1: constant expected near <eof>");
+    assert_eq!(error(&"{".repeat(1000)), "data:1: chunk has too many syntax levels");
+}
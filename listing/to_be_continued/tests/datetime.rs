@@ -0,0 +1,85 @@
+#![cfg(feature = "datetime")]
+
+mod common;
+
+use lua_rs::value::Value;
+use common::eval;
+
+#[test]
+fn rfc3339() {
+    assert_eq!(eval(r#"
+        return datetime.parse("2024-02-29T12:00:00+08:00"), datetime.parse("1970-01-01T00:00:00Z")
+    "#), [Value::Integer(1709179200), Value::Integer(0), Value::Integer(0)]);
+    assert_eq!(eval(r#"
+        return datetime.parse("2000-01-01"), datetime.parse("1969-12-31t23:59:59.25-00:30")
+    "#), [Value::Integer(946684800), Value::Float(1800.0 - 0.75), Value::Integer(-1800)]);
+
+    assert_eq!(eval(r#"
+        local t = datetime.parse("2024-02-29T12:00:00+08:00")
+        return datetime.format(t), datetime.format(t, "+05:30"), datetime.format(t + 0.5, -3600),
+            datetime.format(-1.000001)
+    "#), ["2024-02-29T04:00:00Z".into(), "2024-02-29T09:30:00+05:30".into(),
+        "2024-02-29T03:00:00.5-01:00".into(), "1969-12-31T23:59:58.999999Z".into()]);
+
+    // the local time zone round trip
+    assert_eq!(eval(r#"
+        local t = 1700000000
+        return datetime.parse(datetime.format(t, "local")) == t
+    "#), [Value::Boolean(true)]);
+
+    for s in ["2024-02-30T00:00:00Z", "2023-02-29", "2024-01-01T24:00:00Z", "2024-01-01T00:00:00",
+            "2024-01-01T00:00:00+8:00", "2024-1-01", "2024-01-01T00:00:00.Z", ""] {
+        assert_eq!(eval(format!("return datetime.parse('{s}')")),
+            [Value::Nil, format!("invalid RFC 3339 time '{s}'").into()]);
+    }
+}
+
+#[test]
+fn fields() {
+    assert_eq!(eval(r#"
+        local f = datetime.fields(datetime.parse("2024-12-31T23:59:58.5Z"), "+01:00")
+        return f.year, f.month, f.day, f.hour, f.min, f.sec, f.usec, f.wday, f.yday, f.offset
+    "#), [2025, 1, 1, 0, 59, 58, 500000, 4, 1, 3600].map(Value::Integer));
+
+    // normalized, for the calendar arithmetic
+    assert_eq!(eval(r#"
+        local f = datetime.fields(datetime.parse("2024-01-31T10:00:00Z"))
+        f.month = f.month + 1
+        f.day = 1
+        local a = datetime.time(f)
+        local b = datetime.time({year = 2024, month = 13, day = 0, hour = 25}, "+02:00")
+        return datetime.format(a), datetime.format(b), datetime.time({year = 1970, month = 1, day = 1})
+    "#), ["2024-02-01T10:00:00Z".into(), "2024-12-31T23:00:00Z".into(), Value::Integer(0)]);
+
+    assert_eq!(eval(r#"
+        local t = datetime.now()
+        local u = datetime.time(datetime.fields(t, "local"), "local")
+        return math.abs(u - t) < 1e-6, math.abs(os.time() - t) < 2
+    "#), [Value::Boolean(true), Value::Boolean(true)]);
+}
+
+#[test]
+fn durations() {
+    assert_eq!(eval(r#"
+        return datetime.parse_duration("1h30m"), datetime.parse_duration("1.5s"),
+            datetime.parse_duration("-300ms"), datetime.parse_duration("2d1s"),
+            datetime.parse_duration("0")
+    "#), [Value::Integer(5400), Value::Float(1.5), Value::Float(-0.3), Value::Integer(172801),
+        Value::Integer(0)]);
+    assert_eq!(eval("return datetime.parse_duration('1x')"),
+        [Value::Nil, "invalid duration '1x'".into()]);
+    assert_eq!(eval("return datetime.parse_duration('10')"),
+        [Value::Nil, "invalid duration '10'".into()]);
+
+    assert_eq!(eval(r#"
+        local f = datetime.format_duration
+        return f(5400), f(1.5), f(-0.0003), f(0), f(90061.25), f(2e-9)
+    "#), ["1h30m0s".into(), "1.5s".into(), "-300us".into(), "0s".into(), "25h1m1.25s".into(),
+        "2ns".into()]);
+
+    assert_eq!(eval(r#"
+        local a = datetime.monotonic()
+        local b = datetime.monotonic()
+        return math.type(a), b >= a
+    "#), ["float".into(), Value::Boolean(true)]);
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "serde")]
+
+use std::collections::HashMap;
+use serde::Deserialize;
+use lua_rs::Lua;
+use lua_rs::de::from_value;
+use lua_rs::value::Value;
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct ServerConfig {
+    name: String,
+    port: u16,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[test]
+fn eval_config() {
+    let config: ServerConfig = Lua::eval_config("test_lua/mod/server_config.lua").unwrap();
+    assert_eq!(config, ServerConfig { name: "server".into(), port: 8080, tags: vec!["a".into(), "b".into()] });
+
+    let err = Lua::eval_config::<ServerConfig>("test_lua/mod/bad_config.lua").unwrap_err();
+    assert_eq!(err.to_string(), "invalid type: string \"8080\", expected u16");
+
+    let err = Lua::eval_config::<ServerConfig>("test_lua/mod/empty_config.lua").unwrap_err();
+    assert_eq!(err.to_string(), "config should return a table, got no value");
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+enum Listen {
+    Stdio,
+    Tcp(u16),
+    Unix { path: String },
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Service {
+    listen: Vec<Listen>,
+    timeout: Option<f64>,
+    limits: HashMap<String, i64>,
+    point: (i32, i32),
+    data: serde_bytes_like::Bytes,
+}
+
+// bytes without the serde_bytes crate
+mod serde_bytes_like {
+    #[derive(Debug, PartialEq)]
+    pub struct Bytes(pub Vec<u8>);
+
+    impl<'de> serde::Deserialize<'de> for Bytes {
+        fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            struct V;
+            impl serde::de::Visitor<'_> for V {
+                type Value = Bytes;
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("bytes")
+                }
+                fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Bytes, E> {
+                    Ok(Bytes(v))
+                }
+            }
+            d.deserialize_byte_buf(V)
+        }
+    }
+}
+
+// the value of the expression
+fn eval(source: &str) -> Value {
+    let mut lua = Lua::new();
+    lua.load(format!("return {source}")).unwrap().call(&[]).unwrap().remove(0)
+}
+
+#[test]
+fn values() {
+    let service: Service = from_value(eval(r#"{
+        listen = { "Stdio", { Tcp = 8080.0 }, { Unix = { path = "/tmp/s" } } },
+        limits = { conn = 100, [1] = nil, rate = 2 ^ 10 },
+        point = { 3, -4 },
+        data = "\xff\0",
+    }"#)).unwrap();
+    assert_eq!(service, Service {
+        listen: vec![Listen::Stdio, Listen::Tcp(8080), Listen::Unix { path: "/tmp/s".into() }],
+        timeout: None,
+        limits: HashMap::from([("conn".into(), 100), ("rate".into(), 1024)]),
+        point: (3, -4),
+        data: serde_bytes_like::Bytes(vec![0xff, 0]),
+    });
+
+    let err = |source: &str| from_value::<ServerConfig>(eval(source)).unwrap_err().to_string();
+    assert_eq!(err("{ port = 80 }"), "missing field `name`");
+    assert_eq!(err("{ name = 'a', port = 1.5 }"), "invalid value: floating point `1.5`, expected u16");
+    assert_eq!(err("{ name = 'a', port = 70000 }"), "invalid value: integer `70000`, expected u16");
+    assert_eq!(err("{ name = 'a', port = 1, tags = { 'x', 2 } }"),
+        "invalid type: integer `2`, expected a string");
+    assert_eq!(err("print"), "invalid type: function, expected struct ServerConfig");
+}
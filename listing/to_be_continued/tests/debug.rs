@@ -0,0 +1,57 @@
+mod common;
+
+use std::panic;
+use lua_rs::disasm;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use common::eval;
+
+#[test]
+fn disassemble() {
+    let proto = parse::load(r#"
+        local a = 7
+        local function f(x, y)
+            return a + x, print
+        end
+        print(f(1, 2))
+    "#.as_bytes());
+
+    let listing = disasm::disassemble(&proto);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[0], "function <main> (9 byte codes, 1 params+, 7 slots, 0 upvalues, 3 locals, 2 constants)");
+    assert_eq!(lines[1], "  [0]  LoadInt(1, 7)           ; local 'a'");
+    assert_eq!(lines[2], "  [1]  Closure(2, 0)           ; local 'f', function <main[0]>");
+    assert_eq!(lines[3], "  [2]  GetField(3, 0, 1)       ; local '_ENV', \"print\"");
+    assert!(listing.contains("locals (3):\n  [0]  _ENV  0  9\n  [1]  a     1  9\n  [2]  f     1  9\n"));
+
+    // the inner function follows
+    assert!(listing.contains("\nfunction <main[0]> (5 byte codes, 2 params, 4 slots, 2 upvalues, 2 locals, 1 constants)\n"));
+    assert!(listing.contains("  [0]  GetUpvalue(2, 0)        ; upvalue 'a'\n"));
+    assert!(listing.contains("  [2]  GetUpField(3, 1, 0)     ; upvalue '_ENV', \"print\"\n"));
+    assert!(listing.ends_with("upvalues (2):\n  [0]  a     Local(1)\n  [1]  _ENV  Local(0)\n"));
+}
+
+#[test]
+fn getlocal() {
+    assert_eq!(eval(r#"
+        local function f(a, b, ...) local c end
+        return debug.getlocal(f, 1), debug.getlocal(f, 2), debug.getlocal(f, 3),
+            debug.getlocal(f, 0), debug.getlocal(print, 1)
+    "#), [Value::from("a"), Value::from("b"), Value::Nil, Value::Nil, Value::Nil]);
+
+    let e = panic::catch_unwind(|| eval("debug.getlocal(1, 1)")).unwrap_err();
+    assert_eq!(e.downcast_ref::<String>().unwrap(),
+        "[string \"?\"]:1: bad argument #1 to 'getlocal' (level is not supported)");
+}
+
+#[test]
+fn getupvalue() {
+    assert_eq!(eval(r#"
+        local x, y = 1, 2
+        local function f() return x + y end
+        local n1, v1 = debug.getupvalue(f, 1)
+        y = 3 -- open upvalue
+        local n2, v2 = debug.getupvalue(f, 2)
+        return n1, v1, n2, v2, debug.getupvalue(f, 3)
+    "#), [Value::from("x"), Value::Integer(1), Value::from("y"), Value::Integer(3)]);
+}
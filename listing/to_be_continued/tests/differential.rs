@@ -0,0 +1,83 @@
+// Differential testing against the official Lua interpreter.
+//
+// Run each script in test_lua/ both on this interpreter and on the official
+// `lua5.4` (or the binary set by $LUA), and compare the stdout and exit
+// status. Skipped if the official interpreter is not found.
+
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+// scripts whose outputs are expected to diverge, with reasons
+const DIVERGENT: &[(&str, &str)] = &[
+    ("numbers.lua", "floats are not formatted by `%.14g`"),
+    ("spectral-norm.lua", "floats are not formatted by `%.14g`"),
+];
+
+fn find_lua() -> Option<String> {
+    if let Ok(lua) = env::var("LUA") {
+        return Some(lua);
+    }
+    ["lua5.4", "lua"].into_iter()
+        .find(|lua| Command::new(lua).arg("-v").output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).starts_with("Lua 5.4")))
+        .map(String::from)
+}
+
+fn run(bin: &str, script: &str) -> Output {
+    Command::new(bin).arg(script).output()
+        .unwrap_or_else(|e| panic!("fail to run {bin}: {e}"))
+}
+
+// describe the first different line
+fn diff(expect: &[u8], got: &[u8]) -> String {
+    let expect = String::from_utf8_lossy(expect);
+    let got = String::from_utf8_lossy(got);
+    let mut got_lines = got.lines();
+    for (i, e) in expect.lines().enumerate() {
+        match got_lines.next() {
+            Some(g) if g == e => (),
+            Some(g) => return format!("line {}: expect {e:?}, got {g:?}", i + 1),
+            None => return format!("line {}: expect {e:?}, got EOF", i + 1),
+        }
+    }
+    match got_lines.next() {
+        Some(g) => format!("extra output: {g:?}"),
+        None => String::from("same output"),
+    }
+}
+
+#[test]
+fn differential() {
+    let Some(lua) = find_lua() else {
+        eprintln!("official Lua interpreter not found, skip differential test");
+        return;
+    };
+
+    let mut scripts: Vec<_> = fs::read_dir("test_lua").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .collect();
+    scripts.sort();
+
+    let mut failures = Vec::new();
+    for path in scripts {
+        let script = path.to_str().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if DIVERGENT.iter().any(|(n, _)| *n == name) {
+            continue;
+        }
+
+        let expect = run(&lua, script);
+        let got = run(env!("CARGO_BIN_EXE_lua-rs"), script);
+
+        if expect.status.success() != got.status.success() {
+            failures.push(format!("{name}: exit status: expect {}, got {}",
+                expect.status, got.status));
+        } else if expect.stdout != got.stdout {
+            failures.push(format!("{name}: {}", diff(&expect.stdout, &got.stdout)));
+        }
+    }
+
+    assert!(failures.is_empty(), "divergent scripts:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,278 @@
+use std::fs;
+use std::panic;
+use std::rc::Rc;
+use lua_rs::bytecode::ByteCode;
+use lua_rs::dump;
+use lua_rs::error::LuaError;
+use lua_rs::parse::{self, FuncProto};
+use lua_rs::value::Value;
+use lua_rs::vm::{ExeState, ExecLimit};
+
+fn compile(source: &str) -> FuncProto {
+    parse::load_named(source.as_bytes(), "=test")
+}
+
+fn undump_error(chunk: &[u8]) -> String {
+    let err = panic::catch_unwind(|| dump::undump(chunk, "=test")).unwrap_err();
+    err.downcast_ref::<String>().unwrap().clone()
+}
+
+// scripts of test_lua/ for syntax errors, which are not compiled
+const SYNTAX_ERRORS: &[&str] = &["const_assign.lua"];
+
+// the sources of the scripts of test_lua/ which compile
+fn scripts() -> Vec<(String, String)> {
+    let mut scripts = Vec::new();
+    for entry in fs::read_dir("test_lua").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "lua") {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let source = fs::read_to_string(&path).unwrap();
+        let compiled = panic::catch_unwind(|| compile(&source)).is_ok();
+        if SYNTAX_ERRORS.contains(&name.as_str()) {
+            assert!(!compiled, "{name} is expected to fail to compile");
+        } else {
+            assert!(compiled, "{name} fails to compile");
+            scripts.push((name, source));
+        }
+    }
+    assert!(!scripts.is_empty());
+    scripts
+}
+
+#[test]
+fn round_trip() {
+    for (name, source) in scripts() {
+        let proto = compile(&source);
+        for strip in [false, true] {
+            let chunk = dump::dump(&proto, strip);
+            let proto2 = dump::undump(&chunk, "=test");
+            assert_eq!(dump::dump(&proto2, strip), chunk, "{name} strip={strip}");
+        }
+    }
+}
+
+// Compiling the same source always gives the same chunk. Each compiling
+// runs in a new thread, where hash maps have different random seeds.
+#[test]
+fn reproducible() {
+    for (name, source) in scripts() {
+        let chunks: Vec<Vec<u8>> = (0..3).map(|_| {
+            let source = source.clone();
+            std::thread::spawn(move || dump::dump(&compile(&source), false)).join().unwrap()
+        }).collect();
+        assert!(chunks.windows(2).all(|w| w[0] == w[1]), "{name}");
+    }
+}
+
+#[test]
+fn execute() {
+    let proto = compile(r#"
+        local n = 0
+        local function add(x) n = n + x return n end
+        add(1.5)
+        return add(2), "hello", nil, true, 1 << 40
+    "#);
+    for strip in [false, true] {
+        let proto = dump::undump(&dump::dump(&proto, strip), "=test");
+        assert_eq!(ExeState::new().exec_main(&proto),
+            [Value::Float(3.5), "hello".into(), Value::Nil, Value::Boolean(true), Value::Integer(1 << 40)]);
+    }
+}
+
+#[test]
+fn strip() {
+    let proto = compile("local up = 1; return function(a) return up + a end");
+    let full = dump::undump(&dump::dump(&proto, false), "=other");
+    assert_eq!(full.chunk_name, "=test");
+    assert_eq!(full.locals.len(), proto.locals.len());
+
+    let stripped = dump::undump(&dump::dump(&proto, true), "=other");
+    assert_eq!(stripped.chunk_name, "=?");
+    assert!(stripped.locals.is_empty() && stripped.upvalue_names.is_empty());
+    let Value::LuaFunction(inner) = &stripped.constants[0] else {
+        panic!("expect function");
+    };
+    assert_eq!(inner.chunk_name, "=?");
+    assert!(inner.locals.is_empty());
+    assert!(dump::dump(&proto, true).len() < dump::dump(&proto, false).len());
+
+    let proto = compile("local up = 7; return debug.getupvalue(function() return up end, 1)");
+    let stripped = dump::undump(&dump::dump(&proto, true), "=test");
+    assert_eq!(ExeState::new().exec_main(&stripped), ["(no name)".into(), Value::Integer(7)]);
+}
+
+// the header is same on all hosts
+#[test]
+fn header() {
+    let chunk = dump::dump(&compile("return"), false);
+    let mut header = b"\x1bLua\x54R\x01\x19\x93\r\n\x1a\n\x04\x08\x08".to_vec();
+    header.extend_from_slice(&[0x78, 0x56, 0, 0, 0, 0, 0, 0]);
+    header.extend_from_slice(&[0, 0, 0, 0, 0, 0x28, 0x77, 0x40]); // 370.5
+    assert_eq!(chunk[..header.len()], header);
+    assert!(dump::is_binary(&chunk));
+    assert!(!dump::is_binary(b"return"));
+}
+
+#[test]
+fn reject() {
+    let chunk = dump::dump(&compile("local t = {1, 'a'} return #t"), false);
+    let with = |i: usize, bytes: &[u8]| {
+        let mut c = chunk.clone();
+        c.splice(i..i + bytes.len(), bytes.iter().copied());
+        undump_error(&c)
+    };
+
+    assert_eq!(with(1, b"Lux"), "test: bad binary format (not a binary chunk)");
+    assert_eq!(with(4, &[0x53]), "test: bad binary format (version mismatch)");
+    assert_eq!(with(5, &[0]), "test: bad binary format (format mismatch)");
+    assert_eq!(with(6, &[2]), "test: bad binary format (format version mismatch)");
+    assert_eq!(with(9, b"\n"), "test: bad binary format (corrupted chunk)");
+    assert_eq!(with(13, &[8]), "test: bad binary format (Instruction size mismatch)");
+    assert_eq!(with(14, &[4]), "test: bad binary format (integer size mismatch)");
+    assert_eq!(with(15, &[4]), "test: bad binary format (float size mismatch)");
+
+    // dumped on big-endian hosts by naive writers
+    assert_eq!(with(16, &0x5678_i64.to_be_bytes()), "test: bad binary format (integer format mismatch)");
+    assert_eq!(with(24, &370.5_f64.to_be_bytes()), "test: bad binary format (float format mismatch)");
+
+    // truncated at any position
+    for len in 0..chunk.len() {
+        let err = undump_error(&chunk[..len]);
+        assert!(err == "test: bad binary format (truncated chunk)"
+            || (len == 0 && err.ends_with("(not a binary chunk)"))
+            || (len < 4 && err.ends_with("(truncated chunk)")), "{len}: {err}");
+    }
+
+    let mut extra = chunk.clone();
+    extra.push(0);
+    assert_eq!(undump_error(&extra), "test: bad binary format (extra bytes)");
+
+    // names of errors
+    let err = panic::catch_unwind(|| dump::undump(&chunk[..10], "@dir/file.luac")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(), "dir/file.luac: bad binary format (truncated chunk)");
+    let err = panic::catch_unwind(|| dump::undump(&chunk[..10], "\x1bLua")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(), "binary string: bad binary format (truncated chunk)");
+}
+
+// operands of byte codes are checked, same with ProtoBuilder
+#[test]
+fn operands() {
+    let with = |f: fn(&mut FuncProto)| {
+        let mut proto = compile("local a, b = ... if a then return b + 1 end");
+        f(&mut proto);
+        undump_error(&dump::dump(&proto, false))
+    };
+    assert_eq!(with(|p| p.byte_codes[0] = ByteCode::LoadConst(0, 9)),
+        "test: bad binary format (byte code 0: constant 9 out of range)");
+    assert_eq!(with(|p| p.max_stack_size = 1),
+        "test: bad binary format (byte code 0: register 1 out of stack size)");
+    assert_eq!(with(|p| p.byte_codes[1] = ByteCode::Jump(100)),
+        "test: bad binary format (byte code 1: jump to 102 out of range)");
+    assert_eq!(with(|p| p.lines.truncate(1)),
+        "test: bad binary format (byte code 5: lines mismatch)");
+    assert_eq!(with(|p| *p.byte_codes.last_mut().unwrap() = ByteCode::LoadNil(0, 1)),
+        "test: bad binary format (byte code 5: missing return at the end)");
+
+    // chunks with any byte changed are rejected, or run without panics
+    // of the VM, but Lua errors only
+    let chunk = dump::dump(&compile("local t = {} for i = 1, 3 do t[i] = i * 2 end \
+        local function f(...) return select('#', ...), ... end \
+        return f(table.unpack(t)), #t .. 'x'"), false);
+    for i in 32..chunk.len() {
+        for b in [0, 1, 0x7f, 0x80, 0xff] {
+            let mut c = chunk.clone();
+            c[i] ^= b;
+            let Ok(proto) = panic::catch_unwind(|| dump::undump(&c, "=test")) else {
+                continue;
+            };
+            let mut state = ExeState::new();
+            let env = state.globals().into();
+            let f = Value::LuaFunction(Rc::new(proto));
+            if let Err(LuaError::RuntimeError { value, .. }) =
+                    state.pcall_with_limit(f, &[env], ExecLimit::Instructions(10000)) {
+                let msg = value.to_string();
+                assert!(!msg.contains("out of bounds") && !msg.contains("out of range for slice"),
+                    "byte {i} ^ {b}: {msg}");
+            }
+        }
+    }
+}
+
+// huge counts from corrupted chunks fail without allocation
+#[test]
+fn huge_count() {
+    let chunk = dump::dump(&compile("return"), true);
+    // the header of 32 bytes, and source "", nparam, has_varargs and stack size
+    let mut c = chunk[..32 + 4].to_vec();
+    c.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+    assert_eq!(undump_error(&c), "test: bad binary format (truncated chunk)");
+
+    c.truncate(36);
+    c.extend_from_slice(&[0xff; 11]);
+    assert_eq!(undump_error(&c), "test: bad binary format (integer overflow)");
+}
+
+fn eval(source: &str) -> Vec<Value> {
+    ExeState::new().exec_main(&compile(source))
+}
+
+#[test]
+fn string_dump() {
+    let rets = eval(r#"
+        local function f(a, b) return a * b + 1 end
+        return string.dump(f), string.dump(f, true)
+    "#);
+    for (chunk, strip) in rets.iter().zip([false, true]) {
+        let chunk = chunk.as_bytes().unwrap();
+        assert!(dump::is_binary(chunk));
+        let proto = dump::undump(chunk, "=dumped");
+        assert_eq!(proto.nparam, 2);
+        assert_eq!(proto.locals.is_empty(), strip);
+
+        let f = Value::LuaFunction(Rc::new(proto));
+        let rets = ExeState::new().pcall(f, &[Value::Integer(3), Value::Integer(4)]).unwrap();
+        assert_eq!(rets, [Value::Integer(13)]);
+    }
+
+    // closures are dumped by their prototypes
+    let rets = eval("local up = 1; return string.dump(function() return up end)");
+    assert_eq!(dump::undump(rets[0].as_bytes().unwrap(), "=dumped").upindexes.len(), 1);
+
+    let err = panic::catch_unwind(|| eval("return string.dump(print)")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(), "test:1: unable to dump given function");
+    let err = panic::catch_unwind(|| eval("return string.dump({})")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(),
+        "test:1: bad argument #1 to 'dump' (function expected, got table)");
+}
+
+// `lua-rs -c`, the chunks are cached into files and run without parsing
+#[test]
+fn compile_cli() {
+    let dir = std::env::temp_dir().join(format!("lua-rs-dump-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (source, output) = (dir.join("hello.lua"), dir.join("hello.luac"));
+    fs::write(&source, "print('hello ' .. (arg[1] or 'world'))").unwrap();
+
+    let lua_rs = || std::process::Command::new(env!("CARGO_BIN_EXE_lua-rs"));
+    for strip in [None, Some("-s")] {
+        let status = lua_rs().arg("-c").args(strip).arg("-o").arg(&output).arg(&source)
+            .status().unwrap();
+        assert!(status.success());
+        let chunk = fs::read(&output).unwrap();
+        assert!(dump::is_binary(&chunk));
+        assert_eq!(dump::undump(&chunk, "=test").locals.is_empty(), strip.is_some());
+
+        let out = lua_rs().arg(&output).arg("lua").output().unwrap();
+        assert!(out.stdout.ends_with(b"hello lua\n")); // after the trace
+    }
+
+    // syntax errors
+    fs::write(&source, "print('hello").unwrap();
+    let out = lua_rs().arg("-c").arg("-o").arg(&output).arg(&source).output().unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unfinished string"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "readline")]
+
+use std::fs;
+use lua_rs::editor::LineEditor;
+
+fn no_completion() -> lua_rs::editor::Completer {
+    Box::new(|_| (0, Vec::new()))
+}
+
+#[test]
+fn history() {
+    let mut editor = LineEditor::new(no_completion(), None).unwrap();
+    editor.add_history("first");
+    editor.add_history("second");
+    editor.add_history("second");
+    editor.add_history("  ");
+    assert_eq!(editor.history(), ["first", "second"]);
+}
+
+#[test]
+fn history_file() {
+    let path = std::env::temp_dir().join(format!("lua_rs_history_{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut editor = LineEditor::new(no_completion(), Some(path.clone())).unwrap();
+    editor.add_history("x = 1");
+    editor.add_history("print(x)");
+
+    // appended once entered, so a new editor loads them
+    let mut editor2 = LineEditor::new(no_completion(), Some(path.clone())).unwrap();
+    assert_eq!(editor2.history(), ["x = 1", "print(x)"]);
+    editor2.add_history("y = 2");
+    editor.add_history("z = 3");
+
+    let editor = LineEditor::new(no_completion(), Some(path.clone())).unwrap();
+    assert_eq!(editor.history(), ["x = 1", "print(x)", "y = 2", "z = 3"]);
+    fs::remove_file(&path).unwrap();
+}
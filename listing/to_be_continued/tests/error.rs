@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::io;
+use std::panic;
+use lua_rs::error::LuaError;
+use lua_rs::parse;
+use lua_rs::value::{ConversionError, Value};
+use lua_rs::vm::ExeState;
+
+fn exec(source: &str) -> Vec<Value> {
+    ExeState::new().exec_main(&parse::load_named(source.as_bytes(), "=test"))
+}
+
+#[test]
+fn display() {
+    assert_eq!(LuaError::syntax("test.lua", 3, "unfinished string").to_string(),
+        "test.lua:3: unfinished string");
+    assert_eq!(LuaError::runtime("boom").to_string(), "boom");
+    assert_eq!(LuaError::runtime(true).to_string(), "(error object is a boolean value)");
+    assert_eq!(LuaError::MemoryError.to_string(), "not enough memory");
+}
+
+#[test]
+fn source() {
+    let e: LuaError = io::Error::new(io::ErrorKind::NotFound, "no file").into();
+    assert!(matches!(e, LuaError::IoError(_)));
+    assert_eq!(e.source().unwrap().to_string(), "no file");
+    assert!(LuaError::runtime("boom").source().is_none());
+
+    let e: LuaError = i32::try_from(Value::Float(1.5)).unwrap_err().into();
+    assert!(matches!(e, LuaError::TypeError(_)));
+    assert_eq!(e.to_string(), "number has no i32 representation");
+    assert!(e.source().is_some());
+}
+
+#[test]
+fn borrowed_strings() {
+    let v = Value::from("abc");
+    assert_eq!(<&str>::try_from(&v), Ok("abc"));
+    assert_eq!(<&[u8]>::try_from(&v), Ok(b"abc".as_slice()));
+    assert_eq!(<&str>::try_from(&Value::from(b"\xff".as_slice())), Err(ConversionError::Utf8));
+    assert_eq!(<&str>::try_from(&Value::Integer(1)).unwrap_err().to_string(),
+        "number can not be converted to &str");
+}
+
+// the Result versions of load() and exec_main()
+#[test]
+fn try_exec() {
+    let mut state = ExeState::new();
+    let err = state.try_load(b"x = 'abc", "=try").unwrap_err();
+    assert!(matches!(err, LuaError::SyntaxError { line: 1, .. }), "{err}");
+
+    let proto = state.try_load(b"local n = ... if n < 0 then error('negative') end return n * 2", "=try").unwrap();
+    let proto = std::rc::Rc::new(proto);
+    assert_eq!(state.try_exec_main(proto.clone(), &[Value::Integer(3)]).unwrap(), [Value::Integer(6)]);
+    let err = state.try_exec_main(proto.clone(), &[Value::Integer(-1)]).unwrap_err();
+    assert_eq!(err.to_string(), "try:1: negative");
+    let err = state.try_exec_main(proto.clone(), &[]).unwrap_err();
+    assert!(matches!(err, LuaError::RuntimeError { .. }), "{err}");
+
+    // still usable after errors
+    assert_eq!(state.try_exec_main(proto, &[Value::Integer(4)]).unwrap(), [Value::Integer(8)]);
+}
+
+#[test]
+#[should_panic(expected = r#"[string "?"]:3: unfinished string"#)]
+fn syntax_line() {
+    parse::load("local a = 1\n-- comment\nprint('hello\n".as_bytes());
+}
+
+#[test]
+fn chunk_names() {
+    let source = "local a = 1\n-- comment\nprint('hello\n";
+    for (name, msg) in [
+        ("@test_lua/x.lua", "test_lua/x.lua:3: unfinished string"),
+        ("=stdin", "stdin:3: unfinished string"),
+        (source, r#"[string "local a = 1..."]:3: unfinished string"#),
+    ] {
+        let e = panic::catch_unwind(|| parse::load_named(source.as_bytes(), name)).unwrap_err();
+        assert_eq!(e.downcast_ref::<String>().unwrap(), msg);
+    }
+
+    let proto = parse::load_named("return function() end".as_bytes(), "=chunk");
+    assert_eq!(proto.chunk_name, "=chunk");
+    let Value::LuaFunction(f) = &proto.constants[0] else {
+        panic!("function expected");
+    };
+    assert_eq!(f.chunk_name, "=chunk");
+}
+
+#[test]
+fn short_source() {
+    assert_eq!(parse::short_source("=stdin"), "stdin");
+    assert_eq!(parse::short_source("@a.lua"), "a.lua");
+    assert_eq!(parse::short_source("print(1)"), r#"[string "print(1)"]"#);
+    assert_eq!(parse::short_source("a = 1\nb = 2"), r#"[string "a = 1..."]"#);
+
+    // truncated as the official Lua
+    let long = "x".repeat(100);
+    assert_eq!(parse::short_source(&format!("={long}")), "x".repeat(59));
+    assert_eq!(parse::short_source(&format!("@{long}.lua")), format!("...{}.lua", "x".repeat(52)));
+    assert_eq!(parse::short_source(&long), format!("[string \"{}...\"]", "x".repeat(45)));
+    assert_eq!(parse::short_source(&"x".repeat(44)), format!("[string \"{}\"]", "x".repeat(44)));
+
+    // not split UTF-8 chars
+    assert_eq!(parse::short_source(&format!("={}", "é".repeat(40))), "é".repeat(29));
+}
+
+// pcall() and error(), with the positions by levels
+#[test]
+fn protected_call() {
+    let rets = exec(r#"
+        local function f(level) error("boom", level) end
+        local function g(level)
+            f(level)
+        end
+        local _, e1 = pcall(f)
+        local _, e2 = pcall(g, 2)
+        local _, e3 = pcall(g, 0)
+        local _, e4 = pcall(error, "from pcall")
+        local ok, e5 = pcall(function () local t = nil; return t.x end)
+        return e1, e2, e3, e4, e5, ok, pcall(function (a, b) return a + b end, 1, 2)
+    "#);
+    assert_eq!(rets, ["test:2: boom".into(), "test:4: boom".into(), "boom".into(),
+        "from pcall".into(), "test:10: attempt to index a nil value (local 't')".into(),
+        Value::Boolean(false), Value::Boolean(true), Value::Integer(3)]);
+
+    // error objects of any type, without positions
+    let rets = exec(r#"
+        local err = { code = 42 }
+        local _, e1 = pcall(error, err)
+        local _, e2 = pcall(function () error(err) end)
+        local _, e3 = pcall(error)
+        local _, e4 = pcall(function () error(12) end)
+        return e1 == err, e2 == err, e3, e4
+    "#);
+    assert_eq!(rets, [Value::Boolean(true), Value::Boolean(true), Value::Nil, Value::Integer(12)]);
+
+    // nested, through Rust functions and coroutines
+    let rets = exec(r#"
+        local _, e1 = pcall(function ()
+            table.sort({ 3, 2, 1 }, function () error("in sort") end)
+        end)
+        local ok, e2 = pcall(pcall, error, "nested")
+        local _, e3 = coroutine.resume(coroutine.create(function () error("in co") end))
+        return e1, ok, e2, e3
+    "#);
+    assert_eq!(rets, ["test:3: in sort".into(), Value::Boolean(true), Value::Boolean(false),
+        "test:6: in co".into()]);
+}
+
+#[test]
+fn message_handler() {
+    let rets = exec(r#"
+        local _, e1 = xpcall(function () error("E") end, function (m) return "handled: " .. m end)
+        local _, e2 = xpcall(error, function () error("again") end)
+        return e1, e2, xpcall(function (...) return ... end, print, 1, 2)
+    "#);
+    assert_eq!(rets, ["handled: test:2: E".into(), "error in error handling".into(),
+        Value::Boolean(true), Value::Integer(1), Value::Integer(2)]);
+}
+
+// errors raised by error() are returned to the host with their values
+#[test]
+fn host_error() {
+    let mut state = ExeState::new();
+    let f = Value::LuaFunction(parse::load(r#"error({ code = 1 })"#.as_bytes()).into());
+    let err = state.pcall(f, &[state.globals().into()]).unwrap_err();
+    assert_eq!(err.to_string(), "(error object is a table value)");
+    let LuaError::RuntimeError { value: Value::Table(t), .. } = err else {
+        panic!("table expected");
+    };
+    assert_eq!(t.borrow().index(&"code".into()), &Value::Integer(1));
+
+    // by Rust functions
+    state.globals().set("check", Value::RustFunction(|state| {
+        state.raise_error("invalid input".into(), 1)
+    }));
+    let f = Value::LuaFunction(parse::load_named("\ncheck()".as_bytes(), "=host").into());
+    let err = state.pcall(f, &[state.globals().into()]).unwrap_err();
+    assert_eq!(err.to_string(), "host:2: invalid input");
+}
+
+// the traceback of the Lua functions unwound by the error
+#[test]
+fn traceback() {
+    let mut state = ExeState::new();
+    let proto = state.try_load(br#"
+        local function inner(x)
+            return x.field
+        end
+        local t = {}
+        function t.outer() inner(nil) end
+        function run() t.outer() end
+        run()
+    "#, "=trace").unwrap();
+    let err = state.try_exec_main(std::rc::Rc::new(proto), &[]).unwrap_err();
+    let LuaError::RuntimeError { traceback, .. } = &err else {
+        panic!("runtime error expected: {err}");
+    };
+    assert_eq!(traceback, "stack traceback:\
+        \n\ttrace:3: in upvalue 'inner'\
+        \n\ttrace:6: in field 'outer'\
+        \n\ttrace:7: in function 'run'\
+        \n\ttrace:8: in main chunk");
+    assert_eq!(err.to_string(), "trace:3: attempt to index a nil value (local 'x')");
+    assert_eq!(format!("{err:#}"), format!("{err}\n{traceback}"));
+
+    // through Rust functions, and only the innermost ones of deep calls
+    let proto = state.try_load(br#"
+        local function f(n)
+            if n == 0 then error("deep") end
+            f(n - 1)
+        end
+        table.sort({1, 2}, function() f(30) end)
+    "#, "=deep").unwrap();
+    let err = state.try_exec_main(std::rc::Rc::new(proto), &[]).unwrap_err();
+    let LuaError::RuntimeError { traceback, .. } = &err else {
+        panic!("runtime error expected: {err}");
+    };
+    let lines: Vec<&str> = traceback.lines().collect();
+    assert_eq!(lines.len(), 22);
+    assert_eq!(lines[1], "\tdeep:3: in upvalue 'f'");
+    assert_eq!(lines[2], "\tdeep:4: in upvalue 'f'");
+    assert_eq!(lines[21], "\t...\t(skipping 13 levels)");
+
+    // not by the host
+    assert_eq!(format!("{:#}", LuaError::runtime("boom")), "boom");
+}
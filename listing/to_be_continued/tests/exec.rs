@@ -0,0 +1,90 @@
+use lua_rs::error::LuaError;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+#[test]
+fn return_values() {
+    let mut state = ExeState::new();
+    let rets = state.exec_file("test_lua/mod/config.lua").unwrap();
+    assert_eq!(rets.len(), 2);
+    assert_eq!(rets[0].index(&"name".into()), Value::from("server"));
+    assert_eq!(rets[0].index(&"port".into()), Value::Integer(8080));
+    assert_eq!(rets[1], Value::from("v1"));
+
+    // no return values
+    let rets = state.exec_file("test_lua/mod/noreturn.lua").unwrap();
+    assert!(rets.is_empty());
+}
+
+#[test]
+fn tail_call_main() {
+    let mut state = ExeState::new();
+    state.exec_main(&parse::load(&b"function id(n) return n end"[..]));
+
+    // the tail call drains the main chunk's frame, but the state
+    // should still be usable after
+    assert_eq!(state.exec_main(&parse::load(&b"return id(3)"[..])), [Value::Integer(3)]);
+    assert_eq!(state.exec_main(&parse::load(&b"return id(4)"[..])), [Value::Integer(4)]);
+}
+
+#[test]
+fn no_file() {
+    let mut state = ExeState::new();
+    let err = state.exec_file("test_lua/mod/nonexist.lua").unwrap_err();
+    assert!(matches!(err, LuaError::IoError(_)));
+}
+
+#[derive(Debug, PartialEq)]
+struct ServerConfig {
+    name: String,
+    port: i64,
+}
+
+impl TryFrom<Value> for ServerConfig {
+    type Error = String;
+    fn try_from(t: Value) -> Result<Self, String> {
+        let Value::Integer(port) = t.index(&"port".into()) else {
+            return Err("invalid port".into());
+        };
+        let name = t.index(&"name".into());
+        let name = name.as_str().ok_or("invalid name")?.to_string();
+        Ok(ServerConfig { name, port })
+    }
+}
+
+#[test]
+fn eval_config() {
+    let config: ServerConfig = ExeState::eval_config("test_lua/mod/server_config.lua").unwrap();
+    assert_eq!(config, ServerConfig { name: "server".into(), port: 8080 });
+
+    let err = ExeState::eval_config::<ServerConfig>("test_lua/mod/bad_config.lua").unwrap_err();
+    assert_eq!(err.to_string(), "invalid port");
+
+    let err = ExeState::eval_config::<ServerConfig>("test_lua/mod/empty_config.lua").unwrap_err();
+    assert_eq!(err.to_string(), "config should return a table, got no value");
+}
+
+#[test]
+fn restricted_config() {
+    let err = ExeState::eval_config::<ServerConfig>("test_lua/mod/print_config.lua").unwrap_err();
+    let LuaError::RuntimeError { value, .. } = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert!(value.as_str().unwrap().ends_with("attempt to call a nil value (global 'print')"), "{value}");
+}
+
+#[test]
+fn errors() {
+    let err = ExeState::new().exec_file("test_lua/mod/syntax_error.lua").unwrap_err();
+    assert!(matches!(err, LuaError::SyntaxError { line: 3, .. }), "{err}");
+
+    // the state is still usable after the runtime error
+    let mut state = ExeState::new();
+    let err = state.exec_file("test_lua/mod/runtime_error.lua").unwrap_err();
+    assert!(matches!(err, LuaError::RuntimeError { .. }), "{err}");
+    assert_eq!(state.exec_file("test_lua/mod/config.lua").unwrap().len(), 2);
+
+    let err = ExeState::eval_config::<ServerConfig>("test_lua/mod/syntax_error.lua").unwrap_err();
+    assert!(matches!(err, LuaError::SyntaxError { .. }), "{err}");
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "fs")]
+
+mod common;
+
+use std::fs;
+use std::panic;
+use lua_rs::value::Value;
+use common::eval;
+
+#[test]
+fn directories() {
+    let dir = std::env::temp_dir().join(format!("lua-rs-fs-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let dir = dir.to_str().unwrap();
+
+    let rets = eval(format!(r#"
+        local dir = {dir:?}
+        local ok = fs.mkdir(dir)
+        local _, err = fs.mkdir(dir)
+        fs.mkdir(dir .. "/sub")
+        local f = io.open(dir .. "/a.txt", "w")
+        f:write("12345")
+        f:close()
+
+        local names = {{}}
+        for name in fs.dir(dir) do names[#names+1] = name end
+        table.sort(names)
+
+        local attr = fs.attributes(dir .. "/a.txt")
+        return ok, err, table.concat(names, ","), attr.mode, attr.size,
+            fs.attributes(dir .. "/sub", "mode"), math.type(attr.modification),
+            fs.rmdir(dir .. "/sub"), fs.attributes(dir .. "/sub")
+    "#));
+    assert_eq!(rets[..9], [Value::Boolean(true), format!("{dir}: File exists").into(),
+        ".,..,a.txt,sub".into(), "file".into(), Value::Integer(5), "directory".into(),
+        "integer".into(), Value::Boolean(true), Value::Nil]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn currentdir() {
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(eval("return fs.currentdir()"), [cwd.to_str().unwrap().into()]);
+}
+
+#[test]
+fn errors() {
+    let err = panic::catch_unwind(|| eval("for name in fs.dir('/no/such/dir') do end")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(),
+        "[string \"?\"]:1: cannot open /no/such/dir: No such file or directory");
+    let err = panic::catch_unwind(|| eval("return fs.attributes('.', 'color')")).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(),
+        "[string \"?\"]:1: bad argument #2 to 'attributes' (invalid attribute name 'color')");
+}
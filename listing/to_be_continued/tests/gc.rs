@@ -0,0 +1,93 @@
+use std::rc::Rc;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn cycles() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, "
+        local a, b = {}, {}
+        a.b = b; b.a = a
+        local t = {}
+        t.f = function() return t end
+        return a, t
+    ");
+    let (Value::Table(a), Value::Table(t)) = (&rets[0], &rets[1]) else {
+        panic!("{rets:?}");
+    };
+    let (a, t) = (Rc::downgrade(a), Rc::downgrade(t));
+
+    // referred by the results
+    state.collect_garbage();
+    assert!(a.upgrade().is_some());
+    assert!(t.upgrade().is_some());
+
+    // table a and b, table t, its closure and the upvalue
+    drop(rets);
+    assert!(a.upgrade().is_some());
+    assert_eq!(state.collect_garbage(), 5);
+    assert!(a.upgrade().is_none());
+    assert!(t.upgrade().is_none());
+    assert_eq!(state.stats().gc_cycles, 2);
+}
+
+#[test]
+fn reachable() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, "
+        g = {}
+        g.self = g
+        local n = 0
+        function counter() n = n + 1; return n end
+        local t = {}
+        for i = 1, 100 do t[i] = {i, t} end
+        for i = 1, 3 do collectgarbage() end
+        counter()
+        return #g.self.self == 0, counter(), t[100][1], t[50][2] == t
+    ");
+    assert_eq!(rets, [Value::Boolean(true), Value::Integer(2), Value::Integer(100),
+        Value::Boolean(true)]);
+}
+
+#[test]
+fn automatic() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, "
+        local before = vmstats().gc_cycles
+        for i = 1, 10000 do
+            local a, b = {}, {}
+            a.b = b; b.a = a
+        end
+        return vmstats().gc_cycles > before
+    ");
+    assert_eq!(rets, [Value::Boolean(true)]);
+
+    let rets = exec(&mut state, "
+        collectgarbage('stop')
+        local running = collectgarbage('isrunning')
+        local before = vmstats().gc_cycles
+        for i = 1, 10000 do local t = {} end
+        local after = vmstats().gc_cycles
+        collectgarbage('restart')
+        return running, after - before, collectgarbage('isrunning')
+    ");
+    assert_eq!(rets, [Value::Boolean(false), Value::Integer(0), Value::Boolean(true)]);
+}
+
+#[test]
+fn options() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, "
+        return collectgarbage(), collectgarbage('step'), collectgarbage('count') >= 0
+    ");
+    assert_eq!(rets, [Value::Integer(0), Value::Boolean(true), Value::Boolean(true)]);
+
+    let rets = exec(&mut state, "return pcall(collectgarbage, 'full')");
+    assert_eq!(rets, [Value::Boolean(false),
+        Value::from("bad argument #1 to 'collectgarbage' (invalid option 'full')")]);
+}
@@ -0,0 +1,69 @@
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn get_set_remove() {
+    let mut state = ExeState::new();
+    let globals = state.globals();
+    globals.set("limit", 10);
+    globals.set(1, "first");
+    exec(&mut state, "count = limit * 2; removed = true");
+
+    assert_eq!(globals.get("count"), Value::Integer(20));
+    assert_eq!(globals.get(1), "first".into());
+    assert!(globals.contains_key("removed"));
+    assert_eq!(globals.remove("removed"), Value::Boolean(true));
+    assert!(!globals.contains_key("removed"));
+    assert_eq!(globals.remove("removed"), Value::Nil);
+    assert_eq!(exec(&mut state, "return removed"), [Value::Nil]);
+
+    // assigning nil removes too
+    let n = globals.len();
+    globals.set("count", ());
+    assert_eq!(globals.len(), n - 1);
+}
+
+// find and call the test functions defined by a script
+#[test]
+fn iterate() {
+    let mut state = ExeState::new();
+    exec(&mut state, r#"
+        results = {}
+        function test_a() results.a = true end
+        function test_b() results.b = true; test_c = function() end end
+        function helper() end
+        not_test = 1
+    "#);
+
+    let globals = state.globals();
+    let mut names = Vec::new();
+    for (k, v) in &globals {
+        let Some(name) = k.as_str().filter(|k| k.starts_with("test_")) else {
+            continue;
+        };
+        // the iteration is over a snapshot, so calling is fine
+        state.pcall(v, &[]).unwrap();
+        names.push(name.to_string());
+    }
+    names.sort();
+    assert_eq!(names, ["test_a", "test_b"]);
+    assert!(globals.contains_key("test_c"));
+    assert_eq!(exec(&mut state, "return results.a, results.b"),
+        [Value::Boolean(true), Value::Boolean(true)]);
+}
+
+#[test]
+fn array_entries() {
+    // the array part is iterated first, in order
+    let state = ExeState::new();
+    let globals = state.globals();
+    globals.set(1, "a");
+    globals.set(2, "b");
+    let entries: Vec<(Value, Value)> = globals.iter().take(2).collect();
+    assert_eq!(entries, [(Value::Integer(1), "a".into()), (Value::Integer(2), "b".into())]);
+}
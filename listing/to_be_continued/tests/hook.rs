@@ -0,0 +1,82 @@
+use std::io;
+use std::rc::Rc;
+use std::cell::RefCell;
+use lua_rs::parse;
+use lua_rs::vm::{ExeState, ExecLimit};
+
+// build a state which records the events
+fn recorder() -> (ExeState, Rc<RefCell<Vec<String>>>) {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let state = ExeState::builder()
+        .output(io::sink())
+        .hook({
+            let events = events.clone();
+            move |e| events.borrow_mut().push(format!("{e:?}"))
+        })
+        .build();
+    (state, events)
+}
+
+#[test]
+fn load_and_call() {
+    let (mut state, events) = recorder();
+    state.exec_file("test_lua/mod/noreturn.lua").unwrap();
+    assert_eq!(*events.borrow(), [
+        r#"Load { chunk: "test_lua/mod/noreturn.lua" }"#,
+        "Call { depth: 1 }", // the main chunk
+        "Call { depth: 2 }", // print()
+        "Return { depth: 2, nret: 0 }",
+        "Return { depth: 1, nret: 0 }",
+    ]);
+}
+
+#[test]
+fn caught_error() {
+    let (mut state, events) = recorder();
+    let proto = parse::load(&b"
+        return function()
+            local function inner(f) f() end
+            inner(print)
+            inner(nil)
+        end
+    "[..]);
+    let f = state.exec_main(&proto).remove(0);
+    state.pcall_with_limit(f, &[], ExecLimit::Instructions(1000)).unwrap_err();
+    assert_eq!(*events.borrow(), [
+        "Call { depth: 1 }",
+        "Call { depth: 2 }",
+        "Call { depth: 3 }",
+        "Return { depth: 3, nret: 0 }",
+        "Return { depth: 2, nret: 0 }",
+        "Call { depth: 2 }",
+        r#"Error { depth: 2, message: "[string \"?\"]:3: attempt to call a nil value (local 'f')" }"#,
+    ]);
+}
+
+#[test]
+fn uncaught_error() {
+    let (mut state, events) = recorder();
+    let proto = parse::load(&b"local t = nil; t()"[..]);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| state.exec_main(&proto)));
+    assert!(result.is_err());
+    assert_eq!(*events.borrow(), [
+        r#"Error { depth: 0, message: "[string \"?\"]:1: attempt to call a nil value (local 't')" }"#,
+    ]);
+}
+
+#[test]
+fn gc() {
+    let (mut state, events) = recorder();
+    let proto = parse::load(&b"
+        local a, b = {}, {}
+        a.b, b.a = b, a
+        a, b = nil, nil
+        collectgarbage()
+    "[..]);
+    state.exec_main(&proto);
+    assert_eq!(*events.borrow(), [
+        "Call { depth: 1 }", // collectgarbage()
+        "Gc { collected: 2 }",
+        "Return { depth: 1, nret: 1 }",
+    ]);
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use lua_rs::value::Value;
+use common::eval;
+
+// Serve one connection with @response, and return the port and the
+// handle to join for the request received.
+fn serve(response: &'static str) -> (u16, thread::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut r = BufReader::new(&stream);
+        let mut request = String::new();
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            if let Some(n) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                len = n.trim().parse().unwrap();
+            }
+            request += &line;
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        r.read_exact(&mut body).unwrap();
+        request += &String::from_utf8(body).unwrap();
+        (&stream).write_all(response.as_bytes()).unwrap();
+        request
+    });
+    (port, handle)
+}
+
+#[test]
+fn get() {
+    let (port, server) = serve("HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A: 1\r\nx-a: 2\r\n\r\nhello");
+    let rets = eval(format!(r#"
+        local resp = http.get("http://127.0.0.1:{port}/path?q=1", {{ headers = {{ Accept = "text/plain" }} }})
+        return resp.status, resp.body, resp.headers["x-a"], resp.headers["content-length"]
+    "#));
+    assert_eq!(rets, [Value::Integer(200), "hello".into(), "1, 2".into(), "5".into()]);
+    let request = server.join().unwrap();
+    assert!(request.starts_with("GET /path?q=1 HTTP/1.1\r\n"), "{request}");
+    assert!(request.contains(&format!("host: 127.0.0.1:{port}\r\n")), "{request}");
+    assert!(request.contains("accept: text/plain\r\n"), "{request}");
+}
+
+#[test]
+fn post_chunked() {
+    let (port, server) = serve("HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+        4\r\nnot \r\n5;x=y\r\nfound\r\n0\r\n\r\n");
+    let rets = eval(format!(r#"
+        local resp = http.post("http://127.0.0.1:{port}/hook", '{{"ok":true}}',
+            {{ headers = {{ ["Content-Type"] = "application/json" }}, timeout = 5 }})
+        return resp.status, resp.body
+    "#));
+    assert_eq!(rets, [Value::Integer(404), "not found".into()]);
+    let request = server.join().unwrap();
+    assert!(request.starts_with("POST /hook HTTP/1.1\r\n"), "{request}");
+    assert!(request.contains("content-type: application/json\r\n"), "{request}");
+    assert!(request.ends_with("\r\n\r\n{\"ok\":true}"), "{request}");
+}
+
+#[test]
+fn request() {
+    let (port, server) = serve("HTTP/1.1 204 No Content\r\n\r\n");
+    let rets = eval(format!(r#"
+        local resp = http.request {{ url = "http://localhost:{port}", method = "delete" }}
+        return resp.status, resp.body
+    "#));
+    assert_eq!(rets, [Value::Integer(204), "".into()]);
+    assert!(server.join().unwrap().starts_with("DELETE / HTTP/1.1\r\n"));
+}
+
+#[test]
+fn failures() {
+    // a port not listening
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let rets = eval(format!("return http.get('http://127.0.0.1:{port}/')"));
+    assert_eq!(rets[0], Value::Nil);
+    assert!(rets[1].to_string().starts_with("io: "), "{}", rets[1]);
+
+    assert_eq!(eval("return http.get('ftp://example.com/')"),
+        [Value::Nil, "bad uri: unknown scheme: ftp".into()]);
+    assert_eq!(eval("return http.get('example.com')"),
+        [Value::Nil, "bad uri: example.com is missing scheme".into()]);
+}
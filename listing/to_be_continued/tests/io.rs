@@ -0,0 +1,172 @@
+mod common;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::{eval, error};
+
+// a path in the temporary directory, unique for each test
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lua-rs-io-{}-{name}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn write_read() {
+    let path = temp_path("write_read");
+    let rets = eval(format!(r#"
+        local name = {:?}
+        local f = io.open(name, "w")
+        local same = f:write("first line\n", 42, " ", 1.5, "\n") == f
+        f:write("last")
+        f:close()
+
+        f = io.open(name)
+        local a, b, c, d, e = f:read("l", "n", "n", "L", "a")
+        local eof, eof_line = f:read("a", "l")
+        f:close()
+        return same, a, b, c, d, e, eof, eof_line
+    "#, path.to_str().unwrap()));
+    assert_eq!(rets, [Value::Boolean(true), "first line".into(), Value::Integer(42), Value::Float(1.5),
+        "\n".into(), "last".into(), "".into(), Value::Nil]);
+    assert_eq!(fs::read(&path).unwrap(), b"first line\n42 1.5\nlast");
+
+    // counts, and appending
+    let rets = eval(format!(r#"
+        local name = {:?}
+        local f = io.open(name, "a")
+        f:write("\nmore")
+        f:close()
+        f = io.open(name, "rb")
+        local a, b, c = f:read(5, "*l", 0)
+        local rest = f:read("*a")
+        local eof, zero = f:read(0), f:read(3)
+        f:close()
+        return a, b, c, rest, eof, zero
+    "#, path.to_str().unwrap()));
+    assert_eq!(rets, ["first".into(), " line".into(), "".into(), "42 1.5\nlast\nmore".into(),
+        Value::Nil, Value::Nil]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn read_numbers() {
+    let path = temp_path("read_numbers");
+    fs::write(&path, "12.5xyz 0x1p4\n-3e2 0x10 .5 1e+ abc").unwrap();
+    let rets = eval(format!(r#"
+        local f = io.open({:?})
+        local t = {{}}
+        t[1] = f:read("n")
+        t[2] = f:read(3)
+        for i = 3, 7 do t[i] = f:read("n") end
+        t[8] = f:read("n")
+        t[9] = f:read("a")
+        f:close()
+        return table.unpack(t, 1, 9)
+    "#, path.to_str().unwrap()));
+    assert_eq!(rets, [Value::Float(12.5), "xyz".into(), Value::Float(16.0), Value::Float(-300.0),
+        Value::Integer(16), Value::Float(0.5), Value::Nil, Value::Nil, "abc".into()]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn lines() {
+    let path = temp_path("lines");
+    fs::write(&path, "a\nbb\n\nccc").unwrap();
+    let rets = eval(format!(r#"
+        local name = {:?}
+        local t = {{}}
+        for l in io.lines(name) do t[#t+1] = l end
+        for l in io.lines(name, "L") do t[#t+1] = l end
+        for a, b in io.lines(name, 1, 2) do t[#t+1] = a .. "|" .. b end
+
+        local f = io.open(name)
+        local n = 0
+        for l in f:lines() do n = n + 1 end
+        local still_open = io.type(f)
+        f:close()
+        return table.concat(t, ","), n, still_open, io.type(f), io.type(42)
+    "#, path.to_str().unwrap()));
+    assert_eq!(rets, ["a,bb,,ccc,a\n,bb\n,\n,ccc,a|\nb,b|\n\n,c|cc".into(), Value::Integer(4),
+        "file".into(), "closed file".into(), Value::Nil]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn seek() {
+    let path = temp_path("seek");
+    let rets = eval(format!(r#"
+        local f = io.open({:?}, "w+")
+        f:write("0123456789")
+        local size = f:seek("end")
+        local start = f:seek("set")
+        local a = f:read(3)
+        local cur = f:seek() -- after the read-ahead is discarded
+        f:seek("cur", 2)
+        local b = f:read(1)
+        f:seek("set", 1)
+        f:write("x")
+        f:seek("set")
+        local all = f:read("a")
+        f:close()
+        return size, start, a, cur, b, all
+    "#, path.to_str().unwrap()));
+    assert_eq!(rets, [Value::Integer(10), Value::Integer(0), "012".into(), Value::Integer(3),
+        "5".into(), "0x23456789".into()]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// io.write() and print() share the output of the state, in order
+#[test]
+fn stdout() {
+    let sink = Sink::default();
+    let mut state = ExeState::builder().output(sink.clone()).build();
+    let proto = parse::load(br#"
+        io.write("a", 1, "\n")
+        print("b")
+        io.stdout:write("c"):write("d\n")
+        print(io.write() == io.stdout)
+    "#.as_slice());
+    state.execute(&proto, &[]);
+    assert_eq!(&*sink.0.borrow(), b"a1\nb\ncd\ntrue\n");
+}
+
+#[test]
+fn errors() {
+    let path = temp_path("errors");
+    let name = path.to_str().unwrap();
+    assert_eq!(eval(format!("return io.open({name:?})")),
+        [Value::Nil, format!("{name}: No such file or directory").into(), Value::Integer(2)]);
+    assert_eq!(error(format!("io.open({name:?}, 'rw')")),
+        "[string \"?\"]:1: bad argument #2 to 'open' (invalid mode)");
+    assert_eq!(error(format!("for l in io.lines({name:?}) do end")),
+        format!("[string \"?\"]:1: {name}: No such file or directory"));
+    assert_eq!(error(format!("local f = io.open({name:?}, 'w') f:close() f:read()")),
+        "[string \"?\"]:1: attempt to use a closed file");
+    assert_eq!(error("io.stdout:write({})"),
+        "[string \"?\"]:1: bad argument #1 to 'write' (string expected, got table)");
+    assert_eq!(error("io.stdout.read(42)"),
+        "[string \"?\"]:1: bad argument #1 to 'read' (file expected, got number)");
+    assert_eq!(eval("return io.stdout:close()"),
+        [Value::Nil, "cannot close standard file".into()]);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,64 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn generic_for() {
+    let mut state = ExeState::new();
+    let names = state.create_iterator(["a", "b", "c"]);
+    state.globals().set("names", names);
+    let squares = state.create_iterator((1..=10i64).map(|i| i * i));
+    state.globals().set("squares", squares);
+
+    assert_eq!(exec(&mut state, "
+        local s = ''
+        for name in names do s = s .. name end
+        local sum = 0
+        for n in squares do sum = sum + n end
+        return s, sum
+    "), ["abc".into(), Value::Integer(385)]);
+
+    // consumed
+    assert_eq!(exec(&mut state, "for n in squares do return n end return 'end'"), ["end".into()]);
+}
+
+// items are converted when the loop asks, so infinite iterators work,
+// and the iterator is dropped after the loop breaks out
+#[test]
+fn lazy() {
+    let mut state = ExeState::new();
+    let pulled = Rc::new(Cell::new(0));
+    let counter = pulled.clone();
+    let iter = (1..).inspect(move |_: &i64| counter.set(counter.get() + 1));
+    let naturals = state.create_iterator(iter);
+    state.globals().set("naturals", naturals);
+
+    assert_eq!(exec(&mut state, "
+        for i in naturals do
+            if i == 5 then return i end
+        end
+    "), [Value::Integer(5)]);
+    assert_eq!(pulled.get(), 5);
+
+    // continued from the last position
+    assert_eq!(exec(&mut state, "return naturals(), naturals()"), [Value::Integer(6), Value::Integer(7)]);
+
+    state.globals().set("naturals", ());
+    assert_eq!(Rc::strong_count(&pulled), 1);
+}
+
+// nil items end the loop
+#[test]
+fn nil_items() {
+    let mut state = ExeState::new();
+    let iter = state.create_iterator(vec![Some(1i64), None, Some(3)]);
+    state.globals().set("iter", iter);
+    assert_eq!(exec(&mut state, "local n = 0 for _ in iter do n = n + 1 end return n, iter()"),
+        [Value::Integer(1), Value::Integer(3)]);
+}
@@ -0,0 +1,130 @@
+// Regression tests of the lexer on pathological inputs, some of which
+// are found by fuzzing. All of them should raise syntax errors by
+// `Lex::syntax_error()`, but no other panics.
+
+mod common;
+
+use std::fs;
+use std::panic;
+use std::time::Instant;
+use lua_rs::lex::{Lex, Token};
+use lua_rs::parse;
+use lua_rs::value::Value;
+use common::eval;
+
+// load @source and return the error message
+fn syntax_error(source: impl AsRef<[u8]>) -> String {
+    let source = source.as_ref();
+    let e = panic::catch_unwind(|| parse::load_named(source, "=input")).unwrap_err();
+    match e.downcast::<String>() {
+        Ok(s) => *s,
+        Err(e) => panic!("not syntax error: {:?}", e.downcast_ref::<&str>()),
+    }
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(syntax_error(r#"s = "\x"#), "input:1: hexadecimal digit expected");
+    assert_eq!(syntax_error(r#"s = "\xf<""#), "input:1: hexadecimal digit expected");
+    assert_eq!(syntax_error(r#"s = "\526""#), "input:1: decimal escape too large");
+    assert_eq!(syntax_error("s = \"\\"), "input:1: unfinished string");
+    assert_eq!(syntax_error(r#"s = "\q""#), "input:1: invalid string escape");
+    assert_eq!(eval(r#"return "\x41\65\0666\255""#), [Value::from(&b"AAB6\xff"[..])]);
+
+    // escaped newlines, where "\r\n" is one, and the lines are counted
+    assert_eq!(eval("return \"a\\\nb\\\r\nc\""), [Value::from("a\nb\nc")]);
+    assert_eq!(syntax_error("s = \"a\\\n\\q\""), "input:2: invalid string escape");
+}
+
+#[test]
+fn long_brackets() {
+    assert_eq!(eval("return [[\nab]], [==[a]]b]=]c]==], [=[]]=]"),
+        [Value::from("ab"), Value::from("a]]b]=]c"), Value::from("]")]);
+    assert_eq!(eval("--[[ long\ncomment ]] return 1 --[==[ ]] ]==]"), [Value::Integer(1)]);
+    assert_eq!(eval("--[ line comment\n--[= line comment\nreturn 2"), [Value::Integer(2)]);
+
+    assert_eq!(syntax_error("s = [==[ abc\n]=]"), "input:2: unfinished long string near <eof>");
+    assert_eq!(syntax_error("--[[ The Comp"), "input:1: unfinished long comment near <eof>");
+    assert_eq!(syntax_error("s = [=x"), "input:1: invalid long string delimiter");
+
+    let deep = format!("s = [{}[ ]]", "=".repeat(300));
+    assert_eq!(syntax_error(deep), "input:1: long bracket level too deep");
+}
+
+#[test]
+fn long_inputs() {
+    // blanks and comments are skipped without recursion
+    let blanks = " ".repeat(1 << 20) + &"-- comment\n".repeat(1 << 16) + "return 1";
+    assert_eq!(eval(blanks), [Value::Integer(1)]);
+
+    // megabyte-long string literal and long line
+    let s = "x".repeat(1 << 20);
+    assert_eq!(eval(format!("return '{s}', [[{s}]]")), [Value::from(&s[..]), Value::from(&s[..])]);
+
+    let name = "a".repeat((1 << 24) + 1);
+    assert_eq!(syntax_error(format!("{name} = 1")), "input:1: lexical element too long");
+}
+
+#[test]
+fn unfinished() {
+    assert_eq!(syntax_error("s = 'abc"), "input:1: unfinished string");
+    assert_eq!(syntax_error("a = 1\nb = 0x"), "input:2: malformed number near '0x'");
+    assert_eq!(syntax_error("a = 1e+"), "input:1: malformed number near '1e+'");
+    assert_eq!(syntax_error("a = 1 @"), "input:1: invalid char 64");
+}
+
+#[test]
+fn names() {
+    // names prefixed or suffixed by keywords
+    assert_eq!(eval("local andy, do_, _end, If = 1, 2, 3, 4 return andy + do_ + _end + If"),
+        [Value::Integer(10)]);
+
+    // repeated names are interned, and each occurrence is resolved
+    let incs = "x = x + 1 ".repeat(1000);
+    assert_eq!(eval(format!("local x = 0 {incs} return x")), [Value::Integer(1000)]);
+
+    // many distinct globals
+    let sets: String = (0..1000).map(|i| format!("v{i} = {i} ")).collect();
+    assert_eq!(eval(format!("{sets} return v0 + v999")), [Value::Integer(999)]);
+}
+
+// Throughput of the lexer on a multi-megabyte corpus of the test
+// scripts, and of the lexer and the parser on a data-style file. Run
+// it in release mode:
+//
+//     cargo test --release --test lex -- --ignored --nocapture
+#[test]
+#[ignore]
+fn throughput() {
+    let mut scripts = String::new();
+    for entry in fs::read_dir("test_lua").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lua") {
+            scripts += &fs::read_to_string(path).unwrap();
+            scripts.push('\n');
+        }
+    }
+    let scripts = scripts.repeat((8 << 20) / scripts.len() + 1);
+
+    let mut data = String::from("return {\n");
+    for i in 0..100000 {
+        data += &format!("  {{ id = {i}, name = \"item{i}\", weight = {i}.5, tags = {{ \"a\", \"b\" }} }},\n");
+    }
+    data += "}\n";
+
+    let mb = |s: &str| s.len() as f64 / (1 << 20) as f64;
+    for (what, corpus) in [("scripts", &scripts), ("data", &data)] {
+        let start = Instant::now();
+        let mut lex = Lex::new(corpus.as_bytes(), what.into());
+        let mut ntoken = 0;
+        while lex.next() != Token::Eos {
+            ntoken += 1;
+        }
+        println!("lex {what}: {:.1} MB, {ntoken} tokens, {:.0} MB/s",
+            mb(corpus), mb(corpus) / start.elapsed().as_secs_f64());
+    }
+
+    let start = Instant::now();
+    parse::load(data.as_bytes());
+    println!("parse data: {:.0} MB/s", mb(&data) / start.elapsed().as_secs_f64());
+}
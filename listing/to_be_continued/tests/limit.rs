@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::{ExeState, ExecLimit};
+
+// return the function defined by @source
+fn load_function(state: &mut ExeState, source: &str) -> Value {
+    let proto = parse::load(source.as_bytes());
+    state.exec_main(&proto).remove(0)
+}
+
+#[test]
+fn instruction_limit() {
+    let mut state = ExeState::new();
+    let f = load_function(&mut state, "
+        return function(n)
+            local s = 0
+            for i = 1, n do s = s + i end
+            return s, n
+        end
+    ");
+
+    let rets = state.pcall_with_limit(f.clone(), &[Value::Integer(10)],
+        ExecLimit::Instructions(1000)).unwrap();
+    assert_eq!(rets, vec![Value::Integer(55), Value::Integer(10)]);
+
+    let err = state.pcall_with_limit(f.clone(), &[Value::Integer(1000)],
+        ExecLimit::Instructions(1000)).unwrap_err();
+    assert_eq!(err.to_string(), "instruction limit exceeded");
+
+    // the state is still usable
+    let rets = state.pcall_with_limit(f, &[Value::Integer(100)],
+        ExecLimit::Instructions(1000)).unwrap();
+    assert_eq!(rets[0], Value::Integer(5050));
+}
+
+#[test]
+fn time_limit() {
+    let mut state = ExeState::new();
+    let f = load_function(&mut state, "return function() while true do end end");
+
+    let start = Instant::now();
+    let err = state.pcall_with_limit(f, &[], ExecLimit::Time(Duration::from_millis(50)))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "time limit exceeded");
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn os_timelimit() {
+    let mut state = ExeState::new();
+    state.open_timelimit();
+    let f = load_function(&mut state, r#"
+        return function()
+            local ok1, a, b = os.timelimit(function(x) return x, x + 1 end, 1, 10)
+            local ok2, msg = os.timelimit(function() while true do end end, 0.05)
+            local ok3, c = os.timelimit(function() return 3 end, 1e300)
+            local e = {}
+            local ok4, err = os.timelimit(function() error(e) end, 1)
+            return ok1, a, b, ok2, msg, ok3, c, ok4, err == e
+        end
+    "#);
+
+    let rets = state.pcall_with_limit(f, &[], ExecLimit::Instructions(u64::MAX)).unwrap();
+    assert_eq!(rets, vec![
+        Value::Boolean(true), Value::Integer(10), Value::Integer(11),
+        Value::Boolean(false), Value::from("time limit exceeded"),
+        Value::Boolean(true), Value::Integer(3),
+        Value::Boolean(false), Value::Boolean(true),
+    ]);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+use lua_rs::dump;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::eval;
+
+#[test]
+fn load_string() {
+    assert_eq!(eval(r#"
+        local f = load("return 1 + ...")
+        local g = load("return x", "chunk", "t", {x = "env x"})
+        x = 1
+        load("x = x + 1")()
+        return f(41), g(), x, loadstring("return 2 * 21")()
+    "#), [Value::Integer(42), "env x".into(), Value::Integer(2), Value::Integer(42)]);
+
+    // the pieces of the reader function
+    assert_eq!(eval(r#"
+        local parts = {"return ", "'pie", "ces'"}
+        local i = 0
+        return load(function() i = i + 1 return parts[i] end)()
+    "#), ["pieces".into()]);
+
+    // syntax errors
+    assert_eq!(eval("local f, msg = load(\"x = 'abc\") return f, msg"),
+        [Value::Nil, "[string \"x = 'abc\"]:1: unfinished string".into()]);
+    assert_eq!(eval("local f, msg = loadstring(\"x = 'abc\", '=name') return f, msg"),
+        [Value::Nil, "name:1: unfinished string".into()]);
+    assert_eq!(eval("return select(2, load('syntax error here'))"),
+        ["[string \"syntax error here\"]:1: syntax error near 'error'".into()]);
+    assert_eq!(eval("return select(2, load('if x then\\n  t = {1 2}', '=t'))"),
+        ["t:2: '}' expected near '2'".into()]);
+    assert_eq!(eval("return select(2, load('f(', '=t'))"), ["t:1: unexpected symbol near <eof>".into()]);
+    assert_eq!(eval("local f, msg = load('return 1', nil, 'b') return f, msg"),
+        [Value::Nil, "attempt to load a text chunk (mode is 'b')".into()]);
+    assert_eq!(eval("local ok, msg = pcall(load, function() return 1 end) return ok, msg"),
+        [Value::Boolean(false), "reader function must return a string".into()]);
+}
+
+#[test]
+fn load_binary() {
+    let mut state = ExeState::new();
+    let chunk = dump::dump(&parse::load("return 'binary', ...".as_bytes()), false);
+    state.globals().set("chunk", Value::from(chunk));
+    let rets = state.exec_main(&parse::load(r#"
+        local f = load(chunk)
+        local g, msg = load(chunk, "bin", "t")
+        local a, b = f(1)
+        return a, b, g, msg
+    "#.as_bytes()));
+    assert_eq!(rets, ["binary".into(), Value::Integer(1), Value::Nil,
+        "attempt to load a binary chunk (mode is 't')".into()]);
+}
+
+#[test]
+fn files() {
+    let path = std::env::temp_dir().join(format!("lua-rs-load-{}.lua", std::process::id()));
+    fs::write(&path, "count = (count or 0) + 1 return count, ...").unwrap();
+    let path = path.to_str().unwrap();
+
+    assert_eq!(eval(format!(r#"
+        local f = loadfile("{path}")
+        local a, b = f("arg")
+        local c = dofile("{path}")
+        local env = {{}}
+        local d = loadfile("{path}", "t", env)()
+        return a, b, c, d, env.count, count
+    "#)), [Value::Integer(1), "arg".into(), Value::Integer(2), Value::Integer(1),
+        Value::Integer(1), Value::Integer(2)]);
+
+    fs::write(path, "return +").unwrap();
+    let rets = eval(format!(r#"
+        local ok, msg = pcall(dofile, "{path}")
+        local f, msg2 = loadfile("{path}")
+        local g, msg3 = loadfile("{path}.none")
+        return ok, msg, f, msg2, g, msg3
+    "#));
+    fs::remove_file(path).unwrap();
+    assert_eq!(rets[0], Value::Boolean(false));
+    assert_eq!(rets[2], Value::Nil);
+    assert_eq!(rets[1], rets[3]);
+    assert_eq!(rets[5], format!("cannot open {path}.none: No such file or directory").into());
+}
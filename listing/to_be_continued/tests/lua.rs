@@ -0,0 +1,53 @@
+use lua_rs::Lua;
+use lua_rs::error::LuaError;
+use lua_rs::value::Value;
+
+#[test]
+fn embedding() {
+    let mut lua = Lua::new();
+    lua.set_global("x", 20);
+    lua.load("function add(a) return x + a end").unwrap().call(&[]).unwrap();
+
+    assert_eq!(lua.eval::<i64>("add(22)").unwrap(), 42);
+    assert_eq!(lua.call("add", &[Value::Integer(1)]).unwrap(), [Value::Integer(21)]);
+
+    // statements, and globals set by scripts
+    assert_eq!(lua.eval::<String>("y = 'hi' return y .. '!'").unwrap(), "hi!");
+    assert_eq!(lua.get::<String>("y").unwrap(), "hi");
+    assert_eq!(lua.globals().get("y"), "hi".into());
+
+    // varargs of chunks, which can be called again
+    let mut chunk = lua.load("local a, b = ... return a * b").unwrap();
+    assert_eq!(chunk.call(&[Value::Integer(6), Value::Integer(7)]).unwrap(), [Value::Integer(42)]);
+    assert_eq!(chunk.call(&[Value::Integer(2), Value::Integer(3)]).unwrap(), [Value::Integer(6)]);
+    assert_eq!(lua.load("return 1.5").unwrap().eval::<f64>().unwrap(), 1.5);
+}
+
+#[test]
+fn errors() {
+    let mut lua = Lua::new();
+    match lua.load("x = 1\ny = 'abc") {
+        Err(LuaError::SyntaxError { source, line, msg }) => {
+            assert_eq!(source, r#"[string "x = 1..."]"#);
+            assert_eq!(line, 2);
+            assert_eq!(msg, "unfinished string");
+        }
+        _ => panic!("expect syntax error"),
+    }
+    assert!(lua.load("x = = 1").is_err());
+
+    let err = lua.eval::<i64>("error('boom')").unwrap_err();
+    assert!(matches!(err, LuaError::RuntimeError { .. }), "{err}");
+    assert!(err.to_string().ends_with("boom"), "{err}");
+
+    let err = lua.call("nothing", &[]).unwrap_err();
+    assert_eq!(err.to_string(), "global 'nothing' is not a function");
+
+    lua.set_global("s", "abc");
+    let err = lua.get::<i64>("s").unwrap_err();
+    assert!(matches!(err, LuaError::TypeError(_)), "{err}");
+    assert_eq!(err.to_string(), "string can not be converted to i64");
+
+    // still usable after errors
+    assert_eq!(lua.eval::<i64>("1 + 2").unwrap(), 3);
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use lua_rs::value::Value;
+use common::{eval, error};
+
+#[test]
+fn integer_subtype() {
+    assert_eq!(eval("return math.floor(2^53), math.floor(-3.5), math.ceil(3.2), math.floor(3), math.ceil('2.5')"),
+        [Value::Integer(1 << 53), Value::Integer(-4), Value::Integer(4), Value::Integer(3), Value::Integer(3)]);
+    // not representable
+    assert_eq!(eval("return math.floor(2^63), math.ceil(-1/0)"),
+        [Value::Float(9223372036854775808.0), Value::Float(f64::NEG_INFINITY)]);
+
+    assert_eq!(eval("return math.abs(-3), math.abs(-2.5), math.abs(math.mininteger)"),
+        [Value::Integer(3), Value::Float(2.5), Value::Integer(i64::MIN)]);
+    assert_eq!(eval("return math.fmod(7, 3), math.fmod(-7, 3), math.fmod(math.mininteger, -1), math.fmod(-6, 4.0)"),
+        [Value::Integer(1), Value::Integer(-1), Value::Integer(0), Value::Float(-2.0)]);
+    assert_eq!(eval("return math.modf(3.75)"), [Value::Float(3.0), Value::Float(0.75)]);
+    assert_eq!(eval("return math.modf(-5)"), [Value::Integer(-5), Value::Float(0.0)]);
+    assert_eq!(eval("return math.modf(-1/0)"), [Value::Float(f64::NEG_INFINITY), Value::Float(0.0)]);
+    assert_eq!(eval("return math.max(1, 2.0, 2), math.min(3, 1.0, 1), math.max(math.maxinteger, 2^63)"),
+        [Value::Float(2.0), Value::Float(1.0), Value::Float(9223372036854775808.0)]);
+
+    assert_eq!(eval("return math.tointeger(3.0), math.tointeger(3.5), math.tointeger('8'), math.tointeger({})"),
+        [Value::Integer(3), Value::Nil, Value::Integer(8), Value::Nil]);
+    assert_eq!(eval("return math.type(1), math.type(1.0), math.type('1')"),
+        ["integer".into(), "float".into(), Value::Nil]);
+    assert_eq!(eval("return math.maxinteger + 1 == math.mininteger, math.type(math.pi), math.huge > 2^1000"),
+        [Value::Boolean(true), "float".into(), Value::Boolean(true)]);
+}
+
+#[test]
+fn floats() {
+    assert_eq!(eval("return math.sqrt(16), math.exp(0), math.sin(0), math.cos(0)"),
+        [Value::Float(4.0), Value::Float(1.0), Value::Float(0.0), Value::Float(1.0)]);
+    assert_eq!(eval("return math.log(8, 2), math.log(1000, 10), math.log(1), math.log(27, 3) - 3 < 1e-15"),
+        [Value::Float(3.0), Value::Float(3.0), Value::Float(0.0), Value::Boolean(true)]);
+}
+
+#[test]
+fn random() {
+    // deterministic for each state, and repeatable by the seeds
+    let source = "local t = {} for i = 1, 10 do t[i] = math.random(100) end return table.concat(t, ',')";
+    assert_eq!(eval(source), eval(source));
+    assert_eq!(eval("return math.randomseed(7)"), [Value::Integer(7), Value::Integer(0)]);
+    assert_eq!(eval(format!("math.randomseed(1, 2) local s = (function() {source} end)() \
+            math.randomseed(1, 2) return s == (function() {source} end)()")),
+        [Value::Boolean(true)]);
+
+    assert_eq!(eval("
+        for i = 1, 1000 do
+            local f = math.random()
+            local a, b = math.random(6), math.random(-3, 3)
+            if f < 0 or f >= 1 or a < 1 or a > 6 or b < -3 or b > 3 then return false end
+        end
+        return math.type(math.random(0)), math.random(5, 5), math.random(math.mininteger, math.maxinteger) ~= nil
+    "), ["integer".into(), Value::Integer(5), Value::Boolean(true)]);
+
+    // all values are hit
+    assert_eq!(eval("
+        local seen, n = {}, 0
+        for i = 1, 1000 do
+            local r = math.random(10)
+            if not seen[r] then seen[r] = true n = n + 1 end
+        end
+        return n
+    "), [Value::Integer(10)]);
+
+    assert_eq!(error("math.random(0.5)"), "[string \"?\"]:1: bad argument #1 to 'random' (number has no integer representation)");
+    assert_eq!(error("math.random(2, 1)"), "[string \"?\"]:1: bad argument #2 to 'random' (interval is empty)");
+    assert_eq!(error("math.random(1, 2, 3)"), "[string \"?\"]:1: wrong number of arguments");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("math.floor('x')"), "[string \"?\"]:1: bad argument #1 to 'floor' (number expected, got string)");
+    assert_eq!(error("math.sqrt()"), "[string \"?\"]:1: bad argument #1 to 'sqrt' (number expected, got no value)");
+    assert_eq!(error("math.fmod(1, 0)"), "[string \"?\"]:1: bad argument #2 to 'fmod' (zero)");
+    assert_eq!(error("math.type()"), "[string \"?\"]:1: bad argument #1 to 'type' (value expected)");
+}
+
+// integer arithmetic wraps around, and `//` and `%` round towards minus
+// infinity, both at runtime and by constant folding
+#[test]
+fn arithmetic() {
+    assert_eq!(eval("local a, b = -7, 2 return a // b, a % b, -7 // 2, -7 % 2, 7 // -2.0, -7.5 % 2, 5.5 % -2"),
+        [Value::Integer(-4), Value::Integer(1), Value::Integer(-4), Value::Integer(1),
+            Value::Float(-4.0), Value::Float(0.5), Value::Float(-0.5)]);
+    assert_eq!(eval("local max, min = math.maxinteger, math.mininteger \
+            return max + 1 == min, min - 1 == max, max * 2, min // -1, min % -1"),
+        [Value::Boolean(true), Value::Boolean(true), Value::Integer(-2), Value::Integer(i64::MIN),
+            Value::Integer(0)]);
+    assert_eq!(eval("return 1 // 0.0, -1 % math.huge"),
+        [Value::Float(f64::INFINITY), Value::Float(f64::INFINITY)]);
+    assert_eq!(error("local a = 1 return a // 0"), "[string \"?\"]:1: attempt to perform 'n//0'");
+    assert_eq!(error("return 1 % 0"), "[string \"?\"]:1: attempt to perform 'n%0'");
+}
+
+// integer overflows wrap around and shifts of 64 or more bits give 0,
+// both at runtime and by constant folding
+#[test]
+fn overflow() {
+    assert_eq!(eval("return -0x8000000000000000, - -0x8000000000000000, -math.mininteger, -9223372036854775808"),
+        [Value::Integer(i64::MIN), Value::Integer(i64::MIN), Value::Integer(i64::MIN), Value::Float(-9223372036854775808.0)]);
+    assert_eq!(eval("local a, b, c = 1, 64, -1 return a << b, c >> 1, a << -1, 2 >> -1, 1 << 64, -1 >> 1, 1 << 63, 1 >> -63"),
+        [Value::Integer(0), Value::Integer(i64::MAX), Value::Integer(0), Value::Integer(4),
+            Value::Integer(0), Value::Integer(i64::MAX), Value::Integer(i64::MIN), Value::Integer(i64::MIN)]);
+    assert_eq!(eval("local a = 3 return a << 100, a >> 64, a << -200, a << 1.0, 2.0 << 62"),
+        [Value::Integer(0), Value::Integer(0), Value::Integer(0), Value::Integer(6), Value::Integer(i64::MIN)]);
+    assert_eq!(eval("local m = math.mininteger return 1 << m, 1 >> m, m >> m, 1 << math.mininteger"),
+        [Value::Integer(0), Value::Integer(0), Value::Integer(0), Value::Integer(0)]);
+    assert_eq!(error("return 1.5 << 1"), "[string \"?\"]:1: number has no integer representation");
+    assert_eq!(error("return 1 | 2^63"), "[string \"?\"]:1: number has no integer representation");
+}
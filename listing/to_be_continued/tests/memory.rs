@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use lua_rs::error::LuaError;
+use lua_rs::memory::CountingAlloc;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const MB: usize = 1 << 20;
+
+fn load_function(state: &mut ExeState, source: &str) -> Value {
+    state.exec_main(&parse::load(source.as_bytes())).remove(0)
+}
+
+// The test harness captures the output of the trace feature in memory
+// of the same thread, which is counted.
+#[cfg(not(feature = "trace"))]
+#[test]
+fn limit() {
+    let mut state = ExeState::builder().max_memory(32 * MB).build();
+    let f = load_function(&mut state, "
+        return function(n)
+            local t = {}
+            for i = 1, n do t[i] = i end
+            return #t
+        end
+    ");
+
+    let rets = state.pcall(f.clone(), &[Value::Integer(1000)]).unwrap();
+    assert_eq!(rets, [Value::Integer(1000)]);
+
+    let err = state.pcall(f.clone(), &[Value::Integer(10_000_000)]).unwrap_err();
+    assert!(matches!(err, LuaError::MemoryError), "{err}");
+    assert_eq!(err.to_string(), "not enough memory");
+
+    // the state is still usable, while the memory allocated by the
+    // thread is counted, e.g. the cache of backtraces for the panic
+    let rets = state.pcall(f, &[Value::Integer(1000)]).unwrap();
+    assert_eq!(rets, [Value::Integer(1000)]);
+
+    // allocated by a Rust function
+    let f = load_function(&mut state, "return function(n) return string.rep('x', n) end");
+    state.pcall(f.clone(), &[Value::Integer(MB as i64)]).unwrap();
+    let err = state.pcall(f, &[Value::Integer(40 * MB as i64)]).unwrap_err();
+    assert!(matches!(err, LuaError::MemoryError), "{err}");
+}
+
+#[test]
+fn watermarks() {
+    let fired = Rc::new(RefCell::new(Vec::new()));
+    let (fired50, fired80) = (fired.clone(), fired.clone());
+    let mut state = ExeState::builder()
+        .max_memory(8 * MB)
+        .memory_watermark(50, move |used| fired50.borrow_mut().push((50, used)))
+        .memory_watermark(80, move |used| fired80.borrow_mut().push((80, used)))
+        .build();
+    let f = load_function(&mut state, "
+        return function(n)
+            type(n) -- checked here, before allocating
+            local s = string.rep('x', n * 1024)
+            return #s
+        end
+    ");
+
+    state.pcall(f.clone(), &[Value::Integer(1024)]).unwrap();
+    assert!(fired.borrow().is_empty());
+
+    // crossing 50% only
+    state.pcall(f.clone(), &[Value::Integer(5 * 1024)]).unwrap();
+    assert_eq!(fired.borrow().len(), 1);
+    let (level, used) = fired.borrow()[0];
+    assert_eq!(level, 50);
+    assert!(used >= 5 * MB, "{used}");
+
+    // fired again after dropping below, and crossing 80% too
+    state.pcall(f.clone(), &[Value::Integer(7 * 1024)]).unwrap();
+    let levels: Vec<_> = fired.borrow().iter().map(|&(level, _)| level).collect();
+    assert_eq!(levels, [50, 50, 80]);
+
+    // and then the hard limit
+    let err = state.pcall(f, &[Value::Integer(9 * 1024)]).unwrap_err();
+    assert!(matches!(err, LuaError::MemoryError), "{err}");
+    assert_eq!(fired.borrow().len(), 5);
+}
+
+// coroutines start with small stacks, which are shrunk when suspended
+#[cfg(not(feature = "trace"))]
+#[test]
+fn coroutines() {
+    let mut state = ExeState::new();
+    let f = load_function(&mut state, "
+        local function deep(n)
+            if n > 0 then return deep(n - 1) + 1 end
+            return 0
+        end
+        return function(n)
+            local actors = {}
+            for i = 1, n do
+                local co = coroutine.create(function()
+                    deep(150)
+                    coroutine.yield()
+                end)
+                coroutine.resume(co)
+                actors[i] = co
+            end
+            return actors
+        end
+    ");
+    let before = state.memory_used();
+    let actors = state.pcall(f, &[Value::Integer(1000)]).unwrap();
+    let per_actor = (state.memory_used() - before) / 1000;
+    assert!(per_actor < 2048, "{per_actor}");
+    drop(actors);
+}
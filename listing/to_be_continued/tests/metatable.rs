@@ -0,0 +1,266 @@
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+fn exec_err(source: &str) -> String {
+    let mut state = ExeState::new();
+    let f = Value::LuaFunction(parse::load(source.as_bytes()).into());
+    let env = state.globals().into(); // the main function's `_ENV`
+    state.pcall(f, &[env]).unwrap_err().to_string()
+}
+
+#[test]
+fn index() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        -- table
+        local defaults = { color = "red", size = 1 }
+        local t = setmetatable({ size = 2 }, { __index = defaults })
+        local r1, r2, r3 = t.color, t.size, t.missing
+
+        -- chain
+        local a = setmetatable({}, { __index = t })
+        local r4 = a.color
+
+        -- function, with the table and the key
+        local calls = 0
+        local f = setmetatable({}, { __index = function (tb, k)
+            calls = calls + 1
+            return k .. "!"
+        end })
+        local r5, r6 = f.x, f[1]
+
+        -- raw access
+        local r7 = rawget(a, "color")
+        return r1, r2, r3, r4, r5, r6, calls, r7
+    "#);
+    assert_eq!(rets, ["red".into(), Value::Integer(2), Value::Nil, "red".into(),
+        "x!".into(), "1!".into(), Value::Integer(2), Value::Nil]);
+}
+
+#[test]
+fn new_index() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        -- table: new keys go to the other table, existing ones stay
+        local store = {}
+        local t = setmetatable({ old = 1 }, { __newindex = store })
+        t.old = 2
+        t.new = 3
+        t[1] = 4
+        local r1, r2, r3, r4 = t.old, rawget(t, "new"), store.new, store[1]
+
+        -- function
+        local log = ""
+        local f = setmetatable({}, { __newindex = function (tb, k, v)
+            log = log .. k .. "=" .. v .. ";"
+            rawset(tb, k, v * 10)
+        end })
+        f.x = 1
+        f.x = 2 -- existing now, so no metamethod
+        return r1, r2, r3, r4, log, f.x
+    "#);
+    assert_eq!(rets, [Value::Integer(2), Value::Nil, Value::Integer(3), Value::Integer(4),
+        "x=1;".into(), Value::Integer(2)]);
+}
+
+// classes and methods, by `__index` on the metatable itself
+#[test]
+fn class() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local Account = {}
+        Account.__index = Account
+        function Account.new(balance)
+            return setmetatable({ balance = balance }, Account)
+        end
+        function Account:deposit(v)
+            self.balance = self.balance + v
+        end
+
+        local Saving = setmetatable({}, { __index = Account })
+        Saving.__index = Saving
+        function Saving.new(balance)
+            return setmetatable(Account.new(balance), Saving)
+        end
+        function Saving:interest()
+            self:deposit(self.balance // 10)
+        end
+
+        local s = Saving.new(100)
+        s:deposit(50)
+        s:interest()
+        return s.balance, getmetatable(s) == Saving
+    "#);
+    assert_eq!(rets, [Value::Integer(165), Value::Boolean(true)]);
+}
+
+// `_ENV` with metatable, e.g. the strict mode
+#[test]
+fn globals() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local declared = {}
+        setmetatable(_ENV, {
+            __newindex = function (t, k, v)
+                declared[k] = true
+                rawset(t, k, v)
+            end,
+            __index = function (t, k)
+                return "undeclared " .. k
+            end,
+        })
+        x = 1
+        return x, y, declared.x
+    "#);
+    assert_eq!(rets, [Value::Integer(1), "undeclared y".into(), Value::Boolean(true)]);
+}
+
+#[test]
+fn protected() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local t = setmetatable({}, { __metatable = "locked" })
+        local m = {}
+        local u = setmetatable({}, m)
+        setmetatable(u, nil)
+        return getmetatable(t), getmetatable(u), getmetatable(1)
+    "#);
+    assert_eq!(rets, ["locked".into(), Value::Nil, Value::Nil]);
+
+    let err = exec_err("setmetatable(setmetatable({}, { __metatable = 1 }), {})");
+    assert!(err.contains("cannot change a protected metatable"), "{err}");
+}
+
+#[test]
+fn errors() {
+    let err = exec_err("local t; return t.x");
+    assert!(err.contains("attempt to index a nil value (local 't')"), "{err}");
+    let err = exec_err("a = {}; return a.b.c");
+    assert!(err.contains("attempt to index a nil value (field 'b')"), "{err}");
+    let err = exec_err("local n = 1; n.x = 2");
+    assert!(err.contains("attempt to index a number value (local 'n')"), "{err}");
+    let err = exec_err("local t = { x = true }; return t.x.y");
+    assert!(err.contains("attempt to index a boolean value (field 'x')"), "{err}");
+
+    // loops
+    let err = exec_err("local t = {}; t.__index = t; setmetatable(t, t); return t.x");
+    assert!(err.contains("'__index' chain too long; possible loop"), "{err}");
+    let err = exec_err("local t = {}; t.__newindex = t; setmetatable(t, t); t.x = 1");
+    assert!(err.contains("'__newindex' chain too long; possible loop"), "{err}");
+
+    let err = exec_err("setmetatable({}, 1)");
+    assert!(err.contains("bad argument #2 to 'setmetatable' (nil or table expected, got number)"), "{err}");
+}
+
+#[test]
+fn arith() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local V = {}
+        local function new(x) return setmetatable({ x = x }, V) end
+        local function x(v) return type(v) == "table" and v.x or v end
+        V.__add = function (a, b) return new(x(a) + x(b)) end
+        V.__sub = function (a, b) return new(x(a) - x(b)) end
+        V.__mul = function (a, b) return new(x(a) * x(b)) end
+        V.__div = function (a, b) return new(x(a) / x(b)) end
+        V.__mod = function (a, b) return new(x(a) % x(b)) end
+        V.__idiv = function (a, b) return new(x(a) // x(b)) end
+        V.__pow = function (a, b) return new(x(a) ^ x(b)) end
+        V.__unm = function (a) return new(-a.x) end
+        V.__band = function (a, b) return new(x(a) & x(b)) end
+        V.__shl = function (a, b) return new(x(a) << x(b)) end
+        V.__bnot = function (a) return new(~a.x) end
+
+        local a, b = new(7), new(2)
+        local k = 1000
+        return (a + b).x, (a - b).x, (a * 3).x, (a / b).x, (a % 4).x, (a // k).x,
+            (b ^ 3).x, (-a).x, (a & 3).x, (b << 4).x, (~b).x,
+            (1 - a).x, (2 * a).x, (k + a).x, ("10" - a).x
+    "#);
+    assert_eq!(rets, [Value::Integer(9), Value::Integer(5), Value::Integer(21),
+        Value::Float(3.5), Value::Integer(3), Value::Integer(0),
+        Value::Float(8.0), Value::Integer(-7), Value::Integer(3), Value::Integer(32),
+        Value::Integer(-3), Value::Integer(-6), Value::Integer(14), Value::Integer(1007),
+        Value::Integer(3)]);
+
+    // the metamethod of the second operand, and strings still coerced
+    let rets = exec(&mut state, r#"
+        local m = { __add = function (a, b) return "added" end }
+        local t = setmetatable({}, m)
+        return {} + t, "1" + "2", "3" | 4
+    "#);
+    assert_eq!(rets, ["added".into(), Value::Integer(3), Value::Integer(7)]);
+}
+
+#[test]
+fn arith_errors() {
+    let err = exec_err("local t = {}; return t + 1");
+    assert!(err.contains("attempt to perform arithmetic on a table value (local 't')"), "{err}");
+    let err = exec_err("local n, t = 1, {}; return n * t");
+    assert!(err.contains("attempt to perform arithmetic on a table value (local 't')"), "{err}");
+    let err = exec_err("return -x");
+    assert!(err.contains("attempt to perform arithmetic on a nil value (global 'x')"), "{err}");
+    let err = exec_err("local s = 'a'; return 1 + s");
+    assert!(err.contains("attempt to perform arithmetic on a string value (local 's')"), "{err}");
+    let err = exec_err("local t = {}; return t & 1");
+    assert!(err.contains("attempt to perform bitwise operation on a table value (local 't')"), "{err}");
+    let err = exec_err("local f = 1.5; return f | 1");
+    assert!(err.contains("number has no integer representation"), "{err}");
+}
+
+#[test]
+fn call() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local Counter = {}
+        Counter.__call = function (self, n)
+            self.count = self.count + (n or 1)
+            return self.count
+        end
+        local c = setmetatable({ count = 0 }, Counter)
+        c()
+        local r1 = c(10)
+
+        -- all kinds of calls
+        local function tail() return c(100) end
+        local r2 = tail()
+        local t = { c(1000) }
+        local r3 = t[1]
+
+        -- as iterators of generic for, with (object, state, control)
+        local iter = setmetatable({}, { __call = function (self, s, i)
+            if i < s then return i + 1 end
+        end })
+        local sum = 0
+        for i in iter, 4, 0 do sum = sum + i end
+
+        -- chained: outer(2) is inner(outer, 2), and then f(inner, outer, 2)
+        local inner = setmetatable({}, { __call = function (a, b, n)
+            return a == b, n
+        end })
+        local outer = setmetatable({}, { __call = inner })
+        local r4, r5 = outer(2)
+        return r1, r2, r3, sum, r4, r5
+    "#);
+    assert_eq!(rets, [Value::Integer(11), Value::Integer(111), Value::Integer(1111),
+        Value::Integer(10), Value::Boolean(false), Value::Integer(2)]);
+
+    // called by Rust functions too
+    let rets = exec(&mut state, r#"
+        local f = setmetatable({}, { __call = function (self, a, b) return a < b end })
+        local t = { 3, 1, 2 }
+        table.sort(t, function (a, b) return f(a, b) end)
+        return t[1], t[2], t[3]
+    "#);
+    assert_eq!(rets, [Value::Integer(1), Value::Integer(2), Value::Integer(3)]);
+    let f = exec(&mut state, "return setmetatable({}, { __call = function (self, x) return x * 2 end })");
+    assert_eq!(state.pcall(f[0].clone(), &[Value::Integer(21)]).unwrap(), [Value::Integer(42)]);
+
+    let err = exec_err("local t = setmetatable({}, {}); t()");
+    assert!(err.contains("attempt to call a table value (local 't')"), "{err}");
+}
@@ -0,0 +1,158 @@
+use lua_rs::minify::minify;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(source: &[u8]) -> Vec<Value> {
+    ExeState::new().exec_main(&parse::load(source))
+}
+
+// the minified code returns the same values with the source
+fn check_same(source: &str) -> String {
+    let (code, report) = minify(source.as_bytes());
+    assert_eq!(exec(&code), exec(source.as_bytes()), "{}", String::from_utf8_lossy(&code));
+    assert_eq!(report.output_size, code.len());
+    String::from_utf8(code).unwrap()
+}
+
+#[test]
+fn program() {
+    let source = r#"
+        -- counts of words
+        local function count_words(words)
+            local counts = {}
+            for _, word in ipairs(words) do
+                counts[word] = (counts[word] or 0) + 1
+            end
+            return counts
+        end
+
+        --[[ a class
+             with methods ]]
+        local Stack = {}
+        Stack.__index = Stack
+        function Stack.new() return setmetatable({ items = {}, size = 0 }, Stack) end
+        function Stack:push(v)
+            self.size = self.size + 1
+            self.items[self.size] = v
+        end
+
+        local stack = Stack.new()
+        for i = 1, 10, 3 do
+            stack:push(i * 2)
+        end
+        local total = 0
+        local index = 1
+        while index <= stack.size do
+            total = total + stack.items[index]
+            index = index + 1
+        end
+        local n = 0
+        repeat
+            local last = n
+            n = n + 1
+        until last >= 3
+        return total, n, #stack.items, count_words({ "a", "b", "a" }).a
+    "#;
+    let code = check_same(source);
+    assert!(!code.contains("--"), "{code}");
+    assert!(!code.contains("stack"), "{code}");
+    assert!(code.contains(".items"), "{code}");
+    assert!(code.contains("self.size"), "{code}");
+
+    let (_, report) = minify(source.as_bytes());
+    assert!(report.output_size * 2 < report.input_size, "{report}");
+    assert_eq!(report.renamed, 13);
+    assert!(report.to_string().ends_with("13 locals renamed"), "{report}");
+}
+
+#[test]
+fn scopes() {
+    // shadowing, and locals visible after their declarations
+    check_same(r#"
+        local x = 1
+        local r = {}
+        do
+            local x = x + 1
+            r[1] = x
+            local function f(x) return x * 10 end
+            r[2] = f(x)
+        end
+        r[3] = x
+        local a, b = 1, 2
+        local b, a = a, b
+        r[4] = a * 10 + b
+        return r[1], r[2], r[3], r[4]
+    "#);
+
+    // upvalues and globals are not shadowed
+    check_same(r#"
+        a = 100
+        local outer = 1
+        local function f()
+            local inner = 2
+            return function () return outer + inner + a end
+        end
+        return f()()
+    "#);
+
+    // generic for, with the variables visible in the body only
+    check_same(r#"
+        local k, v = "k", "v"
+        local s = ""
+        for k, v in ipairs({ 10, 20 }) do
+            s = s .. k .. "=" .. v .. ";"
+        end
+        return s .. k .. v
+    "#);
+
+    // labels and goto, continue, and table fields with local names
+    check_same(r#"
+        local n, x = 0, 5
+        for i = 1, 5 do
+            if i % 2 == 0 then continue end
+            n = n + i
+        end
+        ::top::
+        n = n + 1
+        if n < 12 then goto top end
+        local t = { x = x, [x] = 1, x }
+        return n, t.x, t[5], t[1]
+    "#);
+}
+
+// tokens which are merged without spaces
+#[test]
+fn spaces() {
+    let code = check_same(r#"
+        local a, t = 3, {}
+        t[ [[\a\b\c]] ] = 1
+        local s = 1 .. 2
+        local f = function (...) local _, y = ... return y end
+        local b = a - -a
+        local k <const> = 2
+        local c = "x" .. .5
+        local g = a; (function () t.g = 1 end)()
+        return t[ [[\a\b\c]] ], s, b, c, t.g, a // k, f(1, 2)
+    "#);
+    assert!(code.contains("- -"), "{code}");
+    assert!(code.contains("[ [["), "{code}");
+    assert!(code.contains(";("), "{code}");
+}
+
+#[test]
+fn literals() {
+    let code = check_same(r#"
+        return "it's", 'say "hi"', "a\nb", "\0" .. "1", 'tab\there', [==[
+long ]] string]==], 0.5, 1e100, 0xff, 0xffffffffffffffff, 2^53,
+        1e15, 1.50, 0x7fffffffffffffff
+    "#);
+    assert!(code.contains(r#""it's""#), "{code}");
+    assert!(code.contains(r#"'say "hi"'"#), "{code}");
+    assert!(code.contains(r#""\0""#), "{code}");
+    assert!(code.contains(",.5,"), "{code}");
+    assert!(code.contains("255"), "{code}");
+
+    // the shorter one of the source and the formatted value
+    assert!(code.contains(",1e15,1.5,0x7fffffffffffffff"), "{code}");
+}
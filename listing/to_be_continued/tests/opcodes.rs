@@ -0,0 +1,156 @@
+mod common;
+
+use std::fs;
+use std::panic;
+use lua_rs::bytecode::{ByteCode, Instruction, OpCode};
+use lua_rs::bytecode::ByteCode::*;
+use lua_rs::parse::{self, FuncProto};
+use lua_rs::value::Value;
+
+#[test]
+fn load() {
+    assert_codes!([LoadInt(0, 7), LoadBool(1, true), LoadConst(2, 0), Move(3, 0)],
+        ["hello"] => [7, true, "hello", 7]);
+
+    // LoadNil and MoveN work on ranges
+    assert_codes!([LoadInt(0, 1), LoadInt(1, 2), LoadInt(2, 3), LoadNil(1, 1)]
+        => [1, (), 3]);
+    assert_codes!([LoadNil(0, 3)] => [(), (), ()]);
+    assert_codes!([LoadInt(0, 1), LoadInt(1, 2), MoveN(2, 0, 2), MoveN(0, 3, 1)]
+        => [2, 2, 1, 2]);
+}
+
+#[test]
+fn arithmetic() {
+    assert_codes!([LoadInt(0, 3), LoadInt(1, 4), Add(2, 0, 1), AddInt(3, 0, 10), AddConst(4, 0, 0)],
+        [0.5] => [3, 4, 7, 13, 3.5]);
+
+    // integer and float
+    assert_codes!([LoadInt(0, 7), LoadInt(1, 2), Div(2, 0, 1), Idiv(3, 0, 1), Mod(4, 0, 1), Pow(5, 1, 1)]
+        => [7, 2, 3.5, 3, 1, 4.0]);
+
+    assert_codes!([LoadInt(0, 6), BitAndInt(1, 0, 3), BitOrInt(2, 0, 1), ShiftLInt(3, 0, 2), Neg(4, 0), BitNot(5, 0)]
+        => [6, 2, 7, 24, -6, -7]);
+}
+
+#[test]
+fn unops() {
+    assert_codes!([LoadConst(0, 0), Len(1, 0), Not(2, 0), LoadNil(3, 1), Not(4, 3)],
+        ["abc"] => ["abc", 3, false, (), true]);
+}
+
+#[test]
+fn jump_and_test() {
+    // the LoadInt(1, 1) is skipped
+    assert_codes!([LoadInt(0, 0), Jump(1), LoadInt(1, 1), LoadInt(1, 2)] => [0, 2]);
+
+    // TestOrJump jumps if false
+    assert_codes!([LoadBool(0, false), TestOrJump(0, 1), LoadInt(1, 1), LoadInt(2, 2)]
+        => [false, (), 2]);
+    assert_codes!([LoadBool(0, true), TestOrJump(0, 1), LoadInt(1, 1), LoadInt(2, 2)]
+        => [true, 1, 2]);
+
+    // compare skips the next code if the result matches
+    assert_codes!([LoadInt(0, 1), LoadInt(1, 0), LessInt(0, 2, true), LoadInt(1, 9)] => [1, 0]);
+    assert_codes!([LoadInt(0, 5), LoadInt(1, 0), LessInt(0, 2, true), LoadInt(1, 9)] => [5, 9]);
+
+    // `x = a or b` in 2 codes: copy @a and jump if true, otherwise copy @b
+    assert_codes!([LoadInt(0, 7), LoadInt(1, 8), TestAndSetJump(2, 0, 1), Move(2, 1)]
+        => [7, 8, 7]);
+    assert_codes!([LoadNil(0, 1), LoadInt(1, 8), TestAndSetJump(2, 0, 1), Move(2, 1)]
+        => [(), 8, 8]);
+}
+
+#[test]
+fn for_loop() {
+    // sum = 0; for i = 1, 4 do sum = sum + i end
+    assert_codes!([
+        LoadInt(0, 0), // sum
+        LoadInt(1, 1), LoadInt(2, 4), LoadInt(3, 1), // i, limit, step
+        ForPrepare(1, 2),
+        Add(0, 0, 1),
+        ForLoop(1, 2),
+    ] => [10, 5, 4, 1]);
+}
+
+#[test]
+fn table() {
+    assert_codes!([
+        NewTable(0, 2, 1),
+        SetIntConst(0, 1, 0),
+        SetFieldConst(0, 1, 2),
+        GetInt(1, 0, 1),
+        GetField(2, 0, 1),
+        Len(3, 0),
+    ], ["a", "k", 100] => [_, "a", 100, 1]);
+}
+
+#[test]
+fn get_table() {
+    // integer keys hit the array part, and fall back to the map part
+    // when out of the array
+    assert_codes!([
+        NewTable(0, 2, 1),
+        SetIntConst(0, 1, 0),
+        SetIntConst(0, 2, 1),
+        LoadInt(1, 9),
+        SetTableConst(0, 1, 0),
+        LoadInt(2, 2),
+        GetTable(3, 0, 2),
+        GetTable(4, 0, 1),
+        LoadConst(5, 2),
+        GetTable(6, 0, 5),
+        LoadInt(7, 0),
+        GetTable(8, 0, 7),
+    ], ["a", "b", 2.0] => [_, 9, 2, "b", "a", 2.0, "b", 0, ()]);
+}
+
+#[test]
+fn concat() {
+    assert_codes!([LoadConst(0, 0), LoadConst(1, 1), Concat(2, 0, 1)],
+        ["hello, ", "world"] => ["hello, ", "world", "hello, world"]);
+}
+
+#[test]
+fn encoding() {
+    let inst = Instruction::from(LoadConst(3, 0x1234));
+    assert_eq!(inst.opcode(), OpCode::LoadConst as u8);
+    assert_eq!((inst.a(), inst.bx()), (3, 0x1234));
+
+    let inst = Instruction::from(Jump(-2));
+    assert_eq!((inst.opcode(), inst.sbx()), (OpCode::Jump as u8, -2));
+
+    let inst = Instruction::from(Equal(1, 2, true));
+    assert_eq!((inst.a(), inst.b(), inst.c()), (1, 2, 1));
+
+    let code = ByteCode::extra_arg(0x123456);
+    assert_eq!((code.ax(), Instruction::from(code).ax()), (0x123456, 0x123456));
+
+    assert_eq!(Instruction::from(Return0).0, OpCode::Return0 as u32);
+    assert_eq!(ByteCode::try_from(Instruction(0xff)), Err(0xff));
+}
+
+fn check_round_trip(proto: &FuncProto) {
+    for &code in &proto.byte_codes {
+        assert_eq!(ByteCode::try_from(Instruction::from(code)), Ok(code));
+    }
+    for c in &proto.constants {
+        if let Value::LuaFunction(f) = c {
+            check_round_trip(f);
+        }
+    }
+}
+
+#[test]
+fn encoding_round_trip() {
+    for entry in fs::read_dir("test_lua").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lua") {
+            // some scripts are for syntax errors
+            let source = fs::read(&path).unwrap();
+            if let Ok(proto) = panic::catch_unwind(|| parse::load(&source[..])) {
+                check_round_trip(&proto);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::eval;
+
+#[test]
+fn date_time() {
+    assert_eq!(eval(r#"
+        return os.date("!%Y-%m-%d %H:%M:%S %j %a %b", 86400 * 365 + 3661)
+    "#), ["1971-01-01 01:01:01 001 Fri Jan".into()]);
+
+    // the date table round trip, in local time
+    assert_eq!(eval(r#"
+        local t = os.date("*t", 1000000000)
+        local u = os.date("!*t", 0)
+        return os.time(t), t.year, u.year, u.month, u.day, u.wday, u.yday, u.isdst
+    "#), [Value::Integer(1000000000), Value::Integer(2001), Value::Integer(1970),
+        Value::Integer(1), Value::Integer(1), Value::Integer(5), Value::Integer(1),
+        Value::Boolean(false)]);
+
+    // normalized, and the table is updated
+    assert_eq!(eval(r#"
+        local t = {year = 2023, month = 14, day = 1}
+        local a = os.time(t)
+        local b = os.time({year = 2024, month = 2, day = 1})
+        return a == b, t.year, t.month, t.hour
+    "#), [Value::Boolean(true), Value::Integer(2024), Value::Integer(2), Value::Integer(12)]);
+
+    assert_eq!(eval("return os.difftime(10, 4), math.type(os.clock())"),
+        [Value::Float(6.0), "float".into()]);
+
+    // only the conversions of C99 are allowed
+    assert_eq!(eval(r#"return os.date("!%Ey %Od %%", 0)"#), ["70 01 %".into()]);
+    assert_eq!(eval(r#"
+        local _, a = pcall(os.date, "%Q")
+        local _, b = pcall(os.date, "%Y %Ez")
+        local _, c = pcall(os.date, "%Y %")
+        return a, b, c
+    "#), ["bad argument #1 to 'date' (invalid conversion specifier '%Q')".into(),
+        "bad argument #1 to 'date' (invalid conversion specifier '%Ez')".into(),
+        "bad argument #1 to 'date' (invalid conversion specifier '%')".into()]);
+}
+
+#[test]
+fn getenv() {
+    std::env::set_var("LUA_RS_TEST_GETENV", "value");
+    assert_eq!(eval("return os.getenv('LUA_RS_TEST_GETENV'), os.getenv('LUA_RS_NOT_DEFINED')"),
+        ["value".into(), Value::Nil]);
+}
+
+#[test]
+fn remove_rename() {
+    let dir = std::env::temp_dir();
+    let a = dir.join(format!("lua-rs-os-{}-a", std::process::id()));
+    let b = dir.join(format!("lua-rs-os-{}-b", std::process::id()));
+    fs::write(&a, "x").unwrap();
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+    assert_eq!(eval(format!("return os.rename({a:?}, {b:?})")), [Value::Boolean(true)]);
+    assert_eq!(fs::read(b).unwrap(), b"x");
+    assert_eq!(eval(format!("return os.remove({b:?})")), [Value::Boolean(true)]);
+    assert_eq!(eval(format!("return os.remove({b:?})")),
+        [Value::Nil, format!("{b}: No such file or directory").into(), Value::Integer(2)]);
+    assert_eq!(eval(format!("return os.rename({a:?}, {b:?})")),
+        [Value::Nil, format!("{a}: No such file or directory").into(), Value::Integer(2)]);
+
+    // empty directories
+    fs::create_dir(a).unwrap();
+    assert_eq!(eval(format!("return os.remove({a:?})")), [Value::Boolean(true)]);
+}
+
+#[test]
+fn exit() {
+    let script = std::env::temp_dir().join(format!("lua-rs-os-{}-exit.lua", std::process::id()));
+    for (code, status) in [("", 0), ("true", 0), ("false", 1), ("3", 3)] {
+        fs::write(&script, format!("io.write('out') os.exit({code}) print('never')")).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_lua-rs")).arg(&script).output().unwrap();
+        assert_eq!(output.status.code(), Some(status));
+        assert_eq!(output.stdout, b"out"); // flushed
+    }
+    fs::remove_file(&script).unwrap();
+}
+
+#[test]
+fn execute() {
+    assert_eq!(eval("return os.execute()"), [Value::Boolean(true)]);
+    assert_eq!(eval("return os.execute('exit 0')"),
+        [Value::Boolean(true), "exit".into(), Value::Integer(0)]);
+    assert_eq!(eval("return os.execute('exit 3')"),
+        [Value::Nil, "exit".into(), Value::Integer(3)]);
+    assert_eq!(eval("return os.execute('kill -9 $$')"),
+        [Value::Nil, "signal".into(), Value::Integer(9)]);
+}
+
+#[test]
+fn popen() {
+    assert_eq!(eval(r#"
+        local f = io.popen("echo hello; echo world")
+        local a, b, c = f:read("l", "l", "l")
+        return a, b, c, f:close()
+    "#), ["hello".into(), "world".into(), Value::Nil,
+        Value::Boolean(true), "exit".into(), Value::Integer(0)]);
+
+    let path = std::env::temp_dir().join(format!("lua-rs-os-{}-popen", std::process::id()));
+    let path = path.to_str().unwrap();
+    assert_eq!(eval(format!(r#"
+        local f = io.popen("cat > {path}; exit 2", "w")
+        f:write("to ", "cat")
+        return f:close()
+    "#)), [Value::Nil, "exit".into(), Value::Integer(2)]);
+    assert_eq!(fs::read(path).unwrap(), b"to cat");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn no_subprocess() {
+    let mut state = ExeState::builder().allow_subprocess(false).build();
+    let rets = state.exec_main(&parse::load(b"return os.execute, io.popen, os.time ~= nil".as_slice()));
+    assert_eq!(rets, [Value::Nil, Value::Nil, Value::Boolean(true)]);
+}
+
+#[test]
+fn env_table() {
+    std::env::set_var("LUA_RS_TEST_ENV", "value");
+    assert_eq!(eval("return os.env.LUA_RS_TEST_ENV, os.env.LUA_RS_NOT_DEFINED, os.env[1]"),
+        ["value".into(), Value::Nil, Value::Nil]);
+
+    // read-only by default
+    let rets = eval("return pcall(function() os.env.LUA_RS_TEST_ENV = 'x' end)");
+    assert_eq!(rets[0], Value::Boolean(false));
+    assert_eq!(std::env::var("LUA_RS_TEST_ENV").unwrap(), "value");
+
+    // writes seen by the state and its commands, but not the process
+    let mut state = ExeState::builder().writable_env(true).build();
+    let rets = state.exec_main(&parse::load(br#"
+        os.env.LUA_RS_TEST_ENV_W = 42
+        os.env.LUA_RS_TEST_ENV = nil
+        local f = io.popen("echo $LUA_RS_TEST_ENV_W-$LUA_RS_TEST_ENV")
+        local out = f:read("l")
+        f:close()
+        return out, os.env.LUA_RS_TEST_ENV_W, os.getenv("LUA_RS_TEST_ENV"),
+            pcall(function() os.env["A=B"] = "x" end)
+    "#.as_slice()));
+    assert_eq!(rets[..4], ["42-".into(), "42".into(), Value::Nil, Value::Boolean(false)]);
+    assert!(std::env::var_os("LUA_RS_TEST_ENV_W").is_none());
+    assert_eq!(std::env::var("LUA_RS_TEST_ENV").unwrap(), "value");
+
+    // not shared by other states
+    assert_eq!(eval("return os.env.LUA_RS_TEST_ENV"), ["value".into()]);
+}
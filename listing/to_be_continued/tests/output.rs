@@ -0,0 +1,45 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use lua_rs::parse;
+use lua_rs::vm::ExeState;
+
+// output sink shared with the test
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn buffered_print() {
+    let sink = Sink::default();
+    let mut state = ExeState::builder().output(sink.clone()).build();
+
+    let proto = parse::load(r#"
+        for i = 1, 3 do
+            print(i, "x\255")
+        end
+    "#.as_bytes());
+    state.execute(&proto, &[]);
+
+    // flushed at the end of the chunk, and binary strings are kept
+    assert_eq!(&*sink.0.borrow(), b"1\tx\xff\n2\tx\xff\n3\tx\xff\n");
+}
+
+// the hook catches the errors of the chunk, but the output is still
+// flushed at the end
+#[test]
+fn flushed_with_hook() {
+    let sink = Sink::default();
+    let mut state = ExeState::builder().output(sink.clone()).hook(|_| ()).build();
+    state.execute(&parse::load(&b"print('hooked')"[..]), &[]);
+    assert_eq!(&*sink.0.borrow(), b"hooked\n");
+}
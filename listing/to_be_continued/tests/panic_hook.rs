@@ -0,0 +1,29 @@
+// The panic hook is process-wide, so this is a separate test binary
+// with one test, which is not interfered by other tests.
+use std::panic;
+use std::sync::Mutex;
+use lua_rs::Lua;
+
+// messages reported by the panic hook
+static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Lua errors caught by the protected calls are not reported, but other
+// panics are still reported by the previous hook.
+#[test]
+fn silent_errors() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        REPORTED.lock().unwrap().push(info.payload_as_str().unwrap_or("").to_string());
+        default(info); // for the failures of this test
+    }));
+
+    let mut lua = Lua::new();
+    assert!(!lua.eval::<bool>("pcall(error, 'caught')").unwrap());
+    assert!(lua.eval::<i64>("error('returned')").is_err());
+    assert!(lua.eval::<i64>("1 +").is_err());
+    assert!(!lua.eval::<bool>("coroutine.resume(coroutine.create(error))").unwrap());
+    assert!(REPORTED.lock().unwrap().is_empty());
+
+    assert!(panic::catch_unwind(|| panic!("outside")).is_err());
+    assert_eq!(*REPORTED.lock().unwrap(), ["outside"]);
+}
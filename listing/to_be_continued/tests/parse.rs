@@ -0,0 +1,293 @@
+mod common;
+
+use std::fs;
+use std::panic;
+use lua_rs::parse::{self, FuncProto};
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::{eval, script};
+
+// nest @inner in @n levels of @open and @close
+fn nest(open: &str, inner: &str, close: &str, n: usize) -> String {
+    open.repeat(n) + inner + &close.repeat(n)
+}
+
+// Near the limit, which should not overflow the stack of test threads.
+#[test]
+fn within_depth() {
+    assert_eq!(eval(format!("return {}", nest("(", "1", ")", 190))), [Value::Integer(1)]);
+    assert_eq!(eval(format!("return {}", nest("- ", "1", "", 190))), [Value::Integer(1)]);
+    assert_eq!(eval(format!("return {}", nest("{", "", "}", 190))).len(), 1);
+    assert_eq!(eval(format!("return 2{}", " ^ 1".repeat(190))), [Value::Float(2.0)]);
+    assert_eq!(eval(format!("x = 0 {} return x", nest("do ", "x = 1", " end", 190))), [Value::Integer(1)]);
+    assert_eq!(eval(format!("return ({})()", nest("function() return ", "3", " end", 90))).len(), 1);
+
+    // chained indexes are parsed by loop but not recursion
+    let chain = format!("local a = {{}} a.b = a return a{}", ".b".repeat(10000));
+    assert_eq!(eval(&chain).len(), 1);
+}
+
+#[test]
+fn too_deep() {
+    for source in [
+        format!("return {}", nest("(", "1", ")", 1000)),
+        format!("return {}", nest("- ", "1", "", 1000)),
+        format!("return {}", nest("{", "", "}", 1000)),
+        format!("return 'a'{}", " .. 'a'".repeat(1000)),
+        nest("while true do ", "", " end", 1000),
+        format!("return {}", nest("function() return ", "1", " end", 1000)),
+    ] {
+        let e = panic::catch_unwind(|| parse::load(source.as_bytes())).unwrap_err();
+        assert_eq!(e.downcast_ref::<String>().unwrap(), r#"[string "?"]:1: chunk has too many syntax levels"#);
+    }
+}
+
+#[test]
+fn max_depth() {
+    let source = format!("return {}", nest("(", "1", ")", 300));
+    assert!(panic::catch_unwind(|| parse::load(source.as_bytes())).is_err());
+
+    let proto = parse::load_with_depth(source.as_bytes(), 400);
+    assert_eq!(ExeState::new().exec_main(&proto), [Value::Integer(1)]);
+}
+
+// Constants more than u8 and u16 in a function, which are used by each
+// kind of byte codes taking constants.
+#[test]
+fn lots_of_constants() {
+    for n in [300, 70000] {
+        let mut source = String::from("local t = {}\n");
+        for i in 0..n {
+            source += &format!("t.k{i} = 's{i}'\n");
+        }
+        source += "
+            g_last = 'last'
+            local o = {last = 1, [2.5] = 'x'; m = function(self, a) return a + 0.5 end}
+            function o.f() return 'f' end
+            function o:g() return self.last end
+            return t.k0, t['k' .. (n - 1)], g_last, o.last, o[2.5], o:m(1), o.f(), o:g(),
+                2.5 * 2, 'last' == g_last, function() return t end ~= nil";
+        let source = source.replace("(n - 1)", &(n - 1).to_string());
+
+        let proto = parse::load(source.as_bytes());
+        assert!(proto.constants.len() > n * 2);
+        assert_eq!(eval(&source), [
+            Value::from("s0"),
+            Value::from(format!("s{}", n - 1)),
+            Value::from("last"),
+            Value::Integer(1),
+            Value::from("x"),
+            Value::Float(1.5),
+            Value::from("f"),
+            Value::Integer(1),
+            Value::Float(5.0),
+            Value::Boolean(true),
+            Value::Boolean(true),
+        ]);
+    }
+}
+
+#[test]
+fn function_limits() {
+    let names = |prefix: &str, n: usize| (0..n).map(|i| format!("{prefix}{i}"))
+        .collect::<Vec<_>>().join(", ");
+    for (source, msg) in [
+        (format!("local {}", names("a", 201)),
+            "too many local variables (limit is 200)"),
+        (format!("f({})", names("a", 300)),
+            "function or expression needs too many registers"),
+        (format!("local {} local function f() local {} return function() return {{{}, {}}} end end",
+                names("a", 150), names("b", 150), names("a", 150), names("b", 150)),
+            "too many upvalues (limit is 255)"),
+    ] {
+        let e = panic::catch_unwind(|| parse::load(source.as_bytes())).unwrap_err();
+        assert!(e.downcast_ref::<String>().unwrap().ends_with(msg), "{msg}");
+    }
+
+    // just within the limits
+    let source = format!("local {} local function f() local {}
+            return function() local t = {{{}, {}}} a99, b99 = 1, 2 return a99 + b99 end end
+            return f()()", names("a", 100), names("b", 100), names("a", 100), names("b", 100));
+    assert_eq!(eval(&source), [Value::Integer(3)]);
+}
+
+fn max_stack_size(source: &str) -> usize {
+    parse::load(source.as_bytes()).max_stack_size
+}
+
+#[test]
+fn stack_size() {
+    assert_eq!(max_stack_size(""), 1); // _ENV
+    assert_eq!(max_stack_size("local a, b, c"), 4);
+    assert_eq!(max_stack_size("local a = 1 print(a, a, a)"), 6);
+    assert_eq!(max_stack_size("local a, b, c, d = f()"), 5);
+    assert_eq!(max_stack_size("local t = {} t:m()"), 4);
+
+    let Value::LuaFunction(f) = &parse::load("local f = function(a, b) end".as_bytes()).constants[0] else {
+        panic!("function expected");
+    };
+    assert_eq!(f.max_stack_size, 2);
+}
+
+// all registers set by byte codes are in the frame
+fn check_stack_size(proto: &FuncProto) {
+    for code in &proto.byte_codes {
+        if let Some(dst) = code.dst() {
+            assert!((dst as usize) < proto.max_stack_size, "{code:?}");
+        }
+    }
+    for c in &proto.constants {
+        if let Value::LuaFunction(f) = c {
+            check_stack_size(f);
+        }
+    }
+}
+
+#[test]
+fn stack_size_covers_registers() {
+    for entry in fs::read_dir("test_lua").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lua") {
+            let source = fs::read(&path).unwrap();
+            if let Ok(proto) = panic::catch_unwind(|| parse::load(&source[..])) {
+                check_stack_size(&proto);
+            }
+        }
+    }
+}
+
+// constants are equal by bits, but not by values
+#[test]
+fn distinct_constants() {
+    let proto = parse::load("local a, b, c, d = 0.0, -0.0, 0, 1.0; return 1, 'a', 0.0".as_bytes());
+    let constants: Vec<String> = proto.constants.iter().map(|c| format!("{c:?}")).collect();
+    assert_eq!(constants, ["0.0", "-0.0", "1.0", "'a'"]);
+
+    let rets = eval("return 1/0.0, 1/-0.0");
+    assert_eq!(rets, [Value::Float(f64::INFINITY), Value::Float(f64::NEG_INFINITY)]);
+}
+
+// constant left operands are discharged after the right calls
+#[test]
+fn const_left_operand() {
+    let rets = eval(r#"
+        local function f(n) return n end
+        return 1 - f(3), 2 * f(4), 10 // f(3), 1 < f(3), "a" == f("a"), 2.5 + f(1),
+            "a" .. f("b"), 1 .. f(2) .. 3
+    "#);
+    assert_eq!(rets, [Value::Integer(-2), Value::Integer(8), Value::Integer(3),
+        Value::Boolean(true), Value::Boolean(true), Value::Float(3.5), "ab".into(), "123".into()]);
+}
+
+// array entries are discharged in order, before the next entry which
+// may use the registers, e.g. nested constructors and indexing
+#[test]
+fn table_constructor() {
+    let rets = eval("
+        local a = {x = {y = 1}}
+        local t = {5, {1, 2}, a.x.y, a.x.y + 1, k = 0, {a.x, {7}}}
+        return #t, t[1], t[2][2], t[3], t[4], t[5][1].y, t[5][2][1], t.k
+    ");
+    assert_eq!(rets, [Value::Integer(5), Value::Integer(5), Value::Integer(2), Value::Integer(1),
+        Value::Integer(2), Value::Integer(1), Value::Integer(7), Value::Integer(0)]);
+
+    let rets = eval("
+        local function f() return 1, 2, 3 end
+        local t = {f(), f(), k = 1}
+        local u = {f(), f()}
+        return #t, #u
+    ");
+    assert_eq!(rets, [Value::Integer(2), Value::Integer(4)]);
+}
+
+// a reader returning one byte at a time, like slow pipes
+struct ByteReader<'a>(&'a [u8]);
+
+impl std::io::Read for ByteReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.split_first() {
+            Some((&b, rest)) if !buf.is_empty() => {
+                buf[0] = b;
+                self.0 = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+#[test]
+fn sources() {
+    let mut state = ExeState::new();
+    assert_eq!(state.exec_main(&parse::load_str("return 'str', 1 + 2")), ["str".into(), Value::Integer(3)]);
+
+    let source = "local s = [[long\nstring]] -- comment\nreturn s, 0x10, 'a\\tb'";
+    assert_eq!(state.exec_main(&parse::load(ByteReader(source.as_bytes()))),
+        ["long\nstring".into(), Value::Integer(16), "a\tb".into()]);
+    assert_eq!(state.exec_main(&parse::load(source.as_bytes())),
+        ["long\nstring".into(), Value::Integer(16), "a\tb".into()]);
+
+    let err = panic::catch_unwind(|| parse::load_str("x = 'abc")).unwrap_err();
+    assert_eq!(lua_rs::vm::panic_message(&*err), "[string \"x = 'abc\"]:1: unfinished string");
+}
+
+// both syntax and runtime errors are prefixed by the position
+#[test]
+fn error_positions() {
+    let syntax_error = |source: &str| {
+        let err = panic::catch_unwind(|| parse::load_named(source.as_bytes(), "=t")).unwrap_err();
+        lua_rs::vm::panic_message(&*err)
+    };
+    assert_eq!(syntax_error("x = 1\n\nbreak"), "t:3: break outside loop");
+    assert_eq!(syntax_error("local a <foo> = 1"), "t:1: unknown attribute 'foo'");
+    assert_eq!(syntax_error("::a:: ::a::"), "t:1: duplicate label a");
+    assert_eq!(syntax_error("local function f()\n  goto next\nend"),
+        "t:3: no visible label 'next' for goto at line 2");
+
+    let runtime_error = |source: &str| {
+        let proto = parse::load_named(source.as_bytes(), "=t");
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(|| ExeState::new().exec_main(&proto))).unwrap_err();
+        lua_rs::vm::panic_message(&*err)
+    };
+    assert_eq!(runtime_error("local x\nx()"), "t:2: attempt to call a nil value (local 'x')");
+    assert_eq!(runtime_error("local t = {}\n\nreturn t.a.b"), "t:3: attempt to index a nil value (field 'a')");
+    assert_eq!(runtime_error("select(0)"), "t:1: bad argument #1 to 'select' (index out of range)");
+    assert_eq!(runtime_error("error('x', 0)"), "x");
+}
+
+// merged Move and LoadNil in explists and assignments, by
+// test_lua/multi_assign.lua
+#[test]
+fn multi_assign_script() {
+    assert_eq!(script("multi_assign.lua").unwrap(), "\
+nil\tnil\tnil\tnil
+1\t2\t3
+3\t2\t1
+nil\tnil\tnil
+1\tnil\tnil\tnil
+nil\t2\t3
+3\t3\t2\t1
+3\tnil\tnil\tnil
+3\t3\t2
+true\t3
+");
+}
+
+// boolean results of comparisons inside and/or, by
+// test_lua/logic_value.lua
+#[test]
+fn logic_value_script() {
+    assert_eq!(script("logic_value.lua").unwrap(), "\
+true\t2\t2\tfalse
+true\tfalse\ttrue\tfalse
+true\tfalse\ttrue\tfalse
+2\ttrue\t2
+nil\tyes\tno
+2
+1
+3
+table\ttrue
+true
+then
+");
+}
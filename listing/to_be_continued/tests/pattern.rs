@@ -0,0 +1,92 @@
+use lua_rs::patterns::{Capture, LuaPattern};
+
+// the whole match of @pattern in @s
+fn find(pattern: &str, s: &str) -> Option<String> {
+    let caps = LuaPattern::new(pattern).unwrap().captures(s.as_bytes())?;
+    Some(String::from_utf8(s.as_bytes()[caps.range()].to_vec()).unwrap())
+}
+
+fn error(pattern: &str) -> String {
+    LuaPattern::new(pattern).unwrap_err().to_string()
+}
+
+#[test]
+fn classes() {
+    assert_eq!(find("%d+", "abc 123 def").as_deref(), Some("123"));
+    assert_eq!(find("%a+", "  hello, world").as_deref(), Some("hello"));
+    assert_eq!(find("[%w_]+", "-- foo_bar1 --").as_deref(), Some("foo_bar1"));
+    assert_eq!(find("[^%s]+", "   x-y  ").as_deref(), Some("x-y"));
+    assert_eq!(find("[a-c]+", "xxabcabd").as_deref(), Some("abcab"));
+    assert_eq!(find("[]]", "a]b").as_deref(), Some("]"));
+    assert_eq!(find("[a-]+", "x-a-").as_deref(), Some("-a-"));
+    assert_eq!(find("%.%%", "1.5.%").as_deref(), Some(".%"));
+    assert_eq!(find(".", ""), None);
+}
+
+#[test]
+fn quantifiers_anchors() {
+    assert_eq!(find("a*", "bbb").as_deref(), Some(""));
+    assert_eq!(find("ba*", "baaac").as_deref(), Some("baaa"));
+    assert_eq!(find("<.->", "<a><b>").as_deref(), Some("<a>"));
+    assert_eq!(find("<.*>", "<a><b>").as_deref(), Some("<a><b>"));
+    assert_eq!(find("colou?r", "color").as_deref(), Some("color"));
+    assert_eq!(find("^ab", "cab"), None);
+    assert_eq!(find("ab$", "abab").as_deref(), Some("ab"));
+    assert_eq!(find("a$b", "a$b").as_deref(), Some("a$b"));
+    assert_eq!(find("^$", "").as_deref(), Some(""));
+}
+
+#[test]
+fn captures() {
+    let pattern = LuaPattern::new("(%w+)=(%w*)()").unwrap();
+    let caps = pattern.captures(b" key=value").unwrap();
+    assert_eq!(caps.range(), 1..10);
+    assert_eq!(caps.get(0), Some(Capture::Bytes(b"key=value")));
+    assert_eq!(caps.get(1), Some(Capture::Bytes(b"key")));
+    assert_eq!(caps.get(2), Some(Capture::Bytes(b"value")));
+    assert_eq!(caps.get(3), Some(Capture::Position(10)));
+    assert_eq!(caps.get(4), None);
+
+    // back reference, balance and frontier
+    assert_eq!(find("([\"'])(.-)%1", r#"say "it's" ok"#).as_deref(), Some(r#""it's""#));
+    assert_eq!(find("%b()", "f(a(b)c) d)").as_deref(), Some("(a(b)c)"));
+    assert_eq!(find("%f[%w]%w+%f[%W]", "  THE (quick) fox").as_deref(), Some("THE"));
+    assert_eq!(find("%f[%a]%a+$", "end.").as_deref(), None);
+    assert_eq!(find("()", "abc").as_deref(), Some(""));
+}
+
+#[test]
+fn captures_iter() {
+    let pattern = LuaPattern::new("(%w+)=(%w+)").unwrap();
+    let pairs: Vec<_> = pattern.captures_iter(b"a=1, b=2, c").map(|caps| caps.values()).collect();
+    assert_eq!(pairs, [
+        vec![Capture::Bytes(b"a"), Capture::Bytes(b"1")],
+        vec![Capture::Bytes(b"b"), Capture::Bytes(b"2")],
+    ]);
+
+    // empty matches, but not right after the previous match
+    let pattern = LuaPattern::new("a*").unwrap();
+    let ranges: Vec<_> = pattern.captures_iter(b"baac").map(|caps| caps.range()).collect();
+    assert_eq!(ranges, [0..0, 1..3, 4..4]);
+
+    // '^' is not an anchor
+    let pattern = LuaPattern::new("^a").unwrap();
+    assert_eq!(pattern.captures_iter(b"a^a^a").count(), 2);
+    assert!(!pattern.is_match(b"^a"));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("abc%"), "malformed pattern (ends with '%')");
+    assert_eq!(error("[a-z"), "malformed pattern (missing ']')");
+    assert_eq!(error("[a%]"), "malformed pattern (missing ']')");
+    assert_eq!(error("%b("), "malformed pattern (missing arguments to '%b')");
+    assert_eq!(error("%fa"), "missing '[' after '%f' in pattern");
+    assert_eq!(error("(a"), "unfinished capture");
+    assert_eq!(error("a)"), "invalid pattern capture");
+    assert_eq!(error("(a%1)"), "invalid capture index %1");
+    assert_eq!(error("%0"), "invalid capture index %0");
+    assert_eq!(error(&"()".repeat(33)), "too many captures");
+    assert_eq!(error(&"a?".repeat(200)), "pattern too complex");
+    assert!(LuaPattern::new("a".repeat(1000)).is_ok());
+}
@@ -0,0 +1,58 @@
+use lua_rs::error::LuaError;
+use lua_rs::parse;
+use lua_rs::pool::{JobHandle, LuaPool};
+use lua_rs::value::Value;
+
+// run @source on a worker, and return the first return value as integer
+fn eval(pool: &LuaPool, source: &str) -> JobHandle<i64> {
+    let source = source.to_string();
+    pool.submit(move |state| {
+        let proto = parse::load(source.as_bytes());
+        match state.exec_main(&proto).first() {
+            Some(&Value::Integer(i)) => i,
+            v => panic!("unexpected result: {v:?}"),
+        }
+    })
+}
+
+#[test]
+fn jobs() {
+    let pool = LuaPool::new(4, "function fib(n) if n < 2 then return n end return fib(n-1) + fib(n-2) end");
+
+    let results: Vec<_> = (10..20)
+        .map(|n| eval(&pool, &format!("return fib({n})")))
+        .collect();
+    let results: Vec<i64> = results.into_iter().map(|rx| rx.recv().unwrap()).collect();
+    assert_eq!(results, [55, 89, 144, 233, 377, 610, 987, 1597, 2584, 4181]);
+}
+
+#[test]
+fn failed_job() {
+    let pool = LuaPool::new(1, "base = 100");
+
+    // the failed job gives the error, and its state is replaced
+    let err = eval(&pool, "base = 1; return nil + 1").recv().unwrap_err();
+    assert_eq!(err.to_string(), "[string \"?\"]:1: attempt to perform arithmetic on a nil value");
+    assert_eq!(eval(&pool, "return base").recv().unwrap(), 100);
+
+    // so does the panic of the Rust closure
+    let err = pool.submit(|_| -> i64 { panic!("oops") }).recv().unwrap_err();
+    assert!(matches!(&err, LuaError::RuntimeError { .. }), "{err}");
+    assert_eq!(err.to_string(), "oops");
+    assert_eq!(eval(&pool, "return base + 1").recv().unwrap(), 101);
+}
+
+#[test]
+fn failed_prelude() {
+    let pool = LuaPool::new(2, "error('no config')");
+
+    // the workers are alive, and every job gets the prelude's error
+    for _ in 0..4 {
+        let err = eval(&pool, "return 1").recv().unwrap_err();
+        assert!(matches!(&err, LuaError::RuntimeError { .. }), "{err}");
+        assert!(err.to_string().ends_with(": no config"), "{err}");
+    }
+
+    let err = LuaPool::new(1, "return +").submit(|_| 1).recv().unwrap_err();
+    assert!(err.to_string().contains("unexpected symbol"), "{err}");
+}
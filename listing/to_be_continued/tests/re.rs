@@ -0,0 +1,137 @@
+#![cfg(feature = "re")]
+
+mod common;
+
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::eval;
+
+// the whole match of @pattern in @s, or nil
+fn m(pattern: &str, s: &str) -> Value {
+    let mut state = ExeState::new();
+    state.globals().set("p", pattern);
+    state.globals().set("s", s);
+    let rets = state.exec_main(&parse::load("local caps = re.match(p, s) return caps and caps[0]".as_bytes()));
+    rets.into_iter().next().unwrap()
+}
+
+#[test]
+fn syntax() {
+    let none = Value::Nil;
+    for (pattern, s, expected) in [
+        ("a|b|cd", "xcd", "cd".into()),
+        ("colou?r", "the color", "color".into()),
+        ("a{2,3}", "aaaa", "aaa".into()),
+        ("a{2,3}?", "aaaa", "aa".into()),
+        ("a{2}", "a", none.clone()),
+        ("x*", "aaa", "".into()),
+        ("<.+>", "<a><b>", "<a><b>".into()),
+        ("<.+?>", "<a><b>", "<a>".into()),
+        ("[a-c]+", "xxbcaz", "bca".into()),
+        ("[^a-c ]+", "abc def", "def".into()),
+        ("[]a]+", "]a]b", "]a]".into()),
+        ("[a-]+", "-a-b", "-a-".into()),
+        ("[[:digit:]x]+", "ab1x2c", "1x2".into()),
+        ("[\\d.]+", "v1.25b", "1.25".into()),
+        ("\\w+@\\w+\\.com", "mail bob@example.com now", "bob@example.com".into()),
+        ("\\bcat\\b", "concat cat", "cat".into()),
+        ("\\Bcat", "cat concat", "cat".into()),
+        ("^ab", "cab", none.clone()),
+        ("b$", "ab\n", none.clone()),
+        ("(?m)^b$", "a\nb\nc", "b".into()),
+        ("a.c", "a\nc", none.clone()),
+        ("(?s)a.c", "a\nc", "a\nc".into()),
+        ("(?i)hello", "HeLLo", "HeLLo".into()),
+        ("(?i:a)b", "AB Ab", "Ab".into()),
+        ("[a-z]+(?i)X", "abx", "abx".into()),
+        ("\\x41\\x{263A}", "A\u{263A}", "A\u{263A}".into()),
+        ("é.", "aéé", "éé".into()),
+        ("\\.\\*", "a.*", ".*".into()),
+        ("(a|ab)(c|bcd)", "abcd", "abcd".into()),
+        ("(a*)*b", "aaab", "aaab".into()),
+        ("", "abc", "".into()),
+    ] {
+        assert_eq!(m(pattern, s), expected, "{pattern}");
+    }
+
+    // exponential for backtracking engines
+    let s = "a".repeat(30);
+    assert_eq!(m("(a*)*c", &s), Value::Nil);
+    assert_eq!(m("(a|aa)+$", &s), s.as_str().into());
+}
+
+#[test]
+fn captures() {
+    assert_eq!(eval(r#"
+        local date = re.compile([[(?P<y>\d{4})-(?<m>\d\d)-(\d\d)(T)?]])
+        local caps = date:match("on 2024-02-29 and 2025-01-01")
+        return caps[0], caps.y, caps.m, caps[1], caps[3], caps[4], date:is_match("2024-1-1")
+    "#), ["2024-02-29".into(), "2024".into(), "02".into(), "2024".into(), "29".into(), Value::Nil,
+        Value::Boolean(false)]);
+
+    assert_eq!(eval(r#"
+        local all = re.find_all("(\\w)(\\d)", "a1 b2 c3")
+        local some = re.find_all("\\d", "1 2 3", 2)
+        local i, j = re.find("\\d+", "ab123c")
+        local k = re.find("b", "abc", 3)
+        return #all, all[2][0], all[3][1], all[3][2], #some, i, j, k
+    "#), [Value::Integer(3), "b2".into(), "c".into(), "3".into(), Value::Integer(2),
+        Value::Integer(3), Value::Integer(5), Value::Nil]);
+
+    assert_eq!(eval(r#"
+        local t = {}
+        for caps in re.gmatch("a*", "baaac") do
+            t[#t+1] = "[" .. caps[0] .. "]"
+        end
+        return table.concat(t)
+    "#), ["[][aaa][]".into()]);
+}
+
+#[test]
+fn replace_split() {
+    assert_eq!(eval(r#"
+        local a, n = re.replace("(cat|dog)s?", "cats and dogs", "<$1>")
+        local b = re.replace("(?P<first>\\w+) (?P<last>\\w+)", "John Smith", "${last}, $first $$1")
+        local c = re.replace("\\d+", "1 22 333", function(caps) return #caps[0] end)
+        local d = re.replace("\\w+", "hello world", {hello = "bye"})
+        local e = re.replace("x*", "abc", "-")
+        local f = re.replace("a", "aaa", "b", 2)
+        return a, n, b, c, d, e, f
+    "#), ["<cat> and <dog>".into(), Value::Integer(2), "Smith, John $1".into(), "1 2 3".into(),
+        "bye world".into(), "-a-b-c-".into(), "bba".into()]);
+
+    assert_eq!(eval(r#"
+        local a = re.split("\\s*,\\s*", "a , b,c,, d")
+        local b = re.split(",", "a,b,c", 2)
+        return table.concat(a, "|"), #a, table.concat(b, "|"), re.escape("1.5*[x]")
+    "#), ["a|b|c||d".into(), Value::Integer(5), "a|b,c".into(), "1\\.5\\*\\[x\\]".into()]);
+}
+
+#[test]
+fn errors() {
+    let err = |source: &str| -> String {
+        let rets = eval(format!("local ok, msg = pcall(function() {source} end) return ok, msg"));
+        assert_eq!(rets[0], Value::Boolean(false), "{source}");
+        rets[1].to_string()
+    };
+    assert_eq!(err("re.compile('(ab')"),
+        "[string \"?\"]:1: bad argument #1 to 'compile' (invalid regex: unclosed group in '(ab')");
+    assert_eq!(err("re.match('ab)', 'x')"),
+        "[string \"?\"]:1: bad argument #1 to 'match' (invalid regex: unopened group in 'ab)')");
+    assert_eq!(err("re.compile('*a')"),
+        "[string \"?\"]:1: bad argument #1 to 'compile' (invalid regex: repetition operator missing expression in '*a')");
+    assert_eq!(err("re.compile('[z-a]')"),
+        "[string \"?\"]:1: bad argument #1 to 'compile' (invalid regex: invalid character class range, the start must be <= the end in '[z-a]')");
+    assert_eq!(err("re.compile('a', 'x')"), "[string \"?\"]:1: bad argument #2 to 'compile' (invalid flag 'x')");
+    assert_eq!(err("re.compile('\\\\q')"),
+        "[string \"?\"]:1: bad argument #1 to 'compile' (invalid regex: unrecognized escape sequence in '\\q')");
+    assert_eq!(err("re.compile('a{,2}')"),
+        "[string \"?\"]:1: bad argument #1 to 'compile' (invalid regex: repetition quantifier expects a valid decimal in 'a{,2}')");
+    assert_eq!(err("re.compile('(a{1000}){1000}')"),
+        "[string \"?\"]:1: bad argument #1 to 'compile' (invalid regex: Compiled regex exceeds size limit of 10485760 bytes.)");
+    assert_eq!(err("re.compile('x'):match({})"), "[string \"?\"]:1: bad argument #2 to 'match' (string expected, got table)");
+
+    // flags of compile()
+    assert_eq!(eval("return re.compile('^B.', 'ims'):match('a\\nb\\n')[0]"), ["b\n".into()]);
+}
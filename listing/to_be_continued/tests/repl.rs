@@ -0,0 +1,95 @@
+use lua_rs::repl::Repl;
+
+#[test]
+fn pretty_print() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval("t = {10, 'x', k = {a = 1}}"), Ok(String::new()));
+    assert_eq!(repl.eval("t").unwrap(), r#"{ 10, "x", k = { a = 1 } }"#);
+    assert_eq!(repl.eval("1, 2.5, nil, 'a\\n'").unwrap(), "1\t2.5\tnil\t\"a\\n\"");
+
+    // keys which are not names
+    assert_eq!(repl.eval("{['end'] = 1, ['a b'] = 2}").unwrap(), r#"{ ["a b"] = 2, ["end"] = 1 }"#);
+
+    assert_eq!(repl.eval("t.self = t").unwrap(), "");
+    assert_eq!(repl.eval("t").unwrap(), r#"{ 10, "x", k = { a = 1 }, self = <cycle> }"#);
+}
+
+#[test]
+fn history() {
+    let mut repl = Repl::new();
+    repl.eval("1").unwrap();
+    repl.eval("'two'").unwrap();
+    repl.eval("x = 100").unwrap(); // statements are not saved
+    repl.eval("_ .. '!'").unwrap();
+    assert_eq!(repl.eval("_3, _2, _").unwrap(), r#"1	"two"	"two!""#);
+    assert_eq!(repl.eval("_").unwrap(), "1");
+}
+
+#[test]
+fn errors() {
+    let mut repl = Repl::new();
+    assert!(repl.eval("x = ").is_err());
+    assert_eq!(repl.eval("do local t; t() end").unwrap_err(), "stdin:1: attempt to call a nil value (local 't')");
+
+    // the state is still usable after errors
+    assert_eq!(repl.eval("1 + 1").unwrap(), "2");
+}
+
+#[test]
+fn completion() {
+    let mut repl = Repl::new();
+    repl.eval("config = {name = 'x', nested = {deep = 1}}").unwrap();
+
+    assert_eq!(repl.complete("conf"), (0, vec!["config".to_string()]));
+    assert_eq!(repl.complete("x = wh"), (4, vec!["while".to_string()]));
+    assert_eq!(repl.complete("config.n"), (7, vec!["name".to_string(), "nested".to_string()]));
+    assert_eq!(repl.complete("f(config.nested.d"), (16, vec!["deep".to_string()]));
+    assert_eq!(repl.complete("string:re"), (7, vec!["rep".to_string(), "reverse".to_string()]));
+
+    // not a table
+    assert_eq!(repl.complete("config.name.x"), (12, vec![]));
+}
+
+#[test]
+fn session_locals() {
+    let mut repl = Repl::new();
+    repl.eval("local x = 1").unwrap();
+    assert_eq!(repl.eval("x").unwrap(), "1");
+
+    repl.eval("local a, b, c = 10, 20").unwrap();
+    assert_eq!(repl.eval("a, b, c").unwrap(), "10\t20\tnil");
+
+    repl.eval("local y = x + 1; local z <const> = y * 10").unwrap();
+    assert_eq!(repl.eval("y, z").unwrap(), "2\t20");
+
+    repl.eval("local function fact(n) if n <= 1 then return 1 end return n * fact(n-1) end").unwrap();
+    assert_eq!(repl.eval("fact(5)").unwrap(), "120");
+
+    // locals in inner blocks and functions are still locals
+    repl.eval("do local inner = 1 end").unwrap();
+    repl.eval("local function f() local hidden = 2 return hidden end").unwrap();
+    assert_eq!(repl.eval("inner, hidden, f()").unwrap(), "nil\tnil\t2");
+
+    repl.eval("for i = 1, 3 do local loop = i end").unwrap();
+    assert_eq!(repl.eval("i, loop").unwrap(), "nil\tnil");
+}
+
+#[test]
+fn incomplete() {
+    for source in ["function f()", "if x then", "t = {1,", "s = [[abc", "--[[ comment",
+            "local function", "for i = 1, 3 do", "repeat", "print(", "1 +", "a."] {
+        assert!(!Repl::is_complete(source), "{source}");
+    }
+    // syntax errors before the end
+    for source in ["x = 1", "1 + 1", "x = = 1", "x = 'abc", "return 1 end", "end", ")"] {
+        assert!(Repl::is_complete(source), "{source}");
+    }
+
+    let mut repl = Repl::new();
+    let source = "function add(a, b)\n  return a + b\nend";
+    assert!(!Repl::is_complete("function add(a, b)\n  return a + b"));
+    assert!(Repl::is_complete(source));
+    repl.eval(source).unwrap();
+    assert_eq!(repl.eval("add(1,\n2)").unwrap(), "3");
+    assert_eq!(repl.eval("[[a\nb]]").unwrap(), "\"a\\nb\"");
+}
@@ -0,0 +1,114 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use lua_rs::parse;
+use lua_rs::value::{Value, Table};
+use lua_rs::vm::ExeState;
+
+static NLOAD: AtomicUsize = AtomicUsize::new(0);
+static TOTAL: AtomicI64 = AtomicI64::new(0);
+
+fn add(state: &mut ExeState) -> i32 {
+    TOTAL.fetch_add(state.get::<i64>(1), Ordering::Relaxed);
+    0
+}
+
+// module factory, which returns a table of functions
+fn load_counter(state: &mut ExeState) -> i32 {
+    NLOAD.fetch_add(1, Ordering::Relaxed);
+    assert_eq!(state.get::<&Value>(1), &Value::from("counter"));
+
+    let mut t = Table::new(0, 1);
+    t.map.insert("add".into(), Value::RustFunction(add));
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    1
+}
+
+#[test]
+fn native_module() {
+    let proto = parse::load(r#"
+        local c = require "counter"
+        c.add(2)
+        c.add(3)
+        local again = require "counter"
+        again.add(10)
+        -- Lua files are searched first
+        require("test_lua.mod.noreturn")
+    "#.as_bytes());
+
+    let mut state = ExeState::new();
+    state.register_module("counter", load_counter);
+    state.register_module("test_lua.mod.noreturn", load_counter);
+    state.execute(&proto, &[]);
+
+    assert_eq!(NLOAD.load(Ordering::Relaxed), 1);
+    assert_eq!(TOTAL.load(Ordering::Relaxed), 15);
+}
+
+fn is_nil(state: &mut ExeState) -> i32 {
+    assert_eq!(state.get::<&Value>(1), &Value::Nil);
+    0
+}
+fn equal(state: &mut ExeState) -> i32 {
+    assert_eq!(state.get::<&Value>(1), state.get::<&Value>(2));
+    0
+}
+fn load_check(state: &mut ExeState) -> i32 {
+    let mut t = Table::new(0, 2);
+    t.map.insert("is_nil".into(), Value::RustFunction(is_nil));
+    t.map.insert("equal".into(), Value::RustFunction(equal));
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    1
+}
+
+#[test]
+fn isolated_module() {
+    let proto = parse::load(r#"
+        local check = require "check"
+        package.isolate = true
+        local m = require "test_lua.mod.pollute"
+        check.equal(m, "xxx")
+        check.is_nil(polluted)
+
+        -- globals assigned later are visible too
+        local get_late = require "test_lua.mod.late"
+        check.is_nil(get_late())
+        late = "yyy"
+        check.equal(get_late(), "yyy")
+    "#.as_bytes());
+
+    let mut state = ExeState::new();
+    state.register_module("check", load_check);
+    state.execute(&proto, &[]);
+}
+
+#[test]
+fn preload() {
+    let mut state = ExeState::new();
+    let mut nload = 0;
+    state.preload_module("test_lua.mod.greet", move |state| {
+        nload += 1;
+        let name = state.get::<&Value>(1).to_string();
+        let data = state.get::<&Value>(2).to_string();
+        state.push(format!("{name} {data} {nload}"));
+        1
+    });
+    let rets = state.exec_main(&parse::load(r#"
+        package.preload.answer = function(...) return {...} end
+        local answer = require "answer"
+        -- before the Lua files
+        local greet = require "test_lua.mod.greet"
+        return greet, require "test_lua.mod.greet", answer[1], answer[2], require "answer" == answer,
+            package.loaded.answer == answer
+    "#.as_bytes()));
+    assert_eq!(rets, ["test_lua.mod.greet :preload: 1".into(), "test_lua.mod.greet :preload: 1".into(),
+        "answer".into(), ":preload:".into(), Value::Boolean(true), Value::Boolean(true)]);
+
+    let rets = ExeState::new().exec_main(&parse::load(r#"
+        local ok, msg = pcall(require, "nothing")
+        return ok, msg
+    "#.as_bytes()));
+    assert_eq!(rets[0], Value::Boolean(false));
+    assert!(rets[1].to_string().starts_with("module 'nothing' not found:\n\tno field package.preload['nothing']\n\tno file './nothing.lua'"),
+        "{}", rets[1]);
+}
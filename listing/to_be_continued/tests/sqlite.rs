@@ -0,0 +1,98 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use lua_rs::parse;
+use lua_rs::stdlib::sqlite::{Database, Statement};
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::eval;
+
+#[test]
+fn query() {
+    assert_eq!(eval(r#"
+        local db = sqlite.open(":memory:")
+        db:exec([[
+            CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, avatar BLOB);
+            INSERT INTO users (name, score) VALUES ('alice', 1.5);
+        ]])
+        local insert = db:prepare("INSERT INTO users (name, score, avatar) VALUES (?, ?, ?)")
+        insert:bind("bob", 2, bytes.new("\0\1")):step()
+        local id = db:last_insert_rowid()
+        insert:reset(true):bind("carol"):step()
+
+        local function str(v) return v == nil and "nil" or v .. "" end
+        local t = {}
+        for row in db:rows("SELECT * FROM users WHERE id >= ? ORDER BY id", 1) do
+            t[#t+1] = table.concat({row.id, row.name, str(row.score), str(row.avatar)}, ",")
+        end
+        return table.concat(t, ";"), id, db:changes()
+    "#), ["1,alice,1.5,nil;2,bob,2.0,\0\u{1};3,carol,nil,nil".into(), Value::Integer(2),
+        Value::Integer(1)]);
+
+    // named parameters, and the statement run again
+    assert_eq!(eval(r#"
+        local db = sqlite.open(":memory:")
+        db:exec("CREATE TABLE kv (k TEXT, v); INSERT INTO kv VALUES ('a', 1), ('b', 2), ('c', 3)")
+        local stmt = db:prepare("SELECT k, v * 10 AS ten FROM kv WHERE v >= :min AND v <= $max")
+        stmt:bind({min = 2, max = 3})
+        local sum = 0
+        for _ = 1, 2 do
+            for row in stmt:rows() do
+                sum = sum + row.ten
+            end
+        end
+        local columns = stmt:columns()
+        local has_row = stmt:step()
+        local first = stmt:row()
+        return sum, columns[1], columns[2], has_row, first.k, first.ten
+    "#), [Value::Integer(100), "k".into(), "ten".into(), Value::Boolean(true), "b".into(),
+        Value::Integer(20)]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(eval(r#"
+        local db = sqlite.open(":memory:")
+        local stmt, msg = db:prepare("SELECT * FROM missing")
+        local ok, err = pcall(db.exec, db, "CREATE TABLE")
+        local ok2, err2 = pcall(function()
+            db:exec("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            db:prepare("INSERT INTO t VALUES (?)"):bind(1, 2)
+        end)
+        local s = db:prepare("SELECT 1")
+        s:finalize()
+        db:close()
+        local ok3, err3 = pcall(db.exec, db, "SELECT 1")
+        local ok4, err4 = pcall(s.step, s)
+        return stmt, msg, ok, err, ok2, err2, ok3, err3, ok4, err4
+    "#), [Value::Nil, "no such table: missing".into(),
+        Value::Boolean(false), "incomplete input".into(),
+        Value::Boolean(false), "[string \"?\"]:7: bad argument #3 to 'bind' (only 1 parameters)".into(),
+        Value::Boolean(false), "attempt to use a closed database".into(),
+        Value::Boolean(false), "attempt to use a finalized statement".into()]);
+
+    let dir = std::env::temp_dir().join(format!("lua-rs-sqlite-{}", std::process::id()));
+    let rets = eval(format!("return sqlite.open('{}/no/such.db')", dir.display()));
+    assert_eq!(rets[0], Value::Nil);
+    assert!(rets[1].to_string().ends_with("such.db: unable to open database file"), "{}", rets[1]);
+}
+
+// a statement keeps its database open after the database is freed
+#[test]
+fn lifetime() {
+    let mut state = ExeState::new();
+    let rets = state.exec_main(&parse::load(r#"
+        local stmt = sqlite.open(":memory:"):prepare("SELECT 42 AS answer")
+        collectgarbage()
+        local db = sqlite.open(":memory:")
+        return stmt, db, stmt:rows()().answer
+    "#.as_bytes()));
+    assert_eq!(rets[2], Value::Integer(42));
+
+    // the userdata of the Rust types
+    let Value::UserData(stmt) = &rets[0] else { panic!() };
+    assert!(stmt.borrow().is::<Statement>());
+    let Value::UserData(db) = &rets[1] else { panic!() };
+    assert!(db.borrow().is::<Database>());
+}
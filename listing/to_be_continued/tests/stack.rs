@@ -0,0 +1,57 @@
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+const RECURSION: &str = r#"
+    local function f(n)
+        local a, b, c, d, e, g, h, i = 1, 2, 3, 4, 5, 6, 7, 8
+        if n == 0 then return 0 end
+        return 1 + f(n - 1)
+    end
+    f(N)
+"#;
+
+fn run(state: &mut ExeState, depth: usize) {
+    let proto = parse::load(RECURSION.replace('N', &depth.to_string()).as_bytes());
+    state.execute(&proto, &[]);
+}
+
+#[test]
+fn within_limits() {
+    run(&mut ExeState::new(), 100);
+    run(&mut ExeState::builder().stack_size(0).max_stack_size(2000).build(), 100);
+}
+
+#[test]
+#[should_panic(expected = "stack overflow")]
+fn max_stack_size() {
+    run(&mut ExeState::builder().max_stack_size(500).build(), 100);
+}
+
+// Lua calling Lua is not limited by max_call_depth()
+#[test]
+fn deep_recursion() {
+    run(&mut ExeState::new(), 10000);
+    run(&mut ExeState::builder().max_call_depth(50).build(), 100);
+}
+
+// but Rust calling Lua is, e.g. by pcall() or metamethods
+#[test]
+fn max_call_depth() {
+    let proto = parse::load(&b"
+        local function f(n)
+            if n == 0 then return 0 end
+            local ok, r = pcall(f, n - 1)
+            if not ok then error(r, 0) end
+            return r + 1
+        end
+        local ok1, r1 = pcall(f, 40)
+        local ok2, r2 = pcall(f, 60)
+        local t = setmetatable({}, {__index = function(t, k) return k == 0 and 0 or t[k - 1] + 1 end})
+        local ok3, r3 = pcall(function() return t[100] end)
+        return r1, ok2, r2, ok3, r3
+    "[..]);
+    let rets = ExeState::builder().max_call_depth(50).build().exec_main(&proto);
+    assert_eq!(rets, [Value::Integer(40), Value::Boolean(false), "C stack overflow".into(),
+        Value::Boolean(false), "[string \"?\"]:10: C stack overflow".into()]);
+}
@@ -0,0 +1,73 @@
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::{ExeState, ExecLimit};
+
+fn exec(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn counters() {
+    let mut state = ExeState::new();
+    assert_eq!(state.stats().instructions, 0);
+    assert_eq!(state.stats().calls, 0);
+
+    exec(&mut state, r#"
+        local function depth(n)
+            if n > 0 then return 1 + depth(n - 1) end
+            return 0
+        end
+        depth(10)
+        local t = {}
+        for i = 1, 5 do
+            t[i] = { tostring = "a long string, not short" .. i }
+        end
+    "#);
+    let stats = state.stats();
+    assert!(stats.instructions > 50, "{stats:?}");
+    assert_eq!(stats.calls, 11);
+    assert_eq!(stats.peak_call_depth, 11);
+    assert!(stats.peak_stack_size > 11, "{stats:?}");
+    assert!(stats.tables >= 6, "{stats:?}");
+    assert!(stats.strings >= 5, "{stats:?}");
+    assert_eq!(stats.gc_cycles, 0);
+
+    // accumulated
+    exec(&mut state, "type(1)");
+    assert_eq!(state.stats().calls, 12);
+    assert!(state.stats().instructions > stats.instructions);
+}
+
+// exact numbers of byte codes, with and without limits
+#[test]
+fn instructions() {
+    let source = "local n = 0; for i = 1, 100 do n = n + i end; return n";
+    let proto = parse::load(source.as_bytes());
+    let f = Value::LuaFunction(proto.into());
+
+    let mut state = ExeState::new();
+    state.pcall(f.clone(), &[]).unwrap();
+    let once = state.stats().instructions;
+    state.pcall_with_limit(f.clone(), &[], ExecLimit::Instructions(10_000)).unwrap();
+    assert_eq!(state.stats().instructions, once * 2);
+
+    // executed ones are counted when the limit is exceeded
+    state.pcall_with_limit(f, &[], ExecLimit::Instructions(50)).unwrap_err();
+    assert_eq!(state.stats().instructions, once * 2 + 50);
+}
+
+#[test]
+fn vmstats() {
+    let mut state = ExeState::new();
+    let rets = exec(&mut state, r#"
+        local function f() end
+        f()
+        f()
+        local t = {}
+        local s = vmstats()
+        return s.calls, s.peak_call_depth, s.gc_cycles, s.instructions > 0, s.tables > 0
+    "#);
+    // vmstats() is called too
+    assert_eq!(rets, [Value::Integer(3), Value::Integer(1), Value::Integer(0),
+        Value::Boolean(true), Value::Boolean(true)]);
+}
@@ -0,0 +1,278 @@
+mod common;
+
+use std::panic;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::{eval, script};
+
+fn format(fmt: &str, args: &str) -> String {
+    eval(format!("return string.format({fmt}, {args})"))[0].to_string()
+}
+
+fn format_error(fmt: &str, args: &str) -> String {
+    eval_error(&format!("return string.format({fmt}, {args})"))
+}
+
+fn eval_error(source: &str) -> String {
+    let err = panic::catch_unwind(|| eval(source)).unwrap_err();
+    match err.downcast_ref::<String>() {
+        Some(s) => s.clone(),
+        None => err.downcast_ref::<&str>().unwrap().to_string(),
+    }
+}
+
+// Reference outputs in format_ref.txt are generated by glibc's printf(),
+// one case per line: type of the argument, `f` for float or `i` for
+// integer, the format, the argument, and the output, separated by tabs.
+#[test]
+fn format_conformance() {
+    let mut fails = Vec::new();
+    let cases = include_str!("format_ref.txt");
+    for line in cases.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [kind, fmt, arg, expect] = fields[..] else {
+            panic!("invalid case: {line:?}");
+        };
+        let arg = match (kind, arg) {
+            ("f", "inf") => String::from("1/0"),
+            ("f", "-inf") => String::from("-1/0"),
+            ("f", _) => format!("{:?}", arg.parse::<f64>().unwrap()),
+            (_, "-9223372036854775808") => String::from("-9223372036854775807 - 1"),
+            _ => arg.to_string(),
+        };
+        let got = format(&format!("'{fmt}'"), &arg);
+        if got != expect {
+            fails.push(format!("format({fmt:?}, {arg}): expect {expect:?}, got {got:?}"));
+        }
+    }
+    assert!(fails.is_empty(), "{} of {} cases fail:\n{}",
+        fails.len(), cases.lines().count(), fails.join("\n"));
+}
+
+#[test]
+fn format_basic() {
+    assert_eq!(format("'%d%%, %s, %5.2s|, %-4s|, %c%c'", "10, 'str', 'abc', 'ab', 76, 117"),
+        "10%, str,    ab|, ab  |, Lu");
+    assert_eq!(format("'%s %s %s'", "nil, true, 1.5"), "nil true 1.5");
+    assert_eq!(format("'%5s|'", "12"), "   12|");
+
+    // numeric strings and floats with integer values
+    assert_eq!(format("'%d %x %.1f'", "'12', 255.0, '0.25'"), "12 ff 0.2");
+
+    // no modifiers, so zeros are kept
+    assert_eq!(format("'%s'", "'a\\0b'"), "a\0b");
+
+    // %p
+    assert_eq!(format("'%p'", "1"), "(null)");
+    assert!(format("'%p'", "{}").starts_with("0x"));
+}
+
+#[test]
+fn format_errors() {
+    assert_eq!(format_error("'%d %d'", "1"), "[string \"?\"]:1: bad argument #3 to 'format' (no value)");
+    assert_eq!(format_error("'%d'", "1.5"),
+        "[string \"?\"]:1: bad argument #2 to 'format' (number has no integer representation)");
+    assert_eq!(format_error("'%f'", "{}"), "[string \"?\"]:1: bad argument #2 to 'format' (number expected, got table)");
+    assert_eq!(format_error("'%10s'", "'a\\0b'"), "[string \"?\"]:1: bad argument #2 to 'format' (string contains zeros)");
+    assert_eq!(format_error("'%y'", "1"), "[string \"?\"]:1: invalid conversion '%y' to 'format'");
+    assert_eq!(format_error("'%100d'", "1"), "[string \"?\"]:1: invalid conversion specification: '%100d'");
+    assert_eq!(format_error("'%.123f'", "1"), "[string \"?\"]:1: invalid conversion specification: '%.123f'");
+    assert_eq!(format_error("'%#d'", "1"), "[string \"?\"]:1: invalid conversion specification: '%#d'");
+    assert_eq!(format_error("'%05s'", "'a'"), "[string \"?\"]:1: invalid conversion specification: '%05s'");
+    assert_eq!(format_error("'%.3c'", "65"), "[string \"?\"]:1: invalid conversion specification: '%.3c'");
+    assert_eq!(format_error("'%-----------------------d'", "1"), "[string \"?\"]:1: invalid format string to 'format'");
+}
+
+// Relative positions of all combinations, including the extremes,
+// against the definition in the manual: negative positions count from
+// the end, and then i is corrected to 1 at least, and j to the length
+// at most.
+#[test]
+fn relative_positions() {
+    let lua_int = |n: i64| match n {
+        i64::MIN => String::from("(-9223372036854775807 - 1)"),
+        n => format!("({n})"),
+    };
+    let positions: Vec<i64> = (-7..=7).chain([i64::MIN, i64::MIN + 1, i64::MAX]).collect();
+
+    for s in ["", "a", "ab", "abcde"] {
+        let len = s.len() as i64;
+        let rel = |k: i64| if k < 0 { len + k + 1 } else { k };
+        for &i in &positions {
+            let mut source = String::from("return ");
+            let mut expect = Vec::new();
+            for &j in &positions {
+                let (start, end) = (rel(i).max(1), rel(j).min(len));
+                let sub = if start > end { "" } else { &s[start as usize - 1..end as usize] };
+                expect.push(Value::from(sub));
+                source += &format!("string.sub('{s}', {}, {}), ", lua_int(i), lua_int(j));
+            }
+            source += "nil";
+            expect.push(Value::Nil);
+            assert_eq!(eval(&source), expect, "string.sub({s:?}, {i}, j)");
+        }
+    }
+}
+
+#[test]
+fn sub_byte() {
+    assert_eq!(eval("return string.sub('hello', 2), string.sub('hello', -3, -2), string.sub(12345, 2, 3)"),
+        ["ello".into(), "ll".into(), "23".into()]);
+    assert_eq!(eval("return string.byte('ABC'), string.byte('ABC', -1), string.byte('ABC', 10)"),
+        [Value::Integer(65), Value::Integer(67)]);
+    assert_eq!(eval("return string.byte('ABC', 1, -1)"),
+        [Value::Integer(65), Value::Integer(66), Value::Integer(67)]);
+    assert_eq!(eval("return string.byte('')"), []);
+}
+
+#[test]
+fn find_plain() {
+    assert_eq!(eval("return string.find('hello world', 'o')"), [Value::Integer(5), Value::Integer(5)]);
+    assert_eq!(eval("return string.find('hello world', 'o', 6)"), [Value::Integer(8), Value::Integer(8)]);
+    assert_eq!(eval("return string.find('hello world', 'world', -5)"), [Value::Integer(7), Value::Integer(11)]);
+    assert_eq!(eval("return string.find('hello world', 'xyz')"), [Value::Nil]);
+    assert_eq!(eval("return string.find('hello', 'l', 10)"), [Value::Nil]);
+
+    // empty pattern matches at @init, even after the end
+    assert_eq!(eval("return string.find('abc', '', 2)"), [Value::Integer(2), Value::Integer(1)]);
+    assert_eq!(eval("return string.find('abc', '', 4)"), [Value::Integer(4), Value::Integer(3)]);
+    assert_eq!(eval("return string.find('abc', '', 5)"), [Value::Nil]);
+
+    // special characters are literal in plain mode
+    assert_eq!(eval("return string.find('a.b(c)%d', '(c)%', 1, true)"), [Value::Integer(4), Value::Integer(7)]);
+    assert_eq!(eval("return string.find('1 + 2', '+', 1, true)"), [Value::Integer(3), Value::Integer(3)]);
+
+    // the windows skipped by the last bytes
+    let rets = eval(r#"
+        local line = string.rep("ab", 100) .. "abcab" .. string.rep("ab", 10)
+        return string.find(line, "abcab"), string.find(line, "bcb"), string.find("aaab", "aab")
+    "#);
+    assert_eq!(rets, [Value::Integer(201), Value::Nil, Value::Integer(2), Value::Integer(4)]);
+}
+
+#[test]
+fn len_char_reverse() {
+    assert_eq!(eval("return string.len(''), string.len('hello'), string.len(123), #string.rep('x', 50)"),
+        [Value::Integer(0), Value::Integer(5), Value::Integer(3), Value::Integer(50)]);
+    assert_eq!(eval("return string.char(72, 105), string.char(), string.char(0, 255):byte(1, -1)"),
+        ["Hi".into(), "".into(), Value::Integer(0), Value::Integer(255)]);
+    assert_eq!(eval("return string.reverse('hello'), string.reverse('')"), ["olleh".into(), "".into()]);
+
+    assert_eq!(eval_error("return string.char(65, 256)"), "[string \"?\"]:1: bad argument #2 to 'char' (value out of range)");
+}
+
+#[test]
+fn methods() {
+    let rets = eval(r#"
+        local s = "Hello"
+        return s:upper(), s:len(), ("abc"):rep(2, "-"), s:sub(2, 3):reverse(), s.lower(s)
+    "#);
+    assert_eq!(rets, ["HELLO".into(), Value::Integer(5), "abc-abc".into(), "le".into(), "hello".into()]);
+
+    // shared by all strings, and missing fields are nil
+    let rets = eval(r#"
+        local mt = getmetatable("")
+        return mt.__index == string, getmetatable("x") == mt, ("x").nothing, getmetatable(1)
+    "#);
+    assert_eq!(rets, [Value::Boolean(true), Value::Boolean(true), Value::Nil, Value::Nil]);
+
+    assert_eq!(eval_error("local n = 1; return n:len()"), "[string \"?\"]:1: attempt to index a number value (local 'n')");
+}
+
+#[test]
+fn gmatch() {
+    let rets = eval(r#"
+        local words, pairs = {}, {}
+        for w in string.gmatch("one two  three", "%a+") do words[#words+1] = w end
+        for k, v in ("a=1, b=2"):gmatch("(%w+)=(%w+)") do pairs[#pairs+1] = k .. v end
+        local positions = {}
+        for p in string.gmatch("abc", "()", 2) do positions[#positions+1] = p end
+        return #words, words[3], pairs[1], pairs[2], #positions, positions[1]
+    "#);
+    assert_eq!(rets, [Value::Integer(3), "three".into(), "a1".into(), "b2".into(),
+        Value::Integer(3), Value::Integer(2)]);
+
+    assert_eq!(eval_error("return string.gmatch('x', '[a')"), "[string \"?\"]:1: malformed pattern (missing ']')");
+}
+
+#[test]
+fn find_match() {
+    assert_eq!(eval("return string.find('hello world', 'o%s*w')"), [Value::Integer(5), Value::Integer(7)]);
+    assert_eq!(eval("return string.find('key = val', '(%w+)%s*=%s*(%w+)')"),
+        [Value::Integer(1), Value::Integer(9), "key".into(), "val".into()]);
+    assert_eq!(eval("return string.find('abc', '^b'), string.find('abc', '^b', 2)"),
+        [Value::Nil, Value::Integer(2), Value::Integer(2)]);
+    assert_eq!(eval("return string.find('abc', '()c')"), [Value::Integer(3), Value::Integer(3), Value::Integer(3)]);
+
+    assert_eq!(eval("return string.match('  trim me  ', '^%s*(.-)%s*$')"), ["trim me".into()]);
+    assert_eq!(eval("return string.match('2024-01-15', '(%d+)-(%d+)-(%d+)')"),
+        ["2024".into(), "01".into(), "15".into()]);
+    assert_eq!(eval("return ('x = 10'):match('%d+'), string.match('abc', '%d'), string.match('abc', '.', -1)"),
+        ["10".into(), Value::Nil, "c".into()]);
+}
+
+#[test]
+fn gsub() {
+    assert_eq!(eval("return string.gsub('hello world', 'o', '0')"), ["hell0 w0rld".into(), Value::Integer(2)]);
+    assert_eq!(eval("return string.gsub('hello world', '(%w+)', '<%1>')"),
+        ["<hello> <world>".into(), Value::Integer(2)]);
+    assert_eq!(eval("return string.gsub('hello', '', '-')"), ["-h-e-l-l-o-".into(), Value::Integer(6)]);
+    assert_eq!(eval("return string.gsub('abc', '%w', '%0%0', 2)"), ["aabbc".into(), Value::Integer(2)]);
+    assert_eq!(eval("return string.gsub('abc', '^.', '%1%%')"), ["a%bc".into(), Value::Integer(1)]);
+    assert_eq!(eval("return string.gsub('a,b', '(%w)', '%1', 0)"), ["a,b".into(), Value::Integer(0)]);
+
+    // tables and functions, where false and nil keep the match
+    let rets = eval(r#"
+        local vars = {name = "Lua", version = 5.4}
+        local s1 = string.gsub("$name $version $none", "%$(%w+)", vars)
+        local s2 = string.gsub("1 2 3", "%d", function(d) if d ~= "2" then return d * 10 end end)
+        return s1, s2
+    "#);
+    assert_eq!(rets, ["Lua 5.4 $none".into(), "10 2 30".into()]);
+
+    assert_eq!(eval_error("return string.gsub('a', 'a', '%2')"), "[string \"?\"]:1: invalid capture index %2 in replacement string");
+    assert_eq!(eval_error("return string.gsub('a', 'a', '%x')"), "[string \"?\"]:1: invalid use of '%' in replacement string");
+    assert_eq!(eval_error("return string.gsub('a', 'a', function() return {} end)"), "[string \"?\"]:1: invalid replacement value (a table)");
+}
+
+#[test]
+fn format_quoted() {
+    assert_eq!(format("'%q'", "'he said \"hi\"\\n\\\\'"), "\"he said \\\"hi\\\"\\\n\\\\\"");
+    assert_eq!(format("'%q'", "'\\0\\r1\\0012\\127'"), "\"\\0\\0131\\0012\\127\"");
+    assert_eq!(format("'%q %q %q %q'", "1, -2, nil, true"), "1 -2 nil true");
+    assert_eq!(format("'%q %q'", "-9223372036854775807 - 1, 0.5"), "0x8000000000000000 0x1p-1");
+    assert_eq!(format("'%q %q %q'", "1/0, -1/0, 0/0"), "1e9999 -1e9999 (0/0)");
+    assert_eq!(format_error("'%10q'", "1"), "[string \"?\"]:1: specifier '%q' cannot have modifiers");
+    assert_eq!(format_error("'%q'", "{}"), "[string \"?\"]:1: bad argument #2 to 'format' (value has no literal form)");
+
+    // read back as the same values, where the quoted strings are not UTF-8
+    let values = r#"
+        "a\0b\r\n\t\"\\", "\0011\2550\x80", 42, -7, -9223372036854775807 - 1,
+        0.1, -1.5e300, 2^63, 1/0, -1/0
+    "#;
+    let quoted = eval(format!("return string.format(string.rep('%q', 10, ','), {values})"));
+    let source = [b"return ", quoted[0].as_bytes().unwrap()].concat();
+    let rets = ExeState::new().exec_main(&parse::load(source.as_slice()));
+    assert_eq!(rets, eval(format!("return {values}")));
+
+    let nan = eval(format!("local x = {}; return x ~= x", format("'%q'", "0/0")));
+    assert_eq!(nan, [Value::Boolean(true)]);
+}
+
+// string.rep with separators, by test_lua/string_rep*.lua
+#[test]
+fn rep_scripts() {
+    assert_eq!(script("string_rep.lua").unwrap(), "\
+ababab
+ab, ab, ab
+ab
+
+
+----
+12312
+298
+");
+    assert_eq!(script("string_rep_overflow.lua").unwrap_err(),
+        "test_lua/string_rep_overflow.lua:2: resulting string too large");
+}
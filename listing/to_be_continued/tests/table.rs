@@ -0,0 +1,219 @@
+mod common;
+
+use std::panic;
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::{eval, error, script};
+
+// check the list sorted by @comp, where @init fills the list `t`
+fn sorted(init: &str, comp: &str) -> bool {
+    let source = format!(r#"
+        local t = {{}}
+        {init}
+        local n = #t
+        table.sort(t, {comp})
+        if #t ~= n then return false end
+        local lt = {comp} or function(a, b) return a < b end
+        for i = 2, #t do
+            if lt(t[i], t[i-1]) then return false end
+        end
+        return true"#);
+    eval(&source) == [Value::Boolean(true)]
+}
+
+#[test]
+fn sort() {
+    assert_eq!(eval("local t = {3, 1, 2} table.sort(t) return t[1], t[2], t[3]"),
+        [Value::Integer(1), Value::Integer(2), Value::Integer(3)]);
+    assert_eq!(eval("local t = {'b', 'c', 'a'} table.sort(t, function(a, b) return a > b end) \
+            return t[1], t[3]"),
+        ["c".into(), "a".into()]);
+    assert_eq!(eval("local t = {} table.sort(t) table.sort({1}) return #t"), [Value::Integer(0)]);
+
+    // pseudo-random, sorted, reversed, and equal elements, which are
+    // long enough for randomized pivots
+    let random = "local x = 7 for i = 1, 2000 do x = (x * 1103515245 + 12345) % 2147483648 t[i] = x % 1000 end";
+    assert!(sorted(random, "nil"));
+    assert!(sorted(random, "function(a, b) return a > b end"));
+    assert!(sorted("for i = 1, 1000 do t[i] = i end", "nil"));
+    assert!(sorted("for i = 1, 1000 do t[i] = -i end", "nil"));
+    assert!(sorted("for i = 1, 1000 do t[i] = 5 end", "nil"));
+    assert!(sorted("for i = 1, 1000 do t[i] = i % 2 + 0.5 end", "nil"));
+}
+
+#[test]
+fn sort_errors() {
+    assert_eq!(error("table.sort(1)"), "[string \"?\"]:1: bad argument #1 to 'sort' (table expected, got number)");
+    assert_eq!(error("table.sort({}, 1)"), "[string \"?\"]:1: bad argument #2 to 'sort' (function expected, got number)");
+    assert_eq!(error("table.sort({1, 'x'})"), "[string \"?\"]:1: attempt to compare string with number");
+
+    // inconsistent comparators are detected, and do not break the list
+    for comp in ["function(a, b) return true end", "function(a, b) return a <= b end"] {
+        let source = format!("local t = {{}} for i = 1, 100 do t[i] = i % 3 end \
+            table.sort(t, {comp})");
+        assert_eq!(error(&source), "[string \"?\"]:1: invalid order function for sorting");
+    }
+
+    // the list is unchanged by errors of the comparator
+    let mut state = ExeState::new();
+    let t = state.exec_main(&parse::load(&b"t = {3, 2, 1} return t"[..])).remove(0);
+    let sort = state.exec_main(&parse::load(&b"return table.sort"[..])).remove(0);
+    let comp = state.exec_main(&parse::load(&b"return function(a, b) return a < nil end"[..])).remove(0);
+    assert!(state.pcall(sort, &[t, comp]).is_err());
+    assert_eq!(state.exec_main(&parse::load(&b"return t[1], t[2], t[3]"[..])),
+        [Value::Integer(3), Value::Integer(2), Value::Integer(1)]);
+}
+
+// count entries by pairs(), after running @source on the table `t`
+fn count_pairs(init: &str, body: &str) -> Vec<Value> {
+    eval(format!("local t = {{}} {init} \
+        local n, sum = 0, 0 \
+        for k, v in pairs(t) do {body} n = n + 1 sum = sum + v end \
+        return n, sum"))
+}
+
+#[test]
+fn next() {
+    assert_eq!(eval("return next({})"), [Value::Nil]);
+    assert_eq!(eval("return next({10})"), [Value::Integer(1), Value::Integer(10)]);
+    assert_eq!(eval("return next({10, 20}, 1)"), [Value::Integer(2), Value::Integer(20)]);
+    assert_eq!(eval("return next({10, 20}, 2.0)"), [Value::Nil]);
+    assert_eq!(eval("return next({x = 1}, 'x')"), [Value::Nil]);
+    assert_eq!(error("next({x = 1}, 'y')"), "[string \"?\"]:1: invalid key to 'next'");
+    assert_eq!(error("next(1)"), "[string \"?\"]:1: bad argument #1 to 'next' (table expected, got number)");
+    assert_eq!(error("for k in pairs(nil) do end"),
+        "[string \"?\"]:1: bad argument #1 to 'for iterator' (table expected, got nil)");
+
+    let init = "for i = 1, 100 do t[i] = i t['k' .. i] = i end";
+    assert_eq!(count_pairs(init, ""), [Value::Integer(200), Value::Integer(10100)]);
+
+    // assigning nil to the current and other fields during traversal
+    assert_eq!(count_pairs(init, "t[k] = nil"), [Value::Integer(200), Value::Integer(10100)]);
+    assert_eq!(eval(format!("local t = {{}} {init} \
+            for k in pairs(t) do t[k] = nil end return next(t)")), [Value::Nil]);
+    let [Value::Integer(n), _] = count_pairs(init, "t['k' .. (101 - v)] = nil t[101 - v] = nil")[..] else {
+        panic!("not integers");
+    };
+    assert!(n < 200);
+
+    // assigning existing fields
+    assert_eq!(count_pairs(init, "t[k] = v * 2"), [Value::Integer(200), Value::Integer(10100)]);
+
+    // nested traversals of the same table
+    assert_eq!(count_pairs(init, "for _ in pairs(t) do end"), [Value::Integer(200), Value::Integer(10100)]);
+
+    // adding fields is undefined, but safe
+    eval(format!("local t = {{}} {init} \
+        local n = 0 \
+        for k in pairs(t) do n = n + 1 if n < 1000 then t['new' .. n] = n end end"));
+}
+
+#[test]
+fn insert_remove() {
+    assert_eq!(eval("local t = {} table.insert(t, 'a') table.insert(t, 1, 'b') \
+            table.insert(t, 3, 'c') table.insert(t, 2, 'd') \
+            return table.concat(t, ','), #t"),
+        ["b,d,a,c".into(), Value::Integer(4)]);
+    assert_eq!(eval("local t = {1, 2, 3} \
+            return table.remove(t, 1), table.remove(t), #t, t[1], t[2]"),
+        [Value::Integer(1), Value::Integer(3), Value::Integer(1), Value::Integer(2), Value::Nil]);
+
+    // #t + 1, and 0 of empty lists
+    assert_eq!(eval("local t = {1} return table.remove(t, 2), table.remove({}), #t"),
+        [Value::Nil, Value::Nil, Value::Integer(1)]);
+    assert_eq!(eval("local t = {[0] = 'x'} return table.remove(t, 0), t[0]"),
+        ["x".into(), Value::Nil]);
+
+    // nil is appended as nothing
+    assert_eq!(eval("local t = {1, 2} table.insert(t, nil) table.insert(t, 1, 0) return #t, t[3]"),
+        [Value::Integer(3), Value::Integer(2)]);
+
+    assert_eq!(error("table.insert({}, 1, 2, 3)"), "[string \"?\"]:1: wrong number of arguments to 'insert'");
+    assert_eq!(error("table.insert({}, 2, 'x')"), "[string \"?\"]:1: bad argument #2 to 'insert' (position out of bounds)");
+    assert_eq!(error("table.insert({}, 'x', 'x')"), "[string \"?\"]:1: bad argument #2 to 'insert' (number expected, got string)");
+    assert_eq!(error("table.remove({1}, 3)"), "[string \"?\"]:1: bad argument #2 to 'remove' (position out of bounds)");
+    assert_eq!(error("table.remove(nil)"), "[string \"?\"]:1: bad argument #1 to 'remove' (table expected, got nil)");
+}
+
+#[test]
+fn concat_unpack() {
+    assert_eq!(eval("local t = {'a', 2, 3.5} \
+            return table.concat(t), table.concat(t, '-', 2), table.concat(t, 0, 1, 2), table.concat({}, 'x')"),
+        ["a23.5".into(), "2-3.5".into(), "a02".into(), "".into()]);
+    assert_eq!(error("table.concat({1, {}, 3})"), "[string \"?\"]:1: invalid value (at index 2) in table for 'concat'");
+    assert_eq!(error("table.concat({}, {})"), "[string \"?\"]:1: bad argument #2 to 'concat' (string expected, got table)");
+
+    assert_eq!(eval("return table.unpack({1, 2, 3})"),
+        [Value::Integer(1), Value::Integer(2), Value::Integer(3)]);
+    assert_eq!(eval("return table.unpack({1, 2, 3}, 2, 4)"),
+        [Value::Integer(2), Value::Integer(3), Value::Nil]);
+    assert_eq!(eval("return select('#', table.unpack({}, 3))"), [Value::Integer(0)]);
+    assert_eq!(eval("local t = table.pack(table.unpack({1, nil, 3}, 1, 3)) return t.n, t[3]"),
+        [Value::Integer(3), Value::Integer(3)]);
+    assert_eq!(error("table.unpack({}, 1, 1e8)"), "[string \"?\"]:1: too many results to unpack");
+    assert_eq!(error("table.unpack({}, -(1 << 62), 1 << 62)"),
+        "[string \"?\"]:1: too many results to unpack");
+}
+
+#[test]
+fn move_elements() {
+    // to another table, returned
+    assert_eq!(eval("local a, b = {1, 2, 3}, {'x'} \
+            local r = table.move(a, 1, 3, 2, b) return r == b, table.concat(b, ','), #a"),
+        [Value::Boolean(true), "x,1,2,3".into(), Value::Integer(3)]);
+
+    // overlapping, forward and backward
+    assert_eq!(eval("local t = {1, 2, 3, 4, 5} table.move(t, 2, 5, 1) return table.concat(t, ',')"),
+        ["2,3,4,5,5".into()]);
+    assert_eq!(eval("local t = {1, 2, 3, 4, 5} table.move(t, 1, 4, 2) return table.concat(t, ',')"),
+        ["1,1,2,3,4".into()]);
+
+    // empty ranges, and nil elements
+    assert_eq!(eval("local t = {1, 2} return table.move(t, 3, 1, 1) == t, table.concat(t, ',')"),
+        [Value::Boolean(true), "1,2".into()]);
+    assert_eq!(eval("local t = table.move({1, nil, 3}, 1, 3, 1, {7, 8, 9}) return t[1], t[2], t[3]"),
+        [Value::Integer(1), Value::Nil, Value::Integer(3)]);
+    assert_eq!(eval("local t = table.move({'a', 'b'}, '1', 2.0, -1, {}) return t[-1], t[0]"),
+        ["a".into(), "b".into()]);
+
+    assert_eq!(error("table.move({}, 1, 2)"),
+        "[string \"?\"]:1: bad argument #4 to 'move' (number expected, got no value)");
+    assert_eq!(error("table.move({}, 1, 2, 3, 4)"),
+        "[string \"?\"]:1: bad argument #5 to 'move' (table expected, got number)");
+    assert_eq!(error("table.move({}, -1, math.maxinteger, 1)"),
+        "[string \"?\"]:1: bad argument #3 to 'move' (too many elements to move)");
+    assert_eq!(error("table.move({}, 1, 10, math.maxinteger)"),
+        "[string \"?\"]:1: bad argument #4 to 'move' (destination wrap around)");
+}
+
+#[test]
+fn array_growth() {
+    // integer keys in the hash part are not hidden by the nil padding
+    // when the array part grows over them
+    assert_eq!(eval("local t = {} t[1] = 1 t[10] = 10 for i = 2, 5 do t[i] = i end \
+            t[11] = 11 return t[10], t[11]"),
+        [Value::Integer(10), Value::Integer(11)]);
+    assert_eq!(eval("local t = {1, 2} t[7] = 7 t[5] = 5 t[6] = nil t[8] = 8 \
+            local n = 0 for k in pairs(t) do n = n + 1 end return t[5], t[7], n"),
+        [Value::Integer(5), Value::Integer(7), Value::Integer(5)]);
+}
+
+// the nil policy of the array part, by test_lua/table_nil.lua
+#[test]
+fn table_nil_script() {
+    assert_eq!(script("table_nil.lua").unwrap(), "\
+3
+2
+0
+3\tnil
+2
+2\ta\tb
+3\tc
+1\t1
+2\t2
+4\t1\tnil\t3\tnil
+0\t0
+10\t11
+");
+}
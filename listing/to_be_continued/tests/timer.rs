@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn fired(state: &mut ExeState) -> Vec<Value> {
+    state.exec_main(&parse::load(b"return table.concat(fired, ',')".as_slice()))
+}
+
+#[test]
+fn poll() {
+    let mut state = ExeState::new();
+    state.open_timer();
+    state.exec_main(&parse::load(br#"
+        fired = {}
+        local function log(s) return function() fired[#fired+1] = s end end
+        timer.after(2, log("a2"))
+        timer.after(1, log("a1"))
+        local n = 0
+        timer.every(1, function()
+            n = n + 1
+            fired[#fired+1] = "e" .. n
+            if n == 2 then timer.after(0, log("new")) end
+            return n < 4
+        end)
+        local id = timer.after(1.5, log("cancelled"))
+        timer.cancel(id)
+    "#.as_slice()));
+
+    // the time of the last polling is the base of scheduling
+    let t0 = Instant::now();
+    let sec = |n: f64| t0 + Duration::from_secs_f64(n);
+    assert_eq!(state.poll_timers(t0).unwrap(), 0);
+    assert!(state.next_timer().unwrap() > t0);
+
+    assert_eq!(state.poll_timers(sec(1.0)).unwrap(), 2);
+    assert_eq!(fired(&mut state), ["a1,e1".into()]);
+
+    // the new timer is due, but called in the next polling
+    assert_eq!(state.poll_timers(sec(2.0)).unwrap(), 2);
+    assert_eq!(fired(&mut state), ["a1,e1,a2,e2".into()]);
+    assert_eq!(state.next_timer(), Some(sec(2.0)));
+
+    // missed ticks are skipped
+    assert_eq!(state.poll_timers(sec(10.0)).unwrap(), 2);
+    assert_eq!(fired(&mut state), ["a1,e1,a2,e2,new,e3".into()]);
+    assert_eq!(state.next_timer(), Some(sec(11.0)));
+
+    // stopped by returning false
+    assert_eq!(state.poll_timers(sec(11.0)).unwrap(), 1);
+    assert_eq!(state.next_timer(), None);
+    assert_eq!(fired(&mut state), ["a1,e1,a2,e2,new,e3,e4".into()]);
+}
+
+#[test]
+fn errors() {
+    let mut state = ExeState::new();
+    state.open_timer();
+    state.exec_main(&parse::load(br#"
+        fired = {}
+        timer.after(1, function() error("boom") end)
+        timer.after(1, function() fired[1] = "ok" end)
+    "#.as_slice()));
+
+    let t1 = Instant::now() + Duration::from_secs(1);
+    let err = state.poll_timers(t1).unwrap_err();
+    assert!(err.to_string().ends_with("boom"), "{err}");
+
+    // the rest are called by the next polling
+    assert_eq!(state.poll_timers(t1).unwrap(), 1);
+    assert_eq!(fired(&mut state), ["ok".into()]);
+
+    let err = std::panic::catch_unwind(|| {
+        let mut state = ExeState::new();
+        state.open_timer();
+        state.exec_main(&parse::load(b"timer.every(-1, print)".as_slice()));
+    }).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().unwrap(),
+        "[string \"?\"]:1: bad argument #1 to 'every' (non-negative number expected)");
+}
+
+// not opened by default
+#[test]
+fn opt_in() {
+    let rets = ExeState::new().exec_main(&parse::load(b"return timer".as_slice()));
+    assert_eq!(rets, [Value::Nil]);
+}
@@ -0,0 +1,97 @@
+// events forwarded into the tracing crate, run by
+// `cargo test --features tracing`
+#![cfg(feature = "tracing")]
+
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use lua_rs::vm::ExeState;
+
+// a subscriber which logs the spans and the events as lines
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0.push_str(&format!("{value:?}"));
+        } else {
+            self.0.push_str(&format!(" {}={value:?}", field.name()));
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn new_span(&self, span: &Attributes) -> Id {
+        let mut fields = Fields(span.metadata().name().to_string());
+        span.record(&mut fields);
+        let mut log = self.0.lock().unwrap();
+        log.push(fields.0);
+        Id::from_u64(log.len() as u64)
+    }
+    fn record(&self, _: &Id, _: &Record) {}
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, event: &Event) {
+        let mut fields = Fields(String::new());
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields.0);
+    }
+    fn enter(&self, _: &Id) {
+        self.0.lock().unwrap().push("enter".into());
+    }
+    fn exit(&self, _: &Id) {
+        self.0.lock().unwrap().push("exit".into());
+    }
+}
+
+fn trace(source: &str) -> Vec<String> {
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut state = ExeState::new();
+        let proto = state.try_load(source.as_bytes(), "=trace").unwrap();
+        let _ = state.try_exec_main(Rc::new(proto), &[]);
+    });
+    let log = recorder.0.lock().unwrap();
+    log.clone()
+}
+
+#[test]
+fn calls() {
+    assert_eq!(trace("local function f() return 1, 2 end f()"), [
+        "call depth=1", "enter",
+        "call depth=2", "enter", "return nret=2", "exit",
+        "return nret=0", "exit",
+    ]);
+}
+
+// the spans of the calls aborted by errors are exited by pcall(), and
+// the ones of suspended coroutines by resume()
+#[test]
+fn aborted() {
+    assert_eq!(trace("local function f() error('boom') end pcall(f)"), [
+        "call depth=1", "enter",
+        "call depth=2", "enter",
+        "call depth=3", "enter",
+        "call depth=4", "enter",
+        "error depth=4 error=\"trace:1: boom\"",
+        "exit", "exit", "return nret=2", "exit",
+        "return nret=0", "exit",
+    ]);
+    assert_eq!(trace("coroutine.wrap(function() coroutine.yield() end)()"), [
+        "call depth=1", "enter",
+        "call depth=2", "enter", "return nret=1", "exit",
+        "call depth=2", "enter",
+        "call depth=3", "enter",
+        "call depth=4", "enter", "return nret=0", "exit",
+        "exit", "return nret=0", "exit",
+        "return nret=0", "exit",
+    ]);
+}
@@ -0,0 +1,80 @@
+// coroutine.transfer(), run by `cargo test --features transfer`
+#![cfg(feature = "transfer")]
+
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+fn exec(source: &str) -> Vec<Value> {
+    ExeState::new().exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn ping_pong() {
+    let rets = exec(r#"
+        local log = {}
+        local ping, pong
+        ping = coroutine.create(function(n)
+            for i = 1, n do
+                log[#log+1] = "ping" .. i
+                coroutine.transfer(pong, i)
+            end
+            return "done"
+        end)
+        pong = coroutine.create(function(i)
+            while true do
+                log[#log+1] = "pong" .. i
+                i = coroutine.transfer(ping)
+            end
+        end)
+        local ok, r = coroutine.resume(ping, 2)
+        return ok, r, coroutine.status(ping), coroutine.status(pong),
+            log[1] .. log[2] .. log[3] .. log[4]
+    "#);
+    assert_eq!(rets, [Value::Boolean(true), "done".into(), "dead".into(), "suspended".into(),
+        "ping1pong1ping2pong2".into()]);
+}
+
+// the transfers do not nest, and the values are returned to the
+// resumer when any coroutine yields
+#[test]
+fn scheduler() {
+    let rets = exec(r#"
+        local a, b
+        a = coroutine.create(function(n)
+            while n < 100000 do n = coroutine.transfer(b, n + 1) end
+            coroutine.yield("yielded by a", n)
+        end)
+        b = coroutine.create(function(n)
+            while true do n = coroutine.transfer(a, n + 1) end
+        end)
+        local ok, msg, n = coroutine.resume(a, 0)
+        return msg, n, coroutine.transfer(b, 7)
+    "#);
+    // out of coroutines, the transfer resumes b, which transfers to a
+    // which is suspended by yield(), and then a returns
+    assert_eq!(rets, ["yielded by a".into(), Value::Integer(100000)]);
+}
+
+#[test]
+fn errors() {
+    let rets = exec(r#"
+        local co = coroutine.create(function() error("oops", 0) end)
+        local t = coroutine.create(function() coroutine.transfer(co) end)
+        local ok, err = coroutine.resume(t)
+        local self_ok, self_err = coroutine.resume(coroutine.create(function()
+            return coroutine.transfer(coroutine.running())
+        end))
+        return ok, err, coroutine.status(co), coroutine.status(t), self_ok, self_err
+    "#);
+    assert_eq!(rets, [Value::Boolean(false), "oops".into(), "dead".into(), "suspended".into(),
+        Value::Boolean(false), "[string \"?\"]:6: cannot transfer to non-suspended coroutine".into()]);
+
+    // out of coroutines, the error objects are propagated as they are
+    let rets = exec(r#"
+        local e = {}
+        local ok, v = pcall(coroutine.transfer, coroutine.create(function() error(e) end))
+        return ok, v == e
+    "#);
+    assert_eq!(rets, [Value::Boolean(false), Value::Boolean(true)]);
+}
@@ -0,0 +1,118 @@
+use std::panic::{self, AssertUnwindSafe};
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+
+struct Counter {
+    n: i64,
+}
+
+fn counter_new(state: &mut ExeState) -> i32 {
+    let n = state.get::<i64>(1);
+    let v = state.create_userdata(Counter { n });
+    state.push(v);
+    1
+}
+
+fn counter_incr(state: &mut ExeState) -> i32 {
+    let by = if state.get_top() >= 2 { state.get::<i64>(2) } else { 1 };
+    let n = {
+        let mut c = state.check_userdata::<Counter>(1, "incr");
+        c.n += by;
+        c.n
+    };
+    state.push(n);
+    1
+}
+
+fn counter_get(state: &mut ExeState) -> i32 {
+    let n = state.check_userdata::<Counter>(1, "get").n;
+    state.push(n);
+    1
+}
+
+fn counter_add(state: &mut ExeState) -> i32 {
+    let a = state.check_userdata::<Counter>(1, "__add").n;
+    let b = state.check_userdata::<Counter>(2, "__add").n;
+    let v = state.create_userdata(Counter { n: a + b });
+    state.push(v);
+    1
+}
+
+fn new_state() -> ExeState {
+    let mut state = ExeState::new();
+    state.register_userdata::<Counter>("Counter", &[
+        ("incr", counter_incr),
+        ("get", counter_get),
+        ("__add", counter_add),
+    ]);
+    state.globals().set("Counter", Value::RustFunction(counter_new));
+    state
+}
+
+fn eval(state: &mut ExeState, source: &str) -> Vec<Value> {
+    state.exec_main(&parse::load(source.as_bytes()))
+}
+
+#[test]
+fn methods() {
+    let mut state = new_state();
+    let rets = eval(&mut state, "
+        local c = Counter(10)
+        c:incr()
+        c:incr(5)
+        local sum = c + Counter(100)
+        return c:get(), sum:get(), type(c), c == c, c == sum
+    ");
+    assert_eq!(rets, [Value::Integer(16), Value::Integer(116), "userdata".into(),
+        Value::Boolean(true), Value::Boolean(false)]);
+}
+
+// the value of other types, or not userdata
+#[test]
+fn downcast() {
+    fn is_counter(state: &mut ExeState) -> i32 {
+        let n = state.get_userdata::<Counter>(1).map(|c| c.n);
+        let is_string = state.get_userdata::<String>(1).is_some();
+        state.push(n.map_or(Value::Nil, Value::Integer));
+        state.push(is_string);
+        2
+    }
+    let mut state = new_state();
+    state.globals().set("is_counter", Value::RustFunction(is_counter));
+    let rets = eval(&mut state, "
+        local a, b = is_counter(Counter(3))
+        local c, d = is_counter({})
+        return a, b, c, d
+    ");
+    assert_eq!(rets, [Value::Integer(3), Value::Boolean(false), Value::Nil, Value::Boolean(false)]);
+}
+
+#[test]
+fn errors() {
+    let error = |source: &str| {
+        let mut state = new_state();
+        let err = panic::catch_unwind(AssertUnwindSafe(|| eval(&mut state, source))).unwrap_err();
+        err.downcast_ref::<String>().unwrap().clone()
+    };
+    assert_eq!(error("local c = Counter(1) c.get({})"),
+        "[string \"?\"]:1: bad argument #1 to 'get' (Counter expected, got table)");
+    assert_eq!(error("local c = Counter(1) c.get()"),
+        "[string \"?\"]:1: bad argument #1 to 'get' (Counter expected, got no value)");
+    assert_eq!(error("local c = Counter(1) return c:nothing()"),
+        "[string \"?\"]:1: attempt to call a nil value (method 'nothing')");
+}
+
+// without registering, the value has no metatable
+#[test]
+fn unregistered() {
+    struct Opaque;
+    let mut state = ExeState::new();
+    let v = state.create_userdata(Opaque);
+    state.globals().set("opaque", v);
+    let rets = eval(&mut state, "
+        local ok = pcall(function() return opaque.x end)
+        return type(opaque), getmetatable(opaque), ok
+    ");
+    assert_eq!(rets, ["userdata".into(), Value::Nil, Value::Boolean(false)]);
+}
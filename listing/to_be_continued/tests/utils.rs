@@ -0,0 +1,103 @@
+// Direct tests of the helpers in utils.rs, which are shared by the VM,
+// the parser and several libraries, so they must agree with the official
+// Lua exactly, e.g. string.sub() and string.byte() by the positions.
+
+use lua_rs::utils::{self, start_pos, end_pos};
+use lua_rs::value::Value;
+
+// `posrelatI()` and `getendpos()` in lstrlib.c, in i128 to not overflow
+fn start_ref(pos: i64, len: usize) -> i128 {
+    let (pos, len) = (pos as i128, len as i128);
+    if pos > 0 {
+        pos
+    } else if pos == 0 || pos < -len {
+        1
+    } else {
+        len + pos + 1
+    }
+}
+fn end_ref(pos: i64, len: usize) -> i128 {
+    let (pos, len) = (pos as i128, len as i128);
+    if pos > len {
+        len
+    } else if pos >= 0 {
+        pos
+    } else if pos < -len {
+        0
+    } else {
+        len + pos + 1
+    }
+}
+
+#[test]
+fn positions() {
+    let extremes = [i64::MIN, i64::MIN + 1, -(1 << 32), 1 << 32, i64::MAX - 1, i64::MAX];
+    for len in 0..6 {
+        for pos in (-8..=8).chain(extremes) {
+            assert_eq!(start_pos(pos, len) as i128, start_ref(pos, len).min(usize::MAX as i128),
+                "start_pos({pos}, {len})");
+            assert_eq!(end_pos(pos, len) as i128, end_ref(pos, len), "end_pos({pos}, {len})");
+        }
+    }
+
+    // slicing as described in utils.rs
+    let s = b"hello";
+    let sub = |i, j| {
+        let (start, end) = (start_pos(i, s.len()), end_pos(j, s.len()));
+        if start <= end { &s[start-1..end] } else { &[][..] }
+    };
+    assert_eq!(sub(2, -2), b"ell");
+    assert_eq!(sub(-3, i64::MAX), b"llo");
+    assert_eq!(sub(i64::MIN, 1), b"h");
+    assert_eq!(sub(4, 2), b"");
+    assert_eq!(sub(6, 10), b"");
+    assert_eq!(sub(0, 0), b"");
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(utils::ftoi(3.0), Some(3));
+    assert_eq!(utils::ftoi(-0.0), Some(0));
+    assert_eq!(utils::ftoi(3.5), None);
+    assert_eq!(utils::ftoi(-9223372036854775808.0), Some(i64::MIN));
+    assert_eq!(utils::ftoi(9223372036854775808.0), None);
+    assert_eq!(utils::ftoi(f64::NAN), None);
+    assert_eq!(utils::ftoi(f64::INFINITY), None);
+
+    assert_eq!([(7, 2), (-7, 2), (7, -2), (-7, -2)].map(|(a, b)| utils::int_div(a, b)), [3, -4, -4, 3]);
+    assert_eq!([(7, 2), (-7, 2), (7, -2), (-7, -2)].map(|(a, b)| utils::int_mod(a, b)), [1, 1, -1, -1]);
+    assert_eq!(utils::int_div(i64::MIN, -1), i64::MIN);
+    assert_eq!(utils::int_mod(i64::MIN, -1), 0);
+    assert_eq!(utils::float_div(-7.0, 2.0), -4.0);
+    assert_eq!(utils::float_mod(-7.0, 2.0), 1.0);
+    assert_eq!(utils::float_mod(7.0, -2.0), -1.0);
+    assert_eq!(utils::float_mod(-0.5, f64::INFINITY), f64::INFINITY);
+
+    assert_eq!(utils::shift_left(1, 63), i64::MIN);
+    assert_eq!(utils::shift_left(1, 64), 0);
+    assert_eq!(utils::shift_left(4, -1), 2);
+    assert_eq!(utils::shift_right(-1, 1), i64::MAX);
+    assert_eq!(utils::shift_right(1, -63), i64::MIN);
+    assert_eq!(utils::shift_left(1, i64::MIN), 0);
+    assert_eq!(utils::shift_right(1, i64::MIN), 0);
+}
+
+#[test]
+fn numbers() {
+    let num = |s: &str| utils::str_to_number(s.as_bytes());
+    assert_eq!(num(" 10 "), Some(Value::Integer(10)));
+    assert_eq!(num("0x10"), Some(Value::Integer(16)));
+    assert_eq!(num("-0xffffffffffffffff"), Some(Value::Integer(1)));
+    assert_eq!(num("9223372036854775808"), Some(Value::Float(9223372036854775808.0)));
+    assert_eq!(num("1e2"), Some(Value::Float(100.0)));
+    assert_eq!(num(".5"), Some(Value::Float(0.5)));
+    assert_eq!(num("0x.8p1"), Some(Value::Float(1.0)));
+    assert_eq!(num("1e"), None);
+    assert_eq!(num("1 2"), None);
+    assert_eq!(num(""), None);
+    assert_eq!(num("inf"), None);
+
+    assert_eq!(utils::memmem(b"hello", b"ll"), Some(2));
+    assert_eq!(utils::memmem(b"hello", b""), Some(0));
+    assert_eq!(utils::memmem(b"hello", b"lo!"), None);
+}
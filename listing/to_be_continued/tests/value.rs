@@ -0,0 +1,51 @@
+use lua_rs::value::{Value, ConversionError};
+
+#[test]
+fn from_numbers() {
+    assert_eq!(Value::from(-3_i32), Value::Integer(-3));
+    assert_eq!(Value::from(u32::MAX), Value::Integer(u32::MAX as i64));
+    assert_eq!(Value::from(7_usize), Value::Integer(7));
+    assert_eq!(Value::from(usize::MAX), Value::Float(usize::MAX as f64));
+}
+
+#[test]
+fn try_into_integers() {
+    assert_eq!(i64::try_from(Value::Integer(-5)), Ok(-5));
+    assert_eq!(i64::try_from(Value::Float(3.0)), Ok(3));
+    assert_eq!(i64::try_from(Value::from(" 0x10 ")), Ok(16));
+    assert_eq!(i32::try_from(Value::Integer(1 << 40)), Err(ConversionError::Range { to: "i32" }));
+    assert_eq!(u32::try_from(Value::Integer(-1)), Err(ConversionError::Range { to: "u32" }));
+    assert_eq!(usize::try_from(Value::Integer(-1)), Err(ConversionError::Range { to: "usize" }));
+    assert_eq!(i64::try_from(Value::Float(1.5)), Err(ConversionError::Range { to: "i64" }));
+    assert_eq!(i64::try_from(Value::Float(9223372036854775808.0)),
+        Err(ConversionError::Range { to: "i64" }));
+    assert_eq!(i64::try_from(Value::Float(f64::NAN)), Err(ConversionError::Range { to: "i64" }));
+    assert_eq!(i64::try_from(Value::from("x")), Err(ConversionError::Type { from: "string", to: "i64" }));
+    assert_eq!(i64::try_from(Value::Nil), Err(ConversionError::Type { from: "nil", to: "i64" }));
+}
+
+#[test]
+fn try_into_others() {
+    assert_eq!(f64::try_from(Value::Integer(2)), Ok(2.0));
+    assert_eq!(f64::try_from(Value::from("2.5")), Ok(2.5));
+    assert_eq!(f64::try_from(Value::Boolean(true)),
+        Err(ConversionError::Type { from: "boolean", to: "f64" }));
+
+    assert_eq!(bool::try_from(Value::Nil), Ok(false));
+    assert_eq!(bool::try_from(Value::Integer(0)), Ok(true));
+
+    assert_eq!(String::try_from(Value::from("hi")), Ok("hi".to_string()));
+    assert_eq!(String::try_from(Value::from("x".repeat(100))), Ok("x".repeat(100)));
+    assert_eq!(String::try_from(Value::Integer(12)), Ok("12".to_string()));
+    assert_eq!(String::try_from(Value::from(&b"\xff"[..])), Err(ConversionError::Utf8));
+    assert_eq!(String::try_from(Value::Nil), Err(ConversionError::Type { from: "nil", to: "String" }));
+    assert_eq!(Vec::<u8>::try_from(Value::from(&b"\xff"[..])), Ok(vec![0xff]));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(ConversionError::Type { from: "table", to: "i64" }.to_string(),
+        "table can not be converted to i64");
+    assert_eq!(ConversionError::Range { to: "u32" }.to_string(), "number has no u32 representation");
+    assert_eq!(ConversionError::Utf8.to_string(), "string is not valid UTF-8");
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::panic;
+use lua_rs::parse::{self, FuncProto};
+use lua_rs::bytecode::ByteCode;
+use lua_rs::verify::Verifier;
+
+// violations as "line N: msg"
+fn verify(verifier: &Verifier, source: &str) -> Vec<String> {
+    let proto = parse::load(source.as_bytes());
+    match verifier.verify(&proto) {
+        Ok(()) => Vec::new(),
+        Err(violations) => violations.iter().map(|v| v.to_string()).collect(),
+    }
+}
+
+#[test]
+fn globals() {
+    let v = Verifier::sandbox();
+    assert_eq!(verify(&v, r#"
+        local f = load("return 1")
+        print(string.format("%d", 1))
+        local function g()
+            setmetatable({}, {})
+            return os.time()
+        end
+        require = nil
+    "#), [
+        "line 2: access to global 'load'",
+        "line 5: access to global 'setmetatable'",
+        "line 6: access to global 'os'",
+        "line 8: access to global 'require'",
+    ]);
+
+    // locals are fine
+    assert!(verify(&v, "local load = 1; local os = {}; return load, os.time").is_empty());
+}
+
+#[test]
+fn fields() {
+    let v = Verifier::sandbox();
+    assert_eq!(verify(&v, r#"
+        local d = string.dump
+        local s = string
+        if s then end
+        s.dump(print)
+        local t = {}
+        t.dump = 1
+        local k = tostring(1)
+        local x = string[k]
+        return table.dump, string.format
+    "#), [
+        "line 2: access to 'string.dump'",
+        "line 5: access to 'string.dump'",
+        "line 7: access to 'string.dump'",
+        "line 9: dynamic index of global 'string'",
+    ]);
+}
+
+#[test]
+fn global_assignment() {
+    let v = Verifier::new().deny_global_assignment();
+    assert_eq!(verify(&v, "x = 1\nlocal y = x\nfunction f() z = y end"), [
+        "line 1: assignment to global 'x'",
+        "line 3: assignment to global 'f'",
+        "line 3: assignment to global 'z'",
+    ]);
+    assert!(verify(&Verifier::new(), "x = 1 function f() z = x end").is_empty());
+}
+
+#[test]
+fn explicit_env() {
+    let v = Verifier::new();
+    assert_eq!(verify(&v, "local e = _ENV"), ["line 1: use of '_ENV'"]);
+    assert_eq!(verify(&v, "local t = {}\nt.env = _ENV"), ["line 2: use of '_ENV'"]);
+    assert_eq!(verify(&v, "local k = 'lo' .. 'ad'\nreturn _ENV[k]"), ["line 2: use of '_ENV'"]);
+    assert_eq!(verify(&v, "function f()\n return _ENV\nend"), ["line 2: use of '_ENV'"]);
+    assert_eq!(verify(&v, "local _ENV = {}\nprint(1)"), ["line 1: use of '_ENV'"]);
+    assert!(!verify(&v, "print(_ENV)").is_empty());
+}
+
+// the compiler itself never uses `_ENV` explicitly
+#[test]
+fn clean_scripts() {
+    let v = Verifier::new().deny_global("load");
+    for entry in fs::read_dir("test_lua").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "lua") {
+            continue;
+        }
+        let Ok(proto) = panic::catch_unwind(|| parse::load(fs::File::open(&path).unwrap())) else {
+            continue;
+        };
+        assert_eq!(v.verify(&proto), Ok(()), "{path:?}");
+    }
+}
+
+#[test]
+fn stripped() {
+    let proto = parse::load("return 1".as_bytes());
+    let proto = lua_rs::dump::undump(&lua_rs::dump::dump(&proto, true), "=test");
+    let err = Verifier::new().verify(&proto).unwrap_err();
+    assert_eq!(err[0].to_string(), "line 0: no debug information");
+}
+
+#[test]
+fn bad_constant_index() {
+    // a hand-made proto with a constant index out of range
+    let proto = FuncProto {
+        byte_codes: vec![ByteCode::GetUpField(0, 0, 5), ByteCode::GetField(1, 0, 6)],
+        lines: vec![1, 2],
+        upvalue_names: vec!["_ENV".into()],
+        ..Default::default()
+    };
+    assert!(Verifier::sandbox().verify(&proto).is_ok());
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "zlib")]
+
+mod common;
+
+use lua_rs::parse;
+use lua_rs::value::Value;
+use lua_rs::vm::ExeState;
+use common::eval;
+
+fn unhex(s: &str) -> Value {
+    let bytes: Vec<u8> = (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i+2], 16).unwrap())
+        .collect();
+    bytes.into()
+}
+
+// the output of zlib and gzip with dynamic Huffman codes
+#[test]
+fn decompress() {
+    let mut state = ExeState::new();
+    let globals = state.globals();
+    globals.set("short", unhex("78dacb48cdc9c957c8402701680308b1"));
+    globals.set("long", unhex("78da7dd44b0e81510c80d1b955dc25e8c3733984f84308212c5f2cc0197fa39eb4bd4cd7c3\
+        986fc7f37418f7d7b43f8fdde3f6be8ee3ed33bbfc5aa0255aa135da026d89b6425ba36d343b612413a209d98470423a\
+        219e904f08282494124aee8e84524229a194504a282594124a0995844a42c5f3925049a82454122a0995844a422da196\
+        504ba8f98124d4126a09b5845a42fd47e80b86a9e6c3"));
+    globals.set("gz", unhex("1f8b08080000000002ff612e747874004bafca2c5048492c4904001772e1bb09000000"));
+    let rets = state.exec_main(&parse::load(br#"
+        local t = {}
+        for i = 0, 49 do
+            t[#t+1] = "line " .. i .. ": the quick brown fox\n"
+        end
+        return zlib.inflate(short), zlib.inflate(long) == table.concat(t), zlib.gunzip(gz),
+            zlib.gunzip(gz .. gz)
+    "#.as_slice()));
+    assert_eq!(rets, ["hello hello hello hello".into(), Value::Boolean(true), "gzip data".into(),
+        "gzip datagzip data".into()]);
+}
+
+#[test]
+fn round_trip() {
+    assert_eq!(eval(r#"
+        local data = {"", "a", string.rep("abc", 1000), string.rep("x", 100000)}
+        local t = {}
+        for i = 1, 3000 do t[i] = string.char(i * 7 % 256, i % 13) end
+        data[#data+1] = table.concat(t)
+        local ok = true
+        for _, s in ipairs(data) do
+            for level = 0, 9 do
+                ok = ok and zlib.inflate(zlib.deflate(s, level)) == s
+                    and zlib.gunzip(zlib.gzip(s, level)) == s
+            end
+        end
+        return ok, #zlib.deflate(data[3]) < 100, #zlib.deflate(data[3], 0) > 3000,
+            zlib.inflate(zlib.deflate(bytes.new("buf")))
+    "#), [Value::Boolean(true), Value::Boolean(true), Value::Boolean(true), "buf".into()]);
+}
+
+#[test]
+fn errors() {
+    let fail = |source: &str, msg: &str| {
+        assert_eq!(eval(source), [Value::Nil, msg.into()], "{source}");
+    };
+    fail("return zlib.inflate('')", "truncated data");
+    fail("return zlib.inflate('xyz')", "invalid zlib header");
+    fail(r"return zlib.inflate('\x78\x9c\xff\xff')", "invalid deflate data");
+    fail("local z = zlib.deflate('hello') return zlib.inflate(z:sub(1, -2))", "truncated data");
+    fail("local z = zlib.deflate('hello') return zlib.inflate(z:sub(1, -5) .. 'abcd')", "adler32 mismatch");
+    fail("return zlib.inflate(zlib.deflate('hello') .. 'x')", "extra bytes after data");
+    fail("return zlib.gunzip('not gzip data')", "invalid gzip header");
+    fail("local z = zlib.gzip('hello') return zlib.gunzip(z:sub(1, -9) .. 'abcdefgh')", "crc32 mismatch");
+    fail("return zlib.gunzip(zlib.gzip('hello') .. 'x')", "truncated data");
+
+    // limit of the output, e.g. for zip bombs
+    fail("return zlib.inflate(zlib.deflate(string.rep('a', 1000)), 999)", "output too large");
+    fail("return zlib.gunzip(zlib.gzip(string.rep('a', 1000), 0), 10)", "output too large");
+    assert_eq!(eval("return #zlib.inflate(zlib.deflate(string.rep('a', 1000)), 1000)"),
+        [Value::Integer(1000)]);
+}
+
+#[test]
+fn checksums() {
+    assert_eq!(eval(r#"
+        return zlib.crc32("123456789"), zlib.adler32("Wikipedia"),
+            zlib.crc32("6789", zlib.crc32("12345")), zlib.crc32(""), zlib.adler32("")
+    "#), [Value::Integer(3421780262), Value::Integer(300286872), Value::Integer(3421780262),
+        Value::Integer(0), Value::Integer(1)]);
+}